@@ -0,0 +1,186 @@
+use std::convert::TryFrom;
+
+use crate::*;
+
+/// What starts an assertion in the source, after the comment marker:
+/// `//! assert a == 34 after 5000 steps`.
+const ASSERTION_PREFIX: &str = "//! assert ";
+
+/// A check written in a program's source of what a variable or memory
+/// address holds once the emulator has run it for some number of
+/// instructions, as:
+///
+/// ```text
+/// //! assert <var|cell[address]> <op> <value> after <n> steps
+/// ```
+///
+/// where `op` is one of `==`, `!=`, `<`, `<=`, `>`, `>=`, or `===`, which
+/// compare as `equal`, `notEqual`, and so on do, and `value` is a number,
+/// `null`, `true`, `false`, or content such as `@copper`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assertion {
+    /// The source line it's on.
+    pub line: usize,
+    pub target: Watchpoint,
+    pub op: String,
+    pub value: Value,
+    pub steps: usize,
+    cond: Cond,
+}
+
+/// How an `Assertion` came out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AssertionResult {
+    pub assertion: Assertion,
+
+    /// What the target held after the assertion's steps.
+    pub actual: Value,
+    pub passed: bool,
+}
+
+impl Assertion {
+    /// Every assertion in `text`, in order.
+    pub fn parse_all(text: &str) -> Result<Vec<Assertion>> {
+        text.lines()
+            .enumerate()
+            .filter_map(|(line_no, line)| {
+                let directive = line.trim().strip_prefix(ASSERTION_PREFIX)?;
+                Some(
+                    Assertion::parse(directive, line_no)
+                        .with_context(|| format!("Line {}: {}", line_no, line.trim())),
+                )
+            })
+            .collect()
+    }
+
+    /// The assertion `directive`, the part after `//! assert`.
+    fn parse(directive: &str, line: usize) -> Result<Assertion> {
+        let tok: Vec<_> = directive.split_whitespace().collect();
+        let (target, op, value, steps) = match tok[..] {
+            [target, op, value, "after", steps, "steps"]
+            | [target, op, value, "after", steps, "step"] => (target, op, value, steps),
+            _ => bail!("assertions take the form <var|cell[address]> <op> <value> after <n> steps"),
+        };
+
+        let cond = match op {
+            "==" => Cond::Eq,
+            "!=" => Cond::Ne,
+            "<" => Cond::Lt,
+            "<=" => Cond::Le,
+            ">" => Cond::Gt,
+            ">=" => Cond::Ge,
+            "===" => Cond::StrictEq,
+            _ => bail!(
+                "unknown comparison {}; use ==, !=, <, <=, >, >=, or ===",
+                op
+            ),
+        };
+        let value = match value {
+            "null" => Value::Null,
+            "true" => Value::Number(1.0),
+            "false" => Value::Number(0.0),
+            content if content.starts_with('@') => Value::Content(Symbol::new(content)),
+            number => Value::Number(
+                parse_number(number)
+                    .with_context(|| format!("{} is not a number, null, or content", number))?,
+            ),
+        };
+
+        Ok(Assertion {
+            line,
+            target: Watchpoint::try_from(target)?,
+            op: op.to_string(),
+            value,
+            steps: steps.parse().context("steps must be an integer")?,
+            cond,
+        })
+    }
+
+    /// What the target holds now in `emu`.
+    fn actual(&self, emu: &Emulator) -> Value {
+        match &self.target {
+            Watchpoint::Var(var) => emu.var(var.as_str()),
+            Watchpoint::Memory(cell, address) => {
+                match emu.get_cell_value(cell.as_str(), *address) {
+                    Some(value) => Value::Number(value),
+                    None => Value::Null,
+                }
+            }
+        }
+    }
+}
+
+/// Compiles `text` with `options` and runs it in the emulator, checking each
+/// of its assertions once it has run as many instructions as they say, or
+/// the program has reached an `end` or `stop`, if sooner. The results are in
+/// the order of the assertions in the source.
+///
+/// The emulator has the memory cells the stacks use, and any others the
+/// assertions read from.
+pub fn run_assertions(
+    text: &str,
+    options: &parser::CompileOptions,
+) -> Result<Vec<AssertionResult>> {
+    let assertions = Assertion::parse_all(text)?;
+    let ir = IntermediateRepresentation::parse_with_options(text, options).context("parse")?;
+    let (output, _) = ir.generate().context("generate")?;
+
+    let mut names = ir.stack_cells();
+    for assertion in assertions.iter() {
+        if let Watchpoint::Memory(cell, _) = &assertion.target {
+            if !names.contains(cell) {
+                names.push(cell.clone());
+            }
+        }
+    }
+    let cells = names.into_iter().map(Cell::new);
+
+    let mut emu = Emulator::new(cells, &output.join("\n")).context("init emulator")?;
+    emu.set_source_map(&ir.source_map());
+
+    let mut order: Vec<usize> = (0..assertions.len()).collect();
+    order.sort_by_key(|&j| assertions[j].steps);
+    let mut actual = vec![Value::Null; assertions.len()];
+    let mut ended = false;
+    for j in order {
+        while !ended && emu.steps() < assertions[j].steps {
+            let ip = emu.counter();
+            ended = matches!(
+                emu.instruction(ip),
+                None | Some(Instruction::End) | Some(Instruction::Stop)
+            );
+            if !ended {
+                emu.step();
+            }
+        }
+        actual[j] = assertions[j].actual(&emu);
+    }
+
+    Ok(assertions
+        .into_iter()
+        .zip(actual)
+        .map(|(assertion, actual)| AssertionResult {
+            passed: assertion.cond.test(&actual, &assertion.value),
+            assertion,
+            actual,
+        })
+        .collect())
+}
+
+/// `ok` or `FAILED`, with the assertion as written and, if it failed, what
+/// the target held.
+impl std::fmt::Display for AssertionResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let assertion = &self.assertion;
+        write!(
+            f,
+            "line {}: {} {} {} after {} steps ... ",
+            assertion.line, assertion.target, assertion.op, assertion.value, assertion.steps
+        )?;
+        if self.passed {
+            write!(f, "ok")
+        } else {
+            write!(f, "FAILED: {} is {}", assertion.target, self.actual)
+        }
+    }
+}
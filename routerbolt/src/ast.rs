@@ -0,0 +1,377 @@
+use std::convert::TryFrom;
+
+use crate::parser::{clean_line, lex_line};
+use crate::*;
+
+/// A program as written, with its statements nested into the blocks of
+/// functions, conditionals, and loops. This is the first stage of parsing;
+/// `parser::lower` turns it into the IR.
+///
+/// Each statement keeps its source line, since most of the language is one
+/// statement per line, and lowering is done a line at a time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Ast {
+    pub statements: Vec<Statement>,
+
+    /// The text of every source line, including blank lines and comments.
+    pub source: Vec<String>,
+}
+
+/// A source line, counting from 0, though messages count from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub line: usize,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    pub line: Line,
+    pub kind: StatementKind,
+}
+
+/// The statements between a `{` and its `}`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub statements: Vec<Statement>,
+
+    /// The line that closes the block, which may also continue the statement,
+    /// as `} else {` and `} while cond a b` do. Blocks still open at the end of
+    /// the program are closed there, and have none.
+    pub end: Option<Line>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StatementKind {
+    /// `fn` or `inline fn`. The args and returns are either side of the `->`.
+    Function {
+        name: FunctionName,
+        inline: bool,
+        args: Vec<Expression>,
+        returns: Vec<Expression>,
+        body: Block,
+    },
+
+    /// `if`, whose `then` block ends at the `} else {` if there is one.
+    If {
+        condition: Vec<Expression>,
+        then: Block,
+        otherwise: Option<Block>,
+    },
+
+    While {
+        condition: Vec<Expression>,
+        body: Block,
+    },
+
+    /// `do { ... } while`, whose condition is on the closing line.
+    DoWhile {
+        body: Block,
+        condition: Vec<Expression>,
+    },
+
+    Loop {
+        body: Block,
+    },
+
+    /// An `asm { ... }` block, whose lines are passed through as is.
+    Asm {
+        lines: Vec<Line>,
+        end: Line,
+    },
+
+    Label(String),
+
+    /// `stack_config`, `debug`, and `epilogue`, which apply to the whole
+    /// program wherever they are.
+    Directive {
+        name: String,
+        args: Vec<Expression>,
+    },
+
+    /// Anything else: a single line such as `set`, `call`, or a Mindustry
+    /// instruction.
+    Command {
+        name: String,
+        args: Vec<Expression>,
+    },
+}
+
+/// An operand. The language has no compound expressions, so each is a single
+/// token.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+    /// A variable, literal, or stack variable.
+    Term(Term),
+
+    /// Punctuation such as `->`, or anything else that isn't a term.
+    Word(String),
+}
+
+impl Expression {
+    fn new(token: &str) -> Expression {
+        match Term::try_from(token) {
+            Ok(term) => Expression::Term(term),
+            Err(..) => Expression::Word(token.to_string()),
+        }
+    }
+}
+
+impl Ast {
+    /// Parses the structure of a program. Only the nesting of blocks is
+    /// checked here; each statement is checked as it is lowered.
+    pub fn parse(text: &str) -> Result<Ast> {
+        Ast::parse_lines(text.lines().enumerate())
+    }
+
+    /// Parses a program given a line at a time, with the number of each, so
+    /// that it needn't be gathered into one string first. The lines are read
+    /// once, in order; any skipped are taken to be blank.
+    pub fn parse_lines<S: AsRef<str>>(lines: impl IntoIterator<Item = (usize, S)>) -> Result<Ast> {
+        let mut source = Vec::default();
+        let mut out_of_order = None;
+        let mut lines = lines.into_iter().map(|(line, text)| {
+            let text = text.as_ref().to_string();
+            if line < source.len() {
+                out_of_order.get_or_insert(line);
+            }
+            source.resize(source.len().max(line), String::default());
+            source.push(text.clone());
+            Line { line, text }
+        });
+
+        let (statements, end) = parse_block(&mut lines)?;
+        if let Some(end) = end {
+            bail!("Line {}: {}: missing opening {{", end.line + 1, end.text);
+        }
+        if let Some(line) = out_of_order {
+            bail!("Line {} is out of order", line + 1);
+        }
+
+        Ok(Ast { statements, source })
+    }
+
+    /// Parses a program as it's read from `reader`.
+    pub fn read(reader: impl std::io::BufRead) -> Result<Ast> {
+        let mut error = None;
+        let lines = reader
+            .lines()
+            .map_while(|line| line.map_err(|err| error = Some(err)).ok())
+            .enumerate();
+        let ast = Ast::parse_lines(lines);
+        match error {
+            Some(err) => Err(err).context("read source"),
+            None => ast,
+        }
+    }
+
+    /// Every line of the program that has a statement or closes a block, in
+    /// source order. This is the order lowering visits them in.
+    pub fn lines(&self) -> Vec<&Line> {
+        let mut lines = Vec::default();
+        for statement in self.statements.iter() {
+            statement.push_lines(&mut lines);
+        }
+        lines
+    }
+}
+
+impl Statement {
+    /// The lines of this statement, including any blocks and their ends.
+    pub fn lines(&self) -> Vec<&Line> {
+        let mut lines = Vec::default();
+        self.push_lines(&mut lines);
+        lines
+    }
+
+    fn push_lines<'a>(&'a self, lines: &mut Vec<&'a Line>) {
+        lines.push(&self.line);
+        match &self.kind {
+            StatementKind::Function { body, .. }
+            | StatementKind::While { body, .. }
+            | StatementKind::DoWhile { body, .. }
+            | StatementKind::Loop { body } => body.push_lines(lines),
+            StatementKind::If {
+                then, otherwise, ..
+            } => {
+                then.push_lines(lines);
+                if let Some(otherwise) = otherwise {
+                    otherwise.push_lines(lines);
+                }
+            }
+            StatementKind::Asm { lines: asm, end } => {
+                lines.extend(asm.iter());
+                lines.push(end);
+            }
+            StatementKind::Label(..)
+            | StatementKind::Directive { .. }
+            | StatementKind::Command { .. } => {}
+        }
+    }
+}
+
+impl Block {
+    /// The lines of the statements in this block, not including its end.
+    pub fn body_lines(&self) -> Vec<&Line> {
+        let mut lines = Vec::default();
+        for statement in self.statements.iter() {
+            statement.push_lines(&mut lines);
+        }
+        lines
+    }
+
+    fn push_lines<'a>(&'a self, lines: &mut Vec<&'a Line>) {
+        for statement in self.statements.iter() {
+            statement.push_lines(lines);
+        }
+        lines.extend(self.end.iter());
+    }
+}
+
+/// Parses statements up to the `}` that ends the block, which is returned, or
+/// the end of the program.
+fn parse_block(lines: &mut impl Iterator<Item = Line>) -> Result<(Vec<Statement>, Option<Line>)> {
+    let mut statements = Vec::default();
+    while let Some(line) = lines.next() {
+        let tok = lex_line(clean_line(&line.text));
+        if tok.is_empty() || tok[0].starts_with("//") {
+            continue;
+        }
+
+        if tok[0] == "}" {
+            return Ok((statements, Some(line)));
+        }
+
+        let kind = if tok.last() == Some(&"{") {
+            parse_block_statement(&line, &tok, lines)?
+        } else if tok.len() == 1 && tok[0].ends_with(':') {
+            StatementKind::Label(tok[0][..tok[0].len() - 1].to_string())
+        } else {
+            let args = tok[1..].iter().map(|t| Expression::new(t)).collect();
+            let name = tok[0].to_string();
+            if ["stack_config", "debug", "epilogue"].contains(&tok[0]) {
+                StatementKind::Directive { name, args }
+            } else {
+                StatementKind::Command { name, args }
+            }
+        };
+        statements.push(Statement { line, kind });
+    }
+
+    Ok((statements, None))
+}
+
+/// Parses a statement that opens a block, along with the block.
+fn parse_block_statement(
+    line: &Line,
+    tok: &[&str],
+    lines: &mut impl Iterator<Item = Line>,
+) -> Result<StatementKind> {
+    let expressions = |tok: &[&str]| tok.iter().map(|t| Expression::new(t)).collect();
+    let block = |lines: &mut dyn Iterator<Item = Line>| -> Result<Block> {
+        let (statements, end) = parse_block(&mut &mut *lines)?;
+        Ok(Block { statements, end })
+    };
+    let closed = |block: Block| -> Result<Block> {
+        if let Some(end) = block
+            .end
+            .as_ref()
+            .filter(|end| end.text.trim_end().ends_with('{'))
+        {
+            bail!(
+                "Line {}: {}: only an if may be continued with an else",
+                end.line + 1,
+                end.text
+            );
+        }
+        Ok(block)
+    };
+    let header = &tok[..tok.len() - 1];
+
+    let kind = match header.first().copied() {
+        Some("asm") if header.len() == 1 => {
+            let mut asm = Vec::default();
+            for line in lines.by_ref() {
+                if lex_line(clean_line(&line.text)) == ["}"] {
+                    return Ok(StatementKind::Asm {
+                        lines: asm,
+                        end: line,
+                    });
+                }
+                asm.push(line);
+            }
+            bail!("asm block is missing its closing }}");
+        }
+        Some("fn") | Some("inline") => {
+            let inline = header[0] == "inline";
+            let header = if inline && header.get(1) == Some(&"fn") {
+                &header[2..]
+            } else if !inline {
+                &header[1..]
+            } else {
+                bail!(
+                    "Line {}: {}: form is `inline fn name ... {{`",
+                    line.line + 1,
+                    line.text
+                );
+            };
+            let name = header
+                .first()
+                .with_context(|| format!("Line {}: {}: function name", line.line + 1, line.text))?;
+            let name = FunctionName::try_from(*name)
+                .with_context(|| format!("Line {}: {}: function name", line.line + 1, line.text))?;
+            let (args, returns) = match header.iter().position(|t| *t == "->") {
+                Some(arrow) => (&header[1..arrow], &header[arrow + 1..]),
+                None => (&header[1..], &[][..]),
+            };
+            StatementKind::Function {
+                name,
+                inline,
+                args: expressions(args),
+                returns: expressions(returns),
+                body: closed(block(lines)?)?,
+            }
+        }
+        Some("if") => {
+            let then = block(lines)?;
+            let end = then.end.as_ref().map(|end| lex_line(clean_line(&end.text)));
+            let otherwise = if end == Some(vec!["}", "else", "{"]) {
+                Some(closed(block(lines)?)?)
+            } else {
+                None
+            };
+            StatementKind::If {
+                condition: expressions(&header[1..]),
+                then,
+                otherwise,
+            }
+        }
+        Some("while") => StatementKind::While {
+            condition: expressions(&header[1..]),
+            body: closed(block(lines)?)?,
+        },
+        Some("do") => {
+            let body = closed(block(lines)?)?;
+            let end = body
+                .end
+                .as_ref()
+                .map(|end| lex_line(clean_line(&end.text)))
+                .unwrap_or_default();
+            let condition = match end.get(1) {
+                Some(&"while") => expressions(&end[2..]),
+                _ => Vec::default(),
+            };
+            StatementKind::DoWhile { body, condition }
+        }
+        Some("loop") => StatementKind::Loop {
+            body: closed(block(lines)?)?,
+        },
+        _ => bail!(
+            "Line {}: {}: unknown kind of block",
+            line.line + 1,
+            line.text
+        ),
+    };
+
+    Ok(kind)
+}
@@ -0,0 +1,142 @@
+use crate::*;
+
+/// Something a program did that can be seen from outside it, and so should be
+/// the same whichever backend its stack uses.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Observation {
+    /// A variable compared changed to this value.
+    Set(Symbol, Value),
+
+    /// `printflush` sent this text to a message block.
+    Printed(Symbol, String),
+}
+
+impl std::fmt::Display for Observation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Observation::Set(var, value) => write!(f, "{} became {}", var, value),
+            Observation::Printed(target, text) => write!(f, "printed {:?} to {}", text, target),
+        }
+    }
+}
+
+/// Compiles `text` once with an internal stack of `stack_size` entries and
+/// once with an external stack in `bank1`, runs both in the emulator, and
+/// fails if what they do differs: each change to one of `vars`, and each
+/// `printflush`, in order. The backends take different numbers of
+/// instructions, so these are compared in order rather than step by step.
+///
+/// Each run stops when the program ends, or after `max_steps`. If either
+/// doesn't end, only what both did is compared. `setup` prepares each
+/// emulator the same way, e.g. with `Emulator::set_world`.
+pub fn compare_backends<F>(
+    text: &str,
+    options: &parser::CompileOptions,
+    stack_size: usize,
+    vars: &[&str],
+    max_steps: usize,
+    setup: F,
+) -> Result<()>
+where
+    F: Fn(&mut Emulator),
+{
+    let internal = StackConfig::Internal(stack_size);
+    let external = StackConfig::External(ExternalParams {
+        cell_name: Symbol::new("bank1"),
+        offset: 0,
+        len: None,
+        more_cells: Vec::default(),
+        bank_routines: None,
+    });
+    let (internal, internal_ended) =
+        observe(text, options, internal, vars, max_steps, &setup).context("internal stack")?;
+    let (external, external_ended) =
+        observe(text, options, external, vars, max_steps, &setup).context("external stack")?;
+
+    for (j, (a, b)) in internal.iter().zip(external.iter()).enumerate() {
+        if a.1 != b.1 {
+            bail!(
+                "backends differ at observation {}: with an internal stack, {} at step {}, \
+                 but with an external stack, {} at step {}",
+                j,
+                a.1,
+                a.0,
+                b.1,
+                b.0
+            );
+        }
+    }
+
+    if internal_ended && external_ended && internal.len() != external.len() {
+        let (longer, backend) = if internal.len() > external.len() {
+            (&internal, "an internal")
+        } else {
+            (&external, "an external")
+        };
+        let (step, next) = &longer[internal.len().min(external.len())];
+        bail!(
+            "backends differ after {} observations: only with {} stack, {} at step {}",
+            internal.len().min(external.len()),
+            backend,
+            next,
+            step
+        );
+    }
+
+    Ok(())
+}
+
+/// Runs the program compiled for `stack_config`, giving what it did, each
+/// with the step it did it on, and whether it ended.
+fn observe<F>(
+    text: &str,
+    options: &parser::CompileOptions,
+    stack_config: StackConfig,
+    vars: &[&str],
+    max_steps: usize,
+    setup: &F,
+) -> Result<(Vec<(usize, Observation)>, bool)>
+where
+    F: Fn(&mut Emulator),
+{
+    let cell = match &stack_config {
+        StackConfig::External(ext) => Some(Cell::new(ext.cell_name.clone())),
+        StackConfig::Internal(_) => None,
+    };
+    let options = parser::CompileOptions {
+        stack_config: Some(stack_config),
+        ..options.clone()
+    };
+    let ir = parser::parse_with_options(text, &options).context("compile")?;
+    let (output, _) = ir.generate().context("generate")?;
+    let mut emu = Emulator::new(cell, &output.join("\n")).context("load")?;
+    setup(&mut emu);
+
+    let mut values: Vec<Value> = vars.iter().map(|var| emu.var(var)).collect();
+    let mut observations = Vec::default();
+    while emu.steps() < max_steps {
+        let events = emu.step();
+        for event in events.iter() {
+            if let Event::Printed { target, text } = event {
+                observations.push((
+                    emu.steps(),
+                    Observation::Printed(target.clone(), text.clone()),
+                ));
+            }
+        }
+
+        for (var, value) in vars.iter().zip(values.iter_mut()) {
+            let new = emu.var(var);
+            if new != *value {
+                *value = new.clone();
+                observations.push((emu.steps(), Observation::Set(Symbol::new(var), new)));
+            }
+        }
+
+        if events.is_empty() || events.contains(&Event::Ended) {
+            return Ok((observations, true));
+        }
+    }
+
+    Ok((observations, false))
+}
@@ -0,0 +1,119 @@
+use crate::*;
+
+/// How long `Benchmark::run` runs a program for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BenchmarkLength {
+    /// Instructions run.
+    Steps(usize),
+
+    /// Game ticks, as `Emulator::ticks` counts them, so that time the program
+    /// spends in `wait` counts too.
+    Ticks(u64),
+}
+
+/// How many instructions each iteration of a program's work takes, measured
+/// by running it in the emulator and counting the instructions between each
+/// `benchmark_lap` it reaches. This is what decides how often the work gets
+/// done in game, so comparing it before and after a change shows what an
+/// optimization bought.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Benchmark {
+    /// The instructions run in all.
+    pub steps: usize,
+    pub ticks: u64,
+
+    /// How many instructions had run when each lap was reached, in order.
+    pub laps: Vec<usize>,
+}
+
+impl Benchmark {
+    /// Runs `emu` for `length`, or until the program reaches a `stop`,
+    /// counting laps at the addresses of its source map's `laps`.
+    pub fn run(emu: &mut Emulator, length: BenchmarkLength) -> Benchmark {
+        // A lap at the very end of the program is reached by going on from
+        // the start.
+        let laps: Vec<usize> = emu
+            .source_map()
+            .laps
+            .iter()
+            .map(|&address| match emu.instruction(address) {
+                Some(_) => address,
+                None => 0,
+            })
+            .collect();
+
+        let mut benchmark = Benchmark::default();
+        loop {
+            let done = match length {
+                BenchmarkLength::Steps(steps) => emu.steps() >= steps,
+                BenchmarkLength::Ticks(ticks) => emu.ticks() >= ticks,
+            };
+            let ip = emu.counter();
+            if done || matches!(emu.instruction(ip), None | Some(Instruction::Stop)) {
+                break;
+            }
+            if laps.contains(&ip) {
+                benchmark.laps.push(emu.steps());
+            }
+            emu.step();
+        }
+        benchmark.steps = emu.steps();
+        benchmark.ticks = emu.ticks();
+        benchmark
+    }
+
+    /// The instructions run between each lap and the next.
+    pub fn lap_steps(&self) -> Vec<usize> {
+        self.laps.windows(2).map(|pair| pair[1] - pair[0]).collect()
+    }
+
+    /// The mean of `lap_steps`, if there were at least two laps.
+    pub fn per_lap(&self) -> Option<f64> {
+        let lap_steps = self.lap_steps();
+        if lap_steps.is_empty() {
+            return None;
+        }
+        Some(lap_steps.iter().sum::<usize>() as f64 / lap_steps.len() as f64)
+    }
+
+    /// How `new` compares to this, as `<old> -> <new> per lap (<change>%)`,
+    /// if both had laps to compare.
+    pub fn comparison(&self, new: &Benchmark) -> Option<String> {
+        let (old, new) = (self.per_lap()?, new.per_lap()?);
+        Some(format!(
+            "{:.1} -> {:.1} instructions per lap ({:+.1}%)",
+            old,
+            new,
+            (new - old) * 100.0 / old
+        ))
+    }
+}
+
+/// The totals, then the mean, fewest, and most instructions per lap.
+impl std::fmt::Display for Benchmark {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Ran {} instructions in {} ticks, reaching {} laps",
+            self.steps,
+            self.ticks,
+            self.laps.len()
+        )?;
+        let lap_steps = self.lap_steps();
+        match (
+            self.per_lap(),
+            lap_steps.iter().min(),
+            lap_steps.iter().max(),
+        ) {
+            (Some(mean), Some(min), Some(max)) => writeln!(
+                f,
+                "Per lap: {:.1} instructions on average, {} fewest, {} most",
+                mean, min, max
+            ),
+            _ => writeln!(
+                f,
+                "Too few laps to measure; mark the end of each iteration with benchmark_lap"
+            ),
+        }
+    }
+}
@@ -1,47 +1,865 @@
-use std::convert::AsRef;
-use std::io::Write;
+use std::convert::{TryFrom, TryInto};
+use std::io::{Read, Write};
 
-use anyhow::Context;
+use anyhow::{bail, Context, Result};
 
 use routerbolt::*;
 
-fn main_internal() -> Result<()> {
-    let args: Vec<_> = std::env::args().collect();
+/// How often `--watch` checks whether the input has changed.
+const WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// The exit status when the program has errors, or for `lint`, warnings.
+const EXIT_ERRORS: i32 = 1;
+
+/// The exit status when compiling fails for any other reason, such as bad
+/// usage or a file that can't be read.
+const EXIT_FAILURE: i32 = 2;
+
+/// How warnings and errors are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MessageFormat {
+    Human,
+
+    /// One JSON object per line, as `Diagnostic::to_json` gives.
+    Json,
+}
+
+impl MessageFormat {
+    /// Reads `--message-format=<human|json>`, giving whether `flag` was it.
+    fn parse_flag(&mut self, flag: &str) -> Result<bool> {
+        let value = match flag.strip_prefix("--message-format=") {
+            Some(value) => value,
+            None => return Ok(false),
+        };
+        *self = match value {
+            "human" => MessageFormat::Human,
+            "json" => MessageFormat::Json,
+            _ => bail!("--message-format must be human or json"),
+        };
+        Ok(true)
+    }
+
+    /// `diagnostic` from the source at `path`, as a line of output.
+    fn format(self, diagnostic: &Diagnostic, path: &str) -> String {
+        match self {
+            MessageFormat::Human => diagnostic.to_string(),
+            MessageFormat::Json => diagnostic.to_json(Some(path).filter(|path| *path != "-")),
+        }
+    }
+}
+
+/// Programs that put what they read on the system clipboard, tried in order
+/// until one runs: those of macOS, Windows, Wayland, and X.
+const CLIPBOARD_COMMANDS: &[&[&str]] = &[
+    &["pbcopy"],
+    &["clip"],
+    &["wl-copy"],
+    &["xclip", "-selection", "clipboard"],
+    &["xsel", "--clipboard", "--input"],
+];
+
+/// Writes what the compiler logs to stderr, for `--verbose`.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!(
+                "[{} {}] {}",
+                record.level().as_str().to_lowercase(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
 
-    let (inp, outp) = if args.len() == 3 {
-        (&args[1], &args[2])
+/// Takes `--verbose` or `-v` out of `args` to log each stage of compiling,
+/// and `-vv` to also log each op as it's sized and generated. Warnings, such
+/// as an op generating more code than its size, are always logged.
+fn init_logging(args: &mut Vec<String>) {
+    let level = if args.iter().any(|arg| arg == "-vv") {
+        log::LevelFilter::Trace
+    } else if args.iter().any(|arg| arg == "-v" || arg == "--verbose") {
+        log::LevelFilter::Debug
     } else {
-        eprintln!("Usage {} <infile> <outifle>", &args[0]);
+        log::LevelFilter::Warn
+    };
+    args.retain(|arg| !matches!(arg.as_str(), "-v" | "-vv" | "--verbose"));
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+fn main_internal() -> Result<()> {
+    let mut args: Vec<_> = std::env::args().collect();
+    init_logging(&mut args);
+    if args.get(1).map(String::as_str) == Some("lint") {
+        return lint(&args);
+    }
+    if args.get(1).map(String::as_str) == Some("decompile") {
+        return decompile_file(&args);
+    }
+    if args.get(1).map(String::as_str) == Some("build") {
+        return build(&args);
+    }
+    if args.get(1).map(String::as_str) == Some("diff") {
+        return diff(&args);
+    }
+    if args.get(1).map(String::as_str) == Some("bench") {
+        return bench(&args);
+    }
+    if args.get(1).map(String::as_str) == Some("test") {
+        return test(&args);
+    }
+
+    let usage = format!(
+        "Usage {} <infile|-> <outfile|-> [--profile <debug|release>] [--stack-config \"<stack_config args>\"] [--eliminate-dead-code] [--peephole] [--thread-jumps] [--strip-unused-functions] [--instruction-limit <n|none>] [--epilogue <auto|end|stop|loop>] [--stats] [--source-map] [--symbols] [--schematic] [--emit=json|symbolic|ir|ir-text] [--resolve] [--annotated[=<path>]] [--no-annotated] [--quiet] [--watch] [--clipboard] [--banner \"<template>\"] [--variant <name> \"<stack_config args>\"]... [--message-format=human|json] [--verbose|-vv] [--help|-h]",
+        &args[0]
+    );
+
+    let profile = profile(&args)?;
+    let mut options = parser::CompileOptions::with_profile(profile);
+    let mut stats = false;
+    let mut source_map = false;
+    let mut symbols = false;
+    let mut schematic = false;
+    let mut json = false;
+    let mut symbolic = false;
+    let mut emit_ir = false;
+    let mut emit_ir_text = false;
+    let mut resolve = false;
+    let mut quiet = false;
+    let mut watch = false;
+    let mut clipboard = false;
+    let mut banner = None;
+    let mut message_format = MessageFormat::Human;
+
+    // Each other version of the program to write, by the name it's written
+    // under and the stack it's compiled with.
+    let mut variants = Vec::default();
+
+    // The annotated listing goes next to the output in the debug profile,
+    // unless asked for elsewhere or not at all.
+    let mut annotated_path = None;
+    let mut annotated = profile == parser::Profile::Debug;
+
+    // Flags may come before, between, or after the input and output files,
+    // either of which may be `-` for stdin or stdout.
+    let mut files = Vec::default();
+    let mut flags = args[1..].iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--help" | "-h" => {
+                println!("{}", usage);
+                return Ok(());
+            }
+            "--annotated" => annotated = true,
+            "--no-annotated" => annotated = false,
+            "--quiet" => quiet = true,
+            "--watch" => watch = true,
+            "--clipboard" => clipboard = true,
+            "--banner" => banner = Some(flags.next().context("--banner requires a template")?),
+            "--variant" => {
+                let name = flags.next().context("--variant requires a name")?;
+                let config = flags.next().context("--variant requires a stack config")?;
+                variants.push((name, config));
+            }
+            "--stats" => stats = true,
+            "--source-map" => source_map = true,
+            "--symbols" => symbols = true,
+            "--schematic" => schematic = true,
+            "--emit=json" => json = true,
+            "--emit=symbolic" => symbolic = true,
+            "--emit=ir" => emit_ir = true,
+            "--emit=ir-text" => emit_ir_text = true,
+            "--resolve" => resolve = true,
+            flag if flag.starts_with("--annotated=") => {
+                annotated = true;
+                annotated_path = Some(flag["--annotated=".len()..].to_string());
+            }
+            flag if flag == "-" || !flag.starts_with('-') => files.push(flag),
+            flag => {
+                if !compile_option(&mut options, flag, &mut flags)?
+                    && !message_format.parse_flag(flag)?
+                {
+                    bail!("unknown option {}", flag);
+                }
+            }
+        }
+    }
+
+    // Variants have every other option, wherever it was given.
+    let variants = variants
+        .into_iter()
+        .map(|(name, config)| {
+            let mut variant = options.clone();
+            variant
+                .set_stack_config(config)
+                .with_context(|| format!("--variant {}", name))?;
+            Ok((name, variant))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let (inp, outp) = match files[..] {
+        [inp, outp] => (inp, outp),
+        _ => {
+            eprintln!("{}", usage);
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+
+    // Files written alongside the output are named after it, so there must
+    // be one.
+    if outp == "-" && (symbolic || source_map || symbols || schematic || !variants.is_empty()) {
+        bail!("--emit=symbolic, --source-map, --symbols, --schematic, and --variant need an output file, not stdout");
+    }
+    let beside_output = |extension: &str| format!("{}.{}", outp, extension);
+
+    // With `--watch`, the parts of the program an edit leaves as they were
+    // are reused from the last compile, rather than lowered again.
+    let mut cache = parser::LoweringCache::default();
+    let mut variant_caches: Vec<parser::LoweringCache> =
+        variants.iter().map(|_| Default::default()).collect();
+    let mut compile = || -> Result<()> {
+        // Parse input into series of `Op`, and determine the offset of each
+        // instruction so that we can use them in the second pass. This requires
+        // knowing how many instructions each will generate.
+        let input_text = read_input(inp)?;
+        let input_text = input_text.as_str();
+
+        // The banner is filled in afresh each time, as the source changes.
+        let name = std::path::Path::new(inp)
+            .file_stem()
+            .map_or("stdin".into(), |stem| stem.to_string_lossy());
+        let with_banner = |options: &parser::CompileOptions| {
+            let mut options = options.clone();
+            options.banner =
+                banner.map(|template| parser::expand_banner(template, &name, input_text));
+            options
+        };
+
+        // The input is the output of `--emit=symbolic`, with its label table
+        // alongside.
+        if resolve {
+            if inp == "-" {
+                bail!("--resolve reads the label table beside the input, so needs an input file");
+            }
+            let labels =
+                std::fs::read_to_string(format!("{}.labels", inp)).context("read label table")?;
+            let program = SymbolicProgram::parse(input_text, &labels).context("parse")?;
+            let output = program.resolve().context("resolve")?;
+            return write_output(outp, &lines(&output)).context("write output file");
+        }
+
+        let ir = parser::parse_with_cache(input_text, &with_banner(&options), &mut cache)
+            .context("parse")?;
+        if !quiet {
+            for warning in ir.warnings() {
+                eprintln!("{}", message_format.format(&warning, inp));
+            }
+        }
+
+        let (output, annotated_output, code_stats) =
+            generate_with_stats(&ir).context("generate")?;
+        // Stats go to stderr when the program goes to stdout, to keep it clean.
+        if stats && outp == "-" {
+            eprint!("{}", code_stats);
+        } else if stats {
+            print!("{}", code_stats);
+        }
+
+        if emit_ir {
+            write_output(outp, &ir.to_json().context("serialize ir")?)
+                .context("write output file")?;
+        } else if emit_ir_text {
+            write_output(outp, &print(&ir).context("print ir")?).context("write output file")?;
+        } else if json {
+            let json = generate_json(&ir).context("generate json")?;
+            write_output(outp, &json).context("write output file")?;
+        } else if symbolic {
+            let labels = beside_output("labels");
+            let program = SymbolicProgram::new(&ir, &output);
+            write_output(outp, &program.to_string()).context("write output file")?;
+            std::fs::write(labels, program.labels_text()).context("write label table")?;
+        } else {
+            write_output(outp, &lines(&output)).context("write output file")?;
+        }
+
+        // Variants differ only in their stack, so their warnings would be the
+        // same, and only the program is written for them.
+        for ((name, variant), cache) in variants.iter().zip(variant_caches.iter_mut()) {
+            let ir = parser::parse_with_cache(input_text, &with_banner(variant), cache)
+                .with_context(|| format!("parse variant {}", name))?;
+            let (output, _) = ir
+                .generate()
+                .with_context(|| format!("generate variant {}", name))?;
+            write_output(&beside_output(name), &lines(&output)).context("write variant")?;
+        }
+
+        // Whatever is written, what's copied is the program, ready to paste
+        // into a processor.
+        if clipboard {
+            copy_to_clipboard(&lines(&output)).context("copy to clipboard")?;
+        }
+
+        // Without a path, there's nowhere to put the listing when writing to
+        // stdout, so it's only written if asked for.
+        let annotated_path = match &annotated_path {
+            Some(path) => Some(path.clone()),
+            None if outp == "-" => None,
+            None => Some(beside_output("annotated")),
+        };
+        if let (true, Some(path)) = (annotated, annotated_path) {
+            write_output(&path, &lines(&annotated_output)).context("write annotated file")?;
+        }
+        if source_map {
+            std::fs::write(beside_output("map"), ir.source_map().to_string())
+                .context("write source map")?;
+        }
+        if symbols {
+            std::fs::write(beside_output("sym"), ir.symbol_file().to_string())
+                .context("write symbol file")?;
+        }
+        if schematic {
+            std::fs::write(beside_output("msch"), routerbolt::schematic(&ir, &output))
+                .context("write schematic")?;
+            std::fs::write(beside_output("msch.txt"), schematic_base64(&ir, &output))
+                .context("write schematic")?;
+        }
+
+        Ok(())
+    };
+
+    // Errors from the compiler are written as the warnings are, and any
+    // others with no line.
+    let report = |e: &anyhow::Error| match message_format {
+        MessageFormat::Human => eprintln!("{:?}", e),
+        MessageFormat::Json => {
+            for diagnostic in error_diagnostics(e) {
+                eprintln!("{}", message_format.format(&diagnostic, inp));
+            }
+        }
+    };
+
+    if !watch {
+        return match compile() {
+            Err(e) if message_format == MessageFormat::Json => {
+                report(&e);
+                std::process::exit(exit_status(&e));
+            }
+            result => result,
+        };
+    }
+
+    // Recompile whenever the input changes, until interrupted.
+    if inp == "-" {
+        bail!("--watch needs an input file, not stdin");
+    }
+    let mut last_modified = None;
+    loop {
+        let modified = std::fs::metadata(inp).and_then(|m| m.modified()).ok();
+        if modified != last_modified {
+            last_modified = modified;
+            match compile() {
+                Ok(()) => eprintln!("Compiled {} to {}", inp, outp),
+                Err(e) => report(&e),
+            }
+        }
+        std::thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// The profile `args` ask for. It's read before the other options, since
+/// they adjust it.
+fn profile(args: &[String]) -> Result<parser::Profile> {
+    match args.iter().position(|arg| arg == "--profile") {
+        Some(j) => {
+            let value = args.get(j + 1).context("--profile requires a value")?;
+            value.as_str().try_into().context("--profile")
+        }
+        None => Ok(parser::Profile::Debug),
+    }
+}
+
+/// Applies `flag` to `options` if it's one of the options for how to compile,
+/// taking any value it has from `flags`, and gives whether it was. The
+/// profile is skipped over, having been read by `profile`.
+fn compile_option(
+    options: &mut parser::CompileOptions,
+    flag: &str,
+    flags: &mut std::slice::Iter<String>,
+) -> Result<bool> {
+    match flag {
+        "--stack-config" => {
+            let value = flags.next().context("--stack-config requires a value")?;
+            options.set_stack_config(value).context("--stack-config")?;
+        }
+        "--profile" => {
+            flags.next();
+        }
+        "--eliminate-dead-code" => options.eliminate_dead_code = true,
+        "--peephole" => options.peephole = true,
+        "--thread-jumps" => options.thread_jumps = true,
+        "--strip-unused-functions" => options.strip_unused_functions = true,
+        "--instruction-limit" => {
+            let value = flags
+                .next()
+                .context("--instruction-limit requires a value")?;
+            options.instruction_limit = match value.as_str() {
+                "none" => None,
+                value => Some(value.parse().context("--instruction-limit")?),
+            };
+        }
+        "--epilogue" => {
+            let value = flags.next().context("--epilogue requires a value")?;
+            options.epilogue = Some(value.as_str().try_into().context("--epilogue")?);
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Compiles two versions of a program, or one with two sets of options, and
+/// shows how the code differs. Exits with status 1 if it does.
+fn diff(args: &[String]) -> Result<()> {
+    let (old, new) = match versions(&args[2..])? {
+        Some(versions) => versions,
+        None => {
+            eprintln!(
+                "Usage {} diff <old|-> [<new|->] [options] [--vs [<new|->] [options]]",
+                &args[0]
+            );
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+    let (old_path, old_text, old_options) = old;
+    let (new_path, new_text, new_options) = new;
+    let compile = |text: &str, options: &parser::CompileOptions| -> Result<DiffSide> {
+        let ir = IntermediateRepresentation::parse_with_options(text, options).context("parse")?;
+        let (output, _) = ir.generate().context("generate")?;
+        Ok(DiffSide::new(&ir, &output))
+    };
+    let old = compile(&old_text, &old_options).with_context(|| format!("old {}", old_path))?;
+    let new = compile(&new_text, &new_options).with_context(|| format!("new {}", new_path))?;
+
+    let diff = ProgramDiff::new(old, new);
+    print!("{}", diff);
+    if !diff.is_empty() {
+        std::process::exit(EXIT_ERRORS);
+    }
+    Ok(())
+}
+
+/// Runs a program in the emulator for a while and reports how many
+/// instructions each lap of its work takes, or compares two versions as
+/// `diff` does.
+fn bench(args: &[String]) -> Result<()> {
+    let mut length = BenchmarkLength::Steps(100_000);
+    let mut rest = Vec::default();
+    let mut flags = args[2..].iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--steps" => {
+                let value = flags.next().context("--steps requires a value")?;
+                length = BenchmarkLength::Steps(value.parse().context("--steps")?);
+            }
+            "--ticks" => {
+                let value = flags.next().context("--ticks requires a value")?;
+                length = BenchmarkLength::Ticks(value.parse().context("--ticks")?);
+            }
+            _ => rest.push(flag.clone()),
+        }
+    }
+
+    let compare = rest.iter().any(|arg| arg == "--vs")
+        || rest
+            .iter()
+            .filter(|arg| *arg == "-" || !arg.starts_with('-'))
+            .count()
+            > 1;
+    let (old, new) = match versions(&rest)? {
+        Some(versions) => versions,
+        None => {
+            eprintln!(
+                "Usage {} bench <infile|-> [<new|->] [options] [--steps <n>|--ticks <n>] [--vs [<new|->] [options]]",
+                &args[0]
+            );
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+
+    let run = |(path, text, options): &Version| -> Result<Benchmark> {
+        let ir = IntermediateRepresentation::parse_with_options(text, options).context("parse")?;
+        let (output, _) = ir.generate().context("generate")?;
+        let cells = ir.stack_cells().into_iter().map(Cell::new);
+        let mut emu = Emulator::new(cells, &output.join("\n"))
+            .with_context(|| format!("init emulator for {}", path))?;
+        emu.set_source_map(&ir.source_map());
+        Ok(Benchmark::run(&mut emu, length))
+    };
+    let old_benchmark = run(&old)?;
+    if !compare {
+        print!("{}", old_benchmark);
         return Ok(());
+    }
+
+    let new_benchmark = run(&new)?;
+    print!("Old: {}", old_benchmark);
+    print!("New: {}", new_benchmark);
+    if let Some(comparison) = old_benchmark.comparison(&new_benchmark) {
+        println!("{}", comparison);
+    }
+    Ok(())
+}
+
+/// A version of a program to compare, as its path, source, and options.
+type Version = (String, String, parser::CompileOptions);
+
+/// The two versions of a program that `diff` and `bench` compare, from
+/// `args` after the subcommand, or `None` if the files given don't make two.
+/// The new version has the options of the old, followed by its own after
+/// `--vs`, and is the same source unless another is given.
+fn versions(args: &[String]) -> Result<Option<(Version, Version)>> {
+    let (old_args, new_args) = match args.iter().position(|arg| arg == "--vs") {
+        Some(split) => (&args[..split], &args[split + 1..]),
+        None => (args, &args[args.len()..]),
+    };
+    let old_profile = profile(old_args)?;
+    let new_profile = if new_args.iter().any(|arg| arg == "--profile") {
+        profile(new_args)?
+    } else {
+        old_profile
     };
+    let mut old_options = parser::CompileOptions::with_profile(old_profile);
+    let mut new_options = parser::CompileOptions::with_profile(new_profile);
+    let old_files = diff_options(old_args, &mut old_options)?;
+    diff_options(old_args, &mut new_options)?;
+    let new_files = diff_options(new_args, &mut new_options)?;
 
-    // Parse input into series of `Op`, and determine the offset of each
-    // instruction so that we can use them in the second pass. This requires
-    // knowing how many instructions each will generate.
-    let input_text = std::fs::read(&inp).context("read input file")?;
-    let input_text = std::str::from_utf8(&input_text).context("decode input as utf8")?;
+    let (old_path, new_path) = match (&old_files[..], &new_files[..]) {
+        ([old], []) => (old, old),
+        ([old], [new]) | ([old, new], []) => (old, new),
+        _ => return Ok(None),
+    };
 
-    let ir = IntermediateRepresentation::parse(input_text).context("parse")?;
-    let (output, annotated) = generate(&ir).context("generate")?;
+    let old_text = read_input(old_path)?;
+    let new_text = if new_path == old_path {
+        old_text.clone()
+    } else {
+        read_input(new_path)?
+    };
+    Ok(Some((
+        (old_path.clone(), old_text, old_options),
+        (new_path.clone(), new_text, new_options),
+    )))
+}
 
-    write_file(outp.as_ref(), &output).context("write output file")?;
-    write_file(format!("{}.annotated", &outp).as_ref(), &annotated)
-        .context("write annotated file")?;
+/// Applies the options among `args` to `options`, and gives the rest, which
+/// are files.
+fn diff_options(args: &[String], options: &mut parser::CompileOptions) -> Result<Vec<String>> {
+    let mut files = Vec::default();
+    let mut flags = args.iter();
+    while let Some(flag) = flags.next() {
+        if flag == "-" || !flag.starts_with('-') {
+            files.push(flag.clone());
+        } else if !compile_option(options, flag, &mut flags)? {
+            bail!("unknown option {}", flag);
+        }
+    }
+    Ok(files)
+}
+
+/// Compiles a program and runs it in the emulator, checking the assertions
+/// in its source. Exits with status 1 if any fail.
+fn test(args: &[String]) -> Result<()> {
+    let mut options = parser::CompileOptions::with_profile(profile(&args[2..])?);
+    let inp = match &diff_options(&args[2..], &mut options)?[..] {
+        [inp] => inp.clone(),
+        _ => {
+            eprintln!(
+                "Usage {} test <infile|-> [--profile <debug|release>] [--stack-config \"<stack_config args>\"] [compile options]",
+                &args[0]
+            );
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
 
+    let input_text = read_input(&inp)?;
+    let results = run_assertions(&input_text, &options)?;
+    for result in results.iter() {
+        println!("{}", result);
+    }
+    let failed = results.iter().filter(|result| !result.passed).count();
+    println!("{} passed, {} failed", results.len() - failed, failed);
+    if failed > 0 {
+        std::process::exit(EXIT_ERRORS);
+    }
     Ok(())
 }
 
-fn write_file(path: &std::path::Path, lines: &Vec<String>) -> Result<()> {
-    let mut fd = std::fs::File::create(path)?;
-    for line in lines.iter() {
-        fd.write(line.as_ref())?;
-        fd.write(b"\n")?;
+/// Checks a program for warnings without generating it, exiting with status
+/// 1 if there are any, or it fails to parse.
+fn lint(args: &[String]) -> Result<()> {
+    let mut lints = Lint::DEFAULT.to_vec();
+    let mut options = parser::CompileOptions::default();
+    let mut message_format = MessageFormat::Human;
+    let mut inp = None;
+    let mut flags = args[2..].iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--enable" | "--disable" => {
+                let value = flags
+                    .next()
+                    .with_context(|| format!("{} requires a value", flag))?;
+                let named = match value.as_str() {
+                    "all" => Lint::ALL.to_vec(),
+                    value => vec![Lint::try_from(value).context(flag.clone())?],
+                };
+                lints.retain(|lint| !named.contains(lint));
+                if flag == "--enable" {
+                    lints.extend(named);
+                }
+            }
+            "--stack-config" => {
+                let value = flags.next().context("--stack-config requires a value")?;
+                options.set_stack_config(value).context("--stack-config")?;
+            }
+            flag if inp.is_none() && (flag == "-" || !flag.starts_with('-')) => inp = Some(flag),
+            flag => {
+                if !message_format.parse_flag(flag)? {
+                    bail!("unknown option {}", flag);
+                }
+            }
+        }
+    }
+
+    let inp = match inp {
+        Some(inp) => inp,
+        None => {
+            eprintln!(
+                "Usage {} lint <infile|-> [--enable <warning|all>] [--disable <warning|all>] [--stack-config \"<stack_config args>\"] [--message-format=human|json]",
+                &args[0]
+            );
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+
+    let input_text = read_input(inp)?;
+    let ir = match IntermediateRepresentation::parse_with_options(&input_text, &options) {
+        Ok(ir) => ir,
+        Err(e) => {
+            for diagnostic in e.diagnostics() {
+                println!("{}", message_format.format(&diagnostic, inp));
+            }
+            std::process::exit(EXIT_ERRORS);
+        }
+    };
+    let warnings = find_lints(&ir, &lints);
+    for warning in warnings.iter() {
+        println!("{}", message_format.format(warning, inp));
+    }
+    if !warnings.is_empty() {
+        std::process::exit(EXIT_ERRORS);
     }
     Ok(())
 }
 
+/// Writes routerbolt source for a Mindustry logic program.
+fn decompile_file(args: &[String]) -> Result<()> {
+    if args.len() != 4 {
+        eprintln!("Usage {} decompile <infile|-> <outfile|->", &args[0]);
+        std::process::exit(EXIT_FAILURE);
+    }
+
+    let input_text = read_input(&args[2])?;
+    let source = decompile(&input_text).context("decompile")?;
+    write_output(&args[3], &source).context("write output file")
+}
+
+/// Compiles each target of a project, writing each to its output, and
+/// reports their sizes. Exits with status 1 if any fail.
+fn build(args: &[String]) -> Result<()> {
+    let mut path = None;
+    let mut names = Vec::default();
+    let mut stats = false;
+    let mut quiet = false;
+    let mut flags = args[2..].iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--target" => names.push(flags.next().context("--target requires a value")?),
+            "--stats" => stats = true,
+            "--quiet" => quiet = true,
+            flag if path.is_none() && !flag.starts_with('-') => path = Some(flag),
+            _ => {
+                eprintln!(
+                    "Usage {} build [manifest, default {}] [--target <name>]... [--stats] [--quiet]",
+                    &args[0], MANIFEST_NAME
+                );
+                bail!("unknown option {}", flag);
+            }
+        }
+    }
+
+    let manifest = Manifest::read(std::path::Path::new(path.unwrap_or(MANIFEST_NAME)))?;
+    let targets = if names.is_empty() {
+        manifest.targets.iter().collect()
+    } else {
+        names
+            .iter()
+            .map(|name| {
+                manifest
+                    .target(name)
+                    .with_context(|| format!("target {} is not defined", name))
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    let mut total = 0;
+    let mut failed = 0;
+    for target in targets.iter() {
+        let output = manifest.path(&target.output);
+        let result = manifest.build(target).and_then(|build| {
+            if let Some(dir) = output.parent() {
+                std::fs::create_dir_all(dir).context("create output directory")?;
+            }
+            std::fs::write(&output, lines(&build.code)).context("write output file")?;
+            Ok(build)
+        });
+        match result {
+            Ok(build) => {
+                if !quiet {
+                    for warning in build.warnings.iter() {
+                        eprintln!("{}", warning);
+                    }
+                }
+                println!(
+                    "{}: {} instructions -> {}",
+                    target.name,
+                    build.stats.total,
+                    output.display()
+                );
+                if stats {
+                    print!("{}", build.stats);
+                }
+                total += build.stats.total;
+            }
+            Err(e) => {
+                eprintln!("{}: {:#}", target.name, e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!(
+        "Built {} of {} targets, {} instructions in total",
+        targets.len() - failed,
+        targets.len(),
+        total
+    );
+    if failed > 0 {
+        std::process::exit(EXIT_ERRORS);
+    }
+    Ok(())
+}
+
+/// Reads the file at `path`, or stdin for `-`.
+fn read_input(path: &str) -> Result<String> {
+    let mut input = Vec::default();
+    if path == "-" {
+        std::io::stdin()
+            .read_to_end(&mut input)
+            .context("read stdin")?;
+    } else {
+        input = std::fs::read(path).context("read input file")?;
+    }
+    String::from_utf8(input).context("decode input as utf8")
+}
+
+/// Puts `text` on the system clipboard, using the first of
+/// `CLIPBOARD_COMMANDS` installed.
+fn copy_to_clipboard(text: &str) -> Result<()> {
+    for command in CLIPBOARD_COMMANDS {
+        let mut child = match std::process::Command::new(command[0])
+            .args(&command[1..])
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(_) => continue,
+        };
+        child
+            .stdin
+            .take()
+            .context("open clipboard program's stdin")?
+            .write_all(text.as_bytes())
+            .with_context(|| format!("write to {}", command[0]))?;
+        let status = child
+            .wait()
+            .with_context(|| format!("run {}", command[0]))?;
+        if !status.success() {
+            bail!("{} failed with {}", command[0], status);
+        }
+        return Ok(());
+    }
+
+    let names: Vec<_> = CLIPBOARD_COMMANDS
+        .iter()
+        .map(|command| command[0])
+        .collect();
+    bail!("no clipboard program found; tried {}", names.join(", "));
+}
+
+/// `lines`, each ending in a newline.
+fn lines(lines: &[String]) -> String {
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// Writes `text` to the file at `path`, or to stdout for `-`.
+fn write_output(path: &str, text: &str) -> Result<()> {
+    if path == "-" {
+        std::io::stdout().write_all(text.as_bytes())?;
+    } else {
+        std::fs::write(path, text)?;
+    }
+    Ok(())
+}
+
+/// Every problem `e` reports: those of the `CompileError` it comes from, or
+/// else just the one, with no line.
+fn error_diagnostics(e: &anyhow::Error) -> Vec<Diagnostic> {
+    match e.downcast_ref::<CompileError>() {
+        Some(error) => error.diagnostics(),
+        None => vec![Diagnostic {
+            severity: Severity::Error,
+            lint: None,
+            error_code: None,
+            line: None,
+            message: format!("{:#}", e),
+        }],
+    }
+}
+
+/// `EXIT_ERRORS` if `e` is a problem with the program, or else
+/// `EXIT_FAILURE`.
+fn exit_status(e: &anyhow::Error) -> i32 {
+    match e.downcast_ref::<CompileError>() {
+        Some(_) => EXIT_ERRORS,
+        None => EXIT_FAILURE,
+    }
+}
+
 fn main() {
     if let Err(e) = main_internal().context("main") {
         eprintln!("{:?}", &e);
+        std::process::exit(exit_status(&e));
     }
 }
@@ -1,6 +1,7 @@
-use std::rc::Rc;
+use std::convert::TryInto;
+use std::io::{BufRead, Write};
 
-use anyhow::Context;
+use anyhow::{Context, Result};
 
 use routerbolt::*;
 
@@ -9,7 +10,7 @@ fn main_internal() -> Result<()> {
 
     if args.len() < 4 || (args[1] != "stack" && args[1] != "cell") {
         eprintln!(
-            "Usage: {} <stack|cell> <size|name> <infile> <max_steps> [watches]",
+            "Usage: {} <stack|cell> <size|name> <infile> <max_steps> [--profile <debug|release>] [--execution-profile] [--interactive] [--mem <cell>=<file>]... [watches]",
             &args[0]
         );
         return Ok(());
@@ -20,27 +21,126 @@ fn main_internal() -> Result<()> {
         None
     } else {
         // StackConfig::External(Rc::new(args[2].to_string()));
-        Some(Cell::new(Rc::new(args[2].to_string())))
+        Some(Cell::new(args[2].as_str()))
     };
 
     let inp = &args[3];
     let max_steps: usize = args[4].parse().context("max_steps must be an integer")?;
-    let watches: Vec<Rc<String>> = args[5..]
-        .iter()
-        .map(|w| w.to_string())
-        .map(|s| Rc::new(s.to_string()))
-        .collect();
+    let mut extra = &args[5..];
+
+    // With a profile, the input is source to compile with it first, for the
+    // stack given above.
+    let mut profile = None;
+    if extra.first().map(String::as_str) == Some("--profile") {
+        let value = extra.get(1).context("--profile requires a value")?;
+        let value: parser::Profile = value.as_str().try_into().context("--profile")?;
+        profile = Some(value);
+        extra = &extra[2..];
+    }
+
+    // Report how much each part of the program ran.
+    let execution_profile = extra.first().map(String::as_str) == Some("--execution-profile");
+    if execution_profile {
+        extra = &extra[1..];
+    }
+
+    // Take commands from stdin rather than running straight through, with
+    // `max_steps` as the most each command runs.
+    let interactive = extra.first().map(String::as_str) == Some("--interactive");
+    if interactive {
+        extra = &extra[1..];
+    }
+
+    // Start memory cells with the contents of files, adding any other than
+    // the stack's.
+    let mut cells: Vec<Cell> = cell.into_iter().collect();
+    while extra.first().map(String::as_str) == Some("--mem") {
+        let value = extra.get(1).context("--mem requires a value")?;
+        let (name, path) = value.split_once('=').context("--mem takes <cell>=<file>")?;
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("read memory file {}", path))?;
+        if !cells.iter().any(|cell| cell.name().as_str() == name) {
+            cells.push(Cell::new(name));
+        }
+        let cell = cells
+            .iter_mut()
+            .find(|cell| cell.name().as_str() == name)
+            .unwrap();
+        cell.load(&text)
+            .with_context(|| format!("--mem {}", value))?;
+        extra = &extra[2..];
+    }
+
+    let watches: Vec<Symbol> = extra.iter().map(Symbol::from).collect();
 
     // Parse input into series of `Op`, and determine the offset of each
     // instruction so that we can use them in the second pass. This requires
     // knowing how many instructions each will generate.
     let input_text = std::fs::read(&inp).context("read input file")?;
     let input_text = std::str::from_utf8(&input_text).context("decode input as utf8")?;
-    let mut emu = Emulator::new(cell, &input_text).context("init emulator")?;
+    let mut source_map = None;
+    let program = match profile {
+        Some(profile) => {
+            let mut options = parser::CompileOptions::with_profile(profile);
+            let kind = if args[1] == "stack" { "size" } else { "cell" };
+            options
+                .set_stack_config(&format!("{} {}", kind, &args[2]))
+                .context("stack config")?;
+            let ir = parser::parse_with_options(input_text, &options).context("parse")?;
+            let (output, _) = generate(&ir).context("generate")?;
+            source_map = Some(ir.source_map());
+            output.join("\n")
+        }
+        None => input_text.to_string(),
+    };
+    let mut emu = Emulator::new(cells, &program).context("init emulator")?;
     emu.set_watches(watches);
+    if let Some(source_map) = source_map.as_ref() {
+        emu.set_source_map(source_map);
+    }
+    emu.set_warn_print_overflow(true);
+    if interactive {
+        return debug(Debugger::new(emu, max_steps), execution_profile);
+    }
     for line in emu.run(max_steps) {
         println!("{}", &line);
     }
+    println!("Elapsed: {} ticks", emu.ticks());
+    if execution_profile {
+        print!("{}", emu.execution_profile());
+    }
+    Ok(())
+}
+
+/// Runs `debugger` on commands from stdin until `quit` or the end of input.
+fn debug(mut debugger: Debugger, execution_profile: bool) -> Result<()> {
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(sim) ");
+        std::io::stdout().flush()?;
+        let line = match lines.next() {
+            Some(line) => line.context("read command")?,
+            None => break,
+        };
+        if matches!(line.trim(), "quit" | "q") {
+            break;
+        }
+        match debugger.execute(&line) {
+            Ok(output) => {
+                for line in output {
+                    println!("{}", line);
+                }
+            }
+            Err(e) => println!("Error: {:#}", e),
+        }
+    }
+
+    println!();
+    println!("Elapsed: {} ticks", debugger.emu.ticks());
+    if execution_profile {
+        print!("{}", debugger.emu.execution_profile());
+    }
     Ok(())
 }
 
@@ -0,0 +1,87 @@
+use crate::*;
+
+/// Several processors running at once, sharing memory cells, as when one
+/// program produces values for another to consume.
+///
+/// Each tick, the processors run in the order they were added, each for as
+/// many instructions as it runs in a tick (see
+/// `Emulator::set_instructions_per_tick`), so a value one writes in a tick is
+/// there for those after it in the same tick. A processor that is waiting
+/// sits out the ticks until its `wait` is done.
+pub struct Cluster {
+    cells: Vec<Cell>,
+    processors: Vec<Emulator>,
+    ticks: u64,
+}
+
+impl Cluster {
+    /// A cluster sharing `cells`, with no processors yet. Cell names must
+    /// be unique.
+    pub fn new<C: IntoIterator<Item = Cell>>(cells: C) -> Result<Cluster> {
+        let cells: Vec<Cell> = cells.into_iter().collect();
+        for (j, cell) in cells.iter().enumerate() {
+            if cells[..j].iter().any(|other| other.name() == cell.name()) {
+                bail!("duplicate cell {}", cell.name());
+            }
+        }
+
+        Ok(Cluster {
+            cells,
+            processors: Vec::default(),
+            ticks: 0,
+        })
+    }
+
+    /// Adds a processor running `program`, which can use all the shared
+    /// cells, and gives its index. It starts at the current tick, so `@tick`
+    /// counts from when it was added.
+    pub fn add_processor(&mut self, program: &str) -> Result<usize> {
+        let emulator = Emulator::new(None, program)?;
+        self.processors.push(emulator);
+        Ok(self.processors.len() - 1)
+    }
+
+    pub fn processor(&self, j: usize) -> &Emulator {
+        &self.processors[j]
+    }
+
+    /// The processor at `j`, e.g. to set its world or watches. Its memory
+    /// cells are those of the cluster only while the cluster runs it.
+    pub fn processor_mut(&mut self, j: usize) -> &mut Emulator {
+        &mut self.processors[j]
+    }
+
+    /// The ticks run so far.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Runs `ticks` ticks, and gives what happened on each processor.
+    /// Breakpoints and watchpoints don't stop the cluster.
+    pub fn run_ticks(&mut self, ticks: u64) -> Vec<Vec<Event>> {
+        let mut events = vec![Vec::default(); self.processors.len()];
+        for _ in 0..ticks {
+            for (processor, events) in self.processors.iter_mut().zip(events.iter_mut()) {
+                let start = processor.ticks();
+                processor.swap_cells(&mut self.cells);
+                while processor.ticks() == start && start <= self.ticks {
+                    let step = processor.step();
+                    if step.is_empty() {
+                        break;
+                    }
+                    events.extend(step);
+                }
+                processor.swap_cells(&mut self.cells);
+            }
+            self.ticks += 1;
+        }
+        events
+    }
+
+    /// Reads from the shared memory cell named `cell`, or `None` if the
+    /// address was never written.
+    pub fn get_cell_value(&self, cell: &str, address: usize) -> Option<f64> {
+        let cell = self.cells.iter().find(|c| c.name() == cell)?;
+        cell.get(address)
+    }
+}
@@ -0,0 +1,223 @@
+use crate::*;
+
+/// How many instructions each part of a program takes, to show where to look
+/// when it needs to shrink.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CodeStats {
+    /// The whole program.
+    pub total: usize,
+
+    /// Code outside any function, including the `end` placed before the
+    /// stack tables.
+    pub top_level: usize,
+
+    /// Each function, in order of address.
+    pub functions: Vec<(FunctionName, usize)>,
+
+    /// Each loop, by the source line it begins on, in order. Loops are also
+    /// counted as part of the function or top-level code they are in.
+    pub loops: Vec<(usize, usize)>,
+
+    /// The code debug checks jump to when they fail.
+    pub debug_handlers: usize,
+
+    /// The jump tables of each internal stack.
+    pub stack_tables: Vec<(String, usize)>,
+
+    /// The routines of each stack spanning several memory banks.
+    pub bank_routines: Vec<(String, usize)>,
+
+    /// The instruction limit the program was generated with, if any. See
+    /// `budget`.
+    pub limit: Option<usize>,
+}
+
+impl CodeStats {
+    /// Starts the stats for a program whose code, before any debug handlers
+    /// or stack support, is `program_size` instructions.
+    pub fn new(
+        ir: &IntermediateRepresentation,
+        program_size: usize,
+        loops: Vec<(usize, usize)>,
+    ) -> CodeStats {
+        // Functions are attributed by their range of addresses, and everything
+        // else in the program to the top level.
+        let mut functions: Vec<_> = ir
+            .functions()
+            .values()
+            .filter_map(|function| match (function.address, function.end) {
+                (Some(address), Some(end)) => Some((address, function.name.clone(), end - address)),
+                _ => None,
+            })
+            .collect();
+        functions.sort_by_key(|(address, ..)| *address.as_ref());
+
+        let functions: Vec<(FunctionName, usize)> = functions
+            .into_iter()
+            .map(|(_, name, size)| (name, size.into()))
+            .collect();
+        let in_functions: usize = functions.iter().map(|(_, size)| size).sum();
+        let handler_size: usize = DEBUG_HANDLER_SIZE.into();
+
+        CodeStats {
+            total: program_size,
+            top_level: program_size - in_functions,
+            functions,
+            loops,
+            debug_handlers: ir.debug_handlers.len() * handler_size,
+            stack_tables: Vec::default(),
+            bank_routines: Vec::default(),
+            limit: ir.instruction_limit,
+        }
+    }
+
+    pub fn add_stack_support(&mut self, stack: &StackRef, support: &StackSupport) {
+        let name = format!("stack{}", stack);
+        match support {
+            StackSupport::Tables(size) => {
+                self.stack_tables.push((name, STACK_ENTRY_SIZE * size));
+            }
+            StackSupport::BankRoutines(ext) => {
+                let size: usize = ext.bank_routine_size().into();
+                self.bank_routines.push((name, 2 * size));
+            }
+        }
+    }
+
+    /// The parts of the program that don't overlap, largest first. Loops are
+    /// left out, since they are part of other code.
+    pub fn parts(&self) -> Vec<(String, usize)> {
+        let mut parts = vec![
+            ("top-level code".to_string(), self.top_level),
+            ("debug handlers".to_string(), self.debug_handlers),
+        ];
+        for (name, size) in self.functions.iter() {
+            parts.push((format!("function {}", name), *size));
+        }
+        for (name, size) in self.stack_tables.iter() {
+            parts.push((format!("jump tables for {}", name), *size));
+        }
+        for (name, size) in self.bank_routines.iter() {
+            parts.push((format!("bank routines for {}", name), *size));
+        }
+
+        parts.retain(|(_, size)| *size > 0);
+        parts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        parts
+    }
+
+    /// How many instructions the program may take: the instruction limit, or
+    /// without one, the most a processor accepts.
+    pub fn budget(&self) -> usize {
+        self.limit.unwrap_or(MAX_INSTRUCTIONS)
+    }
+
+    /// How many more instructions fit in the budget.
+    pub fn headroom(&self) -> usize {
+        self.budget().saturating_sub(self.total)
+    }
+
+    /// Fails if the program is longer than `limit`, listing what takes up the
+    /// most room.
+    pub fn check_limit(&self, limit: usize) -> Result<()> {
+        if self.total <= limit {
+            return Ok(());
+        }
+
+        let parts = self.parts();
+        let mut message = format!(
+            "program is {} instructions, over the limit of {}. Largest parts:",
+            self.total, limit
+        );
+        for (name, size) in parts.iter().take(5) {
+            message.push_str(&format!("\n  {}: {}", name, size));
+        }
+
+        // An external stack keeps its values in a memory cell, so needs no
+        // tables in the program.
+        let tables: usize = self.stack_tables.iter().map(|(_, size)| size).sum();
+        if tables > 0 && (self.total - tables <= limit || parts[0].0.starts_with("jump tables")) {
+            message.push_str(&format!(
+                "\nThe internal stack tables take {} instructions. An external stack \
+                 (`stack_config cell <cell>`) needs none.",
+                tables
+            ));
+        }
+
+        Err(CompileError::SizeOverflow {
+            size: self.total,
+            limit,
+            message,
+        }
+        .into())
+    }
+}
+
+/// A table of the size of each part of the program, and of how much of the
+/// budget each takes, followed by the total and the headroom left. Loops are
+/// listed last, since they are part of the code above them.
+impl std::fmt::Display for CodeStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut parts = vec![("top-level code".to_string(), self.top_level)];
+        for (name, size) in self.functions.iter() {
+            parts.push((format!("function {}", name), *size));
+        }
+        if self.debug_handlers > 0 {
+            parts.push(("debug handlers".to_string(), self.debug_handlers));
+        }
+        for (name, size) in self.stack_tables.iter() {
+            parts.push((format!("jump tables for {}", name), *size));
+        }
+        for (name, size) in self.bank_routines.iter() {
+            parts.push((format!("bank routines for {}", name), *size));
+        }
+        let loops: Vec<(String, usize)> = self
+            .loops
+            .iter()
+            .map(|(line, size)| (format!("loop at line {}", line + 1), *size))
+            .collect();
+
+        let budget = self.budget();
+        let width = parts
+            .iter()
+            .chain(loops.iter())
+            .map(|(name, _)| name.len())
+            .chain(std::iter::once("part".len()))
+            .max()
+            .unwrap_or_default();
+        let row = |f: &mut std::fmt::Formatter, name: &str, size: usize| {
+            let share = 100.0 * size as f64 / budget as f64;
+            writeln!(
+                f,
+                "{:width$}  {:>5}  {:>5.1}%",
+                name,
+                size,
+                share,
+                width = width
+            )
+        };
+
+        writeln!(f, "{:width$}  {:>5}  budget", "part", "size", width = width)?;
+        for (name, size) in parts.iter() {
+            row(f, name, *size)?;
+        }
+        row(f, "total", self.total)?;
+        row(f, "headroom", self.headroom())?;
+        if self.total > budget {
+            writeln!(
+                f,
+                "Over the budget of {} by {}",
+                budget,
+                self.total - budget
+            )?;
+        }
+
+        if !loops.is_empty() {
+            writeln!(f, "Loops, also counted above:")?;
+        }
+        for (name, size) in loops.iter() {
+            row(f, name, *size)?;
+        }
+        Ok(())
+    }
+}
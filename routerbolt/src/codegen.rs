@@ -1,8 +1,8 @@
-use std::rc::Rc;
+use std::convert::TryFrom;
 
 use crate::*;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Backend {
     /// Uses a look up table in the program itself to store the stack.
     Internal,
@@ -10,15 +10,54 @@ pub enum Backend {
     /// Uses a memory bank or memory cell to store the stack. Faster and
     /// typically supports larger stack sizes.
     External,
+
+    /// Uses several memory banks to store the stack, for when one is not
+    /// enough. Each access goes through a routine that picks the bank.
+    Banked,
+}
+
+impl Backend {
+    /// Number of instructions needed to read or write one entry of an
+    /// external stack.
+    pub fn cell_access_size(&self) -> usize {
+        match self {
+            Backend::Banked => 4,
+            Backend::Internal | Backend::External => 1,
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum BackendParams {
-    Internal(Rc<InternalParams>),
-    External(Rc<ExternalParams>),
+    Internal(Shared<InternalParams>),
+    External(Shared<ExternalParams>),
+}
+
+impl BackendParams {
+    /// Moves the addresses of the stack tables or bank routines. See
+    /// `IntermediateRepresentation::relocate`.
+    pub fn relocate(&mut self, relocate: &dyn Fn(Address) -> Result<Address>) -> Result<()> {
+        match self {
+            BackendParams::Internal(int) => {
+                let int = Shared::make_mut(int);
+                int.push_table_start = relocate(int.push_table_start)?;
+                int.pop_table_start = relocate(int.pop_table_start)?;
+                int.poke_table_start = relocate(int.poke_table_start)?;
+            }
+            BackendParams::External(ext) => {
+                if let Some(routines) = ext.bank_routines {
+                    Shared::make_mut(ext).bank_routines = Some(BankRoutines {
+                        read: relocate(routines.read)?,
+                        write: relocate(routines.write)?,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct InternalParams {
     pub push_entry_size: AddressDelta,
     pub pop_entry_size: AddressDelta,
@@ -28,18 +67,393 @@ pub struct InternalParams {
     pub poke_table_start: Address,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct ExternalParams {
-    pub cell_name: Rc<String>,
+    pub cell_name: Symbol,
+
+    // First address in the cell used by the stack. The stack pointer starts
+    // here rather than at 0, so addresses computed from it need no adjustment.
+    pub offset: usize,
+
+    // Number of addresses reserved for the stack, if bounded.
+    pub len: Option<usize>,
+
+    // Further memory banks the stack continues into once `cell_name` is
+    // full, in order. Each holds `BANK_SIZE` entries.
+    pub more_cells: Vec<Symbol>,
+
+    // Entry points of the routines that access a stack spanning several
+    // banks. Set by the parser once the size of the program is known.
+    pub bank_routines: Option<BankRoutines>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BankRoutines {
+    pub read: Address,
+    pub write: Address,
+}
+
+/// Number of entries in a Mindustry memory bank.
+pub const BANK_SIZE: usize = 512;
+
+/// Number of entries in a Mindustry memory cell.
+pub const CELL_SIZE: usize = 64;
+
+/// The number of entries in the memory block linked as `name`, going by the
+/// name Mindustry gives it when linked: `cell1` for a memory cell, or `bank1`
+/// for a memory bank. `None` for any other name.
+pub fn memory_capacity(name: &str) -> Option<usize> {
+    let size = if name.starts_with("bank") {
+        BANK_SIZE
+    } else if name.starts_with("cell") {
+        CELL_SIZE
+    } else {
+        return None;
+    };
+    name[4..]
+        .chars()
+        .all(|c| c.is_ascii_digit())
+        .then_some(size)
+}
+
+impl ExternalParams {
+    pub fn is_banked(&self) -> bool {
+        !self.more_cells.is_empty()
+    }
+
+    /// All the cells holding the stack, in order.
+    pub fn cells(&self) -> impl Iterator<Item = &Symbol> {
+        std::iter::once(&self.cell_name).chain(self.more_cells.iter())
+    }
+
+    /// The entries in all the stack's cells, if known from their names. See
+    /// `memory_capacity`.
+    pub fn capacity(&self) -> Option<usize> {
+        self.cells().map(|cell| memory_capacity(cell)).sum()
+    }
+
+    /// Reads the entry at `address` into `dest`. Emits
+    /// `Backend::cell_access_size` instructions. `mf` is the variable prefix.
+    ///
+    /// Destroys: `MF_bank_addr` `MF_bank_val` `MF_bank_ret` `MF_bank`
+    pub fn read<D, A>(&self, mf: &str, dest: D, address: A, out: &mut Vec<String>) -> Result<()>
+    where
+        D: std::fmt::Display,
+        A: std::fmt::Display,
+    {
+        match &self.bank_routines {
+            None if !self.is_banked() => {
+                out.push(format!("read {} {} {}", dest, self.cell_name, address));
+            }
+            None => bail!("Internal error: bank routines not placed"),
+            Some(routines) => {
+                out.push(format!("set {mf}bank_addr {}", address));
+                out.push(format!("op add {mf}bank_ret @counter 1"));
+                out.push(format!("jump {} always x false", routines.read));
+                out.push(format!("set {} {mf}bank_val", dest));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes `value` to the entry at `address`. Emits
+    /// `Backend::cell_access_size` instructions. `mf` is the variable prefix.
+    ///
+    /// Destroys: `MF_bank_addr` `MF_bank_val` `MF_bank_ret` `MF_bank`
+    pub fn write<V, A>(&self, mf: &str, value: V, address: A, out: &mut Vec<String>) -> Result<()>
+    where
+        V: std::fmt::Display,
+        A: std::fmt::Display,
+    {
+        match &self.bank_routines {
+            None if !self.is_banked() => {
+                out.push(format!("write {} {} {}", value, self.cell_name, address));
+            }
+            None => bail!("Internal error: bank routines not placed"),
+            Some(routines) => {
+                out.push(format!("set {mf}bank_addr {}", address));
+                out.push(format!("set {mf}bank_val {}", value));
+                out.push(format!("op add {mf}bank_ret @counter 1"));
+                out.push(format!("jump {} always x false", routines.write));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Number of instructions in each of the bank routines.
+    pub fn bank_routine_size(&self) -> AddressDelta {
+        (4 + 2 * self.cells().count()).into()
+    }
+}
+
+/// How the program ends, chosen with the `epilogue` directive.
+///
+/// A processor that runs past its last instruction starts over from the top,
+/// which reruns the stack setup check on every pass.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Epilogue {
+    /// Runs off the end, with an `end` only if needed to keep out of the
+    /// debug handlers and stack tables that follow the program.
+    #[default]
+    Auto,
+
+    /// Ends with `end`, starting over from the top.
+    End,
+
+    /// Ends with `stop`, halting the processor for good.
+    Stop,
+
+    /// Ends with a jump back to the start of the program, past the stack setup.
+    Loop,
+}
+
+impl TryFrom<&str> for Epilogue {
+    type Error = anyhow::Error;
+
+    fn try_from(name: &str) -> Result<Epilogue> {
+        match name {
+            "auto" => Ok(Epilogue::Auto),
+            "end" => Ok(Epilogue::End),
+            "stop" => Ok(Epilogue::Stop),
+            "loop" => Ok(Epilogue::Loop),
+            _ => bail!("epilogue must be one of auto, end, stop, or loop"),
+        }
+    }
+}
+
+/// Checks added to the generated code with `debug` directives.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DebugOptions {
+    /// If set, check for stack overflow before each push, reporting the
+    /// function it happened in to this message block and halting.
+    pub stack_guard: Option<Symbol>,
+
+    /// If set, place a canary below each function's frame and check it on
+    /// return, reporting to this message block and halting if overwritten.
+    pub stack_canary: Option<Symbol>,
+}
+
+impl DebugOptions {
+    /// The message block a trap is reported to, if its check is enabled.
+    pub fn message_block(&self, trap: DebugTrap) -> Option<&Symbol> {
+        match trap {
+            DebugTrap::StackOverflow => self.stack_guard.as_ref(),
+            DebugTrap::StackCorruption => self.stack_canary.as_ref(),
+        }
+    }
+
+    /// Extra stack entries each call uses for its canary.
+    pub fn canary_size(&self) -> usize {
+        if self.stack_canary.is_some() {
+            1
+        } else {
+            0
+        }
+    }
+}
+
+/// A problem found by a `debug` check, which halts the program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum DebugTrap {
+    StackOverflow,
+    StackCorruption,
+}
+
+impl std::fmt::Display for DebugTrap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DebugTrap::StackOverflow => write!(f, "stack overflow"),
+            DebugTrap::StackCorruption => write!(f, "stack corruption"),
+        }
+    }
+}
+
+/// The value written below each frame by `debug stack_canary`.
+pub const STACK_CANARY: usize = 0x5AFE_CA11;
+
+/// A stack declared with `stack_config ... as name`, in addition to the
+/// default stack used for function calls and stack variables.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NamedStack {
+    pub name: StackName,
+    pub stack_config: StackConfig,
+    pub backend_params: BackendParams,
+}
+
+/// The stack used by a push, pop, peek, or poke. Named stacks carry their
+/// backend, since it may differ from that of the default stack.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum StackRef {
+    Default,
+    Named(StackName, Backend),
+}
+
+impl StackRef {
+    pub fn backend(&self, default: Backend) -> Backend {
+        match self {
+            StackRef::Default => default,
+            StackRef::Named(_, backend) => *backend,
+        }
+    }
+
+    /// The variable holding the number of entries on the stack, for the
+    /// variable prefix `mf`.
+    pub fn size_var(&self, mf: &str) -> String {
+        match self {
+            StackRef::Default => format!("{}stack_sz", mf),
+            StackRef::Named(name, _) => format!("{}stack_sz_{}", mf, name),
+        }
+    }
+
+    /// The prefix of the variables holding the entries of an internal stack.
+    pub fn table_var(&self, mf: &str) -> String {
+        match self {
+            StackRef::Default => format!("{}stack", mf),
+            StackRef::Named(name, _) => format!("{}stack_{}", mf, name),
+        }
+    }
+}
+
+impl std::fmt::Display for StackRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StackRef::Default => Ok(()),
+            StackRef::Named(name, _) => write!(f, " {}", name),
+        }
+    }
+}
+
+pub fn generate(ir: &IntermediateRepresentation) -> CompileResult<(Vec<String>, Vec<String>)> {
+    let (output, annotated, _) = generate_with_stats(ir)?;
+    Ok((output, annotated))
+}
+
+/// Generates the program as `generate` does, and also reports how many
+/// instructions each part of it takes.
+pub fn generate_with_stats(
+    ir: &IntermediateRepresentation,
+) -> CompileResult<(Vec<String>, Vec<String>, CodeStats)> {
+    generate_program(ir, None).map_err(|err| CompileError::from_anyhow(err, false))
 }
 
-pub fn generate(ir: &IntermediateRepresentation) -> Result<(Vec<String>, Vec<String>)> {
+/// Generates the program as `generate_with_stats` does, calling `hook` with
+/// the code each op generates as it goes, so tools can inspect or
+/// instrument it without changing codegen itself.
+pub fn generate_with_hook(
+    ir: &IntermediateRepresentation,
+    hook: &mut dyn CodegenHook,
+) -> CompileResult<(Vec<String>, Vec<String>, CodeStats)> {
+    generate_program(ir, Some(hook)).map_err(|err| CompileError::from_anyhow(err, false))
+}
+
+/// Called by `generate_with_hook` for each op of the program, in order.
+/// Closures taking a `&mut GeneratedOp` are hooks too.
+pub trait CodegenHook {
+    /// Called once `generated.op` has generated its code, before it's added
+    /// to the annotated listing.
+    fn op_generated(&mut self, generated: &mut GeneratedOp) -> Result<()>;
+}
+
+impl<F: FnMut(&mut GeneratedOp) -> Result<()>> CodegenHook for F {
+    fn op_generated(&mut self, generated: &mut GeneratedOp) -> Result<()> {
+        self(generated)
+    }
+}
+
+/// An op and the code it generated, as a `CodegenHook` sees it.
+///
+/// The code may be rewritten, but not lengthened or shortened: every
+/// address, and so every jump, was fixed from the ops' sizes when the
+/// program was parsed. To count how often an op runs, for example, replace
+/// one of its instructions with one that does the same and also counts, or
+/// record the addresses and count them in the emulator.
+#[derive(Debug)]
+pub struct GeneratedOp<'a> {
+    /// The position of the op in `IntermediateRepresentation::ops`.
+    pub index: usize,
+    pub op: &'a IrOp,
+
+    /// The source line the op came from, if any.
+    pub line: Option<usize>,
+
+    /// The address of the op's first instruction.
+    pub address: usize,
+    pub code: &'a mut [String],
+}
+
+impl<'a> GeneratedOp<'a> {
+    /// The addresses of the op's instructions.
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.address..self.address + self.code.len()
+    }
+}
+
+fn generate_program(
+    ir: &IntermediateRepresentation,
+    mut hook: Option<&mut dyn CodegenHook>,
+) -> Result<(Vec<String>, Vec<String>, CodeStats)> {
     let mut output = Vec::default();
     let mut annotated = Vec::default();
-    let mut instruction_count = 0.into();
+    let mut instruction_count: Address = 0.into();
+
+    if !matches!(ir.stack_config, StackConfig::Internal(0)) {
+        annotated.push(format!("// Stack usage: {}", ir.stack_usage));
+        annotated.push(String::default());
+    }
+
+    // The removed dead code is listed below, so the notes about it are left
+    // out.
+    let warnings: Vec<Diagnostic> = ir
+        .warnings()
+        .into_iter()
+        .filter(|warning| warning.severity == Severity::Warning)
+        .collect();
+    for warning in warnings.iter() {
+        annotated.push(format!("// Warning: {}", warning.message));
+    }
+    if !warnings.is_empty() {
+        annotated.push(String::default());
+    }
+
+    if ir.peephole_saved > 0 {
+        annotated.push(format!(
+            "// Peephole optimizations saved {} instructions",
+            ir.peephole_saved
+        ));
+        annotated.push(String::default());
+    }
+
+    if !ir.dead_code.is_empty() {
+        annotated.push("// Removed unreachable code:".to_string());
+        for dead in ir.dead_code.iter() {
+            annotated.push(format!("//   {}", dead));
+        }
+        annotated.push(String::default());
+    }
 
-    for op in ir.ops().iter() {
+    log::debug!(
+        "generating {} ops for the {:?} backend",
+        ir.ops().len(),
+        ir.backend()
+    );
+    let mut loops = Vec::default();
+    let mut last_line = None;
+    for (j, (op, line)) in ir.ops().iter().zip(ir.op_lines.iter()).enumerate() {
         let annotation_start = output.len();
+        if let (Some(end), Some(line)) = (loop_end(op), line) {
+            let size: usize = (end? - instruction_count).into();
+            loops.push((*line, size));
+        }
+
+        // Show the source above the code generated from it, once per line.
+        if let Some(line) = line.filter(|line| Some(*line) != last_line) {
+            if let Some(text) = ir.source_lines.get(line) {
+                annotated.push(format!("// src {}: {}", line, text));
+            }
+            last_line = Some(line);
+        }
 
         op.generate(
             ir,
@@ -48,59 +462,249 @@ pub fn generate(ir: &IntermediateRepresentation) -> Result<(Vec<String>, Vec<Str
             &mut instruction_count,
         )?;
 
+        if let Some(hook) = hook.as_mut() {
+            let mut generated = GeneratedOp {
+                index: j,
+                op,
+                line: *line,
+                address: instruction_count.into(),
+                code: &mut output[annotation_start..],
+            };
+            hook.op_generated(&mut generated)
+                .with_context(|| format!("codegen hook at op {}", j))?;
+        }
+
         for (j, line) in output[annotation_start..].iter().enumerate() {
             annotated.push(format!("{}\t{}", instruction_count + j.into(), line));
         }
 
         annotated.push(String::default());
 
+        // Addresses were assigned from the sizes, so any difference moves
+        // everything after.
+        let size: usize = op.code_size(*ir.backend()).into();
+        let generated = output.len() - annotation_start;
+        log::trace!(
+            "op {} at {}: generated {} of {} instructions",
+            j,
+            instruction_count,
+            generated,
+            size
+        );
+        if generated != size {
+            log::warn!(
+                "op {} at {} generated {} instructions but has size {}: {:?}",
+                j,
+                instruction_count,
+                generated,
+                size,
+                op
+            );
+        }
+
         instruction_count += op.code_size(*ir.backend());
     }
 
-    if let Backend::Internal = ir.backend() {
-        generate_internal_stack(
-            &ir.stack_config,
-            &mut output,
-            Some(&mut annotated),
-            &mut instruction_count,
-        );
+    // Everything past here is only reached by jumping to it.
+    let stacks = stack_support(ir);
+    let epilogue = match ir.epilogue {
+        Epilogue::Auto if stacks.is_empty() && ir.debug_handlers.is_empty() => None,
+        Epilogue::Auto | Epilogue::End => Some("end".to_string()),
+        Epilogue::Stop => Some("stop".to_string()),
+        Epilogue::Loop => Some(format!("jump {} always x false", ir.program_start)),
+    };
+    if let Some(epilogue) = epilogue {
+        annotated.push("// End before debug handlers and stack tables (annotations do not show the actual generated stack because it is so long)".to_string());
+        annotated.push(epilogue.clone());
+        annotated.push(String::default());
+        output.push(epilogue);
+        instruction_count += 1.into();
     }
+    let program_size: usize = instruction_count.into();
+    log::debug!(
+        "program is {} instructions before stack support",
+        program_size
+    );
+    let mut stats = CodeStats::new(ir, program_size, loops);
 
-    Ok((output, annotated))
+    generate_debug_handlers(
+        ir,
+        &mut output,
+        Some(&mut annotated),
+        &mut instruction_count,
+    )?;
+
+    generate_stack_support(
+        &stacks,
+        &ir.variable_prefix,
+        &mut output,
+        Some(&mut annotated),
+        &mut instruction_count,
+    )?;
+
+    for (stack, support) in stacks.iter() {
+        stats.add_stack_support(stack, support);
+    }
+    stats.total = output.len();
+
+    if let Some(limit) = ir.instruction_limit {
+        stats.check_limit(limit)?;
+    }
+
+    Ok((output, annotated, stats))
 }
 
-pub fn generate_internal_stack(
-    config: &StackConfig,
+/// The most instructions a Mindustry processor accepts.
+pub const MAX_INSTRUCTIONS: usize = 1000;
+
+/// Generates the code debug checks jump to when they fail, which reports the
+/// problem and where it happened and then halts, repeating the report forever.
+pub fn generate_debug_handlers(
+    ir: &IntermediateRepresentation,
     out: &mut Vec<String>,
     mut ann: Option<&mut Vec<String>>,
     ic: &mut Address,
-) {
-    let size = match config {
-        StackConfig::Internal(size) if *size == 0 => {
-            return;
-        }
-        StackConfig::Internal(size) => {
-            if let Some(ann) = ann.as_mut() {
-                ann.push(format!("\n Begin stack of size {}", size));
+) -> Result<()> {
+    let mut handlers: Vec<_> = ir.debug_handlers.iter().collect();
+    handlers.sort_by_key(|(_, address)| *address.as_ref());
+
+    for ((trap, function), address) in handlers {
+        if *address != *ic {
+            return Err(CompileError::internal("debug handler misplaced").into());
+        }
+
+        let message = ir
+            .debug
+            .message_block(*trap)
+            .context("Internal error: handler for disabled debug check")?;
+
+        let start = out.len();
+        match function {
+            Some(function) => out.push(format!("print \"{} in {}\"", trap, function)),
+            None => out.push(format!("print \"{} outside any function\"", trap)),
+        }
+        out.push(format!("printflush {}", message));
+        out.push(format!("jump {} always x false", address));
+
+        if let Some(ann) = ann.as_mut() {
+            ann.push(format!("// Debug handler: {}", trap));
+            for (j, line) in out[start..].iter().enumerate() {
+                ann.push(format!("{}\t{}", *ic + j.into(), line));
             }
-            *size
+            ann.push(String::default());
         }
-        StackConfig::External(..) => {
-            return;
+
+        *ic += DEBUG_HANDLER_SIZE;
+    }
+
+    Ok(())
+}
+
+/// Number of instructions in each debug handler.
+pub const DEBUG_HANDLER_SIZE: AddressDelta = AddressDelta::new(3);
+
+/// Code a stack needs placed after the program.
+pub enum StackSupport<'a> {
+    /// Jump tables for an internal stack of this size.
+    Tables(usize),
+
+    /// Routines to access a stack spanning several memory banks.
+    BankRoutines(&'a ExternalParams),
+}
+
+/// The default and named stacks that need code after the program, in the
+/// order their parameters were computed by the parser.
+fn stack_support(ir: &IntermediateRepresentation) -> Vec<(StackRef, StackSupport<'_>)> {
+    fn support<'a>(params: &'a BackendParams, config: &StackConfig) -> Option<StackSupport<'a>> {
+        match (params, config) {
+            (_, StackConfig::Internal(0)) => None,
+            (_, StackConfig::Internal(size)) => Some(StackSupport::Tables(*size)),
+            (BackendParams::External(ext), _) if ext.is_banked() => {
+                Some(StackSupport::BankRoutines(ext))
+            }
+            _ => None,
         }
-    };
+    }
 
-    out.push("end".to_string());
-    if let Some(ann) = ann.as_mut() {
-        ann.push("// End before stack table (annotations do not show the actual generated stack because it is so long)".to_string());
-        ann.push("end".to_string());
-        ann.push(String::default());
+    let mut stacks = Vec::default();
+    if let Some(s) = support(ir.backend_params(), &ir.stack_config) {
+        stacks.push((StackRef::Default, s));
     }
-    *ic += 1.into();
 
-    gen("push", size, out, &mut None, ic, push);
-    gen("pop", size, out, &mut None, ic, pop);
-    gen("poke", size, out, &mut None, ic, poke);
+    for named in ir.named_stacks.iter() {
+        if let Some(s) = support(&named.backend_params, &named.stack_config) {
+            let stack = StackRef::Named(named.name.clone(), named.stack_config.backend());
+            stacks.push((stack, s));
+        }
+    }
+
+    stacks
+}
+
+/// Generates the jump tables for the internal stacks, and the routines for
+/// stacks spanning several banks, for the variable prefix `mf`.
+pub fn generate_stack_support(
+    stacks: &[(StackRef, StackSupport)],
+    mf: &str,
+    out: &mut Vec<String>,
+    mut ann: Option<&mut Vec<String>>,
+    ic: &mut Address,
+) -> Result<()> {
+    for (stack, support) in stacks.iter() {
+        match support {
+            StackSupport::Tables(size) => {
+                let size = *size;
+                if let Some(ann) = ann.as_mut() {
+                    ann.push(format!("\n Begin stack{} of size {}", stack, size));
+                }
+
+                gen("entry", size, out, &mut None, ic, |j, out| {
+                    entry(stack, mf, j, out)
+                });
+            }
+            StackSupport::BankRoutines(ext) => {
+                let routines = ext
+                    .bank_routines
+                    .context("Internal error: bank routines not placed")?;
+                for (name, address) in [("read", routines.read), ("write", routines.write)] {
+                    if address != *ic {
+                        bail!("Internal error: bank routine misplaced");
+                    }
+
+                    let start = out.len();
+                    bank_routine(ext, mf, name == "read", out);
+                    if let Some(ann) = ann.as_mut() {
+                        ann.push(format!("// Bank {} routine for stack{}", name, stack));
+                        for (j, line) in out[start..].iter().enumerate() {
+                            ann.push(format!("{}\t{}", *ic + j.into(), line));
+                        }
+                        ann.push(String::default());
+                    }
+
+                    *ic += ext.bank_routine_size();
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads or writes `MF_bank_val` at stack address `MF_bank_addr`, in whichever
+/// bank holds it, then returns to `MF_bank_ret`.
+fn bank_routine(ext: &ExternalParams, mf: &str, read: bool, out: &mut Vec<String>) {
+    out.push(format!("op idiv {mf}bank {mf}bank_addr {}", BANK_SIZE));
+    out.push(format!("op mod {mf}bank_addr {mf}bank_addr {}", BANK_SIZE));
+    out.push(format!("op mul {mf}bank {mf}bank 2"));
+    out.push(format!("op add @counter @counter {mf}bank"));
+    for cell in ext.cells() {
+        if read {
+            out.push(format!("read {mf}bank_val {} {mf}bank_addr", cell));
+        } else {
+            out.push(format!("write {mf}bank_val {} {mf}bank_addr", cell));
+        }
+        out.push(format!("set @counter {mf}bank_ret"));
+    }
 }
 
 fn gen<F>(
@@ -134,18 +738,18 @@ fn gen<F>(
     }
 }
 
-fn pop(index: usize, output: &mut Vec<String>) {
-    output.push(format!("set MF_acc MF_stack[{}]", index));
-    output.push("set @counter MF_resume".to_string());
-}
-
-fn poke(index: usize, output: &mut Vec<String>) {
-    output.push(format!("set MF_stack[{}] MF_acc", index));
-    output.push("set @counter MF_resume".to_string());
-}
+/// Number of instructions in each entry of an internal stack's jump table.
+pub const STACK_ENTRY_SIZE: usize = 3;
 
-fn push(index: usize, output: &mut Vec<String>) {
-    output.push(format!("set MF_stack[{}] MF_acc", index));
-    output.push("op add MF_stack_sz MF_stack_sz 1".to_string());
-    output.push("set @counter MF_resume".to_string());
+/// One entry of an internal stack's jump table. Push and poke enter at the
+/// first instruction, and pop (and peek) at the second. A push or poke also
+/// reads back the value it wrote, which is harmless, so that both can share the
+/// return. The push site increments the stack size itself, as a call does once
+/// for all its pushes, which costs less than an instruction in every entry.
+/// Mindustry can't index variables, so every entry must name its own for both
+/// reading and writing, and this is as small as the table gets.
+fn entry(stack: &StackRef, mf: &str, index: usize, output: &mut Vec<String>) {
+    output.push(format!("set {}[{}] {mf}acc", stack.table_var(mf), index));
+    output.push(format!("set {mf}acc {}[{}]", stack.table_var(mf), index));
+    output.push(format!("set @counter {mf}resume"));
 }
@@ -0,0 +1,263 @@
+use crate::*;
+
+/// Finds the text of the source an `include` names.
+pub type IncludeResolver = Box<dyn Fn(&str) -> Result<String>>;
+
+/// Compiles programs with settings given in code rather than written into
+/// the source, for tools that embed the compiler. For example:
+///
+/// ```ignore
+/// let program = Compiler::new()
+///     .profile(parser::Profile::Release)
+///     .stack_config("cell bank1")
+///     .instruction_limit(Some(500))
+///     .define("TARGET", "@copper")
+///     .compile(text)?;
+/// ```
+///
+/// With an include resolver, a line `include <name>` in the source adds the
+/// text the resolver gives for `name` to the end of the program, as a
+/// manifest's libraries are, so included files should hold only functions.
+/// Each is added once, however many times it's included, and may include
+/// others. Lines past the end of the source are in the included text.
+///
+/// Defines replace each word of the source that is the name with the value
+/// before it is parsed, except in strings and comments, so they cost nothing
+/// at run time.
+///
+/// A variable prefix names the compiler's own variables, `MF_stack_sz`,
+/// `MF_acc` and the rest, so they can't collide with the program's, or those
+/// of other code sharing the processor. The program's own variables are left
+/// as they are, even if they start with `MF_`.
+pub struct Compiler {
+    options: parser::CompileOptions,
+
+    /// Checked when compiling, so that setting it can't fail.
+    stack_config: Option<String>,
+
+    defines: Vec<(String, String)>,
+    include_resolver: Option<IncludeResolver>,
+}
+
+/// Compiles `source` with the default options, giving the program as text to
+/// paste into a processor, one instruction per line. For other options, see
+/// `Compiler`.
+pub fn compile_to_string(source: &str) -> CompileResult<String> {
+    Ok(Compiler::new().compile(source)?.code.join("\n"))
+}
+
+/// A compiled program.
+#[derive(Debug)]
+pub struct Program {
+    pub ir: IntermediateRepresentation,
+    pub code: Vec<String>,
+    pub annotated: Vec<String>,
+    pub stats: CodeStats,
+}
+
+impl Default for Compiler {
+    fn default() -> Compiler {
+        Compiler::new()
+    }
+}
+
+impl Compiler {
+    /// A compiler with the default options, as `parser::parse` uses.
+    pub fn new() -> Compiler {
+        Compiler::with_options(parser::CompileOptions::default())
+    }
+
+    pub fn with_options(options: parser::CompileOptions) -> Compiler {
+        Compiler {
+            options,
+            stack_config: None,
+            defines: Vec::default(),
+            include_resolver: None,
+        }
+    }
+
+    /// Optimizes as `profile` does, leaving the other options as they are.
+    pub fn profile(mut self, profile: parser::Profile) -> Compiler {
+        let preset = parser::CompileOptions::with_profile(profile);
+        self.options.eliminate_dead_code = preset.eliminate_dead_code;
+        self.options.peephole = preset.peephole;
+        self.options.strip_debug_checks = preset.strip_debug_checks;
+        self.options.thread_jumps = preset.thread_jumps;
+        self
+    }
+
+    /// Configures the default stack, and so the backend, from the arguments
+    /// of a `stack_config` directive, e.g. `cell bank1 len 64` or `size 16`.
+    pub fn stack_config(mut self, config: &str) -> Compiler {
+        self.stack_config = Some(config.to_string());
+        self
+    }
+
+    /// The most instructions the program may take, or `None` for no limit.
+    pub fn instruction_limit(mut self, limit: Option<usize>) -> Compiler {
+        self.options.instruction_limit = limit;
+        self
+    }
+
+    pub fn epilogue(mut self, epilogue: Epilogue) -> Compiler {
+        self.options.epilogue = Some(epilogue);
+        self
+    }
+
+    /// Replaces the word `name` in the source with `value`.
+    pub fn define(mut self, name: &str, value: &str) -> Compiler {
+        self.defines.retain(|(other, _)| other != name);
+        self.defines.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Finds the source for `include` lines.
+    pub fn include_resolver<F: Fn(&str) -> Result<String> + 'static>(
+        mut self,
+        resolver: F,
+    ) -> Compiler {
+        self.include_resolver = Some(Box::new(resolver));
+        self
+    }
+
+    /// Starts the compiler's own variables with `prefix` rather than `MF_`.
+    /// Checked when compiling, so that setting it can't fail.
+    pub fn variable_prefix(mut self, prefix: &str) -> Compiler {
+        self.options.variable_prefix = prefix.to_string();
+        self
+    }
+
+    /// The options the program is compiled with.
+    pub fn options(&self) -> CompileResult<parser::CompileOptions> {
+        let mut options = self.options.clone();
+        if let Some(config) = &self.stack_config {
+            options
+                .set_stack_config(config)
+                .context("stack_config")
+                .map_err(|err| CompileError::from_anyhow(err, true))?;
+        }
+        Ok(options)
+    }
+
+    /// The source as it is parsed, with includes added and defines replaced.
+    pub fn expand(&self, text: &str) -> CompileResult<String> {
+        self.expand_source(text)
+            .map(|lines| lines.join("\n"))
+            .map_err(|err| CompileError::from_anyhow(err, true))
+    }
+
+    /// Parses `text`, as `parser::parse_with_options` does.
+    pub fn parse(&self, text: &str) -> CompileResult<IntermediateRepresentation> {
+        let lines = self
+            .expand_source(text)
+            .map_err(|err| CompileError::from_anyhow(err, true))?;
+        parser::parse_lines_with_options(lines.into_iter().enumerate(), &self.options()?)
+    }
+
+    /// Parses and generates `text`.
+    pub fn compile(&self, text: &str) -> CompileResult<Program> {
+        self.generate(self.parse(text)?)
+    }
+
+    /// Generates the program for `ir`, which was parsed with these options.
+    pub fn generate(&self, ir: IntermediateRepresentation) -> CompileResult<Program> {
+        let (code, annotated, stats) = generate_with_stats(&ir)?;
+        Ok(Program {
+            ir,
+            code,
+            annotated,
+            stats,
+        })
+    }
+
+    fn expand_source(&self, text: &str) -> Result<Vec<String>> {
+        let mut lines: Vec<String> = text.lines().map(String::from).collect();
+        let mut included = Vec::default();
+        let mut j = 0;
+        while j < lines.len() {
+            let tok = parser::lex_line(parser::clean_line(&lines[j]));
+            if tok.first() == Some(&"include") {
+                let source_line = |line: &str| SourceLine {
+                    stage: "",
+                    line: j,
+                    text: line.trim().to_string(),
+                };
+                let resolver = self
+                    .include_resolver
+                    .as_ref()
+                    .context("include needs an include resolver")
+                    .with_context(|| source_line(&lines[j]))?;
+                let name = match tok[..] {
+                    [_, name] => name.to_string(),
+                    _ => bail!("{}: form is `include <name>`", source_line(&lines[j])),
+                };
+                if !included.contains(&name) {
+                    let text = resolver(&name)
+                        .with_context(|| format!("include {}", name))
+                        .with_context(|| source_line(&lines[j]))?;
+                    lines.extend(text.lines().map(String::from));
+                    included.push(name);
+                }
+                lines[j] = String::default();
+            } else {
+                lines[j] = self.replace_defines(&lines[j]);
+            }
+            j += 1;
+        }
+        Ok(lines)
+    }
+
+    /// `line` with each word that is defined replaced by its value, up to
+    /// any comment.
+    fn replace_defines(&self, line: &str) -> String {
+        if self.defines.is_empty() {
+            return line.to_string();
+        }
+
+        let (code, comment) = split_comment(line);
+        let mut result = map_words(code, |word| {
+            match self.defines.iter().find(|(name, _)| name == word) {
+                Some((_, value)) => value.clone(),
+                None => word.to_string(),
+            }
+        });
+        result.push_str(comment);
+        result
+    }
+}
+
+/// `line` split where a `//` comment outside strings starts, if it has one.
+fn split_comment(line: &str) -> (&str, &str) {
+    let mut in_string = false;
+    for (j, c) in line.char_indices() {
+        if c == '"' {
+            in_string = !in_string;
+        } else if !in_string && line[j..].starts_with("//") {
+            return line.split_at(j);
+        }
+    }
+    (line, "")
+}
+
+/// `line` with each word outside strings replaced by what `f` gives for it.
+fn map_words<F: Fn(&str) -> String>(line: &str, f: F) -> String {
+    let mut result = String::default();
+    let mut word = String::default();
+    let mut in_string = false;
+    for c in line.chars().chain(std::iter::once(' ')) {
+        if !in_string && (c.is_whitespace() || c == '"') {
+            result.push_str(&f(&word));
+            word.clear();
+        }
+        if c == '"' {
+            in_string = !in_string;
+        }
+        if in_string || c.is_whitespace() || c == '"' {
+            result.push(c);
+        } else {
+            word.push(c);
+        }
+    }
+    result.pop();
+    result
+}
@@ -0,0 +1,207 @@
+use std::convert::TryFrom;
+
+use crate::*;
+
+/// An interactive session on an `Emulator`, run by commands as typed at the
+/// simulator's prompt:
+///
+/// - `step [n]` (`s`): runs the next `n` instructions, 1 by default, whatever
+///   breakpoints they are on.
+/// - `next [n]` (`n`): as `step`, but runs any call to its end. See
+///   `Emulator::step_over`.
+/// - `finish`: runs until the function the program is in returns.
+/// - `continue` (`c`): runs until a breakpoint or watchpoint, the program
+///   ends, or it has run the most steps allowed.
+/// - `break [address]` (`b`): stops before the instruction at `address`, or
+///   without one, lists the breakpoints. `delete <address>` removes one.
+/// - `watch <var|cell[address]>` (`w`): stops after anything changes it.
+///   `unwatch` removes it.
+/// - `display <var>`: shows the variable before each instruction in the
+///   trace. `undisplay` removes it.
+/// - `print <var|cell[address]|cell[start..end]>` (`p`): shows a variable,
+///   or a memory address or range of them.
+/// - `where`: shows the instruction that runs next.
+///
+/// Anything the program prints shows up in the trace, as it does for
+/// `Emulator::run`.
+pub struct Debugger {
+    pub emu: Emulator,
+
+    /// The most instructions a command runs, so that a program stuck in a
+    /// loop gives control back.
+    pub max_steps: usize,
+}
+
+/// What `Debugger::execute` lists for `help`.
+pub const DEBUGGER_HELP: &str = "\
+step [n], s       run the next n instructions
+next [n], n       as step, running calls to their end
+finish            run until the function returns
+continue, c       run until a breakpoint, watchpoint, or end
+break [address]   stop before the instruction, or list breakpoints
+delete <address>  remove a breakpoint
+watch <var>       stop when the variable or cell[address] changes
+unwatch <var>     remove a watchpoint
+display <var>     show the variable in the trace
+undisplay <var>   stop showing the variable
+print <var>       show a variable, cell[address], or cell[start..end]
+where             show the next instruction
+quit, q           leave";
+
+impl Debugger {
+    pub fn new(emu: Emulator, max_steps: usize) -> Debugger {
+        Debugger { emu, max_steps }
+    }
+
+    /// Runs `command`, and gives the lines to show for it.
+    pub fn execute(&mut self, command: &str) -> Result<Vec<String>> {
+        let tok: Vec<_> = command.split_whitespace().collect();
+        let count = || -> Result<usize> {
+            match tok.get(1) {
+                Some(n) => n.parse().context("count must be an integer"),
+                None => Ok(1),
+            }
+        };
+        let argument = || -> Result<&str> {
+            match tok[..] {
+                [_, argument] => Ok(argument),
+                _ => bail!("{} takes one argument", tok[0]),
+            }
+        };
+
+        let lines = match tok.first().copied() {
+            None => Vec::default(),
+            Some("step") | Some("s") => {
+                let mut events = Vec::default();
+                for _ in 0..count()? {
+                    events.extend(self.emu.step());
+                }
+                self.emu.render(&events)
+            }
+            Some("next") | Some("n") => {
+                let mut lines = Vec::default();
+                for _ in 0..count()? {
+                    lines.extend(self.emu.step_over(self.max_steps));
+                }
+                lines
+            }
+            Some("finish") => self.emu.step_out(self.max_steps),
+            Some("continue") | Some("c") => self.emu.run(self.max_steps),
+            Some("break") | Some("b") if tok.len() == 1 => {
+                let breakpoints: Vec<_> = self
+                    .emu
+                    .breakpoints()
+                    .iter()
+                    .map(|address| address.to_string())
+                    .collect();
+                vec![format!("Breakpoints: {}", breakpoints.join(" "))]
+            }
+            Some("break") | Some("b") => {
+                let address = self.address(argument()?)?;
+                let mut breakpoints = self.emu.breakpoints().to_vec();
+                if !breakpoints.contains(&address) {
+                    breakpoints.push(address);
+                }
+                self.emu.set_breakpoints(breakpoints);
+                vec![format!("Breakpoint at {}", address)]
+            }
+            Some("delete") => {
+                let address = self.address(argument()?)?;
+                let mut breakpoints = self.emu.breakpoints().to_vec();
+                breakpoints.retain(|other| *other != address);
+                self.emu.set_breakpoints(breakpoints);
+                Vec::default()
+            }
+            Some("watch") | Some("w") => {
+                let watchpoint = Watchpoint::try_from(argument()?)?;
+                let mut watchpoints = self.emu.watchpoints().to_vec();
+                if !watchpoints.contains(&watchpoint) {
+                    watchpoints.push(watchpoint.clone());
+                }
+                self.emu.set_watchpoints(watchpoints);
+                vec![format!("Watchpoint on {}", watchpoint)]
+            }
+            Some("unwatch") => {
+                let watchpoint = Watchpoint::try_from(argument()?)?;
+                let mut watchpoints = self.emu.watchpoints().to_vec();
+                watchpoints.retain(|other| *other != watchpoint);
+                self.emu.set_watchpoints(watchpoints);
+                Vec::default()
+            }
+            Some("display") => {
+                let var = Symbol::new(argument()?);
+                let mut watches = self.emu.watches().to_vec();
+                if !watches.contains(&var) {
+                    watches.push(var);
+                }
+                self.emu.set_watches(watches);
+                Vec::default()
+            }
+            Some("undisplay") => {
+                let var = Symbol::new(argument()?);
+                let mut watches = self.emu.watches().to_vec();
+                watches.retain(|other| *other != var);
+                self.emu.set_watches(watches);
+                Vec::default()
+            }
+            Some("print") | Some("p") => self.print(argument()?)?,
+            Some("where") => vec![self.location()],
+            Some("help") => DEBUGGER_HELP.lines().map(String::from).collect(),
+            Some(command) => bail!("unknown command {}; try help", command),
+        };
+        Ok(lines)
+    }
+
+    /// An address in the program.
+    fn address(&self, text: &str) -> Result<usize> {
+        let address = text.parse().context("address must be an integer")?;
+        if self.emu.instruction(address).is_none() {
+            bail!("there is no instruction at {}", address);
+        }
+        Ok(address)
+    }
+
+    /// The values for `print`.
+    fn print(&self, text: &str) -> Result<Vec<String>> {
+        let (cell, range) = match text.strip_suffix(']').and_then(|t| t.split_once('[')) {
+            Some(parts) => parts,
+            None => return Ok(vec![format!("{} = {}", text, self.emu.var(text))]),
+        };
+        let (start, end) = match range.split_once("..") {
+            Some((start, end)) => (start, end),
+            None => (range, ""),
+        };
+        let start: usize = start.parse().context("address must be an integer")?;
+        let end = match end {
+            "" => start + 1,
+            end => end.parse().context("address must be an integer")?,
+        };
+
+        Ok((start..end)
+            .map(|address| {
+                let value = match self.emu.get_cell_value(cell, address) {
+                    Some(value) => Value::Number(value),
+                    None => Value::Null,
+                };
+                format!("{}[{}] = {}", cell, address, value)
+            })
+            .collect())
+    }
+
+    /// The instruction that runs next, with the source line it came from.
+    fn location(&self) -> String {
+        let ip = self.emu.counter();
+        let mut line = match self.emu.instruction(ip) {
+            Some(instruction) => format!("At {}: \"{}\"", ip, instruction),
+            None => format!("At {}, past the end of the program", ip),
+        };
+        let source_map = self.emu.source_map();
+        if let Some(location) = source_map.location(ip) {
+            line.push_str(&format!("\t// src {}", location.line));
+            if let Some(text) = source_map.source_lines.get(location.line) {
+                line.push_str(&format!(": {}", text.trim()));
+            }
+        }
+        line
+    }
+}
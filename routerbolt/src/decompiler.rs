@@ -0,0 +1,1333 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::ops::Range;
+
+use crate::*;
+
+/// Turns Mindustry logic, such as a program exported from the game, back into
+/// routerbolt source.
+///
+/// Jump targets become labels, backward jumps become `do`/`while` and `loop`
+/// loops, and the compiler's own `if`, `if`/`else`, and `while` are recognized.
+/// If the program sets up an external stack the way the compiler does, calls
+/// whose target can be recovered as a whole become `fn`s with stack variables,
+/// and the compiler's other stack code becomes `callproc`, `ret`, `push`,
+/// `pop`, `peek`, and `poke`. Everything else is passed along as is, or with
+/// `asm` if the compiler wouldn't accept it.
+///
+/// The source compiles back to the same number of instructions at the same
+/// addresses, so jumps computed from `@counter` still land where they did.
+pub fn decompile(text: &str) -> Result<String> {
+    Decompiler::new(read_instructions(text)?).decompile()
+}
+
+/// The tokens of an instruction.
+type Tokens = Vec<String>;
+
+/// Reads the instructions of a program, one per line, skipping blank lines and
+/// `#` comments. Labels, which newer versions of Mindustry write, are
+/// replaced with the addresses they name.
+fn read_instructions(text: &str) -> Result<Vec<Tokens>> {
+    let mut instructions: Vec<Tokens> = Vec::default();
+    let mut labels = HashMap::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let tokens = parser::lex_line(line);
+        if tokens.len() == 1 && tokens[0].ends_with(':') {
+            let name = &tokens[0][..tokens[0].len() - 1];
+            if labels
+                .insert(name.to_string(), instructions.len())
+                .is_some()
+            {
+                bail!("label {} is defined twice", name);
+            }
+        } else {
+            instructions.push(tokens.into_iter().map(String::from).collect());
+        }
+    }
+
+    for tokens in instructions.iter_mut() {
+        if tokens[0] == "jump" && tokens.len() > 1 {
+            if let Some(address) = labels.get(&tokens[1]) {
+                tokens[1] = address.to_string();
+            }
+        }
+    }
+
+    Ok(instructions)
+}
+
+/// The target and condition of a `jump`, or of a `set @counter` to a fixed
+/// address, if the target is in the program. `always` is given alone.
+fn parse_jump(tokens: &[String], len: usize) -> Option<(usize, Tokens)> {
+    let (target, condition) = match tokens {
+        [set, counter, target] if set == "set" && counter == "@counter" => (target, &[][..]),
+        [jump, target, condition @ ..] if jump == "jump" && !condition.is_empty() => {
+            (target, condition)
+        }
+        _ => return None,
+    };
+
+    let target: usize = target.parse().ok().filter(|target| *target <= len)?;
+    let condition = match condition {
+        [] => vec!["always".to_string()],
+        [always, ..] if always == "always" => vec!["always".to_string()],
+        [name, _, _] if CONDITIONS.contains(&name.as_str()) => condition.to_vec(),
+        _ => return None,
+    };
+
+    Some((target, condition))
+}
+
+/// Whether `a` holds exactly when `b` doesn't, as the compiler negates the
+/// condition of a `while` for the test at its top.
+fn is_negation(a: &[String], b: &[String]) -> bool {
+    if b == ["always"] {
+        return a == ["equal", "0", "1"];
+    }
+    if a.len() != 3 || b.len() != 3 || a[1..] != b[1..] {
+        return false;
+    }
+
+    // The arguments may be stack variables, which `Condition` doesn't take,
+    // but are the same anyway.
+    let placeholder = || MindustryTerm::try_from("a").unwrap();
+    let condition = (Symbol::new(&b[0]), placeholder(), placeholder());
+    match Condition::try_from(condition).ok().and_then(|c| c.negate()) {
+        Some(negated) => negated.to_string() == format!("{} a a", a[0]),
+        None => false,
+    }
+}
+
+/// An argument pushed for a call.
+#[derive(Clone, Debug, PartialEq)]
+enum Arg {
+    Value(String),
+
+    /// A stack variable of the caller, at this depth in its frame.
+    Depth(usize),
+}
+
+/// What the instructions of an `Item` do.
+#[derive(Clone, Debug, PartialEq)]
+enum Kind {
+    /// A single instruction not otherwise recognized.
+    Instruction,
+
+    Jump {
+        target: usize,
+        condition: Tokens,
+    },
+
+    /// Pushes the return address and `args`, leaving room for the callee's
+    /// stack variables, `frame` entries in all, and jumps to `target`.
+    Call {
+        target: usize,
+        args: Vec<Arg>,
+        frame: usize,
+    },
+
+    /// Pops `frame` entries and returns to the address below them.
+    Return {
+        frame: usize,
+    },
+
+    Push,
+    Pop,
+
+    /// Reads the stack entry `depth` below the top into `dest`.
+    Read {
+        dest: String,
+        depth: usize,
+    },
+
+    /// Writes `value` to the stack entry `depth` below the top.
+    Write {
+        value: String,
+        depth: usize,
+    },
+}
+
+/// Consecutive instructions the decompiler handles together.
+#[derive(Clone, Debug)]
+struct Item {
+    address: usize,
+    len: usize,
+    kind: Kind,
+}
+
+/// A call target whose code can be written as a `fn`.
+struct Function {
+    /// The items of its body, which runs up to the next call target.
+    items: Range<usize>,
+
+    args: usize,
+
+    /// Entries pushed by each call: the return address, the arguments, and
+    /// the function's other stack variables.
+    frame: usize,
+
+    /// How many values each `return` passes back in `MF_ret<n>`.
+    returns: usize,
+}
+
+/// A line of source, or several, for the instructions at `address`.
+struct Stmt {
+    address: usize,
+    len: usize,
+    kind: StmtKind,
+}
+
+enum StmtKind {
+    Line(String),
+    Jump { target: usize, condition: Tokens },
+    Callproc(usize),
+}
+
+/// The structure recovered from a run of `Stmt`s, by index.
+enum Node {
+    Stmt(usize),
+    If {
+        address: usize,
+        condition: Tokens,
+        then: Vec<Node>,
+        otherwise: Option<Vec<Node>>,
+    },
+    While {
+        address: usize,
+        condition: Tokens,
+        body: Vec<Node>,
+    },
+    DoWhile {
+        address: usize,
+        condition: Tokens,
+        body: Vec<Node>,
+    },
+}
+
+impl Node {
+    fn address(&self, stmts: &[Stmt]) -> usize {
+        match self {
+            Node::Stmt(j) => stmts[*j].address,
+            Node::If { address, .. }
+            | Node::While { address, .. }
+            | Node::DoWhile { address, .. } => *address,
+        }
+    }
+}
+
+/// Some of the program, all outside any function or all in one.
+struct Segment {
+    stmts: Vec<Stmt>,
+    nodes: Vec<Node>,
+    function: Option<usize>,
+}
+
+struct Decompiler {
+    instructions: Vec<Tokens>,
+
+    /// Every address something jumps to, which must start an item and a
+    /// line of source.
+    targets: HashSet<usize>,
+
+    /// The cell of the program's external stack, and the offset it starts at.
+    stack: Option<(String, String)>,
+
+    items: Vec<Item>,
+
+    /// The item at each address an item starts at.
+    index: HashMap<usize, usize>,
+
+    /// By the address they start at.
+    functions: HashMap<usize, Function>,
+}
+
+impl Decompiler {
+    fn new(instructions: Vec<Tokens>) -> Decompiler {
+        let targets = instructions
+            .iter()
+            .filter_map(|tokens| parse_jump(tokens, instructions.len()))
+            .map(|(target, _)| target)
+            .collect();
+
+        let mut decompiler = Decompiler {
+            instructions,
+            targets,
+            stack: None,
+            items: Vec::default(),
+            index: HashMap::default(),
+            functions: HashMap::default(),
+        };
+        decompiler.stack = decompiler.find_stack();
+        decompiler.items = decompiler.find_items();
+        decompiler.index = decompiler
+            .items
+            .iter()
+            .enumerate()
+            .map(|(j, item)| (item.address, j))
+            .collect();
+        decompiler.functions = decompiler.find_functions();
+        decompiler
+    }
+
+    fn decompile(&self) -> Result<String> {
+        let segments = self.segments();
+
+        // Only jumps left as jumps need labels.
+        let mut labels = HashSet::new();
+        for segment in segments.iter() {
+            collect_labels(&segment.stmts, &segment.nodes, &mut labels);
+        }
+
+        let mut lines = Vec::default();
+        if let Some((cell, offset)) = &self.stack {
+            match offset.as_str() {
+                "0" => lines.push(format!("stack_config cell {}", cell)),
+                offset => lines.push(format!("stack_config cell {} offset {}", cell, offset)),
+            }
+        }
+
+        let mut emitted = HashSet::new();
+        for segment in segments.iter() {
+            let indent = match segment.function {
+                Some(address) => {
+                    let function = &self.functions[&address];
+                    if lines.last().map(String::is_empty) == Some(false) {
+                        lines.push(String::default());
+                    }
+                    lines.push(self.function_header(address, function));
+                    for slot in function.args + 1..function.frame {
+                        lines.push(format!("  let {}", slot_name(function, slot)));
+                    }
+                    1
+                }
+                None => 0,
+            };
+
+            let mut out = Lines {
+                stmts: &segment.stmts,
+                labels: &labels,
+                emitted: &mut emitted,
+                lines: &mut lines,
+            };
+            out.render(&segment.nodes, indent);
+
+            if segment.function.is_some() {
+                lines.push("}".to_string());
+                lines.push(String::default());
+            }
+        }
+
+        // A jump to the end of the program, which starts it over.
+        if labels.contains(&self.instructions.len()) {
+            lines.push(format!("{}:", label_name(self.instructions.len())));
+        }
+
+        while lines.last().map(String::is_empty) == Some(true) {
+            lines.pop();
+        }
+
+        if lines.is_empty() {
+            return Ok(String::default());
+        }
+        let mut text = lines.join("\n");
+        text.push('\n');
+        Ok(text)
+    }
+
+    fn function_header(&self, address: usize, function: &Function) -> String {
+        let mut header = format!("fn {}", function_name(address));
+        for slot in 1..=function.args {
+            header.push(' ');
+            header.push_str(&slot_name(function, slot));
+        }
+        if function.returns > 0 {
+            header.push_str(" ->");
+            for k in 0..function.returns {
+                header.push_str(&format!(" result{}", k));
+            }
+        }
+        header.push_str(" {");
+        header
+    }
+
+    /// Whether the instruction at `address` is `pattern`, where `_` matches
+    /// any token.
+    fn is(&self, address: usize, pattern: &[&str]) -> bool {
+        match self.instructions.get(address) {
+            Some(tokens) => {
+                tokens.len() == pattern.len()
+                    && tokens
+                        .iter()
+                        .zip(pattern.iter())
+                        .all(|(token, pattern)| *pattern == "_" || token == pattern)
+            }
+            None => false,
+        }
+    }
+
+    /// The token at `position` of the instruction at `address`.
+    fn token(&self, address: usize, position: usize) -> &str {
+        &self.instructions[address][position]
+    }
+
+    /// The setup of an external stack at the start of the program, as the
+    /// compiler generates it: the cell the stack is in, and where it starts.
+    fn find_stack(&self) -> Option<(String, String)> {
+        let prologue = self.is(0, &["jump", "3", "equal", "MF_init", "1"])
+            && self.is(1, &["set", "MF_stack_sz", "_"])
+            && self.is(2, &["set", "MF_init", "1"])
+            && !self.targets.contains(&1)
+            && !self.targets.contains(&2);
+        if !prologue {
+            return None;
+        }
+
+        // Internal stacks are left alone, since their jump tables would need
+        // to be reproduced exactly.
+        let cell = (3..self.instructions.len()).find_map(|address| {
+            let read = self.is(address, &["read", "_", "_", "MF_stack_sz"]);
+            let write = self.is(address, &["write", "MF_acc", "_", "MF_stack_sz"]);
+            if read || write {
+                Some(self.token(address, 2).to_string())
+            } else {
+                None
+            }
+        })?;
+        Some((cell, self.token(1, 2).to_string()))
+    }
+
+    /// Splits the program into items, after the stack setup if there is one.
+    fn find_items(&self) -> Vec<Item> {
+        let mut items = Vec::default();
+        let mut address = if self.stack.is_some() { 3 } else { 0 };
+        while address < self.instructions.len() {
+            let (kind, len) = self
+                .recognize(address)
+                .filter(|(_, len)| (address + 1..address + len).all(|a| !self.targets.contains(&a)))
+                .unwrap_or_else(|| {
+                    match parse_jump(&self.instructions[address], self.instructions.len()) {
+                        Some((target, condition)) => (Kind::Jump { target, condition }, 1),
+                        None => (Kind::Instruction, 1),
+                    }
+                });
+            items.push(Item { address, len, kind });
+            address += len;
+        }
+        items
+    }
+
+    /// The compiler's code for the stack, if it starts at `address`.
+    fn recognize(&self, address: usize) -> Option<(Kind, usize)> {
+        let (cell, _) = self.stack.as_ref()?;
+        let cell = cell.as_str();
+        if let Some(call) = self.recognize_call(address, cell) {
+            return Some(call);
+        }
+
+        let top = ["_", "_", cell, "MF_stack_sz"];
+        if self.is(address, &["op", "sub", "MF_stack_sz", "MF_stack_sz", "_"])
+            && self.is(address + 1, &top)
+        {
+            let frame = self.token(address, 4).parse().ok()?;
+            match self.instructions[address + 1][..2].join(" ").as_str() {
+                "read @counter" => return Some((Kind::Return { frame }, 2)),
+                "read MF_acc" if frame == 1 => return Some((Kind::Pop, 2)),
+                _ => {}
+            }
+        }
+
+        if self.is(address, &["write", "MF_acc", cell, "MF_stack_sz"])
+            && self.is(
+                address + 1,
+                &["op", "add", "MF_stack_sz", "MF_stack_sz", "1"],
+            )
+        {
+            return Some((Kind::Push, 2));
+        }
+
+        let (tokens, depth) = self.stack_access(address, cell)?;
+        match tokens[0].as_str() {
+            "read" if tokens[1] != "@counter" => {
+                let dest = tokens[1].clone();
+                Some((Kind::Read { dest, depth }, 2))
+            }
+            "write" => {
+                let value = tokens[1].clone();
+                Some((Kind::Write { value, depth }, 2))
+            }
+            _ => None,
+        }
+    }
+
+    /// A read or write of the stack entry some depth below the top, which
+    /// takes two instructions: the second, and the depth.
+    fn stack_access(&self, address: usize, cell: &str) -> Option<(&Tokens, usize)> {
+        if !self.is(address, &["op", "sub", "MF_tmp", "MF_stack_sz", "_"])
+            || !(self.is(address + 1, &["read", "_", cell, "MF_tmp"])
+                || self.is(address + 1, &["write", "_", cell, "MF_tmp"]))
+        {
+            return None;
+        }
+        let depth = self
+            .token(address, 4)
+            .parse()
+            .ok()
+            .filter(|depth| *depth > 0)?;
+        Some((&self.instructions[address + 1], depth))
+    }
+
+    /// How much `MF_stack_sz` is increased by at `address`.
+    fn increment(&self, address: usize) -> Option<usize> {
+        if !self.is(address, &["op", "add", "MF_stack_sz", "MF_stack_sz", "_"]) {
+            return None;
+        }
+        self.token(address, 4).parse().ok().filter(|n| *n > 0)
+    }
+
+    /// A call, which pushes the return address, then each argument, then
+    /// makes room for the callee's other stack variables, and jumps.
+    fn recognize_call(&self, address: usize, cell: &str) -> Option<(Kind, usize)> {
+        if !self.is(address, &["op", "add", "MF_acc", "@counter", "_"])
+            || !self.is(address + 1, &["write", "MF_acc", cell, "MF_stack_sz"])
+        {
+            return None;
+        }
+        let offset: usize = self.token(address, 4).parse().ok()?;
+        let mut frame = self.increment(address + 2)?;
+        let mut args = Vec::default();
+        let mut next = address + 3;
+
+        loop {
+            let jump = self
+                .instructions
+                .get(next)
+                .and_then(|tokens| parse_jump(tokens, self.instructions.len()));
+            if let Some((target, condition)) = jump {
+                // The return address is just after the jump.
+                if condition != ["always"] || offset != next - address {
+                    return None;
+                }
+                let kind = Kind::Call {
+                    target,
+                    args,
+                    frame,
+                };
+                return Some((kind, next + 1 - address));
+            }
+
+            // Room for stack variables is only made after the last argument.
+            if frame != args.len() + 1 {
+                return None;
+            }
+
+            // An argument from the caller's own frame is read first, from
+            // deeper down by what has been pushed so far.
+            match self.stack_access(next, cell) {
+                Some((tokens, depth)) if tokens[..2] == ["read", "MF_acc"] && depth > frame => {
+                    args.push(Arg::Depth(depth - frame));
+                    next += 2;
+                    if !self.is(next, &["write", "MF_acc", cell, "MF_stack_sz"]) {
+                        return None;
+                    }
+                }
+                _ => {
+                    if !self.is(next, &["write", "_", cell, "MF_stack_sz"]) {
+                        return None;
+                    }
+                    args.push(Arg::Value(self.token(next, 1).to_string()));
+                }
+            }
+
+            frame += self.increment(next + 1)?;
+            next += 2;
+        }
+    }
+
+    /// Whether control never continues past the item at `j`.
+    fn is_terminator(&self, j: usize) -> bool {
+        let item = &self.items[j];
+        match &item.kind {
+            Kind::Jump { condition, .. } => condition == &["always"],
+            Kind::Return { .. } => true,
+            Kind::Instruction => {
+                let name = self.token(item.address, 0);
+                name == "end" || name == "stop"
+            }
+            _ => false,
+        }
+    }
+
+    /// Finds the call targets that can be written as `fn`s. Each function
+    /// runs up to the next call target, and must be entered only by calls and
+    /// left only by returns. Whether a function can be written this way may
+    /// depend on whether its callers are functions, so targets are ruled out
+    /// until the rest agree.
+    fn find_functions(&self) -> HashMap<usize, Function> {
+        let mut starts: Vec<usize> = self
+            .items
+            .iter()
+            .filter_map(|item| match &item.kind {
+                Kind::Call { target, .. } => self.index.get(target).copied(),
+                _ => None,
+            })
+            .filter(|j| *j > 0)
+            .collect();
+        starts.sort_unstable();
+        starts.dedup();
+
+        let mut functions = HashMap::new();
+        for (n, start) in starts.iter().enumerate() {
+            let end = starts.get(n + 1).copied().unwrap_or(self.items.len());
+            let address = self.items[*start].address;
+            let mut shapes = self.items.iter().filter_map(|item| match &item.kind {
+                Kind::Call {
+                    target,
+                    args,
+                    frame,
+                } if *target == address => Some((args.len(), *frame)),
+                _ => None,
+            });
+            let (args, frame) = shapes.next().unwrap();
+            if shapes.all(|shape| shape == (args, frame)) {
+                let items = *start..end;
+                let returns = 0;
+                let function = Function {
+                    items,
+                    args,
+                    frame,
+                    returns,
+                };
+                functions.insert(address, function);
+            }
+        }
+
+        loop {
+            let invalid: Vec<usize> = functions
+                .iter()
+                .filter(|(address, function)| !self.is_function(**address, function, &functions))
+                .map(|(address, _)| *address)
+                .collect();
+            if invalid.is_empty() {
+                break;
+            }
+            for address in invalid {
+                functions.remove(&address);
+            }
+        }
+
+        let returns: Vec<(usize, usize)> = functions
+            .iter()
+            .map(|(address, function)| {
+                (*address, self.count_returns(*address, function, &functions))
+            })
+            .collect();
+        for (address, returns) in returns {
+            functions.get_mut(&address).unwrap().returns = returns;
+        }
+
+        functions
+    }
+
+    fn is_function(
+        &self,
+        address: usize,
+        function: &Function,
+        functions: &HashMap<usize, Function>,
+    ) -> bool {
+        let last = function.items.end - 1;
+        if !self.is_terminator(function.items.start - 1) || !self.is_terminator(last) {
+            return false;
+        }
+        let end = self.items[last].address + self.items[last].len;
+
+        for (j, item) in self.items.iter().enumerate() {
+            let inside = function.items.contains(&j);
+            match &item.kind {
+                Kind::Jump { target, .. } => {
+                    if inside != (address..end).contains(target) {
+                        return false;
+                    }
+                }
+                Kind::Call { target, args, .. } => {
+                    if *target == address && !self.can_call(j, args, functions) {
+                        return false;
+                    }
+                }
+                Kind::Return { frame } => {
+                    if inside && *frame != function.frame {
+                        return false;
+                    }
+                }
+                Kind::Push | Kind::Pop => {
+                    if inside {
+                        return false;
+                    }
+                }
+                Kind::Instruction => {
+                    let tokens = &self.instructions[item.address];
+                    if inside && tokens.get(1).map(String::as_str) == Some("MF_stack_sz") {
+                        return false;
+                    }
+                }
+                Kind::Read { .. } | Kind::Write { .. } => {}
+            }
+        }
+
+        true
+    }
+
+    /// The function whose body contains the item at `j`.
+    fn enclosing<'a>(
+        &self,
+        j: usize,
+        functions: &'a HashMap<usize, Function>,
+    ) -> Option<&'a Function> {
+        functions
+            .values()
+            .find(|function| function.items.contains(&j))
+    }
+
+    /// Whether the call at `j` can be written as a `call`.
+    fn can_call(&self, j: usize, args: &[Arg], functions: &HashMap<usize, Function>) -> bool {
+        let caller = self.enclosing(j, functions);
+        args.iter().all(|arg| match arg {
+            Arg::Value(_) => true,
+            Arg::Depth(depth) => caller.and_then(|caller| slot(caller, *depth)).is_some(),
+        })
+    }
+
+    /// How many values the function passes back: each `return` must set the
+    /// same number of `MF_ret<n>` just before it, and each call must read
+    /// them just after it. Otherwise these are left as they are, and the
+    /// function returns nothing.
+    fn count_returns(
+        &self,
+        address: usize,
+        function: &Function,
+        functions: &HashMap<usize, Function>,
+    ) -> usize {
+        let mut counts = function
+            .items
+            .clone()
+            .filter(|j| matches!(self.items[*j].kind, Kind::Return { .. }))
+            .map(|j| self.return_values(j, function));
+        let returns = match counts.next() {
+            Some(Some(returns)) => returns,
+            _ => return 0,
+        };
+        if !counts.all(|count| count == Some(returns)) {
+            return 0;
+        }
+
+        let bound = self
+            .items
+            .iter()
+            .enumerate()
+            .all(|(j, item)| match &item.kind {
+                Kind::Call { target, .. } if *target == address => {
+                    let caller = self.enclosing(j, functions);
+                    (0..returns).all(|k| self.binding(j + 1 + k, k, caller).is_some())
+                }
+                _ => true,
+            });
+        if bound {
+            returns
+        } else {
+            0
+        }
+    }
+
+    /// The return value the item at `j` sets, and what to, if that can be
+    /// written as a value of `return`.
+    fn return_value(&self, j: usize, function: &Function) -> Option<(usize, String)> {
+        let item = &self.items[j];
+        let (ret, value) = match (&item.kind, self.instructions[item.address].as_slice()) {
+            (Kind::Instruction, [set, ret, value]) if set == "set" && !is_internal(value) => {
+                (ret, value.clone())
+            }
+            (Kind::Read { dest, depth }, _) => (dest, slot(function, *depth)?),
+            _ => return None,
+        };
+        let n = ret.strip_prefix("MF_ret")?.parse().ok()?;
+        Some((n, value))
+    }
+
+    /// How many `MF_ret<n>` are set, in order, just before the `return` at
+    /// `j`, if they can be written as its values.
+    fn return_values(&self, j: usize, function: &Function) -> Option<usize> {
+        let last = Some(j)
+            .filter(|j| *j > function.items.start)
+            .and_then(|j| self.return_value(j - 1, function));
+        let count = match last {
+            Some((n, _)) => n + 1,
+            None => return Some(0),
+        };
+        if j < function.items.start + count {
+            return None;
+        }
+
+        let values = (0..count).all(|k| {
+            let item = j - count + k;
+            self.return_value(item, function).map(|(n, _)| n) == Some(k)
+                && (k == 0 || !self.targets.contains(&self.items[item].address))
+        });
+        Some(count).filter(|_| values && !self.targets.contains(&self.items[j].address))
+    }
+
+    /// The variable that the item at `j` sets to return value `k`, just after
+    /// a call.
+    fn binding(&self, j: usize, k: usize, caller: Option<&Function>) -> Option<String> {
+        let item = self.items.get(j)?;
+        if self.targets.contains(&item.address) {
+            return None;
+        }
+        let ret = format!("MF_ret{}", k);
+        match &item.kind {
+            Kind::Instruction => match self.instructions[item.address].as_slice() {
+                [set, var, value] if set == "set" && *value == ret && !is_internal(var) => {
+                    Some(var.clone())
+                }
+                _ => None,
+            },
+            Kind::Write { value, depth } if *value == ret => slot(caller?, *depth),
+            _ => None,
+        }
+    }
+
+    /// Splits the items into code outside functions and functions, and
+    /// renders each.
+    fn segments(&self) -> Vec<Segment> {
+        let mut segments = Vec::default();
+        let mut j = 0;
+        while j < self.items.len() {
+            let address = self.items[j].address;
+            let (function, items) = match self.functions.get(&address) {
+                Some(function) => (Some(address), function.items.clone()),
+                None => {
+                    let end = (j + 1..self.items.len())
+                        .find(|k| self.functions.contains_key(&self.items[*k].address))
+                        .unwrap_or(self.items.len());
+                    (None, j..end)
+                }
+            };
+            j = items.end;
+
+            let stmts = self.render_items(items, function.map(|address| &self.functions[&address]));
+            let nodes = self.structure(&stmts, 0..stmts.len());
+            segments.push(Segment {
+                stmts,
+                nodes,
+                function,
+            });
+        }
+        segments
+    }
+
+    fn render_items(&self, items: Range<usize>, function: Option<&Function>) -> Vec<Stmt> {
+        let mut stmts = Vec::default();
+        let mut j = items.start;
+        while j < items.end {
+            let rendered = self.render_item(j, items.end, function);
+            let (kind, consumed) = match rendered {
+                Some(rendered) => rendered,
+                None => {
+                    // As the instructions were.
+                    let item = &self.items[j];
+                    for address in item.address..item.address + item.len {
+                        stmts.push(self.render_instruction(address));
+                    }
+                    j += 1;
+                    continue;
+                }
+            };
+
+            let last = &self.items[j + consumed - 1];
+            let address = self.items[j].address;
+            let len = last.address + last.len - address;
+            stmts.push(Stmt { address, len, kind });
+            j += consumed;
+        }
+        stmts
+    }
+
+    /// Renders the item at `j`, with any after it that it takes in, up to
+    /// `end`, giving how many it took. `None` leaves its instructions as they
+    /// are.
+    fn render_item(
+        &self,
+        j: usize,
+        end: usize,
+        function: Option<&Function>,
+    ) -> Option<(StmtKind, usize)> {
+        let item = &self.items[j];
+        if let Some(function) = function {
+            if let Some(rendered) = self.render_return(j, end, function) {
+                return Some(rendered);
+            }
+            if let Some(rendered) = self.render_temporaries(j, end, function) {
+                return Some(rendered);
+            }
+        }
+
+        let line = |line: String| Some((StmtKind::Line(line), 1));
+        match (&item.kind, function) {
+            (Kind::Instruction, _) => None,
+            (Kind::Jump { target, condition }, _) => {
+                let (target, condition) = (*target, condition.clone());
+                Some((StmtKind::Jump { target, condition }, 1))
+            }
+            (
+                Kind::Call {
+                    target,
+                    args,
+                    frame,
+                },
+                _,
+            ) => {
+                if let Some(callee) = self.functions.get(target) {
+                    let mut call = format!("call {}", function_name(*target));
+                    for arg in args.iter() {
+                        let arg = match arg {
+                            Arg::Value(value) => value.clone(),
+                            Arg::Depth(depth) => slot(function?, *depth)?,
+                        };
+                        call.push(' ');
+                        call.push_str(&arg);
+                    }
+                    if callee.returns > 0 {
+                        call.push_str(" ->");
+                        for k in 0..callee.returns {
+                            call.push(' ');
+                            call.push_str(&self.binding(j + 1 + k, k, function)?);
+                        }
+                    }
+                    Some((StmtKind::Line(call), 1 + callee.returns))
+                } else if args.is_empty()
+                    && *frame == 1
+                    && self.is(item.address + item.len - 1, &["set", "@counter", "_"])
+                {
+                    Some((StmtKind::Callproc(*target), 1))
+                } else {
+                    None
+                }
+            }
+            (Kind::Return { frame }, Some(function)) if *frame == function.frame => {
+                line("return".to_string())
+            }
+            (Kind::Return { frame: 1 }, None) => line("ret".to_string()),
+            (Kind::Push, None) => line("push".to_string()),
+            (Kind::Pop, None) => line("pop".to_string()),
+            (Kind::Read { dest, depth }, Some(function)) => {
+                line(format!("set {} {}", dest, slot(function, *depth)?))
+            }
+            (Kind::Write { value, depth }, Some(function)) => {
+                line(format!("set {} {}", slot(function, *depth)?, value))
+            }
+            (Kind::Read { dest, depth }, None) if dest == "MF_acc" => {
+                line(peek_or_poke("peek", *depth))
+            }
+            (Kind::Write { value, depth }, None) if value == "MF_acc" => {
+                line(peek_or_poke("poke", *depth))
+            }
+            _ => None,
+        }
+    }
+
+    /// A `return` with values, from the `MF_ret<n>` set before it.
+    fn render_return(
+        &self,
+        j: usize,
+        end: usize,
+        function: &Function,
+    ) -> Option<(StmtKind, usize)> {
+        let returns = function.returns;
+        if returns == 0
+            || j + returns >= end
+            || !matches!(self.items[j + returns].kind, Kind::Return { .. })
+        {
+            return None;
+        }
+        let mut line = "return".to_string();
+        for k in 0..returns {
+            match self.return_value(j + k, function) {
+                Some((n, value)) if n == k => {
+                    line.push(' ');
+                    line.push_str(&value);
+                }
+                _ => return None,
+            }
+        }
+        Some((StmtKind::Line(line), returns + 1))
+    }
+
+    /// Stack variables the compiler read into temporaries just to use in the
+    /// next instruction are put back in it, along with a stack variable it
+    /// wrote its result to.
+    fn render_temporaries(
+        &self,
+        j: usize,
+        end: usize,
+        function: &Function,
+    ) -> Option<(StmtKind, usize)> {
+        let mut reads = Vec::default();
+        let mut next = j;
+        while next < end {
+            match &self.items[next].kind {
+                Kind::Read { dest, depth } if is_temporary(dest) => {
+                    reads.push((dest.as_str(), slot(function, *depth)?));
+                    next += 1;
+                }
+                _ => break,
+            }
+        }
+        if next == end {
+            return None;
+        }
+
+        let mut uses = vec![0; reads.len()];
+        let mut substitute = |token: &String| match reads.iter().position(|(temp, _)| temp == token)
+        {
+            Some(n) => {
+                uses[n] += 1;
+                reads[n].1.clone()
+            }
+            None => token.clone(),
+        };
+
+        let consumer = &self.items[next];
+        let (kind, consumed) = match &consumer.kind {
+            Kind::Instruction => match self.instructions[consumer.address].as_slice() {
+                [op, operation, dest, a, b] if op == "op" => {
+                    let (a, b) = (substitute(a), substitute(b));
+
+                    // The result may be written straight to a stack variable.
+                    let write =
+                        self.items[next + 1..end]
+                            .first()
+                            .and_then(|item| match &item.kind {
+                                Kind::Write { value, depth }
+                                    if value == dest && is_temporary(dest) =>
+                                {
+                                    slot(function, *depth)
+                                }
+                                _ => None,
+                            });
+                    let consumed = if write.is_some() { 2 } else { 1 };
+                    let dest = write.unwrap_or_else(|| dest.clone());
+                    let line = format!("op {} {} {} {}", operation, dest, a, b);
+                    (StmtKind::Line(line), consumed)
+                }
+                [print, value] if print == "print" => {
+                    (StmtKind::Line(format!("print {}", substitute(value))), 1)
+                }
+                _ => return None,
+            },
+            Kind::Jump { target, condition } => {
+                let condition = condition.iter().map(&mut substitute).collect();
+                (
+                    StmtKind::Jump {
+                        target: *target,
+                        condition,
+                    },
+                    1,
+                )
+            }
+            Kind::Write { value, depth } if !reads.is_empty() => {
+                let line = format!("set {} {}", slot(function, *depth)?, substitute(value));
+                (StmtKind::Line(line), 1)
+            }
+            _ => return None,
+        };
+
+        let taken = next - j + consumed;
+        let merged = uses.iter().all(|n| *n == 1) && taken > 1;
+        let distinct = reads
+            .iter()
+            .map(|(temp, _)| temp)
+            .collect::<HashSet<_>>()
+            .len()
+            == reads.len();
+        let inside = (j + 1..j + taken).any(|k| self.targets.contains(&self.items[k].address));
+        if merged && distinct && !inside {
+            Some((kind, taken))
+        } else {
+            None
+        }
+    }
+
+    /// A single instruction, as it was.
+    fn render_instruction(&self, address: usize) -> Stmt {
+        let tokens = &self.instructions[address];
+        let kind = match parse_jump(tokens, self.instructions.len()) {
+            Some((target, condition)) => StmtKind::Jump { target, condition },
+            None => {
+                let symbols: Vec<Symbol> = tokens.iter().map(Symbol::from).collect();
+                let line = tokens.join(" ");
+                if tokens[0] != "jump" && MindustryCommand::try_from(symbols).is_ok() {
+                    StmtKind::Line(line)
+                } else {
+                    StmtKind::Line(format!("asm {}", line))
+                }
+            }
+        };
+        Stmt {
+            address,
+            len: 1,
+            kind,
+        }
+    }
+
+    /// Recovers loops and conditionals from the jumps among `range` of
+    /// `stmts`. Any way of nesting them compiles back to the same jumps, but
+    /// nothing may jump to the middle of one.
+    fn structure(&self, stmts: &[Stmt], range: Range<usize>) -> Vec<Node> {
+        let mut nodes = Vec::default();
+        let mut j = range.start;
+        while j < range.end {
+            let (node, next) = self
+                .structure_at(stmts, j, range.end)
+                .unwrap_or((Node::Stmt(j), j + 1));
+            nodes.push(node);
+            j = next;
+        }
+        nodes
+    }
+
+    fn structure_at(&self, stmts: &[Stmt], j: usize, end: usize) -> Option<(Node, usize)> {
+        let start = |k: usize| match stmts.get(k) {
+            Some(stmt) if k < end => stmt.address,
+            _ => stmts[k - 1].address + stmts[k - 1].len,
+        };
+        let find = |address: usize| (j..=end).find(|k| start(*k) == address);
+        let jump = |k: usize| match &stmts[k].kind {
+            StmtKind::Jump { target, condition } if !self.targets.contains(&stmts[k].address) => {
+                Some((*target, condition))
+            }
+            _ => None,
+        };
+        let address = stmts[j].address;
+
+        // The furthest jump back to here closes a loop around everything
+        // before it.
+        let back = (j + 1..end)
+            .rev()
+            .find(|k| jump(*k).map(|(target, _)| target) == Some(address));
+        if let Some(k) = back {
+            let condition = jump(k).unwrap().1.clone();
+            let body = self.structure(stmts, j..k);
+            return Some((
+                Node::DoWhile {
+                    address,
+                    condition,
+                    body,
+                },
+                k + 1,
+            ));
+        }
+
+        let (target, condition) = match &stmts[j].kind {
+            StmtKind::Jump { target, condition } => (*target, condition.clone()),
+            _ => return None,
+        };
+
+        // The compiler's `while` tests the negated condition at the top, to
+        // skip the loop, and the condition at the bottom, to repeat it.
+        if let Some(k) = find(target).filter(|k| *k > j + 1) {
+            let bottom = k - 1;
+            if let Some((back, repeat)) = jump(bottom) {
+                if back == start(j + 1) && is_negation(&condition, repeat) {
+                    let condition = repeat.clone();
+                    let body = self.structure(stmts, j + 1..bottom);
+                    return Some((
+                        Node::While {
+                            address,
+                            condition,
+                            body,
+                        },
+                        k,
+                    ));
+                }
+            }
+        }
+
+        // Its `if` jumps over a jump past the body when the condition holds,
+        // and the body of an `else` follows a jump past that.
+        if j + 2 < end && target == start(j + 2) {
+            if let Some((skip, always)) = jump(j + 1) {
+                let k = find(skip).filter(|k| *k > j + 2 && always == &["always"])?;
+                let otherwise = match jump(k - 1) {
+                    Some((past, always)) if k - 1 > j + 2 && always == &["always"] => {
+                        find(past).filter(|k2| *k2 > k)
+                    }
+                    _ => None,
+                };
+
+                let (then, otherwise, next) = match otherwise {
+                    Some(k2) => (
+                        self.structure(stmts, j + 2..k - 1),
+                        Some(self.structure(stmts, k..k2)),
+                        k2,
+                    ),
+                    None => (self.structure(stmts, j + 2..k), None, k),
+                };
+                let node = Node::If {
+                    address,
+                    condition,
+                    then,
+                    otherwise,
+                };
+                return Some((node, next));
+            }
+        }
+
+        None
+    }
+}
+
+/// Lines of source being rendered.
+struct Lines<'a> {
+    stmts: &'a [Stmt],
+    labels: &'a HashSet<usize>,
+    emitted: &'a mut HashSet<usize>,
+    lines: &'a mut Vec<String>,
+}
+
+impl<'a> Lines<'a> {
+    fn push(&mut self, indent: usize, line: &str) {
+        self.lines.push(format!("{}{}", "  ".repeat(indent), line));
+    }
+
+    /// The label for `address`, if something jumps there and it hasn't been
+    /// placed yet.
+    fn label(&mut self, address: usize, indent: usize) {
+        if self.labels.contains(&address) && self.emitted.insert(address) {
+            self.push(indent, &format!("{}:", label_name(address)));
+        }
+    }
+
+    fn render(&mut self, nodes: &[Node], indent: usize) {
+        for node in nodes.iter() {
+            self.label(node.address(self.stmts), indent);
+            match node {
+                Node::Stmt(j) => {
+                    let line = match &self.stmts[*j].kind {
+                        StmtKind::Line(line) => line.clone(),
+                        StmtKind::Jump { target, condition } => {
+                            format!("jump {} {}", label_name(*target), condition.join(" "))
+                        }
+                        StmtKind::Callproc(target) => format!("callproc {}", label_name(*target)),
+                    };
+                    self.push(indent, &line);
+                }
+                Node::If {
+                    condition,
+                    then,
+                    otherwise,
+                    ..
+                } => {
+                    self.push(indent, &format!("if {} {{", condition.join(" ")));
+                    self.render(then, indent + 1);
+                    if let Some(otherwise) = otherwise {
+                        self.push(indent, "} else {");
+                        self.render(otherwise, indent + 1);
+                    }
+                    self.push(indent, "}");
+                }
+                Node::While {
+                    condition, body, ..
+                } => {
+                    self.push(indent, &format!("while {} {{", condition.join(" ")));
+                    self.render(body, indent + 1);
+                    self.push(indent, "}");
+                }
+                Node::DoWhile {
+                    condition, body, ..
+                } => {
+                    if condition == &["always"] {
+                        self.push(indent, "loop {");
+                        self.render(body, indent + 1);
+                        self.push(indent, "}");
+                    } else {
+                        self.push(indent, "do {");
+                        self.render(body, indent + 1);
+                        self.push(indent, &format!("}} while {}", condition.join(" ")));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Adds the addresses that jumps left in `nodes` go to.
+fn collect_labels(stmts: &[Stmt], nodes: &[Node], labels: &mut HashSet<usize>) {
+    for node in nodes.iter() {
+        match node {
+            Node::Stmt(j) => match &stmts[*j].kind {
+                StmtKind::Jump { target, .. } | StmtKind::Callproc(target) => {
+                    labels.insert(*target);
+                }
+                StmtKind::Line(_) => {}
+            },
+            Node::If {
+                then, otherwise, ..
+            } => {
+                collect_labels(stmts, then, labels);
+                if let Some(otherwise) = otherwise {
+                    collect_labels(stmts, otherwise, labels);
+                }
+            }
+            Node::While { body, .. } | Node::DoWhile { body, .. } => {
+                collect_labels(stmts, body, labels)
+            }
+        }
+    }
+}
+
+fn label_name(address: usize) -> String {
+    format!("label_{}", address)
+}
+
+fn function_name(address: usize) -> String {
+    format!("function_{}", address)
+}
+
+/// The stack variable `depth` below the top of `function`'s frame, if that is
+/// in the frame. The return address is at the bottom.
+fn slot(function: &Function, depth: usize) -> Option<String> {
+    if depth == 0 || depth >= function.frame {
+        return None;
+    }
+    Some(slot_name(function, function.frame - depth))
+}
+
+/// Arguments come first in a frame, after the return address, then the
+/// function's other stack variables.
+fn slot_name(function: &Function, slot: usize) -> String {
+    if slot <= function.args {
+        format!("*arg{}", slot)
+    } else {
+        format!("*local{}", slot - function.args)
+    }
+}
+
+fn peek_or_poke(name: &str, depth: usize) -> String {
+    match depth {
+        1 => name.to_string(),
+        depth => format!("{} {}", name, depth - 1),
+    }
+}
+
+/// The compiler's temporaries for stack variables, `MF_t<n>`.
+fn is_temporary(name: &str) -> bool {
+    name.starts_with("MF_t") && name["MF_t".len()..].parse::<usize>().is_ok()
+}
+
+/// Variables the compiler uses for itself, which can't stand in for a value
+/// passed to or from a function.
+fn is_internal(name: &str) -> bool {
+    name.starts_with("MF_")
+}
@@ -0,0 +1,243 @@
+use crate::*;
+
+/// How many unchanged instructions are shown around each change.
+const DIFF_CONTEXT: usize = 3;
+
+/// The differences between two programs generated from the same or related
+/// source, such as before and after an edit or an optimization, with each
+/// instruction aligned against its counterpart.
+///
+/// Instructions are compared as in the `SymbolicProgram`, so jumps to the
+/// source's labels and functions match wherever they moved to. Jumps to other
+/// addresses match whatever the address, since nearly any change moves
+/// them. Calls and the internal stack's tables compute addresses into
+/// `@counter`, so show up as changed when the code they reach moves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProgramDiff {
+    pub old: DiffSide,
+    pub new: DiffSide,
+
+    /// Every instruction of both programs, in order.
+    pub lines: Vec<DiffLine>,
+}
+
+/// One of the programs being compared.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiffSide {
+    /// The instructions, with jumps to labels as in `SymbolicProgram`.
+    pub instructions: Vec<String>,
+
+    /// The source line each instruction was generated from, if known, as
+    /// `<line>: <text>`.
+    pub sources: Vec<Option<String>>,
+
+    /// What each instruction is compared by.
+    keys: Vec<String>,
+}
+
+/// An instruction in a `ProgramDiff`, by its address in the program it is in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffLine {
+    Same { old: usize, new: usize },
+    Removed { old: usize },
+    Added { new: usize },
+}
+
+impl DiffSide {
+    /// `output`, the program generated from `ir`.
+    pub fn new(ir: &IntermediateRepresentation, output: &[String]) -> DiffSide {
+        let program = SymbolicProgram::new(ir, output);
+        let source_map = ir.source_map();
+        let sources = (0..output.len())
+            .map(|address| {
+                let line = source_map.location(address)?.line;
+                let text = ir.source_lines.get(line)?;
+                Some(format!("{}: {}", line, text.trim()))
+            })
+            .collect();
+
+        // Names made up for an address are left out of the comparison.
+        let keys = program
+            .instructions
+            .iter()
+            .map(|line| {
+                let target = line
+                    .strip_prefix("jump ")
+                    .and_then(|operands| operands.split_whitespace().next());
+                match target {
+                    Some(target)
+                        if program.labels.iter().any(|(name, address)| {
+                            name == target && *name == format!("L{}", address)
+                        }) =>
+                    {
+                        line.replacen(target, "L", 1)
+                    }
+                    _ => line.clone(),
+                }
+            })
+            .collect();
+
+        DiffSide {
+            instructions: program.instructions,
+            sources,
+            keys,
+        }
+    }
+}
+
+impl ProgramDiff {
+    pub fn new(old: DiffSide, new: DiffSide) -> ProgramDiff {
+        let (n, m) = (old.keys.len(), new.keys.len());
+
+        // `common[j][k]` is the length of the longest common subsequence of
+        // the instructions from `j` and `k` on.
+        let mut common = vec![vec![0; m + 1]; n + 1];
+        for j in (0..n).rev() {
+            for k in (0..m).rev() {
+                common[j][k] = if old.keys[j] == new.keys[k] {
+                    common[j + 1][k + 1] + 1
+                } else {
+                    std::cmp::max(common[j + 1][k], common[j][k + 1])
+                };
+            }
+        }
+
+        let mut lines = Vec::default();
+        let (mut j, mut k) = (0, 0);
+        while j < n || k < m {
+            if j < n && k < m && old.keys[j] == new.keys[k] {
+                lines.push(DiffLine::Same { old: j, new: k });
+                j += 1;
+                k += 1;
+            } else if k == m || (j < n && common[j + 1][k] >= common[j][k + 1]) {
+                lines.push(DiffLine::Removed { old: j });
+                j += 1;
+            } else {
+                lines.push(DiffLine::Added { new: k });
+                k += 1;
+            }
+        }
+
+        ProgramDiff { old, new, lines }
+    }
+
+    /// Whether the programs are the same.
+    pub fn is_empty(&self) -> bool {
+        self.lines
+            .iter()
+            .all(|line| matches!(line, DiffLine::Same { .. }))
+    }
+
+    /// How many instructions were removed and added.
+    pub fn counts(&self) -> (usize, usize) {
+        let removed = self
+            .lines
+            .iter()
+            .filter(|line| matches!(line, DiffLine::Removed { .. }))
+            .count();
+        let added = self
+            .lines
+            .iter()
+            .filter(|line| matches!(line, DiffLine::Added { .. }))
+            .count();
+        (removed, added)
+    }
+
+    /// The text of the instruction at `line`.
+    fn instruction(&self, line: &DiffLine) -> &str {
+        match line {
+            DiffLine::Same { new, .. } | DiffLine::Added { new } => &self.new.instructions[*new],
+            DiffLine::Removed { old } => &self.old.instructions[*old],
+        }
+    }
+}
+
+/// The changes, each with a few unchanged instructions around it, as:
+///
+/// ```text
+///    12    12   set a 1          // src 3: set a 1
+/// -  13         op add a a 1     // src 4: op add a a 1
+/// +        13   op add a a 2     // src 4: op add a a 2
+/// ```
+///
+/// giving the address of each instruction in the old and new programs, and
+/// the source line it came from. Runs of unchanged instructions in between
+/// are shown as `...`.
+impl std::fmt::Display for ProgramDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (removed, added) = self.counts();
+        writeln!(
+            f,
+            "{} instructions -> {} instructions: {} removed, {} added",
+            self.old.instructions.len(),
+            self.new.instructions.len(),
+            removed,
+            added
+        )?;
+
+        let changed: Vec<usize> = self
+            .lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| !matches!(line, DiffLine::Same { .. }))
+            .map(|(j, _)| j)
+            .collect();
+        let shown = |j: usize| {
+            changed
+                .iter()
+                .any(|&c| c <= j + DIFF_CONTEXT && j <= c + DIFF_CONTEXT)
+        };
+        let width = self
+            .lines
+            .iter()
+            .map(|line| self.instruction(line).len())
+            .max()
+            .unwrap_or(0);
+
+        let mut skipped = false;
+        for (j, line) in self.lines.iter().enumerate() {
+            if !shown(j) {
+                skipped = true;
+                continue;
+            }
+            if std::mem::take(&mut skipped) {
+                writeln!(f, "...")?;
+            }
+
+            let address = |address: Option<&usize>| match address {
+                Some(address) => format!("{:5}", address),
+                None => " ".repeat(5),
+            };
+            let (mark, old, new, source) = match line {
+                DiffLine::Same { old, new } => (' ', Some(old), Some(new), &self.new.sources[*new]),
+                DiffLine::Removed { old } => ('-', Some(old), None, &self.old.sources[*old]),
+                DiffLine::Added { new } => ('+', None, Some(new), &self.new.sources[*new]),
+            };
+            let instruction = self.instruction(line);
+            match source {
+                Some(source) => writeln!(
+                    f,
+                    "{}{} {}   {:width$}  // src {}",
+                    mark,
+                    address(old),
+                    address(new),
+                    instruction,
+                    source,
+                    width = width
+                )?,
+                None => writeln!(
+                    f,
+                    "{}{} {}   {}",
+                    mark,
+                    address(old),
+                    address(new),
+                    instruction
+                )?,
+            }
+        }
+        if skipped && !changed.is_empty() {
+            writeln!(f, "...")?;
+        }
+        Ok(())
+    }
+}
@@ -1,42 +1,696 @@
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::convert::TryFrom;
+
+use serde::{Deserialize, Serialize};
 
 use crate::*;
 
-/// Simple emulator for a small subset of Mindustry programs. The goal here is
-/// to write control flow tests, so we only need a handful of operations. I've
-/// taken various shortcuts here (e.g., all values are integers, conditionals
-/// just treat anything involving null as false, etc).
+/// How close two numbers must be for `equal` to consider them the same.
+pub const EQUALITY_EPSILON: f64 = 0.000001;
+
+/// A value in a variable. A variable that was never set is null.
+///
+/// As in Mindustry, null is 0 in math, in comparisons other than `equal`,
+/// `notEqual`, and `strictEqual`, and when written to memory. `equal` and
+/// `notEqual` compare it as 0 too, unless both sides are null, which are
+/// equal. `strictEqual` is only true for null and null.
+///
+/// The only other objects are content, such as `@copper`, which `lookup`
+/// gives, and the buildings and units of the `World`. They are 1 as a
+/// number, and like null, only equal to themselves.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Value {
+    Null,
+    Number(f64),
+
+    /// Content by the name code refers to it by, such as `@copper`.
+    Content(Symbol),
+
+    /// A building or unit of the `World`, by name.
+    Object(Symbol),
+}
+
+impl Value {
+    /// The value as a number.
+    pub fn num(&self) -> f64 {
+        match self {
+            Value::Null => 0.0,
+            Value::Number(n) => *n,
+            Value::Content(_) | Value::Object(_) => 1.0,
+        }
+    }
+
+    /// The number, or `None` for null or content.
+    pub fn number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    fn equal(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Number(_), _) | (_, Value::Number(_)) => {
+                (self.num() - other.num()).abs() < EQUALITY_EPSILON
+            }
+            _ => self == other,
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Null => "null".fmt(f),
+            Value::Number(n) => format_number(*n).fmt(f),
+            Value::Content(name) | Value::Object(name) => name.trim_start_matches('@').fmt(f),
+        }
+    }
+}
 
-#[derive(Clone, Debug)]
+/// A memory cell. Memory in Mindustry is all 0 to begin with; here, a slot
+/// that was never written reads as 0, but is `None`, so tests can tell.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Cell {
-    name: Rc<String>,
-    data: Vec<Option<usize>>,
+    name: Symbol,
+    data: Vec<Option<f64>>,
 }
 
 impl Cell {
-    pub fn new(name: Rc<String>) -> Cell {
+    /// A cell of 512 entries, as large as a memory bank.
+    pub fn new<S: Into<Symbol>>(name: S) -> Cell {
+        Cell::memory_bank(name)
+    }
+
+    /// A memory cell, of 64 entries.
+    pub fn memory_cell<S: Into<Symbol>>(name: S) -> Cell {
+        Cell::with_size(name, CELL_SIZE)
+    }
+
+    /// A memory bank, of 512 entries.
+    pub fn memory_bank<S: Into<Symbol>>(name: S) -> Cell {
+        Cell::with_size(name, BANK_SIZE)
+    }
+
+    /// A cell of `size` entries. Reads outside it give 0, and writes do
+    /// nothing, as in Mindustry.
+    pub fn with_size<S: Into<Symbol>>(name: S, size: usize) -> Cell {
         Cell {
-            data: vec![None; 512],
-            name,
+            data: vec![None; size],
+            name: name.into(),
+        }
+    }
+
+    pub fn name(&self) -> Symbol {
+        self.name.clone()
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// The value at `address`, or `None` if it was never written.
+    pub fn get(&self, address: usize) -> Option<f64> {
+        self.data.get(address).copied().flatten()
+    }
+
+    /// Writes the cell's starting contents from `text`, as the simulator's
+    /// `--mem` reads them: numbers separated by whitespace, for addresses from
+    /// 0 on. `<address>:` moves on to that address, and `_` skips one,
+    /// leaving it unwritten. `#` starts a comment.
+    pub fn load(&mut self, text: &str) -> Result<()> {
+        let mut address = 0;
+        for (j, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            for token in line.split_whitespace() {
+                (|| -> Result<()> {
+                    if let Some(target) = token.strip_suffix(':') {
+                        address = target.parse().context("address must be an integer")?;
+                        return Ok(());
+                    }
+                    if address >= self.data.len() {
+                        bail!("address {} is past the end of {}", address, self.name);
+                    }
+                    if token != "_" {
+                        let value = token.parse().context("value must be a number")?;
+                        self.data[address] = Some(value);
+                    }
+                    address += 1;
+                    Ok(())
+                })()
+                .with_context(|| format!("Line {}: {}", j, token))?;
+            }
         }
+        Ok(())
     }
 }
 
 impl Default for Cell {
     fn default() -> Cell {
-        Self::new(Rc::new("bank1".to_string()))
+        Self::new(Symbol::new("bank1"))
+    }
+}
+
+/// The kinds of content `lookup` can find by ID.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ContentType {
+    Item,
+    Liquid,
+    Unit,
+    Block,
+}
+
+impl ContentType {
+    pub const ALL: &'static [ContentType] = &[
+        ContentType::Item,
+        ContentType::Liquid,
+        ContentType::Unit,
+        ContentType::Block,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContentType::Item => "item",
+            ContentType::Liquid => "liquid",
+            ContentType::Unit => "unit",
+            ContentType::Block => "block",
+        }
+    }
+
+    /// The content of this type the emulator knows to begin with, in order
+    /// of ID. Only items and liquids are known; tests that look up units or
+    /// blocks give their own with `set_lookup_table`.
+    fn defaults(&self) -> &'static [&'static str] {
+        match self {
+            ContentType::Item => &[
+                "copper",
+                "lead",
+                "metaglass",
+                "graphite",
+                "sand",
+                "coal",
+                "titanium",
+                "thorium",
+                "scrap",
+                "silicon",
+                "plastanium",
+                "phase-fabric",
+                "surge-alloy",
+                "spore-pod",
+                "blast-compound",
+                "pyratite",
+                "beryllium",
+                "tungsten",
+                "oxide",
+                "carbide",
+                "fissile-matter",
+                "dormant-cyst",
+            ],
+            ContentType::Liquid => &[
+                "water",
+                "slag",
+                "oil",
+                "cryofluid",
+                "neoplasm",
+                "arkycite",
+                "gallium",
+                "ozone",
+                "hydrogen",
+                "nitrogen",
+                "cyanogen",
+            ],
+            ContentType::Unit | ContentType::Block => &[],
+        }
+    }
+}
+
+impl std::fmt::Display for ContentType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.name().fmt(f)
+    }
+}
+
+/// A building or unit in the `World`, and what `sensor` gives for it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WorldObject {
+    name: Symbol,
+    sensors: HashMap<Symbol, Value>,
+}
+
+impl WorldObject {
+    /// A building by the name it's linked as, such as `switch1`, or a unit by
+    /// its type, such as `@poly`.
+    pub fn new<S: Into<Symbol>>(name: S) -> WorldObject {
+        WorldObject {
+            name: name.into(),
+            sensors: HashMap::default(),
+        }
+    }
+
+    /// Sets what `sensor` gives for `property`, such as `@copper`. Any
+    /// other property is null.
+    pub fn with_sensor(mut self, property: &str, value: Value) -> WorldObject {
+        self.sensors.insert(Symbol::new(property), value);
+        self
+    }
+}
+
+/// A `ucontrol` a program ran, with the arguments' values.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct UnitCommand {
+    /// The type of the unit bound at the time, if any.
+    pub unit: Option<Symbol>,
+    pub command: Symbol,
+    pub args: Vec<Value>,
+}
+
+/// The map a program runs in, as far as the emulator models it: the buildings
+/// linked to the processor, for `getlink` and `sensor`; the units `ubind` can
+/// bind; and the content `lookup` finds. Unit commands do nothing, but are
+/// recorded so tests can check them.
+///
+/// Linked buildings are variables by their names, as in Mindustry, and the
+/// bound unit is `@unit`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct World {
+    links: Vec<WorldObject>,
+    units: Vec<WorldObject>,
+    lookup: HashMap<ContentType, Vec<Symbol>>,
+    bound: Option<Symbol>,
+    commands: Vec<UnitCommand>,
+}
+
+impl Default for World {
+    fn default() -> World {
+        let mut world = World {
+            links: Vec::default(),
+            units: Vec::default(),
+            lookup: HashMap::default(),
+            bound: None,
+            commands: Vec::default(),
+        };
+        for kind in ContentType::ALL {
+            let names = kind.defaults().iter().map(|name| Symbol::new(name));
+            world.set_lookup_table(*kind, names.collect());
+        }
+        world
+    }
+}
+
+impl World {
+    /// Links a building to the processor, after those already linked.
+    pub fn link(&mut self, building: WorldObject) {
+        self.links.push(building);
+    }
+
+    /// Adds a unit for `ubind` to bind. Only the first of a type is bound.
+    pub fn add_unit(&mut self, unit: WorldObject) {
+        self.units.push(unit);
+    }
+
+    /// Sets the content `lookup` finds of a type, in order of ID, by name
+    /// without the `@`. Each can also be referred to by name, as `@name`.
+    pub fn set_lookup_table(&mut self, kind: ContentType, names: Vec<Symbol>) {
+        let content = names
+            .iter()
+            .map(|name| Symbol::new(&format!("@{}", name)))
+            .collect();
+        self.lookup.insert(kind, content);
+    }
+
+    /// The type of the bound unit, if any.
+    pub fn bound(&self) -> Option<Symbol> {
+        self.bound.clone()
+    }
+
+    /// The unit commands run so far, in order.
+    pub fn commands(&self) -> &[UnitCommand] {
+        &self.commands
+    }
+
+    /// The variables the world defines: each linked building, `@links`, and
+    /// each piece of content.
+    fn constants(&self) -> Vec<(Symbol, Value)> {
+        let mut constants: Vec<(Symbol, Value)> = self
+            .links
+            .iter()
+            .map(|building| (building.name.clone(), Value::Object(building.name.clone())))
+            .collect();
+        constants.push((
+            Symbol::new("@links"),
+            Value::Number(self.links.len() as f64),
+        ));
+        for content in self.lookup.values() {
+            constants.extend(
+                content
+                    .iter()
+                    .map(|name| (name.clone(), Value::Content(name.clone()))),
+            );
+        }
+        constants
+    }
+
+    fn object(&self, name: &Symbol) -> Option<&WorldObject> {
+        self.links
+            .iter()
+            .chain(self.units.iter())
+            .find(|object| object.name == *name)
+    }
+
+    /// Runs an instruction that uses the world. Others do nothing.
+    fn execute(&mut self, instruction: &Instruction, vars: &mut HashMap<Symbol, Value>) {
+        // Content and objects are referred to by name, whether written out
+        // or in a variable.
+        let name = |vars: &HashMap<Symbol, Value>, arg: &Symbol| match resolve(vars, arg) {
+            Value::Content(name) | Value::Object(name) => name,
+            _ => arg.clone(),
+        };
+
+        match instruction {
+            Instruction::Lookup(kind, dest, id) => {
+                // An ID with no content is null.
+                let id = resolve(vars, id).num();
+                let content = self
+                    .lookup
+                    .get(kind)
+                    .filter(|_| id >= 0.0)
+                    .and_then(|content| content.get(truncate(id)));
+                let value = match content {
+                    Some(name) => Value::Content(name.clone()),
+                    None => Value::Null,
+                };
+                vars.insert(dest.clone(), value);
+            }
+            Instruction::GetLink(dest, index) => {
+                let index = resolve(vars, index).num();
+                let building = self.links.get(truncate(index)).filter(|_| index >= 0.0);
+                let value = match building {
+                    Some(building) => Value::Object(building.name.clone()),
+                    None => Value::Null,
+                };
+                vars.insert(dest.clone(), value);
+            }
+            Instruction::Sensor(dest, target, property) => {
+                let value = match resolve(vars, target) {
+                    Value::Object(target) => self
+                        .object(&target)
+                        .and_then(|object| object.sensors.get(&name(vars, property)))
+                        .cloned()
+                        .unwrap_or(Value::Null),
+                    _ => Value::Null,
+                };
+                vars.insert(dest.clone(), value);
+            }
+            Instruction::UBind(unit) => {
+                let unit = name(vars, unit);
+                self.bound = self
+                    .units
+                    .iter()
+                    .find(|u| u.name == unit)
+                    .map(|u| u.name.clone());
+                let value = match &self.bound {
+                    Some(unit) => Value::Object(unit.clone()),
+                    None => Value::Null,
+                };
+                vars.insert(Symbol::new("@unit"), value);
+            }
+            Instruction::UControl(command, args) => {
+                self.commands.push(UnitCommand {
+                    unit: self.bound.clone(),
+                    command: command.clone(),
+                    args: args.iter().map(|arg| resolve(vars, arg)).collect(),
+                });
+            }
+            _ => {}
+        }
     }
 }
 
+/// Simple emulator for a small subset of Mindustry programs. The goal here is
+/// to write control flow tests, so we only need a handful of operations. I've
+/// taken various shortcuts here (e.g., there are no strings, and the only
+/// objects are null, content, and those of the `World`).
+///
+/// Values are doubles or null, as in Mindustry. As there, a value used as an
+/// address or written to `@counter` is truncated to an integer, a math result
+/// that isn't a finite number is 0, and `equal` treats numbers within
+/// `EQUALITY_EPSILON` of each other as equal. See `Value` for null.
 pub struct Emulator {
-    cell: Option<Cell>,
+    cells: Vec<Cell>,
     instructions: Vec<Instruction>,
-    vars: HashMap<Rc<String>, usize>,
-    counter: Rc<String>,
-    watches: Vec<Rc<String>>,
+    vars: HashMap<Symbol, Value>,
+    counter: Symbol,
+    watches: Vec<Symbol>,
+    watchpoints: Vec<Watchpoint>,
     breakpoints: Vec<usize>,
-    print_buffer: Vec<String>,
+
+    // The program's source map, from `set_source_map`, and the return
+    // address of each call in progress, innermost last.
+    source_map: SourceMap,
+    call_stack: Vec<usize>,
+    buffers: Buffers,
+    draw_flushes: Vec<DrawFlush>,
+    warn_print_overflow: bool,
+
+    world: World,
+    rng: Rng,
+    clock: Clock,
+
+    // How many times the instruction at each address has run.
+    executions: Vec<u64>,
+
+    // The steps run so far, and copies of the state taken every
+    // `snapshot_interval` steps, oldest first, for `run_back`.
+    steps: usize,
+    snapshot_interval: Option<usize>,
+    snapshots: Vec<Snapshot>,
+}
+
+/// A copy of everything running the program changes, as it was after `steps`
+/// steps. This is also what `save_state` writes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Snapshot {
+    steps: usize,
+    executions: Vec<u64>,
+    cells: Vec<Cell>,
+    vars: HashMap<Symbol, Value>,
+    call_stack: Vec<usize>,
+    buffers: Buffers,
+    draw_flushes: Vec<DrawFlush>,
+    world: World,
+    rng: Rng,
+    clock: Clock,
+}
+
+/// How many instructions a micro processor runs each tick, which is what the
+/// emulator runs unless told otherwise. A logic processor runs 8, and a hyper
+/// processor 25.
+pub const DEFAULT_INSTRUCTIONS_PER_TICK: usize = 2;
+
+const TICKS_PER_SECOND: f64 = 60.0;
+
+/// Simulated time. A processor runs a fixed number of instructions each
+/// tick, of which there are 60 a second.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Clock {
+    instructions_per_tick: usize,
+    ticks: u64,
+
+    // Instructions run so far in this tick.
+    steps: usize,
+}
+
+impl Clock {
+    /// Counts an instruction, moving on to the next tick once this one has
+    /// run its share.
+    fn step(&mut self) {
+        self.steps += 1;
+        if self.steps >= self.instructions_per_tick {
+            self.ticks += 1;
+            self.steps = 0;
+        }
+    }
+
+    /// Runs a `wait`. As in Mindustry, the processor runs it once a tick, each
+    /// time giving up the rest of the tick, until enough time has passed. So
+    /// the instruction after it runs in the tick `seconds` after the `wait`
+    /// started, rounded up.
+    fn wait(&mut self, seconds: f64) {
+        let ticks = (seconds * TICKS_PER_SECOND).ceil();
+        if ticks >= 1.0 {
+            self.ticks += ticks as u64;
+            self.steps = 0;
+        }
+        self.step();
+    }
+
+    /// Sets `@tick`, `@second`, and `@time` to the time since the program
+    /// started, in ticks, seconds, and milliseconds. Time only passes between
+    /// ticks, so they're the same for each instruction in one.
+    fn define_time(&self, vars: &mut HashMap<Symbol, Value>) {
+        let ticks = self.ticks as f64;
+        vars.insert(Symbol::new("@tick"), Value::Number(ticks));
+        vars.insert(
+            Symbol::new("@second"),
+            Value::Number(ticks / TICKS_PER_SECOND),
+        );
+        vars.insert(
+            Symbol::new("@time"),
+            Value::Number(ticks * 1000.0 / TICKS_PER_SECOND),
+        );
+    }
+}
+
+/// Something `run` stops on when it changes. See `Emulator::set_watchpoints`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Watchpoint {
+    Var(Symbol),
+
+    /// An address in a memory cell, written `bank1[12]`.
+    Memory(Symbol, usize),
+}
+
+impl std::convert::TryFrom<&str> for Watchpoint {
+    type Error = anyhow::Error;
+
+    fn try_from(text: &str) -> Result<Watchpoint> {
+        let (cell, address) = match text.strip_suffix(']').and_then(|t| t.split_once('[')) {
+            Some(parts) => parts,
+            None => return Ok(Watchpoint::Var(Symbol::new(text))),
+        };
+        let address = address
+            .parse()
+            .with_context(|| format!("address of watchpoint {} must be an integer", text))?;
+        Ok(Watchpoint::Memory(Symbol::new(cell), address))
+    }
+}
+
+impl std::fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Watchpoint::Var(var) => var.fmt(f),
+            Watchpoint::Memory(cell, address) => write!(f, "{}[{}]", cell, address),
+        }
+    }
+}
+
+/// The most characters the print buffer holds. As in Mindustry, anything
+/// printed past this is dropped.
+pub const MAX_PRINT_BUFFER: usize = 400;
+
+/// What `print` and `draw` have added since the last flush.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct Buffers {
+    print: String,
+    draw: Vec<DrawCommand>,
+
+    // Characters `print` has dropped since the last step, for lack of room.
+    dropped: usize,
+}
+
+impl Buffers {
+    fn print(&mut self, text: &str) {
+        let room = MAX_PRINT_BUFFER.saturating_sub(self.print.chars().count());
+        let kept: String = text.chars().take(room).collect();
+        self.dropped += text.chars().count() - kept.chars().count();
+        self.print.push_str(&kept);
+    }
+}
+
+/// A `draw` a program ran, with the arguments' values. Arguments left out are
+/// 0, so there are always six.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DrawCommand {
+    pub command: Symbol,
+    pub args: Vec<Value>,
+}
+
+/// The draw commands a `drawflush` sent to a display.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DrawFlush {
+    pub display: Symbol,
+    pub commands: Vec<DrawCommand>,
+}
+
+/// Something that happened as the emulator ran, for tools that would rather
+/// not pick apart the trace `run` gives. `Emulator::render` turns these into
+/// that trace.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// The instruction at `ip` ran. `watches` has the value of each variable
+    /// given to `Emulator::set_watches` just before it did, or `None` for a
+    /// stack variable not in the frame of the function being run.
+    Executed {
+        ip: usize,
+        instruction: Instruction,
+        watches: Vec<(Symbol, Option<Value>)>,
+    },
+
+    /// `print` dropped this many characters, as the print buffer was full.
+    /// Only given after `Emulator::set_warn_print_overflow`.
+    PrintOverflow(usize),
+
+    /// `printflush` sent the print buffer to `target`.
+    Printed { target: Symbol, text: String },
+
+    /// `drawflush` sent this many draw commands to `display`. See
+    /// `Emulator::draw_flushes` for the commands.
+    Drew { display: Symbol, commands: usize },
+
+    /// The instruction at `ip` changed what a watchpoint watches.
+    WatchpointChanged {
+        watchpoint: Watchpoint,
+        old: Value,
+        new: Value,
+        ip: usize,
+    },
+
+    /// Running stopped at a breakpoint, before the instruction there.
+    BreakpointHit(usize),
+
+    /// The program reached `end`, or ran past its last instruction, so starts
+    /// over from the first.
+    Ended,
+
+    /// Running used up its steps. `histogram` has the addresses of the last
+    /// `LOOP_WINDOW` instructions run, each with how often it ran, most
+    /// first, and `cycle` the shortest sequence of addresses that repeated
+    /// throughout them, if any, so a stuck loop is easy to tell from a long
+    /// run.
+    StepLimit {
+        steps: usize,
+        histogram: Vec<(usize, usize)>,
+        cycle: Option<Vec<usize>>,
+    },
+}
+
+/// How many of the last instructions run `Event::StepLimit` looks at. Runs of
+/// fewer steps, such as single steps, don't give it.
+pub const LOOP_WINDOW: usize = 100;
+
+/// How many addresses the trace shows from `Event::StepLimit`'s histogram.
+const REPORTED_ADDRESSES: usize = 5;
+
+/// The generator for `op rand`. It's SplitMix64, which is small and good
+/// enough for tests, and the same for a seed on every platform.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -44,7 +698,30 @@ pub enum Math {
     Add,
     Sub,
     Mul,
+    Div,
     Mod,
+    Idiv,
+    Pow,
+    Min,
+    Max,
+
+    // Bitwise, on the values truncated to 64-bit integers.
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+    Not,
+
+    // 1 if both are nonzero, else 0.
+    Land,
+
+    // These only use the first argument.
+    Sqrt,
+    Abs,
+    Log,
+    Floor,
+    Ceil,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -52,8 +729,11 @@ pub enum Cond {
     Always,
     Lt,
     Gt,
+    Le,
+    Ge,
     Eq,
     Ne,
+    StrictEq,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -61,34 +741,135 @@ pub enum Instruction {
     // As end, except don't reset instruction pointer -- just move past the pause.
     Pause,
     End,
-    Math(Math, Rc<String>, Rc<String>, Rc<String>),
-    Read(Rc<String>, Rc<String>, Rc<String>),
-    Write(Rc<String>, Rc<String>, Rc<String>),
-    Set(Rc<String>, Rc<String>),
-    Jump(Cond, usize, Rc<String>, Rc<String>),
-    Print(Rc<String>),
-    PrintFlush(Rc<String>),
+    // Halts for good -- the instruction pointer stays on the stop.
+    Stop,
+    Math(Math, Symbol, Symbol, Symbol),
+    Read(Symbol, Symbol, Symbol),
+    Write(Symbol, Symbol, Symbol),
+    Set(Symbol, Symbol),
+    Jump(Cond, usize, Symbol, Symbol),
+    // `op rand`: sets the first to a random number from 0 up to the second.
+    Rand(Symbol, Symbol),
+    // Sets the first to the fourth if the condition holds for the second and
+    // third, else the fifth.
+    Select(Cond, Symbol, Symbol, Symbol, Symbol, Symbol),
+    // Sets the symbol to the content of that type with the ID.
+    Lookup(ContentType, Symbol, Symbol),
+    // These use the `World`.
+    GetLink(Symbol, Symbol),
+    Sensor(Symbol, Symbol, Symbol),
+    UBind(Symbol),
+    UControl(Symbol, [Symbol; 5]),
+    Print(Symbol),
+    PrintFlush(Symbol),
+    Draw(Symbol, [Symbol; 6]),
+    DrawFlush(Symbol),
+    // Waits for the number of seconds.
+    Wait(Symbol),
 }
 
-impl std::fmt::Display for Math {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl Math {
+    pub const ALL: &'static [Math] = &[
+        Math::Add,
+        Math::Sub,
+        Math::Mul,
+        Math::Div,
+        Math::Mod,
+        Math::Idiv,
+        Math::Pow,
+        Math::Min,
+        Math::Max,
+        Math::And,
+        Math::Or,
+        Math::Xor,
+        Math::Shl,
+        Math::Shr,
+        Math::Not,
+        Math::Land,
+        Math::Sqrt,
+        Math::Abs,
+        Math::Log,
+        Math::Floor,
+        Math::Ceil,
+    ];
+
+    /// The name of the operation in an `op` instruction.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Math::Add => "add",
+            Math::Sub => "sub",
+            Math::Mul => "mul",
+            Math::Div => "div",
+            Math::Mod => "mod",
+            Math::Idiv => "idiv",
+            Math::Pow => "pow",
+            Math::Min => "min",
+            Math::Max => "max",
+            Math::And => "and",
+            Math::Or => "or",
+            Math::Xor => "xor",
+            Math::Shl => "shl",
+            Math::Shr => "shr",
+            Math::Not => "not",
+            Math::Land => "land",
+            Math::Sqrt => "sqrt",
+            Math::Abs => "abs",
+            Math::Log => "log",
+            Math::Floor => "floor",
+            Math::Ceil => "ceil",
+        }
+    }
+
+    /// Applies the operation as Mindustry does, before a result that isn't a
+    /// finite number is replaced by 0.
+    pub fn apply(&self, a: f64, b: f64) -> f64 {
+        // Java's casts from double to long saturate, as Rust's do, and its
+        // shifts use only the low six bits of the count, as `wrapping_shl`
+        // does.
+        let (x, y) = (a as i64, b as i64);
         match self {
-            Math::Add => "add".fmt(f),
-            Math::Sub => "sub".fmt(f),
-            Math::Mul => "mul".fmt(f),
-            Math::Mod => "mod".fmt(f),
+            Math::Add => a + b,
+            Math::Sub => a - b,
+            Math::Mul => a * b,
+            Math::Div => a / b,
+            Math::Mod => a % b,
+            Math::Idiv => (a / b).floor(),
+            Math::Pow => a.powf(b),
+            Math::Min => a.min(b),
+            Math::Max => a.max(b),
+            Math::And => (x & y) as f64,
+            Math::Or => (x | y) as f64,
+            Math::Xor => (x ^ y) as f64,
+            Math::Shl => x.wrapping_shl(y as u32) as f64,
+            Math::Shr => x.wrapping_shr(y as u32) as f64,
+            Math::Not => (!x) as f64,
+            Math::Land => f64::from(u8::from(a != 0.0 && b != 0.0)),
+            Math::Sqrt => a.sqrt(),
+            Math::Abs => a.abs(),
+            Math::Log => a.ln(),
+            Math::Floor => a.floor(),
+            Math::Ceil => a.ceil(),
         }
     }
 }
 
+impl std::fmt::Display for Math {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.name().fmt(f)
+    }
+}
+
 impl std::fmt::Display for Cond {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Cond::Always => "always".fmt(f),
             Cond::Lt => "lessThan".fmt(f),
             Cond::Gt => "greaterThan".fmt(f),
+            Cond::Le => "lessThanEq".fmt(f),
+            Cond::Ge => "greaterThanEq".fmt(f),
             Cond::Eq => "equal".fmt(f),
             Cond::Ne => "notEqual".fmt(f),
+            Cond::StrictEq => "strictEqual".fmt(f),
         }
     }
 }
@@ -98,6 +879,7 @@ impl std::fmt::Display for Instruction {
         match self {
             Instruction::Pause => "pause".fmt(f),
             Instruction::End => "end".fmt(f),
+            Instruction::Stop => "stop".fmt(f),
             Instruction::Math(op, dest, arg1, arg2) => {
                 write!(f, "op {} {} {} {}", op, dest, arg1, arg2)
             }
@@ -113,18 +895,90 @@ impl std::fmt::Display for Instruction {
             Instruction::Jump(cond, dest, arg1, arg2) => {
                 write!(f, "jump {} {} {} {}", dest, cond, arg1, arg2)
             }
+            Instruction::Rand(dest, max) => {
+                write!(f, "op rand {} {} 0", dest, max)
+            }
+            Instruction::Select(cond, dest, arg1, arg2, if_true, if_false) => {
+                write!(
+                    f,
+                    "select {} {} {} {} {} {}",
+                    dest, cond, arg1, arg2, if_true, if_false
+                )
+            }
+            Instruction::Lookup(kind, dest, id) => {
+                write!(f, "lookup {} {} {}", kind, dest, id)
+            }
+            Instruction::GetLink(dest, index) => {
+                write!(f, "getlink {} {}", dest, index)
+            }
+            Instruction::Sensor(dest, target, property) => {
+                write!(f, "sensor {} {} {}", dest, target, property)
+            }
+            Instruction::UBind(unit) => {
+                write!(f, "ubind {}", unit)
+            }
+            Instruction::UControl(command, args) => {
+                write!(f, "ucontrol {}", command)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                Ok(())
+            }
             Instruction::Print(what) => {
                 write!(f, "print {}", what)
             }
             Instruction::PrintFlush(output) => {
                 write!(f, "printflush {}", output)
             }
+            Instruction::Draw(command, args) => {
+                write!(f, "draw {}", command)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                Ok(())
+            }
+            Instruction::DrawFlush(display) => {
+                write!(f, "drawflush {}", display)
+            }
+            Instruction::Wait(seconds) => {
+                write!(f, "wait {}", seconds)
+            }
         }
     }
 }
 
 impl Emulator {
-    pub fn new(cell: Option<Cell>, program: &str) -> Result<Emulator> {
+    /// An emulator for `program`, linked to `cells`, whose `op rand` always
+    /// gives the same numbers. See `with_seed` to choose others.
+    ///
+    /// `cells` may be one cell, as `Some(cell)`, none, or several, such as
+    /// a bank for the stack and another for data. `get_mem` reads the first.
+    pub fn new<C: IntoIterator<Item = Cell>>(cells: C, program: &str) -> Result<Emulator> {
+        Emulator::with_seed(cells, program, 0)
+    }
+
+    /// An emulator for `program`, linked to `cells`, whose `op rand` gives
+    /// the numbers for `seed`. The same seed gives the same numbers every run.
+    pub fn with_seed<C: IntoIterator<Item = Cell>>(
+        cells: C,
+        program: &str,
+        seed: u64,
+    ) -> Result<Emulator> {
+        Symbol::interning(|| Emulator::load_program(cells, program, seed))
+    }
+
+    fn load_program<C: IntoIterator<Item = Cell>>(
+        cells: C,
+        program: &str,
+        seed: u64,
+    ) -> Result<Emulator> {
+        let cells: Vec<Cell> = cells.into_iter().collect();
+        for (j, cell) in cells.iter().enumerate() {
+            if cells[..j].iter().any(|other| other.name == cell.name) {
+                bail!("more than one cell is named {}", cell.name);
+            }
+        }
+
         let mut instructions = Vec::default();
 
         for (line_no, line) in program.lines().enumerate() {
@@ -134,40 +988,45 @@ impl Emulator {
                 continue;
             }
 
-            let tok: Vec<_> = line.split_whitespace().collect();
+            // Strings such as `"a b"` are one argument.
+            let tok = parser::lex_line(line);
 
             if tok[0] == "end" {
                 check_n_tok(&tok, 1, line_no)?;
                 instructions.push(Instruction::End);
+            } else if tok[0] == "stop" {
+                check_n_tok(&tok, 1, line_no)?;
+                instructions.push(Instruction::Stop);
             } else if tok[0] == "pause" {
                 check_n_tok(&tok, 1, line_no)?;
                 instructions.push(Instruction::Pause);
+            } else if tok[0] == "op" && tok.get(1) == Some(&"rand") {
+                check_n_tok(&tok, 5, line_no)?;
+                instructions.push(Instruction::Rand(Symbol::new(tok[2]), Symbol::new(tok[3])));
             } else if tok[0] == "op" {
                 check_n_tok(&tok, 5, line_no)?;
-                let out = Rc::new(tok[2].to_string());
-                let arg1 = Rc::new(tok[3].to_string());
-                let arg2 = Rc::new(tok[4].to_string());
-                let op = if tok[1] == "add" {
-                    Math::Add
-                } else if tok[1] == "sub" {
-                    Math::Sub
-                } else if tok[1] == "mul" {
-                    Math::Mul
-                } else if tok[1] == "mod" {
-                    Math::Mod
-                } else {
-                    bail!(
-                        "Line {}: unsupported op command {} (emulator only supports add, mul, sub)",
+                let out = Symbol::new(tok[2]);
+                let arg1 = Symbol::new(tok[3]);
+                let arg2 = Symbol::new(tok[4]);
+                let op = match Math::ALL.iter().find(|op| op.name() == tok[1]) {
+                    Some(op) => *op,
+                    None => bail!(
+                        "Line {}: unsupported op command {} (emulator only supports {})",
+                        line_no,
                         tok[1],
-                        line_no
-                    );
+                        Math::ALL
+                            .iter()
+                            .map(Math::name)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
                 };
                 instructions.push(Instruction::Math(op, out, arg1, arg2));
             } else if tok[0] == "read" || tok[0] == "write" {
                 check_n_tok(&tok, 4, line_no)?;
-                let name = Rc::new(tok[1].to_string());
-                let cell = Rc::new(tok[2].to_string());
-                let address = Rc::new(tok[3].to_string());
+                let name = Symbol::new(tok[1]);
+                let cell = Symbol::new(tok[2]);
+                let address = Symbol::new(tok[3]);
 
                 if tok[0] == "read" {
                     instructions.push(Instruction::Read(name, cell, address));
@@ -176,142 +1035,699 @@ impl Emulator {
                 }
             } else if tok[0] == "set" {
                 check_n_tok(&tok, 3, line_no)?;
-                let dest = Rc::new(tok[1].to_string());
-                let source = Rc::new(tok[2].to_string());
+                let dest = Symbol::new(tok[1]);
+                let source = Symbol::new(tok[2]);
                 instructions.push(Instruction::Set(dest, source));
             } else if tok[0] == "jump" {
                 check_n_tok(&tok, 5, line_no)?;
-                let cond = Rc::new(tok[2].to_string());
                 let dest: usize = tok[1]
                     .parse()
                     .context("Line {}: jump dest must be integer")?;
-                let op1 = Rc::new(tok[3].to_string());
-                let op2 = Rc::new(tok[4].to_string());
-                let c = if *cond == "equal" {
-                    Cond::Eq
-                } else if *cond == "notEqual" {
-                    Cond::Ne
-                } else if *cond == "lessThan" {
-                    Cond::Lt
-                } else if *cond == "greaterThan" {
-                    Cond::Gt
-                } else if *cond == "always" {
-                    Cond::Always
-                } else {
-                    bail!("Line {}: Unsupported condition {}", line_no, cond);
-                };
+                let op1 = Symbol::new(tok[3]);
+                let op2 = Symbol::new(tok[4]);
+                let c = parse_cond(tok[2], line_no)?;
                 instructions.push(Instruction::Jump(c, dest, op1, op2));
+            } else if tok[0] == "select" {
+                check_n_tok(&tok, 7, line_no)?;
+                let c = parse_cond(tok[2], line_no)?;
+                instructions.push(Instruction::Select(
+                    c,
+                    Symbol::new(tok[1]),
+                    Symbol::new(tok[3]),
+                    Symbol::new(tok[4]),
+                    Symbol::new(tok[5]),
+                    Symbol::new(tok[6]),
+                ));
+            } else if tok[0] == "lookup" {
+                check_n_tok(&tok, 4, line_no)?;
+                let kind = match ContentType::ALL.iter().find(|kind| kind.name() == tok[1]) {
+                    Some(kind) => *kind,
+                    None => bail!("Line {}: unsupported lookup type {}", line_no, tok[1]),
+                };
+                instructions.push(Instruction::Lookup(
+                    kind,
+                    Symbol::new(tok[2]),
+                    Symbol::new(tok[3]),
+                ));
+            } else if tok[0] == "getlink" {
+                check_n_tok(&tok, 3, line_no)?;
+                instructions.push(Instruction::GetLink(
+                    Symbol::new(tok[1]),
+                    Symbol::new(tok[2]),
+                ));
+            } else if tok[0] == "sensor" {
+                check_n_tok(&tok, 4, line_no)?;
+                instructions.push(Instruction::Sensor(
+                    Symbol::new(tok[1]),
+                    Symbol::new(tok[2]),
+                    Symbol::new(tok[3]),
+                ));
+            } else if tok[0] == "ubind" {
+                check_n_tok(&tok, 2, line_no)?;
+                instructions.push(Instruction::UBind(Symbol::new(tok[1])));
+            } else if tok[0] == "ucontrol" {
+                check_n_tok(&tok, 7, line_no)?;
+                let mut args: [Symbol; 5] = std::array::from_fn(|_| Symbol::new("0"));
+                for (arg, tok) in args.iter_mut().zip(&tok[2..]) {
+                    *arg = Symbol::new(tok);
+                }
+                instructions.push(Instruction::UControl(Symbol::new(tok[1]), args));
             } else if tok[0] == "print" {
-                instructions.push(Instruction::Print(Rc::new(line[5..].trim().to_string())));
+                instructions.push(Instruction::Print(Symbol::new(line[5..].trim())));
             } else if tok[0] == "printflush" {
                 check_n_tok(&tok, 2, line_no)?;
-                instructions.push(Instruction::PrintFlush(Rc::new(tok[1].to_string())));
+                instructions.push(Instruction::PrintFlush(Symbol::new(tok[1])));
+            } else if tok[0] == "draw" {
+                if tok.len() < 2 || tok.len() > 8 {
+                    bail!("Line {}: draw takes 1 to 7 arguments", line_no);
+                }
+                let mut args: [Symbol; 6] = std::array::from_fn(|_| Symbol::new("0"));
+                for (arg, tok) in args.iter_mut().zip(&tok[2..]) {
+                    *arg = Symbol::new(tok);
+                }
+                instructions.push(Instruction::Draw(Symbol::new(tok[1]), args));
+            } else if tok[0] == "wait" {
+                check_n_tok(&tok, 2, line_no)?;
+                instructions.push(Instruction::Wait(Symbol::new(tok[1])));
+            } else if tok[0] == "drawflush" {
+                check_n_tok(&tok, 2, line_no)?;
+                instructions.push(Instruction::DrawFlush(Symbol::new(tok[1])));
             } else {
                 bail!("line {}: unknown instruction {}", line_no, line);
             }
         }
 
-        Ok(Emulator {
-            cell,
+        let executions = vec![0; instructions.len()];
+        let mut emulator = Emulator {
+            cells,
             instructions,
             vars: HashMap::new(),
-            counter: Rc::new(String::from("@counter")),
+            counter: Symbol::new("@counter"),
             watches: Vec::default(),
+            watchpoints: Vec::default(),
             breakpoints: Vec::default(),
-            print_buffer: Vec::default(),
-        })
+            source_map: SourceMap::default(),
+            call_stack: Vec::default(),
+            buffers: Buffers::default(),
+            draw_flushes: Vec::default(),
+            warn_print_overflow: false,
+            world: World::default(),
+            rng: Rng { state: seed },
+            clock: Clock {
+                instructions_per_tick: DEFAULT_INSTRUCTIONS_PER_TICK,
+                ticks: 0,
+                steps: 0,
+            },
+            executions,
+            steps: 0,
+            snapshot_interval: None,
+            snapshots: Vec::default(),
+        };
+        emulator.define_world();
+        emulator.clock.define_time(&mut emulator.vars);
+        Ok(emulator)
+    }
+
+    /// Sets the content `lookup` finds of a type. See
+    /// `World::set_lookup_table`.
+    pub fn set_lookup_table(&mut self, kind: ContentType, names: Vec<Symbol>) {
+        self.world.set_lookup_table(kind, names);
+        self.define_world();
+    }
+
+    /// Replaces the world the program runs in.
+    pub fn set_world(&mut self, world: World) {
+        self.world = world;
+        self.define_world();
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// Whether `run` notes in its output when `print` drops text because the
+    /// print buffer is full. See `MAX_PRINT_BUFFER`. Off by default.
+    pub fn set_warn_print_overflow(&mut self, warn: bool) {
+        self.warn_print_overflow = warn;
+    }
+
+    /// Sets how many instructions run each tick, as for a faster processor.
+    /// See `DEFAULT_INSTRUCTIONS_PER_TICK`.
+    pub fn set_instructions_per_tick(&mut self, instructions_per_tick: usize) -> Result<()> {
+        if instructions_per_tick == 0 {
+            bail!("a processor must run at least one instruction per tick");
+        }
+        self.clock.instructions_per_tick = instructions_per_tick;
+        Ok(())
+    }
+
+    /// The ticks that have passed since the program started: one for each
+    /// `instructions_per_tick` instructions run.
+    pub fn ticks(&self) -> u64 {
+        self.clock.ticks
+    }
+
+    /// Each `drawflush` so far, in order, with the commands it drew.
+    pub fn draw_flushes(&self) -> &[DrawFlush] {
+        &self.draw_flushes
+    }
+
+    /// The instructions run since the program started.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// The address of the instruction that runs next.
+    pub fn counter(&self) -> usize {
+        truncate(resolve(&self.vars, &self.counter).num())
+    }
+
+    /// The instruction at `address`, if there is one.
+    pub fn instruction(&self, address: usize) -> Option<&Instruction> {
+        self.instructions.get(address)
+    }
+
+    /// The source map given to `set_source_map`, or else an empty one.
+    pub fn source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
+    /// How many instructions each source line and function has run, from
+    /// the source map given to `set_source_map`.
+    pub fn execution_profile(&self) -> ExecutionProfile {
+        ExecutionProfile::new(&self.executions, &self.source_map)
     }
 
-    /// Runs until `end`, or `n` steps.
+    /// Copies the state every `interval` steps from now on, so `run_back` can
+    /// return to any step since. Each copy includes every memory cell, so
+    /// for long runs, an interval in the thousands keeps this small while
+    /// still letting `run_back` replay quickly.
+    pub fn set_snapshot_interval(&mut self, interval: usize) -> Result<()> {
+        if interval == 0 {
+            bail!("snapshot interval must be at least 1");
+        }
+        self.snapshot_interval = Some(interval);
+        Ok(())
+    }
+
+    /// Undoes the last `n` steps, to find where a value went wrong without
+    /// starting over. This restores the latest snapshot from before then and
+    /// runs forward from it, ignoring breakpoints and watchpoints, so it
+    /// needs `set_snapshot_interval` to have been called first.
+    pub fn run_back(&mut self, n: usize) -> Result<()> {
+        let target = self
+            .steps
+            .checked_sub(n)
+            .with_context(|| format!("only {} steps have run", self.steps))?;
+        let j = self
+            .snapshots
+            .iter()
+            .rposition(|snapshot| snapshot.steps <= target)
+            .with_context(|| format!("no snapshot from before step {}", target))?;
+        self.snapshots.truncate(j + 1);
+        self.restore(self.snapshots[j].clone());
+
+        let mut events = Vec::default();
+        while self.steps < target {
+            let ip = truncate(resolve(&self.vars, &self.counter).num());
+            self.step_at(ip, &mut events);
+            events.clear();
+        }
+        Ok(())
+    }
+
+    /// Saves the state of the program as JSON, to pick up from later with
+    /// `load_state`: its variables, including `@counter`, memory, print and
+    /// draw buffers, world, and time. The program itself isn't included, nor
+    /// are debugging settings such as breakpoints.
+    pub fn save_state(&self) -> String {
+        serde_json::to_string(&self.snapshot()).unwrap()
+    }
+
+    /// Restores state from `save_state`, which must be for the same program.
+    /// Snapshots for `run_back` from before then are discarded.
+    pub fn load_state(&mut self, state: &str) -> Result<()> {
+        let snapshot: Snapshot = serde_json::from_str(state).context("parse emulator state")?;
+        self.snapshots.clear();
+        self.restore(snapshot);
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            steps: self.steps,
+            executions: self.executions.clone(),
+            cells: self.cells.clone(),
+            vars: self.vars.clone(),
+            call_stack: self.call_stack.clone(),
+            buffers: self.buffers.clone(),
+            draw_flushes: self.draw_flushes.clone(),
+            world: self.world.clone(),
+            rng: self.rng.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.steps = snapshot.steps;
+        self.executions = snapshot.executions;
+        self.cells = snapshot.cells;
+        self.vars = snapshot.vars;
+        self.call_stack = snapshot.call_stack;
+        self.buffers = snapshot.buffers;
+        self.draw_flushes = snapshot.draw_flushes;
+        self.world = snapshot.world;
+        self.rng = snapshot.rng;
+        self.clock = snapshot.clock;
+    }
+
+    fn define_world(&mut self) {
+        self.vars.extend(self.world.constants());
+    }
+
+    /// Runs until `end`, or `n` steps, and gives the trace. See `run_events`.
     pub fn run(&mut self, max_steps: usize) -> Vec<String> {
+        let events = self.run_until(max_steps, None);
+        self.render(&events)
+    }
+
+    /// Runs as `run` does, but gives what happened as events rather than as
+    /// text.
+    pub fn run_events(&mut self, max_steps: usize) -> Vec<Event> {
+        self.run_until(max_steps, None)
+    }
+
+    /// Runs the next instruction, even if there's a breakpoint on it.
+    pub fn step(&mut self) -> Vec<Event> {
+        let mut events = Vec::default();
+        if !self.instructions.is_empty() {
+            let ip = truncate(resolve(&self.vars, &self.counter).num());
+            self.step_at(ip, &mut events);
+        }
+        events
+    }
+
+    /// The trace `run` gives for `events`, one line per instruction run,
+    /// followed by anything it printed or drew, and why it stopped. With a
+    /// source map, each instruction is shown with the source line it came
+    /// from.
+    pub fn render(&self, events: &[Event]) -> Vec<String> {
         let mut output = Vec::default();
+        for event in events {
+            match event {
+                Event::Executed {
+                    ip,
+                    instruction,
+                    watches,
+                } => {
+                    let watches: Vec<_> = watches
+                        .iter()
+                        .map(|(name, value)| match value {
+                            Some(value) => format!("{}:{} ", name, value),
+                            None => format!("{}:<none> ", name),
+                        })
+                        .collect();
+                    let mut line = format!("{}:\t{}\"{}\"", ip, watches.join(""), instruction);
+                    if let Some(location) = self.source_map.location(*ip) {
+                        line.push_str(&format!("\t// src {}", location.line));
+                        if let Some(text) = self.source_map.source_lines.get(location.line) {
+                            line.push_str(&format!(": {}", text.trim()));
+                        }
+                    }
+                    output.push(line);
+                }
+                Event::PrintOverflow(dropped) => output.push(format!(
+                    "\tWarning: print buffer is full, so {} characters were dropped",
+                    dropped
+                )),
+                Event::Printed { target, text } => {
+                    for line in text.lines() {
+                        output.push(format!("\tPrinted to {}: {}", target, line));
+                    }
+                }
+                Event::Drew { display, commands } => {
+                    output.push(format!("\tDrew {} to {}", commands, display))
+                }
+                Event::WatchpointChanged {
+                    watchpoint,
+                    old,
+                    new,
+                    ip,
+                } => output.push(format!(
+                    "Watchpoint {} changed from {} to {} at {}",
+                    watchpoint, old, new, ip
+                )),
+                Event::BreakpointHit(ip) => output.push(format!("Hit breakpoint at {}", ip)),
+                Event::Ended => {}
+                Event::StepLimit {
+                    steps,
+                    histogram,
+                    cycle,
+                } => {
+                    let counts: Vec<_> = histogram
+                        .iter()
+                        .take(REPORTED_ADDRESSES)
+                        .map(|(ip, n)| format!("{} ({} times)", ip, n))
+                        .collect();
+                    output.push(format!(
+                        "Stopped after {} steps. Most run of the last {}: {}",
+                        steps,
+                        LOOP_WINDOW,
+                        counts.join(", ")
+                    ));
+                    if let Some(cycle) = cycle {
+                        let cycle: Vec<_> = cycle.iter().map(|ip| ip.to_string()).collect();
+                        output.push(format!("\tStuck repeating {}", cycle.join(" -> ")));
+                    }
+                }
+            }
+        }
+        output
+    }
+
+    /// Runs the next instruction, and if it starts a call, the rest of the
+    /// call too, stopping at the return address. If a call has been started
+    /// but has yet to jump into the function, finishes it. Otherwise stops as
+    /// `run` does, after at most `max_steps`.
+    ///
+    /// Calls are only known after `set_source_map`. Without it, this runs a
+    /// single instruction.
+    pub fn step_over(&mut self, max_steps: usize) -> Vec<String> {
+        let ip = truncate(resolve(&self.vars, &self.counter).num());
+        let in_call = self.source_map.calls.iter().any(|call| {
+            call.start < ip
+                && ip < call.return_address
+                && self.call_stack.last() == Some(&call.return_address)
+        });
+        let depth = self.call_stack.len() - usize::from(in_call);
+        let events = self.run_until(max_steps, Some(depth));
+        self.render(&events)
+    }
+
+    /// Runs until the function the program is in returns, stopping at the
+    /// return address. Outside any function, this is the same as `run`.
+    ///
+    /// Calls are only known after `set_source_map`.
+    pub fn step_out(&mut self, max_steps: usize) -> Vec<String> {
+        let depth = self.call_stack.len().checked_sub(1);
+        let events = self.run_until(max_steps, depth);
+        self.render(&events)
+    }
+
+    /// Runs as `run` does, but once past the first instruction, stops if
+    /// fewer than `depth` calls are in progress.
+    fn run_until(&mut self, max_steps: usize, depth: Option<usize>) -> Vec<Event> {
+        let mut events = Vec::default();
 
         if self.instructions.is_empty() {
-            return output;
+            return events;
         }
 
         // Ignore breakpoints for the very first step.
-        let mut first_step = true;
-        while output.len() < max_steps {
-            let ip = *self.vars.get(&self.counter).unwrap_or(&0);
-            if !first_step && self.breakpoints.contains(&ip) {
-                output.push(format!("Hit breakpoint at {}", ip));
-                return output;
-            }
-            first_step = false;
-
-            self.vars.insert(self.counter.clone(), ip + 1);
-            let instruction = &self.instructions[ip];
-            let watch_output: Vec<_> = self
-                .watches
-                .iter()
-                .map(|n| {
-                    if n.starts_with("*") {
-                        format!("{}:<not_implemented>", &n)
-                    } else {
-                        match self.vars.get(n.as_ref()) {
-                            Some(v) => format!("{}:{} ", &n, &v),
-                            None => format!("{}:null ", &n),
-                        }
-                    }
-                })
-                .collect();
-            output.push(format!(
-                "{}:\t{}\"{}\"",
-                ip,
-                watch_output.join(""),
-                instruction,
-            ));
-
-            execute(
-                instruction,
-                &mut self.cell,
-                &mut self.vars,
-                &self.counter,
-                &mut self.print_buffer,
-            );
+        let mut recent = std::collections::VecDeque::with_capacity(LOOP_WINDOW);
+        for step in 0..max_steps {
+            if step > 0 && depth.is_some_and(|depth| self.call_stack.len() <= depth) {
+                return events;
+            }
 
-            if let Instruction::PrintFlush(which) = instruction {
-                for line in self.print_buffer.join("").lines() {
-                    output.push(format!("\tPrinted to {}: {}", &which, line));
-                }
-                self.print_buffer.clear();
+            let ip = truncate(resolve(&self.vars, &self.counter).num());
+            if step > 0 && self.breakpoints.contains(&ip) {
+                events.push(Event::BreakpointHit(ip));
+                return events;
             }
 
-            if *instruction == Instruction::End
-                || *self.vars.get(&self.counter).unwrap_or(&0) >= self.instructions.len()
-            {
-                self.vars.insert(self.counter.clone(), 0);
-                break;
+            if recent.len() == LOOP_WINDOW {
+                recent.pop_front();
             }
+            recent.push_back(ip);
+
+            if !self.step_at(ip, &mut events) {
+                return events;
+            }
+        }
+
+        if recent.len() == LOOP_WINDOW {
+            let recent: Vec<usize> = recent.into_iter().collect();
+            events.push(Event::StepLimit {
+                steps: max_steps,
+                histogram: histogram(&recent),
+                cycle: find_cycle(&recent),
+            });
+        }
+        events
+    }
+
+    /// Runs the instruction at `ip`, adding what happened to `events`. Returns
+    /// whether to go on to the next, which it doesn't after `end`, `stop`,
+    /// `pause`, or a watchpoint.
+    fn step_at(&mut self, ip: usize, events: &mut Vec<Event>) -> bool {
+        if let Some(interval) = self.snapshot_interval {
+            let last = self.snapshots.last().map(|snapshot| snapshot.steps);
+            if last.is_none_or(|last| self.steps >= last + interval) {
+                self.snapshots.push(self.snapshot());
+            }
+        }
+        self.steps += 1;
+        self.executions[ip] += 1;
+
+        if let Some(call) = self.source_map.calls.iter().find(|call| call.start == ip) {
+            self.call_stack.push(call.return_address);
+        }
+
+        self.vars
+            .insert(self.counter.clone(), Value::Number((ip + 1) as f64));
+        let instruction = &self.instructions[ip];
+        let watches = self
+            .watches
+            .iter()
+            .map(|n| {
+                let value = if n.starts_with('*') {
+                    self.stack_var(n, ip)
+                } else {
+                    Some(resolve(&self.vars, n))
+                };
+                (n.clone(), value)
+            })
+            .collect();
+        events.push(Event::Executed {
+            ip,
+            instruction: instruction.clone(),
+            watches,
+        });
+
+        let watched: Vec<Value> = self
+            .watchpoints
+            .iter()
+            .map(|watchpoint| self.watched_value(watchpoint))
+            .collect();
+
+        execute(
+            instruction,
+            &mut self.cells,
+            &mut self.vars,
+            &self.counter,
+            &mut self.world,
+            &mut self.rng,
+            &mut self.buffers,
+        );
+        match instruction {
+            Instruction::Wait(seconds) => {
+                self.clock.wait(resolve(&self.vars, seconds).num());
+            }
+            _ => self.clock.step(),
+        }
+        self.clock.define_time(&mut self.vars);
+
+        if self.buffers.dropped > 0 && self.warn_print_overflow {
+            events.push(Event::PrintOverflow(self.buffers.dropped));
+        }
+        self.buffers.dropped = 0;
+
+        if let Instruction::PrintFlush(which) = instruction {
+            events.push(Event::Printed {
+                target: which.clone(),
+                text: std::mem::take(&mut self.buffers.print),
+            });
+        }
+
+        if let Instruction::DrawFlush(display) = instruction {
+            let commands = std::mem::take(&mut self.buffers.draw);
+            events.push(Event::Drew {
+                display: display.clone(),
+                commands: commands.len(),
+            });
+            self.draw_flushes.push(DrawFlush {
+                display: display.clone(),
+                commands,
+            });
+        }
+
+        // Stop once the instruction is done, as for `end` or `pause`.
+        let mut hit_watchpoint = false;
+        for (watchpoint, old) in self.watchpoints.iter().zip(watched) {
+            let new = self.watched_value(watchpoint);
+            if new != old {
+                events.push(Event::WatchpointChanged {
+                    watchpoint: watchpoint.clone(),
+                    old,
+                    new,
+                    ip,
+                });
+                hit_watchpoint = true;
+            }
+        }
+
+        if *instruction == Instruction::Stop {
+            self.vars
+                .insert(self.counter.clone(), Value::Number(ip as f64));
+            return false;
+        }
+
+        let next = resolve(&self.vars, &self.counter).num();
+        if *instruction == Instruction::End || next < 0.0 || next >= self.instructions.len() as f64
+        {
+            self.vars.insert(self.counter.clone(), Value::Number(0.0));
+            self.call_stack.clear();
+            events.push(Event::Ended);
+            return false;
+        }
 
-            if *instruction == Instruction::Pause {
-                break;
-            }
+        if self.call_stack.last() == Some(&truncate(next)) {
+            self.call_stack.pop();
         }
 
-        output
+        *instruction != Instruction::Pause && !hit_watchpoint
+    }
+
+    /// Tells the emulator where each instruction came from, to show the
+    /// source line alongside it in the trace, for `set_source_breakpoints`,
+    /// and for `step_over` and `step_out`. `map` must be for the program being
+    /// run.
+    pub fn set_source_map(&mut self, map: &SourceMap) {
+        self.source_map = map.clone();
+    }
+
+    /// Sets breakpoints on the first instruction of each source line, counting
+    /// from 0. Lines that generated no code are ignored. Needs
+    /// `set_source_map` first.
+    pub fn set_source_breakpoints(&mut self, lines: &[usize]) {
+        self.breakpoints = self.source_map.breakpoints(lines);
     }
 
     pub fn set_breakpoints(&mut self, breakpoints: Vec<usize>) {
         self.breakpoints = breakpoints;
     }
 
-    pub fn set_watches(&mut self, watches: Vec<Rc<String>>) {
+    pub fn breakpoints(&self) -> &[usize] {
+        &self.breakpoints
+    }
+
+    /// Shows the values of `watches` before each instruction in the trace.
+    /// Stack variables, such as `*x`, are those of the current frame of the
+    /// function being run, which needs `set_source_map`.
+    pub fn set_watches(&mut self, watches: Vec<Symbol>) {
         self.watches = watches;
     }
 
+    pub fn watches(&self) -> &[Symbol] {
+        &self.watches
+    }
+
+    /// Makes `run` stop after any instruction that changes one of
+    /// `watchpoints`, noting the old and new values in its output. An
+    /// unwritten memory address is null.
+    pub fn set_watchpoints(&mut self, watchpoints: Vec<Watchpoint>) {
+        self.watchpoints = watchpoints;
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    fn watched_value(&self, watchpoint: &Watchpoint) -> Value {
+        match watchpoint {
+            Watchpoint::Var(var) => resolve(&self.vars, var),
+            Watchpoint::Memory(cell, address) => match self.get_cell_value(cell, *address) {
+                Some(value) => Value::Number(value),
+                None => Value::Null,
+            },
+        }
+    }
+
+    /// Adds another memory cell, e.g. for a stack spanning several banks. It
+    /// replaces any cell of the same name.
+    pub fn add_cell(&mut self, cell: Cell) {
+        match self.cells.iter_mut().find(|other| other.name == cell.name) {
+            Some(other) => *other = cell,
+            None => self.cells.push(cell),
+        }
+    }
+
+    /// Reads from the first memory cell, truncating the value to an integer
+    /// as `get_var` does.
     pub fn get_mem(&self, address: usize) -> Option<usize> {
-        let data = &self.cell.as_ref()?.data;
+        let data = &self.cells.first()?.data;
         if address >= data.len() {
             None
         } else {
-            data[address]
+            data[address].map(truncate)
+        }
+    }
+
+    /// Exchanges the emulator's memory cells with `cells`, so a `Cluster` can
+    /// lend each processor the cells they share.
+    pub(crate) fn swap_cells(&mut self, cells: &mut Vec<Cell>) {
+        std::mem::swap(&mut self.cells, cells);
+    }
+
+    /// Reads from the memory cell named `cell`, truncating the value to an
+    /// integer as `get_var` does.
+    pub fn get_cell_mem(&self, cell: &str, address: usize) -> Option<usize> {
+        self.get_cell_value(cell, address).map(truncate)
+    }
+
+    /// Reads from the memory cell named `cell`.
+    pub fn get_cell_value(&self, cell: &str, address: usize) -> Option<f64> {
+        let cell = self.cells.iter().find(|c| c.name.as_str() == cell)?;
+        cell.get(address)
+    }
+
+    /// The value of stack variable `name` in the current frame of the function
+    /// the instruction at `ip` is in, if it has such a variable. Only the
+    /// default stack holds frames.
+    fn stack_var(&self, name: &str, ip: usize) -> Option<Value> {
+        let function = self.source_map.function(ip)?;
+        let var = StackVar::try_from(name).ok()?;
+        let depth = *self.source_map.stack_vars.get(function)?.get(&var)?;
+        let size = resolve(&self.vars, &Symbol::new("MF_stack_sz")).num();
+        let index = truncate(size).checked_sub(depth)?;
+        match &self.source_map.stack {
+            StackLocation::Internal => {
+                let entry = Symbol::new(&format!("MF_stack[{}]", index));
+                Some(resolve(&self.vars, &entry))
+            }
+            StackLocation::External(cells) => {
+                let cell = cells.get(index / BANK_SIZE)?;
+                let value = self.get_cell_value(cell, index % BANK_SIZE);
+                Some(Value::Number(value.unwrap_or(0.0)))
+            }
         }
     }
 
-    pub fn get_var(&self, var: &Rc<String>) -> Option<usize> {
-        resolve(&self.vars, var)
+    /// The value of `var` truncated to an integer, which is what most tests
+    /// want. A negative value is 0. See `get_value` for the value itself.
+    pub fn get_var(&self, var: &str) -> Option<usize> {
+        self.get_value(var).map(truncate)
+    }
+
+    /// The value of `var`, or `None` if it's null.
+    pub fn get_value(&self, var: &str) -> Option<f64> {
+        resolve(&self.vars, &Symbol::new(var)).number()
+    }
+
+    /// The value of `var`, which may be null, content, or an object.
+    pub fn var(&self, var: &str) -> Value {
+        resolve(&self.vars, &Symbol::new(var))
     }
 }
 
@@ -325,102 +1741,197 @@ fn check_n_tok(tok: &[&str], n: usize, line_no: usize) -> Result<()> {
 
 fn execute(
     instruction: &Instruction,
-    cell: &mut Option<Cell>,
-    vars: &mut HashMap<Rc<String>, usize>,
-    counter: &Rc<String>,
-    print_buffer: &mut Vec<String>,
+    cells: &mut [Cell],
+    vars: &mut HashMap<Symbol, Value>,
+    counter: &Symbol,
+    world: &mut World,
+    rng: &mut Rng,
+    buffers: &mut Buffers,
 ) {
     match instruction {
         Instruction::End => {}
+        Instruction::Stop => {}
         Instruction::Pause => {}
         Instruction::Math(math, dest, op1, op2) => {
-            let op1 = resolve(vars, op1).unwrap_or(0);
-            let op2 = resolve(vars, op2).unwrap_or(0);
-
-            let r = match math {
-                Math::Add => op1.overflowing_add(op2).0,
-                Math::Sub => op1.overflowing_sub(op2).0,
-                Math::Mul => op1.overflowing_mul(op2).0,
-                Math::Mod if op2 > 0 => op1 % op2,
-                Math::Mod => 0,
-            };
-            vars.insert(dest.clone(), r);
+            let op1 = resolve(vars, op1).num();
+            let op2 = resolve(vars, op2).num();
+
+            let r = math.apply(op1, op2);
+            vars.insert(
+                dest.clone(),
+                Value::Number(if r.is_finite() { r } else { 0.0 }),
+            );
+        }
+        Instruction::Rand(dest, max) => {
+            let r = rng.next_f64() * resolve(vars, max).num();
+            vars.insert(
+                dest.clone(),
+                Value::Number(if r.is_finite() { r } else { 0.0 }),
+            );
         }
         Instruction::Read(name, cell_name, address) => {
-            let val = match (resolve(vars, address), cell.as_ref()) {
-                (Some(address), Some(cell))
-                    if cell.name == *cell_name && address < cell.data.len() =>
-                {
-                    cell.data[address]
-                }
-                _ => None,
-            };
-
-            match val {
-                Some(val) => {
-                    vars.insert(name.clone(), val.clone());
-                }
-                None => {
-                    vars.remove(name);
-                }
+            // Reading a cell that doesn't exist does nothing, but reading
+            // outside one gives 0.
+            if let Some(cell) = cells.iter().find(|cell| cell.name == *cell_name) {
+                let value = resolve_address(vars, address)
+                    .and_then(|address| cell.data.get(address).copied().flatten())
+                    .unwrap_or(0.0);
+                vars.insert(name.clone(), Value::Number(value));
             }
         }
         Instruction::Write(value, cell_name, address) => {
-            match (resolve(vars, address), resolve(vars, value), cell) {
-                (Some(address), value, Some(cell))
-                    if cell.name == *cell_name && address < cell.data.len() =>
-                {
-                    cell.data[address] = value;
+            let cell = cells.iter_mut().find(|cell| cell.name == *cell_name);
+            match (resolve_address(vars, address), cell) {
+                (Some(address), Some(cell)) if address < cell.data.len() => {
+                    cell.data[address] = Some(resolve(vars, value).num());
                 }
                 _ => {}
             }
         }
-        Instruction::Set(dest, source) => match resolve(vars, source) {
-            Some(value) => {
-                vars.insert(dest.clone(), value);
-            }
-            None => {
-                vars.remove(dest);
-            }
-        },
-        Instruction::PrintFlush(..) => {}
+        Instruction::Set(dest, source) => {
+            let value = resolve(vars, source);
+            vars.insert(dest.clone(), value);
+        }
+        // These are run by `Emulator::run`.
+        Instruction::PrintFlush(..) | Instruction::DrawFlush(..) | Instruction::Wait(..) => {}
+        Instruction::Draw(command, args) => {
+            buffers.draw.push(DrawCommand {
+                command: command.clone(),
+                args: args.iter().map(|arg| resolve(vars, arg)).collect(),
+            });
+        }
         Instruction::Print(arg) => {
             if arg.starts_with("\"") && arg.ends_with("\"") && arg.len() >= 2 {
-                print_buffer.push(
-                    arg[1..arg.len() - 1]
+                buffers.print(
+                    &arg[1..arg.len() - 1]
                         .replace("\\n", "\n")
                         .replace("\\t", "\t")
-                        .replace("\\\"", "\"")
-                        .to_string(),
+                        .replace("\\\"", "\""),
                 )
             } else {
-                let v = match resolve(vars, arg) {
-                    Some(n) => n.to_string(),
-                    None => "null".to_string(),
-                };
-                print_buffer.push(v);
+                buffers.print(&resolve(vars, arg).to_string());
             }
         }
         Instruction::Jump(cond, dest, op1, op2) => {
-            let met = match (cond, resolve(vars, op1), resolve(vars, op2)) {
-                (Cond::Always, _, _) => true,
-                (Cond::Eq, op1, op2) => op1 == op2,
-                (Cond::Ne, op1, op2) => op1 != op2,
-                (Cond::Lt, op1, op2) => op1 < op2,
-                (Cond::Gt, op1, op2) => op1 > op2,
+            if cond.test(&resolve(vars, op1), &resolve(vars, op2)) {
+                vars.insert(counter.clone(), Value::Number(*dest as f64));
+            }
+        }
+        Instruction::Select(cond, dest, op1, op2, if_true, if_false) => {
+            let value = if cond.test(&resolve(vars, op1), &resolve(vars, op2)) {
+                resolve(vars, if_true)
+            } else {
+                resolve(vars, if_false)
             };
+            vars.insert(dest.clone(), value);
+        }
+        Instruction::Lookup(..)
+        | Instruction::GetLink(..)
+        | Instruction::Sensor(..)
+        | Instruction::UBind(..)
+        | Instruction::UControl(..) => world.execute(instruction, vars),
+    }
+}
 
-            if met {
-                vars.insert(counter.clone(), *dest);
-            }
+impl Cond {
+    /// Whether the condition holds for `a` and `b`.
+    pub(crate) fn test(&self, a: &Value, b: &Value) -> bool {
+        match self {
+            Cond::Always => true,
+            Cond::Eq => a.equal(b),
+            Cond::Ne => !a.equal(b),
+            Cond::StrictEq => a == b,
+            Cond::Lt => a.num() < b.num(),
+            Cond::Gt => a.num() > b.num(),
+            Cond::Le => a.num() <= b.num(),
+            Cond::Ge => a.num() >= b.num(),
         }
     }
 }
 
-pub fn resolve(vars: &HashMap<Rc<String>, usize>, arg: &Rc<String>) -> Option<usize> {
-    match arg.parse::<usize>() {
-        Ok(n) => Some(n),
-        Err(..) => vars.get(arg).copied(),
+fn parse_cond(cond: &str, line_no: usize) -> Result<Cond> {
+    Ok(match cond {
+        "equal" => Cond::Eq,
+        "notEqual" => Cond::Ne,
+        "lessThan" => Cond::Lt,
+        "greaterThan" => Cond::Gt,
+        "lessThanEq" => Cond::Le,
+        "greaterThanEq" => Cond::Ge,
+        "strictEqual" => Cond::StrictEq,
+        "always" => Cond::Always,
+        _ => bail!("Line {}: Unsupported condition {}", line_no, cond),
+    })
+}
+
+/// The value of `arg`, which is either a constant or the name of a variable.
+pub fn resolve(vars: &HashMap<Symbol, Value>, arg: &Symbol) -> Value {
+    if *arg == "null" {
+        return Value::Null;
+    }
+    match parse_number(arg) {
+        Some(n) => Value::Number(n),
+        None => vars.get(arg).cloned().unwrap_or(Value::Null),
+    }
+}
+
+/// Parses a numeric constant, as Mindustry does. Names such as `inf` and
+/// `nan` are variables, not numbers, even though Rust would parse them.
+pub(crate) fn parse_number(text: &str) -> Option<f64> {
+    match text {
+        "true" => return Some(1.0),
+        "false" => return Some(0.0),
+        _ => {}
+    }
+
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    if let Some(hex) = digits.strip_prefix("0x") {
+        let n = i64::from_str_radix(hex, 16).ok()? as f64;
+        return Some(if digits.len() < text.len() { -n } else { n });
+    }
+    if !digits.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    text.parse().ok()
+}
+
+/// `arg` as an index into a memory cell, truncated toward zero. Null is 0.
+fn resolve_address(vars: &HashMap<Symbol, Value>, arg: &Symbol) -> Option<usize> {
+    Some(resolve(vars, arg).num())
+        .filter(|address| *address >= 0.0)
+        .map(truncate)
+}
+
+/// Each address in `ips`, with how often it appears, most first.
+fn histogram(ips: &[usize]) -> Vec<(usize, usize)> {
+    let mut counts: HashMap<usize, usize> = HashMap::default();
+    for ip in ips.iter() {
+        *counts.entry(*ip).or_default() += 1;
+    }
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+}
+
+/// The shortest sequence that `ips` repeats from start to end, at least
+/// twice, starting from the lowest address in it.
+fn find_cycle(ips: &[usize]) -> Option<Vec<usize>> {
+    let period = (1..=ips.len() / 2).find(|p| (*p..ips.len()).all(|j| ips[j] == ips[j - p]))?;
+    let mut cycle = ips[ips.len() - period..].to_vec();
+    let lowest = (0..period).min_by_key(|j| cycle[*j]).unwrap();
+    cycle.rotate_left(lowest);
+    Some(cycle)
+}
+
+fn truncate(value: f64) -> usize {
+    value as usize
+}
+
+/// Prints a whole number without a fractional part, as Mindustry does.
+fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        value.to_string()
     }
 }
 
@@ -440,10 +1951,20 @@ mod tests {
         assert_eq!(1, emu.run(10).len());
     }
 
+    #[test]
+    fn test_stop() {
+        let x = Symbol::new("x");
+
+        let mut emu = Emulator::new(None, "op add x x 1\nstop\nop add x x 1").unwrap();
+        assert_eq!(emu.run(10).len(), 2);
+        assert_eq!(emu.run(10).len(), 1);
+        assert_eq!(emu.get_var(&x), Some(1));
+    }
+
     #[test]
     fn test_math() {
-        let x = Rc::new(String::from("x"));
-        let y = Rc::new(String::from("y"));
+        let x = Symbol::new("x");
+        let y = Symbol::new("y");
 
         let mut emu = Emulator::new(None, "op add x 1 2\nop sub y 7 3\nop mul x x y").unwrap();
         assert_eq!(emu.run(1).len(), 1);
@@ -456,8 +1977,8 @@ mod tests {
 
     #[test]
     fn test_loop() {
-        let x = Rc::new(String::from("x"));
-        let y = Rc::new(String::from("y"));
+        let x = Symbol::new("x");
+        let y = Symbol::new("y");
 
         let mut emu = Emulator::new(
             None,
@@ -471,7 +1992,7 @@ mod tests {
 
     #[test]
     fn test_loop_infinite() {
-        let x = Rc::new(String::from("x"));
+        let x = Symbol::new("x");
 
         let mut emu =
             Emulator::new(None, "op add x x x\nop add x x 1\njump 0 always x false").unwrap();
@@ -489,10 +2010,10 @@ mod tests {
 
     #[test]
     fn test_read_counter() {
-        let x = Rc::new(String::from("x"));
-        let y = Rc::new(String::from("y"));
-        let z = Rc::new(String::from("z"));
-        let counter = Rc::new(String::from("@counter"));
+        let x = Symbol::new("x");
+        let y = Symbol::new("y");
+        let z = Symbol::new("z");
+        let counter = Symbol::new("@counter");
 
         let mut emu = Emulator::new(
             None,
@@ -521,8 +2042,8 @@ mod tests {
 
     #[test]
     fn test_set_counter() {
-        let x = Rc::new(String::from("x"));
-        let counter = Rc::new(String::from("@counter"));
+        let x = Symbol::new("x");
+        let counter = Symbol::new("@counter");
 
         let mut emu = Emulator::new(
             None,
@@ -536,9 +2057,9 @@ mod tests {
 
     #[test]
     fn test_set() {
-        let x = Rc::new(String::from("x"));
-        let y = Rc::new(String::from("y"));
-        let z = Rc::new(String::from("z"));
+        let x = Symbol::new("x");
+        let y = Symbol::new("y");
+        let z = Symbol::new("z");
 
         let mut emu = Emulator::new(None, "set x 5\nset y x\nop mul z x y").unwrap();
         assert_eq!(emu.run(10).len(), 3);
@@ -579,7 +2100,7 @@ mod tests {
 
     #[test]
     fn test_read_write() {
-        let x = Rc::new(String::from("x"));
+        let x = Symbol::new("x");
 
         let mut emu =
             Emulator::new(None, "read x bank1 5\nwrite 5 bank1 5\nread x bank1 5").unwrap();
@@ -589,7 +2110,7 @@ mod tests {
         assert_eq!(emu.get_var(&x), None);
 
         let cell = Cell {
-            name: Rc::new("bank1".to_string()),
+            name: Symbol::new("bank1"),
             data: vec![None; 512],
         };
         let mut emu = Emulator::new(
@@ -597,8 +2118,9 @@ mod tests {
             "read x bank1 5\nwrite 5 bank1 5\nread x bank1 5",
         )
         .unwrap();
+        // Memory that was never written is 0.
         assert_eq!(emu.run(1).len(), 1);
-        assert_eq!(emu.get_var(&x), None);
+        assert_eq!(emu.get_var(&x), Some(0));
         assert_eq!(emu.run(2).len(), 2);
         assert_eq!(emu.get_var(&x), Some(5));
 
@@ -623,20 +2145,205 @@ mod tests {
             "write 7 bank1 512\nread x bank1 512\nwrite 10 bank1 1000\nread x bank1 1000\nread x bank1 33\nwrite 12 bank1 33\nread x bank1 33",
         )
             .unwrap();
+        // So is memory outside the cell.
         assert_eq!(emu.run(2).len(), 2);
-        assert_eq!(emu.get_var(&x), None);
+        assert_eq!(emu.get_var(&x), Some(0));
         assert_eq!(emu.run(2).len(), 2);
-        assert_eq!(emu.get_var(&x), None);
+        assert_eq!(emu.get_var(&x), Some(0));
         assert_eq!(emu.run(1).len(), 1);
-        assert_eq!(emu.get_var(&x), None);
+        assert_eq!(emu.get_var(&x), Some(0));
         assert_eq!(emu.run(2).len(), 2);
         assert_eq!(emu.get_var(&x), Some(12));
     }
 
+    #[test]
+    fn test_null() {
+        // Null is 0 in math, and when written to memory.
+        let mut emu = Emulator::new(
+            Some(Cell::default()),
+            "op add x y 1\nset z null\nwrite z bank1 0\nread w bank1 0",
+        )
+        .unwrap();
+        emu.run(10);
+        assert_eq!(emu.get_value("x"), Some(1.0));
+        assert_eq!(emu.get_value("z"), None);
+        assert_eq!(emu.get_value("w"), Some(0.0));
+        assert_eq!(emu.get_mem(0), Some(0));
+
+        let jumps = |cond: &str, a: &str, b: &str| {
+            let program = format!("set zero 0\njump 3 {} {} {}\nend\nset y 1", cond, a, b);
+            let mut emu = Emulator::new(None, &program).unwrap();
+            emu.run(10);
+            emu.get_var("y") == Some(1)
+        };
+
+        assert!(jumps("equal", "null", "null"));
+        assert!(jumps("equal", "unset", "zero"));
+        assert!(jumps("equal", "unset", "0"));
+        assert!(!jumps("notEqual", "unset", "0"));
+        assert!(!jumps("equal", "unset", "1"));
+        assert!(jumps("strictEqual", "unset", "null"));
+        assert!(!jumps("strictEqual", "unset", "0"));
+        assert!(jumps("strictEqual", "zero", "0"));
+        assert!(jumps("lessThan", "unset", "1"));
+        assert!(!jumps("lessThan", "unset", "-1"));
+        assert!(jumps("greaterThanEq", "unset", "0"));
+
+        // Null as an address or counter is 0.
+        let mut emu = Emulator::new(
+            Some(Cell::default()),
+            "write 3 bank1 unset\nread x bank1 0\nprint unset\nprintflush message1",
+        )
+        .unwrap();
+        let output = emu.run(4);
+        assert_eq!(emu.get_var("x"), Some(3));
+        assert_eq!(output.last().unwrap(), "\tPrinted to message1: null");
+    }
+
+    #[test]
+    fn test_multiple_cells() {
+        let x = Symbol::new("x");
+        let y = Symbol::new("y");
+
+        let mut emu = Emulator::new(
+            Some(Cell::default()),
+            "write 3 bank1 5\nwrite 4 bank2 5\nread x bank1 5\nread y bank2 5",
+        )
+        .unwrap();
+        emu.add_cell(Cell::new(Symbol::new("bank2")));
+        assert_eq!(emu.run(10).len(), 4);
+        assert_eq!(emu.get_var(&x), Some(3));
+        assert_eq!(emu.get_var(&y), Some(4));
+        assert_eq!(emu.get_cell_mem("bank2", 5), Some(4));
+        assert_eq!(emu.get_cell_mem("bank3", 5), None);
+    }
+
+    #[test]
+    fn test_idiv() {
+        let x = Symbol::new("x");
+        let mut emu = Emulator::new(None, "op idiv x 1030 512").unwrap();
+        emu.run(1);
+        assert_eq!(emu.get_var(&x), Some(2));
+    }
+
+    #[test]
+    fn test_floating_point() {
+        let mut emu = Emulator::new(
+            None,
+            "set x 0.5\nop mul y x 3\nop sub z 1 2.25\nop idiv w -7 2\nop mod v 7.5 2",
+        )
+        .unwrap();
+        emu.run(10);
+        assert_eq!(emu.get_value("x"), Some(0.5));
+        assert_eq!(emu.get_value("y"), Some(1.5));
+        assert_eq!(emu.get_value("z"), Some(-1.25));
+        assert_eq!(emu.get_value("w"), Some(-4.0));
+        assert_eq!(emu.get_value("v"), Some(1.5));
+
+        // Truncated for tests that want integers.
+        assert_eq!(emu.get_var("y"), Some(1));
+        assert_eq!(emu.get_var("z"), Some(0));
+
+        // Not a finite number, so 0.
+        let mut emu = Emulator::new(None, "op idiv x 1 0\nop mod y 1 0").unwrap();
+        emu.run(10);
+        assert_eq!(emu.get_value("x"), Some(0.0));
+        assert_eq!(emu.get_value("y"), Some(0.0));
+
+        assert_eq!(parse_number("0x1f"), Some(31.0));
+        assert_eq!(parse_number("-0x10"), Some(-16.0));
+        assert_eq!(parse_number("true"), Some(1.0));
+        assert_eq!(parse_number(".5"), Some(0.5));
+        assert_eq!(parse_number("inf"), None);
+        assert_eq!(parse_number("nan"), None);
+    }
+
+    #[test]
+    fn test_floating_point_truncation() {
+        // Addresses and the counter are truncated.
+        let mut emu = Emulator::new(
+            Some(Cell::default()),
+            "write 7 bank1 2.9\nread x bank1 2\nop add @counter 4.7 0\nset y 1\nset y 2",
+        )
+        .unwrap();
+        emu.run(10);
+        assert_eq!(emu.get_var("x"), Some(7));
+        assert_eq!(emu.get_var("y"), Some(2));
+        assert_eq!(emu.get_mem(2), Some(7));
+    }
+
+    #[test]
+    fn test_equality_epsilon() {
+        let mut emu =
+            Emulator::new(None, "op add x 0.1 0.2\njump 3 equal x 0.3\nend\nset y 1").unwrap();
+        emu.run(10);
+        assert_eq!(emu.get_var("y"), Some(1));
+
+        let mut emu =
+            Emulator::new(None, "set x 0.3001\njump 3 notEqual x 0.3\nend\nset y 1").unwrap();
+        emu.run(10);
+        assert_eq!(emu.get_var("y"), Some(1));
+    }
+
+    #[test]
+    fn test_print_numbers() {
+        let mut emu = Emulator::new(
+            None,
+            "set x 2.5\nprint x\nprint \" \"\nprint 4.0\nprintflush message1",
+        )
+        .unwrap();
+        let output = emu.run(10);
+        assert_eq!(output.last().unwrap(), "\tPrinted to message1: 2.5 4");
+    }
+
+    #[test]
+    fn test_math_ops() {
+        let op = |op: &str, a: &str, b: &str| {
+            let mut emu = Emulator::new(None, &format!("op {} x {} {}", op, a, b)).unwrap();
+            emu.run(1);
+            emu.get_value("x").unwrap()
+        };
+
+        assert_eq!(op("div", "7", "2"), 3.5);
+        assert_eq!(op("div", "1", "0"), 0.0);
+        assert_eq!(op("idiv", "7", "2"), 3.0);
+        assert_eq!(op("idiv", "-7", "2"), -4.0);
+        assert_eq!(op("mod", "-7", "3"), -1.0);
+        assert_eq!(op("pow", "2", "10"), 1024.0);
+        assert_eq!(op("min", "2", "-3"), -3.0);
+        assert_eq!(op("max", "2", "-3"), 2.0);
+        assert_eq!(op("sqrt", "16", "0"), 4.0);
+        assert_eq!(op("sqrt", "-1", "0"), 0.0);
+        assert_eq!(op("abs", "-2.5", "0"), 2.5);
+        assert_eq!(op("log", "1", "0"), 0.0);
+        assert_eq!(op("log", "0", "0"), 0.0);
+        assert_eq!(op("floor", "-1.5", "0"), -2.0);
+        assert_eq!(op("ceil", "1.2", "0"), 2.0);
+
+        assert_eq!(op("and", "12", "10"), 8.0);
+        assert_eq!(op("or", "12", "10"), 14.0);
+        assert_eq!(op("xor", "12", "10"), 6.0);
+        assert_eq!(op("and", "7.9", "3"), 3.0);
+        assert_eq!(op("shl", "1", "4"), 16.0);
+        assert_eq!(op("shl", "1", "65"), 2.0);
+        assert_eq!(op("shr", "-16", "2"), -4.0);
+        assert_eq!(op("not", "0", "0"), -1.0);
+        assert_eq!(op("not", "5", "0"), -6.0);
+        assert_eq!(op("land", "2", "3"), 1.0);
+        assert_eq!(op("land", "2", "0"), 0.0);
+
+        assert!(Emulator::new(None, "op noise x 1 2").is_err());
+        for math in Math::ALL.iter() {
+            let program = format!("op {} x 1 2", math);
+            let emu = Emulator::new(None, &program).unwrap();
+            assert_eq!(emu.instructions[0].to_string(), program);
+        }
+    }
+
     #[test]
     fn test_out_of_bounds_counter_same_as_end() {
-        let x = Rc::new(String::from("x"));
-        let y = Rc::new(String::from("y"));
+        let x = Symbol::new("x");
+        let y = Symbol::new("y");
 
         for program in &[
             "op add x x 1\nset @counter 100\nset y 2",
@@ -652,4 +2359,608 @@ mod tests {
             assert_eq!(emu.get_var(&y), None);
         }
     }
+
+    #[test]
+    fn test_select() {
+        let selects = |cond: &str, a: &str, b: &str| {
+            let program = format!("set zero 0\nselect r {} {} {} 10 20", cond, a, b);
+            let mut emu = Emulator::new(None, &program).unwrap();
+            emu.run(10);
+            emu.get_var("r").unwrap()
+        };
+
+        assert_eq!(selects("lessThan", "1", "2"), 10);
+        assert_eq!(selects("lessThan", "2", "1"), 20);
+        assert_eq!(selects("equal", "unset", "zero"), 10);
+        assert_eq!(selects("strictEqual", "unset", "zero"), 20);
+        assert_eq!(selects("always", "0", "0"), 10);
+
+        // The chosen value is copied as it is.
+        let mut emu = Emulator::new(None, "select r notEqual 1 1 x null").unwrap();
+        emu.run(10);
+        assert_eq!(emu.get_value("r"), None);
+
+        let err = Emulator::new(None, "select r bogus 1 1 x y").err().unwrap();
+        assert!(err.to_string().contains("Unsupported condition bogus"));
+        assert_eq!(
+            Emulator::new(None, "select r lessThan a b 1 2")
+                .unwrap()
+                .instructions[0]
+                .to_string(),
+            "select r lessThan a b 1 2"
+        );
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut emu = Emulator::new(
+            None,
+            "lookup item a 0
+             lookup liquid b 3.7
+             lookup item c 1000
+             lookup item d -1
+             jump 6 equal a @copper
+             end
+             print a
+             print b
+             print c
+             printflush message1",
+        )
+        .unwrap();
+        let output = emu.run(20);
+        assert_eq!(
+            output.last().unwrap(),
+            "\tPrinted to message1: coppercryofluidnull"
+        );
+        assert_eq!(emu.get_value("d"), None);
+
+        // Content is 1 as a number, and only equal to itself.
+        assert_eq!(Value::Content(Symbol::new("@lead")).num(), 1.0);
+        assert!(!Value::Content(Symbol::new("@lead")).equal(&Value::Content(Symbol::new("@sand"))));
+        assert!(Value::Content(Symbol::new("@lead")).equal(&Value::Number(1.0)));
+        assert!(!Value::Content(Symbol::new("@lead")).equal(&Value::Null));
+
+        // Units and blocks are only known if given.
+        let mut emu = Emulator::new(None, "lookup unit a 1\nlookup block b 0").unwrap();
+        emu.set_lookup_table(
+            ContentType::Unit,
+            vec![Symbol::new("dagger"), Symbol::new("mace")],
+        );
+        emu.run(10);
+        assert_eq!(
+            resolve(&emu.vars, &Symbol::new("a")),
+            Value::Content(Symbol::new("@mace"))
+        );
+        assert_eq!(resolve(&emu.vars, &Symbol::new("b")), Value::Null);
+
+        assert!(Emulator::new(None, "lookup fluid a 0").is_err());
+    }
+
+    #[test]
+    fn test_rand() {
+        let program = "op rand a 10 0\nop rand b 10 0\nop rand c -2 0\nop rand d x 0";
+        let values = |seed| {
+            let mut emu = Emulator::with_seed(None, program, seed).unwrap();
+            emu.run(10);
+            ["a", "b", "c", "d"]
+                .iter()
+                .map(|var| emu.get_value(var).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let first = values(7);
+        assert_eq!(first, values(7));
+        assert_ne!(first, values(8));
+        assert!((0.0..10.0).contains(&first[0]));
+        assert!((0.0..10.0).contains(&first[1]));
+        assert_ne!(first[0], first[1]);
+        assert!(first[2] <= 0.0 && first[2] > -2.0);
+        assert_eq!(first[3], 0.0);
+
+        // `new` is seeded too.
+        let run = || {
+            let mut emu = Emulator::new(None, program).unwrap();
+            emu.run(10);
+            emu.get_value("a")
+        };
+        assert_eq!(run(), run());
+
+        let emu = Emulator::new(None, "op rand a 10 0").unwrap();
+        assert_eq!(emu.instructions[0].to_string(), "op rand a 10 0");
+    }
+
+    #[test]
+    fn test_world() {
+        let mut world = World::default();
+        world.link(WorldObject::new("vault1").with_sensor("@copper", Value::Number(250.0)));
+        world.link(WorldObject::new("switch1").with_sensor("@enabled", Value::Number(1.0)));
+        world.add_unit(WorldObject::new("@poly").with_sensor("@health", Value::Number(400.0)));
+
+        let mut emu = Emulator::new(
+            None,
+            "set n @links
+             getlink b 1
+             sensor on b @enabled
+             sensor copper vault1 @copper
+             sensor lead vault1 @lead
+             sensor none nothing @copper
+             getlink missing 2
+             ubind @mega
+             ucontrol idle 0 0 0 0 0
+             ubind @poly
+             sensor health @unit @health
+             ucontrol move copper 5 0 0 0
+             print b",
+        )
+        .unwrap();
+        emu.set_world(world);
+        let output = emu.run(20);
+
+        assert_eq!(emu.get_var("n"), Some(2));
+        assert_eq!(emu.get_var("on"), Some(1));
+        assert_eq!(emu.get_var("copper"), Some(250));
+        assert_eq!(emu.get_value("lead"), None);
+        assert_eq!(emu.get_value("none"), None);
+        assert_eq!(emu.get_value("missing"), None);
+        assert_eq!(emu.get_var("health"), Some(400));
+        assert!(output.last().unwrap().contains("print b"));
+
+        let world = emu.world();
+        assert_eq!(world.bound(), Some(Symbol::new("@poly")));
+        assert_eq!(
+            world.commands(),
+            &[
+                UnitCommand {
+                    unit: None,
+                    command: Symbol::new("idle"),
+                    args: vec![Value::Number(0.0); 5],
+                },
+                UnitCommand {
+                    unit: Some(Symbol::new("@poly")),
+                    command: Symbol::new("move"),
+                    args: vec![
+                        Value::Number(250.0),
+                        Value::Number(5.0),
+                        Value::Number(0.0),
+                        Value::Number(0.0),
+                        Value::Number(0.0),
+                    ],
+                },
+            ]
+        );
+
+        // Buildings print by name, and are only equal to themselves.
+        let mut emu = Emulator::new(None, "getlink a 0\nprint a\nprintflush message1").unwrap();
+        let mut world = World::default();
+        world.link(WorldObject::new("cell1"));
+        emu.set_world(world);
+        assert_eq!(emu.run(10).last().unwrap(), "\tPrinted to message1: cell1");
+        assert!(Value::Object(Symbol::new("cell1")).equal(&Value::Object(Symbol::new("cell1"))));
+        assert!(!Value::Object(Symbol::new("cell1")).equal(&Value::Object(Symbol::new("cell2"))));
+
+        assert!(Emulator::new(None, "ucontrol move 1 2").is_err());
+        assert_eq!(
+            Emulator::new(None, "ucontrol move 1 2 0 0 0")
+                .unwrap()
+                .instructions[0]
+                .to_string(),
+            "ucontrol move 1 2 0 0 0"
+        );
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut emu = Emulator::new(
+            None,
+            "set x 10
+             draw clear 0 0 0 0 0 0
+             draw color 255 x 0 255
+             draw rect x 20 5 5
+             drawflush display1
+             draw line 0 0 x x
+             drawflush display2
+             drawflush display1",
+        )
+        .unwrap();
+        let output = emu.run(20);
+        assert!(output.contains(&"\tDrew 3 to display1".to_string()));
+
+        let number = |n| Value::Number(n);
+        let command = |command: &str, args: Vec<f64>| DrawCommand {
+            command: Symbol::new(command),
+            args: args.into_iter().map(number).collect(),
+        };
+        let flushes = emu.draw_flushes();
+        assert_eq!(flushes.len(), 3);
+        assert_eq!(flushes[0].display, Symbol::new("display1"));
+        assert_eq!(
+            flushes[0].commands,
+            vec![
+                command("clear", vec![0.0; 6]),
+                command("color", vec![255.0, 10.0, 0.0, 255.0, 0.0, 0.0]),
+                command("rect", vec![10.0, 20.0, 5.0, 5.0, 0.0, 0.0]),
+            ]
+        );
+        assert_eq!(
+            flushes[1].commands,
+            vec![command("line", vec![0.0, 0.0, 10.0, 10.0, 0.0, 0.0])]
+        );
+        assert!(flushes[2].commands.is_empty());
+
+        assert!(Emulator::new(None, "draw").is_err());
+        assert!(Emulator::new(None, "draw a 1 2 3 4 5 6 7").is_err());
+        assert_eq!(
+            Emulator::new(None, "draw rect 1 2").unwrap().instructions[0].to_string(),
+            "draw rect 1 2 0 0 0 0"
+        );
+    }
+
+    #[test]
+    fn test_cell_load() {
+        let mut cell = Cell::with_size("cell1", 8);
+        cell.load("# A lookup table.\n3 1.5 _ -2\n6: 7 # Near the end.\n")
+            .unwrap();
+        assert_eq!(
+            cell.data,
+            vec![
+                Some(3.0),
+                Some(1.5),
+                None,
+                Some(-2.0),
+                None,
+                None,
+                Some(7.0),
+                None
+            ]
+        );
+
+        let mut emu = Emulator::new(Some(cell.clone()), "read a cell1 1\nread b cell1 6").unwrap();
+        emu.run(2);
+        assert_eq!(emu.get_value("a"), Some(1.5));
+        assert_eq!(emu.get_var("b"), Some(7));
+
+        let error = format!("{:#}", cell.load("1\n7: 1 2").unwrap_err());
+        assert_eq!(error, "Line 1: 2: address 8 is past the end of cell1");
+        assert!(cell.load("x").is_err());
+        assert!(cell.load("a: 1").is_err());
+    }
+
+    #[test]
+    fn test_cells() {
+        let cells = vec![Cell::with_size("cell1", 64), Cell::new("bank1")];
+        let mut emu = Emulator::new(
+            cells,
+            "write 1 cell1 63
+             write 2 cell1 64
+             write 3 bank1 64
+             read a cell1 64
+             read b bank1 64
+             read c cell1 63",
+        )
+        .unwrap();
+        emu.run(10);
+        assert_eq!(emu.get_cell_mem("cell1", 63), Some(1));
+        assert_eq!(emu.get_cell_mem("cell1", 64), None);
+        assert_eq!(emu.get_cell_mem("bank1", 64), Some(3));
+        assert_eq!(emu.get_var("a"), Some(0));
+        assert_eq!(emu.get_var("b"), Some(3));
+        assert_eq!(emu.get_var("c"), Some(1));
+
+        // The first cell is the one `get_mem` reads.
+        assert_eq!(emu.get_mem(63), Some(1));
+
+        // Adding a cell of the same name replaces it.
+        emu.add_cell(Cell::with_size("cell1", 8));
+        assert_eq!(emu.get_cell_mem("cell1", 63), None);
+        assert_eq!(emu.cells[0].size(), 8);
+        assert_eq!(emu.cells.len(), 2);
+
+        let cells = vec![Cell::new("bank1"), Cell::with_size("bank1", 64)];
+        assert!(Emulator::new(cells, "end").is_err());
+
+        assert_eq!(Cell::memory_cell("cell1").size(), 64);
+        assert_eq!(Cell::memory_bank("bank1").size(), 512);
+    }
+
+    #[test]
+    fn test_ticks() {
+        let program = "set i 0\nop add i i 1\njump 1 lessThan i 10";
+
+        // 1 + 10 * 2 = 21 instructions.
+        let mut emu = Emulator::new(None, program).unwrap();
+        assert_eq!(emu.run(100).len(), 21);
+        assert_eq!(emu.ticks(), 10);
+
+        let mut emu = Emulator::new(None, program).unwrap();
+        emu.set_instructions_per_tick(8).unwrap();
+        emu.run(100);
+        assert_eq!(emu.ticks(), 2);
+
+        // Ticks carry on from one run to the next.
+        emu.run(100);
+        assert_eq!(emu.ticks(), 5);
+
+        assert!(emu.set_instructions_per_tick(0).is_err());
+    }
+
+    #[test]
+    fn test_time() {
+        // Waits for 90 ticks, a second and a half, to pass. At 2 instructions
+        // a tick, the first `set` runs on tick 90, and the others on tick 91.
+        let mut emu = Emulator::new(
+            None,
+            "op add deadline @tick 90
+             jump 1 lessThan @tick deadline
+             set second @second
+             set time @time
+             set tick @tick",
+        )
+        .unwrap();
+        emu.run(1000);
+        assert_eq!(emu.get_var("deadline"), Some(90));
+        assert_eq!(emu.get_value("tick"), Some(91.0));
+        assert_eq!(emu.get_value("second"), Some(1.5));
+        assert_eq!(emu.get_value("time"), Some(91.0 * 1000.0 / 60.0));
+        assert_eq!(emu.ticks(), 92);
+    }
+
+    #[test]
+    fn test_wait() {
+        let mut emu = Emulator::new(
+            None,
+            "set a @tick
+             wait 0.5
+             set b @tick
+             wait seconds
+             set c @tick
+             wait 0.01
+             set d @tick
+             wait -1
+             set e @tick",
+        )
+        .unwrap();
+        emu.run(100);
+        assert_eq!(emu.get_var("a"), Some(0));
+        assert_eq!(emu.get_var("b"), Some(30));
+        // An unset duration is 0, so doesn't wait.
+        assert_eq!(emu.get_var("c"), Some(31));
+        // Any wait at all takes at least a tick.
+        assert_eq!(emu.get_var("d"), Some(33));
+        assert_eq!(emu.get_var("e"), Some(34));
+        assert!(Emulator::new(None, "wait").is_err());
+    }
+
+    #[test]
+    fn test_print_buffer_limit() {
+        let program = "print \"0123456789\"
+                       op add i i 1
+                       jump 0 lessThan i 45
+                       printflush message1";
+
+        let mut emu = Emulator::new(None, program).unwrap();
+        let output = emu.run(1000);
+        let printed = output.last().unwrap();
+        assert_eq!(
+            printed.len(),
+            "\tPrinted to message1: ".len() + MAX_PRINT_BUFFER
+        );
+        assert!(!output.iter().any(|line| line.contains("Warning")));
+
+        let mut emu = Emulator::new(None, program).unwrap();
+        emu.set_warn_print_overflow(true);
+        let output = emu.run(1000);
+        let warnings: Vec<_> = output
+            .iter()
+            .filter(|line| line.contains("Warning"))
+            .collect();
+        assert_eq!(warnings.len(), 5);
+        assert_eq!(
+            warnings[0],
+            "\tWarning: print buffer is full, so 10 characters were dropped"
+        );
+
+        // The buffer empties on flush.
+        let mut emu = Emulator::new(
+            None,
+            "print \"abc\"\nprintflush message1\nprint \"de\"\nprintflush message1",
+        )
+        .unwrap();
+        let output = emu.run(10);
+        assert_eq!(output.last().unwrap(), "\tPrinted to message1: de");
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        use std::convert::TryFrom;
+
+        let mut emu = Emulator::new(
+            Some(Cell::default()),
+            "set a 1
+             set b 1
+             set a 1
+             write 7 bank1 3
+             op add a a 1
+             end",
+        )
+        .unwrap();
+        emu.set_watchpoints(vec![
+            Watchpoint::try_from("a").unwrap(),
+            Watchpoint::try_from("bank1[3]").unwrap(),
+        ]);
+
+        let output = emu.run(100);
+        assert_eq!(
+            output.last().unwrap(),
+            "Watchpoint a changed from null to 1 at 0"
+        );
+
+        // Setting it to the same value isn't a change.
+        let output = emu.run(100);
+        assert_eq!(output.len(), 4);
+        assert_eq!(
+            output.last().unwrap(),
+            "Watchpoint bank1[3] changed from null to 7 at 3"
+        );
+
+        let output = emu.run(100);
+        assert_eq!(
+            output.last().unwrap(),
+            "Watchpoint a changed from 1 to 2 at 4"
+        );
+        emu.run(100);
+        assert_eq!(emu.get_var("@counter"), Some(0));
+
+        assert_eq!(
+            Watchpoint::try_from("bank2[12]").unwrap(),
+            Watchpoint::Memory(Symbol::new("bank2"), 12)
+        );
+        assert!(Watchpoint::try_from("bank2[x]").is_err());
+    }
+
+    #[test]
+    fn test_run_back() {
+        let program = "op rand r 100 0
+                       op add i i 1
+                       write i bank1 i
+                       jump 0 lessThan i 10";
+        let mut emu = Emulator::new(Some(Cell::default()), program).unwrap();
+        assert!(emu.run_back(0).is_err());
+        emu.set_snapshot_interval(3).unwrap();
+        emu.run(10);
+        let r = emu.get_var("r");
+        emu.run(20);
+        assert_eq!(emu.steps(), 30);
+        assert_eq!(emu.get_var("i"), Some(8));
+
+        // Back to between snapshots, with the same random numbers.
+        emu.run_back(20).unwrap();
+        assert_eq!(emu.steps(), 10);
+        assert_eq!(emu.get_var("i"), Some(3));
+        assert_eq!(emu.get_var("r"), r);
+        assert_eq!(emu.get_cell_mem("bank1", 2), Some(2));
+        assert_eq!(emu.get_cell_mem("bank1", 3), None);
+
+        // Running forward again does the same as the first time.
+        emu.run(20);
+        assert_eq!(emu.get_var("i"), Some(8));
+        emu.run_back(30).unwrap();
+        assert_eq!(emu.get_var("i"), None);
+        assert!(emu.run_back(1).is_err());
+    }
+
+    #[test]
+    fn test_save_state() {
+        let program = "op rand r 100 0
+                       op add i i 1
+                       write i bank1 i
+                       print i
+                       jump 0 lessThan i 10
+                       printflush message1";
+        let mut emu = Emulator::new(Some(Cell::default()), program).unwrap();
+        emu.run(12);
+        let state = emu.save_state();
+        let rest = emu.run(100);
+
+        // Another emulator picks up where the first left off.
+        let mut other = Emulator::new(Some(Cell::default()), program).unwrap();
+        other.load_state(&state).unwrap();
+        assert_eq!(other.get_var("@counter"), Some(2));
+        assert_eq!(other.get_cell_mem("bank1", 2), Some(2));
+        assert_eq!(other.steps(), 12);
+        assert_eq!(other.run(100), rest);
+        assert_eq!(other.get_var("r"), emu.get_var("r"));
+        assert_eq!(other.ticks(), emu.ticks());
+
+        assert!(other.load_state("{}").is_err());
+    }
+
+    #[test]
+    fn test_events() {
+        let mut emu = Emulator::new(
+            None,
+            "print \"hi\"
+             printflush message1
+             end",
+        )
+        .unwrap();
+        emu.set_watches(vec![Symbol::new("@counter"), Symbol::new("*x")]);
+        emu.set_breakpoints(vec![1]);
+
+        let events = emu.step();
+        assert_eq!(
+            events,
+            vec![Event::Executed {
+                ip: 0,
+                instruction: Instruction::Print(Symbol::new("\"hi\"")),
+                watches: vec![
+                    (Symbol::new("@counter"), Some(Value::Number(1.0))),
+                    (Symbol::new("*x"), None),
+                ],
+            }]
+        );
+
+        // Running from a breakpoint runs the instruction there first.
+        let events = emu.run_events(100);
+        assert_eq!(events.len(), 4);
+        assert_eq!(
+            events[1],
+            Event::Printed {
+                target: Symbol::new("message1"),
+                text: "hi".to_string(),
+            }
+        );
+        assert_eq!(events[3], Event::Ended);
+        assert_eq!(emu.render(&events)[1], "\tPrinted to message1: hi");
+
+        assert_eq!(emu.run_events(100).last(), Some(&Event::BreakpointHit(1)));
+    }
+
+    #[test]
+    fn test_step_limit() {
+        // A jump back to the wrong place, so `i` never reaches 10.
+        let mut emu = Emulator::new(
+            None,
+            "set i 0
+             op add i i 1
+             set j 0
+             jump 2 lessThan i 10
+             end",
+        )
+        .unwrap();
+
+        // Single steps don't count as using up the steps.
+        assert_eq!(emu.run_events(1).len(), 1);
+
+        let events = emu.run_events(1000);
+        assert_eq!(
+            events.last(),
+            Some(&Event::StepLimit {
+                steps: 1000,
+                histogram: vec![(2, 50), (3, 50)],
+                cycle: Some(vec![2, 3]),
+            })
+        );
+        let trace = emu.render(&events);
+        assert_eq!(
+            trace[trace.len() - 2..],
+            [
+                "Stopped after 1000 steps. Most run of the last 100: 2 (50 times), 3 (50 times)",
+                "\tStuck repeating 2 -> 3",
+            ]
+        );
+
+        // Code that takes a different path each time has no cycle.
+        let mut emu = Emulator::new(
+            None,
+            "op rand r 2 0
+             jump 3 lessThan r 1
+             set a 1
+             jump 0 always x false",
+        )
+        .unwrap();
+        assert!(matches!(
+            emu.run_events(1000).last(),
+            Some(Event::StepLimit { cycle: None, .. }),
+        ));
+    }
 }
@@ -0,0 +1,333 @@
+use crate::*;
+
+/// The kind of name a `CompileError::UndefinedSymbol` refers to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Label,
+    StackVar,
+}
+
+impl std::fmt::Display for SymbolKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SymbolKind::Function => write!(f, "function"),
+            SymbolKind::Label => write!(f, "label"),
+            SymbolKind::StackVar => write!(f, "stack variable"),
+        }
+    }
+}
+
+/// Why compiling a program failed, as returned by `parser::parse` and
+/// `generate`, for callers that need to tell failures apart.
+///
+/// Each variant carries the full message, including the offending source
+/// line where there is one, which is what `Display` shows. `line` counts from
+/// 0, though the message counts from 1, as editors do.
+#[derive(Clone, PartialEq, Eq)]
+pub enum CompileError {
+    /// The source is malformed, or asks for something that can't be done.
+    Parse {
+        line: Option<usize>,
+        message: String,
+    },
+
+    /// A function, label, or stack variable that isn't defined.
+    /// `suggestions` are the defined names closest to it, as in the message.
+    UndefinedSymbol {
+        kind: SymbolKind,
+        name: String,
+        suggestions: Vec<String>,
+        line: Option<usize>,
+        message: String,
+    },
+
+    /// The program is longer than the instruction limit.
+    SizeOverflow {
+        size: usize,
+        limit: usize,
+        message: String,
+    },
+
+    /// A bug in the compiler.
+    Internal { message: String },
+
+    /// More than one statement failed to parse. Each is listed, in order.
+    Diagnostics {
+        diagnostics: Vec<Diagnostic>,
+        message: String,
+    },
+}
+
+pub type CompileResult<T> = std::result::Result<T, CompileError>;
+
+impl CompileError {
+    /// `name` isn't defined. `defined` are the names of that kind that are,
+    /// from which the closest are suggested.
+    pub fn undefined<S: AsRef<str>>(
+        kind: SymbolKind,
+        name: &dyn std::fmt::Display,
+        defined: impl IntoIterator<Item = S>,
+    ) -> CompileError {
+        let name = name.to_string();
+        let suggestions = suggestions(&name, defined);
+        let mut message = format!("{} {} is not defined", kind, name);
+        if !suggestions.is_empty() {
+            message.push_str(&format!("; did you mean {}?", or_list(&suggestions)));
+        }
+        CompileError::UndefinedSymbol {
+            message,
+            kind,
+            name,
+            suggestions,
+            line: None,
+        }
+    }
+
+    pub fn internal(message: &str) -> CompileError {
+        CompileError::Internal {
+            message: format!("Internal error: {}", message),
+        }
+    }
+
+    /// A stable name for the kind of error, for tools: `parse`,
+    /// `undefined_symbol`, `size_overflow`, `internal`, or `diagnostics`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompileError::Parse { .. } => "parse",
+            CompileError::UndefinedSymbol { .. } => "undefined_symbol",
+            CompileError::SizeOverflow { .. } => "size_overflow",
+            CompileError::Internal { .. } => "internal",
+            CompileError::Diagnostics { .. } => "diagnostics",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            CompileError::Parse { message, .. }
+            | CompileError::UndefinedSymbol { message, .. }
+            | CompileError::SizeOverflow { message, .. }
+            | CompileError::Internal { message }
+            | CompileError::Diagnostics { message, .. } => message,
+        }
+    }
+
+    /// Every problem this error reports: several for `Diagnostics`, or else
+    /// just the one.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            CompileError::Diagnostics { diagnostics, .. } => diagnostics.clone(),
+            error => vec![Diagnostic::from(error)],
+        }
+    }
+
+    /// The error for the problems found in a program, which must be at least
+    /// one. A single error is returned as it is.
+    pub(crate) fn from_errors(mut errors: Vec<CompileError>) -> CompileError {
+        if errors.len() == 1 {
+            return errors.remove(0);
+        }
+
+        let diagnostics: Vec<Diagnostic> = errors.iter().map(Diagnostic::from).collect();
+        let message = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        CompileError::Diagnostics {
+            diagnostics,
+            message,
+        }
+    }
+
+    /// The source line the error is on, if known.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            CompileError::Parse { line, .. } | CompileError::UndefinedSymbol { line, .. } => *line,
+            _ => None,
+        }
+    }
+
+    /// Converts an error from inside the compiler. Errors raised as a
+    /// `CompileError` keep their kind, and pick up the source line and the
+    /// rest of the message from the context around them. Any other error is a
+    /// `Parse` error if it happened while parsing, or an `Internal` one if
+    /// during code generation, since the parser should have caught it.
+    pub(crate) fn from_anyhow(err: anyhow::Error, parsing: bool) -> CompileError {
+        let full = format!("{:#}", err);
+        let line = err.downcast_ref::<SourceLine>().map(|source| source.line);
+
+        let mut error = match err.downcast_ref::<CompileError>() {
+            Some(error) => error.clone(),
+            None if parsing => CompileError::Parse {
+                line: None,
+                message: String::default(),
+            },
+            None => CompileError::Internal {
+                message: String::default(),
+            },
+        };
+
+        match &mut error {
+            CompileError::Parse {
+                line: error_line,
+                message,
+            }
+            | CompileError::UndefinedSymbol {
+                line: error_line,
+                message,
+                ..
+            } => {
+                *error_line = error_line.or(line);
+                *message = full;
+            }
+            CompileError::SizeOverflow { message, .. }
+            | CompileError::Internal { message }
+            | CompileError::Diagnostics { message, .. } => *message = full,
+        }
+        error
+    }
+}
+
+/// The names in `defined` close enough to `name` that it may be a typo of
+/// them, closest first, and at most three. Names are close if a third of their
+/// characters or fewer must be inserted, removed, or changed to get from one
+/// to the other.
+pub fn suggestions<S: AsRef<str>>(name: &str, defined: impl IntoIterator<Item = S>) -> Vec<String> {
+    let limit = std::cmp::max(1, name.chars().count() / 3);
+    let mut close: Vec<(usize, String)> = defined
+        .into_iter()
+        .map(|candidate| candidate.as_ref().to_string())
+        .filter(|candidate| candidate != name)
+        .map(|candidate| (edit_distance(name, &candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .collect();
+    close.sort();
+    close.dedup();
+    close
+        .into_iter()
+        .take(3)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+/// The Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (j, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = j + 1;
+        for (k, b) in b.iter().enumerate() {
+            let substitute = diagonal + usize::from(a != *b);
+            diagonal = row[k + 1];
+            row[k + 1] = substitute.min(row[k] + 1).min(row[k + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// `a`, `a or b`, or `a, b, or c`.
+pub(crate) fn or_list(names: &[String]) -> String {
+    match names {
+        [] => String::default(),
+        [name] => name.clone(),
+        [first, second] => format!("{} or {}", first, second),
+        [rest @ .., last] => format!("{}, or {}", rest.join(", "), last),
+    }
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+// As with `anyhow::Error`, the message is more use than the structure when
+// a test or `main` unwraps an error.
+impl std::fmt::Debug for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The program can't be compiled.
+    Error,
+
+    /// The program compiles, but likely doesn't do what was meant.
+    Warning,
+
+    /// Something the compiler did that may be worth knowing.
+    Note,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// One problem found in a program, or a note about it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+
+    /// The kind of warning or note, which `#allow` names to suppress it.
+    /// Errors have none, since they can't be suppressed.
+    pub lint: Option<Lint>,
+
+    /// For errors, the kind of `CompileError`, as `CompileError::code` names
+    /// it. None for warnings and notes, and for failures that aren't a
+    /// problem with the program, such as a file that can't be read.
+    pub error_code: Option<&'static str>,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// The lint's name for warnings and notes, or the error's code.
+    pub fn code(&self) -> Option<&'static str> {
+        self.lint.map(|lint| lint.name()).or(self.error_code)
+    }
+}
+
+impl From<&CompileError> for Diagnostic {
+    fn from(error: &CompileError) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            lint: None,
+            error_code: Some(error.code()),
+            line: error.line(),
+            message: error.message().to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// Context for an error on a line of the source. See
+/// `CompileError::from_anyhow`.
+#[derive(Debug)]
+pub(crate) struct SourceLine {
+    pub stage: &'static str,
+    pub line: usize,
+    pub text: String,
+}
+
+impl std::fmt::Display for SourceLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}Line {}: {}", self.stage, self.line + 1, self.text)
+    }
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use crate::*;
+
+/// How many lines `ExecutionProfile`'s report lists.
+const REPORTED_LINES: usize = 10;
+
+/// How many instructions each part of a program ran in the emulator, to show
+/// which loops use up the most of its instruction budget in game. See
+/// `Emulator::execution_profile`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExecutionProfile {
+    /// Every instruction run.
+    pub total: u64,
+
+    /// How many times the instruction at each address ran.
+    pub instructions: Vec<u64>,
+
+    /// Each source line that ran, with the instructions run for it, most
+    /// first. Lines that ran as many are in order.
+    pub lines: Vec<(usize, u64)>,
+
+    /// Code outside any function, including setup and stack tables.
+    pub top_level: u64,
+
+    /// Each function, in order of address.
+    pub functions: Vec<(FunctionName, u64)>,
+}
+
+impl ExecutionProfile {
+    /// Attributes the runs of each instruction, by address, to the source
+    /// lines and functions `map` says it came from. Without a source map,
+    /// everything is top-level code.
+    pub fn new(instructions: &[u64], map: &SourceMap) -> ExecutionProfile {
+        let mut lines: HashMap<usize, u64> = HashMap::default();
+        let mut functions: Vec<(FunctionName, u64)> = map
+            .functions
+            .iter()
+            .map(|(name, _)| (name.clone(), 0))
+            .collect();
+        let mut top_level = 0;
+
+        for (address, count) in instructions.iter().enumerate() {
+            if let Some(location) = map.location(address) {
+                *lines.entry(location.line).or_default() += count;
+            }
+            match map
+                .functions
+                .iter()
+                .position(|(_, range)| range.contains(&address))
+            {
+                Some(j) => functions[j].1 += count,
+                None => top_level += count,
+            }
+        }
+
+        let mut lines: Vec<(usize, u64)> = lines.into_iter().filter(|(_, n)| *n > 0).collect();
+        lines.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        ExecutionProfile {
+            total: instructions.iter().sum(),
+            instructions: instructions.to_vec(),
+            lines,
+            top_level,
+            functions,
+        }
+    }
+}
+
+impl std::fmt::Display for ExecutionProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Total: {} instructions run", self.total)?;
+        writeln!(f, "  top-level code: {}", self.top_level)?;
+        for (name, count) in self.functions.iter() {
+            writeln!(f, "  function {}: {}", name, count)?;
+        }
+        if !self.lines.is_empty() {
+            writeln!(f, "Busiest lines:")?;
+        }
+        for (line, count) in self.lines.iter().take(REPORTED_LINES) {
+            writeln!(f, "  line {}: {}", line, count)?;
+        }
+        Ok(())
+    }
+}
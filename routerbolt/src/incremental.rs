@@ -0,0 +1,124 @@
+use crate::*;
+
+/// Recompiles a program as it's edited, as the web UI does, redoing only the
+/// work an edit makes necessary.
+///
+/// The program is lowered in parts, such as each function, each from address
+/// 0, and moved into place after the parts before it; see
+/// `parser::parse_with_cache`. An edit to the code lowers only the parts it
+/// touches again, and the parts after them are moved to their new addresses
+/// rather than lowered again. An edit to what the program declares, such as a
+/// function's args or `let`s, or a `stack_config`, lowers every part again.
+/// The passes and code generation run over the whole program either way.
+///
+/// Edits that leave the code as it was, such as to comments, blank lines, or
+/// indentation, keep the previous IR, with only the source it's listed with
+/// replaced, and recompiling the same text again does nothing at all.
+pub struct IncrementalCompiler {
+    compiler: Compiler,
+    cache: parser::LoweringCache,
+    last: Option<Compiled>,
+    change: Change,
+}
+
+/// What `IncrementalCompiler::compile` had to do for the latest text.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// The text was the same as before.
+    Unchanged,
+
+    /// Only comments or whitespace changed, so the program was generated
+    /// again from the previous IR.
+    Relisted,
+
+    /// The code changed, so was parsed again, lowering `lowered` parts of it
+    /// and reusing `reused` lowered before. The first compile lowers them
+    /// all.
+    Recompiled { lowered: usize, reused: usize },
+}
+
+struct Compiled {
+    text: String,
+
+    /// Each line of the expanded source as it affects the program. See
+    /// `code_line`.
+    code: Vec<String>,
+    program: Program,
+}
+
+impl IncrementalCompiler {
+    pub fn new(compiler: Compiler) -> IncrementalCompiler {
+        IncrementalCompiler {
+            compiler,
+            cache: parser::LoweringCache::default(),
+            last: None,
+            change: Change::Unchanged,
+        }
+    }
+
+    /// Compiles `text`, reusing what it can from the last program compiled.
+    /// After an error, the next text is compared with the last that
+    /// compiled.
+    pub fn compile(&mut self, text: &str) -> CompileResult<&Program> {
+        if self.last.as_ref().is_some_and(|last| last.text == text) {
+            self.change = Change::Unchanged;
+            return Ok(&self.last.as_ref().unwrap().program);
+        }
+
+        let expanded = self.compiler.expand(text)?;
+        let code: Vec<String> = expanded.lines().map(code_line).collect();
+        match &self.last {
+            Some(last) if last.code == code => {
+                log::debug!("only comments or whitespace changed; reusing the IR");
+                let mut ir = self.last.take().unwrap().program.ir;
+                ir.source_lines = expanded
+                    .lines()
+                    .map(|line| line.trim().to_string())
+                    .collect();
+                self.last = Some(Compiled {
+                    text: text.to_string(),
+                    code,
+                    program: self.compiler.generate(ir)?,
+                });
+                self.change = Change::Relisted;
+            }
+            _ => {
+                let options = self.compiler.options()?;
+                let ir = parser::parse_with_cache(&expanded, &options, &mut self.cache)?;
+                self.last = Some(Compiled {
+                    text: text.to_string(),
+                    code,
+                    program: self.compiler.generate(ir)?,
+                });
+                self.change = Change::Recompiled {
+                    lowered: self.cache.lowered(),
+                    reused: self.cache.reused(),
+                };
+                log::debug!("code changed; {:?}", self.change);
+            }
+        }
+        Ok(&self.last.as_ref().unwrap().program)
+    }
+
+    /// What the last successful `compile` had to do.
+    pub fn change(&self) -> Change {
+        self.change
+    }
+
+    /// The last program compiled, if any.
+    pub fn program(&self) -> Option<&Program> {
+        self.last.as_ref().map(|last| &last.program)
+    }
+}
+
+/// `line` as far as the generated code is concerned: without the
+/// indentation, and empty if it's a comment. Whitespace within a line is
+/// kept, since `asm` blocks pass their lines through as they are.
+fn code_line(line: &str) -> String {
+    let line = line.trim();
+    if line.starts_with("//") {
+        String::default()
+    } else {
+        line.to_string()
+    }
+}
@@ -4,15 +4,13 @@
 ///
 /// These make use of a variable named `MF_acc` as the accumulator, and
 /// `MF_tmp`/`MF_resume` as scratch space.
-use std::rc::Rc;
-
 use crate::*;
 
 /// Pushes return address to the stack and jumps to the target label. RetProc
 /// will return from it.
 ///
 /// Destroys: `MF_acc` `MF_tmp` `MF_resume`
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CallProcOp {
     /// Name of label to call.
     pub target: LabelName,
@@ -21,8 +19,8 @@ pub struct CallProcOp {
 impl Operation for CallProcOp {
     fn code_size(&self, backend: Backend) -> AddressDelta {
         match backend {
-            Backend::Internal => 5,
-            Backend::External => 4,
+            Backend::Internal => 6,
+            Backend::External | Backend::Banked => 3 + backend.cell_access_size(),
         }
         .into()
     }
@@ -34,6 +32,7 @@ impl Operation for CallProcOp {
         annotated: Option<&mut Vec<String>>,
         _instruction_count: &mut Address,
     ) -> Result<()> {
+        let mf = ir.variable_prefix.as_str();
         if let Some(annotated) = annotated {
             annotated.push(format!(
                 "// CallProc {} @{}",
@@ -42,20 +41,32 @@ impl Operation for CallProcOp {
             ));
         }
 
-        let target = ir.labels()[&self.target];
+        let target = ir.labels().get(&self.target).ok_or_else(|| {
+            CompileError::undefined(
+                SymbolKind::Label,
+                &self.target,
+                ir.labels().keys().map(LabelName::unscoped),
+            )
+        })?;
 
         match ir.backend_params() {
             BackendParams::Internal(int) => {
-                output.push("op add MF_acc @counter 4".to_string());
-                output.push("op add MF_resume @counter 2".to_string());
-                output.push(format!("op mul MF_tmp {} MF_stack_sz", int.push_entry_size));
-                output.push(format!("op add @counter {} MF_tmp", int.push_table_start));
+                output.push(format!("op add {mf}acc @counter 5"));
+                output.push(format!("op add {mf}resume @counter 3"));
+                output.push(format!(
+                    "op mul {mf}tmp {} {mf}stack_sz",
+                    int.push_entry_size
+                ));
+                output.push(format!("op add {mf}stack_sz {mf}stack_sz 1"));
+                output.push(format!("op add @counter {} {mf}tmp", int.push_table_start));
                 output.push(format!("set @counter {}", target));
             }
             BackendParams::External(ext) => {
-                output.push("op add MF_acc @counter 3".to_string());
-                output.push(format!("write MF_acc {} MF_stack_sz", ext.cell_name));
-                output.push("op add MF_stack_sz MF_stack_sz 1".to_string());
+                // Return to just after the jump to the target.
+                let access = ir.backend().cell_access_size();
+                output.push(format!("op add {mf}acc @counter {}", 2 + access));
+                ext.write(mf, format!("{mf}acc"), format!("{mf}stack_sz"), output)?;
+                output.push(format!("op add {mf}stack_sz {mf}stack_sz 1"));
                 output.push(format!("set @counter {}", target));
             }
         }
@@ -68,14 +79,14 @@ impl Operation for CallProcOp {
 /// `CallProcOp`.
 ///
 /// Destroys: `MF_acc` `MF_tmp` `MF_resume`
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RetProcOp {}
 
 impl Operation for RetProcOp {
     fn code_size(&self, backend: Backend) -> AddressDelta {
         match backend {
             Backend::Internal => 5,
-            Backend::External => 2,
+            Backend::External | Backend::Banked => 1 + backend.cell_access_size(),
         }
         .into()
     }
@@ -87,21 +98,25 @@ impl Operation for RetProcOp {
         annotated: Option<&mut Vec<String>>,
         _instruction_count: &mut Address,
     ) -> Result<()> {
+        let mf = ir.variable_prefix.as_str();
         if let Some(annotated) = annotated {
             annotated.push(format!("// Ret @{}", output.len()));
         }
 
         match ir.backend_params() {
             BackendParams::Internal(int) => {
-                output.push("op sub MF_stack_sz MF_stack_sz 1".to_string());
-                output.push("op add MF_resume @counter 2".to_string());
-                output.push(format!("op mul MF_tmp {} MF_stack_sz", int.pop_entry_size));
-                output.push(format!("op add @counter {} MF_tmp", int.pop_table_start));
-                output.push(format!("set @counter MF_acc"));
+                output.push(format!("op sub {mf}stack_sz {mf}stack_sz 1"));
+                output.push(format!("op add {mf}resume @counter 2"));
+                output.push(format!(
+                    "op mul {mf}tmp {} {mf}stack_sz",
+                    int.pop_entry_size
+                ));
+                output.push(format!("op add @counter {} {mf}tmp", int.pop_table_start));
+                output.push(format!("set @counter {mf}acc"));
             }
             BackendParams::External(ext) => {
-                output.push("op sub MF_stack_sz MF_stack_sz 1".to_string());
-                output.push(format!("read @counter {} MF_stack_sz", ext.cell_name));
+                output.push(format!("op sub {mf}stack_sz {mf}stack_sz 1"));
+                ext.read(mf, "@counter", format!("{mf}stack_sz"), output)?;
             }
         }
 
@@ -113,14 +128,17 @@ impl Operation for RetProcOp {
 ///
 /// Destroys: `MF_tmp` `MF_resume`
 /// Preserves: `MF_acc`
-#[derive(Clone, Debug)]
-pub struct PushOp {}
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PushOp {
+    pub stack: StackRef,
+}
 
 impl Operation for PushOp {
     fn code_size(&self, backend: Backend) -> AddressDelta {
+        let backend = self.stack.backend(backend);
         match backend {
-            Backend::Internal => 3,
-            Backend::External => 2,
+            Backend::Internal => 4,
+            Backend::External | Backend::Banked => 1 + backend.cell_access_size(),
         }
         .into()
     }
@@ -132,19 +150,25 @@ impl Operation for PushOp {
         annotated: Option<&mut Vec<String>>,
         _instruction_count: &mut Address,
     ) -> Result<()> {
+        let mf = ir.variable_prefix.as_str();
         if let Some(annotated) = annotated {
-            annotated.push(format!("// Push @{}", output.len()));
+            annotated.push(format!("// Push{} @{}", self.stack, output.len()));
         }
 
-        match ir.backend_params() {
+        let size_var = self.stack.size_var(&ir.variable_prefix);
+        match ir.stack_params(&self.stack)? {
             BackendParams::Internal(int) => {
-                output.push("op add MF_resume @counter 2".to_string());
-                output.push(format!("op mul MF_tmp {} MF_stack_sz", int.push_entry_size));
-                output.push(format!("op add @counter {} MF_tmp", int.push_table_start));
+                output.push(format!("op add {mf}resume @counter 3"));
+                output.push(format!(
+                    "op mul {mf}tmp {} {}",
+                    int.push_entry_size, size_var
+                ));
+                output.push(format!("op add {} {} 1", size_var, size_var));
+                output.push(format!("op add @counter {} {mf}tmp", int.push_table_start));
             }
             BackendParams::External(ext) => {
-                output.push(format!("write MF_acc {} MF_stack_sz", ext.cell_name));
-                output.push("op add MF_stack_sz MF_stack_sz 1".to_string());
+                ext.write(mf, format!("{mf}acc"), &size_var, output)?;
+                output.push(format!("op add {} {} 1", size_var, size_var));
             }
         }
 
@@ -152,18 +176,76 @@ impl Operation for PushOp {
     }
 }
 
+/// Jumps to the stack overflow handler for `function` if pushing `entries`
+/// more values would overflow `stack`. Inserted before push-like ops by
+/// `debug stack_guard`.
+///
+/// Preserves: All
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StackGuardOp {
+    pub stack: StackRef,
+
+    // Number of entries the guarded op pushes.
+    pub entries: usize,
+
+    // The function the guarded op is in, to report on overflow.
+    pub function: Option<FunctionName>,
+}
+
+impl Operation for StackGuardOp {
+    fn code_size(&self, _backend: Backend) -> AddressDelta {
+        1.into()
+    }
+
+    fn generate(
+        &self,
+        ir: &IntermediateRepresentation,
+        output: &mut Vec<String>,
+        annotated: Option<&mut Vec<String>>,
+        _instruction_count: &mut Address,
+    ) -> Result<()> {
+        if let Some(annotated) = annotated {
+            annotated.push(format!(
+                "// StackGuard{} {} @{}",
+                self.stack,
+                self.entries,
+                output.len()
+            ));
+        }
+
+        let handler = ir.debug_handler(DebugTrap::StackOverflow, &self.function)?;
+
+        // The stack pointer may be at most `end - entries` before the push.
+        let (base, len) = ir.stack_bounds(&self.stack)?;
+        match (base + len).checked_sub(self.entries) {
+            Some(limit) => output.push(format!(
+                "jump {} greaterThan {} {}",
+                handler,
+                self.stack.size_var(&ir.variable_prefix),
+                limit
+            )),
+            None => output.push(format!("jump {} always x false", handler)),
+        }
+
+        Ok(())
+    }
+}
+
 /// Pops the top of the stack into `MF_acc`.
 ///
 /// Destroys: `MF_tmp` `MF_resume`
 /// Returns: `MF_acc`
-#[derive(Clone, Debug)]
-pub struct PopOp {}
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PopOp {
+    pub stack: StackRef,
+}
 
 impl Operation for PopOp {
     fn code_size(&self, backend: Backend) -> AddressDelta {
+        let backend = self.stack.backend(backend);
         match backend {
             Backend::Internal => 4,
-            Backend::External => 2,
+            Backend::External | Backend::Banked => 1 + backend.cell_access_size(),
         }
         .into()
     }
@@ -175,20 +257,25 @@ impl Operation for PopOp {
         annotated: Option<&mut Vec<String>>,
         _instruction_count: &mut Address,
     ) -> Result<()> {
+        let mf = ir.variable_prefix.as_str();
         if let Some(annotated) = annotated {
-            annotated.push(format!("// Pop @{}", output.len()));
+            annotated.push(format!("// Pop{} @{}", self.stack, output.len()));
         }
 
-        match ir.backend_params() {
+        let size_var = self.stack.size_var(&ir.variable_prefix);
+        match ir.stack_params(&self.stack)? {
             BackendParams::Internal(int) => {
-                output.push("op sub MF_stack_sz MF_stack_sz 1".to_string());
-                output.push("op add MF_resume @counter 2".to_string());
-                output.push(format!("op mul MF_tmp {} MF_stack_sz", int.pop_entry_size));
-                output.push(format!("op add @counter {} MF_tmp", int.pop_table_start));
+                output.push(format!("op sub {} {} 1", size_var, size_var));
+                output.push(format!("op add {mf}resume @counter 2"));
+                output.push(format!(
+                    "op mul {mf}tmp {} {}",
+                    int.pop_entry_size, size_var
+                ));
+                output.push(format!("op add @counter {} {mf}tmp", int.pop_table_start));
             }
             BackendParams::External(ext) => {
-                output.push("op sub MF_stack_sz MF_stack_sz 1".to_string());
-                output.push(format!("read MF_acc {} MF_stack_sz", ext.cell_name));
+                output.push(format!("op sub {} {} 1", size_var, size_var));
+                ext.read(mf, format!("{mf}acc"), &size_var, output)?;
             }
         }
 
@@ -201,18 +288,20 @@ impl Operation for PopOp {
 ///
 /// Destroys: `MF_tmp` `MF_resume`
 /// Returns: `MF_acc`
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PeekOp {
     pub depth: MindustryTerm,
+    pub stack: StackRef,
 }
 
 impl Operation for PeekOp {
     fn code_size(&self, backend: Backend) -> AddressDelta {
-        match (backend, self.depth.as_ref().parse::<usize>()) {
-            (Backend::Internal, Ok(..)) => 4,
-            (Backend::Internal, Err(..)) => 5,
-            (Backend::External, Ok(..)) => 2,
-            (Backend::External, Err(..)) => 3,
+        let backend = self.stack.backend(backend);
+        match (backend, self.depth.as_integer()) {
+            (Backend::Internal, Some(..)) => 4,
+            (Backend::Internal, None) => 5,
+            (Backend::External | Backend::Banked, Some(..)) => 1 + backend.cell_access_size(),
+            (Backend::External | Backend::Banked, None) => 2 + backend.cell_access_size(),
         }
         .into()
     }
@@ -224,29 +313,40 @@ impl Operation for PeekOp {
         annotated: Option<&mut Vec<String>>,
         _instruction_count: &mut Address,
     ) -> Result<()> {
+        let mf = ir.variable_prefix.as_str();
         if let Some(annotated) = annotated {
-            annotated.push(format!("// Peek depth {} @{}", self.depth, output.len()));
+            annotated.push(format!(
+                "// Peek depth {}{} @{}",
+                self.depth,
+                self.stack,
+                output.len()
+            ));
         }
 
-        match self.depth.as_ref().parse::<usize>() {
-            Ok(literal_number) => {
-                output.push(format!("op sub MF_tmp MF_stack_sz {}", literal_number + 1));
+        let size_var = self.stack.size_var(&ir.variable_prefix);
+        match self.depth.as_integer() {
+            Some(literal_number) => {
+                output.push(format!(
+                    "op sub {mf}tmp {} {}",
+                    size_var,
+                    literal_number + 1
+                ));
             }
-            Err(..) => {
-                output.push(format!("op sub MF_tmp MF_stack_sz {}", self.depth));
-                output.push(format!("op sub MF_tmp MF_tmp {}", 1));
+            None => {
+                output.push(format!("op sub {mf}tmp {} {}", size_var, self.depth));
+                output.push(format!("op sub {mf}tmp {mf}tmp {}", 1));
             }
         }
 
-        match ir.backend_params() {
+        match ir.stack_params(&self.stack)? {
             BackendParams::Internal(int) => {
                 // Not an error -- peek and pop use the same table.
-                output.push("op add MF_resume @counter 2".to_string());
-                output.push(format!("op mul MF_tmp {} MF_tmp", int.pop_entry_size));
-                output.push(format!("op add @counter {} MF_tmp", int.pop_table_start));
+                output.push(format!("op add {mf}resume @counter 2"));
+                output.push(format!("op mul {mf}tmp {} {mf}tmp", int.pop_entry_size));
+                output.push(format!("op add @counter {} {mf}tmp", int.pop_table_start));
             }
             BackendParams::External(ext) => {
-                output.push(format!("read MF_acc {} MF_tmp", ext.cell_name));
+                ext.read(mf, format!("{mf}acc"), format!("{mf}tmp"), output)?;
             }
         }
 
@@ -258,18 +358,20 @@ impl Operation for PeekOp {
 /// `depth=0` will use the top of the stack.
 ///
 /// Destroys: `MF_tmp` `MF_resume`
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PokeOp {
     pub depth: MindustryTerm,
+    pub stack: StackRef,
 }
 
 impl Operation for PokeOp {
     fn code_size(&self, backend: Backend) -> AddressDelta {
-        match (backend, self.depth.as_ref().parse::<usize>()) {
-            (Backend::Internal, Ok(..)) => 4,
-            (Backend::Internal, Err(..)) => 5,
-            (Backend::External, Ok(..)) => 2,
-            (Backend::External, Err(..)) => 3,
+        let backend = self.stack.backend(backend);
+        match (backend, self.depth.as_integer()) {
+            (Backend::Internal, Some(..)) => 4,
+            (Backend::Internal, None) => 5,
+            (Backend::External | Backend::Banked, Some(..)) => 1 + backend.cell_access_size(),
+            (Backend::External | Backend::Banked, None) => 2 + backend.cell_access_size(),
         }
         .into()
     }
@@ -281,28 +383,39 @@ impl Operation for PokeOp {
         annotated: Option<&mut Vec<String>>,
         _instruction_count: &mut Address,
     ) -> Result<()> {
+        let mf = ir.variable_prefix.as_str();
         if let Some(annotated) = annotated {
-            annotated.push(format!("// Poke depth {} @{}", self.depth, output.len()));
+            annotated.push(format!(
+                "// Poke depth {}{} @{}",
+                self.depth,
+                self.stack,
+                output.len()
+            ));
         }
 
-        match self.depth.as_ref().parse::<usize>() {
-            Ok(literal_number) => {
-                output.push(format!("op sub MF_tmp MF_stack_sz {}", literal_number + 1));
+        let size_var = self.stack.size_var(&ir.variable_prefix);
+        match self.depth.as_integer() {
+            Some(literal_number) => {
+                output.push(format!(
+                    "op sub {mf}tmp {} {}",
+                    size_var,
+                    literal_number + 1
+                ));
             }
-            Err(..) => {
-                output.push(format!("op sub MF_tmp MF_stack_sz {}", self.depth));
-                output.push(format!("op sub MF_tmp MF_tmp {}", 1));
+            None => {
+                output.push(format!("op sub {mf}tmp {} {}", size_var, self.depth));
+                output.push(format!("op sub {mf}tmp {mf}tmp {}", 1));
             }
         }
 
-        match ir.backend_params() {
+        match ir.stack_params(&self.stack)? {
             BackendParams::Internal(int) => {
-                output.push("op add MF_resume @counter 2".to_string());
-                output.push(format!("op mul MF_tmp {} MF_tmp", int.poke_entry_size));
-                output.push(format!("op add @counter {} MF_tmp", int.poke_table_start));
+                output.push(format!("op add {mf}resume @counter 2"));
+                output.push(format!("op mul {mf}tmp {} {mf}tmp", int.poke_entry_size));
+                output.push(format!("op add @counter {} {mf}tmp", int.poke_table_start));
             }
             BackendParams::External(ext) => {
-                output.push(format!("write MF_acc {} MF_tmp", ext.cell_name));
+                ext.write(mf, format!("{mf}acc"), format!("{mf}tmp"), output)?;
             }
         }
 
@@ -313,10 +426,10 @@ impl Operation for PokeOp {
 /// Sets `dest` to `source`.
 ///
 /// Preserves: All
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SetOp {
-    source: MindustryTerm,
-    dest: MindustryTerm,
+    pub source: MindustryTerm,
+    pub dest: MindustryTerm,
 }
 
 impl SetOp {
@@ -355,7 +468,7 @@ impl Operation for SetOp {
 /// Defines a label that may be used with `JumpOp` and `CallProcOp`.
 ///
 /// Preserves: All
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LabelOp {
     pub target: LabelName,
 }
@@ -380,12 +493,40 @@ impl Operation for LabelOp {
     }
 }
 
+/// Marks the end of one iteration of the work a program repeats, for `compiler
+/// bench` to count. Generates no code; the lap is counted each time the
+/// instruction that follows runs.
+///
+/// Preserves: All
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BenchmarkLapOp {}
+
+impl Operation for BenchmarkLapOp {
+    fn code_size(&self, _backend: Backend) -> AddressDelta {
+        0.into()
+    }
+
+    fn generate(
+        &self,
+        _ir: &IntermediateRepresentation,
+        output: &mut Vec<String>,
+        annotated: Option<&mut Vec<String>>,
+        _instruction_count: &mut Address,
+    ) -> Result<()> {
+        if let Some(annotated) = annotated {
+            annotated.push(format!("// BenchmarkLap @{}", output.len()));
+        }
+
+        Ok(())
+    }
+}
+
 /// Jumps to the specified label. This is identical to Mindustry's built-in
 /// jump, except that a label is specified for the first argument instead of the
 /// line number.
 ///
 /// Preserves: All
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct JumpOp {
     pub target: LabelName,
     pub condition: Condition,
@@ -412,11 +553,15 @@ impl Operation for JumpOp {
             ));
         }
 
-        output.push(format!(
-            "jump {} {}",
-            ir.labels()[&self.target],
-            self.condition,
-        ));
+        let target = ir.labels().get(&self.target).ok_or_else(|| {
+            CompileError::undefined(
+                SymbolKind::Label,
+                &self.target,
+                ir.labels().keys().map(LabelName::unscoped),
+            )
+        })?;
+
+        output.push(format!("jump {} {}", target, self.condition));
 
         Ok(())
     }
@@ -425,9 +570,9 @@ impl Operation for JumpOp {
 /// Does a built-in operation as per Mindustry `op`.
 ///
 /// Preserves: All
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MathOp {
-    pub operation: Rc<String>,
+    pub operation: Symbol,
     pub dest: MindustryTerm,
     pub arg1: MindustryTerm,
     pub arg2: MindustryTerm,
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::*;
+
+pub type BlockId = usize;
+
+/// The control-flow graph of a program: its ops split into basic blocks, with
+/// an edge wherever control may pass from one block to another.
+///
+/// Control flow is known exactly at the IR level, since every jump, if, loop,
+/// and call names where it goes. The exceptions are returns, whose target is
+/// only known at runtime, and raw instructions that write `@counter`.
+///
+/// Jumps to debug handlers are left out, since the handlers never come back.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+
+    /// The block each function begins with.
+    pub functions: HashMap<FunctionName, BlockId>,
+}
+
+/// A run of ops that is only entered at the top and only left at the bottom.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    /// Indices in `ops` of the ops in the block.
+    pub ops: Range<usize>,
+
+    /// The address of the first instruction, and the one just past the last.
+    pub start: Address,
+    pub end: Address,
+
+    pub edges: Vec<Edge>,
+
+    /// How control leaves the block other than along its edges, if it does.
+    pub exit: Option<Exit>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Edge {
+    pub target: BlockId,
+    pub kind: EdgeKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// On to the next block.
+    Fallthrough,
+
+    /// A jump, if, loop, `break`, or `continue`. Unless unconditional, the
+    /// block also has a fallthrough edge.
+    Jump,
+
+    /// Into a function or `callproc` target. The block also has a fallthrough
+    /// edge, which is where the call returns to.
+    Call,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exit {
+    /// `end`, or running off the end of the program. Mindustry starts over
+    /// from the top.
+    End,
+
+    /// `stop`, which halts the processor.
+    Stop,
+
+    /// `return` or `ret`, back to the caller.
+    Return,
+
+    /// A raw instruction that sets `@counter` or jumps outside the program,
+    /// which we can't follow.
+    Unknown,
+}
+
+/// Where control may go after a single op.
+#[derive(Default)]
+struct Flow {
+    jump: Option<Address>,
+    call: Option<Address>,
+    falls_through: bool,
+    exit: Option<Exit>,
+}
+
+impl Flow {
+    fn next() -> Flow {
+        Flow {
+            falls_through: true,
+            ..Default::default()
+        }
+    }
+
+    fn jump(target: Address, condition: &Condition) -> Flow {
+        Flow {
+            jump: Some(target),
+            falls_through: !condition.is_always(),
+            ..Default::default()
+        }
+    }
+
+    fn call(target: Address) -> Flow {
+        Flow {
+            call: Some(target),
+            falls_through: true,
+            ..Default::default()
+        }
+    }
+
+    fn exit(exit: Exit) -> Flow {
+        Flow {
+            exit: Some(exit),
+            ..Default::default()
+        }
+    }
+
+    fn branches(&self) -> bool {
+        self.jump.is_some() || self.call.is_some() || !self.falls_through
+    }
+}
+
+impl Cfg {
+    pub fn new(ir: &IntermediateRepresentation) -> Result<Cfg> {
+        let ops = ir.ops();
+        let mut addresses = Vec::with_capacity(ops.len() + 1);
+        let mut address: Address = 0.into();
+        for op in ops.iter() {
+            addresses.push(address);
+            address += op.code_size(*ir.backend());
+        }
+        addresses.push(address);
+
+        // Zero-sized ops share an address with what follows them, so a jump
+        // lands on the first op at its target.
+        let mut op_at: HashMap<Address, usize> = HashMap::default();
+        for (j, address) in addresses[..ops.len()].iter().enumerate() {
+            op_at.entry(*address).or_insert(j);
+        }
+
+        let flows = ops
+            .iter()
+            .map(|op| flow(ir, op))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut leaders = vec![false; ops.len() + 1];
+        leaders[0] = true;
+        leaders[ops.len()] = true;
+        for (j, (op, flow)) in ops.iter().zip(flows.iter()).enumerate() {
+            if flow.branches() {
+                leaders[j + 1] = true;
+            }
+            if let IrOp::Function(..) = op {
+                leaders[j] = true;
+            }
+            for target in flow.jump.iter().chain(flow.call.iter()) {
+                if let Some(k) = op_at.get(target) {
+                    leaders[*k] = true;
+                }
+            }
+        }
+
+        let starts: Vec<usize> = (0..ops.len()).filter(|j| leaders[*j]).collect();
+        let mut block_of = Vec::with_capacity(ops.len());
+        for j in 0..ops.len() {
+            block_of.push(starts.iter().filter(|start| **start <= j).count() - 1);
+        }
+        let block_at = |address: &Address| op_at.get(address).map(|j| block_of[*j]);
+
+        let mut blocks = Vec::with_capacity(starts.len());
+        for (id, start) in starts.iter().enumerate() {
+            let end = starts.get(id + 1).copied().unwrap_or(ops.len());
+            let flow = &flows[end - 1];
+
+            let mut edges = Vec::default();
+            let mut exit = flow.exit;
+            let mut edge = |target: Option<BlockId>, kind| match target {
+                Some(target) => edges.push(Edge { target, kind }),
+                None => exit = Some(Exit::Unknown),
+            };
+            if let Some(target) = flow.jump.as_ref() {
+                edge(block_at(target), EdgeKind::Jump);
+            }
+            if let Some(target) = flow.call.as_ref() {
+                edge(block_at(target), EdgeKind::Call);
+            }
+            if flow.falls_through {
+                match starts.get(id + 1) {
+                    Some(..) => edge(Some(id + 1), EdgeKind::Fallthrough),
+                    None => exit = Some(Exit::End),
+                }
+            }
+
+            blocks.push(BasicBlock {
+                ops: *start..end,
+                start: addresses[*start],
+                end: addresses[end],
+                edges,
+                exit,
+            });
+        }
+
+        let functions = ops
+            .iter()
+            .enumerate()
+            .filter_map(|(j, op)| match op {
+                IrOp::Function(name, ..) => Some((name.clone(), block_of[j])),
+                _ => None,
+            })
+            .collect();
+
+        Ok(Cfg { blocks, functions })
+    }
+
+    /// The block that execution begins in. There is none if the program is
+    /// empty.
+    pub fn entry(&self) -> Option<BlockId> {
+        Some(0).filter(|_| !self.blocks.is_empty())
+    }
+
+    /// The block holding the instruction at `address`.
+    pub fn block_at(&self, address: Address) -> Option<BlockId> {
+        self.blocks
+            .iter()
+            .position(|block| block.start <= address && address < block.end)
+    }
+
+    pub fn successors(&self, id: BlockId) -> impl Iterator<Item = BlockId> + '_ {
+        self.blocks[id].edges.iter().map(|edge| edge.target)
+    }
+
+    pub fn predecessors(&self, id: BlockId) -> Vec<BlockId> {
+        (0..self.blocks.len())
+            .filter(|from| self.successors(*from).any(|to| to == id))
+            .collect()
+    }
+
+    /// Which blocks can be reached from the start of the program, following
+    /// calls into functions.
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut stack: Vec<BlockId> = self.entry().into_iter().collect();
+        while let Some(id) = stack.pop() {
+            if !std::mem::replace(&mut reachable[id], true) {
+                stack.extend(self.successors(id));
+            }
+        }
+        reachable
+    }
+}
+
+fn flow(ir: &IntermediateRepresentation, op: &IrOp) -> Result<Flow> {
+    let loop_op = |index: &IrIndex| &ir.ops()[**index];
+    Ok(match op {
+        IrOp::Jump(op) => {
+            let target = ir.labels().get(&op.target).ok_or_else(|| {
+                CompileError::undefined(
+                    SymbolKind::Label,
+                    &op.target,
+                    ir.labels().keys().map(LabelName::unscoped),
+                )
+            })?;
+            Flow::jump(*target, &op.condition)
+        }
+        IrOp::CallProc(op) => {
+            let target = ir.labels().get(&op.target).ok_or_else(|| {
+                CompileError::undefined(
+                    SymbolKind::Label,
+                    &op.target,
+                    ir.labels().keys().map(LabelName::unscoped),
+                )
+            })?;
+            Flow::call(*target)
+        }
+        IrOp::Call(op) => {
+            let target = ir
+                .functions()
+                .get(&op.target_function)
+                .and_then(|func| func.address)
+                .ok_or_else(|| {
+                    CompileError::undefined(
+                        SymbolKind::Function,
+                        &op.target_function,
+                        ir.functions().keys(),
+                    )
+                })?;
+            Flow::call(target)
+        }
+        IrOp::If(op) => Flow {
+            jump: Some(op.end_address()?),
+            falls_through: true,
+            ..Default::default()
+        },
+        IrOp::Else(op) => Flow::jump(
+            op.end.context("Internal error: Forward refeerence")?,
+            &Condition::always(),
+        ),
+        IrOp::While(op) => {
+            let (target, condition) = op.entry_jump()?;
+            Flow::jump(target, &condition)
+        }
+        IrOp::LoopEnd(op) => {
+            let (target, condition) = op.jump();
+            Flow::jump(target, condition)
+        }
+        IrOp::Break(op) => match loop_end(loop_op(&op.index)) {
+            Some(end) => Flow::jump(end?, &Condition::always()),
+            None => bail!("Internal error: break outside a loop"),
+        },
+        IrOp::Continue(op) => match loop_condition(loop_op(&op.index)) {
+            Some(condition) => Flow::jump(condition?, &Condition::always()),
+            None => bail!("Internal error: continue outside a loop"),
+        },
+        IrOp::BusyWait(op) => Flow::jump(op.start, &op.condition),
+        IrOp::Return(..) | IrOp::RetProc(..) => Flow::exit(Exit::Return),
+        IrOp::Set(SetOp { dest, .. }) | IrOp::Math(MathOp { dest, .. })
+            if dest.to_string() == "@counter" =>
+        {
+            Flow::exit(Exit::Unknown)
+        }
+        IrOp::MindustryCommand(op) => command_flow(&op.command.to_string()),
+        _ => Flow::next(),
+    })
+}
+
+/// The flow of a raw Mindustry instruction, such as from an `asm` block.
+fn command_flow(command: &str) -> Flow {
+    let tok: Vec<&str> = command.split_whitespace().collect();
+    match tok.as_slice() {
+        ["end"] => Flow::exit(Exit::End),
+        ["stop"] => Flow::exit(Exit::Stop),
+        ["jump", target, rest @ ..] => match target.parse::<usize>() {
+            Ok(target) => Flow {
+                jump: Some(target.into()),
+                falls_through: rest.first() != Some(&"always"),
+                ..Default::default()
+            },
+            Err(..) => Flow::exit(Exit::Unknown),
+        },
+        ["op", _, "@counter", ..] | [_, "@counter", ..] => Flow::exit(Exit::Unknown),
+        _ => Flow::next(),
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::*;
+
+/// A run of source lines that can never execute.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeadCode {
+    pub first_line: usize,
+    pub last_line: usize,
+    pub reason: DeadCodeReason,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeadCodeReason {
+    /// The code follows `instruction` at `line`, which never falls through,
+    /// and nothing reachable jumps to it.
+    After {
+        #[serde(deserialize_with = "deserialize_instruction")]
+        instruction: InstructionName,
+        line: usize,
+    },
+
+    /// The body of a function that no reachable code calls.
+    Uncalled(FunctionName),
+}
+
+impl std::fmt::Display for DeadCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.first_line == self.last_line {
+            write!(f, "line {} ", self.first_line + 1)?;
+        } else {
+            write!(f, "lines {}-{} ", self.first_line + 1, self.last_line + 1)?;
+        }
+
+        match &self.reason {
+            DeadCodeReason::After { instruction, line } => {
+                write!(f, "(after `{}` at line {})", instruction, line + 1)
+            }
+            DeadCodeReason::Uncalled(name) => write!(f, "(function {} is never called)", name),
+        }
+    }
+}
+
+/// Removes code that can never execute: the blocks of the control-flow graph
+/// that can't be reached from the start of the program, which includes any
+/// function that no reachable code calls. Each piece removed is listed in
+/// `IntermediateRepresentation::dead_code`.
+///
+/// With `only_functions`, only functions go, and calls from other dead code
+/// still count. Nothing is removed if the program has an instruction that
+/// writes `@counter`, since control could then go anywhere.
+pub struct DeadCodeElimination {
+    pub only_functions: bool,
+}
+
+impl IrPass for DeadCodeElimination {
+    fn name(&self) -> &'static str {
+        "dead-code"
+    }
+
+    fn run(&self, ir: &mut IntermediateRepresentation) -> Result<Changed> {
+        let cfg = ir.cfg()?;
+        let reached = if self.only_functions {
+            called_blocks(ir, &cfg)
+        } else {
+            cfg.reachable()
+        };
+
+        let unknown = cfg
+            .blocks
+            .iter()
+            .zip(reached.iter())
+            .any(|(block, reached)| *reached && block.exit == Some(Exit::Unknown));
+        if unknown {
+            log::debug!("keeping dead code, since control flow is not fully known");
+            return Ok(Changed::No);
+        }
+
+        let mut remove = vec![false; ir.ops().len()];
+        for (block, reached) in cfg.blocks.iter().zip(reached) {
+            if !reached {
+                remove[block.ops.clone()]
+                    .iter_mut()
+                    .for_each(|dead| *dead = true);
+            }
+        }
+
+        // A break or continue finds where to go through its loop, so the loop
+        // stays if they do.
+        for (j, op) in ir.ops().iter().enumerate() {
+            if let IrOp::Break(BreakOp { index }) | IrOp::Continue(ContinueOp { index }) = op {
+                if !remove[j] {
+                    remove[**index] = false;
+                }
+            }
+        }
+
+        if !remove.contains(&true) {
+            return Ok(Changed::No);
+        }
+
+        let dead_code = describe_dead_code(ir, &remove);
+        let uncalled: HashSet<FunctionName> = ir
+            .ops()
+            .iter()
+            .zip(remove.iter())
+            .filter_map(|(op, removed)| match op {
+                IrOp::Function(name, ..) if *removed => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        log::debug!(
+            "removing {} dead ops, including {} functions",
+            remove.iter().filter(|removed| **removed).count(),
+            uncalled.len()
+        );
+
+        ir.remove_ops(&remove)?;
+        ir.functions.retain(|name, _| !uncalled.contains(name));
+        ir.unused = find_unused_symbols(&ir.ops, &ir.op_lines);
+        ir.dead_code.extend(dead_code);
+        ir.dead_code.sort_by_key(|dead| dead.first_line);
+        Ok(Changed::Yes)
+    }
+}
+
+/// Which blocks are outside any function, or in a function that code
+/// outside any function calls, however indirectly. Calls from dead code
+/// count, as does falling or jumping into a function.
+fn called_blocks(ir: &IntermediateRepresentation, cfg: &Cfg) -> Vec<bool> {
+    let mut spans: Vec<(Address, Address, &FunctionName)> = ir
+        .functions()
+        .values()
+        .filter_map(|function| Some((function.address?, function.end?, &function.name)))
+        .collect();
+    spans.sort();
+
+    let owners: Vec<Option<&FunctionName>> = cfg
+        .blocks
+        .iter()
+        .map(|block| {
+            let k = spans.partition_point(|(start, ..)| *start <= block.start);
+            k.checked_sub(1)
+                .map(|k| spans[k])
+                .filter(|(_, end, _)| block.start < *end)
+                .map(|(.., name)| name)
+        })
+        .collect();
+
+    let mut blocks: HashMap<Option<&FunctionName>, Vec<&BasicBlock>> = HashMap::default();
+    for (block, owner) in cfg.blocks.iter().zip(owners.iter()) {
+        blocks.entry(*owner).or_default().push(block);
+    }
+
+    let mut called = HashSet::new();
+    let mut pending = vec![None];
+    while let Some(owner) = pending.pop() {
+        for block in blocks.get(&owner).into_iter().flatten() {
+            for edge in block.edges.iter() {
+                if let Some(callee) = owners[edge.target] {
+                    if called.insert(callee) {
+                        pending.push(Some(callee));
+                    }
+                }
+            }
+        }
+    }
+
+    owners
+        .iter()
+        .map(|owner| owner.is_none_or(|owner| called.contains(owner)))
+        .collect()
+}
+
+/// The source lines of the ops `remove` marks, in runs. A run beginning with
+/// a function is reported as uncalled up to the end of the function, and
+/// anything else as following the last instruction kept before it.
+fn describe_dead_code(ir: &IntermediateRepresentation, remove: &[bool]) -> Vec<DeadCode> {
+    let addresses = ir.op_addresses();
+    let mut dead_code = Vec::default();
+    let mut j = 0;
+    while j < remove.len() {
+        if !remove[j] {
+            j += 1;
+            continue;
+        }
+
+        let (reason, end, last_line) = match &ir.ops()[j] {
+            IrOp::Function(name, ..) => {
+                let function = &ir.functions()[name];
+                (
+                    DeadCodeReason::Uncalled(name.clone()),
+                    function.end,
+                    function.end_line,
+                )
+            }
+            _ => (dead_code_after(ir, remove, j), None, None),
+        };
+
+        let start = j;
+        j += 1;
+        while j < remove.len()
+            && remove[j]
+            && !matches!(ir.ops()[j], IrOp::Function(..))
+            && end.is_none_or(|end| addresses[j] < end)
+        {
+            j += 1;
+        }
+
+        let mut lines = ir.op_lines[start..j].iter().flatten().copied();
+        if let Some(first_line) = lines.next() {
+            let last_line = lines.chain(last_line).max().unwrap_or(first_line);
+            dead_code.push(DeadCode {
+                first_line,
+                last_line,
+                reason,
+            });
+        }
+    }
+    dead_code
+}
+
+/// Why the op at `j` is dead, when it isn't the start of an uncalled
+/// function: the last op kept before it never falls through.
+fn dead_code_after(ir: &IntermediateRepresentation, remove: &[bool], j: usize) -> DeadCodeReason {
+    let ops = ir.ops();
+    let before = (0..j)
+        .rev()
+        .find(|k| !remove[*k] && ops[*k].code_size(*ir.backend()) != 0.into());
+    let instruction = match before.map(|k| &ops[k]) {
+        Some(IrOp::Return(..)) => "return",
+        Some(IrOp::Break(..)) => "break",
+        Some(IrOp::Continue(..)) => "continue",
+        Some(IrOp::RetProc(..)) => "ret",
+        Some(IrOp::LoopEnd(..)) => "loop",
+        Some(IrOp::Else(..)) => "else",
+        Some(IrOp::MindustryCommand(op)) => match op.command.to_string().as_str() {
+            "end" => "end",
+            "stop" => "stop",
+            _ => "jump",
+        },
+        _ => "jump",
+    };
+    DeadCodeReason::After {
+        instruction,
+        line: before.and_then(|k| ir.op_lines[k]).unwrap_or_default(),
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 
 use crate::*;
@@ -20,7 +20,7 @@ use crate::*;
 /// such as "jump always", but without full control flow analysis it seems
 /// sufficient to simply place function definitions at the end of the program
 /// after `end`.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct FunctionOp {
     // Function name. Must be unique.
     pub name: FunctionName,
@@ -38,17 +38,32 @@ pub struct FunctionOp {
     // the stack size using `stack_var_depth`.
     pub locals: HashMap<StackVar, FrameIndex>,
 
+    // Labels defined in the function body. These are only visible inside the
+    // function, and shadow any global label of the same name there.
+    #[serde(serialize_with = "serialize_sorted")]
+    pub labels: HashSet<LabelName>,
+
     // The offset in instructions of the function body. Set later, hence option.
     pub address: Option<Address>,
+
+    // The offset just past the end of the function body. Set once its closing
+    // brace is parsed.
+    pub end: Option<Address>,
+
+    // The source line of the closing brace.
+    pub end_line: Option<usize>,
+
+    // For each return value, the stack variable returned in that position by
+    // every `return` in the function, if there is one. Such values are left
+    // in the frame for the caller to read, rather than copied to `MF_ret<n>`.
+    // `None` until a `return` is seen.
+    pub frame_returns: Option<Vec<Option<StackVar>>>,
 }
 
 impl FunctionOp {
     pub fn stack_var_depth(&self, name: &StackVar) -> Result<StackDepth> {
-        let offset = self.locals.get(name).with_context(|| {
-            format!(
-                "innermost function definition does not have let variable named '{}'",
-                name
-            )
+        let offset = self.locals.get(name).ok_or_else(|| {
+            CompileError::undefined(SymbolKind::StackVar, name, self.locals.keys())
         })?;
 
         // Position relative to stack size. Consider that the 0th
@@ -109,12 +124,44 @@ impl FunctionOp {
             args,
             returns,
             locals,
+            labels: HashSet::default(),
             address: None,
+            end: None,
+            end_line: None,
+            frame_returns: None,
         };
 
         Ok(f)
     }
 
+    /// Notes the values of a `return` in the function. See `frame_return`.
+    pub fn note_return(&mut self, value_names: &[&str]) {
+        let values = value_names
+            .iter()
+            .map(|value| StackVar::try_from(*value).ok());
+        self.frame_returns = Some(match self.frame_returns.take() {
+            None => values.collect(),
+            Some(prev) if prev.len() == value_names.len() => prev
+                .into_iter()
+                .zip(values)
+                .map(|(prev, value)| prev.filter(|prev| Some(prev) == value.as_ref()))
+                .collect(),
+            Some(..) => vec![None; value_names.len()],
+        });
+    }
+
+    /// Where the caller finds return value `j` after the function returns, as
+    /// an offset from the stack pointer, if the function leaves it in its
+    /// frame rather than copying it to `MF_ret<j>`. That's the case if every
+    /// `return` in the function returns the same stack variable there.
+    pub fn frame_return(&self, j: usize, canary: bool) -> Option<usize> {
+        let var = self.frame_returns.as_ref()?.get(j)?.as_ref()?;
+        let index: usize = (*self.locals.get(var)?).into();
+
+        // Above the return address, and the canary below it if any.
+        Some(canary as usize + 1 + index)
+    }
+
     pub fn start_parse(&mut self, address: Address) {
         let set = self.address.replace(address);
         assert!(set.is_none());
@@ -160,7 +207,7 @@ impl Operation for FunctionOp {
 /// `return 5 7 v1 *v2`
 ///
 /// Destroys: `MF_acc` `MF_tmp` `MF_resume`
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ReturnOp {
     // Name of the function this is a return from.
     pub function: FunctionName,
@@ -168,12 +215,20 @@ pub struct ReturnOp {
     // The values being returned.
     pub values: Vec<Term>,
 
+    // Whether to check the canary below the frame before returning.
+    pub canary: bool,
+
     pub size: AddressDelta,
 }
 
 // FIXME: Can probably re-arrange stack math to use fewer instructions.
 impl ReturnOp {
-    pub fn new(function: &FunctionOp, value_names: &[&str], backend: Backend) -> Result<ReturnOp> {
+    pub fn new(
+        function: &FunctionOp,
+        value_names: &[&str],
+        backend: Backend,
+        canary: bool,
+    ) -> Result<ReturnOp> {
         let mut total = 0;
         let mut values = Vec::with_capacity(value_names.len());
 
@@ -189,9 +244,10 @@ impl ReturnOp {
             let value =
                 Term::try_from(value).with_context(|| format!("return value {} '{}'", j, value))?;
             total += match &value {
+                Term::StackVar(..) if function.frame_return(j, canary).is_some() => 0,
                 Term::StackVar(..) => match backend {
                     Backend::Internal => 5,
-                    Backend::External => 2,
+                    Backend::External | Backend::Banked => 1 + backend.cell_access_size(),
                 },
                 Term::Mindustry(..) => 1,
             };
@@ -201,15 +257,18 @@ impl ReturnOp {
         // Remove locals and return address from the stack.
         total += 1;
 
-        // Pop return address and return.
-        total += match backend {
-            Backend::Internal => 4,
-            Backend::External => 1,
+        // Pop return address and return, checking the canary first if enabled.
+        total += match (backend, canary) {
+            (Backend::Internal, false) => 4,
+            (Backend::Internal, true) => 9,
+            (Backend::External | Backend::Banked, false) => backend.cell_access_size(),
+            (Backend::External | Backend::Banked, true) => 2 + 2 * backend.cell_access_size(),
         };
 
         Ok(ReturnOp {
             function: function.name.clone(),
             values,
+            canary,
             size: total.into(),
         })
     }
@@ -227,6 +286,7 @@ impl Operation for ReturnOp {
         annotated: Option<&mut Vec<String>>,
         _instruction_count: &mut Address,
     ) -> Result<()> {
+        let mf = ir.variable_prefix.as_str();
         if let Some(annotated) = annotated {
             annotated.push(format_return_annotation(self, output.len()));
         }
@@ -243,46 +303,107 @@ impl Operation for ReturnOp {
 
         for (j, arg) in self.values.iter().enumerate() {
             match arg {
+                // The caller reads it from the frame.
+                Term::StackVar(..) if function.frame_return(j, self.canary).is_some() => {}
                 Term::StackVar(arg) => {
                     let depth = function.stack_var_depth(&arg)?;
 
                     match ir.backend_params() {
                         BackendParams::Internal(int) => {
-                            output.push("op add MF_resume @counter 3".to_string());
-                            output.push(format!("op sub MF_tmp MF_stack_sz {}", depth));
-                            output.push(format!("op mul MF_tmp {} MF_tmp", int.pop_entry_size));
-                            output.push(format!("op add @counter {} MF_tmp", int.pop_table_start));
+                            output.push(format!("op add {mf}resume @counter 3"));
+                            output.push(format!("op sub {mf}tmp {mf}stack_sz {}", depth));
+                            output.push(format!("op mul {mf}tmp {} {mf}tmp", int.pop_entry_size));
+                            output.push(format!("op add @counter {} {mf}tmp", int.pop_table_start));
 
-                            output.push(format!("set MF_ret{} MF_acc", j));
+                            output.push(format!("set {mf}ret{} {mf}acc", j));
                         }
                         BackendParams::External(ext) => {
-                            output.push(format!("op sub MF_tmp MF_stack_sz {}", depth));
-                            output.push(format!("read MF_ret{} {} MF_tmp", j, ext.cell_name));
+                            output.push(format!("op sub {mf}tmp {mf}stack_sz {}", depth));
+                            ext.read(mf, format!("{mf}ret{}", j), format!("{mf}tmp"), output)?;
                         }
                     }
                 }
                 Term::Mindustry(..) => {
-                    output.push(format!("set MF_ret{} {}", j, arg));
+                    output.push(format!("set {mf}ret{} {}", j, arg));
                 }
             }
         }
 
+        if self.canary {
+            return self.generate_canary_check(ir, function, output);
+        }
+
         // Remove locals and return address from the stack.
         output.push(format!(
-            "op sub MF_stack_sz MF_stack_sz {}",
+            "op sub {mf}stack_sz {mf}stack_sz {}",
             1 + function.locals.len()
         ));
 
         match ir.backend_params() {
             BackendParams::Internal(int) => {
                 // Same as `Ret`, except that we roll in the sub to stack size as above.
-                output.push("op add MF_resume @counter 2".to_string());
-                output.push(format!("op mul MF_tmp {} MF_stack_sz", int.pop_entry_size));
-                output.push(format!("op add @counter {} MF_tmp", int.pop_table_start));
-                output.push(format!("set @counter MF_acc"));
+                output.push(format!("op add {mf}resume @counter 2"));
+                output.push(format!(
+                    "op mul {mf}tmp {} {mf}stack_sz",
+                    int.pop_entry_size
+                ));
+                output.push(format!("op add @counter {} {mf}tmp", int.pop_table_start));
+                output.push(format!("set @counter {mf}acc"));
+            }
+            BackendParams::External(ext) => {
+                ext.read(mf, "@counter", format!("{mf}stack_sz"), output)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ReturnOp {
+    /// Removes the frame and canary from the stack, jumping to the corruption
+    /// handler if the canary was overwritten, and otherwise returns.
+    fn generate_canary_check(
+        &self,
+        ir: &IntermediateRepresentation,
+        function: &FunctionOp,
+        output: &mut Vec<String>,
+    ) -> Result<()> {
+        let mf = ir.variable_prefix.as_str();
+        let handler = ir.debug_handler(DebugTrap::StackCorruption, &Some(self.function.clone()))?;
+
+        // Leaves the stack size at the canary, with the return address above.
+        output.push(format!(
+            "op sub {mf}stack_sz {mf}stack_sz {}",
+            2 + function.locals.len()
+        ));
+
+        match ir.backend_params() {
+            BackendParams::Internal(int) => {
+                output.push(format!("op add {mf}resume @counter 2"));
+                output.push(format!(
+                    "op mul {mf}tmp {} {mf}stack_sz",
+                    int.pop_entry_size
+                ));
+                output.push(format!("op add @counter {} {mf}tmp", int.pop_table_start));
+                output.push(format!(
+                    "jump {} notEqual {mf}acc {}",
+                    handler, STACK_CANARY
+                ));
+
+                output.push(format!("op add {mf}resume @counter 3"));
+                output.push(format!("op add {mf}tmp {mf}stack_sz 1"));
+                output.push(format!("op mul {mf}tmp {} {mf}tmp", int.pop_entry_size));
+                output.push(format!("op add @counter {} {mf}tmp", int.pop_table_start));
+                output.push(format!("set @counter {mf}acc"));
             }
             BackendParams::External(ext) => {
-                output.push(format!("read @counter {} MF_stack_sz", ext.cell_name));
+                ext.read(mf, format!("{mf}tmp"), format!("{mf}stack_sz"), output)?;
+                output.push(format!(
+                    "jump {} notEqual {mf}tmp {}",
+                    handler, STACK_CANARY
+                ));
+                output.push(format!("op add {mf}tmp {mf}stack_sz 1"));
+                ext.read(mf, "@counter", format!("{mf}tmp"), output)?;
             }
         }
 
@@ -297,7 +418,7 @@ impl Operation for ReturnOp {
 /// e.g.: `call foobar "hello" *a b -> ret1 *ret2`
 ///
 /// Destroys: All
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CallOp {
     // The name of the function this call is being made from, if in one. Used to
     // access stack variables, which may be used when a call is made within a
@@ -312,6 +433,9 @@ pub struct CallOp {
     pub args: Vec<Term>,
     pub returns: Vec<Term>,
 
+    // Whether to push a canary under the frame for `ReturnOp` to check.
+    pub canary: bool,
+
     // The number of instructions up to and including the actual jump to the
     // target function entry point.
     pub before_call_size: AddressDelta,
@@ -324,65 +448,170 @@ impl CallOp {
     pub fn new(
         args: Vec<Term>,
         returns: Vec<Term>,
-        target_function_num_locals: usize,
-        target_function: FunctionName,
+        target_function: &FunctionOp,
         call_site_function: Option<FunctionName>,
         backend: Backend,
+        canary: bool,
     ) -> CallOp {
         // Size before (and including) the actual call.
         let mut before_call_size = 0.into();
 
+        // Find the table offset of the stack pointer, which all the pushes
+        // are relative to, then reserve the whole frame.
+        if let Backend::Internal = backend {
+            before_call_size += 2.into();
+        }
+
+        if canary {
+            before_call_size += Self::canary_size(backend);
+        }
+
         // Push return address
         before_call_size += match backend {
-            Backend::Internal => 4,
-            Backend::External => 3,
+            Backend::Internal => 3,
+            Backend::External | Backend::Banked => 2 + backend.cell_access_size(),
         }
         .into();
 
         for arg in args.iter() {
             before_call_size += match (backend, arg) {
-                (Backend::Internal, Term::StackVar(..)) => 7,
-                (Backend::Internal, Term::Mindustry(..)) => 4,
-                (Backend::External, Term::StackVar(..)) => 4,
-                (Backend::External, Term::Mindustry(..)) => 2,
+                (Backend::Internal, Term::StackVar(..)) => 4,
+                (Backend::Internal, Term::Mindustry(..)) => 3,
+                (Backend::External | Backend::Banked, Term::StackVar(..)) => {
+                    2 + 2 * backend.cell_access_size()
+                }
+                (Backend::External | Backend::Banked, Term::Mindustry(..)) => {
+                    1 + backend.cell_access_size()
+                }
             }
             .into();
         }
 
-        // Extra local variables (other than args) must increase stack pointer.
-        if target_function_num_locals != args.len() {
+        // Jump to function entry point, unless the final push returns there
+        // directly, as it does with an internal stack, which has reserved the
+        // extra local variables (other than args) already. With an external
+        // stack, reserving them replaces the final push's increment, counted
+        // above.
+        if !matches!(backend, Backend::Internal) {
             before_call_size += 1.into();
         }
 
-        // Jump to function entry point
-        before_call_size += 1.into();
-
         // Total size including the code after the call to process return
         // variables.
         let mut total_size = before_call_size;
 
-        for arg in returns.iter() {
+        for (j, arg) in returns.iter().enumerate() {
+            let in_frame = target_function.frame_return(j, canary).is_some();
             total_size += match (backend, arg) {
+                // Read from the frame, then poke.
+                (Backend::Internal, Term::StackVar(..)) if in_frame => 8,
+                (Backend::Internal, Term::Mindustry(..)) if in_frame => 5,
+                (Backend::External | Backend::Banked, Term::StackVar(..)) if in_frame => {
+                    2 + 2 * backend.cell_access_size()
+                }
+                (Backend::External | Backend::Banked, Term::Mindustry(..)) if in_frame => {
+                    1 + backend.cell_access_size()
+                }
                 (Backend::Internal, Term::StackVar(..)) => 5,
                 (Backend::Internal, Term::Mindustry(..)) => 1,
-                (Backend::External, Term::StackVar(..)) => 2,
-                (Backend::External, Term::Mindustry(..)) => 1,
+                (Backend::External | Backend::Banked, Term::StackVar(..)) => {
+                    1 + backend.cell_access_size()
+                }
+                (Backend::External | Backend::Banked, Term::Mindustry(..)) => 1,
             }
             .into();
         }
 
         CallOp {
-            target_function,
+            target_function: target_function.name.clone(),
             call_site_function,
             args,
             returns,
+            canary,
             before_call_size,
             total_size,
         }
     }
+
+    /// Copies a return value the function left in its frame, `offset` above
+    /// the stack pointer, straight to its binding.
+    fn generate_frame_return(
+        &self,
+        ir: &IntermediateRepresentation,
+        offset: usize,
+        binding: &Term,
+        output: &mut Vec<String>,
+    ) -> Result<()> {
+        let mf = ir.variable_prefix.as_str();
+        let dest = match binding {
+            Term::StackVar(..) => format!("{mf}acc"),
+            Term::Mindustry(binding) => binding.to_string(),
+        };
+
+        match ir.backend_params() {
+            BackendParams::Internal(int) => {
+                output.push(format!("op add {mf}resume @counter 3"));
+                output.push(format!("op add {mf}tmp {mf}stack_sz {}", offset));
+                output.push(format!("op mul {mf}tmp {} {mf}tmp", int.pop_entry_size));
+                output.push(format!("op add @counter {} {mf}tmp", int.pop_table_start));
+                if let Term::Mindustry(..) = binding {
+                    output.push(format!("set {} {mf}acc", dest));
+                }
+            }
+            BackendParams::External(ext) => {
+                output.push(format!("op add {mf}tmp {mf}stack_sz {}", offset));
+                ext.read(mf, &dest, format!("{mf}tmp"), output)?;
+            }
+        }
+
+        if let Term::StackVar(binding) = binding {
+            let call_site_function = self
+                .call_site_function
+                .as_ref()
+                .context("Internal error: Forward refeerence")?;
+            let depth = ir.functions()[call_site_function].stack_var_depth(binding)?;
+
+            match ir.backend_params() {
+                BackendParams::Internal(int) => {
+                    output.push(format!("op add {mf}resume @counter 3"));
+                    output.push(format!("op sub {mf}tmp {mf}stack_sz {}", depth));
+                    output.push(format!("op mul {mf}tmp {} {mf}tmp", int.poke_entry_size));
+                    output.push(format!("op add @counter {} {mf}tmp", int.poke_table_start));
+                }
+                BackendParams::External(ext) => {
+                    output.push(format!("op sub {mf}tmp {mf}stack_sz {}", depth));
+                    ext.write(mf, format!("{mf}acc"), format!("{mf}tmp"), output)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn canary_size(backend: Backend) -> AddressDelta {
+        match backend {
+            Backend::Internal => 3,
+            Backend::External | Backend::Banked => 1 + backend.cell_access_size(),
+        }
+        .into()
+    }
+}
+
+/// Jumps to the entry `index` places above the stack pointer the call began
+/// with, in the internal stack table starting at `table_start`, given that
+/// `MF_tmp` holds that stack pointer's offset into the tables. The push, poke,
+/// and pop tables are interleaved, so they share an entry size and offset.
+fn call_dispatch(mf: &str, int: &InternalParams, table_start: Address, index: isize) -> String {
+    let entry_size: usize = int.push_entry_size.into();
+    let table_start: usize = table_start.into();
+    let target = table_start as isize + index * entry_size as isize;
+    if target < 0 {
+        format!("op sub @counter {mf}tmp {}", -target)
+    } else {
+        format!("op add @counter {mf}tmp {}", target)
+    }
 }
 
-// FIXME: Can probably re-arrange stack math to use fewer instructions.
 impl Operation for CallOp {
     fn code_size(&self, _backend: Backend) -> AddressDelta {
         self.total_size.into()
@@ -395,6 +624,7 @@ impl Operation for CallOp {
         annotated: Option<&mut Vec<String>>,
         _instruction_count: &mut Address,
     ) -> Result<()> {
+        let mf = ir.variable_prefix.as_str();
         if let Some(annotated) = annotated {
             annotated.push(format_arrow_annotation(
                 "// Call",
@@ -407,7 +637,14 @@ impl Operation for CallOp {
 
         let func = match ir.functions().get(&self.target_function) {
             Some(func) => func,
-            None => bail!("function {} is not found", &self.target_function),
+            None => {
+                return Err(CompileError::undefined(
+                    SymbolKind::Function,
+                    &self.target_function,
+                    ir.functions().keys(),
+                )
+                .into())
+            }
         };
 
         if self.returns.len() != func.returns.len() {
@@ -428,27 +665,80 @@ impl Operation for CallOp {
             );
         }
 
+        let entry = func.address.context("Internal error: Forward reference")?;
+
+        // Reserve room on the stack for any stack variables in addition to the
+        // args. An internal stack reserves the whole frame up front, as its
+        // pushes leave the stack size alone, so the final push goes straight
+        // to the function entry point. An external stack must reserve these
+        // after the final push, folding its increment into the reservation.
+        let internal = matches!(ir.backend_params(), BackendParams::Internal(..));
+        let additional = func.locals.len() - func.args.len();
+        let mut pushes_left = 1 + self.args.len();
+        let mut next_push = || {
+            pushes_left -= 1;
+            let last = pushes_left == 0;
+            let resume = if last && (internal || additional == 0) {
+                format!("set {mf}resume {}", entry)
+            } else {
+                format!("op add {mf}resume @counter 1")
+            };
+            let increment = if last && additional > 0 {
+                None
+            } else {
+                Some(format!("op add {mf}stack_sz {mf}stack_sz 1"))
+            };
+            (resume, increment)
+        };
+
+        // With an internal stack, MF_tmp holds the stack pointer's offset into
+        // the tables throughout, so each push and peek is a single jump
+        // relative to it, to the entry `pushed` places above it.
+        let start = output.len();
+        let mut pushed = 0;
+        if let BackendParams::Internal(int) = ir.backend_params() {
+            output.push(format!(
+                "op mul {mf}tmp {} {mf}stack_sz",
+                int.push_entry_size
+            ));
+            let frame = self.canary as usize + func.locals.len() + 1;
+            output.push(format!("op add {mf}stack_sz {mf}stack_sz {}", frame));
+        }
+
+        // The canary goes below the frame, so that a function that writes past
+        // its own frame overwrites it.
+        if self.canary {
+            match ir.backend_params() {
+                BackendParams::Internal(int) => {
+                    output.push(format!("set {mf}acc {}", STACK_CANARY));
+                    output.push(format!("op add {mf}resume @counter 1"));
+                    output.push(call_dispatch(mf, int, int.push_table_start, pushed));
+                }
+                BackendParams::External(ext) => {
+                    ext.write(mf, STACK_CANARY, format!("{mf}stack_sz"), output)?;
+                    output.push(format!("op add {mf}stack_sz {mf}stack_sz 1"));
+                }
+            }
+            pushed += 1;
+        }
+
         // Push the return address. This is the cleanup code after
         // the call site.
+        let return_offset = self.before_call_size - (output.len() - start + 1).into();
+        let (resume, increment) = next_push();
         match ir.backend_params() {
             BackendParams::Internal(int) => {
-                output.push(format!(
-                    "op add MF_acc @counter {}",
-                    self.before_call_size - 1.into()
-                ));
-                output.push("op add MF_resume @counter 2".to_string());
-                output.push(format!("op mul MF_tmp {} MF_stack_sz", int.push_entry_size));
-                output.push(format!("op add @counter {} MF_tmp", int.push_table_start));
+                output.push(format!("op add {mf}acc @counter {}", return_offset));
+                output.push(resume);
+                output.push(call_dispatch(mf, int, int.push_table_start, pushed));
             }
             BackendParams::External(ext) => {
-                output.push(format!(
-                    "op add MF_acc @counter {}",
-                    self.before_call_size - 1.into()
-                ));
-                output.push(format!("write MF_acc {} MF_stack_sz", ext.cell_name));
-                output.push("op add MF_stack_sz MF_stack_sz 1".to_string());
+                output.push(format!("op add {mf}acc @counter {}", return_offset));
+                ext.write(mf, format!("{mf}acc"), format!("{mf}stack_sz"), output)?;
+                output.extend(increment);
             }
         }
+        pushed += 1;
 
         for (j, arg) in self.args.iter().enumerate() {
             match arg {
@@ -458,78 +748,78 @@ impl Operation for CallOp {
                         .as_ref()
                         .context("Internal error: forward reference")?;
                     let depth = ir.functions()[call_site_function].stack_var_depth(&arg)?;
-
-                    // We have been pushing to the stack, so the value
-                    // we target is being pushed down (we don't use a
-                    // frame pointer, so this is all relative to the
-                    // stack size).
-                    let mut depth: usize = depth.into();
-                    depth += j + 1;
+                    let depth: usize = depth.into();
 
                     // Peek then push.
+                    let (resume, increment) = next_push();
                     match ir.backend_params() {
                         BackendParams::Internal(int) => {
-                            output.push("op add MF_resume @counter 3".to_string());
-                            output.push(format!("op sub MF_tmp MF_stack_sz {}", depth));
-                            output.push(format!("op mul MF_tmp {} MF_tmp", int.pop_entry_size));
-                            output.push(format!("op add @counter {} MF_tmp", int.pop_table_start));
-
-                            output.push("op add MF_resume @counter 2".to_string());
-                            output
-                                .push(format!("op mul MF_tmp {} MF_stack_sz", int.push_entry_size));
-                            output.push(format!("op add @counter {} MF_tmp", int.push_table_start));
+                            output.push(format!("op add {mf}resume @counter 1"));
+                            output.push(call_dispatch(
+                                mf,
+                                int,
+                                int.pop_table_start,
+                                -(depth as isize),
+                            ));
+
+                            output.push(resume);
+                            output.push(call_dispatch(mf, int, int.push_table_start, pushed));
                         }
                         BackendParams::External(ext) => {
-                            output.push(format!("op sub MF_tmp MF_stack_sz {}", depth));
-                            output.push(format!("read MF_acc {} MF_tmp", ext.cell_name));
-                            output.push(format!("write MF_acc {} MF_stack_sz", ext.cell_name));
-                            output.push("op add MF_stack_sz MF_stack_sz 1".to_string());
+                            // We have been pushing to the stack, so the value
+                            // we target is being pushed down (we don't use a
+                            // frame pointer, so this is all relative to the
+                            // stack size).
+                            let depth = depth + j + 1 + self.canary as usize;
+                            output.push(format!("op sub {mf}tmp {mf}stack_sz {}", depth));
+                            ext.read(mf, format!("{mf}acc"), format!("{mf}tmp"), output)?;
+                            ext.write(mf, format!("{mf}acc"), format!("{mf}stack_sz"), output)?;
+                            output.extend(increment);
                         }
                     }
                 }
-                Term::Mindustry(..) => match ir.backend_params() {
-                    BackendParams::Internal(int) => {
-                        output.push(format!("set MF_acc {}", arg));
-                        output.push("op add MF_resume @counter 2".to_string());
-                        output.push(format!("op mul MF_tmp {} MF_stack_sz", int.push_entry_size));
-                        output.push(format!("op add @counter {} MF_tmp", int.push_table_start));
-                    }
-                    BackendParams::External(ext) => {
-                        output.push(format!("write {} {} MF_stack_sz", arg, ext.cell_name));
-                        output.push("op add MF_stack_sz MF_stack_sz 1".to_string());
+                Term::Mindustry(..) => {
+                    let (resume, increment) = next_push();
+                    match ir.backend_params() {
+                        BackendParams::Internal(int) => {
+                            output.push(format!("set {mf}acc {}", arg));
+                            output.push(resume);
+                            output.push(call_dispatch(mf, int, int.push_table_start, pushed));
+                        }
+                        BackendParams::External(ext) => {
+                            ext.write(mf, arg, format!("{mf}stack_sz"), output)?;
+                            output.extend(increment);
+                        }
                     }
-                },
+                }
             }
+            pushed += 1;
         }
 
-        // Reserve room on the stack for any stack variables in
-        // addition to the args.
-        let additional = func.locals.len() - func.args.len();
-        if additional > 0 {
-            output.push(format!("op add MF_stack_sz MF_stack_sz {}", additional));
+        // Jump to the function entry point, unless the final push did.
+        if !internal {
+            if additional > 0 {
+                output.push(format!(
+                    "op add {mf}stack_sz {mf}stack_sz {}",
+                    additional + 1
+                ));
+            }
+            output.push(format!("jump {} always x false", entry));
         }
 
-        // Jump to the function entry point.
-        // Optimization: The final push above could jump directly to
-        // the destination.
-        output.push(format!(
-            "jump {} always x false",
-            func.address
-                .context("Internal error: Forward reference")?
-                .as_ref()
-        ));
-
         // The function's Return should have popped the args and
         // return address off the stack, and placed the return args
-        // into MF_ret<n>.
+        // into MF_ret<n>, except those it leaves in its frame, which we
+        // read from just above the stack pointer.
         //
         // Now we need to map the returned args into the destination
         // requested.
-        //
-        // NOTE: We could do a direct stack-to-stack transfer of return
-        // variables if we made global/local part of the function's call
-        // signature rather than having everything go through MF_ret.
         for (j, arg) in self.returns.iter().enumerate() {
+            if let Some(offset) = func.frame_return(j, self.canary) {
+                self.generate_frame_return(ir, offset, arg, output)?;
+                continue;
+            }
+
             match arg {
                 Term::StackVar(arg) => {
                     let call_site_function = self
@@ -540,20 +830,21 @@ impl Operation for CallOp {
 
                     match ir.backend_params() {
                         BackendParams::Internal(int) => {
-                            output.push("op add MF_resume @counter 4".to_string());
-                            output.push(format!("set MF_acc MF_ret{}", j));
-                            output.push(format!("op sub MF_tmp MF_stack_sz {}", depth));
-                            output.push(format!("op mul MF_tmp {} MF_tmp", int.poke_entry_size));
-                            output.push(format!("op add @counter {} MF_tmp", int.poke_table_start));
+                            output.push(format!("op add {mf}resume @counter 4"));
+                            output.push(format!("set {mf}acc {mf}ret{}", j));
+                            output.push(format!("op sub {mf}tmp {mf}stack_sz {}", depth));
+                            output.push(format!("op mul {mf}tmp {} {mf}tmp", int.poke_entry_size));
+                            output
+                                .push(format!("op add @counter {} {mf}tmp", int.poke_table_start));
                         }
                         BackendParams::External(ext) => {
-                            output.push(format!("op sub MF_tmp MF_stack_sz {}", depth));
-                            output.push(format!("write MF_ret{} {} MF_tmp", j, ext.cell_name));
+                            output.push(format!("op sub {mf}tmp {mf}stack_sz {}", depth));
+                            ext.write(mf, format!("{mf}ret{}", j), format!("{mf}tmp"), output)?;
                         }
                     }
                 }
                 Term::Mindustry(..) => {
-                    output.push(format!("set {} MF_ret{}", arg, j));
+                    output.push(format!("set {} {mf}ret{}", arg, j));
                 }
             }
         }
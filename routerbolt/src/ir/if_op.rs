@@ -56,7 +56,7 @@ use crate::*;
 ///   - Without else: `IfOp` ... `}`
 ///
 /// Preserves: All if no stack vars are used in the condition, otherwise None.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct IfOp {
     condition: Condition,
 
@@ -75,6 +75,22 @@ impl IfOp {
         let set = self.end.replace(end);
         assert!(set.is_none());
     }
+
+    pub fn condition(&self) -> &Condition {
+        &self.condition
+    }
+
+    /// Where the check jumps when the condition fails.
+    pub fn end_address(&self) -> Result<Address> {
+        self.end.context("Internal error: Forward refeerence")
+    }
+
+    /// Moves the address the op jumps to. See
+    /// `IntermediateRepresentation::relocate`.
+    pub fn relocate(&mut self, relocate: &dyn Fn(Address) -> Result<Address>) -> Result<()> {
+        self.end = self.end.map(relocate).transpose()?;
+        Ok(())
+    }
 }
 
 impl Operation for IfOp {
@@ -92,10 +108,7 @@ impl Operation for IfOp {
         annotated: Option<&mut Vec<String>>,
         instruction_count: &mut Address,
     ) -> Result<()> {
-        let end = *self
-            .end
-            .context("Internal error: Forward refeerence")?
-            .as_ref();
+        let end = *self.end_address()?.as_ref();
         if let Some(annotated) = annotated {
             annotated.push(format!("// If: {} @{}", &self.condition, output.len()));
         }
@@ -114,7 +127,7 @@ impl Operation for IfOp {
 /// The "else" in an if statement. See `IfOp` for more.
 ///
 /// Preserves: All if no stack vars are used in the condition, otherwise None.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ElseOp {
     // The first address after the end of the "else" "block".
     pub end: Option<Address>,
@@ -124,6 +137,13 @@ impl ElseOp {
     pub fn declare() -> ElseOp {
         ElseOp { end: None }
     }
+
+    /// Moves the address the op jumps to. See
+    /// `IntermediateRepresentation::relocate`.
+    pub fn relocate(&mut self, relocate: &dyn Fn(Address) -> Result<Address>) -> Result<()> {
+        self.end = self.end.map(relocate).transpose()?;
+        Ok(())
+    }
 }
 
 impl Operation for ElseOp {
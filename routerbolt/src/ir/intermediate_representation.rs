@@ -1,39 +1,162 @@
 use std::collections::HashMap;
-
-use std::rc::Rc;
+use std::convert::TryFrom;
 
 use crate::*;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum StackConfig {
     Internal(usize),
-    External(Rc<String>),
+    External(ExternalParams),
+}
+
+impl StackConfig {
+    pub fn backend(&self) -> Backend {
+        match self {
+            StackConfig::Internal(..) => Backend::Internal,
+            StackConfig::External(ext) if ext.is_banked() => Backend::Banked,
+            StackConfig::External(..) => Backend::External,
+        }
+    }
+
+    /// The initial value of the stack pointer.
+    pub fn base(&self) -> MindustryTerm {
+        match self {
+            StackConfig::Internal(..) => MindustryTerm::zero(),
+            StackConfig::External(ext) => {
+                MindustryTerm::try_from(ext.offset.to_string().as_str()).unwrap()
+            }
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct IntermediateRepresentation {
     pub ops: Vec<IrOp>,
+
+    // The source line each op came from, if any. The setup code at the start
+    // of the program has none.
+    pub op_lines: Vec<Option<usize>>,
+
+    // The text of each source line, for the annotated output.
+    pub source_lines: Vec<String>,
     pub stack_config: StackConfig,
+    pub named_stacks: Vec<NamedStack>,
+    pub stack_usage: StackUsage,
+
+    // Code left out of the program because it can never execute. Only
+    // filled in when dead code elimination is enabled.
+    pub dead_code: Vec<DeadCode>,
+
+    // Instructions saved by the peephole optimizer, if enabled.
+    pub peephole_saved: usize,
+
+    // Definitions the program never uses, reported as warnings.
+    pub unused: Vec<UnusedSymbol>,
+
+    // Generation fails if the program is longer than this.
+    pub instruction_limit: Option<usize>,
+
+    // What the compiler's own variables start with, in place of `MF_`.
+    pub variable_prefix: String,
+
+    // How the program ends, and the address just past the stack setup, which
+    // `Epilogue::Loop` jumps back to.
+    pub epilogue: Epilogue,
+    pub program_start: Address,
+    pub debug: DebugOptions,
+
+    // Address of the handler for each enabled debug check, for each function
+    // and for code outside any function.
+    #[serde(with = "map_as_pairs")]
+    pub debug_handlers: HashMap<(DebugTrap, Option<FunctionName>), Address>,
     pub labels: HashMap<LabelName, Address>,
-    pub functions: HashMap<FunctionName, Rc<FunctionOp>>,
+    pub functions: HashMap<FunctionName, Shared<FunctionOp>>,
     pub backend: Backend,
     pub backend_params: BackendParams,
 }
 
 impl IntermediateRepresentation {
-    pub fn parse(text: &str) -> Result<IntermediateRepresentation> {
+    pub fn parse(text: &str) -> CompileResult<IntermediateRepresentation> {
         parser::parse(text)
     }
 
-    pub fn generate(&self) -> Result<(Vec<String>, Vec<String>)> {
+    pub fn parse_with_options(
+        text: &str,
+        options: &parser::CompileOptions,
+    ) -> CompileResult<IntermediateRepresentation> {
+        parser::parse_with_options(text, options)
+    }
+
+    pub fn generate(&self) -> CompileResult<(Vec<String>, Vec<String>)> {
         generate(self)
     }
 
+    pub fn generate_with_stats(&self) -> CompileResult<(Vec<String>, Vec<String>, CodeStats)> {
+        generate_with_stats(self)
+    }
+
+    pub fn generate_with_hook(
+        &self,
+        hook: &mut dyn CodegenHook,
+    ) -> CompileResult<(Vec<String>, Vec<String>, CodeStats)> {
+        generate_with_hook(self, hook)
+    }
+
+    pub fn generate_json(&self) -> Result<String> {
+        generate_json(self)
+    }
+
+    /// The program with symbolic jump targets. See `SymbolicProgram`.
+    pub fn generate_symbolic(&self) -> Result<SymbolicProgram> {
+        let (output, _) = generate(self)?;
+        Ok(SymbolicProgram::new(self, &output))
+    }
+
+    /// Variables calls may overwrite before they're read, as the `clobbered`
+    /// warning reports. This takes a liveness analysis of the whole program,
+    /// so is only done when asked for.
+    pub fn clobbered(&self) -> Vec<ClobberedVariable> {
+        find_clobbered_variables(self)
+    }
+
+    /// The warnings and notes for the program, less those suppressed by
+    /// `#allow(...)`. See `find_warnings`.
+    pub fn warnings(&self) -> Vec<Diagnostic> {
+        find_warnings(self)
+    }
+
+    /// The control-flow graph of the program. See `Cfg`.
+    pub fn cfg(&self) -> Result<Cfg> {
+        Cfg::new(self)
+    }
+
+    /// The IR itself as JSON, for debugging and for other tools. Unlike
+    /// `generate_json`, this describes the program before code generation.
+    /// Maps are written with their keys in order, so the output is stable.
+    pub fn to_json(&self) -> Result<String> {
+        let value = serde_json::to_value(self)?;
+        Ok(serde_json::to_string_pretty(&value)?)
+    }
+
+    /// Reads back the output of `to_json`. The ops are taken as they are,
+    /// without the checks the parser makes.
+    pub fn from_json(text: &str) -> Result<IntermediateRepresentation> {
+        Ok(serde_json::from_str(text)?)
+    }
+
+    pub fn source_map(&self) -> SourceMap {
+        SourceMap::new(self)
+    }
+
+    pub fn symbol_file(&self) -> SymbolFile {
+        SymbolFile::new(self)
+    }
+
     pub fn ops(&self) -> &Vec<IrOp> {
         &self.ops
     }
 
-    pub fn functions(&self) -> &HashMap<FunctionName, Rc<FunctionOp>> {
+    pub fn functions(&self) -> &HashMap<FunctionName, Shared<FunctionOp>> {
         &self.functions
     }
 
@@ -48,6 +171,75 @@ impl IntermediateRepresentation {
     pub fn backend(&self) -> &Backend {
         &self.backend
     }
+
+    /// Where a failed debug check in `function` jumps to.
+    pub fn debug_handler(
+        &self,
+        trap: DebugTrap,
+        function: &Option<FunctionName>,
+    ) -> Result<Address> {
+        self.debug_handlers
+            .get(&(trap, function.clone()))
+            .copied()
+            .with_context(|| format!("Internal error: missing {} handler", trap))
+    }
+
+    /// The first address and number of entries of a stack. External stacks
+    /// only have a known size if configured with a `len`.
+    pub fn stack_bounds(&self, stack: &StackRef) -> Result<(usize, usize)> {
+        let config = match stack {
+            StackRef::Default => &self.stack_config,
+            StackRef::Named(name, _) => {
+                &self
+                    .named_stacks
+                    .iter()
+                    .find(|named| named.name == *name)
+                    .with_context(|| format!("stack {} is not defined", name))?
+                    .stack_config
+            }
+        };
+
+        match config {
+            StackConfig::Internal(size) => Ok((0, *size)),
+            StackConfig::External(ext) => {
+                let len = ext
+                    .len
+                    .with_context(|| format!("size of stack in {} is unknown", ext.cell_name))?;
+                Ok((ext.offset, len))
+            }
+        }
+    }
+
+    /// The memory cells the default and named stacks keep their entries in,
+    /// each once, to give the emulator.
+    pub fn stack_cells(&self) -> Vec<Symbol> {
+        let mut cells = Vec::default();
+        let configs = std::iter::once(&self.stack_config)
+            .chain(self.named_stacks.iter().map(|named| &named.stack_config));
+        for config in configs {
+            if let StackConfig::External(ext) = config {
+                for cell in std::iter::once(&ext.cell_name).chain(ext.more_cells.iter()) {
+                    if !cells.contains(cell) {
+                        cells.push(cell.clone());
+                    }
+                }
+            }
+        }
+        cells
+    }
+
+    /// The backend params of the stack used by a push, pop, peek, or poke.
+    pub fn stack_params(&self, stack: &StackRef) -> Result<&BackendParams> {
+        match stack {
+            StackRef::Default => Ok(&self.backend_params),
+            StackRef::Named(name, _) => self
+                .named_stacks
+                .iter()
+                .find(|named| named.name == *name)
+                .map(|named| &named.backend_params)
+                .with_context(|| format!("stack {} is not defined", name)),
+        }
+    }
 }
 
 /// Generates the IR to read `source` and write its value to `dest`, where
@@ -56,6 +248,7 @@ pub fn ir_copy_arg(
     dest: Term,
     source: Term,
     function: &Option<FunctionName>,
+    temps: &mut Temporaries,
 ) -> Result<IrSequence> {
     match (source, dest, function.as_ref()) {
         (Term::Mindustry(source), Term::Mindustry(dest), _) => {
@@ -63,6 +256,7 @@ pub fn ir_copy_arg(
         }
         (Term::StackVar(stack_source), Term::Mindustry(dest), Some(function)) => {
             let op = GetStackOp {
+                accumulator: temps.is_accumulator(&dest),
                 global: dest,
                 stack: stack_source,
                 function: function.clone(),
@@ -71,6 +265,7 @@ pub fn ir_copy_arg(
         }
         (Term::Mindustry(source), Term::StackVar(stack_dest), Some(function)) => {
             let op = SetStackOp {
+                accumulator: temps.is_accumulator(&source),
                 global: source,
                 stack: stack_dest,
                 function: function.clone(),
@@ -78,20 +273,23 @@ pub fn ir_copy_arg(
             Ok(IrOp::SetStack(op).into())
         }
         (Term::StackVar(stack_source), Term::StackVar(stack_dest), Some(function)) => {
-            let acc = MindustryTerm::accumulator();
+            let tmp = temps.allocate_last();
             let op1 = GetStackOp {
-                global: acc.clone(),
+                global: tmp.clone(),
                 stack: stack_source,
                 function: function.clone(),
+                accumulator: temps.is_accumulator(&tmp),
             };
             let op1 = IrOp::GetStack(op1);
 
             let op2 = SetStackOp {
-                global: acc,
+                global: tmp.clone(),
                 stack: stack_dest,
                 function: function.clone(),
+                accumulator: temps.is_accumulator(&tmp),
             };
             let op2 = IrOp::SetStack(op2);
+            temps.release(&tmp);
             Ok((op1, op2).into())
         }
         _ => {
@@ -105,6 +303,7 @@ pub fn ir_read_two_write_one(
     arg1: Term,
     arg2: Term,
     function: &Option<FunctionName>,
+    temps: &mut Temporaries,
 ) -> Result<(
     IrSequence,
     MindustryTerm,
@@ -113,13 +312,17 @@ pub fn ir_read_two_write_one(
     IrSequence,
 )> {
     let (read, arg1, arg2) = if arg1 == arg2 {
-        let (read, arg) = ir_read_one_arg(arg1, function)?;
+        let (read, arg) = ir_read_one_arg(arg1, function, temps)?;
         (read, arg.clone(), arg)
     } else {
-        ir_read_two_args(arg1, arg2, function)?
+        ir_read_two_args(arg1, arg2, function, temps)?
     };
 
-    let (dest, write) = ir_write_one(dest, function)?;
+    // Mindustry reads an instruction's arguments before writing its result, so
+    // the result may reuse one of their temporaries.
+    temps.release(&arg1);
+    temps.release(&arg2);
+    let (dest, write) = ir_write_one(dest, function, temps)?;
 
     Ok((read, dest, arg1, arg2, write))
 }
@@ -127,17 +330,19 @@ pub fn ir_read_two_write_one(
 pub fn ir_write_one(
     dest: Term,
     function: &Option<FunctionName>,
+    temps: &mut Temporaries,
 ) -> Result<(MindustryTerm, IrSequence)> {
     match (dest, function.as_ref()) {
         (Term::Mindustry(dest), _) => Ok((dest, None.into())),
         (Term::StackVar(stack_dest), Some(function)) => {
-            let acc = MindustryTerm::accumulator();
+            let tmp = temps.allocate_last();
             let op = SetStackOp {
-                global: acc.clone(),
+                global: tmp.clone(),
                 stack: stack_dest,
                 function: function.clone(),
+                accumulator: temps.is_accumulator(&tmp),
             };
-            Ok((acc, IrOp::SetStack(op).into()))
+            Ok((tmp, IrOp::SetStack(op).into()))
         }
         _ => {
             bail!("Stack variables (start with *) may not be used outside a fuction");
@@ -149,15 +354,17 @@ pub fn ir_write_one(
 pub fn ir_read_one_arg(
     arg: Term,
     function: &Option<FunctionName>,
+    temps: &mut Temporaries,
 ) -> Result<(IrSequence, MindustryTerm)> {
     match (arg, function.as_ref()) {
         (Term::Mindustry(arg), _) => Ok((None.into(), arg)),
         (Term::StackVar(stack_arg), Some(function)) => {
-            let arg = MindustryTerm::accumulator();
+            let arg = temps.allocate_last();
             let op = GetStackOp {
                 global: arg.clone(),
                 stack: stack_arg,
                 function: function.clone(),
+                accumulator: temps.is_accumulator(&arg),
             };
             Ok((IrOp::GetStack(op).into(), arg))
         }
@@ -172,49 +379,54 @@ pub fn ir_read_two_args(
     arg1: Term,
     arg2: Term,
     function: &Option<FunctionName>,
+    temps: &mut Temporaries,
 ) -> Result<(IrSequence, MindustryTerm, MindustryTerm)> {
     if arg1 == arg2 {
-        let (seq, arg) = ir_read_one_arg(arg1, function)?;
+        let (seq, arg) = ir_read_one_arg(arg1, function, temps)?;
         return Ok((seq, arg.clone(), arg));
     }
 
     match (arg1, arg2, function.as_ref()) {
         (Term::Mindustry(arg1), Term::Mindustry(arg2), _) => Ok((None.into(), arg1, arg2)),
         (Term::StackVar(arg1s), Term::Mindustry(arg2), Some(function)) => {
-            let arg1 = MindustryTerm::accumulator();
+            let arg1 = temps.allocate_last();
             let op = GetStackOp {
                 global: arg1.clone(),
                 stack: arg1s,
                 function: function.clone(),
+                accumulator: temps.is_accumulator(&arg1),
             };
             Ok((IrOp::GetStack(op).into(), arg1, arg2))
         }
         (Term::Mindustry(arg1), Term::StackVar(arg2s), Some(function)) => {
-            let arg2 = MindustryTerm::accumulator();
+            let arg2 = temps.allocate_last();
             let op = GetStackOp {
                 global: arg2.clone(),
                 stack: arg2s,
                 function: function.clone(),
+                accumulator: temps.is_accumulator(&arg2),
             };
             Ok((IrOp::GetStack(op).into(), arg1, arg2))
         }
         (Term::StackVar(arg1s), Term::StackVar(arg2s), Some(function)) => {
-            let arg1 = MindustryTerm::stack_tmp();
-            let arg2 = MindustryTerm::accumulator();
+            // Careful -- on the internal backend `GetStackOp` goes through the
+            // accumulator, so only the second read may leave its value there.
+            let arg1 = temps.allocate();
+            let arg2 = temps.allocate_last();
 
             let op1 = GetStackOp {
                 global: arg1.clone(),
                 stack: arg1s,
                 function: function.clone(),
+                accumulator: temps.is_accumulator(&arg1),
             };
             let op1 = IrOp::GetStack(op1);
 
-            // Careful -- `GetStackOp` uses the accumulator, so the second op
-            // to be emitted must be the one that sets it.
             let op2 = GetStackOp {
                 global: arg2.clone(),
                 stack: arg2s,
                 function: function.clone(),
+                accumulator: temps.is_accumulator(&arg2),
             };
             let op2 = IrOp::GetStack(op2);
             Ok(((op1, op2).into(), arg1, arg2))
@@ -5,7 +5,7 @@ use crate::*;
 // Alternatively, create a "StackJump" struct that is more restrictive.
 /// As a rule, where used this will represent location-independent code without
 /// forward references.
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct IrSequence(pub Vec<IrOp>);
 
 impl From<(IrOp, IrOp)> for IrSequence {
@@ -54,12 +54,14 @@ impl IrSequence {
 /// structure, etc.
 ///
 /// That's more than I want to do for a weekend project though.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum IrOp {
     CallProc(CallProcOp),
     Label(LabelOp),
+    BenchmarkLap(BenchmarkLapOp),
     RetProc(RetProcOp),
     Push(PushOp),
+    StackGuard(StackGuardOp),
     Pop(PopOp),
     Peek(PeekOp),
     Poke(PokeOp),
@@ -73,16 +75,35 @@ pub enum IrOp {
     Break(BreakOp),
     Continue(ContinueOp),
     LoopEnd(LoopEndOp),
+    BusyWait(BusyWaitOp),
     Let(LetOp),
     GetStack(GetStackOp),
     SetStack(SetStackOp),
     Set(SetOp),
     Math(MathOp),
+    Sensor(SensorOp),
     Function(FunctionName, AddressDelta),
     Call(CallOp),
     Return(ReturnOp),
 }
 
+impl IrOp {
+    /// Moves the addresses the op holds, such as where a loop or if jumps,
+    /// with `relocate`. See `IntermediateRepresentation::relocate`.
+    pub fn relocate(&mut self, relocate: &dyn Fn(Address) -> Result<Address>) -> Result<()> {
+        match self {
+            IrOp::If(op) => op.relocate(relocate),
+            IrOp::Else(op) => op.relocate(relocate),
+            IrOp::While(op) => op.relocate(relocate),
+            IrOp::DoWhile(op) => op.relocate(relocate),
+            IrOp::InfiniteLoop(op) => op.relocate(relocate),
+            IrOp::LoopEnd(op) => op.relocate(relocate),
+            IrOp::BusyWait(op) => op.relocate(relocate),
+            _ => Ok(()),
+        }
+    }
+}
+
 pub trait Operation {
     /// Returns the number of instructions for the code generated for this op.
     fn code_size(&self, backend: Backend) -> AddressDelta;
@@ -102,6 +123,7 @@ impl Operation for IrOp {
         match self {
             IrOp::CallProc(op) => op.code_size(backend),
             IrOp::Push(op) => op.code_size(backend),
+            IrOp::StackGuard(op) => op.code_size(backend),
             IrOp::Pop(op) => op.code_size(backend),
             IrOp::Peek(op) => op.code_size(backend),
             IrOp::Poke(op) => op.code_size(backend),
@@ -109,8 +131,10 @@ impl Operation for IrOp {
             IrOp::SetStack(op) => op.code_size(backend),
             IrOp::Set(op) => op.code_size(backend),
             IrOp::Math(op) => op.code_size(backend),
+            IrOp::Sensor(op) => op.code_size(backend),
             IrOp::RetProc(op) => op.code_size(backend),
             IrOp::Label(op) => op.code_size(backend),
+            IrOp::BenchmarkLap(op) => op.code_size(backend),
             IrOp::MindustryCommand(op) => op.code_size(backend),
             IrOp::Jump(op) => op.code_size(backend),
             IrOp::If(op) => op.code_size(backend),
@@ -119,6 +143,7 @@ impl Operation for IrOp {
             IrOp::DoWhile(op) => op.code_size(backend),
             IrOp::InfiniteLoop(op) => op.code_size(backend),
             IrOp::LoopEnd(op) => op.code_size(backend),
+            IrOp::BusyWait(op) => op.code_size(backend),
             IrOp::Break(op) => op.code_size(backend),
             IrOp::Continue(op) => op.code_size(backend),
             IrOp::Function(_name, size) => *size,
@@ -138,6 +163,7 @@ impl Operation for IrOp {
         match self {
             IrOp::CallProc(op) => op.generate(ir, output, annotated, instruction_count),
             IrOp::Push(op) => op.generate(ir, output, annotated, instruction_count),
+            IrOp::StackGuard(op) => op.generate(ir, output, annotated, instruction_count),
             IrOp::Pop(op) => op.generate(ir, output, annotated, instruction_count),
             IrOp::Peek(op) => op.generate(ir, output, annotated, instruction_count),
             IrOp::Poke(op) => op.generate(ir, output, annotated, instruction_count),
@@ -145,8 +171,10 @@ impl Operation for IrOp {
             IrOp::SetStack(op) => op.generate(ir, output, annotated, instruction_count),
             IrOp::Set(op) => op.generate(ir, output, annotated, instruction_count),
             IrOp::Math(op) => op.generate(ir, output, annotated, instruction_count),
+            IrOp::Sensor(op) => op.generate(ir, output, annotated, instruction_count),
             IrOp::RetProc(op) => op.generate(ir, output, annotated, instruction_count),
             IrOp::Label(op) => op.generate(ir, output, annotated, instruction_count),
+            IrOp::BenchmarkLap(op) => op.generate(ir, output, annotated, instruction_count),
             IrOp::MindustryCommand(op) => op.generate(ir, output, annotated, instruction_count),
             IrOp::Jump(op) => op.generate(ir, output, annotated, instruction_count),
             IrOp::If(op) => op.generate(ir, output, annotated, instruction_count),
@@ -155,6 +183,7 @@ impl Operation for IrOp {
             IrOp::DoWhile(op) => op.generate(ir, output, annotated, instruction_count),
             IrOp::InfiniteLoop(op) => op.generate(ir, output, annotated, instruction_count),
             IrOp::LoopEnd(op) => op.generate(ir, output, annotated, instruction_count),
+            IrOp::BusyWait(op) => op.generate(ir, output, annotated, instruction_count),
             IrOp::Break(op) => op.generate(ir, output, annotated, instruction_count),
             IrOp::Continue(op) => op.generate(ir, output, annotated, instruction_count),
             IrOp::Function(name, _size) => {
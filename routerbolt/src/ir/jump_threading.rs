@@ -0,0 +1,72 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::*;
+
+/// Retargets a jump whose target is an unconditional jump straight to where
+/// that one goes, e.g.:
+///
+/// jump a lessThan x 5
+/// ...
+/// a:
+/// jump b always
+///
+/// becomes `jump b lessThan x 5`. The jump at `a` stays, since it may also be
+/// reached some other way, but may become dead.
+pub struct JumpThreading;
+
+impl IrPass for JumpThreading {
+    fn name(&self) -> &'static str {
+        "jump-threading"
+    }
+
+    fn run(&self, ir: &mut IntermediateRepresentation) -> Result<Changed> {
+        // The first op that generates code at each address.
+        let mut op_at: HashMap<Address, usize> = HashMap::default();
+        let mut address: Address = 0.into();
+        for (j, op) in ir.ops().iter().enumerate() {
+            let size = op.code_size(*ir.backend());
+            if size != 0.into() {
+                op_at.entry(address).or_insert(j);
+            }
+            address += size;
+        }
+
+        let always_jump = |label: &LabelName| -> Option<&LabelName> {
+            let j = op_at.get(ir.labels().get(label)?)?;
+            match &ir.ops()[*j] {
+                IrOp::Jump(jump) if jump.condition.is_always() => Some(&jump.target),
+                _ => None,
+            }
+        };
+
+        let mut retarget = Vec::default();
+        for (j, op) in ir.ops().iter().enumerate() {
+            let first = match op {
+                IrOp::Jump(jump) => &jump.target,
+                _ => continue,
+            };
+
+            // Follow the chain to its end, leaving alone jumps into a cycle.
+            let mut seen = HashSet::new();
+            let mut target = first;
+            while let Some(next) = always_jump(target) {
+                if !seen.insert(target) {
+                    break;
+                }
+                target = next;
+            }
+            if target != first && always_jump(target).is_none() {
+                retarget.push((j, target.clone()));
+            }
+        }
+
+        let changed = !retarget.is_empty();
+        for (j, target) in retarget {
+            if let IrOp::Jump(jump) = &mut ir.ops[j] {
+                jump.target = target;
+            }
+        }
+
+        Ok(changed.into())
+    }
+}
@@ -0,0 +1,809 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::*;
+
+pub type Variables = BTreeSet<String>;
+
+/// The variables an op reads and writes. Stack variables, literals, and
+/// Mindustry's `@` variables aren't included.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Effects {
+    pub reads: Variables,
+    pub writes: Variables,
+}
+
+impl Effects {
+    /// The effects of `op` in a program whose own variables start with
+    /// `prefix`.
+    pub fn new(op: &IrOp, prefix: &str) -> Effects {
+        let mut effects = Effects::default();
+        let acc = MindustryTerm::accumulator(prefix);
+        match op {
+            IrOp::Set(op) => {
+                effects.read(&op.source);
+                effects.write(&op.dest);
+            }
+            IrOp::Math(op) => {
+                effects.read(&op.arg1);
+                effects.read(&op.arg2);
+                effects.write(&op.dest);
+            }
+            IrOp::Sensor(op) => {
+                effects.read(&op.target);
+                effects.read(&op.property);
+                effects.write(&op.dest);
+            }
+            IrOp::GetStack(op) => effects.write(&op.global),
+            IrOp::SetStack(op) => effects.read(&op.global),
+            IrOp::Call(op) => {
+                op.args.iter().for_each(|arg| effects.read_term(arg));
+                op.returns.iter().for_each(|ret| effects.write_term(ret));
+                effects.write(&acc);
+            }
+            IrOp::Return(op) => {
+                op.values.iter().for_each(|value| effects.read_term(value));
+                effects.write(&acc);
+            }
+            IrOp::CallProc(..) | IrOp::RetProc(..) | IrOp::Pop(..) => effects.write(&acc),
+            IrOp::Push(..) => effects.read(&acc),
+            IrOp::Peek(op) => {
+                effects.read(&op.depth);
+                effects.write(&acc);
+            }
+            IrOp::Poke(op) => {
+                effects.read(&op.depth);
+                effects.read(&acc);
+            }
+            IrOp::Jump(op) => effects.read_condition(&op.condition),
+            IrOp::If(op) => effects.read_condition(op.condition()),
+            IrOp::While(op) => {
+                if let Ok((_, condition)) = op.entry_jump() {
+                    effects.read_condition(&condition);
+                }
+            }
+            IrOp::LoopEnd(op) => effects.read_condition(op.jump().1),
+            IrOp::BusyWait(op) => effects.read_condition(&op.condition),
+            IrOp::MindustryCommand(op) => {
+                let command = op.command.to_string();
+                let tok: Vec<&str> = command.split_whitespace().collect();
+                effects.command(&tok);
+            }
+            _ => {}
+        }
+        effects
+    }
+
+    fn read(&mut self, term: &MindustryTerm) {
+        self.reads.extend(variable(term.as_ref()));
+    }
+
+    fn write(&mut self, term: &MindustryTerm) {
+        self.writes.extend(variable(term.as_ref()));
+    }
+
+    fn read_term(&mut self, term: &Term) {
+        if let Term::Mindustry(term) = term {
+            self.read(term);
+        }
+    }
+
+    fn write_term(&mut self, term: &Term) {
+        if let Term::Mindustry(term) = term {
+            self.write(term);
+        }
+    }
+
+    fn read_condition(&mut self, condition: &Condition) {
+        condition
+            .operands()
+            .into_iter()
+            .for_each(|arg| self.read(arg));
+    }
+
+    /// A raw Mindustry instruction. Arguments that name an output are written,
+    /// and those that choose what the instruction does are skipped.
+    fn command(&mut self, tok: &[&str]) {
+        let (name, args) = match tok.split_first() {
+            Some(split) => split,
+            None => return,
+        };
+
+        let writes: &[usize] = match (*name, args.first().copied()) {
+            ("set", _) | ("read", _) | ("sensor", _) | ("getlink", _) => &[0],
+            ("packcolor", _) | ("select", _) | ("getflag", _) => &[0],
+            ("op", _) | ("lookup", _) | ("getblock", _) | ("fetch", _) => &[1],
+            ("unpackcolor", _) => &[0, 1, 2, 3],
+            ("radar", _) | ("uradar", _) => &[6],
+            ("ulocate", _) => &[4, 5, 6, 7],
+            ("ucontrol", Some("within")) => &[4],
+            ("ucontrol", Some("getBlock")) => &[3, 4, 5],
+            ("spawn", _) => &[5],
+            _ => &[],
+        };
+        let keywords: &[usize] = match *name {
+            "jump" if args.get(1) == Some(&"always") => return,
+            "jump" | "status" | "ulocate" => &[0, 1],
+            "select" => &[1],
+            "radar" | "uradar" => &[0, 1, 2, 3],
+            "op" | "draw" | "control" | "ucontrol" | "lookup" | "getblock" | "setblock"
+            | "setrule" | "effect" | "cutscene" | "fetch" | "setmarker" => &[0],
+            _ => &[],
+        };
+
+        for (j, arg) in args.iter().enumerate() {
+            let set = if writes.contains(&j) {
+                &mut self.writes
+            } else if keywords.contains(&j) {
+                continue;
+            } else {
+                &mut self.reads
+            };
+            set.extend(variable(arg));
+        }
+    }
+}
+
+/// The name, if this is a variable rather than a literal, a stack variable, or
+/// one of Mindustry's `@` variables.
+fn variable(token: &str) -> Option<String> {
+    let literal = token.is_empty()
+        || token.starts_with(&['@', '*', '%'][..])
+        || token.contains('"')
+        || ["null", "true", "false"].contains(&token)
+        || token.parse::<f64>().is_ok()
+        || token.starts_with("0x")
+        || token.starts_with("0b");
+    Some(token.to_string()).filter(|_| !literal)
+}
+
+/// Which variables are live, that is hold a value that may yet be read, at
+/// each point in the program. This includes user globals as well as `MF_acc`
+/// and the `MF_t<n>` temporaries.
+///
+/// Found by working backward over the `Cfg`. A call is followed through a
+/// summary of the function: what it may read before writing it, what it
+/// writes on every path to a return, and what it may write at all. A function
+/// returns to every one of its call sites.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Liveness {
+    /// Variables live on entry to each block of the CFG.
+    pub live_in: Vec<Variables>,
+
+    /// Variables live just after each op.
+    pub live_after: Vec<Variables>,
+
+    /// Every reachable call, in the order of the ops.
+    pub calls: Vec<LiveAcrossCall>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LiveAcrossCall {
+    /// Index in `ops` of the call.
+    pub op: usize,
+    pub function: FunctionName,
+
+    /// Variables whose value from before the call may be read after it.
+    pub live: Variables,
+
+    /// Those of `live` the function may write, but not on every path.
+    pub clobbered: Variables,
+}
+
+/// A set of variables, each a bit indexed as in `Analysis::names`. Every set
+/// in an analysis has room for all of its variables.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Bits(Vec<u64>);
+
+impl Bits {
+    fn new(len: usize) -> Bits {
+        Bits(vec![0; len.div_ceil(64)])
+    }
+
+    fn insert(&mut self, j: usize) {
+        self.0[j / 64] |= 1 << (j % 64);
+    }
+
+    /// Adds `other`, giving whether that changed anything.
+    fn union(&mut self, other: &Bits) -> bool {
+        let mut changed = false;
+        for (word, other) in self.0.iter_mut().zip(other.0.iter()) {
+            changed |= *other & !*word != 0;
+            *word |= *other;
+        }
+        changed
+    }
+
+    fn subtract(&mut self, other: &Bits) {
+        for (word, other) in self.0.iter_mut().zip(other.0.iter()) {
+            *word &= !*other;
+        }
+    }
+
+    fn intersect(&mut self, other: &Bits) {
+        for (word, other) in self.0.iter_mut().zip(other.0.iter()) {
+            *word &= *other;
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().enumerate().flat_map(|(j, word)| {
+            (0..64)
+                .filter(move |k| word & (1 << k) != 0)
+                .map(move |k| j * 64 + k)
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Summary {
+    // Read before being written.
+    reads: Bits,
+
+    // Written on every path to a return. `None` if the function never
+    // returns, which is as good as writing everything.
+    writes: Option<Bits>,
+
+    may_write: Bits,
+}
+
+struct Analysis<'a> {
+    ir: &'a IntermediateRepresentation,
+    cfg: &'a Cfg,
+
+    // Every variable the program reads or writes, and what each op does with
+    // them.
+    names: Vec<String>,
+    reads: Vec<Bits>,
+    writes: Vec<Bits>,
+
+    // The function each block is part of, or `None` outside any function.
+    function_of: Vec<Option<&'a FunctionName>>,
+
+    // Blocks with an edge to each block, other than a call.
+    predecessors: Vec<Vec<BlockId>>,
+
+    // The blocks of each function, and the reachable blocks that end in a
+    // call to it.
+    blocks: HashMap<&'a FunctionName, Vec<BlockId>>,
+    call_sites: HashMap<&'a FunctionName, Vec<BlockId>>,
+    summaries: HashMap<&'a FunctionName, Summary>,
+}
+
+/// What's live on entry to and at the end of each block.
+struct Solution {
+    live_in: Vec<Bits>,
+    live_out: Vec<Bits>,
+}
+
+impl Liveness {
+    pub fn new(ir: &IntermediateRepresentation, cfg: &Cfg) -> Liveness {
+        let (analysis, solution) = Analysis::run(ir, cfg);
+
+        let mut live_after = vec![Variables::default(); ir.ops().len()];
+        for (id, block) in cfg.blocks.iter().enumerate() {
+            let mut live = solution.live_out[id].clone();
+            for j in block.ops.clone().rev() {
+                live_after[j] = analysis.variables(&live);
+                live = analysis.transfer(j, live, None);
+            }
+        }
+
+        Liveness {
+            live_in: solution
+                .live_in
+                .iter()
+                .map(|live| analysis.variables(live))
+                .collect(),
+            live_after,
+            calls: analysis.live_across_calls(&solution),
+        }
+    }
+}
+
+impl<'a> Analysis<'a> {
+    /// Summarizes the functions, and then finds what's live when each
+    /// returns, which is whatever is live after any call to it.
+    fn run(ir: &'a IntermediateRepresentation, cfg: &'a Cfg) -> (Analysis<'a>, Solution) {
+        let mut analysis = Analysis::new(ir, cfg);
+        analysis.summarize();
+        let solution = analysis.solve(true);
+        (analysis, solution)
+    }
+
+    fn new(ir: &'a IntermediateRepresentation, cfg: &'a Cfg) -> Analysis<'a> {
+        let effects: Vec<Effects> = ir
+            .ops()
+            .iter()
+            .map(|op| Effects::new(op, &ir.variable_prefix))
+            .collect();
+        let mut index: HashMap<&str, usize> = HashMap::default();
+        let mut names = Vec::default();
+        for effects in effects.iter() {
+            for name in effects.reads.iter().chain(effects.writes.iter()) {
+                index.entry(name.as_str()).or_insert_with(|| {
+                    names.push(name.clone());
+                    names.len() - 1
+                });
+            }
+        }
+        let bits = |variables: &Variables| {
+            let mut bits = Bits::new(names.len());
+            variables
+                .iter()
+                .for_each(|name| bits.insert(index[name.as_str()]));
+            bits
+        };
+        let reads = effects.iter().map(|effects| bits(&effects.reads)).collect();
+        let writes = effects
+            .iter()
+            .map(|effects| bits(&effects.writes))
+            .collect();
+
+        // Calls are summarized rather than followed, so each function's blocks
+        // are those reached from its start without following calls.
+        let mut function_of = vec![None; cfg.blocks.len()];
+        let mut reached = vec![false; cfg.blocks.len()];
+        let mut starts: Vec<(Option<&FunctionName>, BlockId)> =
+            cfg.entry().map(|entry| (None, entry)).into_iter().collect();
+        let mut functions: Vec<_> = cfg.functions.iter().collect();
+        functions.sort_by_key(|(_, block)| **block);
+        starts.extend(
+            functions
+                .into_iter()
+                .map(|(name, block)| (Some(name), *block)),
+        );
+        for (function, start) in starts {
+            let mut stack = vec![start];
+            while let Some(id) = stack.pop() {
+                if std::mem::replace(&mut reached[id], true) {
+                    continue;
+                }
+                function_of[id] = function;
+                stack.extend(
+                    cfg.blocks[id]
+                        .edges
+                        .iter()
+                        .filter(|edge| edge.kind != EdgeKind::Call)
+                        .map(|edge| edge.target),
+                );
+            }
+        }
+
+        let mut predecessors = vec![Vec::default(); cfg.blocks.len()];
+        for (id, block) in cfg.blocks.iter().enumerate() {
+            for edge in block.edges.iter().filter(|e| e.kind != EdgeKind::Call) {
+                predecessors[edge.target].push(id);
+            }
+        }
+
+        let mut blocks: HashMap<&FunctionName, Vec<BlockId>> = cfg
+            .functions
+            .keys()
+            .map(|name| (name, Vec::default()))
+            .collect();
+        let mut call_sites: HashMap<&FunctionName, Vec<BlockId>> = cfg
+            .functions
+            .keys()
+            .map(|name| (name, Vec::default()))
+            .collect();
+        for (id, block) in cfg.blocks.iter().enumerate() {
+            if !reached[id] {
+                continue;
+            }
+            if let Some(function) = function_of[id] {
+                blocks.get_mut(function).unwrap().push(id);
+            }
+            if let Some(IrOp::Call(call)) = block.ops.clone().last().map(|j| &ir.ops()[j]) {
+                if let Some(sites) = call_sites.get_mut(&call.target_function) {
+                    sites.push(id);
+                }
+            }
+        }
+
+        let summaries = cfg
+            .functions
+            .keys()
+            .map(|name| {
+                let summary = Summary {
+                    reads: Bits::new(names.len()),
+                    writes: None,
+                    may_write: Bits::new(names.len()),
+                };
+                (name, summary)
+            })
+            .collect();
+
+        Analysis {
+            ir,
+            cfg,
+            names,
+            reads,
+            writes,
+            function_of,
+            predecessors,
+            blocks,
+            call_sites,
+            summaries,
+        }
+    }
+
+    fn empty(&self) -> Bits {
+        Bits::new(self.names.len())
+    }
+
+    fn variables(&self, bits: &Bits) -> Variables {
+        bits.iter().map(|j| self.names[j].clone()).collect()
+    }
+
+    /// The function op `j` calls, if it's a call to one that's summarized.
+    fn callee(&self, j: usize) -> Option<&'a FunctionName> {
+        match &self.ir.ops()[j] {
+            IrOp::Call(call) if self.summaries.contains_key(&call.target_function) => self
+                .summaries
+                .get_key_value(&call.target_function)
+                .map(|(name, _)| *name),
+            _ => None,
+        }
+    }
+
+    /// The function names, each after those it calls where recursion allows,
+    /// so that working through them in order settles most in one pass.
+    fn callees_first(&self) -> Vec<&'a FunctionName> {
+        let mut names: Vec<&'a FunctionName> = self.summaries.keys().copied().collect();
+        names.sort_by_key(|name| self.cfg.functions[*name]);
+
+        let mut order = Vec::default();
+        let mut visited: HashMap<&FunctionName, bool> = HashMap::default();
+        for name in names {
+            // Each function is pushed once those it calls have been.
+            let mut stack = vec![(name, false)];
+            while let Some((name, done)) = stack.pop() {
+                if done {
+                    order.push(name);
+                    continue;
+                }
+                if visited.insert(name, true).is_some() {
+                    continue;
+                }
+                stack.push((name, true));
+                for id in self.blocks[name].iter() {
+                    for j in self.cfg.blocks[*id].ops.clone() {
+                        if let Some(callee) = self.callee(j) {
+                            if !visited.contains_key(callee) {
+                                stack.push((callee, false));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        order
+    }
+
+    fn summarize(&mut self) {
+        let names = self.callees_first();
+
+        // Everything a function may write, including through its calls.
+        loop {
+            let mut changed = false;
+            for name in names.iter() {
+                let mut may_write = self.empty();
+                for id in self.blocks[name].iter() {
+                    for j in self.cfg.blocks[*id].ops.clone() {
+                        may_write.union(&self.writes[j]);
+                        if let Some(callee) = self.callee(j) {
+                            may_write.union(&self.summaries[callee].may_write);
+                        }
+                    }
+                }
+                let summary = self.summaries.get_mut(name).unwrap();
+                changed |= summary.may_write != may_write;
+                summary.may_write = may_write;
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        // What it writes on every path, assuming at first (as for recursion)
+        // that calls write everything.
+        loop {
+            let mut changed = false;
+            for name in names.iter() {
+                let writes = self.must_write(name);
+                let summary = self.summaries.get_mut(name).unwrap();
+                changed |= summary.writes != writes;
+                summary.writes = writes;
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        // What it reads before writing, which is what's live at its start if
+        // nothing is live when it returns.
+        let solution = self.solve(false);
+        for name in names.iter() {
+            let reads = solution.live_in[self.cfg.functions[*name]].clone();
+            self.summaries.get_mut(name).unwrap().reads = reads;
+        }
+    }
+
+    /// What `function` writes on every path from its start to a return.
+    fn must_write(&self, function: &FunctionName) -> Option<Bits> {
+        let entry = self.cfg.functions[function];
+        let blocks = &self.blocks[function];
+        let mut written: HashMap<BlockId, Option<Bits>> =
+            blocks.iter().map(|id| (*id, None)).collect();
+
+        loop {
+            let mut changed = false;
+            for id in blocks.iter().copied() {
+                let mut before = if id == entry {
+                    Some(self.empty())
+                } else {
+                    None
+                };
+                for pred in self.predecessors[id].iter() {
+                    if self.function_of[*pred] == Some(function) {
+                        before = meet(before, &written[pred]);
+                    }
+                }
+
+                let mut after = before;
+                for j in self.cfg.blocks[id].ops.clone() {
+                    if let Some(after) = after.as_mut() {
+                        after.union(&self.writes[j]);
+                    }
+                    if let Some(callee) = self.callee(j) {
+                        after = match (after, &self.summaries[callee].writes) {
+                            (Some(mut after), Some(writes)) => {
+                                after.union(writes);
+                                Some(after)
+                            }
+                            _ => None,
+                        };
+                    }
+                }
+
+                if written[&id] != after {
+                    written.insert(id, after);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        blocks
+            .iter()
+            .filter(|id| self.cfg.blocks[**id].exit == Some(Exit::Return))
+            .fold(None, |writes, id| meet(writes, &written[id]))
+    }
+
+    /// Works backward to find what's live on entry to and at the end of each
+    /// block. With `returns`, what's live when a function returns is what's
+    /// live after any call to it, and a call reads what its summary says.
+    /// Without, nothing is live when a function returns, and a call reads
+    /// what's live at the start of the function, which is how the summaries
+    /// are found.
+    ///
+    /// Each block is worked through again only when something it depends on
+    /// changes: a successor, the function it calls, the calls to the function
+    /// it returns from, or for `end`, the start of the program.
+    fn solve(&self, returns: bool) -> Solution {
+        let blocks = &self.cfg.blocks;
+        let mut live_in = vec![self.empty(); blocks.len()];
+        let mut live_out = vec![self.empty(); blocks.len()];
+        let mut live_at_return: HashMap<&FunctionName, Bits> = HashMap::default();
+
+        let entry = self.cfg.entry();
+        let ends: Vec<BlockId> = (0..blocks.len())
+            .filter(|id| blocks[*id].exit == Some(Exit::End))
+            .collect();
+        let mut callers: HashMap<BlockId, &FunctionName> = HashMap::default();
+        for (function, sites) in self.call_sites.iter() {
+            for id in sites {
+                callers.insert(*id, function);
+            }
+        }
+
+        // Popped from the end, so blocks are first worked through from the
+        // last, as suits a backward analysis.
+        let mut worklist: Vec<BlockId> = (0..blocks.len()).collect();
+        let mut queued = vec![true; blocks.len()];
+        let enqueue = |worklist: &mut Vec<BlockId>, queued: &mut Vec<bool>, id: BlockId| {
+            if !std::mem::replace(&mut queued[id], true) {
+                worklist.push(id);
+            }
+        };
+        let starts: HashMap<BlockId, &FunctionName> = self
+            .cfg
+            .functions
+            .iter()
+            .map(|(name, start)| (*start, name))
+            .collect();
+
+        while let Some(id) = worklist.pop() {
+            queued[id] = false;
+            let block = &blocks[id];
+
+            let mut live = self.empty();
+            for edge in block.edges.iter().filter(|e| e.kind != EdgeKind::Call) {
+                live.union(&live_in[edge.target]);
+            }
+            match (block.exit, self.function_of[id]) {
+                // Globals keep their values when the program starts over.
+                (Some(Exit::End), _) => {
+                    if let Some(entry) = entry {
+                        live.union(&live_in[entry]);
+                    }
+                }
+                (Some(Exit::Return), Some(function)) if returns => {
+                    if let Some(at_return) = live_at_return.get(function) {
+                        live.union(at_return);
+                    }
+                }
+                _ => {}
+            }
+
+            if returns && live != live_out[id] {
+                if let Some(function) = callers.get(&id) {
+                    let at_return = live_at_return
+                        .entry(function)
+                        .or_insert_with(|| self.empty());
+                    if at_return.union(&live) {
+                        for ret in self.blocks[*function].iter() {
+                            if blocks[*ret].exit == Some(Exit::Return) {
+                                enqueue(&mut worklist, &mut queued, *ret);
+                            }
+                        }
+                    }
+                }
+            }
+            live_out[id] = live.clone();
+
+            let reads = if returns { None } else { Some(&live_in) };
+            for j in block.ops.clone().rev() {
+                live = self.transfer(j, live, reads);
+            }
+
+            if live != live_in[id] {
+                live_in[id] = live;
+                for pred in self.predecessors[id].iter() {
+                    enqueue(&mut worklist, &mut queued, *pred);
+                }
+                if Some(id) == entry {
+                    for end in ends.iter() {
+                        enqueue(&mut worklist, &mut queued, *end);
+                    }
+                }
+                if let Some(function) = starts.get(&id).filter(|_| !returns) {
+                    for site in self.call_sites[function].iter() {
+                        enqueue(&mut worklist, &mut queued, *site);
+                    }
+                }
+            }
+        }
+
+        Solution { live_in, live_out }
+    }
+
+    /// What's live before op `j`, given what's live after it. A call reads
+    /// what its summary says, or with `live_in`, what's live at the start of
+    /// the function.
+    fn transfer(&self, j: usize, mut live: Bits, live_in: Option<&Vec<Bits>>) -> Bits {
+        live.subtract(&self.writes[j]);
+        if let Some(callee) = self.callee(j) {
+            let summary = &self.summaries[callee];
+            match &summary.writes {
+                Some(writes) => live.subtract(writes),
+                None => live = self.empty(),
+            }
+            match live_in {
+                Some(live_in) => live.union(&live_in[self.cfg.functions[callee]]),
+                None => live.union(&summary.reads),
+            };
+        }
+        live.union(&self.reads[j]);
+        live
+    }
+
+    /// Every reachable call, with what's live across it.
+    fn live_across_calls(&self, solution: &Solution) -> Vec<LiveAcrossCall> {
+        let mut sites: Vec<(BlockId, &FunctionName)> = self
+            .call_sites
+            .iter()
+            .flat_map(|(function, sites)| sites.iter().map(move |id| (*id, *function)))
+            .collect();
+        sites.sort();
+
+        sites
+            .into_iter()
+            .map(|(id, function)| {
+                let j = self.cfg.blocks[id].ops.end - 1;
+                let summary = &self.summaries[function];
+                let live = match &summary.writes {
+                    Some(writes) => {
+                        let mut live = solution.live_out[id].clone();
+                        live.subtract(&self.writes[j]);
+                        live.subtract(writes);
+                        live
+                    }
+                    None => self.empty(),
+                };
+                let mut clobbered = live.clone();
+                clobbered.intersect(&summary.may_write);
+                LiveAcrossCall {
+                    op: j,
+                    function: function.clone(),
+                    live: self.variables(&live),
+                    clobbered: self.variables(&clobbered),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The variables in both, where `None` stands for every variable.
+fn meet(a: Option<Bits>, b: &Option<Bits>) -> Option<Bits> {
+    match (a, b) {
+        (None, b) => b.clone(),
+        (a, None) => a,
+        (Some(mut a), Some(b)) => {
+            a.intersect(b);
+            Some(a)
+        }
+    }
+}
+
+/// A variable whose value a call may or may not overwrite, depending on the
+/// path taken through the function, and which is read after the call.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClobberedVariable {
+    pub function: FunctionName,
+    pub variable: String,
+
+    /// The line of the call.
+    pub line: usize,
+}
+
+impl std::fmt::Display for ClobberedVariable {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "call to {} may overwrite {}, which is read after the call (line {})",
+            self.function,
+            self.variable,
+            self.line + 1
+        )
+    }
+}
+
+/// Finds the variables calls may overwrite that are read after them. A program
+/// whose control flow can't be followed has none.
+pub fn find_clobbered_variables(ir: &IntermediateRepresentation) -> Vec<ClobberedVariable> {
+    let cfg = match ir.cfg() {
+        Ok(cfg) => cfg,
+        Err(..) => return Vec::default(),
+    };
+
+    // Only the calls are needed, not what's live after every op.
+    let (analysis, solution) = Analysis::run(ir, &cfg);
+    analysis
+        .live_across_calls(&solution)
+        .into_iter()
+        .flat_map(|call| {
+            let line = ir.op_lines[call.op].unwrap_or_default();
+            let function = call.function;
+            call.clobbered
+                .into_iter()
+                .map(move |variable| ClobberedVariable {
+                    function: function.clone(),
+                    variable,
+                    line,
+                })
+        })
+        .collect()
+}
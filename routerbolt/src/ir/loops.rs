@@ -4,7 +4,7 @@ use crate::*;
 /// loop types.
 ///
 /// Destroys: All
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LoopEndOp {
     // Start of the loop body.
     body_start: Address,
@@ -18,8 +18,50 @@ trait LoopTrait {
     fn condition_address(&self) -> Result<Address>;
 }
 
+// Moves the addresses of the condition check and the end of a loop.
+fn relocate_forward(
+    forward: Option<(Address, Address)>,
+    relocate: &dyn Fn(Address) -> Result<Address>,
+) -> Result<Option<(Address, Address)>> {
+    forward
+        .map(|(check, end)| Ok((relocate(check)?, relocate(end)?)))
+        .transpose()
+}
+
+/// The address just past the end of the loop `op` begins, if it begins one.
+pub fn loop_end(op: &IrOp) -> Option<Result<Address>> {
+    match op {
+        IrOp::While(op) => Some(op.end_address()),
+        IrOp::DoWhile(op) => Some(op.end_address()),
+        IrOp::InfiniteLoop(op) => Some(op.end_address()),
+        _ => None,
+    }
+}
+
+/// Where `continue` goes in the loop begun by `op`, if it begins one.
+pub fn loop_condition(op: &IrOp) -> Option<Result<Address>> {
+    match op {
+        IrOp::While(op) => Some(op.condition_address()),
+        IrOp::DoWhile(op) => Some(op.condition_address()),
+        IrOp::InfiniteLoop(op) => Some(op.condition_address()),
+        _ => None,
+    }
+}
+
 impl LoopEndOp {
     const SIZE: AddressDelta = AddressDelta::new(1);
+
+    /// The jump back to the start of the body, and when it is taken.
+    pub fn jump(&self) -> (Address, &Condition) {
+        (self.body_start, &self.condition)
+    }
+
+    /// Moves the address the op jumps back to. See
+    /// `IntermediateRepresentation::relocate`.
+    pub fn relocate(&mut self, relocate: &dyn Fn(Address) -> Result<Address>) -> Result<()> {
+        self.body_start = relocate(self.body_start)?;
+        Ok(())
+    }
 }
 
 impl Operation for LoopEndOp {
@@ -43,7 +85,8 @@ impl Operation for LoopEndOp {
             ));
         }
 
-        output.push(format!("jump {} {}", self.body_start, &self.condition));
+        let (target, condition) = self.jump();
+        output.push(format!("jump {} {}", target, condition));
 
         Ok(())
     }
@@ -52,9 +95,11 @@ impl Operation for LoopEndOp {
 /// Begins a while loop. The condition is the same as Mindustry's jump. In
 /// particular, only one condition may be checked.
 ///
-/// At present this desugars to `While` ... `LoopEnd`, where the While just jumps
-/// to the LoopEnd. That's an extra instruction run per loop, but shares more
-/// code with the other loops.
+/// This desugars to `While` ... `LoopEnd`. Where it can, the While skips the
+/// loop if the negated condition holds, and otherwise falls into the body.
+/// When the condition has no negation or needs stack variables read first, the
+/// While instead jumps to the check at the LoopEnd, which costs an extra
+/// instruction each time the loop is entered.
 ///
 /// E.g.:
 ///
@@ -62,7 +107,7 @@ impl Operation for LoopEndOp {
 ///   op add a a 1
 ///   print "hello"
 /// }
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct WhileOp {
     // Start of the loop body.
     body_start: Address,
@@ -74,6 +119,9 @@ pub struct WhileOp {
     // Loop condition.
     condition: Condition,
 
+    // Negated loop condition, checked on entry, if we can check it there.
+    entry_condition: Option<Condition>,
+
     // Address where we check the loop condition and then loop or end as
     // appropriate.
     forward: Option<(Address, Address)>,
@@ -83,14 +131,27 @@ impl WhileOp {
     const SIZE: AddressDelta = AddressDelta::new(1);
 
     pub fn new(address: Address, end_sequence: IrSequence, condition: Condition) -> WhileOp {
+        let entry_condition = if end_sequence.0.is_empty() {
+            condition.negate()
+        } else {
+            None
+        };
+
         WhileOp {
             body_start: address + Self::SIZE,
             end_sequence: Box::new(end_sequence),
             forward: None,
             condition,
+            entry_condition,
         }
     }
 
+    /// The ops that check the condition at the end of the loop, once the end
+    /// is known.
+    pub fn end_sequence(&self) -> &IrSequence {
+        &self.end_sequence
+    }
+
     pub fn resolve_forward(&mut self, body_end: Address, backend: Backend) -> &IrSequence {
         self.end_sequence.push(IrOp::LoopEnd(LoopEndOp {
             body_start: self.body_start,
@@ -101,6 +162,26 @@ impl WhileOp {
         assert!(set.is_none());
         &self.end_sequence
     }
+
+    /// The jump made on entering the loop, and when it is taken: either past
+    /// the loop if the condition fails, or always to the check at the end.
+    pub fn entry_jump(&self) -> Result<(Address, Condition)> {
+        Ok(match &self.entry_condition {
+            Some(condition) => (self.end_address()?, condition.clone()),
+            None => (self.condition_address()?, Condition::always()),
+        })
+    }
+
+    /// Moves the addresses the op holds, including those in the end
+    /// sequence. See `IntermediateRepresentation::relocate`.
+    pub fn relocate(&mut self, relocate: &dyn Fn(Address) -> Result<Address>) -> Result<()> {
+        self.body_start = relocate(self.body_start)?;
+        self.forward = relocate_forward(self.forward, relocate)?;
+        for op in self.end_sequence.0.iter_mut() {
+            op.relocate(relocate)?;
+        }
+        Ok(())
+    }
 }
 
 impl LoopTrait for WhileOp {
@@ -132,17 +213,15 @@ impl Operation for WhileOp {
         annotated: Option<&mut Vec<String>>,
         _instruction_count: &mut Address,
     ) -> Result<()> {
-        // Remember, the WhileOp is just the start of the loop. All we do is
-        // jump to the condition check. (Workaround to negation, same as with
-        // if).
+        // Remember, the WhileOp is just the start of the loop. Either skip
+        // the loop if the condition fails, or jump to the condition check at
+        // the end so that if it fails, we'll end the loop.
         if let Some(annotated) = annotated {
             annotated.push(format!("// While @{}", output.len()));
         }
 
-        // FIXME: Can optimize by negating. Like if but not as bad. This is
-        // jumping to the condition check at the end so that if it fails, we'll
-        // end the loop. As an indirect way of negating.
-        output.push(format!("jump {} always x false", self.condition_address()?));
+        let (target, condition) = self.entry_jump()?;
+        output.push(format!("jump {} {}", target, condition));
 
         Ok(())
     }
@@ -151,8 +230,8 @@ impl Operation for WhileOp {
 /// Begins a do-while loop. The condition is the same as Mindustry's jump. In
 /// particular, only one condition may be checked.
 ///
-/// This works by adding a LoopEnd at the end of the body, and is actually more
-/// efficient than While as currently implemented.
+/// This works by adding a LoopEnd at the end of the body, and so skips the
+/// check on entry that While needs.
 ///
 /// E.g.:
 ///
@@ -160,7 +239,7 @@ impl Operation for WhileOp {
 ///   print "hello"
 ///   op add a a 1
 /// } while lessThan a 7
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DoWhileOp {
     // Start of the loop body.
     body_start: Address,
@@ -197,6 +276,14 @@ impl DoWhileOp {
 
         end_sequence
     }
+
+    /// Moves the addresses the op holds. See
+    /// `IntermediateRepresentation::relocate`.
+    pub fn relocate(&mut self, relocate: &dyn Fn(Address) -> Result<Address>) -> Result<()> {
+        self.body_start = relocate(self.body_start)?;
+        self.forward = relocate_forward(self.forward, relocate)?;
+        Ok(())
+    }
 }
 
 impl LoopTrait for DoWhileOp {
@@ -248,7 +335,7 @@ impl Operation for DoWhileOp {
 ///   print "hello"
 ///   op add a a 1
 /// }
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct InfiniteLoopOp {
     // Start of the loop body.
     body_start: Address,
@@ -278,6 +365,14 @@ impl InfiniteLoopOp {
 
         IrOp::LoopEnd(op).into()
     }
+
+    /// Moves the addresses the op holds. See
+    /// `IntermediateRepresentation::relocate`.
+    pub fn relocate(&mut self, relocate: &dyn Fn(Address) -> Result<Address>) -> Result<()> {
+        self.body_start = relocate(self.body_start)?;
+        self.end = self.end.map(relocate).transpose()?;
+        Ok(())
+    }
 }
 
 impl LoopTrait for InfiniteLoopOp {
@@ -316,7 +411,7 @@ impl Operation for InfiniteLoopOp {
 /// Since the only scope is function-level, this is as simple as jumping out.
 ///
 /// FIXME: Support conditions.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BreakOp {
     /// The index in `ops` of the loop this is in. This lets us avoid a forward
     /// reference here by referencing the loop.
@@ -367,7 +462,7 @@ impl Operation for BreakOp {
 /// follow that here.
 ///
 /// FIXME: Support conditions.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ContinueOp {
     /// The index in `ops` of the loop this is in. This lets us avoid a forward
     /// reference here by referencing the loop.
@@ -408,3 +503,58 @@ impl Operation for ContinueOp {
         Ok(())
     }
 }
+
+/// Spins in place for as long as the condition holds. The condition is the
+/// same as Mindustry's jump, and may use stack variables, in which case the
+/// reads are redone on every spin.
+///
+/// E.g.:
+///
+/// busywait lessThan @time deadline
+///
+/// Preserves: All if no stack vars are used in the condition, otherwise None.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BusyWaitOp {
+    // First instruction of the condition check, including any stack reads
+    // preceeding this op.
+    pub start: Address,
+
+    // Keep spinning while this is true.
+    pub condition: Condition,
+}
+
+impl BusyWaitOp {
+    /// Moves the address the op spins back to. See
+    /// `IntermediateRepresentation::relocate`.
+    pub fn relocate(&mut self, relocate: &dyn Fn(Address) -> Result<Address>) -> Result<()> {
+        self.start = relocate(self.start)?;
+        Ok(())
+    }
+}
+
+impl Operation for BusyWaitOp {
+    fn code_size(&self, _backend: Backend) -> AddressDelta {
+        1.into()
+    }
+
+    fn generate(
+        &self,
+        _ir: &IntermediateRepresentation,
+        output: &mut Vec<String>,
+        annotated: Option<&mut Vec<String>>,
+        _instruction_count: &mut Address,
+    ) -> Result<()> {
+        if let Some(annotated) = annotated {
+            annotated.push(format!(
+                "// BusyWait: {} {} @{}",
+                &self.condition,
+                self.start,
+                output.len()
+            ));
+        }
+
+        output.push(format!("jump {} {}", self.start, &self.condition));
+
+        Ok(())
+    }
+}
@@ -6,7 +6,7 @@ use crate::*;
 ///   - If stack variables are used: All
 ///   - If it directly changes any variable starting with `MF_`: that variable
 ///   - Otherwise: Preserves all
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MindustryOp {
     pub command: MindustryCommand,
 }
@@ -32,3 +32,46 @@ impl Operation for MindustryOp {
         Ok(())
     }
 }
+
+/// Reads a property of a block or unit, as per Mindustry `sensor`.
+///
+/// e.g.: `sense x vault1 @copper` or `set x vault1.@copper`
+///
+/// Preserves: All
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SensorOp {
+    pub dest: MindustryTerm,
+    pub target: MindustryTerm,
+    pub property: MindustryTerm,
+}
+
+impl Operation for SensorOp {
+    fn code_size(&self, _backend: Backend) -> AddressDelta {
+        1.into()
+    }
+
+    fn generate(
+        &self,
+        _ir: &IntermediateRepresentation,
+        output: &mut Vec<String>,
+        annotated: Option<&mut Vec<String>>,
+        _instruction_count: &mut Address,
+    ) -> Result<()> {
+        if let Some(annotated) = annotated {
+            annotated.push(format!(
+                "// Sensor {} {} {} @{}",
+                &self.dest,
+                &self.target,
+                &self.property,
+                output.len()
+            ));
+        }
+
+        output.push(format!(
+            "sensor {} {} {}",
+            &self.dest, &self.target, &self.property
+        ));
+
+        Ok(())
+    }
+}
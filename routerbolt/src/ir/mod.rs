@@ -1,19 +1,44 @@
 pub mod asm;
+pub mod cfg;
+pub mod dead_code;
 pub mod function;
 pub mod if_op;
 pub mod intermediate_representation;
 pub mod ir_op;
+pub mod jump_threading;
+pub mod liveness;
 pub mod loops;
 pub mod mindustry;
+pub mod pass;
+pub mod peephole;
+pub mod relocate;
+pub mod stack_analysis;
+pub mod temporaries;
+pub mod text;
+pub mod unused;
 pub mod util;
 pub mod variable;
+pub mod visitor;
+pub mod warnings;
 
 pub use asm::*;
+pub use cfg::*;
+pub use dead_code::*;
 pub use function::*;
 pub use if_op::*;
 pub use intermediate_representation::*;
 pub use ir_op::*;
+pub use jump_threading::*;
+pub use liveness::*;
 pub use loops::*;
 pub use mindustry::*;
+pub use pass::*;
+pub use peephole::*;
+pub use stack_analysis::*;
+pub use temporaries::*;
+pub use text::*;
+pub use unused::*;
 pub use util::*;
 pub use variable::*;
+pub use visitor::*;
+pub use warnings::*;
@@ -0,0 +1,110 @@
+use crate::*;
+
+/// Whether a pass did anything.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Changed {
+    Yes,
+    No,
+}
+
+impl From<bool> for Changed {
+    fn from(changed: bool) -> Changed {
+        if changed {
+            Changed::Yes
+        } else {
+            Changed::No
+        }
+    }
+}
+
+/// A transformation of the IR once it has been parsed.
+///
+/// Addresses are fixed as the program is parsed, but a pass run by a
+/// `PassManager` may still change the size of ops: the manager relocates them
+/// afterwards. To remove ops, use `IntermediateRepresentation::remove_ops`,
+/// which relocates them itself. A pass must not otherwise add or remove ops.
+pub trait IrPass {
+    fn name(&self) -> &'static str;
+
+    fn run(&self, ir: &mut IntermediateRepresentation) -> Result<Changed>;
+}
+
+/// Runs passes in the order they were added, repeating them all until none
+/// makes a change, since one pass may open up opportunities for another.
+pub struct PassManager {
+    passes: Vec<Box<dyn IrPass>>,
+
+    // Gives up on reaching a fixpoint after this many rounds.
+    max_iterations: usize,
+}
+
+impl Default for PassManager {
+    fn default() -> PassManager {
+        PassManager {
+            passes: Vec::default(),
+            max_iterations: 16,
+        }
+    }
+}
+
+impl PassManager {
+    /// The passes enabled by `options`.
+    pub fn with_options(options: &parser::CompileOptions) -> PassManager {
+        let mut manager = PassManager::default();
+        if options.thread_jumps {
+            manager.add(JumpThreading);
+        }
+        if options.eliminate_dead_code || options.strip_unused_functions {
+            manager.add(DeadCodeElimination {
+                only_functions: !options.eliminate_dead_code,
+            });
+        }
+        if options.peephole {
+            manager.add(SetForwarding);
+        }
+        manager
+    }
+
+    pub fn add<P: IrPass + 'static>(&mut self, pass: P) -> &mut PassManager {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    pub fn set_max_iterations(&mut self, max_iterations: usize) -> &mut PassManager {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// The names of the passes, in the order they run.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    pub fn run(&self, ir: &mut IntermediateRepresentation) -> Result<Changed> {
+        let mut any = Changed::No;
+        for round in 0..self.max_iterations {
+            let mut changed = Changed::No;
+            for pass in self.passes.iter() {
+                let before = ir.op_addresses();
+                let result = pass
+                    .run(ir)
+                    .with_context(|| format!("pass {}", pass.name()))?;
+
+                // Ops removed with `remove_ops` are already relocated.
+                if result == Changed::Yes && ir.ops().len() + 1 == before.len() {
+                    ir.relocate(&before)
+                        .with_context(|| format!("relocating after pass {}", pass.name()))?;
+                }
+                log::debug!("pass {}, round {}: {:?}", pass.name(), round, result);
+                if result == Changed::Yes {
+                    changed = Changed::Yes;
+                }
+            }
+            if changed == Changed::No {
+                break;
+            }
+            any = Changed::Yes;
+        }
+        Ok(any)
+    }
+}
@@ -0,0 +1,230 @@
+use std::convert::TryFrom;
+
+use crate::*;
+
+/// Rewrites ops into fewer instructions as they are parsed, by looking at each
+/// op together with the one before it. What depends on how a value is used
+/// later is left to `SetForwarding`, once the whole program is known.
+///
+/// Since addresses are fixed as the program is parsed, an op may only be
+/// merged into the one before it if nothing can jump between them, which the
+/// parser tracks by source line. See `is_straight_line`.
+#[derive(Debug)]
+pub struct Peephole {
+    // The default stack pointer, with the program's variable prefix.
+    stack_sz: MindustryTerm,
+
+    // Instructions saved so far.
+    pub saved: usize,
+}
+
+/// What to do with an op, as decided by `Peephole::rewrite`.
+pub enum Rewrite {
+    /// Emit the op as usual.
+    Keep(IrOp),
+
+    /// The op does nothing, so leave it out.
+    Drop,
+
+    /// Replace the previous op with this one, or just remove the previous op
+    /// if `None`.
+    MergeWithLast(Option<IrOp>),
+}
+
+impl Peephole {
+    /// For a program whose own variables start with `prefix`.
+    pub fn new(prefix: &str) -> Peephole {
+        Peephole {
+            stack_sz: MindustryTerm::stack_sz(prefix),
+            saved: 0,
+        }
+    }
+
+    /// An optimizer for another part of the same program, which has saved
+    /// nothing yet.
+    pub fn fork(&self) -> Peephole {
+        Peephole {
+            stack_sz: self.stack_sz.clone(),
+            saved: 0,
+        }
+    }
+
+    /// Rewrites `op`, which follows `last` if nothing can jump between them.
+    pub fn rewrite(&mut self, last: Option<&IrOp>, op: IrOp) -> Rewrite {
+        let rewrite = match (last, op) {
+            // `set x x`
+            (_, IrOp::Set(set)) if set.dest == set.source => Rewrite::Drop,
+
+            // `op add MF_stack_sz MF_stack_sz 1` twice.
+            (Some(IrOp::Math(last)), IrOp::Math(op))
+                if self.stack_adjustment(last).is_some()
+                    && self.stack_adjustment(&op).is_some() =>
+            {
+                let total =
+                    self.stack_adjustment(last).unwrap() + self.stack_adjustment(&op).unwrap();
+                Rewrite::MergeWithLast(adjust_stack(total, op))
+            }
+
+            (_, op) => Rewrite::Keep(op),
+        };
+
+        self.saved += match &rewrite {
+            Rewrite::Keep(..) => 0,
+            Rewrite::Drop | Rewrite::MergeWithLast(Some(..)) => 1,
+            Rewrite::MergeWithLast(None) => 2,
+        };
+        rewrite
+    }
+
+    /// The amount by which `op` moves the default stack pointer, if it adds or
+    /// subtracts a constant.
+    fn stack_adjustment(&self, op: &MathOp) -> Option<isize> {
+        if op.dest != self.stack_sz || op.arg1 != self.stack_sz {
+            return None;
+        }
+
+        let amount: isize = op.arg2.as_ref().parse().ok()?;
+        match op.operation.as_str() {
+            "add" => Some(amount),
+            "sub" => Some(-amount),
+            _ => None,
+        }
+    }
+}
+
+/// Whether the ops of this source line may be merged with those of an
+/// adjacent line. Anything that may be jumped to, or jumps, is excluded.
+pub fn is_straight_line(tok: &[&str]) -> bool {
+    const CONTROL_FLOW: &[&str] = &[
+        "}",
+        "if",
+        "while",
+        "do",
+        "loop",
+        "break",
+        "continue",
+        "fn",
+        "return",
+        "call",
+        "callproc",
+        "ret",
+        "jump",
+        "asm",
+        "let",
+        "end",
+        "busywait",
+        "benchmark_lap",
+    ];
+
+    match tok.first() {
+        None => false,
+        Some(first) => {
+            !first.ends_with(':')
+                && !first.starts_with("//")
+                && tok.last() != Some(&"{")
+                && !CONTROL_FLOW.contains(first)
+        }
+    }
+}
+
+fn adjust_stack(total: isize, mut op: MathOp) -> Option<IrOp> {
+    if total == 0 {
+        return None;
+    }
+
+    let operation = if total > 0 { "add" } else { "sub" };
+    op.operation = Symbol::new(operation);
+    op.arg2 = MindustryTerm::try_from(total.abs().to_string().as_str()).ok()?;
+    Some(IrOp::Math(op))
+}
+
+/// Folds `set tmp x` into the op right after it that reads `tmp`, where
+/// nothing reads that value of `tmp` afterwards, e.g.:
+///
+/// set tmp x
+/// op add y tmp 1
+///
+/// becomes `op add y x 1`. Whether the value is read again comes from
+/// `Liveness`, so a read in an inline function counts at every call it's
+/// copied to, and one in a loop or after a call is seen too.
+///
+/// Nothing is folded if the program has an instruction that writes
+/// `@counter`, since control could then go anywhere, nor into the start of a
+/// block, which something may jump to. The stack pointers are left alone, as
+/// the stack ops read them without saying so in their `Effects`.
+pub struct SetForwarding;
+
+impl IrPass for SetForwarding {
+    fn name(&self) -> &'static str {
+        "set-forwarding"
+    }
+
+    fn run(&self, ir: &mut IntermediateRepresentation) -> Result<Changed> {
+        let cfg = ir.cfg()?;
+        if cfg
+            .blocks
+            .iter()
+            .any(|block| block.exit == Some(Exit::Unknown))
+        {
+            log::debug!("not forwarding sets, since control flow is not fully known");
+            return Ok(Changed::No);
+        }
+        let liveness = Liveness::new(ir, &cfg);
+        let prefix = &ir.variable_prefix;
+        let stack_sz = MindustryTerm::stack_sz(prefix);
+        let is_stack_size = |term: &MindustryTerm| {
+            *term == stack_sz || term.as_ref().starts_with(&format!("{}_", stack_sz))
+        };
+
+        let mut remove = vec![false; ir.ops().len()];
+        for block in cfg.blocks.iter() {
+            for j in block.ops.start..block.ops.end.saturating_sub(1) {
+                let (tmp, source) = match &ir.ops[j] {
+                    IrOp::Set(set) if !remove[j] => (set.dest.clone(), set.source.clone()),
+                    _ => continue,
+                };
+                let writes = Effects::new(&ir.ops[j], prefix).writes;
+                if !writes.contains(tmp.as_ref()) || is_stack_size(&tmp) {
+                    continue;
+                }
+
+                // Where `op` writes `tmp`, the value it replaces isn't needed.
+                let op = &ir.ops[j + 1];
+                let effects = Effects::new(op, prefix);
+                if !effects.reads.contains(tmp.as_ref())
+                    || (liveness.live_after[j + 1].contains(tmp.as_ref())
+                        && !effects.writes.contains(tmp.as_ref()))
+                {
+                    continue;
+                }
+
+                match &mut ir.ops[j + 1] {
+                    IrOp::Set(op) => {
+                        op.source = source;
+                        remove[j + 1] = op.dest == op.source;
+                    }
+                    IrOp::Math(op) => {
+                        if op.arg1 == tmp {
+                            op.arg1 = source.clone();
+                        }
+                        if op.arg2 == tmp {
+                            op.arg2 = source;
+                        }
+                    }
+                    _ => continue,
+                }
+                remove[j] = true;
+            }
+        }
+
+        let removed = remove.iter().filter(|removed| **removed).count();
+        if removed == 0 {
+            return Ok(Changed::No);
+        }
+
+        log::debug!("forwarding {} sets into the op after them", removed);
+        ir.remove_ops(&remove)?;
+        ir.peephole_saved += removed;
+        Ok(Changed::Yes)
+    }
+}
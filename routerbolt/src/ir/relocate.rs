@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use crate::*;
+
+/// Where each address of the program moves to when the ops change size or
+/// are removed.
+///
+/// The parser fixes every address the IR holds, such as where a loop ends or
+/// where a label is, from the sizes of the ops before it. These always fall
+/// at the start of an op, or past the end of the program in the debug
+/// handlers and stack tables that follow it, so knowing where each op moves
+/// is enough to move them all.
+struct Relocation {
+    moved: HashMap<Address, Address>,
+    old_end: Address,
+    new_end: Address,
+}
+
+impl Relocation {
+    /// From the old and new address of each op, then of the end of the
+    /// program. Where several ops start at the same address, only the first
+    /// of them is used, so code inserted by growing an op that generated
+    /// nothing lands after the jumps to that address rather than before.
+    fn new(before: &[Address], after: &[Address]) -> Relocation {
+        let mut moved = HashMap::default();
+        for (old, new) in before.iter().zip(after.iter()) {
+            moved.entry(*old).or_insert(*new);
+        }
+
+        Relocation {
+            moved,
+            old_end: *before.last().unwrap(),
+            new_end: *after.last().unwrap(),
+        }
+    }
+
+    fn apply(&self, address: Address) -> Result<Address> {
+        if address > self.old_end {
+            return Ok(self.new_end + (address - self.old_end));
+        }
+
+        self.moved.get(&address).copied().with_context(|| {
+            format!(
+                "Internal error: can't relocate {}, which is not the start of an op",
+                address
+            )
+        })
+    }
+}
+
+impl IntermediateRepresentation {
+    /// The address of each op, from the sizes of those before it, followed
+    /// by the address just past the last.
+    pub fn op_addresses(&self) -> Vec<Address> {
+        let mut addresses = Vec::with_capacity(self.ops.len() + 1);
+        let mut address: Address = 0.into();
+        for op in self.ops.iter() {
+            addresses.push(address);
+            address += op.code_size(self.backend);
+        }
+        addresses.push(address);
+        addresses
+    }
+
+    /// Moves every address the IR holds from the layout `before`, as
+    /// returned by `op_addresses`, to where the ops are now. This lets a pass
+    /// change the size of ops, so long as it keeps their number. See
+    /// `PassManager`, which does this after each pass.
+    pub fn relocate(&mut self, before: &[Address]) -> Result<()> {
+        if before.len() != self.ops.len() + 1 {
+            bail!(
+                "Internal error: relocating {} ops from a layout of {}",
+                self.ops.len(),
+                before.len() - 1
+            );
+        }
+
+        let after = self.op_addresses();
+        self.move_addresses(&Relocation::new(before, &after))
+    }
+
+    /// Removes the ops for which `remove` is true, and moves the addresses of
+    /// the rest to match. An address of a removed op becomes that of the next
+    /// op kept, and breaks and continues are renumbered to the loop they're
+    /// in, which must be kept if they are.
+    pub fn remove_ops(&mut self, remove: &[bool]) -> Result<()> {
+        if remove.len() != self.ops.len() {
+            bail!(
+                "Internal error: removing from {} ops with {} flags",
+                self.ops.len(),
+                remove.len()
+            );
+        }
+
+        let before = self.op_addresses();
+
+        // The new position and address of each op, removed or not.
+        let mut index = Vec::with_capacity(self.ops.len());
+        let mut after = Vec::with_capacity(self.ops.len() + 1);
+        let mut kept = 0;
+        let mut address: Address = 0.into();
+        for (j, (op, removed)) in self.ops.iter().zip(remove.iter()).enumerate() {
+            if let IrOp::Break(BreakOp { index: loop_index })
+            | IrOp::Continue(ContinueOp { index: loop_index }) = op
+            {
+                if !removed && remove[**loop_index] {
+                    bail!(
+                        "Internal error: removing the loop of the break or continue at op {}",
+                        j
+                    );
+                }
+            }
+
+            index.push(kept);
+            after.push(address);
+            if !removed {
+                kept += 1;
+                address += op.code_size(self.backend);
+            }
+        }
+        after.push(address);
+
+        let ops = std::mem::take(&mut self.ops);
+        let op_lines = std::mem::take(&mut self.op_lines);
+        for ((mut op, line), removed) in ops.into_iter().zip(op_lines).zip(remove.iter()) {
+            if *removed {
+                continue;
+            }
+
+            if let IrOp::Break(BreakOp { index: loop_index })
+            | IrOp::Continue(ContinueOp { index: loop_index }) = &mut op
+            {
+                *loop_index = index[**loop_index].into();
+            }
+            self.ops.push(op);
+            self.op_lines.push(line);
+        }
+
+        self.move_addresses(&Relocation::new(&before, &after))
+    }
+
+    fn move_addresses(&mut self, relocation: &Relocation) -> Result<()> {
+        let relocate = |address| relocation.apply(address);
+
+        for op in self.ops.iter_mut() {
+            op.relocate(&relocate)?;
+        }
+
+        self.program_start = relocate(self.program_start)?;
+        for address in self.labels.values_mut() {
+            *address = relocate(*address)?;
+        }
+        for address in self.debug_handlers.values_mut() {
+            *address = relocate(*address)?;
+        }
+        for function in self.functions.values_mut() {
+            let function = Shared::make_mut(function);
+            function.address = function.address.map(relocate).transpose()?;
+            function.end = function.end.map(relocate).transpose()?;
+        }
+
+        self.backend_params.relocate(&relocate)?;
+        for named in self.named_stacks.iter_mut() {
+            named.backend_params.relocate(&relocate)?;
+        }
+
+        Ok(())
+    }
+}
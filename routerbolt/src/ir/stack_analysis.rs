@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use crate::*;
+
+/// The worst-case number of entries function calls may occupy on the default
+/// stack at once.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StackUsage {
+    /// Calls never use more than this many entries.
+    Bounded(usize),
+
+    /// A function may call itself, directly or through others. `line` is that
+    /// of the call that closes the cycle.
+    Recursive {
+        cycle: Vec<FunctionName>,
+        line: usize,
+    },
+
+    /// The program moves the stack pointer itself with `instruction`, which
+    /// the analysis can't follow.
+    Manual {
+        #[serde(deserialize_with = "deserialize_instruction")]
+        instruction: InstructionName,
+        line: usize,
+    },
+}
+
+impl std::fmt::Display for StackUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StackUsage::Bounded(depth) => write!(f, "at most {} entries", depth),
+            StackUsage::Recursive { cycle, line } => {
+                let cycle: Vec<&str> = cycle.iter().map(|f| f.as_ref()).collect();
+                write!(
+                    f,
+                    "unbounded due to recursion at line {} ({})",
+                    line + 1,
+                    cycle.join(" -> ")
+                )
+            }
+            StackUsage::Manual { instruction, line } => {
+                write!(f, "unknown due to `{}` at line {}", instruction, line + 1)
+            }
+        }
+    }
+}
+
+/// Finds the most entries function calls can occupy on the default stack at
+/// once, by walking the call graph from the calls made outside any function.
+/// Each active call holds its return address plus the callee's locals.
+///
+/// `lines` gives the source line of each op, and `frame_size` the number of
+/// locals (including args) of a function.
+pub fn analyze_stack_usage<F>(ops: &[IrOp], lines: &[usize], frame_size: F) -> StackUsage
+where
+    F: Fn(&FunctionName) -> Option<usize>,
+{
+    let mut calls: HashMap<Option<FunctionName>, Vec<(FunctionName, usize)>> = HashMap::default();
+    for (op, line) in ops.iter().zip(lines.iter().copied()) {
+        let instruction = match op {
+            IrOp::Call(call) => {
+                calls
+                    .entry(call.call_site_function.clone())
+                    .or_default()
+                    .push((call.target_function.clone(), line));
+                continue;
+            }
+            IrOp::CallProc(..) => "callproc",
+            IrOp::Push(PushOp {
+                stack: StackRef::Default,
+            }) => "push",
+            IrOp::Pop(PopOp {
+                stack: StackRef::Default,
+            }) => "pop",
+            _ => continue,
+        };
+
+        return StackUsage::Manual { instruction, line };
+    }
+
+    let mut analysis = CallDepth {
+        calls: &calls,
+        frame_size: &frame_size,
+        depths: HashMap::default(),
+        path: Vec::default(),
+    };
+
+    let mut deepest = 0;
+    for (target, line) in calls.get(&None).into_iter().flatten() {
+        match analysis.depth(target, *line) {
+            Ok(depth) => deepest = deepest.max(depth),
+            Err(usage) => return usage,
+        }
+    }
+
+    StackUsage::Bounded(deepest)
+}
+
+struct CallDepth<'a, F> {
+    // The functions called from each function, or from outside any function,
+    // with the line of each call.
+    calls: &'a HashMap<Option<FunctionName>, Vec<(FunctionName, usize)>>,
+
+    frame_size: &'a F,
+
+    // Functions whose depth is already known.
+    depths: HashMap<FunctionName, usize>,
+
+    // Chain of calls currently being explored, to detect recursion.
+    path: Vec<FunctionName>,
+}
+
+impl<'a, F> CallDepth<'a, F>
+where
+    F: Fn(&FunctionName) -> Option<usize>,
+{
+    /// Stack entries used by a call to `function` made at `line`, including
+    /// all its callees, or the recursion that makes that unbounded.
+    fn depth(&mut self, function: &FunctionName, line: usize) -> Result<usize, StackUsage> {
+        if let Some(depth) = self.depths.get(function) {
+            return Ok(*depth);
+        }
+
+        if let Some(start) = self.path.iter().position(|f| f == function) {
+            let mut cycle = self.path[start..].to_vec();
+            cycle.push(function.clone());
+            return Err(StackUsage::Recursive { cycle, line });
+        }
+
+        // Calls to undefined functions are reported when parsing the call.
+        let frame = (self.frame_size)(function).unwrap_or(0);
+
+        self.path.push(function.clone());
+        let mut deepest = 0;
+        for (callee, line) in self
+            .calls
+            .get(&Some(function.clone()))
+            .into_iter()
+            .flatten()
+        {
+            deepest = deepest.max(self.depth(callee, *line)?);
+        }
+        self.path.pop();
+
+        // The return address and locals, plus whatever the deepest callee uses.
+        let depth = 1 + frame + deepest;
+        self.depths.insert(function.clone(), depth);
+        Ok(depth)
+    }
+}
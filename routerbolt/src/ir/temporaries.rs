@@ -0,0 +1,94 @@
+use std::convert::TryFrom;
+
+use crate::*;
+
+/// Hands out the globals that hold stack variables while a statement uses
+/// them, numbered `MF_t0`, `MF_t1`, and so on, with the program's variable
+/// prefix in place of `MF_`. Each value read gets its own
+/// temporary, so reading one argument never clobbers another, and temporaries
+/// are recycled once released so a program only needs as many as its busiest
+/// statement.
+///
+/// The internal backend's jump tables can only move values through `MF_acc`,
+/// and copying into a temporary costs an extra instruction, so there the last
+/// value a statement reads or writes stays in the accumulator. Everything that
+/// must survive past another stack access still gets a temporary.
+#[derive(Clone, Debug)]
+pub struct Temporaries {
+    backend: Backend,
+    prefix: String,
+    free: Vec<usize>,
+    next: usize,
+}
+
+impl Temporaries {
+    pub fn new(backend: Backend, prefix: &str) -> Temporaries {
+        Temporaries {
+            backend,
+            prefix: prefix.to_string(),
+            free: Vec::default(),
+            next: 0,
+        }
+    }
+
+    /// A temporary that no other live value is using.
+    pub fn allocate(&mut self) -> MindustryTerm {
+        let n = match self.free.pop() {
+            Some(n) => n,
+            None => {
+                self.next += 1;
+                self.next - 1
+            }
+        };
+        self.name(n)
+    }
+
+    /// Where to put a value that is used before the next stack access, which
+    /// is the accumulator on the internal backend.
+    pub fn allocate_last(&mut self) -> MindustryTerm {
+        match self.backend {
+            Backend::Internal => MindustryTerm::accumulator(&self.prefix),
+            Backend::External | Backend::Banked => self.allocate(),
+        }
+    }
+
+    /// Makes `term` available to be allocated again. Anything other than a
+    /// temporary, such as the accumulator or a user's variable, is ignored.
+    pub fn release(&mut self, term: &MindustryTerm) {
+        if let Some(n) = self.number(term) {
+            if n < self.next && !self.free.contains(&n) {
+                self.free.push(n);
+                // Hand out the lowest numbers first, so the same few are reused.
+                self.free.sort_unstable_by(|a, b| b.cmp(a));
+            }
+        }
+    }
+
+    /// Releases every temporary, e.g. at the end of a statement.
+    pub fn release_all(&mut self) {
+        self.free = (0..self.next).rev().collect();
+    }
+
+    /// The number of distinct temporaries allocated so far.
+    pub fn count(&self) -> usize {
+        self.next
+    }
+
+    /// Whether `term` is the accumulator, which the internal backend's stack
+    /// accesses read and write without a copy.
+    pub fn is_accumulator(&self, term: &MindustryTerm) -> bool {
+        self.backend == Backend::Internal && *term == MindustryTerm::accumulator(&self.prefix)
+    }
+
+    fn name(&self, n: usize) -> MindustryTerm {
+        MindustryTerm::try_from(format!("{}t{}", self.prefix, n).as_str()).unwrap()
+    }
+
+    fn number(&self, term: &MindustryTerm) -> Option<usize> {
+        term.as_ref()
+            .strip_prefix(self.prefix.as_str())?
+            .strip_prefix('t')?
+            .parse()
+            .ok()
+    }
+}
@@ -0,0 +1,707 @@
+use serde::ser::{self, Serializer};
+use serde_json::{Map, Number, Value};
+
+use crate::*;
+
+/// Writes the IR in a readable textual form, which `parse_ir` reads back.
+///
+/// Each field of the IR goes on its own line as `name: value`, and the ops
+/// and other lists one entry per line. Values look like Rust's `Debug`
+/// output, with enum variants written as `Math { dest: x, ... }` or
+/// `Bounded(4)`, and strings left unquoted where that's unambiguous:
+///
+/// ops: [
+///   Set { source: "0", dest: i },  # 3 @3
+///   ...
+/// ]
+///
+/// Everything after `#` on a line is a comment. Each op is followed by one
+/// giving its index and address.
+pub fn print(ir: &IntermediateRepresentation) -> Result<String> {
+    let fields = match ir.serialize(NodeSerializer)? {
+        Node::Map(fields) => fields,
+        _ => bail!("the IR should serialize to a struct"),
+    };
+
+    let mut address: Address = 0.into();
+    let mut op_addresses = Vec::default();
+    for op in ir.ops().iter() {
+        op_addresses.push(address);
+        address += op.code_size(*ir.backend());
+    }
+
+    let mut text = String::default();
+    for (name, value) in fields.iter() {
+        text += name;
+        text += ":";
+        match value {
+            Node::Seq(items) if !items.is_empty() && !items.iter().all(Node::is_scalar) => {
+                text += " [\n";
+                for (j, item) in items.iter().enumerate() {
+                    text += "  ";
+                    item.write(&mut text);
+                    text += ",";
+                    if name == "ops" {
+                        text += &format!("  # {} @{}", j, op_addresses[j]);
+                    }
+                    text += "\n";
+                }
+                text += "]";
+            }
+            Node::Map(entries) if !entries.is_empty() => {
+                text += " {\n";
+                for (key, value) in entries.iter() {
+                    text += "  ";
+                    write_str(key, &mut text);
+                    text += ": ";
+                    value.write(&mut text);
+                    text += ",\n";
+                }
+                text += "}";
+            }
+            value => {
+                text += " ";
+                value.write(&mut text);
+            }
+        }
+        text += "\n";
+    }
+    Ok(text)
+}
+
+/// Reads the IR from the output of `print`. As with
+/// `IntermediateRepresentation::from_json`, the ops are taken as they are.
+pub fn parse_ir(text: &str) -> Result<IntermediateRepresentation> {
+    let mut parser = TextParser {
+        tokens: tokenize(text)?,
+        next: 0,
+    };
+    let fields = parser.fields(None)?;
+    Ok(serde_json::from_value(Value::Object(fields))?)
+}
+
+/// A serialized value, before it's written out. Unlike `serde_json::Value`,
+/// struct fields keep the order they're declared in.
+#[derive(Debug)]
+enum Node {
+    Null,
+    Bool(bool),
+    Number(String),
+    Str(String),
+    Seq(Vec<Node>),
+    Map(Vec<(String, Node)>),
+    Variant(&'static str, Box<Node>),
+    TupleVariant(&'static str, Vec<Node>),
+}
+
+impl Node {
+    fn is_scalar(&self) -> bool {
+        matches!(self, Node::Null | Node::Bool(..) | Node::Number(..))
+    }
+
+    fn write(&self, text: &mut String) {
+        match self {
+            Node::Null => *text += "null",
+            Node::Bool(value) => *text += &value.to_string(),
+            Node::Number(value) => *text += value,
+            Node::Str(value) => write_str(value, text),
+            Node::Seq(items) => {
+                text.push('[');
+                write_list(items, text);
+                text.push(']');
+            }
+            Node::Map(entries) => {
+                if entries.is_empty() {
+                    *text += "{}";
+                    return;
+                }
+                *text += "{ ";
+                for (j, (key, value)) in entries.iter().enumerate() {
+                    if j > 0 {
+                        *text += ", ";
+                    }
+                    write_str(key, text);
+                    *text += ": ";
+                    value.write(text);
+                }
+                *text += " }";
+            }
+            Node::Variant(name, value) => {
+                *text += name;
+                if let Node::Map(..) = **value {
+                    text.push(' ');
+                    value.write(text);
+                } else {
+                    text.push('(');
+                    value.write(text);
+                    text.push(')');
+                }
+            }
+            Node::TupleVariant(name, items) => {
+                *text += name;
+                text.push('(');
+                // A single field would read back as a newtype.
+                if items.len() == 1 {
+                    text.push('[');
+                    write_list(items, text);
+                    text.push(']');
+                } else {
+                    write_list(items, text);
+                }
+                text.push(')');
+            }
+        }
+    }
+}
+
+fn write_list(items: &[Node], text: &mut String) {
+    for (j, item) in items.iter().enumerate() {
+        if j > 0 {
+            *text += ", ";
+        }
+        item.write(text);
+    }
+}
+
+/// Writes a string bare, unless it would read back as something else.
+fn write_str(value: &str, text: &mut String) {
+    let bare = match value.chars().next() {
+        Some(c) => c.is_alphabetic() || "_@*$".contains(c),
+        None => false,
+    } && value.chars().all(is_bare)
+        && !matches!(value, "null" | "true" | "false");
+    if bare {
+        *text += value;
+    } else {
+        *text += &serde_json::to_string(value).unwrap();
+    }
+}
+
+fn is_bare(c: char) -> bool {
+    c.is_alphanumeric() || "_@*$.-".contains(c)
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Punct(char),
+    Bare(String),
+    Quoted(String),
+}
+
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::default();
+    for (line_no, line) in text.lines().enumerate() {
+        let line_no = line_no + 1;
+        let mut chars = line.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            if c.is_whitespace() {
+                continue;
+            } else if c == '#' {
+                break;
+            } else if "{}[]():,".contains(c) {
+                tokens.push((Token::Punct(c), line_no));
+            } else if c == '"' {
+                let mut escaped = false;
+                let mut end = None;
+                for (j, c) in chars.by_ref() {
+                    if escaped {
+                        escaped = false;
+                    } else if c == '\\' {
+                        escaped = true;
+                    } else if c == '"' {
+                        end = Some(j);
+                        break;
+                    }
+                }
+                let end = end.with_context(|| format!("line {}: unterminated string", line_no))?;
+                let value = serde_json::from_str(&line[start..=end])
+                    .with_context(|| format!("line {}: invalid string", line_no))?;
+                tokens.push((Token::Quoted(value), line_no));
+            } else {
+                let mut end = start + c.len_utf8();
+                while let Some((j, c)) = chars.peek() {
+                    if !is_bare(*c) && *c != '+' {
+                        break;
+                    }
+                    end = j + c.len_utf8();
+                    chars.next();
+                }
+                tokens.push((Token::Bare(line[start..end].to_string()), line_no));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct TextParser {
+    tokens: Vec<(Token, usize)>,
+    next: usize,
+}
+
+impl TextParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(token, _)| token)
+    }
+
+    fn take(&mut self) -> Result<&Token> {
+        let (token, _) = self
+            .tokens
+            .get(self.next)
+            .context("unexpected end of input")?;
+        self.next += 1;
+        Ok(token)
+    }
+
+    fn line(&self) -> usize {
+        match self.tokens.get(self.next) {
+            Some((_, line)) => *line,
+            None => self.tokens.last().map_or(0, |(_, line)| *line),
+        }
+    }
+
+    fn at(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Punct(c))
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        let line = self.line();
+        match self.take()? {
+            Token::Punct(found) if *found == c => Ok(()),
+            token => bail!("line {}: expected '{}', found {:?}", line, c, token),
+        }
+    }
+
+    /// Fields up to `close`, or to the end of the input if none.
+    fn fields(&mut self, close: Option<char>) -> Result<Map<String, Value>> {
+        let mut fields = Map::new();
+        loop {
+            match close {
+                Some(c) if self.at(c) => {
+                    self.next += 1;
+                    break;
+                }
+                None if self.peek().is_none() => break,
+                _ => (),
+            }
+
+            let line = self.line();
+            let key = match self.take()? {
+                Token::Bare(key) | Token::Quoted(key) => key.clone(),
+                token => bail!("line {}: expected a name, found {:?}", line, token),
+            };
+            self.expect(':')?;
+            let value = self.value()?;
+            fields.insert(key, value);
+            if self.at(',') {
+                self.next += 1;
+            }
+        }
+        Ok(fields)
+    }
+
+    fn values(&mut self, close: char) -> Result<Vec<Value>> {
+        let mut values = Vec::default();
+        while !self.at(close) {
+            values.push(self.value()?);
+            if !self.at(close) {
+                self.expect(',')?;
+            }
+        }
+        self.next += 1;
+        Ok(values)
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        let line = self.line();
+        let bare = match self.take()? {
+            Token::Punct('{') => return Ok(Value::Object(self.fields(Some('}'))?)),
+            Token::Punct('[') => return Ok(Value::Array(self.values(']')?)),
+            Token::Quoted(value) => return Ok(Value::String(value.clone())),
+            Token::Bare(value) => value.clone(),
+            token => bail!("line {}: expected a value, found {:?}", line, token),
+        };
+
+        // A variant with fields.
+        let inner = if self.at('{') {
+            self.next += 1;
+            Value::Object(self.fields(Some('}'))?)
+        } else if self.at('(') {
+            self.next += 1;
+            let mut values = self.values(')')?;
+            if values.len() == 1 {
+                values.pop().unwrap()
+            } else {
+                Value::Array(values)
+            }
+        } else {
+            return Ok(match bare.as_str() {
+                "null" => Value::Null,
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                _ => match bare.parse::<Number>() {
+                    Ok(number) => Value::Number(number),
+                    Err(..) => Value::String(bare),
+                },
+            });
+        };
+
+        let mut variant = Map::new();
+        variant.insert(bare, inner);
+        Ok(Value::Object(variant))
+    }
+}
+
+/// Builds a `Node` from any serializable value. Map entries are sorted by key,
+/// so that the output doesn't depend on hash order.
+struct NodeSerializer;
+
+type NodeResult = std::result::Result<Node, serde_json::Error>;
+
+impl Serializer for NodeSerializer {
+    type Ok = Node;
+    type Error = serde_json::Error;
+    type SerializeSeq = SeqBuilder;
+    type SerializeTuple = SeqBuilder;
+    type SerializeTupleStruct = SeqBuilder;
+    type SerializeTupleVariant = SeqBuilder;
+    type SerializeMap = MapBuilder;
+    type SerializeStruct = MapBuilder;
+    type SerializeStructVariant = MapBuilder;
+
+    fn serialize_bool(self, v: bool) -> NodeResult {
+        Ok(Node::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> NodeResult {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> NodeResult {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> NodeResult {
+        self.serialize_i64(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> NodeResult {
+        Ok(Node::Number(v.to_string()))
+    }
+
+    fn serialize_u8(self, v: u8) -> NodeResult {
+        self.serialize_u64(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> NodeResult {
+        self.serialize_u64(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> NodeResult {
+        self.serialize_u64(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> NodeResult {
+        Ok(Node::Number(v.to_string()))
+    }
+
+    fn serialize_f32(self, v: f32) -> NodeResult {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> NodeResult {
+        Ok(Node::Number(serde_json::to_string(&v)?))
+    }
+
+    fn serialize_char(self, v: char) -> NodeResult {
+        Ok(Node::Str(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> NodeResult {
+        Ok(Node::Str(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> NodeResult {
+        Ok(Node::Seq(
+            v.iter().map(|b| Node::Number(b.to_string())).collect(),
+        ))
+    }
+
+    fn serialize_none(self) -> NodeResult {
+        Ok(Node::Null)
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> NodeResult {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> NodeResult {
+        Ok(Node::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> NodeResult {
+        Ok(Node::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> NodeResult {
+        Ok(Node::Str(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> NodeResult {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> NodeResult {
+        Ok(Node::Variant(variant, Box::new(value.serialize(self)?)))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> std::result::Result<SeqBuilder, Self::Error> {
+        Ok(SeqBuilder::default())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> std::result::Result<SeqBuilder, Self::Error> {
+        Ok(SeqBuilder::default())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> std::result::Result<SeqBuilder, Self::Error> {
+        Ok(SeqBuilder::default())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> std::result::Result<SeqBuilder, Self::Error> {
+        Ok(SeqBuilder {
+            variant: Some(variant),
+            ..Default::default()
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> std::result::Result<MapBuilder, Self::Error> {
+        Ok(MapBuilder {
+            sort: true,
+            ..Default::default()
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> std::result::Result<MapBuilder, Self::Error> {
+        Ok(MapBuilder::default())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> std::result::Result<MapBuilder, Self::Error> {
+        Ok(MapBuilder {
+            variant: Some(variant),
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Default)]
+struct SeqBuilder {
+    variant: Option<&'static str>,
+    items: Vec<Node>,
+}
+
+impl SeqBuilder {
+    fn push<T: ?Sized + Serialize>(
+        &mut self,
+        value: &T,
+    ) -> std::result::Result<(), serde_json::Error> {
+        self.items.push(value.serialize(NodeSerializer)?);
+        Ok(())
+    }
+
+    fn finish(self) -> NodeResult {
+        Ok(match self.variant {
+            Some(variant) => Node::TupleVariant(variant, self.items),
+            None => Node::Seq(self.items),
+        })
+    }
+}
+
+impl ser::SerializeSeq for SeqBuilder {
+    type Ok = Node;
+    type Error = serde_json::Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(
+        &mut self,
+        value: &T,
+    ) -> std::result::Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> NodeResult {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SeqBuilder {
+    type Ok = Node;
+    type Error = serde_json::Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(
+        &mut self,
+        value: &T,
+    ) -> std::result::Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> NodeResult {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqBuilder {
+    type Ok = Node;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        value: &T,
+    ) -> std::result::Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> NodeResult {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqBuilder {
+    type Ok = Node;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        value: &T,
+    ) -> std::result::Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> NodeResult {
+        self.finish()
+    }
+}
+
+#[derive(Default)]
+struct MapBuilder {
+    variant: Option<&'static str>,
+    sort: bool,
+    key: Option<String>,
+    entries: Vec<(String, Node)>,
+}
+
+impl MapBuilder {
+    fn insert<T: ?Sized + Serialize>(
+        &mut self,
+        key: String,
+        value: &T,
+    ) -> std::result::Result<(), serde_json::Error> {
+        self.entries.push((key, value.serialize(NodeSerializer)?));
+        Ok(())
+    }
+
+    fn finish(mut self) -> NodeResult {
+        if self.sort {
+            self.entries.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+        let map = Node::Map(self.entries);
+        Ok(match self.variant {
+            Some(variant) => Node::Variant(variant, Box::new(map)),
+            None => map,
+        })
+    }
+}
+
+impl ser::SerializeMap for MapBuilder {
+    type Ok = Node;
+    type Error = serde_json::Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(
+        &mut self,
+        key: &T,
+    ) -> std::result::Result<(), Self::Error> {
+        self.key = Some(match key.serialize(NodeSerializer)? {
+            Node::Str(key) | Node::Number(key) => key,
+            key => return Err(ser::Error::custom(format!("unsupported map key {:?}", key))),
+        });
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(
+        &mut self,
+        value: &T,
+    ) -> std::result::Result<(), Self::Error> {
+        let key = self.key.take().unwrap();
+        self.insert(key, value)
+    }
+
+    fn end(self) -> NodeResult {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for MapBuilder {
+    type Ok = Node;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> std::result::Result<(), Self::Error> {
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(self) -> NodeResult {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for MapBuilder {
+    type Ok = Node;
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> std::result::Result<(), Self::Error> {
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(self) -> NodeResult {
+        self.finish()
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashSet;
+
+use crate::*;
+
+/// Something the program defines but never uses.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnusedSymbol {
+    /// A `let` stack variable that is never read, defined at `line`.
+    StackVar {
+        function: FunctionName,
+        name: StackVar,
+        line: usize,
+    },
+
+    /// A function with no calls, defined at `line`.
+    Function { name: FunctionName, line: usize },
+
+    /// A label that no `jump` or `callproc` targets, defined at `line`.
+    Label { name: LabelName, line: usize },
+}
+
+impl std::fmt::Display for UnusedSymbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            UnusedSymbol::StackVar {
+                function,
+                name,
+                line,
+            } => write!(
+                f,
+                "stack variable {} in function {} is never read (line {})",
+                name,
+                function,
+                line + 1
+            ),
+            UnusedSymbol::Function { name, line } => {
+                write!(f, "function {} is never called (line {})", name, line + 1)
+            }
+            UnusedSymbol::Label { name, line } => {
+                write!(f, "label {} is never jumped to (line {})", name, line + 1)
+            }
+        }
+    }
+}
+
+/// Finds the stack variables, functions, and labels the program never uses,
+/// in the order they are defined.
+///
+/// `lines` gives the source line of each op. Ops without one, from the setup
+/// code, define nothing.
+pub fn find_unused_symbols(ops: &[IrOp], lines: &[Option<usize>]) -> Vec<UnusedSymbol> {
+    let mut read: HashSet<(&FunctionName, &StackVar)> = HashSet::default();
+    let mut called: HashSet<&FunctionName> = HashSet::default();
+    let mut jumped_to: HashSet<&LabelName> = HashSet::default();
+    for op in ops.iter() {
+        match op {
+            IrOp::GetStack(op) => {
+                read.insert((&op.function, &op.stack));
+            }
+            IrOp::Call(call) => {
+                called.insert(&call.target_function);
+                if let Some(function) = &call.call_site_function {
+                    for arg in call.args.iter() {
+                        if let Term::StackVar(arg) = arg {
+                            read.insert((function, arg));
+                        }
+                    }
+                }
+            }
+            IrOp::Return(ret) => {
+                for value in ret.values.iter() {
+                    if let Term::StackVar(value) = value {
+                        read.insert((&ret.function, value));
+                    }
+                }
+            }
+            IrOp::Jump(jump) => {
+                jumped_to.insert(&jump.target);
+            }
+            IrOp::CallProc(callproc) => {
+                jumped_to.insert(&callproc.target);
+            }
+            _ => {}
+        }
+    }
+
+    let mut unused = Vec::default();
+    let mut function = None;
+    for (op, line) in ops.iter().zip(lines.iter().copied()) {
+        let line = match line {
+            Some(line) => line,
+            None => continue,
+        };
+        match op {
+            IrOp::Function(name, ..) => {
+                function = Some(name);
+                if !called.contains(name) {
+                    unused.push(UnusedSymbol::Function {
+                        name: name.clone(),
+                        line,
+                    });
+                }
+            }
+            IrOp::Let(op) => {
+                // Let is only allowed in a function, which is the last one
+                // begun.
+                if let Some(function) = function {
+                    if !read.contains(&(function, &op.name)) {
+                        unused.push(UnusedSymbol::StackVar {
+                            function: function.clone(),
+                            name: op.name.clone(),
+                            line,
+                        });
+                    }
+                }
+            }
+            IrOp::Label(label) if !jumped_to.contains(&label.target) => {
+                unused.push(UnusedSymbol::Label {
+                    name: label.target.clone(),
+                    line,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    unused
+}
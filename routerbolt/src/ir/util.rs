@@ -1,4 +1,6 @@
+use std::collections::{HashMap, HashSet};
 use std::convert::AsRef;
+use std::hash::Hash;
 
 use crate::*;
 
@@ -47,3 +49,74 @@ pub fn format_return_annotation(return_op: &ReturnOp, instr: usize) -> String {
         &return_op.function, returns_ann, instr
     )
 }
+
+/// The name of an instruction, as reported in diagnostics. This is an alias
+/// so that serde doesn't try to borrow it from the input; it's read back with
+/// `deserialize_instruction` instead.
+pub type InstructionName = &'static str;
+
+/// The instruction names `InstructionName` can hold.
+const INSTRUCTIONS: &[&str] = &[
+    "end", "return", "break", "continue", "ret", "jump", "callproc", "push", "pop", "stop", "loop",
+    "else",
+];
+
+pub fn deserialize_instruction<'de, D>(
+    deserializer: D,
+) -> std::result::Result<InstructionName, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    INSTRUCTIONS
+        .iter()
+        .find(|instruction| **instruction == name)
+        .copied()
+        .ok_or_else(|| serde::de::Error::custom(format!("unknown instruction {}", name)))
+}
+
+/// Serializes a set in order, so that the output doesn't vary from run to run.
+pub fn serialize_sorted<T, S>(
+    set: &HashSet<T>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error>
+where
+    T: Serialize + Ord,
+    S: serde::Serializer,
+{
+    let mut items: Vec<&T> = set.iter().collect();
+    items.sort();
+    serializer.collect_seq(items)
+}
+
+/// Serializes a map as a list of pairs sorted by key, for maps whose keys
+/// JSON can't represent.
+pub mod map_as_pairs {
+    use super::*;
+
+    pub fn serialize<K, V, S>(
+        map: &HashMap<K, V>,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error>
+    where
+        K: Serialize + Ord,
+        V: Serialize,
+        S: serde::Serializer,
+    {
+        let mut pairs: Vec<(&K, &V)> = map.iter().collect();
+        pairs.sort_by_key(|(key, _)| *key);
+        serializer.collect_seq(pairs)
+    }
+
+    pub fn deserialize<'de, K, V, D>(
+        deserializer: D,
+    ) -> std::result::Result<HashMap<K, V>, D::Error>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        V: Deserialize<'de>,
+        D: serde::Deserializer<'de>,
+    {
+        let pairs = Vec::<(K, V)>::deserialize(deserializer)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
@@ -32,7 +32,7 @@ use crate::*;
 /// set *my_var 10
 ///
 /// Destroys: None
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LetOp {
     pub name: StackVar,
     pub pos: FrameIndex,
@@ -68,19 +68,23 @@ impl Operation for LetOp {
 /// e.g.: `set mindustry_var *my_var`
 ///
 /// Destroys: All
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GetStackOp {
     pub global: MindustryTerm,
     pub stack: StackVar,
     pub function: FunctionName,
+
+    // Whether `global` is the accumulator, which the internal backend's jump
+    // tables read and write directly, with no copy.
+    pub accumulator: bool,
 }
 
 impl Operation for GetStackOp {
     fn code_size(&self, backend: Backend) -> AddressDelta {
         match backend {
-            Backend::Internal if self.global.as_ref() != "MF_acc" => 5,
+            Backend::Internal if !self.accumulator => 5,
             Backend::Internal => 4,
-            Backend::External => 2,
+            Backend::External | Backend::Banked => 1 + backend.cell_access_size(),
         }
         .into()
     }
@@ -103,20 +107,21 @@ impl Operation for GetStackOp {
         }
 
         let depth = ir.functions()[&self.function].stack_var_depth(&self.stack)?;
+        let mf = ir.variable_prefix.as_str();
 
         match ir.backend_params() {
             BackendParams::Internal(int) => {
-                output.push("op add MF_resume @counter 3".to_string());
-                output.push(format!("op sub MF_tmp MF_stack_sz {}", depth));
-                output.push(format!("op mul MF_tmp {} MF_tmp", int.pop_entry_size));
-                output.push(format!("op add @counter {} MF_tmp", int.pop_table_start));
-                if self.global.as_ref() != "MF_acc" {
-                    output.push(format!("set {} MF_acc", self.global.as_ref()));
+                output.push(format!("op add {mf}resume @counter 3"));
+                output.push(format!("op sub {mf}tmp {mf}stack_sz {}", depth));
+                output.push(format!("op mul {mf}tmp {} {mf}tmp", int.pop_entry_size));
+                output.push(format!("op add @counter {} {mf}tmp", int.pop_table_start));
+                if !self.accumulator {
+                    output.push(format!("set {} {mf}acc", self.global.as_ref()));
                 }
             }
             BackendParams::External(ext) => {
-                output.push(format!("op sub MF_tmp MF_stack_sz {}", depth));
-                output.push(format!("read {} {} MF_tmp", self.global, ext.cell_name));
+                output.push(format!("op sub {mf}tmp {mf}stack_sz {}", depth));
+                ext.read(mf, &self.global, format!("{mf}tmp"), output)?;
             }
         }
 
@@ -129,19 +134,23 @@ impl Operation for GetStackOp {
 /// e.g.: `set *my_var mindustry_var`
 ///
 /// Destroys: All
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SetStackOp {
     pub global: MindustryTerm,
     pub stack: StackVar,
     pub function: FunctionName,
+
+    // Whether `global` is the accumulator, which the internal backend's jump
+    // tables read and write directly, with no copy.
+    pub accumulator: bool,
 }
 
 impl Operation for SetStackOp {
     fn code_size(&self, backend: Backend) -> AddressDelta {
         match backend {
-            Backend::Internal if self.global.as_ref() != "MF_acc" => 5,
+            Backend::Internal if !self.accumulator => 5,
             Backend::Internal => 4,
-            Backend::External => 2,
+            Backend::External | Backend::Banked => 1 + backend.cell_access_size(),
         }
         .into()
     }
@@ -164,21 +173,22 @@ impl Operation for SetStackOp {
         }
 
         let depth = ir.functions()[&self.function].stack_var_depth(&self.stack)?;
+        let mf = ir.variable_prefix.as_str();
         let depth: usize = depth.into();
 
         match ir.backend_params() {
             BackendParams::Internal(int) => {
-                if self.global.as_ref() != "MF_acc" {
-                    output.push(format!("set MF_acc {}", self.global.as_ref()));
+                if !self.accumulator {
+                    output.push(format!("set {mf}acc {}", self.global.as_ref()));
                 }
-                output.push("op add MF_resume @counter 3".to_string());
-                output.push(format!("op sub MF_tmp MF_stack_sz {}", depth));
-                output.push(format!("op mul MF_tmp {} MF_tmp", int.poke_entry_size));
-                output.push(format!("op add @counter {} MF_tmp", int.poke_table_start));
+                output.push(format!("op add {mf}resume @counter 3"));
+                output.push(format!("op sub {mf}tmp {mf}stack_sz {}", depth));
+                output.push(format!("op mul {mf}tmp {} {mf}tmp", int.poke_entry_size));
+                output.push(format!("op add @counter {} {mf}tmp", int.poke_table_start));
             }
             BackendParams::External(ext) => {
-                output.push(format!("op sub MF_tmp MF_stack_sz {}", depth));
-                output.push(format!("write {} {} MF_tmp", self.global, ext.cell_name));
+                output.push(format!("op sub {mf}tmp {mf}stack_sz {}", depth));
+                ext.write(mf, &self.global, format!("{mf}tmp"), output)?;
             }
         }
 
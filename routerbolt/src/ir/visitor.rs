@@ -0,0 +1,108 @@
+use crate::*;
+
+/// Walks the IR, calling the method for each kind of op it finds. Every
+/// method does nothing by default, so an analysis overrides only those for
+/// the ops it cares about, rather than matching on all of `IrOp`. For
+/// example, to collect every jump target:
+///
+/// struct Targets(Vec<LabelName>);
+///
+/// impl IrVisitor for Targets {
+///     fn visit_jump(&mut self, op: &JumpOp) {
+///         self.0.push(op.target.clone());
+///     }
+/// }
+///
+/// let mut targets = Targets(Vec::default());
+/// targets.visit_ops(ir.ops());
+///
+/// `visit_while` also walks the ops that check the condition at the end of
+/// the loop. The parser places a copy of those in `ops` after the loop body,
+/// so a visitor walking the whole program sees them twice unless it overrides
+/// `visit_while`.
+pub trait IrVisitor {
+    fn visit_ops(&mut self, ops: &[IrOp]) {
+        for op in ops.iter() {
+            self.visit_op(op);
+        }
+    }
+
+    fn visit_sequence(&mut self, sequence: &IrSequence) {
+        self.visit_ops(&sequence.0);
+    }
+
+    fn visit_op(&mut self, op: &IrOp) {
+        walk_op(self, op);
+    }
+
+    fn visit_call_proc(&mut self, _op: &CallProcOp) {}
+    fn visit_label(&mut self, _op: &LabelOp) {}
+    fn visit_benchmark_lap(&mut self, _op: &BenchmarkLapOp) {}
+    fn visit_ret_proc(&mut self, _op: &RetProcOp) {}
+    fn visit_push(&mut self, _op: &PushOp) {}
+    fn visit_stack_guard(&mut self, _op: &StackGuardOp) {}
+    fn visit_pop(&mut self, _op: &PopOp) {}
+    fn visit_peek(&mut self, _op: &PeekOp) {}
+    fn visit_poke(&mut self, _op: &PokeOp) {}
+    fn visit_jump(&mut self, _op: &JumpOp) {}
+    fn visit_mindustry_command(&mut self, _op: &MindustryOp) {}
+    fn visit_if(&mut self, _op: &IfOp) {}
+    fn visit_else(&mut self, _op: &ElseOp) {}
+
+    fn visit_while(&mut self, op: &WhileOp) {
+        self.visit_sequence(op.end_sequence());
+    }
+
+    fn visit_do_while(&mut self, _op: &DoWhileOp) {}
+    fn visit_infinite_loop(&mut self, _op: &InfiniteLoopOp) {}
+    fn visit_break(&mut self, _op: &BreakOp) {}
+    fn visit_continue(&mut self, _op: &ContinueOp) {}
+    fn visit_loop_end(&mut self, _op: &LoopEndOp) {}
+    fn visit_busy_wait(&mut self, _op: &BusyWaitOp) {}
+    fn visit_let(&mut self, _op: &LetOp) {}
+    fn visit_get_stack(&mut self, _op: &GetStackOp) {}
+    fn visit_set_stack(&mut self, _op: &SetStackOp) {}
+    fn visit_set(&mut self, _op: &SetOp) {}
+    fn visit_math(&mut self, _op: &MathOp) {}
+    fn visit_sensor(&mut self, _op: &SensorOp) {}
+    fn visit_function(&mut self, _name: &FunctionName, _size: AddressDelta) {}
+    fn visit_call(&mut self, _op: &CallOp) {}
+    fn visit_return(&mut self, _op: &ReturnOp) {}
+}
+
+/// Calls the method of `visitor` for the kind of `op`. This is what
+/// `IrVisitor::visit_op` does, for visitors that override it to do something
+/// for every op and then carry on as usual.
+pub fn walk_op<V: IrVisitor + ?Sized>(visitor: &mut V, op: &IrOp) {
+    match op {
+        IrOp::CallProc(op) => visitor.visit_call_proc(op),
+        IrOp::Label(op) => visitor.visit_label(op),
+        IrOp::BenchmarkLap(op) => visitor.visit_benchmark_lap(op),
+        IrOp::RetProc(op) => visitor.visit_ret_proc(op),
+        IrOp::Push(op) => visitor.visit_push(op),
+        IrOp::StackGuard(op) => visitor.visit_stack_guard(op),
+        IrOp::Pop(op) => visitor.visit_pop(op),
+        IrOp::Peek(op) => visitor.visit_peek(op),
+        IrOp::Poke(op) => visitor.visit_poke(op),
+        IrOp::Jump(op) => visitor.visit_jump(op),
+        IrOp::MindustryCommand(op) => visitor.visit_mindustry_command(op),
+        IrOp::If(op) => visitor.visit_if(op),
+        IrOp::Else(op) => visitor.visit_else(op),
+        IrOp::While(op) => visitor.visit_while(op),
+        IrOp::DoWhile(op) => visitor.visit_do_while(op),
+        IrOp::InfiniteLoop(op) => visitor.visit_infinite_loop(op),
+        IrOp::Break(op) => visitor.visit_break(op),
+        IrOp::Continue(op) => visitor.visit_continue(op),
+        IrOp::LoopEnd(op) => visitor.visit_loop_end(op),
+        IrOp::BusyWait(op) => visitor.visit_busy_wait(op),
+        IrOp::Let(op) => visitor.visit_let(op),
+        IrOp::GetStack(op) => visitor.visit_get_stack(op),
+        IrOp::SetStack(op) => visitor.visit_set_stack(op),
+        IrOp::Set(op) => visitor.visit_set(op),
+        IrOp::Math(op) => visitor.visit_math(op),
+        IrOp::Sensor(op) => visitor.visit_sensor(op),
+        IrOp::Function(name, size) => visitor.visit_function(name, *size),
+        IrOp::Call(op) => visitor.visit_call(op),
+        IrOp::Return(op) => visitor.visit_return(op),
+    }
+}
@@ -0,0 +1,519 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ops::RangeInclusive;
+
+use crate::*;
+
+/// A kind of warning or note, by the name `#allow(...)` knows it by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// A stack variable, function, or label that is never used. See
+    /// `UnusedSymbol`.
+    Unused,
+
+    /// A variable a call may overwrite. See `ClobberedVariable`.
+    Clobbered,
+
+    /// A label in a function with the same name as one outside it, which
+    /// jumps in the function can no longer reach.
+    Shadowed,
+
+    /// Control can fall into a function from the code before it, or out of
+    /// the end of one without a `return`.
+    Fallthrough,
+
+    /// Code left out by dead code elimination. A note rather than a warning.
+    DeadCode,
+
+    /// An external stack that may not fit in the memory cell or bank it's
+    /// in, going by the cell's name. See `memory_capacity`.
+    StackCapacity,
+
+    /// A function of more than `LARGE_FUNCTION_SIZE` instructions. Only
+    /// checked when asked for, as by the compiler's `lint` mode.
+    LargeFunction,
+
+    /// A number in a statement, other than 0, 1, and -1, that would be clearer
+    /// set to a variable named for what it means. Only checked when asked
+    /// for.
+    MagicNumber,
+}
+
+/// The most instructions a function can have before `Lint::LargeFunction`
+/// suggests splitting it up.
+pub const LARGE_FUNCTION_SIZE: usize = 100;
+
+impl Lint {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::Unused => "unused",
+            Lint::Clobbered => "clobbered",
+            Lint::Shadowed => "shadowed",
+            Lint::Fallthrough => "fallthrough",
+            Lint::DeadCode => "dead_code",
+            Lint::StackCapacity => "stack_capacity",
+            Lint::LargeFunction => "large_function",
+            Lint::MagicNumber => "magic_number",
+        }
+    }
+
+    pub const ALL: &'static [Lint] = &[
+        Lint::Unused,
+        Lint::Clobbered,
+        Lint::Shadowed,
+        Lint::Fallthrough,
+        Lint::DeadCode,
+        Lint::StackCapacity,
+        Lint::LargeFunction,
+        Lint::MagicNumber,
+    ];
+
+    /// Those `find_warnings` checks for, which is all but the stylistic
+    /// `LargeFunction` and `MagicNumber`.
+    pub const DEFAULT: &'static [Lint] = &[
+        Lint::Unused,
+        Lint::Clobbered,
+        Lint::Shadowed,
+        Lint::Fallthrough,
+        Lint::DeadCode,
+        Lint::StackCapacity,
+    ];
+}
+
+impl TryFrom<&str> for Lint {
+    type Error = anyhow::Error;
+
+    fn try_from(name: &str) -> Result<Lint> {
+        Ok(match name {
+            "unused" => Lint::Unused,
+            "clobbered" => Lint::Clobbered,
+            "shadowed" => Lint::Shadowed,
+            "fallthrough" => Lint::Fallthrough,
+            "dead_code" => Lint::DeadCode,
+            "stack_capacity" => Lint::StackCapacity,
+            "large_function" => Lint::LargeFunction,
+            "magic_number" => Lint::MagicNumber,
+            _ => bail!(
+                "unknown warning {}; expected unused, clobbered, shadowed, fallthrough, dead_code, stack_capacity, large_function, or magic_number",
+                name
+            ),
+        })
+    }
+}
+
+impl std::fmt::Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Parses an `#allow(unused, shadowed)` directive, returning `None` if the
+/// line isn't one.
+pub fn parse_allow(line: &str) -> Result<Option<Vec<Lint>>> {
+    let rest = match line.trim().strip_prefix("#allow") {
+        Some(rest) => rest.trim(),
+        None => return Ok(None),
+    };
+
+    let names = rest
+        .strip_prefix('(')
+        .and_then(|rest| rest.strip_suffix(')'))
+        .context("form is `#allow(warning, ...)`")?;
+    names
+        .split(',')
+        .map(|name| Lint::try_from(name.trim()))
+        .collect::<Result<Vec<_>>>()
+        .map(Some)
+}
+
+/// The warnings and notes for a program, in order of line, of the kinds in
+/// `Lint::DEFAULT`.
+///
+/// An `#allow(...)` directive on its own line suppresses those named for the
+/// statement that follows it. If that statement opens a block, such as a
+/// function definition, they are suppressed for the whole block.
+pub fn find_warnings(ir: &IntermediateRepresentation) -> Vec<Diagnostic> {
+    find_lints(ir, Lint::DEFAULT)
+}
+
+/// The warnings and notes for a program of the kinds in `lints`, as
+/// `find_warnings` finds them.
+pub fn find_lints(ir: &IntermediateRepresentation, lints: &[Lint]) -> Vec<Diagnostic> {
+    let mut warnings = Vec::default();
+    let mut warn = |severity, lint, line: Option<usize>, message: String| {
+        if lints.contains(&lint) {
+            warnings.push(Diagnostic {
+                severity,
+                lint: Some(lint),
+                error_code: None,
+                line,
+                message,
+            })
+        }
+    };
+
+    for unused in ir.unused.iter() {
+        let line = match unused {
+            UnusedSymbol::StackVar { line, .. }
+            | UnusedSymbol::Function { line, .. }
+            | UnusedSymbol::Label { line, .. } => *line,
+        };
+        warn(
+            Severity::Warning,
+            Lint::Unused,
+            Some(line),
+            unused.to_string(),
+        );
+    }
+
+    // Finding these takes a liveness analysis, so only when they're wanted.
+    let clobbered = match lints.contains(&Lint::Clobbered) {
+        true => ir.clobbered(),
+        false => Vec::default(),
+    };
+    for clobbered in clobbered.iter() {
+        warn(
+            Severity::Warning,
+            Lint::Clobbered,
+            Some(clobbered.line),
+            clobbered.to_string(),
+        );
+    }
+
+    for (line, message) in find_shadowed_labels(ir) {
+        warn(
+            Severity::Warning,
+            Lint::Shadowed,
+            line,
+            with_line(message, line),
+        );
+    }
+
+    // The CFG can only fail to build for a program that fails to generate,
+    // which reports the problem itself.
+    if let Ok(cfg) = ir.cfg() {
+        for (line, message) in find_fallthrough(ir, &cfg) {
+            warn(
+                Severity::Warning,
+                Lint::Fallthrough,
+                line,
+                with_line(message, line),
+            );
+        }
+    }
+
+    for (line, message) in find_stack_capacity(ir) {
+        warn(
+            Severity::Warning,
+            Lint::StackCapacity,
+            line,
+            with_line(message, line),
+        );
+    }
+
+    if lints.contains(&Lint::LargeFunction) {
+        for (line, message) in find_large_functions(ir) {
+            warn(
+                Severity::Warning,
+                Lint::LargeFunction,
+                line,
+                with_line(message, line),
+            );
+        }
+    }
+
+    if lints.contains(&Lint::MagicNumber) {
+        for (line, number) in find_magic_numbers(&ir.source_lines) {
+            warn(
+                Severity::Warning,
+                Lint::MagicNumber,
+                Some(line),
+                with_line(
+                    format!("magic number {}; consider a named variable", number),
+                    Some(line),
+                ),
+            );
+        }
+    }
+
+    for dead_code in ir.dead_code.iter() {
+        warn(
+            Severity::Note,
+            Lint::DeadCode,
+            Some(dead_code.first_line),
+            format!("removed code that can never run at {}", dead_code),
+        );
+    }
+
+    let allowed = find_allowed(&ir.source_lines);
+    warnings.retain(|warning| {
+        let (lint, line) = match (warning.lint, warning.line) {
+            (Some(lint), Some(line)) => (lint, line),
+            _ => return true,
+        };
+        !allowed
+            .get(&lint)
+            .is_some_and(|ranges| ranges.iter().any(|range| range.contains(&line)))
+    });
+    warnings.sort_by_key(|warning| warning.line);
+    warnings
+}
+
+/// Ends the message with its line, as `UnusedSymbol` and `ClobberedVariable`
+/// do.
+fn with_line(message: String, line: Option<usize>) -> String {
+    match line {
+        Some(line) => format!("{} (line {})", message, line + 1),
+        None => message,
+    }
+}
+
+/// The lines on which each lint is suppressed by `#allow(...)`.
+fn find_allowed(source_lines: &[String]) -> HashMap<Lint, Vec<RangeInclusive<usize>>> {
+    let lines: Vec<Vec<&str>> = source_lines
+        .iter()
+        .map(|line| parser::lex_line(parser::clean_line(line)))
+        .collect();
+    let is_statement =
+        |tok: &Vec<&str>| !tok.is_empty() && !tok[0].starts_with("//") && !tok[0].starts_with('#');
+
+    let mut allowed: HashMap<Lint, Vec<RangeInclusive<usize>>> = HashMap::default();
+    for (j, line) in source_lines.iter().enumerate() {
+        // Errors were reported by the parser.
+        let lints = match parse_allow(line) {
+            Ok(Some(lints)) => lints,
+            _ => continue,
+        };
+
+        let start = match (j + 1..lines.len()).find(|k| is_statement(&lines[*k])) {
+            Some(start) => start,
+            None => continue,
+        };
+
+        let mut end = start;
+        if lines[start].last() == Some(&"{") {
+            let mut depth = 0;
+            for (k, tok) in lines.iter().enumerate().skip(start) {
+                if tok.first() == Some(&"}") {
+                    depth -= 1;
+                }
+                if tok.last() == Some(&"{") {
+                    depth += 1;
+                }
+                end = k;
+                if depth == 0 {
+                    break;
+                }
+            }
+        }
+
+        for lint in lints {
+            allowed.entry(lint).or_default().push(start..=end);
+        }
+    }
+    allowed
+}
+
+/// External stacks that may run past the end of the memory they're in: those
+/// whose `len` does, or without one, the default stack if function calls may
+/// need more entries than are left after its offset.
+fn find_stack_capacity(ir: &IntermediateRepresentation) -> Vec<(Option<usize>, String)> {
+    let stacks = std::iter::once((None, &ir.stack_config)).chain(
+        ir.named_stacks
+            .iter()
+            .map(|stack| (Some(&stack.name), &stack.stack_config)),
+    );
+
+    let mut found = Vec::default();
+    for (name, config) in stacks {
+        let ext = match config {
+            StackConfig::External(ext) => ext,
+            StackConfig::Internal(..) => continue,
+        };
+        let capacity = match ext.capacity() {
+            Some(capacity) => capacity,
+            None => continue,
+        };
+        let cells: Vec<String> = ext.cells().map(Symbol::to_string).collect();
+        let cells = cells.join(", ");
+        let stack = match name {
+            Some(name) => format!("stack {}", name),
+            None => "the stack".to_string(),
+        };
+
+        let message = match (ext.len, name, &ir.stack_usage) {
+            (Some(len), _, _) if ext.offset + len > capacity => format!(
+                "{} ends at address {} of {}, which only holds {} entries",
+                stack,
+                ext.offset + len,
+                cells,
+                capacity
+            ),
+            (None, None, StackUsage::Bounded(depth)) if ext.offset + depth > capacity => format!(
+                "function calls may use {} stack entries, but {} only holds {} from address {}",
+                depth,
+                cells,
+                capacity.saturating_sub(ext.offset),
+                ext.offset
+            ),
+            _ => continue,
+        };
+        found.push((find_stack_config_line(&ir.source_lines, name), message));
+    }
+    found
+}
+
+/// The line of the `stack_config` for the stack, or for the default stack
+/// if `name` is `None`.
+fn find_stack_config_line(source_lines: &[String], name: Option<&StackName>) -> Option<usize> {
+    source_lines.iter().position(|line| {
+        let tok = parser::lex_line(parser::clean_line(line));
+        let named = tok.iter().position(|tok| *tok == "as");
+        tok.first() == Some(&"stack_config")
+            && match (name, named) {
+                (Some(name), Some(j)) => tok.get(j + 1) == Some(&name.to_string().as_str()),
+                (None, None) => true,
+                _ => false,
+            }
+    })
+}
+
+/// Functions longer than `LARGE_FUNCTION_SIZE`, by the line they're defined
+/// on.
+fn find_large_functions(ir: &IntermediateRepresentation) -> Vec<(Option<usize>, String)> {
+    let mut found = Vec::default();
+    for function in ir.functions().values() {
+        let size: usize = match (function.address, function.end) {
+            (Some(address), Some(end)) => (end - address).into(),
+            _ => continue,
+        };
+        if size > LARGE_FUNCTION_SIZE {
+            let name = function.name.as_ref();
+            let line = ir.source_lines.iter().position(|line| {
+                let tok = parser::lex_line(parser::clean_line(line));
+                let fn_at = tok.iter().position(|tok| *tok == "fn");
+                fn_at.is_some_and(|j| tok.get(j + 1) == Some(&name))
+            });
+            found.push((
+                line,
+                format!(
+                    "function {} is {} instructions, over {}; consider splitting it up",
+                    function.name, size, LARGE_FUNCTION_SIZE
+                ),
+            ));
+        }
+    }
+    found.sort_by_key(|(line, _)| *line);
+    found
+}
+
+/// Numbers in statements other than 0, 1, and -1, by line. Setting a
+/// variable to a number is how to name it, so isn't counted, nor are
+/// directives such as `stack_config`.
+fn find_magic_numbers(source_lines: &[String]) -> Vec<(usize, String)> {
+    let mut found = Vec::default();
+    for (j, line) in source_lines.iter().enumerate() {
+        let tok = parser::lex_line(parser::clean_line(line));
+        match tok.first() {
+            None => continue,
+            Some(first) if first.starts_with("//") || first.starts_with('#') => continue,
+            Some(&"stack_config") => continue,
+            Some(&"set") if tok.len() == 3 => continue,
+            _ => {}
+        }
+
+        for tok in tok.iter().skip(1) {
+            let magic = tok
+                .parse::<f64>()
+                .is_ok_and(|n| n != 0.0 && n != 1.0 && n != -1.0);
+            if magic {
+                found.push((j, tok.to_string()));
+            }
+        }
+    }
+    found
+}
+
+/// Labels defined in a function that hide a label of the same name outside
+/// it.
+fn find_shadowed_labels(ir: &IntermediateRepresentation) -> Vec<(Option<usize>, String)> {
+    let mut shadowed = Vec::default();
+    for (j, op) in ir.ops.iter().enumerate() {
+        let target = match op {
+            IrOp::Label(op) => op.target.to_string(),
+            _ => continue,
+        };
+        let (function, label) = match target.split_once('.') {
+            Some((function, label)) => (function, label),
+            None => continue,
+        };
+
+        let outer = LabelName::try_from(label);
+        if outer.is_ok_and(|outer| ir.labels.contains_key(&outer)) {
+            shadowed.push((
+                ir.op_lines[j],
+                format!(
+                    "label {} in function {} shadows the label {} outside it, which jumps in the function can't reach",
+                    label, function, label
+                ),
+            ));
+        }
+    }
+    shadowed
+}
+
+/// Functions that control can fall into from the code before them, or out of
+/// without a `return`. Only code that can run is considered.
+fn find_fallthrough(ir: &IntermediateRepresentation, cfg: &Cfg) -> Vec<(Option<usize>, String)> {
+    let reachable = cfg.reachable();
+    let mut found = Vec::default();
+
+    let mut functions: Vec<(&FunctionName, &BlockId)> = cfg.functions.iter().collect();
+    functions.sort_by_key(|(_, id)| **id);
+    for (name, id) in functions {
+        let entry = &cfg.blocks[*id];
+        let line = ir.op_lines[entry.ops.start];
+
+        let falls_in = cfg.blocks.iter().enumerate().any(|(from, block)| {
+            reachable[from]
+                && block
+                    .edges
+                    .iter()
+                    .any(|edge| edge.target == *id && edge.kind == EdgeKind::Fallthrough)
+        });
+        if falls_in {
+            found.push((
+                line,
+                format!(
+                    "control can fall into function {} from the code before it; put `end` before the definition",
+                    name
+                ),
+            ));
+        }
+
+        let end = match ir.functions.get(name).and_then(|function| function.end) {
+            Some(end) => end,
+            None => continue,
+        };
+        let falls_out = cfg.blocks.iter().enumerate().skip(*id).find(|(j, block)| {
+            reachable[*j]
+                && block.start < end
+                && block.end == end
+                && (block.exit == Some(Exit::End)
+                    || block
+                        .edges
+                        .iter()
+                        .any(|edge| edge.kind == EdgeKind::Fallthrough))
+        });
+        if let Some((_, block)) = falls_out {
+            found.push((
+                ir.op_lines[block.ops.end - 1].or(line),
+                format!(
+                    "control can fall out of the end of function {} without a `return`",
+                    name
+                ),
+            ));
+        }
+    }
+    found
+}
@@ -0,0 +1,173 @@
+use serde::Serialize;
+
+use crate::*;
+
+/// Generates the program along with what external tools need to make sense
+/// of it, as JSON:
+///
+/// ```text
+/// {
+///   "instructions": ["set MF_stack_sz 0", ...],
+///   "labels": {"loop_top": 12, ...},
+///   "functions": [{"name": "f", "address": 20, "end": 41}, ...],
+///   "stacks": [{"name": null, "table_start": 90, "table_end": 154}, ...],
+///   "source_map": [null, {"line": 2, "op": 1}, ...]
+/// }
+/// ```
+///
+/// All on one line. Labels and functions are listed in order of address. Each
+/// stack with an internal jump table gives the addresses it spans, and the
+/// source map has an entry for each instruction as `SourceMap` does, with
+/// lines counting from 1 as in diagnostics.
+pub fn generate_json(ir: &IntermediateRepresentation) -> Result<String> {
+    let (instructions, _) = generate(ir)?;
+
+    let mut labels: Vec<_> = ir.labels().iter().collect();
+    labels.sort_by_key(|(name, address)| (*address.as_ref(), name.to_string()));
+    let labels = labels
+        .into_iter()
+        .map(|(name, address)| (name.as_ref(), *address))
+        .collect();
+
+    let mut functions: Vec<_> = ir.functions().values().collect();
+    functions.sort_by_key(|function| function.address.map(|address| *address.as_ref()));
+    let functions = functions
+        .into_iter()
+        .map(|function| JsonFunction {
+            name: function.name.as_ref(),
+            address: function.address,
+            end: function.end,
+        })
+        .collect();
+
+    let mut stacks = Vec::default();
+    let named = ir.named_stacks.iter().map(|named| {
+        (
+            Some(&named.name),
+            &named.stack_config,
+            &named.backend_params,
+        )
+    });
+    for (name, config, params) in
+        std::iter::once((None, &ir.stack_config, ir.backend_params())).chain(named)
+    {
+        if let (StackConfig::Internal(size), BackendParams::Internal(int)) = (config, params) {
+            if *size > 0 {
+                stacks.push(JsonStack {
+                    name: name.map(|name| name.as_ref()),
+                    table_start: int.push_table_start,
+                    table_end: int.push_table_start + (STACK_ENTRY_SIZE * size).into(),
+                });
+            }
+        }
+    }
+
+    let source_map = ir
+        .source_map()
+        .instructions
+        .iter()
+        .map(|location| {
+            location.map(|location| JsonLocation {
+                line: location.line + 1,
+                op: location.op,
+            })
+        })
+        .collect();
+
+    let json = JsonProgram {
+        instructions: &instructions,
+        labels,
+        functions,
+        stacks,
+        source_map,
+    };
+    Ok(serde_json::to_string(&json)?)
+}
+
+#[derive(Serialize)]
+struct JsonProgram<'a> {
+    instructions: &'a [String],
+
+    // In order of address, which a map would lose.
+    #[serde(serialize_with = "serialize_pairs")]
+    labels: Vec<(&'a str, Address)>,
+    functions: Vec<JsonFunction<'a>>,
+    stacks: Vec<JsonStack<'a>>,
+    source_map: Vec<Option<JsonLocation>>,
+}
+
+#[derive(Serialize)]
+struct JsonFunction<'a> {
+    name: &'a str,
+    address: Option<Address>,
+    end: Option<Address>,
+}
+
+#[derive(Serialize)]
+struct JsonStack<'a> {
+    name: Option<&'a str>,
+    table_start: Address,
+    table_end: Address,
+}
+
+#[derive(Serialize)]
+struct JsonLocation {
+    line: usize,
+    op: usize,
+}
+
+/// Writes `pairs` as a JSON object, keeping their order.
+fn serialize_pairs<S: serde::Serializer>(
+    pairs: &[(&str, Address)],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_map(pairs.iter().map(|(key, value)| (key, value)))
+}
+
+impl Diagnostic {
+    /// The diagnostic as a JSON object on one line, for editors and scripts:
+    ///
+    /// ```text
+    /// {"severity":"warning","message":"...","file":"prog.mf","line":4,"code":"unused"}
+    /// ```
+    ///
+    /// `file` is the source it's in, if known, and the line counts from 1, as
+    /// in messages and editors. The message leaves out the `Line <n>:` it
+    /// starts with when it has a line. `code` is the lint, as `#allow` names
+    /// it, or for errors the kind of error, as `CompileError::code` gives.
+    pub fn to_json(&self, file: Option<&str>) -> String {
+        let json = JsonDiagnostic {
+            severity: self.severity.to_string(),
+            message: self.message_without_line(),
+            file,
+            line: self.line.map(|line| line + 1),
+            code: self.code(),
+        };
+        serde_json::to_string(&json).expect("diagnostics serialize")
+    }
+
+    /// The message, without the `Line <n>: ` it starts with, and the stage
+    /// before that if any, when that's the diagnostic's line.
+    fn message_without_line(&self) -> &str {
+        let line = match self.line {
+            Some(line) => line,
+            None => return &self.message,
+        };
+        let prefix = format!("Line {}: ", line + 1);
+        match self.message.find(&prefix) {
+            Some(start) if !self.message[..start].contains(':') => {
+                &self.message[start + prefix.len()..]
+            }
+            _ => &self.message,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonDiagnostic<'a> {
+    severity: String,
+    message: &'a str,
+    file: Option<&'a str>,
+    line: Option<usize>,
+    code: Option<&'static str>,
+}
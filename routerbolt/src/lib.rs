@@ -1,13 +1,55 @@
+pub mod assertions;
+pub mod ast;
+pub mod backend_check;
+pub mod benchmark;
+pub mod cluster;
+pub mod code_stats;
 pub mod codegen;
+pub mod compiler;
+pub mod debugger;
+pub mod decompiler;
+pub mod diff;
 pub mod emulator;
+pub mod error;
+pub mod execution_profile;
+pub mod incremental;
 pub mod ir;
+pub mod json;
+pub mod manifest;
 pub mod parser;
+pub mod schematic;
+pub mod source_map;
+pub mod symbol_file;
+pub mod symbolic;
 pub mod test_util;
 pub mod types;
 
+pub use assertions::*;
+pub use ast::*;
+pub use backend_check::*;
+pub use benchmark::*;
+pub use cluster::*;
+pub use code_stats::*;
 pub use codegen::*;
+pub use compiler::*;
+pub use debugger::*;
+pub use decompiler::*;
+pub use diff::*;
 pub use emulator::*;
+pub use error::*;
+pub use execution_profile::*;
+pub use incremental::*;
 pub use ir::*;
+pub use json::*;
+pub use manifest::*;
+pub use schematic::*;
+pub use source_map::*;
+pub use symbol_file::*;
+pub use symbolic::*;
 pub use types::*;
 
+#[cfg(feature = "anyhow")]
 pub use anyhow::{bail, Context, Error, Result};
+#[cfg(not(feature = "anyhow"))]
+pub(crate) use anyhow::{bail, Context, Error, Result};
+pub use serde::{Deserialize, Serialize};
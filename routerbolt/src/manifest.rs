@@ -0,0 +1,350 @@
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use serde::Deserialize;
+
+use crate::*;
+
+/// The manifest `compiler build` reads when not given one.
+pub const MANIFEST_NAME: &str = "routerbolt.toml";
+
+/// A project of several programs built together, as described by a
+/// `routerbolt.toml` manifest. For example:
+///
+/// ```toml
+/// # Defaults for every target.
+/// profile = "release"
+/// out_dir = "build"
+///
+/// [[library]]
+/// name = "math"
+/// sources = ["lib/math.mf"]
+///
+/// [[target]]
+/// name = "miner"
+/// sources = ["miner.mf"]
+/// libraries = ["math"]
+/// stack_config = "cell bank1"
+/// ```
+///
+/// A target is its own sources, in order, which `include` each library it
+/// uses, as `Compiler` includes them. Sources may also include a library by
+/// name themselves, or another file by its path from the manifest, and each
+/// is added once, after the target's code. Libraries normally hold only
+/// functions, so the target's code should end before them.
+///
+/// Unknown keys and tables are errors, to catch typos.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    /// The directory that paths in the manifest are relative to.
+    pub root: PathBuf,
+    pub libraries: Vec<Library>,
+    pub targets: Vec<Target>,
+}
+
+/// Source files shared between targets.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Library {
+    pub name: String,
+    pub sources: Vec<PathBuf>,
+}
+
+/// One program of a project, compiled to one output file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    pub name: String,
+    pub sources: Vec<PathBuf>,
+
+    /// The names of the libraries it uses, whose sources follow its own.
+    pub libraries: Vec<String>,
+
+    /// `profile` for the target, or else the manifest's, or else `debug`.
+    pub profile: parser::Profile,
+
+    /// As for `--stack-config`, replacing any unnamed `stack_config` in the
+    /// sources.
+    pub stack_config: Option<String>,
+
+    /// `banner` for the target, or else the manifest's: a template for
+    /// `parser::expand_banner`, with the target's name as `{name}`.
+    pub banner: Option<String>,
+
+    /// `output` for the target, or else `<name>.mlog` in the manifest's
+    /// `out_dir`.
+    pub output: PathBuf,
+}
+
+/// The sources of a target, joined into one program with what they include.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sources {
+    pub text: String,
+
+    /// Each file, with the line of `text` it starts on.
+    files: Vec<(PathBuf, usize)>,
+}
+
+/// A manifest as written, before defaults are filled in and names checked.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestFile {
+    profile: Option<String>,
+    out_dir: Option<PathBuf>,
+    banner: Option<String>,
+    #[serde(default)]
+    library: Vec<LibraryTable>,
+    #[serde(default)]
+    target: Vec<TargetTable>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LibraryTable {
+    name: String,
+    sources: Vec<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TargetTable {
+    name: String,
+    sources: Vec<PathBuf>,
+    #[serde(default)]
+    libraries: Vec<String>,
+    profile: Option<String>,
+    stack_config: Option<String>,
+    banner: Option<String>,
+    output: Option<PathBuf>,
+}
+
+/// A target, compiled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Build {
+    pub code: Vec<String>,
+    pub stats: CodeStats,
+
+    /// The warnings for its sources, each with the file and line it is on.
+    pub warnings: Vec<String>,
+}
+
+impl Manifest {
+    /// Reads the manifest at `path`, whose paths are relative to the directory
+    /// it is in.
+    pub fn read(path: &Path) -> Result<Manifest> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("read manifest {}", path.display()))?;
+        let root = path.parent().unwrap_or_else(|| Path::new(""));
+        Manifest::parse(&text, root).with_context(|| format!("manifest {}", path.display()))
+    }
+
+    /// Parses the text of a manifest whose paths are relative to `root`.
+    pub fn parse(text: &str, root: &Path) -> Result<Manifest> {
+        let file: ManifestFile = toml::from_str(text)?;
+
+        let mut manifest = Manifest {
+            root: root.to_path_buf(),
+            libraries: Vec::default(),
+            targets: Vec::default(),
+        };
+        let profile = match &file.profile {
+            Some(name) => parser::Profile::try_from(name.as_str())?,
+            None => parser::Profile::Debug,
+        };
+        let out_dir = file.out_dir.unwrap_or_default();
+        let banner = file.banner;
+
+        for library in file.library {
+            if manifest.library(&library.name).is_some() {
+                bail!("duplicate library {}", library.name);
+            }
+            manifest.libraries.push(Library {
+                name: library.name,
+                sources: library.sources,
+            });
+        }
+
+        for table in file.target {
+            let name = table.name;
+            if manifest.target(&name).is_some() {
+                bail!("duplicate target {}", name);
+            }
+            let target = Target {
+                sources: table.sources,
+                libraries: table.libraries,
+                profile: match table.profile {
+                    Some(profile) => parser::Profile::try_from(profile.as_str())
+                        .with_context(|| format!("target {}", name))?,
+                    None => profile,
+                },
+                stack_config: table.stack_config,
+                banner: table.banner.or_else(|| banner.clone()),
+                output: table
+                    .output
+                    .unwrap_or_else(|| out_dir.join(format!("{}.mlog", name))),
+                name,
+            };
+
+            if target.sources.is_empty() {
+                bail!("target {} has no sources", target.name);
+            }
+            for library in target.libraries.iter() {
+                if manifest.library(library).is_none() {
+                    let defined = manifest.libraries.iter().map(|library| &library.name);
+                    let suggestions = suggestions(library, defined);
+                    let mut message =
+                        format!("target {}: library {} is not defined", target.name, library);
+                    if !suggestions.is_empty() {
+                        message.push_str(&format!("; did you mean {}?", or_list(&suggestions)));
+                    }
+                    bail!(message);
+                }
+            }
+            manifest.targets.push(target);
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn library(&self, name: &str) -> Option<&Library> {
+        self.libraries.iter().find(|library| library.name == name)
+    }
+
+    pub fn target(&self, name: &str) -> Option<&Target> {
+        self.targets.iter().find(|target| target.name == name)
+    }
+
+    /// Where `path` from the manifest is.
+    pub fn path(&self, path: &Path) -> PathBuf {
+        self.root.join(path)
+    }
+
+    /// Reads the sources of `target`, and adds what they include, and the
+    /// libraries it uses, after them.
+    pub fn sources(&self, target: &Target) -> Result<Sources> {
+        let mut sources = Sources::default();
+        for path in target.sources.iter() {
+            let path = self.path(path);
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("read source {}", path.display()))?;
+            sources.push(path, &text);
+        }
+        for library in target.libraries.iter() {
+            sources.text.push_str(&format!("include {}\n", library));
+        }
+
+        // Included text is added to the end in the order it's included, so
+        // each file starts where the one before it ends.
+        let included = Rc::new(RefCell::new(Vec::default()));
+        let expanded = Compiler::new()
+            .include_resolver(self.include_resolver(included.clone()))
+            .expand(&sources.text)?;
+        let mut start = sources.text.lines().count();
+        for (path, lines) in included.take() {
+            sources.files.push((path, start));
+            start += lines;
+        }
+        sources.text = expanded;
+        Ok(sources)
+    }
+
+    /// Finds what `include` names: a library of the manifest, or else a file
+    /// by its path from the manifest. Each file read is added to `included`,
+    /// with its number of lines.
+    fn include_resolver(
+        &self,
+        included: Rc<RefCell<Vec<(PathBuf, usize)>>>,
+    ) -> impl Fn(&str) -> Result<String> {
+        let root = self.root.clone();
+        let libraries = self.libraries.clone();
+        move |name| {
+            let paths = match libraries.iter().find(|library| library.name == name) {
+                Some(library) => library.sources.clone(),
+                None => vec![PathBuf::from(name)],
+            };
+            let mut sources = Sources::default();
+            for path in paths {
+                let path = root.join(path);
+                let text = std::fs::read_to_string(&path)
+                    .with_context(|| format!("read source {}", path.display()))?;
+                sources.push(path, &text);
+            }
+            let mut included = included.borrow_mut();
+            let mut files = sources.files.iter().peekable();
+            while let Some((path, start)) = files.next() {
+                let end = files
+                    .peek()
+                    .map_or(sources.text.lines().count(), |(_, end)| *end);
+                included.push((path.clone(), end - start));
+            }
+            Ok(sources.text)
+        }
+    }
+
+    /// Compiles `target`. If it fails, the error lists each problem with the
+    /// file and line it is on.
+    pub fn build(&self, target: &Target) -> Result<Build> {
+        let sources = self.sources(target)?;
+        let mut options = parser::CompileOptions::with_profile(target.profile);
+        if let Some(config) = &target.stack_config {
+            options.set_stack_config(config).context("stack_config")?;
+        }
+        options.banner = target
+            .banner
+            .as_ref()
+            .map(|template| parser::expand_banner(template, &target.name, &sources.text));
+        let compiler = Compiler::with_options(options);
+
+        let located = |error: CompileError| {
+            let diagnostics: Vec<_> = error
+                .diagnostics()
+                .iter()
+                .map(|diagnostic| sources.describe(diagnostic))
+                .collect();
+            anyhow::anyhow!(diagnostics.join("\n"))
+        };
+        let ir = compiler.parse(&sources.text).map_err(located)?;
+        let warnings = ir
+            .warnings()
+            .iter()
+            .map(|warning| sources.describe(warning))
+            .collect();
+        let program = compiler.generate(ir).map_err(located)?;
+
+        Ok(Build {
+            code: program.code,
+            stats: program.stats,
+            warnings,
+        })
+    }
+}
+
+impl Sources {
+    /// Appends the file at `path`, whose contents are `text`.
+    pub fn push(&mut self, path: PathBuf, text: &str) {
+        let start = self.text.lines().count();
+        self.files.push((path, start));
+        self.text.push_str(text);
+        if !text.is_empty() && !text.ends_with('\n') {
+            self.text.push('\n');
+        }
+    }
+
+    /// The file that `line` of the joined text comes from, and its line in
+    /// that file, counting from 0.
+    pub fn locate(&self, line: usize) -> Option<(&Path, usize)> {
+        self.files
+            .iter()
+            .rev()
+            .find(|(_, start)| *start <= line)
+            .map(|(path, start)| (path.as_path(), line - start))
+    }
+
+    /// `diagnostic`, preceded by the file and line it is on, if known.
+    pub fn describe(&self, diagnostic: &Diagnostic) -> String {
+        match diagnostic.line.and_then(|line| self.locate(line)) {
+            Some((path, line)) => format!("{} line {}: {}", path.display(), line + 1, diagnostic),
+            None => diagnostic.to_string(),
+        }
+    }
+}
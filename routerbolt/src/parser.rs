@@ -1,144 +1,1288 @@
-use std::collections::HashMap;
-use std::convert::TryInto;
-use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
 
 use anyhow::bail;
 
 use crate::*;
 
-pub fn parse(text: &str) -> Result<IntermediateRepresentation> {
-    let mut context = ParserContext {
-        ops: Vec::default(),
+/// Settings for a compilation that come from the caller rather than the
+/// source, so that the same program can be built in different ways.
+#[derive(Clone, Debug)]
+pub struct CompileOptions {
+    /// Configures the default stack, replacing any unnamed `stack_config` in
+    /// the source.
+    pub stack_config: Option<StackConfig>,
+
+    /// Infers the size of the default stack, as `stack_config size auto` does.
+    /// Takes precedence over `stack_config`.
+    pub auto_stack_size: bool,
+
+    /// Leaves out code that can never execute, such as functions that are
+    /// never called. See `DeadCodeElimination`.
+    pub eliminate_dead_code: bool,
+
+    /// Merges adjacent instructions where possible. See `Peephole`.
+    pub peephole: bool,
+
+    /// Leaves out functions that are never called, as `eliminate_dead_code`
+    /// does, but keeps any other dead code.
+    pub strip_unused_functions: bool,
+
+    /// Fails compilation if the generated program, including any stack tables,
+    /// is longer than this. Defaults to `MAX_INSTRUCTIONS`, the most a
+    /// processor accepts.
+    pub instruction_limit: Option<usize>,
+
+    /// How the program ends, replacing any `epilogue` directive in the source.
+    pub epilogue: Option<Epilogue>,
+
+    /// Ignores `debug` directives, leaving out the checks they add.
+    pub strip_debug_checks: bool,
+
+    /// Retargets jumps to unconditional jumps. See `JumpThreading`.
+    pub thread_jumps: bool,
+
+    /// Text to set `MF_build` to in the first instruction of the program, so
+    /// a processor in game shows which program and version it runs. See
+    /// `expand_banner`.
+    pub banner: Option<String>,
+
+    /// What the names of the compiler's own variables start with in place of
+    /// `MF_`, so they can't collide with the program's, or with those of other
+    /// code sharing the processor.
+    pub variable_prefix: String,
+}
+
+impl Default for CompileOptions {
+    fn default() -> CompileOptions {
+        CompileOptions {
+            stack_config: None,
+            auto_stack_size: false,
+            eliminate_dead_code: false,
+            peephole: false,
+            strip_unused_functions: false,
+            instruction_limit: Some(MAX_INSTRUCTIONS),
+            epilogue: None,
+            strip_debug_checks: false,
+            thread_jumps: false,
+            banner: None,
+            variable_prefix: DEFAULT_VARIABLE_PREFIX.to_string(),
+        }
+    }
+}
+
+/// A preset of `CompileOptions` for each stage of working on a program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+    /// Keeps the checks added by `debug` directives, and leaves the output
+    /// unoptimized so it's easy to follow in the annotations. The same as the
+    /// default options.
+    Debug,
+
+    /// For the finished program: leaves out the `debug` checks and dead code,
+    /// threads jumps, and runs the peephole optimizer, which also folds away
+    /// temporaries that are only read once.
+    Release,
+}
+
+impl TryFrom<&str> for Profile {
+    type Error = anyhow::Error;
+
+    fn try_from(name: &str) -> Result<Profile> {
+        match name {
+            "debug" => Ok(Profile::Debug),
+            "release" => Ok(Profile::Release),
+            _ => bail!("profile must be debug or release"),
+        }
+    }
+}
+
+impl CompileOptions {
+    /// The options for `profile`.
+    pub fn with_profile(profile: Profile) -> CompileOptions {
+        let release = profile == Profile::Release;
+        CompileOptions {
+            eliminate_dead_code: release,
+            peephole: release,
+            strip_debug_checks: release,
+            thread_jumps: release,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the default stack from the arguments of a `stack_config`
+    /// directive, e.g. `cell bank1 len 64` or `size auto`.
+    pub fn set_stack_config(&mut self, text: &str) -> Result<()> {
+        let (config, auto, name) = parse_stack_config(&lex_line(text))?;
+        if name.is_some() {
+            bail!("named stacks must be declared in the source");
+        }
+
+        self.stack_config = Some(config);
+        self.auto_stack_size = auto;
+        Ok(())
+    }
+}
+
+/// The variable `CompileOptions::banner` sets, after the variable prefix.
+pub const BANNER_VARIABLE: &str = "build";
+
+/// Fills in a template for `CompileOptions::banner`, such as `"{name}-v3
+/// {date}"`: `{name}` becomes `name`, `{date}` today's date as `YYYY-MM-DD`
+/// (UTC), and `{hash}` eight hex digits that change whenever `source` does,
+/// to tell builds apart.
+pub fn expand_banner(template: &str, name: &str, source: &str) -> String {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let (year, month, day) = civil_date(seconds / 86400);
+
+    // FNV-1a, which is the same on every platform and version.
+    let hash = source.bytes().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    });
+
+    template
+        .replace("{name}", name)
+        .replace("{date}", &format!("{:04}-{:02}-{:02}", year, month, day))
+        .replace("{hash}", &format!("{:08x}", hash))
+}
+
+/// The year, month, and day `days` after 1970-01-01.
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    // Counts from 0000-03-01, so that leap days fall at the end of a year.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+pub fn parse(text: &str) -> CompileResult<IntermediateRepresentation> {
+    parse_with_options(text, &CompileOptions::default())
+}
+
+pub fn parse_with_options(
+    text: &str,
+    options: &CompileOptions,
+) -> CompileResult<IntermediateRepresentation> {
+    parse_program(text, options).map_err(|err| CompileError::from_anyhow(err, true))
+}
+
+/// Parses a program given a line at a time, as `Ast::parse_lines` takes it,
+/// with `options`, as `parse_with_options` does.
+pub fn parse_lines_with_options<S: AsRef<str>>(
+    lines: impl IntoIterator<Item = (usize, S)>,
+    options: &CompileOptions,
+) -> CompileResult<IntermediateRepresentation> {
+    Ast::parse_lines(lines)
+        .and_then(|ast| parse_ast(ast, options))
+        .map_err(|err| CompileError::from_anyhow(err, true))
+}
+
+/// Parses a program as `parse_with_options` does, reusing the parts of it
+/// that are unchanged since the last parse with `cache`, and keeping the
+/// parts of this one there for the next.
+pub fn parse_with_cache(
+    text: &str,
+    options: &CompileOptions,
+    cache: &mut LoweringCache,
+) -> CompileResult<IntermediateRepresentation> {
+    Ast::parse(text)
+        .and_then(|ast| lower_reusing(&ast, options, Some(cache)))
+        .map_err(|err| CompileError::from_anyhow(err, true))
+}
+
+fn parse_program(text: &str, options: &CompileOptions) -> Result<IntermediateRepresentation> {
+    parse_ast(Ast::parse(text)?, options)
+}
+
+fn parse_ast(ast: Ast, options: &CompileOptions) -> Result<IntermediateRepresentation> {
+    log::debug!("parsed {} source lines into the AST", ast.source.len());
+    lower(&ast, options)
+}
+
+/// Turns a parsed program into the IR, a statement at a time.
+pub fn lower(ast: &Ast, options: &CompileOptions) -> Result<IntermediateRepresentation> {
+    lower_reusing(ast, options, None)
+}
+
+/// Lowers a program as `lower` does, reusing the parts of it in `cache` that
+/// are unchanged, if given one, and leaving the parts of this one there.
+fn lower_reusing(
+    ast: &Ast,
+    options: &CompileOptions,
+    cache: Option<&mut LoweringCache>,
+) -> Result<IntermediateRepresentation> {
+    Symbol::interning(|| lower_program(ast, options, cache))
+}
+
+fn lower_program(
+    ast: &Ast,
+    options: &CompileOptions,
+    cache: Option<&mut LoweringCache>,
+) -> Result<IntermediateRepresentation> {
+    let prefix = &options.variable_prefix;
+    if prefix.is_empty() || prefix.contains(|c: char| c.is_whitespace() || c == '"') {
+        bail!("variable_prefix must be a word, not {:?}", prefix);
+    }
+
+    let mut declared = Declarations {
+        variable_prefix: prefix.clone(),
+        functions: HashMap::default(),
+        inline_functions: HashMap::default(),
         // FIXME: Refactor this is bad.
         backend: Backend::Internal, // temporary until preprocess over
-        instruction_count: Address::from(0),
-        scope_stack: Vec::default(),
-        functions: HashMap::default(),
-        labels: HashMap::default(),
         has_stack: false,
+        named_stacks: Vec::default(),
+        debug: DebugOptions::default(),
+        epilogue: None,
+        auto_stack_size: false,
+        in_asm_block: false,
     };
 
+    let inline_bodies = collect_inline_functions(ast);
+
     let mut stack_config = None;
 
     let mut preparse_fn_stack = Vec::default();
-    for (line_no, line) in text.lines().enumerate() {
-        context
+    for line in ast.lines() {
+        declared
             .preparse_line(
-                &lex_line(clean_line(line)),
+                &lex_line(clean_line(&line.text)),
                 &mut stack_config,
                 &mut preparse_fn_stack,
             )
-            .with_context(|| format!("Preparse Line {}: {}", line_no, line))?;
+            .with_context(|| SourceLine {
+                stage: "Preparse ",
+                line: line.line,
+                text: line.text.to_string(),
+            })?;
+    }
+    log::debug!(
+        "preparse found {} functions, {} named stacks, and stack config {:?}",
+        declared.functions.len(),
+        declared.named_stacks.len(),
+        stack_config
+    );
+
+    if declared.in_asm_block {
+        bail!("asm block is missing its closing }");
+    }
+
+    if options.strip_debug_checks {
+        declared.debug = DebugOptions::default();
+    }
+
+    for (name, body) in inline_bodies.iter() {
+        let function = declared.functions.remove(name).unwrap();
+        let inline = InlineFunction {
+            function,
+            body: body.clone(),
+        };
+        declared
+            .inline_functions
+            .insert(name.clone(), Shared::new(inline));
+    }
+
+    if options.auto_stack_size {
+        declared.auto_stack_size = true;
+        stack_config = Some(StackConfig::Internal(1));
+    } else if let Some(config) = &options.stack_config {
+        declared.auto_stack_size = false;
+        stack_config = Some(config.clone());
     }
 
     let stack_config = stack_config.unwrap_or(StackConfig::Internal(0));
+    check_stack_overlap(&stack_config, &declared.named_stacks)?;
 
     // We may need to zero the stack pointer if using one.
     let (has_stack, backend) = match &stack_config {
         StackConfig::Internal(size) if *size == 0 => (false, Backend::Internal),
         StackConfig::Internal(..) => (true, Backend::Internal),
-        StackConfig::External(..) => (true, Backend::External),
+        StackConfig::External(..) => (true, stack_config.backend()),
     };
 
-    context.backend = backend;
+    log::debug!(
+        "default stack uses the {:?} backend with {:?}{}",
+        backend,
+        stack_config,
+        if declared.auto_stack_size {
+            ", to be sized once stack usage is known"
+        } else {
+            ""
+        }
+    );
+    for (name, config) in declared.named_stacks.iter() {
+        log::debug!(
+            "stack {} uses the {:?} backend with {:?}",
+            name,
+            config.backend(),
+            config
+        );
+    }
+    declared.backend = backend;
+    declared.has_stack = has_stack;
 
-    context.has_stack = has_stack;
+    let mut context = ParserContext::new(&declared, None);
+    if options.peephole {
+        context.peephole = Some(Peephole::new(prefix));
+    }
+
+    if let Some(banner) = &options.banner {
+        if banner.contains('"') || banner.contains('\n') {
+            bail!("the banner can't contain quotes or line breaks");
+        }
+        let command = format!("set {}{} \"{}\"", prefix, BANNER_VARIABLE, banner);
+        let op = IrOp::MindustryCommand(MindustryOp {
+            command: MindustryCommand::raw(&command),
+        });
+        context.instruction_count += op.code_size(backend);
+        context.ops.push(op);
+        context.op_lines.push(0);
+    }
+    let mut init = Vec::default();
     if has_stack {
-        let op = SetOp::new(MindustryTerm::stack_sz(), MindustryTerm::zero());
+        let op = SetOp::new(MindustryTerm::stack_sz(prefix), stack_config.base());
+        init.push(IrOp::Set(op));
+    }
+
+    for (name, config) in declared.named_stacks.iter() {
+        let stack = StackRef::Named(name.clone(), config.backend());
+        let size_var = stack.size_var(prefix).as_str().try_into()?;
+        let op = SetOp::new(size_var, config.base());
+        init.push(IrOp::Set(op));
+    }
+
+    // The processor starts over from the top after the last instruction, but
+    // keeps its variables, so the stacks are only set up on the first pass
+    // after it's built. Until then `MF_init` is null.
+    if !init.is_empty() {
+        let end = init.len() + 2;
+        let guard = format!(
+            "jump {} equal {}init 1",
+            context.instruction_count + end.into(),
+            prefix
+        );
+        init.insert(
+            0,
+            IrOp::MindustryCommand(MindustryOp {
+                command: MindustryCommand::raw(&guard),
+            }),
+        );
+        init.push(IrOp::MindustryCommand(MindustryOp {
+            command: MindustryCommand::raw(&format!("set {}init 1", prefix)),
+        }));
+    }
+    for op in init {
         context.instruction_count += op.code_size(backend);
-        context.ops.push(IrOp::Set(op));
+        context.ops.push(op);
+        context.op_lines.push(0);
     }
+    let init_ops = context.ops.len();
+    let program_start = context.instruction_count;
+    log::debug!(
+        "stack setup takes {} ops; the program starts at {}",
+        init_ops,
+        program_start
+    );
+
+    // Each part of the program is lowered on its own, from address 0, and
+    // then moved into place after the parts before it. Only what preparse
+    // found is shared between them, so a part lowered for an earlier version
+    // of the program can be moved into place instead if it's unchanged. See
+    // `split_parts` for where the program is split. Since the parts don't
+    // depend on each other, those that aren't reused are lowered before any
+    // is moved into place, in parallel with the `parallel` feature.
+    //
+    // A statement that fails to parse is skipped, so that the errors in the
+    // rest of the program are found too. An error opening or closing a block
+    // leaves the scopes in a state the lines after can't be parsed in, so
+    // stops here.
+    let reusable = cache.as_deref().is_some_and(|cache| {
+        cache.peephole == options.peephole && cache.declared.as_ref() == Some(&declared)
+    });
+    let parts = split_parts(ast, &declared.inline_functions);
+    let keys: Vec<Option<PartKey>> = parts
+        .iter()
+        .map(|part| cache.is_some().then(|| part.key()))
+        .collect();
+    let cached: Vec<Option<LoweredPart>> = parts
+        .iter()
+        .zip(keys.iter())
+        .map(|(part, key)| {
+            key.as_ref()
+                .filter(|_| reusable)
+                .and_then(|key| cache.as_deref()?.parts.get(key))
+                .map(|cached| cached.moved_to_line(part.lines[0].line))
+        })
+        .collect();
+    let missing: Vec<&Part> = parts
+        .iter()
+        .zip(cached.iter())
+        .filter(|(_, cached)| cached.is_none())
+        .map(|(part, _)| part)
+        .collect();
+    let (reused, lowered) = (parts.len() - missing.len(), missing.len());
+    let mut fresh = lower_parts(&missing, &declared, context.peephole.as_ref()).into_iter();
+
+    let mut kept = Vec::default();
+    let mut errors = Vec::default();
+    for (cached, key) in cached.into_iter().zip(keys) {
+        let part = match cached {
+            Some(cached) => cached,
+            None => fresh.next().unwrap(),
+        };
 
-    for (line_no, line) in text.lines().enumerate() {
-        // Some ops update this state themselves, but we pull out the common case of one op here.
-        let clean = clean_line(line);
-        for op in context
-            .parse_line(clean, &lex_line(clean_line(line)))
-            .with_context(|| format!("Line {}: {}", line_no, line))?
-            .0
+        if let Some(key) = key.filter(|_| part.errors.is_empty()) {
+            kept.push((key, part.clone()));
+        }
+        let stopped = part.stopped;
+        context.append(part, &ast.source, &mut errors)?;
+        if stopped {
+            break;
+        }
+    }
+    if !errors.is_empty() {
+        return Err(CompileError::from_errors(errors).into());
+    }
+    log::debug!(
+        "lowered to {} ops, {} instructions, reusing {} of {} parts",
+        context.ops.len(),
+        context.instruction_count,
+        reused,
+        reused + lowered
+    );
+
+    let stack_usage = analyze_stack_usage(&context.ops, &context.op_lines, |name| {
+        declared
+            .functions
+            .get(name)
+            .map(|function| function.locals.len() + declared.debug.canary_size())
+    });
+
+    log::debug!("stack usage is {}", stack_usage);
+    let stack_config = if declared.auto_stack_size {
+        match &stack_usage {
+            StackUsage::Bounded(size) => {
+                log::debug!("stack size auto: {} entries", size);
+                StackConfig::Internal(*size)
+            }
+            usage => bail!(
+                "`stack_config size auto` can't infer the stack size, since stack usage is {}; give an explicit size instead",
+                usage
+            ),
+        }
+    } else {
+        stack_config
+    };
+    check_stack_usage(&stack_config, &stack_usage)?;
+
+    // The stack guard handlers and internal stack tables follow the program,
+    // separated from it by an `end`. See `generate`, which must use the same
+    // order.
+    let mut table_start = context.instruction_count + 1.into();
+
+    if declared.debug.stack_guard.is_some() {
+        let bounded = |config: &StackConfig| match config {
+            StackConfig::External(ext) => ext.len.is_some(),
+            StackConfig::Internal(..) => true,
+        };
+        if !bounded(&stack_config)
+            || !declared
+                .named_stacks
+                .iter()
+                .all(|(_, config)| bounded(config))
         {
-            context.instruction_count += op.code_size(context.backend);
-            context.ops.push(op);
+            bail!("`debug stack_guard` requires a `len` for each external stack");
+        }
+    }
+
+    // Stack overflow can happen anywhere, but corruption is only detected on
+    // return from a function.
+    let mut functions: Vec<FunctionName> = declared.functions.keys().cloned().collect();
+    functions.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+    let mut debug_handlers = HashMap::default();
+    for trap in [DebugTrap::StackOverflow, DebugTrap::StackCorruption].iter() {
+        if declared.debug.message_block(*trap).is_none() {
+            continue;
+        }
+
+        let outside = match trap {
+            DebugTrap::StackOverflow => Some(None),
+            DebugTrap::StackCorruption => None,
+        };
+        let sites = outside
+            .into_iter()
+            .chain(functions.iter().cloned().map(Some));
+        for function in sites {
+            log::debug!("{} handler for {:?} at {}", trap, function, table_start);
+            debug_handlers.insert((*trap, function), table_start);
+            table_start += DEBUG_HANDLER_SIZE;
+        }
+    }
+
+    log::debug!("stack tables start at {}", table_start);
+    let backend_params = stack_backend_params(&stack_config, &mut table_start);
+    log::debug!("default stack: {:?}", backend_params);
+    let named_stacks = declared
+        .named_stacks
+        .iter()
+        .cloned()
+        .map(|(name, stack_config)| {
+            let backend_params = stack_backend_params(&stack_config, &mut table_start);
+            log::debug!("stack {}: {:?}", name, backend_params);
+            NamedStack {
+                name,
+                stack_config,
+                backend_params,
+            }
+        })
+        .collect();
+
+    let functions = declared
+        .functions
+        .iter()
+        .map(|(name, function)| {
+            let function = context
+                .defined
+                .remove(name)
+                .unwrap_or_else(|| function.clone());
+            (name.clone(), Shared::new(function))
+        })
+        .collect();
+    let mut ir = IntermediateRepresentation {
+        ops: context.ops,
+        op_lines: context
+            .op_lines
+            .iter()
+            .enumerate()
+            .map(|(j, line)| Some(*line).filter(|_| j >= init_ops))
+            .collect(),
+        source_lines: ast
+            .source
+            .iter()
+            .map(|line| line.trim().to_string())
+            .collect(),
+        stack_config,
+        named_stacks,
+        stack_usage,
+        dead_code: Vec::default(),
+        peephole_saved: context.peephole.map_or(0, |peephole| peephole.saved),
+        unused: Vec::default(),
+        instruction_limit: options.instruction_limit,
+        variable_prefix: prefix.clone(),
+        epilogue: options.epilogue.or(declared.epilogue).unwrap_or_default(),
+        program_start,
+        debug: declared.debug.clone(),
+        debug_handlers,
+        functions,
+        labels: context.labels,
+        backend,
+        backend_params,
+    };
+    ir.unused = find_unused_symbols(&ir.ops, &ir.op_lines);
+    PassManager::with_options(options).run(&mut ir)?;
+
+    if let Some(cache) = cache {
+        cache.declared = Some(declared);
+        cache.peephole = options.peephole;
+        cache.parts = kept.into_iter().collect();
+        cache.reused = reused;
+        cache.lowered = lowered;
+    }
+
+    Ok(ir)
+}
+
+/// Whether a line opens or closes a block, such as `if x {` or `} else {`.
+fn is_block_delimiter(tok: &[&str]) -> bool {
+    tok.first() == Some(&"}") || tok.last() == Some(&"{")
+}
+
+/// The parts of a program lowered by `parse_with_cache`, kept so that those
+/// an edit leaves as they were needn't be lowered again. See
+/// `IncrementalCompiler`.
+#[derive(Default)]
+pub struct LoweringCache {
+    // What the program declared, and whether the peephole optimizer was used.
+    // If either changes, none of the parts can be reused.
+    declared: Option<Declarations>,
+    peephole: bool,
+
+    parts: HashMap<PartKey, LoweredPart>,
+    reused: usize,
+    lowered: usize,
+}
+
+impl LoweringCache {
+    /// How many parts of the program the last parse reused.
+    pub fn reused(&self) -> usize {
+        self.reused
+    }
+
+    /// How many parts of the program the last parse had to lower.
+    pub fn lowered(&self) -> usize {
+        self.lowered
+    }
+}
+
+/// A run of statements lowered on its own. See `split_parts`.
+struct Part<'a> {
+    lines: Vec<&'a Line>,
+
+    // The number of inline calls before this part.
+    inline_count: usize,
+}
+
+/// What lowering a part depends on, other than the declarations: its lines,
+/// numbered from its first, and the inline calls before it.
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct PartKey {
+    inline_count: usize,
+    lines: Vec<(usize, String)>,
+}
+
+/// A part of the program as lowered on its own, with its addresses counted
+/// from 0 and its ops indexed from 0, to be moved into place with
+/// `ParserContext::append`.
+#[derive(Clone)]
+struct LoweredPart {
+    first_line: usize,
+    ops: Vec<IrOp>,
+    op_lines: Vec<usize>,
+    size: AddressDelta,
+    labels: HashMap<LabelName, Address>,
+    functions: HashMap<FunctionName, FunctionOp>,
+
+    // What the peephole optimizer saved.
+    peephole_saved: usize,
+
+    // The errors in the part, and whether one left it in a state the rest of
+    // the program can't be lowered after.
+    errors: Vec<CompileError>,
+    stopped: bool,
+}
+
+impl Part<'_> {
+    fn key(&self) -> PartKey {
+        let first_line = self.lines[0].line;
+        PartKey {
+            inline_count: self.inline_count,
+            lines: self
+                .lines
+                .iter()
+                .map(|line| (line.line - first_line, line.text.clone()))
+                .collect(),
         }
     }
 
-    let backend_params = match &stack_config {
+    /// Lowers the part from address 0, given what the whole program declares
+    /// and the peephole optimizer for it, if used.
+    fn lower(&self, declared: &Declarations, peephole: Option<&Peephole>) -> LoweredPart {
+        let mut context = ParserContext::new(declared, peephole.map(Peephole::fork));
+        context.inline_count = self.inline_count;
+
+        let mut errors = Vec::default();
+        let mut stopped = false;
+        for line in self.lines.iter() {
+            context.line_no = line.line;
+            let result = context
+                .parse_and_push(&line.text)
+                .with_context(|| SourceLine {
+                    stage: "",
+                    line: line.line,
+                    text: line.text.to_string(),
+                });
+            if let Err(err) = result {
+                errors.push(CompileError::from_anyhow(err, true));
+                context.temporaries.release_all();
+                if is_block_delimiter(&lex_line(clean_line(&line.text))) {
+                    stopped = true;
+                    break;
+                }
+            }
+        }
+
+        let peephole_saved = context.peephole.map_or(0, |peephole| peephole.saved);
+        LoweredPart {
+            first_line: self.lines[0].line,
+            ops: context.ops,
+            op_lines: context.op_lines,
+            size: context.instruction_count - Address::from(0),
+            labels: context.labels,
+            functions: context.defined,
+            peephole_saved,
+            errors,
+            stopped,
+        }
+    }
+}
+
+/// Lowers each of `parts` on its own, as `Part::lower` does.
+#[cfg(not(feature = "parallel"))]
+fn lower_parts(
+    parts: &[&Part],
+    declared: &Declarations,
+    peephole: Option<&Peephole>,
+) -> Vec<LoweredPart> {
+    parts
+        .iter()
+        .map(|part| part.lower(declared, peephole))
+        .collect()
+}
+
+/// Lowers each of `parts` on its own, as `Part::lower` does, on rayon's
+/// thread pool. Each part interns its symbols on the thread it's lowered on.
+#[cfg(feature = "parallel")]
+fn lower_parts(
+    parts: &[&Part],
+    declared: &Declarations,
+    peephole: Option<&Peephole>,
+) -> Vec<LoweredPart> {
+    use rayon::prelude::*;
+
+    parts
+        .par_iter()
+        .map(|part| Symbol::interning(|| part.lower(declared, peephole)))
+        .collect()
+}
+
+impl LoweredPart {
+    /// The part, lowered as it was before, now that it starts at `first_line`.
+    fn moved_to_line(&self, first_line: usize) -> LoweredPart {
+        let moved = |line: usize| line - self.first_line + first_line;
+        let mut part = self.clone();
+        part.first_line = first_line;
+        for line in part.op_lines.iter_mut() {
+            *line = moved(*line);
+        }
+        for function in part.functions.values_mut() {
+            function.end_line = function.end_line.map(moved);
+        }
+        part
+    }
+}
+
+/// Splits the program, leaving out its inline functions, into the parts that
+/// are lowered on their own. Each statement outside any block starts a new
+/// part, unless the peephole optimizer could merge its first line with the
+/// line before, as it could for two `set`s in a row. A part lowers the same
+/// on its own as it would after the rest, since nothing else it depends on
+/// changes as the program is lowered, and the labels of inline calls are
+/// numbered from the calls before it.
+fn split_parts<'a>(
+    ast: &'a Ast,
+    inline_functions: &HashMap<FunctionName, Shared<InlineFunction>>,
+) -> Vec<Part<'a>> {
+    let mut parts: Vec<Part> = Vec::default();
+    let mut inline_count = 0;
+    let mut last_straight_line = false;
+    for statement in ast.statements.iter() {
+        if let StatementKind::Function { inline: true, .. } = statement.kind {
+            continue;
+        }
+
+        // A call may be to an inline function, whose body is lowered in its
+        // place, so it may be merged with the line before too.
+        let lines = statement.lines();
+        let first = lex_line(clean_line(&statement.line.text));
+        let joins = last_straight_line && (is_straight_line(&first) || first[0] == "call");
+        last_straight_line = is_straight_line(&lex_line(clean_line(&lines.last().unwrap().text)));
+
+        let calls = count_inline_calls(statement, inline_functions);
+        match parts.last_mut() {
+            Some(part) if joins => part.lines.extend(lines),
+            _ => parts.push(Part {
+                lines,
+                inline_count,
+            }),
+        }
+        inline_count += calls;
+    }
+
+    parts
+}
+
+/// The number of calls `statement` makes to inline functions.
+fn count_inline_calls(
+    statement: &Statement,
+    inline_functions: &HashMap<FunctionName, Shared<InlineFunction>>,
+) -> usize {
+    let count = |block: &Block| -> usize {
+        block
+            .statements
+            .iter()
+            .map(|statement| count_inline_calls(statement, inline_functions))
+            .sum()
+    };
+
+    match &statement.kind {
+        StatementKind::Function { body, .. }
+        | StatementKind::While { body, .. }
+        | StatementKind::DoWhile { body, .. }
+        | StatementKind::Loop { body } => count(body),
+        StatementKind::If {
+            then, otherwise, ..
+        } => count(then) + otherwise.as_ref().map_or(0, count),
+        StatementKind::Command { name, .. } if name == "call" => {
+            let tok = lex_line(clean_line(&statement.line.text));
+            let name = tok
+                .get(1)
+                .and_then(|name| FunctionName::try_from(*name).ok());
+            name.map_or(0, |name| inline_functions.contains_key(&name) as usize)
+        }
+        _ => 0,
+    }
+}
+
+/// Parses the arguments of a `stack_config` directive into the stack's
+/// configuration, whether its size is `auto`, and its name if it has one.
+fn parse_stack_config(tok: &[&str]) -> Result<(StackConfig, bool, Option<StackName>)> {
+    const FORM: &str = "form is `stack_config [ size <stack_size> | size auto | cell <cell_name> [<cell_name>...] [offset <offset>] [len <len>] ] [as <name>]`";
+
+    if tok.len() < 2 {
+        bail!(FORM);
+    }
+
+    // Any further cell names come before the options.
+    let mut options = &tok[2..];
+    let mut more_cells = Vec::default();
+    if tok[0] == "cell" {
+        while let Some(cell) = options
+            .first()
+            .filter(|t| !["offset", "len", "as"].contains(t))
+        {
+            more_cells.push(Symbol::new(cell));
+            options = &options[1..];
+        }
+    }
+
+    let auto = tok[0] == "size" && tok[1] == "auto";
+    let mut config = match tok[0] {
+        // Any non-zero size, so that the stack counts as configured. The
+        // real size is filled in after parsing.
+        "size" if auto => StackConfig::Internal(1),
+        "size" => StackConfig::Internal(
+            tok[1]
+                .parse()
+                .context("stack size must be a non-negative integer")?,
+        ),
+        "cell" => StackConfig::External(ExternalParams {
+            cell_name: Symbol::new(tok[1]),
+            offset: 0,
+            len: None,
+            more_cells,
+            bank_routines: None,
+        }),
+        _ => bail!(FORM),
+    };
+
+    let mut name: Option<StackName> = None;
+    for option in options.chunks(2) {
+        match (option, &mut config) {
+            (["as", value], _) if name.is_none() => {
+                name = Some((*value).try_into().context("stack name")?);
+            }
+            (["offset", value], StackConfig::External(ext)) => {
+                ext.offset = value
+                    .parse()
+                    .context("stack offset must be a non-negative integer")?;
+            }
+            (["len", value], StackConfig::External(ext)) if ext.len.is_none() => {
+                let len = value
+                    .parse()
+                    .context("stack len must be a non-negative integer")?;
+                if len == 0 {
+                    bail!("stack len must be greater than 0");
+                }
+                ext.len = Some(len);
+            }
+            _ => bail!(FORM),
+        }
+    }
+
+    if let StackConfig::External(ext) = &config {
+        let capacity = BANK_SIZE * ext.cells().count();
+        if ext.is_banked() && ext.offset + ext.len.unwrap_or(0) > capacity {
+            bail!(
+                "stack does not fit in {} memory banks of {} entries",
+                ext.cells().count(),
+                BANK_SIZE
+            );
+        }
+    }
+
+    Ok((config, auto, name))
+}
+
+/// Computes the backend params of a stack, advancing `table_start` past its
+/// jump tables if it uses the internal backend.
+fn stack_backend_params(stack_config: &StackConfig, table_start: &mut Address) -> BackendParams {
+    match stack_config {
         StackConfig::Internal(stack_size) => {
-            let push_entry_size = 3;
-            let pop_entry_size = 2;
-            let poke_entry_size = 2;
-            let push_table_start = context.instruction_count + 1.into();
-            let pop_table_start =
-                push_table_start + AddressDelta::from(push_entry_size * stack_size);
-            let poke_table_start =
-                pop_table_start + AddressDelta::from(pop_entry_size * stack_size);
+            // The push, poke, and pop tables are interleaved, so that each
+            // entry is a single run of instructions entered at a different
+            // point for each. Push and poke differ only in the stack size,
+            // which the push site increments. See `generate_stack_support`.
+            let entry_size = STACK_ENTRY_SIZE;
+            let push_table_start = *table_start;
+            let poke_table_start = push_table_start;
+            let pop_table_start = push_table_start + 1.into();
+            *table_start = push_table_start + AddressDelta::from(entry_size * stack_size);
 
             let int = InternalParams {
-                push_entry_size: push_entry_size.into(),
-                pop_entry_size: pop_entry_size.into(),
-                poke_entry_size: poke_entry_size.into(),
+                push_entry_size: entry_size.into(),
+                pop_entry_size: entry_size.into(),
+                poke_entry_size: entry_size.into(),
                 push_table_start,
                 pop_table_start,
                 poke_table_start,
             };
 
-            BackendParams::Internal(Rc::new(int))
+            BackendParams::Internal(Shared::new(int))
         }
-        StackConfig::External(cell_name) => {
-            let ext = ExternalParams {
-                cell_name: cell_name.clone(),
-            };
-            BackendParams::External(Rc::new(ext))
+        StackConfig::External(ext) => {
+            let mut ext = ext.clone();
+            if ext.is_banked() {
+                let read = *table_start;
+                let write = read + ext.bank_routine_size();
+                *table_start = write + ext.bank_routine_size();
+                ext.bank_routines = Some(BankRoutines { read, write });
+            }
+
+            BackendParams::External(Shared::new(ext))
         }
+    }
+}
+
+/// Fails if function calls can provably overflow the default stack.
+fn check_stack_usage(stack_config: &StackConfig, stack_usage: &StackUsage) -> Result<()> {
+    let capacity = match stack_config {
+        // No stack, so any calls were already rejected.
+        StackConfig::Internal(0) => return Ok(()),
+        StackConfig::Internal(size) => *size,
+        StackConfig::External(ext) => match ext.len {
+            Some(len) => len,
+            None => return Ok(()),
+        },
     };
 
-    Ok(IntermediateRepresentation {
-        ops: context.ops,
-        stack_config,
-        functions: context
-            .functions
-            .into_iter()
-            .map(|(k, v)| (k, Rc::new(v)))
-            .collect(),
-        labels: context.labels,
-        backend,
-        backend_params,
-    })
+    match stack_usage {
+        StackUsage::Bounded(depth) if *depth > capacity => bail!(
+            "function calls may use {} stack entries, but the stack only has room for {}",
+            depth,
+            capacity
+        ),
+        _ => Ok(()),
+    }
 }
 
-struct ParserContext {
+/// External stacks placed in the same cell must not share any addresses.
+fn check_stack_overlap(
+    stack_config: &StackConfig,
+    named_stacks: &[(StackName, StackConfig)],
+) -> Result<()> {
+    let stacks: Vec<(String, &ExternalParams)> =
+        std::iter::once(("default".to_string(), stack_config))
+            .chain(
+                named_stacks
+                    .iter()
+                    .map(|(name, config)| (name.to_string(), config)),
+            )
+            .filter_map(|(name, config)| match config {
+                StackConfig::External(ext) => Some((name, ext)),
+                StackConfig::Internal(..) => None,
+            })
+            .collect();
+
+    for (j, (name1, ext1)) in stacks.iter().enumerate() {
+        for (name2, ext2) in stacks[j + 1..].iter() {
+            // Stacks spanning several banks can't share them.
+            if ext1.is_banked() || ext2.is_banked() {
+                if let Some(cell) = ext1.cells().find(|c| ext2.cells().any(|d| *c == d)) {
+                    bail!(
+                        "{} stack and {} stack both use {}, but stacks spanning several banks may not share them",
+                        name1,
+                        name2,
+                        cell
+                    );
+                }
+                continue;
+            }
+
+            let end1 = ext1.len.map(|len| ext1.offset + len).unwrap_or(usize::MAX);
+            let end2 = ext2.len.map(|len| ext2.offset + len).unwrap_or(usize::MAX);
+            if ext1.cell_name == ext2.cell_name && ext1.offset < end2 && ext2.offset < end1 {
+                bail!(
+                    "{} stack and {} stack overlap in {}; give them disjoint `offset` and `len`",
+                    name1,
+                    name2,
+                    ext1.cell_name
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// What preparse finds the program declares: everything lowering a part of
+/// the program needs to know about the rest of it.
+#[derive(Clone, Debug, PartialEq)]
+struct Declarations {
+    // What the compiler's own variables start with. See
+    // `CompileOptions::variable_prefix`.
+    variable_prefix: String,
+
+    // Function definitions. Each is given its address as it's lowered; see
+    // `ParserContext::defined`.
+    functions: HashMap<FunctionName, FunctionOp>,
+
+    // Functions declared `inline`, whose bodies are parsed again at each call
+    // in place of the call.
+    inline_functions: HashMap<FunctionName, Shared<InlineFunction>>,
+
+    // Backend being used (internal stack based on jump table or
+    // external memory cell).
+    backend: Backend,
+
+    // FIXME: Refactor this, backend, et al and init order.
+    has_stack: bool,
+
+    // Stacks declared with `stack_config ... as name`, in declaration order.
+    named_stacks: Vec<(StackName, StackConfig)>,
+
+    // Checks requested with `debug` directives.
+    debug: DebugOptions,
+
+    // Set by an `epilogue` directive.
+    epilogue: Option<Epilogue>,
+
+    // Whether the default stack was configured with `stack_config size auto`.
+    // Until the program is parsed, its size is a placeholder.
+    auto_stack_size: bool,
+
+    // Whether preparse is inside an `asm { ... }` block.
+    in_asm_block: bool,
+}
+
+struct ParserContext<'a> {
+    declared: &'a Declarations,
+
     // The IR instructions being emitted.
     ops: Vec<IrOp>,
 
+    // The source line each of `ops` came from.
+    op_lines: Vec<usize>,
+
     // The number of output instructions that will be emitted by the
     // ops we have thus far. Each IrOp is typically a fixed number
     // of Mindustry statements (usually more than one), but a few
     // (such as function calls) vary.
     instruction_count: Address,
 
-    // Backend being used (internal stack based on jump table or
-    // external memory cell).
-    backend: Backend,
+    // Temporaries for the stack variables a statement uses, released after
+    // each line.
+    temporaries: Temporaries,
+
+    // Tracks structures that desugar into multiple statements (if/else, loops,
+    // functions, etc). Note that in the look up sense, only functions are
+    // scopes. A variable access inside a loop has the same rules as anywhere
+    // else in the function body/global scope it rolls up to.
+    //
+    // These are indices into `ops`.
+    scope_stack: Vec<IrIndex>,
+
+    // The functions defined so far, with their addresses.
+    defined: HashMap<FunctionName, FunctionOp>,
+
+    // Jump labels.
+    labels: HashMap<LabelName, Address>,
+
+    // Whether we are inside an `asm { ... }` block, whose lines are passed
+    // through verbatim until the closing `}`.
+    in_asm_block: bool,
+
+    // The peephole optimizer, if enabled, and whether the last line with any
+    // code could be merged with what follows.
+    peephole: Option<Peephole>,
+    last_straight_line: bool,
+
+    // The source line being parsed.
+    line_no: usize,
+
+    // The number of inline calls so far, used to give the labels of each its
+    // own names.
+    inline_count: usize,
+}
+
+/// An `inline fn`, as declared, and the lines of its body.
+#[derive(Debug, PartialEq)]
+struct InlineFunction {
+    function: FunctionOp,
+    body: Vec<String>,
+}
+
+impl<'a> ParserContext<'a> {
+    fn new(declared: &'a Declarations, peephole: Option<Peephole>) -> ParserContext<'a> {
+        ParserContext {
+            declared,
+            ops: Vec::default(),
+            op_lines: Vec::default(),
+            instruction_count: Address::from(0),
+            temporaries: Temporaries::new(declared.backend, &declared.variable_prefix),
+            scope_stack: Vec::default(),
+            defined: HashMap::default(),
+            labels: HashMap::default(),
+            in_asm_block: false,
+            peephole,
+            last_straight_line: false,
+            line_no: 0,
+            inline_count: 0,
+        }
+    }
+
+    /// Moves a part of the program lowered on its own into place after what
+    /// has been lowered so far, adding its errors to `errors`. Labels are
+    /// checked for being defined twice here, since a part only knows its own.
+    fn append(
+        &mut self,
+        part: LoweredPart,
+        source: &[String],
+        errors: &mut Vec<CompileError>,
+    ) -> Result<()> {
+        let offset = self.instruction_count - Address::from(0);
+        let first_op = self.ops.len();
+        for mut op in part.ops {
+            op.relocate(&|address| Ok(address + offset))?;
+            if let IrOp::Break(BreakOp { index }) | IrOp::Continue(ContinueOp { index }) = &mut op {
+                *index = (first_op + **index).into();
+            }
+            self.ops.push(op);
+        }
+        self.op_lines.extend(part.op_lines);
+        self.instruction_count += part.size;
+
+        let mut part_errors = part.errors;
+        for (label, address) in part.labels {
+            if self
+                .labels
+                .insert(label.clone(), address + offset)
+                .is_none()
+            {
+                continue;
+            }
+
+            let op = (first_op..self.ops.len())
+                .find(|j| matches!(&self.ops[*j], IrOp::Label(op) if op.target == label))
+                .context("Internal error: label defined without an op")?;
+            let line = self.op_lines[op];
+            let err = anyhow::anyhow!("label {} is defined a second time here", label);
+            part_errors.push(CompileError::from_anyhow(
+                err.context(SourceLine {
+                    stage: "",
+                    line,
+                    text: source[line].clone(),
+                }),
+                true,
+            ));
+        }
+        part_errors.sort_by_key(CompileError::line);
+        errors.extend(part_errors);
+
+        for (name, mut function) in part.functions {
+            function.address = function.address.map(|address| address + offset);
+            function.end = function.end.map(|address| address + offset);
+            self.defined.insert(name, function);
+        }
+        if let Some(peephole) = self.peephole.as_mut() {
+            peephole.saved += part.peephole_saved;
+        }
+
+        Ok(())
+    }
+}
+
+impl ParserContext<'_> {
+    /// Parses a line and adds its ops to the program, running them through
+    /// the peephole optimizer if enabled.
+    fn parse_and_push(&mut self, line: &str) -> Result<()> {
+        // Some ops update this state themselves, but we pull out the common case of one op here.
+        let clean = clean_line(line);
+        let tok = lex_line(clean);
+        let straight_line = !self.in_asm_block && is_straight_line(&tok);
+        let seq = if self.in_asm_block {
+            self.parse_asm_line(line)?
+        } else {
+            self.parse_line(clean, &tok)?
+        };
+        self.temporaries.release_all();
+
+        let mut mergeable = straight_line && self.last_straight_line;
+        for op in seq.0 {
+            let last = self.ops.last().filter(|_| mergeable);
+            let rewrite = match self.peephole.as_mut() {
+                Some(peephole) => peephole.rewrite(last, op),
+                None => Rewrite::Keep(op),
+            };
+            mergeable = straight_line;
 
-    // Tracks structures that desugar into multiple statements (if/else, loops,
-    // functions, etc). Note that in the look up sense, only functions are
-    // scopes. A variable access inside a loop has the same rules as anywhere
-    // else in the function body/global scope it rolls up to.
-    //
-    // These are indices into `ops`.
-    scope_stack: Vec<IrIndex>,
+            let op = match rewrite {
+                Rewrite::Keep(op) => op,
+                Rewrite::Drop => {
+                    log::trace!("line {}: peephole dropped an op", self.line_no);
+                    continue;
+                }
+                Rewrite::MergeWithLast(op) => {
+                    let last = self.ops.pop().unwrap();
+                    self.op_lines.pop();
+                    log::trace!(
+                        "line {}: peephole merged op {} into the next",
+                        self.line_no,
+                        self.ops.len()
+                    );
+                    self.instruction_count =
+                        self.instruction_count - last.code_size(self.declared.backend);
+                    match op {
+                        Some(op) => op,
+                        None => continue,
+                    }
+                }
+            };
 
-    // Function definitions.
-    functions: HashMap<FunctionName, FunctionOp>,
+            log::trace!(
+                "line {}: op {} at {} takes {}: {:?}",
+                self.line_no,
+                self.ops.len(),
+                self.instruction_count,
+                op.code_size(self.declared.backend),
+                op
+            );
+            self.instruction_count += op.code_size(self.declared.backend);
+            self.ops.push(op);
+            self.op_lines.push(self.line_no);
+        }
 
-    // Jump labels.
-    labels: HashMap<LabelName, Address>,
+        if !tok.is_empty() && !tok[0].starts_with("//") {
+            self.last_straight_line = straight_line;
+        }
 
-    // FIXME: Refactor this, backend, et al and init order.
-    has_stack: bool,
+        Ok(())
+    }
 }
 
-impl ParserContext {
+impl Declarations {
     // FIXME: Try to share more code between preparse and parse. It's
     // straightforward to share more parsing code; sharing the state logic is
     // harder because things will be an error in psas2 that are expected in
@@ -152,10 +1296,34 @@ impl ParserContext {
         stack_config: &mut Option<StackConfig>,
         preparse_fn_stack: &mut Vec<Option<FunctionName>>,
     ) -> Result<()> {
+        if self.in_asm_block {
+            if tok == ["}"] {
+                self.in_asm_block = false;
+            }
+            return Ok(());
+        }
+
         match tok.get(0).copied() {
+            Some("asm") => self.preparse_asm(&tok[1..]),
             Some("fn") => self.preparse_function(&tok[1..], preparse_fn_stack),
+            Some("inline") if tok.get(1).copied() == Some("fn") => {
+                self.preparse_function(&tok[2..], preparse_fn_stack)
+            }
             Some("let") => self.preparse_let(&tok[1..], preparse_fn_stack),
+            Some("return") => {
+                // A return outside a function is reported in the main pass.
+                if let Some(function) = preparse_enclosing_function(preparse_fn_stack) {
+                    let function = self.functions.get_mut(function).unwrap();
+                    function.note_return(&tok[1..]);
+                }
+                Ok(())
+            }
+            Some(label) if tok.len() == 1 && label.ends_with(':') => {
+                self.preparse_label(&label[..label.len() - 1], preparse_fn_stack)
+            }
             Some("stack_config") => self.preparse_stack_config(&tok[1..], stack_config),
+            Some("debug") => self.preparse_debug(&tok[1..]),
+            Some("epilogue") => self.preparse_epilogue(&tok[1..]),
             Some("}") if tok.last().copied() == Some("{") => Ok(()),
             Some("}") => {
                 preparse_fn_stack.pop().context("missing opening {")?;
@@ -171,28 +1339,77 @@ impl ParserContext {
         }
     }
 
+    fn preparse_asm(&mut self, tok: &[&str]) -> Result<()> {
+        if tok.is_empty() {
+            bail!("form is `asm {` or `asm instruction [args...]`");
+        }
+
+        if tok == ["{"] {
+            self.in_asm_block = true;
+        }
+        Ok(())
+    }
+
     fn preparse_stack_config(
         &mut self,
         tok: &[&str],
         stack_config: &mut Option<StackConfig>,
     ) -> Result<()> {
-        if tok.len() != 2 || (tok[0] != "size" && tok[0] != "cell") {
-            bail!("form is `stack_config [ size <stack_size> | cell <cell_name> ]` {");
+        let (config, auto, name) = parse_stack_config(tok)?;
+
+        match name {
+            None => {
+                if stack_config.is_some() {
+                    bail!("stack config set for second time here");
+                }
+                self.auto_stack_size = auto;
+                stack_config.replace(config);
+            }
+            Some(_) if auto => {
+                bail!("only the unnamed stack may use `size auto`, since it alone holds function calls");
+            }
+            Some(name) => {
+                if self.named_stacks.iter().any(|(other, _)| *other == name) {
+                    bail!("stack {} is configured a second time here", name);
+                }
+                if let StackConfig::Internal(0) = config {
+                    bail!("named stack {} must have a size greater than 0", name);
+                }
+                self.named_stacks.push((name, config));
+            }
         }
 
-        if stack_config.is_some() {
-            bail!("stack config set for second time here");
+        Ok(())
+    }
+
+    fn preparse_debug(&mut self, tok: &[&str]) -> Result<()> {
+        const FORM: &str = "form is `debug [stack_guard | stack_canary] [message_block]`";
+
+        let message = match tok.len() {
+            1 => "message1",
+            2 => tok[1],
+            _ => bail!(FORM),
+        };
+        let message = Some(Symbol::new(message));
+
+        match tok[0] {
+            "stack_guard" => self.debug.stack_guard = message,
+            "stack_canary" => self.debug.stack_canary = message,
+            _ => bail!(FORM),
         }
 
-        if tok[0] == "size" {
-            let size: usize = tok[1]
-                .parse()
-                .context("stack size must be a non-negative integer")?;
-            stack_config.replace(StackConfig::Internal(size));
-        } else {
-            stack_config.replace(StackConfig::External(Rc::new(tok[1].to_string())));
+        Ok(())
+    }
+
+    fn preparse_epilogue(&mut self, tok: &[&str]) -> Result<()> {
+        if tok.len() != 1 {
+            bail!("form is `epilogue [auto | end | stop | loop]`");
+        }
+        if self.epilogue.is_some() {
+            bail!("epilogue may only be given once");
         }
 
+        self.epilogue = Some(tok[0].try_into()?);
         Ok(())
     }
 
@@ -226,14 +1443,8 @@ impl ParserContext {
 
         let name = tok[0];
 
-        let mut it = preparse_fn_stack.iter().rev();
-        let function_name = loop {
-            match it.next() {
-                None => bail!("let may only be used within a function",),
-                Some(None) => {}
-                Some(Some(f)) => break f,
-            }
-        };
+        let function_name = preparse_enclosing_function(preparse_fn_stack)
+            .context("let may only be used within a function")?;
 
         let name: StackVar = name.try_into().with_context(|| {
             format!(
@@ -252,8 +1463,41 @@ impl ParserContext {
         Ok(())
     }
 
+    /// Labels defined in a function are local to it, so we need to know them
+    /// all up front to resolve jumps to labels later in the function.
+    fn preparse_label(
+        &mut self,
+        name: &str,
+        preparse_fn_stack: &[Option<FunctionName>],
+    ) -> Result<()> {
+        if let Some(function_name) = preparse_enclosing_function(preparse_fn_stack) {
+            let label: LabelName = name.try_into().context("label statement label")?;
+            let function = self.functions.get_mut(function_name).unwrap();
+            function.labels.insert(label);
+        }
+
+        Ok(())
+    }
+}
+
+impl ParserContext<'_> {
+    /// Maps a label name as written to the one it refers to at this point in
+    /// the program, which is the function's own if it defines one by that name.
+    fn resolve_label(&self, label: LabelName) -> Result<LabelName> {
+        if let Some(function_name) = self.find_enclosing_function()? {
+            if self.declared.functions[&function_name]
+                .labels
+                .contains(&label)
+            {
+                return Ok(LabelName::scoped(&function_name, &label));
+            }
+        }
+
+        Ok(label)
+    }
+
     fn require_stack(&self) -> Result<()> {
-        if !self.has_stack {
+        if !self.declared.has_stack {
             bail!("This function requires that a stack be configured. Use, e.g., `stack_config cell bank1` to use an external memory bank or `stack_config size <size>` for an internal jump-table stack. Size must be greater than 0, since setting it to 0 explicitly disables the stack.");
         } else {
             Ok(())
@@ -265,9 +1509,18 @@ impl ParserContext {
             return Ok(None.into());
         }
 
-        if tok[0] == "stack_config" {
+        if tok[0] == "stack_config" || tok[0] == "debug" || tok[0] == "epilogue" {
             // Handled in first pass.
             Ok(None.into())
+        } else if tok[0] == "asm" {
+            // Form was validated in the first pass.
+            if tok[1..] == ["{"] {
+                self.in_asm_block = true;
+                Ok(None.into())
+            } else {
+                let command = MindustryCommand::raw(line["asm".len()..].trim());
+                Ok(IrOp::MindustryCommand(MindustryOp { command }).into())
+            }
         } else if tok[0] == "callproc" {
             self.parse_callproc(&tok[1..])
         } else if tok[0] == "ret" {
@@ -278,6 +1531,10 @@ impl ParserContext {
         } else if tok[0].starts_with("//") {
             // Comment
             Ok(None.into())
+        } else if tok[0].starts_with("#allow") {
+            // Read again by `find_warnings`.
+            parse_allow(line)?;
+            Ok(None.into())
         } else if tok[0] == "push" {
             self.parse_push(&tok[1..])
         } else if tok[0] == "poke" {
@@ -302,6 +1559,10 @@ impl ParserContext {
             self.parse_if(&tok[1..])
         } else if tok[0] == "fn" {
             self.parse_function(&tok[1..])
+        } else if tok[0] == "inline" {
+            bail!("inline functions must be defined outside any block")
+        } else if tok[0] == "else" {
+            bail!("else must follow the closing brace of an if, as `}} else {{`")
         } else if tok[0] == "return" {
             self.parse_return(&tok[1..])
         } else if tok[0] == "call" {
@@ -316,6 +1577,29 @@ impl ParserContext {
             self.parse_set(line)
         } else if tok[0] == "print" {
             self.parse_print(line)
+        } else if tok[0] == "abs" {
+            self.parse_abs(&tok[1..])
+        } else if tok[0] == "min" || tok[0] == "max" {
+            self.parse_min_max(tok[0], &tok[1..])
+        } else if tok[0] == "clamp" {
+            self.parse_clamp(&tok[1..])
+        } else if tok[0] == "sense" {
+            self.parse_sense(&tok[1..])
+        } else if tok[0] == "sleep" {
+            self.parse_sleep(&tok[1..])
+        } else if tok[0] == "busywait" {
+            self.parse_busywait(&tok[1..])
+        } else if tok[0] == "include" {
+            bail!("include needs an include resolver; see `Compiler::include_resolver`")
+        } else if tok[0] == "benchmark_lap" {
+            if tok.len() != 1 {
+                bail!("form is `benchmark_lap`");
+            }
+            Ok(IrOp::BenchmarkLap(BenchmarkLapOp {}).into())
+        } else if tok[0] == "bind" {
+            self.parse_bind(&tok[1..])
+        } else if let Some(params) = unit_control_params(tok[0]) {
+            self.parse_unit_control(tok[0], params, &tok[1..])
         } else {
             self.parse_mindustry_command(&tok)
         }
@@ -327,7 +1611,10 @@ impl ParserContext {
             bail!("form is `callproc label`");
         }
         let target = tok[0].try_into().context("callproc target label")?;
-        Ok(IrOp::CallProc(CallProcOp { target }).into())
+        let target = self.resolve_label(target)?;
+        let mut seq = self.stack_guard(&StackRef::Default, 1)?;
+        seq.push(IrOp::CallProc(CallProcOp { target }));
+        Ok(seq)
     }
 
     fn parse_ret(&mut self, tok: &[&str]) -> Result<IrSequence> {
@@ -341,6 +1628,7 @@ impl ParserContext {
 
     fn parse_label(&mut self, name: &str) -> Result<IrSequence> {
         let target: LabelName = name.try_into().context("label statement label")?;
+        let target = self.resolve_label(target)?;
         let prev = self.labels.insert(target.clone(), self.instruction_count);
         if prev.is_some() {
             bail!("label {} is defined a second time here", target);
@@ -348,48 +1636,92 @@ impl ParserContext {
         Ok(IrOp::Label(LabelOp { target }).into())
     }
 
+    /// The overflow check to put before an op pushing `entries` values to
+    /// `stack`, if using `debug stack_guard`.
+    fn stack_guard(&self, stack: &StackRef, entries: usize) -> Result<IrSequence> {
+        if self.declared.debug.stack_guard.is_none() {
+            return Ok(None.into());
+        }
+
+        let op = StackGuardOp {
+            stack: stack.clone(),
+            entries,
+            function: self.find_enclosing_function()?,
+        };
+        Ok(IrOp::StackGuard(op).into())
+    }
+
+    /// Resolves the optional stack name taken by push, pop, peek, and poke.
+    fn parse_stack_ref(&self, name: Option<&str>) -> Result<StackRef> {
+        match name {
+            None => {
+                self.require_stack()?;
+                Ok(StackRef::Default)
+            }
+            Some(name) => {
+                let name: StackName = name.try_into().context("stack name")?;
+                let (_, config) = self
+                    .declared
+                    .named_stacks
+                    .iter()
+                    .find(|(other, _)| *other == name)
+                    .with_context(|| {
+                        format!(
+                            "no stack named {}; declare it with `stack_config ... as {}`",
+                            name, name
+                        )
+                    })?;
+                Ok(StackRef::Named(name, config.backend()))
+            }
+        }
+    }
+
     fn parse_push(&mut self, tok: &[&str]) -> Result<IrSequence> {
-        self.require_stack()?;
-        if !tok.is_empty() {
-            bail!("form is `push`");
+        if tok.len() > 1 {
+            bail!("form is `push [stack]`");
         }
 
-        Ok(IrOp::Push(PushOp {}).into())
+        let stack = self.parse_stack_ref(tok.first().copied())?;
+        let mut seq = self.stack_guard(&stack, 1)?;
+        seq.push(IrOp::Push(PushOp { stack }));
+        Ok(seq)
     }
 
     fn parse_pop(&mut self, tok: &[&str]) -> Result<IrSequence> {
-        self.require_stack()?;
-        if !tok.is_empty() {
-            bail!("form is `pop`");
+        if tok.len() > 1 {
+            bail!("form is `pop [stack]`");
         }
 
-        Ok(IrOp::Pop(PopOp {}).into())
+        let stack = self.parse_stack_ref(tok.first().copied())?;
+        Ok(IrOp::Pop(PopOp { stack }).into())
     }
 
     fn parse_peek(&mut self, tok: &[&str]) -> Result<IrSequence> {
-        self.require_stack()?;
-        let depth = if tok.len() == 0 {
-            MindustryTerm::zero()
-        } else if tok.len() == 1 {
-            tok[0].try_into().context("peek depth")?
-        } else {
-            bail!("form is `peek [depth]`")
+        if tok.len() > 2 {
+            bail!("form is `peek [depth [stack]]`")
+        }
+
+        let depth = match tok.first() {
+            None => MindustryTerm::zero(),
+            Some(depth) => (*depth).try_into().context("peek depth")?,
         };
+        let stack = self.parse_stack_ref(tok.get(1).copied())?;
 
-        Ok(IrOp::Peek(PeekOp { depth }).into())
+        Ok(IrOp::Peek(PeekOp { depth, stack }).into())
     }
 
     fn parse_poke(&mut self, tok: &[&str]) -> Result<IrSequence> {
-        self.require_stack()?;
-        let depth = if tok.len() == 0 {
-            MindustryTerm::zero()
-        } else if tok.len() == 1 {
-            tok[0].try_into().context("poke depth")?
-        } else {
-            bail!("form is `poke [depth]`");
+        if tok.len() > 2 {
+            bail!("form is `poke [depth [stack]]`");
+        }
+
+        let depth = match tok.first() {
+            None => MindustryTerm::zero(),
+            Some(depth) => (*depth).try_into().context("poke depth")?,
         };
+        let stack = self.parse_stack_ref(tok.get(1).copied())?;
 
-        Ok(IrOp::Poke(PokeOp { depth }).into())
+        Ok(IrOp::Poke(PokeOp { depth, stack }).into())
     }
 
     fn parse_jump(&mut self, tok: &[&str]) -> Result<IrSequence> {
@@ -401,6 +1733,7 @@ impl ParserContext {
         let (mut ir_seq, condition) = cond.context("jump condition")?;
 
         let target = tok[0].try_into().context("jump label")?;
+        let target = self.resolve_label(target)?;
         ir_seq.push(IrOp::Jump(JumpOp { target, condition }).into());
         Ok(ir_seq)
     }
@@ -486,12 +1819,14 @@ impl ParserContext {
         self.require_stack()?;
         // We already validated the form in pre-processing.
         let name: FunctionName = tok[0].try_into().unwrap();
-        let function = self.functions.get_mut(&name).unwrap();
+        let mut function = self.declared.functions[&name].clone();
         function.start_parse(self.instruction_count);
+        let size = function.code_size(self.declared.backend);
+        self.defined.insert(name.clone(), function);
 
         self.scope_stack.push(self.ops.len().into());
 
-        Ok(IrOp::Function(name, function.code_size(self.backend)).into())
+        Ok(IrOp::Function(name, size).into())
     }
 
     fn parse_return(&mut self, value_names: &[&str]) -> Result<IrSequence> {
@@ -499,8 +1834,13 @@ impl ParserContext {
         let function_name = self
             .find_enclosing_function()?
             .context("return may not be used outside a function")?;
-        let function = &self.functions[&function_name];
-        let statement = ReturnOp::new(function, value_names, self.backend);
+        let function = &self.declared.functions[&function_name];
+        let statement = ReturnOp::new(
+            function,
+            value_names,
+            self.declared.backend,
+            self.declared.debug.stack_canary.is_some(),
+        );
         statement
             .with_context(|| {
                 format!(
@@ -525,7 +1865,7 @@ impl ParserContext {
         let arg: Term = name.try_into()?;
         match (function_name.as_ref(), &arg) {
             (Some(function_name), Term::StackVar(stack_arg)) => {
-                let function = &self.functions[&function_name];
+                let function = &self.declared.functions[function_name];
                 let local = function.locals.get(&stack_arg);
                 local
                     .with_context(|| {
@@ -547,7 +1887,6 @@ impl ParserContext {
     }
 
     fn parse_call(&mut self, tok: &[&str]) -> Result<IrSequence> {
-        self.require_stack()?;
         if tok.len() < 1 {
             bail!("form is `call name [args] [-> return_values]");
         }
@@ -556,6 +1895,12 @@ impl ParserContext {
 
         let (arg_names, return_names) = parse_arrow(&tok[1..])?;
 
+        if let Some(inline) = self.declared.inline_functions.get(&name).cloned() {
+            return self.parse_inline_call(&inline, arg_names, return_names);
+        }
+
+        self.require_stack()?;
+
         let call_site_function = self.find_enclosing_function()?;
 
         let mut args = Vec::with_capacity(arg_names.len());
@@ -577,10 +1922,16 @@ impl ParserContext {
             returns.push(ret);
         }
 
-        let function = self
-            .functions
-            .get(&name)
-            .with_context(|| format!("function definition for {} not found", &name))?;
+        let function = self.declared.functions.get(&name).ok_or_else(|| {
+            CompileError::undefined(
+                SymbolKind::Function,
+                &name,
+                self.declared
+                    .functions
+                    .keys()
+                    .chain(self.declared.inline_functions.keys()),
+            )
+        })?;
 
         if function.args.len() != args.len() {
             bail!(
@@ -600,15 +1951,153 @@ impl ParserContext {
             );
         }
 
-        Ok(IrOp::Call(CallOp::new(
+        // The return address, args, other locals, and canary.
+        let entries = 1 + function.locals.len() + self.declared.debug.canary_size();
+        let mut seq = self.stack_guard(&StackRef::Default, entries)?;
+        seq.push(IrOp::Call(CallOp::new(
             args,
             returns,
-            function.locals.len(),
-            name.clone(),
+            function,
             call_site_function,
-            self.backend,
-        ))
-        .into())
+            self.declared.backend,
+            self.declared.debug.stack_canary.is_some(),
+        )));
+        Ok(seq)
+    }
+
+    /// Parses the body of an inline function in place of a call to it. Its
+    /// args and stack variables become globals named after the function, each
+    /// `return` sets the return bindings and jumps past the end of the body,
+    /// and its labels are renamed for this call.
+    fn parse_inline_call(
+        &mut self,
+        inline: &InlineFunction,
+        arg_names: &[&str],
+        return_names: &[&str],
+    ) -> Result<IrSequence> {
+        let function = &inline.function;
+        let name = &function.name;
+        if function.args.len() != arg_names.len() {
+            bail!(
+                "function {} takes {} args but called with {} values",
+                name,
+                function.args.len(),
+                arg_names.len()
+            );
+        }
+
+        if function.returns.len() != return_names.len() {
+            bail!(
+                "function {} returns {} values but being bound to {} bindings",
+                name,
+                function.returns.len(),
+                return_names.len()
+            );
+        }
+
+        let prefix = &self.declared.variable_prefix;
+        let global = |var: &str| format!("{}{}_{}", prefix, name, &var[1..]);
+        let label = |label: &str| format!("MF_inline{}_{}", self.inline_count, label);
+        let end_label = label("end");
+
+        let body: Vec<Vec<&str>> = inline
+            .body
+            .iter()
+            .map(|line| lex_line(clean_line(line)))
+            .collect();
+        let labels: HashSet<&str> = body
+            .iter()
+            .filter(|tok| tok.len() == 1 && tok[0].ends_with(':'))
+            .map(|tok| &tok[0][..tok[0].len() - 1])
+            .collect();
+        let last_code = body
+            .iter()
+            .rposition(|tok| !tok.is_empty() && !tok[0].starts_with("//"));
+
+        let mut lines: Vec<String> = function
+            .args
+            .iter()
+            .zip(arg_names.iter())
+            .map(|(arg, value)| format!("set {} {}", global(arg.as_ref()), value))
+            .collect();
+
+        let mut jumps_to_end = false;
+        let mut in_asm_block = false;
+        for (j, (line, tok)) in inline.body.iter().zip(body.iter()).enumerate() {
+            if in_asm_block {
+                in_asm_block = tok[..] != ["}"];
+                lines.push(line.clone());
+                continue;
+            }
+            in_asm_block = tok[..] == ["asm", "{"];
+
+            let tok: Vec<String> = tok
+                .iter()
+                .enumerate()
+                .map(|(k, t)| {
+                    if t.starts_with('*') && function.locals.contains_key(&(*t).try_into()?) {
+                        Ok(global(t))
+                    } else if tok.len() == 1
+                        && t.ends_with(':')
+                        && labels.contains(&t[..t.len() - 1])
+                    {
+                        Ok(format!("{}:", label(&t[..t.len() - 1])))
+                    } else if k == 1 && ["jump", "callproc"].contains(&tok[0]) && labels.contains(t)
+                    {
+                        Ok(label(t))
+                    } else {
+                        Ok(t.to_string())
+                    }
+                })
+                .collect::<Result<_>>()?;
+
+            match tok.first().map(String::as_str) {
+                Some("let") => {}
+                Some("call") => bail!("inline function {} may not call other functions", name),
+                Some("return") => {
+                    let values = &tok[1..];
+                    if values.len() != return_names.len() {
+                        bail!(
+                            "return from inline function {} with {} values instead of {}",
+                            name,
+                            values.len(),
+                            return_names.len()
+                        );
+                    }
+
+                    // With several values, go through the return registers as
+                    // a call does, in case a binding is also a value.
+                    if values.len() == 1 {
+                        lines.push(format!("set {} {}", return_names[0], values[0]));
+                    } else {
+                        for (k, value) in values.iter().enumerate() {
+                            lines.push(format!("set {}ret{} {}", prefix, k, value));
+                        }
+                        for (k, binding) in return_names.iter().enumerate() {
+                            lines.push(format!("set {} {}ret{}", binding, prefix, k));
+                        }
+                    }
+
+                    if Some(j) != last_code {
+                        lines.push(format!("jump {} always", end_label));
+                        jumps_to_end = true;
+                    }
+                }
+                _ => lines.push(tok.join(" ")),
+            }
+        }
+
+        if jumps_to_end {
+            lines.push(format!("{}:", end_label));
+        }
+        self.inline_count += 1;
+
+        for line in lines.iter() {
+            self.parse_and_push(line)
+                .with_context(|| format!("in inline function {}: {}", name, line))?;
+        }
+
+        Ok(None.into())
     }
 
     fn parse_let(&mut self, tok: &[&str]) -> Result<IrSequence> {
@@ -620,22 +2109,81 @@ impl ParserContext {
         let function_name = self
             .find_enclosing_function()?
             .context("let may not be used outside a function")?;
-        let function = &self.functions[&function_name];
+        let function = &self.declared.functions[&function_name];
         let name: StackVar = name.try_into().unwrap();
         let pos = FrameIndex::from(function.locals.len());
         Ok(IrOp::Let(LetOp { name, pos }).into())
     }
 
     fn parse_op(&mut self, tok: &[&str]) -> Result<IrSequence> {
-        let operation = Rc::new(tok[0].to_string());
+        if tok.len() != 4 {
+            bail!("form is `op operation dest arg1 arg2`");
+        }
+
+        let operation = tok[0];
         let dest: Term = tok[1].try_into().context("op dest")?;
         let arg1: Term = tok[2].try_into().context("op arg1")?;
         let arg2: Term = tok[3].try_into().context("op arg2")?;
+        self.math(operation, dest, arg1, arg2)
+    }
+
+    /// `abs x` or `abs dest value`.
+    fn parse_abs(&mut self, tok: &[&str]) -> Result<IrSequence> {
+        let (dest, value) = match tok {
+            [value] => (*value, *value),
+            [dest, value] => (*dest, *value),
+            _ => bail!("form is `abs x` or `abs dest value`"),
+        };
+
+        let dest: Term = dest.try_into().context("abs dest")?;
+        let value: Term = value.try_into().context("abs value")?;
+        self.math("abs", dest, value, MindustryTerm::zero().into())
+    }
+
+    /// `min dest a b` and `max dest a b`.
+    fn parse_min_max(&mut self, operation: &str, tok: &[&str]) -> Result<IrSequence> {
+        if tok.len() != 3 {
+            bail!("form is `{} dest a b`", operation);
+        }
+
+        let dest: Term = tok[0].try_into().context("dest")?;
+        let arg1: Term = tok[1].try_into().context("arg1")?;
+        let arg2: Term = tok[2].try_into().context("arg2")?;
+        self.math(operation, dest, arg1, arg2)
+    }
+
+    /// `clamp x low high` or `clamp dest value low high`. Desugars to a `max`
+    /// into `dest` followed by a `min` in place.
+    fn parse_clamp(&mut self, tok: &[&str]) -> Result<IrSequence> {
+        let (dest, value, low, high) = match tok {
+            [value, low, high] => (*value, *value, *low, *high),
+            [dest, value, low, high] => (*dest, *value, *low, *high),
+            _ => bail!("form is `clamp x low high` or `clamp dest value low high`"),
+        };
+
+        let dest: Term = dest.try_into().context("clamp dest")?;
+        let value: Term = value.try_into().context("clamp value")?;
+        let low: Term = low.try_into().context("clamp low")?;
+        let high: Term = high.try_into().context("clamp high")?;
+
+        // The bounds are read after `dest` is first written.
+        if dest == low || dest == high {
+            bail!("clamp bounds may not also be the destination");
+        }
+
+        let mut seq = self.math("max", dest.clone(), value, low)?;
+        seq.0
+            .append(&mut self.math("min", dest.clone(), dest, high)?.0);
+        Ok(seq)
+    }
+
+    /// Generates the IR for an `op`, any of whose terms may be on the stack.
+    fn math(&mut self, operation: &str, dest: Term, arg1: Term, arg2: Term) -> Result<IrSequence> {
         let function = self.find_enclosing_function()?;
         let (mut seq, dest, arg1, arg2, mut write) =
-            ir_read_two_write_one(dest, arg1, arg2, &function)?;
+            ir_read_two_write_one(dest, arg1, arg2, &function, &mut self.temporaries)?;
         seq.push(IrOp::Math(MathOp {
-            operation,
+            operation: Symbol::new(operation),
             dest,
             arg1,
             arg2,
@@ -646,9 +2194,10 @@ impl ParserContext {
 
     fn parse_print(&mut self, line: &str) -> Result<IrSequence> {
         let value: Term = line.trim()[5..].trim().try_into().context("print value")?;
-        let (mut seq, value) = ir_read_one_arg(value, &self.find_enclosing_function()?)?;
+        let function = self.find_enclosing_function()?;
+        let (mut seq, value) = ir_read_one_arg(value, &function, &mut self.temporaries)?;
         seq.push(IrOp::MindustryCommand(MindustryOp {
-            command: vec![Rc::new(format!("print {}", &value))]
+            command: vec![Symbol::new("print"), Symbol::new(value.as_ref())]
                 .try_into()
                 .context("create print command")?,
         }));
@@ -660,14 +2209,122 @@ impl ParserContext {
             .trim()
             .split_once(|c: char| c.is_whitespace())
         {
+            let source = source.trim();
+            if let Some((target, property)) = split_sensor_property(source) {
+                return self.parse_sense(&[dest, target, property]);
+            }
+
             let dest: Term = dest.try_into().context("set dest")?;
             let source: Term = source.try_into().context("set source")?;
-            ir_copy_arg(dest, source, &self.find_enclosing_function()?)
+            let function = self.find_enclosing_function()?;
+            ir_copy_arg(dest, source, &function, &mut self.temporaries)
         } else {
             bail!("set form is `set a b`");
         }
     }
 
+    fn parse_sense(&mut self, tok: &[&str]) -> Result<IrSequence> {
+        if tok.len() != 3 {
+            bail!("form is `sense dest target @property`");
+        }
+
+        let dest: Term = tok[0].try_into().context("sense dest")?;
+        let target: Term = tok[1].try_into().context("sense target")?;
+        let property: Term = tok[2].try_into().context("sense property")?;
+        let function = self.find_enclosing_function()?;
+        let (mut seq, dest, target, property, mut write) =
+            ir_read_two_write_one(dest, target, property, &function, &mut self.temporaries)?;
+        seq.push(IrOp::Sensor(SensorOp {
+            dest,
+            target,
+            property,
+        }));
+        seq.0.append(&mut write.0);
+        Ok(seq)
+    }
+
+    /// `sleep <duration> [seconds|ticks]` desugars to Mindustry's `wait`, which
+    /// takes seconds. Ticks are converted at 60 per second.
+    fn parse_sleep(&mut self, tok: &[&str]) -> Result<IrSequence> {
+        let ticks = match tok.get(1).copied() {
+            None if tok.len() == 1 => false,
+            Some("seconds") if tok.len() == 2 => false,
+            Some("ticks") if tok.len() == 2 => true,
+            _ => bail!("form is `sleep duration [seconds|ticks]`"),
+        };
+
+        let duration: Term = tok[0].try_into().context("sleep duration")?;
+        let function = self.find_enclosing_function()?;
+        let (mut seq, duration) = ir_read_one_arg(duration, &function, &mut self.temporaries)?;
+
+        let seconds = if !ticks {
+            duration
+        } else if let Ok(ticks) = duration.as_ref().parse::<f64>() {
+            (ticks / TICKS_PER_SECOND).to_string().as_str().try_into()?
+        } else {
+            self.temporaries.release(&duration);
+            let seconds = self.temporaries.allocate_last();
+            seq.push(IrOp::Math(MathOp {
+                operation: Symbol::new("div"),
+                dest: seconds.clone(),
+                arg1: duration,
+                arg2: TICKS_PER_SECOND.to_string().as_str().try_into()?,
+            }));
+            seconds
+        };
+
+        seq.push(IrOp::MindustryCommand(MindustryOp {
+            command: vec![Symbol::new("wait"), Symbol::new(seconds.as_ref())]
+                .try_into()
+                .context("create wait command")?,
+        }));
+        Ok(seq)
+    }
+
+    fn parse_busywait(&mut self, tok: &[&str]) -> Result<IrSequence> {
+        if tok.is_empty() {
+            bail!("form is `busywait condition`");
+        }
+
+        let cond = self.parse_condition(tok);
+        let (mut seq, condition) = cond.context("busywait condition")?;
+
+        // Jump back to the start of any stack reads, so they are redone each
+        // time around.
+        seq.push(IrOp::BusyWait(BusyWaitOp {
+            start: self.instruction_count,
+            condition,
+        }));
+        Ok(seq)
+    }
+
+    fn parse_bind(&mut self, tok: &[&str]) -> Result<IrSequence> {
+        if tok.len() != 1 {
+            bail!("form is `bind unit_type`");
+        }
+
+        self.parse_mindustry_command(&["ubind", tok[0]])
+    }
+
+    /// Expands e.g. `approach x y r` to `ucontrol approach x y r 0 0`, since
+    /// `ucontrol` always takes five arguments regardless of the subcommand.
+    fn parse_unit_control(
+        &mut self,
+        name: &str,
+        params: &[&str],
+        tok: &[&str],
+    ) -> Result<IrSequence> {
+        if tok.len() != params.len() {
+            bail!("form is `{}`", [&[name], params].concat().join(" "));
+        }
+
+        let mut command = vec!["ucontrol", name];
+        command.extend_from_slice(tok);
+        command.resize(2 + UNIT_CONTROL_ARGS, "0");
+        self.parse_mindustry_command(&command)
+            .with_context(|| format!("unit control {}", name))
+    }
+
     fn parse_closing_brace(&mut self, tok: &[&str]) -> Result<IrSequence> {
         let open_index = match self.scope_stack.pop() {
             Some(index) => index,
@@ -683,9 +2340,26 @@ impl ParserContext {
         }
     }
 
+    /// Lines in an `asm` block are emitted as is, without looking at the
+    /// tokens at all. Only blank lines and comments are dropped, since they
+    /// would otherwise throw off the instruction count.
+    fn parse_asm_line(&mut self, line: &str) -> Result<IrSequence> {
+        let line = line.trim();
+        if clean_line(line) == "}" {
+            self.in_asm_block = false;
+            return Ok(None.into());
+        }
+
+        if line.is_empty() || line.starts_with("//") {
+            return Ok(None.into());
+        }
+
+        let command = MindustryCommand::raw(line);
+        Ok(IrOp::MindustryCommand(MindustryOp { command }).into())
+    }
+
     fn parse_mindustry_command(&mut self, tok: &[&str]) -> Result<IrSequence> {
-        let command = tok.iter().copied().map(String::from).map(Rc::new);
-        let command: Vec<Rc<String>> = command.collect();
+        let command: Vec<Symbol> = tok.iter().copied().map(Symbol::new).collect();
         let command = command.try_into().context("parse mindustry command")?;
         let command = MindustryOp { command: command };
         Ok(IrOp::MindustryCommand(command).into())
@@ -693,8 +2367,8 @@ impl ParserContext {
 
     /// If the condition uses stack vars, get them and adjust the condition
     /// to use the temporaries.
-    fn parse_condition(&self, tok: &[&str]) -> Result<(IrSequence, Condition)> {
-        parse_condition(self.find_enclosing_function()?, tok)
+    fn parse_condition(&mut self, tok: &[&str]) -> Result<(IrSequence, Condition)> {
+        parse_condition(self.find_enclosing_function()?, tok, &mut self.temporaries)
     }
 
     /// Finds the top-most enclosing function definition, skipping over ifs and
@@ -753,7 +2427,9 @@ impl ParserContext {
             match &mut self.ops[*open_index] {
                 IrOp::If(ref mut if_op) => {
                     let op = IrOp::Else(ElseOp::declare());
-                    if_op.resolve_forward(self.instruction_count + op.code_size(self.backend));
+                    if_op.resolve_forward(
+                        self.instruction_count + op.code_size(self.declared.backend),
+                    );
                     self.scope_stack.push(self.ops.len().into());
                     Ok(op.into())
                 }
@@ -763,13 +2439,14 @@ impl ParserContext {
             // DoWhile case. Only needed for break/continue.
             match &mut self.ops[*open_index] {
                 IrOp::DoWhile(ref mut do_while_op) => {
-                    let cond = parse_condition(enclosing_function, &tok[1..]);
+                    let cond =
+                        parse_condition(enclosing_function, &tok[1..], &mut self.temporaries);
                     let (end_seq, condition) = cond.context("do-while condition")?;
                     let ops = do_while_op.resolve_forward(
                         self.instruction_count,
                         end_seq,
                         condition,
-                        self.backend,
+                        self.declared.backend,
                     );
                     Ok(ops)
                 }
@@ -791,7 +2468,13 @@ impl ParserContext {
             IrOp::InfiniteLoop(ref mut loop_op) => {
                 Ok(loop_op.resolve_forward(self.instruction_count))
             }
-            IrOp::Function(_func, _size) => {
+            IrOp::Function(func, _size) => {
+                let func = func.clone();
+                if let Some(function) = self.defined.get_mut(&func) {
+                    function.end = Some(self.instruction_count);
+                    function.end_line = Some(self.line_no);
+                }
+
                 // FIXME: at present, we don't check that all paths
                 // return. That would be hard to do without actually
                 // recursively parsing the input. At this time, user
@@ -813,7 +2496,7 @@ impl ParserContext {
                 // into the IrSequence. It would be safer to replace it with a
                 // less general type.
                 Ok(while_op
-                    .resolve_forward(self.instruction_count, self.backend)
+                    .resolve_forward(self.instruction_count, self.declared.backend)
                     .clone())
             }
             _ => unreachable!("unexpected op {:?} on scope stack", op),
@@ -821,9 +2504,41 @@ impl ParserContext {
     }
 }
 
+/// The lines of the body of each `inline fn` defined outside any block, which
+/// are parsed at each call rather than where they are defined.
+fn collect_inline_functions(ast: &Ast) -> HashMap<FunctionName, Vec<String>> {
+    let mut functions = HashMap::default();
+    for statement in ast.statements.iter() {
+        if let StatementKind::Function {
+            name,
+            inline: true,
+            body,
+            ..
+        } = &statement.kind
+        {
+            let body = body
+                .body_lines()
+                .iter()
+                .map(|line| line.text.clone())
+                .collect();
+            functions.insert(name.clone(), body);
+        }
+    }
+
+    functions
+}
+
+/// The innermost function being defined, according to the preparse scope stack.
+fn preparse_enclosing_function(
+    preparse_fn_stack: &[Option<FunctionName>],
+) -> Option<&FunctionName> {
+    preparse_fn_stack.iter().rev().flatten().next()
+}
+
 fn parse_condition(
     function: Option<FunctionName>,
     tok: &[&str],
+    temps: &mut Temporaries,
 ) -> Result<(IrSequence, Condition)> {
     if tok[0] == "always" {
         return Ok((None.into(), Condition::always()));
@@ -835,18 +2550,32 @@ fn parse_condition(
         bail!("condition form is `cond a b`, `always`, or `never`")
     }
 
-    // FIXME: validate the condition?
-    let cond = Rc::new(tok[0].to_string());
+    let cond = Symbol::new(tok[0]);
 
     let arg1: Term = tok[1].try_into().context("condition arg1")?;
     let arg2: Term = tok[2].try_into().context("condition arg2")?;
 
-    let (read_sequence, arg1, arg2) = ir_read_two_args(arg1, arg2, &function)?;
+    let (read_sequence, arg1, arg2) = ir_read_two_args(arg1, arg2, &function, temps)?;
     let condition = (cond, arg1, arg2).try_into().context("condition")?;
 
     Ok((read_sequence, condition))
 }
 
+/// Splits the `vault1.@copper` shorthand for a sensor read into the target and
+/// the property. Strings are left alone, since they may contain anything.
+fn split_sensor_property(source: &str) -> Option<(&str, &str)> {
+    if source.starts_with('"') || source.contains(char::is_whitespace) {
+        return None;
+    }
+
+    let split = source.find(".@")?;
+    if split == 0 {
+        return None;
+    }
+
+    Some((&source[..split], &source[split + 1..]))
+}
+
 /// Takes a token sequence like `foo bar -> qux` and splits on the arrow,
 /// ensuring there is at most one arrow. If the arrow is omitted, all tokens are
 /// interpreted as preceeding it.
@@ -872,7 +2601,99 @@ fn parse_arrow<'a, 'b>(tokens: &'a [&'b str]) -> Result<(&'a [&'b str], &'a [&'b
     }
 }
 
-fn clean_line(line: &str) -> &str {
+/// The words a statement of this language may start with, which `parse_line`
+/// and preparse dispatch on before anything else. A statement starting with
+/// a word from `UNIT_CONTROL` or `INSTRUCTIONS` instead is a unit control
+/// command or a Mindustry instruction. See `is_keyword`.
+pub const STATEMENTS: &[&str] = &[
+    "abs",
+    "asm",
+    "benchmark_lap",
+    "bind",
+    "break",
+    "busywait",
+    "call",
+    "callproc",
+    "clamp",
+    "continue",
+    "debug",
+    "do",
+    "else",
+    "epilogue",
+    "fn",
+    "if",
+    "include",
+    "inline",
+    "jump",
+    "let",
+    "loop",
+    "max",
+    "min",
+    "op",
+    "peek",
+    "poke",
+    "pop",
+    "print",
+    "push",
+    "ret",
+    "return",
+    "sense",
+    "set",
+    "sleep",
+    "stack_config",
+    "while",
+];
+
+/// Whether a statement may start with `word`, so that it can't be used as a
+/// name: one of `STATEMENTS`, a unit control command, or a Mindustry
+/// instruction.
+pub fn is_keyword(word: &str) -> bool {
+    STATEMENTS.contains(&word)
+        || unit_control_params(word).is_some()
+        || INSTRUCTIONS.iter().any(|(name, _, _)| *name == word)
+}
+
+/// The number of arguments `ucontrol` takes after the subcommand.
+const UNIT_CONTROL_ARGS: usize = 5;
+
+/// The `ucontrol` subcommands and the names of the arguments they use. Each
+/// may be used as a statement of its own, e.g. `move x y`.
+///
+/// `stop` is deliberately missing, since it is also a Mindustry instruction.
+/// Use `ucontrol stop` for that.
+pub const UNIT_CONTROL: &[(&str, &[&str])] = &[
+    ("idle", &[]),
+    ("move", &["x", "y"]),
+    ("approach", &["x", "y", "radius"]),
+    ("pathfind", &["x", "y"]),
+    ("autoPathfind", &[]),
+    ("boost", &["enable"]),
+    ("target", &["x", "y", "shoot"]),
+    ("targetp", &["unit", "shoot"]),
+    ("itemDrop", &["to", "amount"]),
+    ("itemTake", &["from", "item", "amount"]),
+    ("payDrop", &[]),
+    ("payTake", &["takeUnits"]),
+    ("payEnter", &[]),
+    ("mine", &["x", "y"]),
+    ("flag", &["value"]),
+    ("build", &["x", "y", "block", "rotation", "config"]),
+    ("getBlock", &["x", "y", "type", "building", "floor"]),
+    ("within", &["x", "y", "radius", "result"]),
+    ("unbind", &[]),
+];
+
+fn unit_control_params(name: &str) -> Option<&'static [&'static str]> {
+    UNIT_CONTROL
+        .iter()
+        .find(|(command, _)| *command == name)
+        .map(|(_, params)| *params)
+}
+
+/// Mindustry logic runs at a fixed 60 ticks per second.
+const TICKS_PER_SECOND: f64 = 60.0;
+
+pub(crate) fn clean_line(line: &str) -> &str {
     let mut line = line.trim();
 
     // A convenience. It's hard to remember not to add them when writing
@@ -885,8 +2706,32 @@ fn clean_line(line: &str) -> &str {
     line
 }
 
-fn lex_line(line: &str) -> Vec<&str> {
-    line.split_whitespace().collect()
+/// Splits on whitespace, except that a quoted string is always a single token
+/// (quotes included), even if it contains whitespace. An unterminated string
+/// runs to the end of the line.
+pub(crate) fn lex_line(line: &str) -> Vec<&str> {
+    let mut tokens = Vec::default();
+    let mut start = None;
+    let mut in_string = false;
+
+    for (j, c) in line.char_indices() {
+        if in_string {
+            in_string = c != '"';
+        } else if c.is_whitespace() {
+            if let Some(start) = start.take() {
+                tokens.push(&line[start..j]);
+            }
+        } else {
+            start.get_or_insert(j);
+            in_string = c == '"';
+        }
+    }
+
+    if let Some(start) = start {
+        tokens.push(&line[start..]);
+    }
+
+    tokens
 }
 
 #[cfg(test)]
@@ -957,6 +2802,20 @@ mod tests {
         assert_eq!(b, &["not"]);
     }
 
+    #[test]
+    fn test_lex_line() {
+        assert!(lex_line("").is_empty());
+        assert!(lex_line("   ").is_empty());
+        assert_eq!(lex_line("  op add  a b 1 "), &["op", "add", "a", "b", "1"]);
+        assert_eq!(
+            lex_line("call greet \"hello  world\" x"),
+            &["call", "greet", "\"hello  world\"", "x"]
+        );
+        assert_eq!(lex_line("print \"\""), &["print", "\"\""]);
+        assert_eq!(lex_line("a\"b c\"d e"), &["a\"b c\"d", "e"]);
+        assert_eq!(lex_line("print \"oops  x"), &["print", "\"oops  x"]);
+    }
+
     #[test]
     fn test_parse_arrow_error() {
         for text in &[
@@ -0,0 +1,171 @@
+use crate::*;
+
+/// Packages a generated program as a Mindustry schematic: a processor running
+/// it, linked to a memory block for each cell its stacks use. Returns the
+/// schematic in base64, as Mindustry's "Import from clipboard" takes it.
+pub fn schematic_base64(ir: &IntermediateRepresentation, output: &[String]) -> String {
+    base64(&schematic(ir, output))
+}
+
+/// Packages a generated program as a Mindustry schematic, in the binary form
+/// of a `.msch` file. See `schematic_base64`.
+pub fn schematic(ir: &IntermediateRepresentation, output: &[String]) -> Vec<u8> {
+    // The processor goes at the left, with the memory in a row to its right.
+    // Memory banks are 2x2, and the position of an even-sized block is the
+    // lower left of its middle four tiles.
+    let mut links = Vec::default();
+    let mut x = 1;
+    for cell in schematic_cells(ir) {
+        let block = if cell.starts_with("bank") {
+            "memory-bank"
+        } else {
+            "memory-cell"
+        };
+        links.push((cell, block, x));
+        x += if block == "memory-bank" { 2 } else { 1 };
+    }
+
+    let mut blocks = vec!["micro-processor"];
+    for (_, block, _) in links.iter() {
+        if !blocks.contains(block) {
+            blocks.push(block);
+        }
+    }
+
+    let height = if blocks.contains(&"memory-bank") {
+        2
+    } else {
+        1
+    };
+    let mut data = Vec::default();
+    write_u16(&mut data, x as u16);
+    write_u16(&mut data, height);
+
+    // Tags.
+    data.push(1);
+    write_utf(&mut data, "name");
+    write_utf(&mut data, "routerbolt program");
+
+    data.push(blocks.len() as u8);
+    for block in blocks.iter() {
+        write_utf(&mut data, block);
+    }
+
+    data.extend_from_slice(&(1 + links.len() as u32).to_be_bytes());
+
+    // The processor, configured as `LogicBlock.compress` does, with each
+    // link relative to it. The config is an object of type 14, a byte array.
+    let mut config = vec![1];
+    let code = output.join("\n");
+    data_bytes(&mut config, code.as_bytes());
+    config.extend_from_slice(&(links.len() as u32).to_be_bytes());
+    for (name, _, x) in links.iter() {
+        write_utf(&mut config, name);
+        write_u16(&mut config, *x as u16);
+        write_u16(&mut config, 0);
+    }
+    let config = zlib_stored(&config);
+
+    data.push(0);
+    data.extend_from_slice(&0u32.to_be_bytes());
+    data.push(14);
+    data_bytes(&mut data, &config);
+    data.push(0);
+
+    // The memory blocks, which need no config.
+    for (_, block, x) in links.iter() {
+        data.push(blocks.iter().position(|b| b == block).unwrap() as u8);
+        data.extend_from_slice(&((*x as u32) << 16).to_be_bytes());
+        data.push(0);
+        data.push(0);
+    }
+
+    let mut schematic = b"msch".to_vec();
+    schematic.push(1);
+    schematic.extend(zlib_stored(&data));
+    schematic
+}
+
+/// The memory cells used by the default and named stacks, in order.
+fn schematic_cells(ir: &IntermediateRepresentation) -> Vec<Symbol> {
+    let configs =
+        std::iter::once(&ir.stack_config).chain(ir.named_stacks.iter().map(|s| &s.stack_config));
+
+    let mut cells: Vec<Symbol> = Vec::default();
+    for config in configs {
+        if let StackConfig::External(ext) = config {
+            for cell in ext.cells() {
+                if !cells.contains(cell) {
+                    cells.push(cell.clone());
+                }
+            }
+        }
+    }
+    cells
+}
+
+fn write_u16(out: &mut Vec<u8>, n: u16) {
+    out.extend_from_slice(&n.to_be_bytes());
+}
+
+/// Writes a string as Java's `DataOutput.writeUTF` does, which for the ASCII
+/// names used here is its length followed by its bytes.
+fn write_utf(out: &mut Vec<u8>, s: &str) {
+    write_u16(out, s.len() as u16);
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Writes a length-prefixed byte array.
+fn data_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// Wraps `data` in a zlib stream, as Java's `DeflaterOutputStream` writes and
+/// `InflaterInputStream` reads. Programs are small, so the data is stored
+/// without compression rather than pulling in a compressor.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+
+    let mut chunks = data.chunks(0xFFFF).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(chunk) = chunks.next() {
+        // Each stored block has a header byte marking whether it is the last,
+        // then its length and the length's complement, little endian.
+        out.push(chunks.peek().is_none() as u8);
+        let len = chunk.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data.iter() {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    out
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::default();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (j, byte)| n | (*byte as u32) << (16 - 8 * j));
+        for j in 0..4 {
+            if j <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * j) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use crate::*;
+
+/// Where an instruction of the generated program came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceLocation {
+    /// The source line, counting from 0 as `Diagnostic::line` does.
+    pub line: usize,
+
+    /// The index of the IR op that generated it.
+    pub op: usize,
+}
+
+/// A function call in the generated program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallSite {
+    /// The address of the first instruction of the call.
+    pub start: usize,
+
+    /// The address the function returns to, after the jump into it.
+    pub return_address: usize,
+}
+
+/// Where the default stack keeps its entries, and so the stack variables of
+/// each frame.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum StackLocation {
+    /// In the variables of the jump tables, `MF_stack[<n>]`.
+    #[default]
+    Internal,
+
+    /// In these memory cells, in order. Each but the last holds `BANK_SIZE`
+    /// entries.
+    External(Vec<Symbol>),
+}
+
+/// Maps each instruction of the generated program back to the source, e.g. to
+/// set breakpoints on source lines in the emulator, or to make sense of a jump
+/// address seen in game.
+///
+/// Instructions that don't come from any source line, such as the setup at the
+/// start of the program and the stack tables at the end, have no location.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    pub instructions: Vec<Option<SourceLocation>>,
+
+    /// Each call, in order of address, for `Emulator::step_over` and
+    /// `step_out`.
+    pub calls: Vec<CallSite>,
+
+    /// The text of each source line, to show alongside the instructions.
+    pub source_lines: Vec<String>,
+
+    /// Each function and the addresses it spans, in order of address.
+    pub functions: Vec<(FunctionName, std::ops::Range<usize>)>,
+
+    /// The stack variables of each function, by how far below the top of
+    /// the stack they are in its frame, as `MF_stack_sz` minus the depth.
+    pub stack_vars: HashMap<FunctionName, HashMap<StackVar, usize>>,
+
+    /// Where the default stack, which holds the frames, keeps its entries.
+    pub stack: StackLocation,
+
+    /// The address of each `benchmark_lap`, for `Benchmark` to count laps
+    /// at.
+    pub laps: Vec<usize>,
+}
+
+impl SourceMap {
+    /// Maps the instructions generated for the ops of `ir`. Since addresses are
+    /// fixed as the program is parsed, this doesn't need to generate it.
+    pub fn new(ir: &IntermediateRepresentation) -> SourceMap {
+        let mut instructions = Vec::default();
+        let mut calls = Vec::default();
+        let mut laps = Vec::default();
+        for (op, (j, line)) in ir.ops().iter().zip(ir.op_lines.iter().enumerate()) {
+            if let IrOp::Call(call) = op {
+                let before_call_size: usize = call.before_call_size.into();
+                calls.push(CallSite {
+                    start: instructions.len(),
+                    return_address: instructions.len() + before_call_size,
+                });
+            }
+            if let IrOp::BenchmarkLap(..) = op {
+                laps.push(instructions.len());
+            }
+
+            let size: usize = op.code_size(*ir.backend()).into();
+            let location = line.map(|line| SourceLocation { line, op: j });
+            instructions.extend(std::iter::repeat_n(location, size));
+        }
+
+        let mut functions: Vec<_> = ir
+            .functions()
+            .values()
+            .filter_map(|function| match (function.address, function.end) {
+                (Some(address), Some(end)) => {
+                    Some((function.name.clone(), address.into()..end.into()))
+                }
+                _ => None,
+            })
+            .collect();
+        functions.sort_by_key(|(_, range)| range.start);
+
+        let mut stack_vars = HashMap::default();
+        for function in ir.functions().values() {
+            let mut vars = HashMap::default();
+            for var in function.locals.keys() {
+                if let Ok(depth) = function.stack_var_depth(var) {
+                    vars.insert(var.clone(), depth.into());
+                }
+            }
+            stack_vars.insert(function.name.clone(), vars);
+        }
+
+        let stack = match ir.backend_params() {
+            BackendParams::Internal(_) => StackLocation::Internal,
+            BackendParams::External(ext) => StackLocation::External(ext.cells().cloned().collect()),
+        };
+
+        SourceMap {
+            instructions,
+            calls,
+            source_lines: ir.source_lines.clone(),
+            functions,
+            stack_vars,
+            stack,
+            laps,
+        }
+    }
+
+    /// The source location of the instruction at `address`, if any.
+    pub fn location(&self, address: usize) -> Option<SourceLocation> {
+        self.instructions.get(address).copied().flatten()
+    }
+
+    /// The function the instruction at `address` is in, if any.
+    pub fn function(&self, address: usize) -> Option<&FunctionName> {
+        self.functions
+            .iter()
+            .find(|(_, range)| range.contains(&address))
+            .map(|(name, _)| name)
+    }
+
+    /// The first instruction generated for each of `lines` that generated any,
+    /// to pass to `Emulator::set_breakpoints`.
+    pub fn breakpoints(&self, lines: &[usize]) -> Vec<usize> {
+        lines
+            .iter()
+            .filter_map(|line| {
+                self.instructions
+                    .iter()
+                    .position(|location| location.map(|location| location.line) == Some(*line))
+            })
+            .collect()
+    }
+}
+
+/// One instruction per line, as `<address>\t<source line>\t<op index>`, with
+/// `-` for instructions with no location. Source lines count from 1, as in
+/// diagnostics.
+impl std::fmt::Display for SourceMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (address, location) in self.instructions.iter().enumerate() {
+            match location {
+                Some(location) => {
+                    writeln!(f, "{}\t{}\t{}", address, location.line + 1, location.op)?
+                }
+                None => writeln!(f, "{}\t-\t-", address)?,
+            }
+        }
+        Ok(())
+    }
+}
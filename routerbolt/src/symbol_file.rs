@@ -0,0 +1,68 @@
+use crate::*;
+
+/// The names in a program and where they ended up, for debuggers, or a
+/// person stepping through it in game, to put names to the addresses and
+/// stack entries they see.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolFile {
+    /// Each function and the addresses it spans, in order of address.
+    pub functions: Vec<(FunctionName, std::ops::Range<usize>)>,
+
+    /// Each label and its address, in order of address. Labels defined in a
+    /// function are given as `<function>.<label>`.
+    pub labels: Vec<(LabelName, usize)>,
+
+    /// The stack variables of each function and how far below the top of
+    /// the stack each is in its frame, so that it's in the entry
+    /// `MF_stack_sz` minus that. In order of function, then depth.
+    pub stack_vars: Vec<(FunctionName, StackVar, usize)>,
+}
+
+impl SymbolFile {
+    pub fn new(ir: &IntermediateRepresentation) -> SymbolFile {
+        let source_map = ir.source_map();
+
+        let mut labels: Vec<_> = ir
+            .labels()
+            .iter()
+            .map(|(name, address)| (name.clone(), (*address).into()))
+            .collect();
+        labels.sort_by(|a: &(LabelName, usize), b| (a.1, a.0.as_ref()).cmp(&(b.1, b.0.as_ref())));
+
+        let mut stack_vars: Vec<_> = source_map
+            .stack_vars
+            .iter()
+            .flat_map(|(function, vars)| {
+                vars.iter()
+                    .map(move |(var, depth)| (function.clone(), var.clone(), *depth))
+            })
+            .collect();
+        stack_vars.sort_by(|a, b| {
+            (a.0.as_ref(), a.2, a.1.as_ref()).cmp(&(b.0.as_ref(), b.2, b.1.as_ref()))
+        });
+
+        SymbolFile {
+            functions: source_map.functions,
+            labels,
+            stack_vars,
+        }
+    }
+}
+
+/// One symbol per line, with its fields separated by tabs: `fn <name> <start>
+/// <end>` for each function, `label <name> <address>` for each label, and
+/// `var <function> <name> <depth>` for each stack variable.
+impl std::fmt::Display for SymbolFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (name, range) in self.functions.iter() {
+            writeln!(f, "fn\t{}\t{}\t{}", name, range.start, range.end)?;
+        }
+        for (name, address) in self.labels.iter() {
+            writeln!(f, "label\t{}\t{}", name, address)?;
+        }
+        for (function, var, depth) in self.stack_vars.iter() {
+            writeln!(f, "var\t{}\t{}\t{}", function, var, depth)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+use crate::*;
+
+/// A generated program whose jumps go to named labels rather than addresses,
+/// along with the table giving the address of each label. This is easier to
+/// review than the numeric program, and can be patched by hand and then turned
+/// into the numeric program with `resolve`.
+///
+/// Jumps to a label or function use its name, and any other jump target is
+/// named `L<address>`. Only `jump` targets are symbolic: calls, returns, and
+/// the internal stack's tables compute addresses into `@counter`, and those
+/// stay numeric, so patches must not move any code.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolicProgram {
+    pub instructions: Vec<String>,
+
+    /// Each label and its address, in order of address.
+    pub labels: Vec<(String, usize)>,
+}
+
+impl SymbolicProgram {
+    /// Replaces the jump targets of `output`, the program generated from `ir`,
+    /// with labels.
+    pub fn new(ir: &IntermediateRepresentation, output: &[String]) -> SymbolicProgram {
+        // Prefer the source's own names for an address, labels before functions.
+        let mut names: Vec<(String, usize)> = ir
+            .labels()
+            .iter()
+            .map(|(name, address)| (name.to_string(), (*address).into()))
+            .collect();
+        names.sort();
+        let mut functions: Vec<(String, usize)> = ir
+            .functions()
+            .values()
+            .filter_map(|function| Some((function.name.to_string(), function.address?.into())))
+            .collect();
+        functions.sort();
+        names.extend(functions);
+
+        let mut by_address: HashMap<usize, String> = HashMap::default();
+        for (name, address) in names {
+            if by_address.contains_key(&address) {
+                continue;
+            }
+            let name = if by_address.values().any(|other| *other == name) {
+                format!("{}_{}", name, address)
+            } else {
+                name
+            };
+            by_address.insert(address, name);
+        }
+
+        let mut labels: HashMap<String, usize> = HashMap::default();
+        let instructions = output
+            .iter()
+            .map(|line| match jump_target(line) {
+                Some((target, rest)) => {
+                    let address: usize = target.parse().unwrap();
+                    if !by_address.contains_key(&address) {
+                        // Steer clear of a source label that happens to be
+                        // called this.
+                        let mut name = format!("L{}", address);
+                        while by_address.values().any(|other| *other == name) {
+                            name.push('_');
+                        }
+                        by_address.insert(address, name);
+                    }
+                    let name = &by_address[&address];
+                    labels.insert(name.clone(), address);
+                    format!("jump {}{}", name, rest)
+                }
+                None => line.clone(),
+            })
+            .collect();
+
+        let mut labels: Vec<(String, usize)> = labels.into_iter().collect();
+        labels.sort_by_key(|(name, address)| (*address, name.clone()));
+        SymbolicProgram {
+            instructions,
+            labels,
+        }
+    }
+
+    /// Reads a program back from the text of its instructions and of its label
+    /// table, as written by `Display` and `labels_text`.
+    pub fn parse(instructions: &str, labels: &str) -> Result<SymbolicProgram> {
+        let instructions = instructions.lines().map(String::from).collect();
+        let labels = labels
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let tok: Vec<_> = line.split_whitespace().collect();
+                match tok[..] {
+                    [name, address] => {
+                        let address = address
+                            .parse()
+                            .with_context(|| format!("address of label {}", name))?;
+                        Ok((name.to_string(), address))
+                    }
+                    _ => bail!("label table lines are `<name> <address>`: {}", line),
+                }
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(SymbolicProgram {
+            instructions,
+            labels,
+        })
+    }
+
+    /// The label table, one label per line as `<name>\t<address>`.
+    pub fn labels_text(&self) -> String {
+        self.labels
+            .iter()
+            .map(|(name, address)| format!("{}\t{}\n", name, address))
+            .collect()
+    }
+
+    /// The numeric program, with each label replaced by its address.
+    pub fn resolve(&self) -> Result<Vec<String>> {
+        let labels: HashMap<&str, usize> = self
+            .labels
+            .iter()
+            .map(|(name, address)| (name.as_str(), *address))
+            .collect();
+
+        self.instructions
+            .iter()
+            .enumerate()
+            .map(|(j, line)| match jump_operands(line) {
+                Some((target, rest)) if target.parse::<usize>().is_err() => {
+                    let address = labels.get(target).with_context(|| {
+                        format!("Instruction {}: label {} is not defined", j, target)
+                    })?;
+                    Ok(format!("jump {}{}", address, rest))
+                }
+                _ => Ok(line.clone()),
+            })
+            .collect()
+    }
+}
+
+/// One instruction per line.
+impl std::fmt::Display for SymbolicProgram {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for line in self.instructions.iter() {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits a `jump` to a numeric address into the address and the rest of the
+/// instruction.
+fn jump_target(line: &str) -> Option<(&str, &str)> {
+    jump_operands(line).filter(|(target, _)| target.parse::<usize>().is_ok())
+}
+
+fn jump_operands(line: &str) -> Option<(&str, &str)> {
+    let operands = line.strip_prefix("jump ")?.trim_start();
+    match operands.find(char::is_whitespace) {
+        Some(end) => Some((&operands[..end], &operands[end..])),
+        None => Some((operands, "")),
+    }
+}
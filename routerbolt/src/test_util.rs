@@ -4,7 +4,13 @@ use crate::*;
 
 pub fn use_cell(cell: bool, size: usize) -> StackConfig {
     if cell {
-        StackConfig::External(Rc::new("bank1".to_string()))
+        StackConfig::External(ExternalParams {
+            cell_name: Symbol::new("bank1"),
+            offset: 0,
+            len: None,
+            more_cells: Vec::default(),
+            bank_routines: None,
+        })
     } else {
         StackConfig::Internal(size)
     }
@@ -39,19 +45,20 @@ pub fn step_until_equal(
 /// Prints compiler input and annotated output to stderr. Since by default Cargo
 /// swallows output from passing tests, this should only be written on failures.
 pub fn test_compile(text: &str, stack_config: StackConfig) -> Vec<String> {
-    let text = match stack_config {
-        StackConfig::Internal(size) => {
-            format!("stack_config size {}\n{}", size, text)
-        }
-        StackConfig::External(name) => {
-            format!("stack_config cell {}\n{}", name, text)
-        }
-    };
-
-    eprintln!("\n\n---  BEGIN COMPILER INPUT ---\n\n{}\n", &text);
+    eprintln!(
+        "\n\n---  BEGIN COMPILER INPUT ---\n\n{:?}\n{}\n",
+        &stack_config, &text
+    );
     eprintln!("\n\n---    END COMPILER INPUT ---\n\n");
 
-    let ir = parser::parse(&text).unwrap();
+    // The emulator runs programs of any length, and some tests use internal
+    // stacks far larger than a processor could hold.
+    let options = parser::CompileOptions {
+        stack_config: Some(stack_config),
+        instruction_limit: None,
+        ..Default::default()
+    };
+    let ir = parser::parse_with_options(text, &options).unwrap();
     let (output, annotated) = ir.generate().unwrap();
     eprintln!("\n\n--- BEGIN COMPILER OUTPUT ---\n\n");
     for a in annotated {
@@ -62,3 +69,15 @@ pub fn test_compile(text: &str, stack_config: StackConfig) -> Vec<String> {
     eprintln!("\n\n---   END COMPILER OUTPUT ---\n\n");
     output
 }
+
+/// Fails if the program does something different with an internal stack than
+/// with an external one. See `compare_backends`.
+pub fn assert_backends_agree(text: &str, vars: &[&str]) {
+    let options = parser::CompileOptions {
+        instruction_limit: None,
+        ..Default::default()
+    };
+    if let Err(e) = compare_backends(text, &options, 64, vars, 100_000, |_| ()) {
+        panic!("{:?}\n\n{}", e, text);
+    }
+}
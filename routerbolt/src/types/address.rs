@@ -1,6 +1,8 @@
+use serde::{Deserialize, Serialize};
+
 /// Address in the generated program. This is the same as the number used in
 /// "jump", and is just the line number in the program.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Address(usize);
 
 impl std::fmt::Display for Address {
@@ -40,7 +42,7 @@ impl AsRef<usize> for Address {
 }
 
 /// The difference between two addresses.
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct AddressDelta(usize);
 
 impl AddressDelta {
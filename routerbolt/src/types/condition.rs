@@ -1,11 +1,22 @@
 use std::convert::{TryFrom, TryInto};
-use std::rc::Rc;
 
 use crate::*;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// The comparisons Mindustry's `jump` understands.
+pub const CONDITIONS: &[&str] = &[
+    "equal",
+    "notEqual",
+    "lessThan",
+    "lessThanEq",
+    "greaterThan",
+    "greaterThanEq",
+    "strictEqual",
+    "always",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Condition {
-    cond: Rc<String>,
+    cond: Symbol,
     arg1: MindustryTerm,
     arg2: MindustryTerm,
 }
@@ -13,7 +24,7 @@ pub struct Condition {
 impl Condition {
     pub fn always() -> Condition {
         Condition {
-            cond: Rc::new(String::from("always")),
+            cond: Symbol::new("always"),
 
             // By convention. These are the defaults in Mindustry.
             arg1: "x".try_into().unwrap(),
@@ -23,11 +34,45 @@ impl Condition {
 
     pub fn never() -> Condition {
         Condition {
-            cond: Rc::new(String::from("equal")),
+            cond: Symbol::new("equal"),
             arg1: "0".try_into().unwrap(),
             arg2: "1".try_into().unwrap(),
         }
     }
+
+    pub fn is_always(&self) -> bool {
+        self.cond.as_str() == "always"
+    }
+
+    /// The arguments compared, which `always` ignores.
+    pub fn operands(&self) -> Vec<&MindustryTerm> {
+        if self.is_always() {
+            Vec::default()
+        } else {
+            vec![&self.arg1, &self.arg2]
+        }
+    }
+
+    /// The condition that holds exactly when this one does not, if Mindustry
+    /// has one. There is no opposite of `strictEqual`.
+    pub fn negate(&self) -> Option<Condition> {
+        let cond = match self.cond.as_str() {
+            "always" => return Some(Condition::never()),
+            "equal" => "notEqual",
+            "notEqual" => "equal",
+            "lessThan" => "greaterThanEq",
+            "greaterThanEq" => "lessThan",
+            "greaterThan" => "lessThanEq",
+            "lessThanEq" => "greaterThan",
+            _ => return None,
+        };
+
+        Some(Condition {
+            cond: Symbol::new(cond),
+            arg1: self.arg1.clone(),
+            arg2: self.arg2.clone(),
+        })
+    }
 }
 
 impl std::fmt::Display for Condition {
@@ -36,16 +81,23 @@ impl std::fmt::Display for Condition {
     }
 }
 
-impl TryFrom<(Rc<String>, MindustryTerm, MindustryTerm)> for Condition {
+impl TryFrom<(Symbol, MindustryTerm, MindustryTerm)> for Condition {
     type Error = Error;
-    fn try_from(other: (Rc<String>, MindustryTerm, MindustryTerm)) -> Result<Self> {
+    fn try_from(other: (Symbol, MindustryTerm, MindustryTerm)) -> Result<Self> {
         let (cond, arg1, arg2) = other;
 
-        // FIXME: validate the condition
         if cond.is_empty() {
             bail!("Invalid condition: <empty>");
         }
 
+        if !CONDITIONS.contains(&cond.as_str()) {
+            bail!(
+                "Invalid condition: {} (must be one of {})",
+                &cond,
+                CONDITIONS.join(", ")
+            );
+        }
+
         Ok(Condition { cond, arg1, arg2 })
     }
 }
@@ -1,6 +1,8 @@
+use serde::{Deserialize, Serialize};
+
 /// Index relative to the start of a stack frame. Subtract from the frame size
 /// to get the stack depth.
-#[derive(Copy, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Copy, Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct FrameIndex(usize);
 
 impl std::fmt::Display for FrameIndex {
@@ -1,10 +1,10 @@
-use std::convert::{TryFrom, TryInto};
+use std::convert::TryFrom;
 use std::rc::Rc;
 
 use crate::*;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct FunctionName(Rc<String>);
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct FunctionName(Symbol);
 
 impl std::fmt::Display for FunctionName {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -15,40 +15,40 @@ impl std::fmt::Display for FunctionName {
 impl TryFrom<String> for FunctionName {
     type Error = Error;
     fn try_from(other: String) -> Result<Self> {
-        Rc::new(other).try_into()
+        Self::try_from(other.as_str())
     }
 }
 
 impl TryFrom<&str> for FunctionName {
     type Error = Error;
     fn try_from(other: &str) -> Result<Self> {
-        other.to_string().try_into()
+        validate_identifier(other)?;
+        Ok(FunctionName(Symbol::new(other)))
     }
 }
 
 impl TryFrom<Rc<String>> for FunctionName {
     type Error = Error;
     fn try_from(other: Rc<String>) -> Result<Self> {
-        // FIXME: Should probably limit the characters that may be used.
-        Ok(FunctionName(other))
+        Self::try_from(other.as_str())
     }
 }
 
 impl TryFrom<&Rc<String>> for FunctionName {
     type Error = Error;
     fn try_from(other: &Rc<String>) -> Result<Self> {
-        other.clone().try_into()
+        Self::try_from(other.as_str())
     }
 }
 
-impl Into<Rc<String>> for FunctionName {
-    fn into(self) -> Rc<String> {
-        self.0.clone()
+impl From<FunctionName> for Symbol {
+    fn from(other: FunctionName) -> Symbol {
+        other.0
     }
 }
 
 impl AsRef<str> for FunctionName {
     fn as_ref(&self) -> &str {
-        self.0.as_ref()
+        self.0.as_str()
     }
 }
@@ -1,7 +1,9 @@
+use serde::{Deserialize, Serialize};
+
 /// An index into `ops`, the list of IR instructions. This is used when one
 /// instruction needs to refer to another. I guess we could do this with Rc
 /// instead, but this is fine too.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct IrIndex(usize);
 
 impl std::fmt::Display for IrIndex {
@@ -1,10 +1,27 @@
-use std::convert::{TryFrom, TryInto};
+use std::convert::TryFrom;
 use std::rc::Rc;
 
 use crate::*;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct LabelName(Rc<String>);
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct LabelName(Symbol);
+
+impl LabelName {
+    /// The internal name of a label defined inside a function. Since `.` may
+    /// not appear in a label name, this can't collide with any other label.
+    pub fn scoped(function: &FunctionName, label: &LabelName) -> LabelName {
+        LabelName(Symbol::new(&format!("{}.{}", function, label)))
+    }
+
+    /// The name as written in the source, without the function a label
+    /// defined in one is scoped to.
+    pub fn unscoped(&self) -> &str {
+        self.0
+            .as_str()
+            .split_once('.')
+            .map_or(self.0.as_str(), |(_, label)| label)
+    }
+}
 
 impl std::fmt::Display for LabelName {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -15,40 +32,40 @@ impl std::fmt::Display for LabelName {
 impl TryFrom<String> for LabelName {
     type Error = Error;
     fn try_from(other: String) -> Result<Self> {
-        Rc::new(other).try_into()
+        Self::try_from(other.as_str())
     }
 }
 
 impl TryFrom<&str> for LabelName {
     type Error = Error;
     fn try_from(other: &str) -> Result<Self> {
-        other.to_string().try_into()
+        validate_identifier(other)?;
+        Ok(LabelName(Symbol::new(other)))
     }
 }
 
 impl TryFrom<Rc<String>> for LabelName {
     type Error = Error;
     fn try_from(other: Rc<String>) -> Result<Self> {
-        // FIXME: Should probably limit the characters that may be used.
-        Ok(LabelName(other))
+        Self::try_from(other.as_str())
     }
 }
 
 impl TryFrom<&Rc<String>> for LabelName {
     type Error = Error;
     fn try_from(other: &Rc<String>) -> Result<Self> {
-        other.clone().try_into()
+        Self::try_from(other.as_str())
     }
 }
 
-impl Into<Rc<String>> for LabelName {
-    fn into(self) -> Rc<String> {
-        self.0.clone()
+impl From<LabelName> for Symbol {
+    fn from(other: LabelName) -> Symbol {
+        other.0
     }
 }
 
 impl AsRef<str> for LabelName {
     fn as_ref(&self) -> &str {
-        self.0.as_ref()
+        self.0.as_str()
     }
 }
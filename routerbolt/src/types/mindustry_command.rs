@@ -1,11 +1,18 @@
 use std::convert::TryFrom;
 use std::fmt::Write;
-use std::rc::Rc;
 
 use crate::*;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct MindustryCommand(Vec<Rc<String>>);
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MindustryCommand(Vec<Symbol>);
+
+impl MindustryCommand {
+    /// A command that is passed along exactly as written, without any
+    /// validation. Used for `asm` blocks.
+    pub fn raw(line: &str) -> MindustryCommand {
+        MindustryCommand(vec![Symbol::new(line)])
+    }
+}
 
 impl std::fmt::Display for MindustryCommand {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -21,21 +28,116 @@ impl std::fmt::Display for MindustryCommand {
     }
 }
 
-impl TryFrom<Vec<Rc<String>>> for MindustryCommand {
+/// Mindustry instructions, with the minimum and maximum number of arguments
+/// each takes. Mindustry fills in missing trailing arguments with defaults, so
+/// where leaving some off is common, the minimum is lower than the number
+/// Mindustry itself writes out.
+///
+/// Instructions missing from here (e.g., ones added in newer versions) can
+/// still be used via `asm`.
+pub const INSTRUCTIONS: &[(&str, usize, usize)] = &[
+    // Input & output.
+    ("read", 3, 3),
+    ("write", 3, 3),
+    ("draw", 1, 7),
+    ("print", 1, 1),
+    ("format", 1, 1),
+    // Block control.
+    ("drawflush", 1, 1),
+    ("printflush", 1, 1),
+    ("getlink", 2, 2),
+    ("control", 2, 6),
+    ("radar", 7, 7),
+    ("sensor", 3, 3),
+    // Operations.
+    ("set", 2, 2),
+    ("op", 4, 4),
+    ("select", 6, 6),
+    ("lookup", 3, 3),
+    ("packcolor", 5, 5),
+    ("unpackcolor", 5, 5),
+    // Flow control.
+    ("wait", 1, 1),
+    ("stop", 0, 0),
+    ("end", 0, 0),
+    ("noop", 0, 0),
+    ("jump", 2, 4),
+    // Unit control.
+    ("ubind", 1, 1),
+    ("ucontrol", 1, 6),
+    ("uradar", 7, 7),
+    ("ulocate", 4, 8),
+    // World processors.
+    ("getblock", 4, 4),
+    ("setblock", 6, 6),
+    ("spawn", 6, 6),
+    ("status", 4, 4),
+    ("spawnwave", 3, 3),
+    ("setrule", 2, 6),
+    ("message", 1, 2),
+    ("cutscene", 1, 5),
+    ("effect", 1, 6),
+    ("explosion", 8, 8),
+    ("setrate", 1, 1),
+    ("fetch", 3, 5),
+    ("sync", 1, 1),
+    ("getflag", 2, 2),
+    ("setflag", 2, 2),
+    ("setprop", 3, 3),
+    ("playsound", 2, 9),
+    ("setmarker", 3, 5),
+    ("makemarker", 5, 5),
+    ("localeprint", 1, 1),
+];
+
+/// Checks that `tokens` is a known instruction with a plausible number of
+/// arguments.
+fn validate_instruction(tokens: &[Symbol]) -> Result<()> {
+    let name = match tokens.first() {
+        Some(name) => name.as_str(),
+        None => bail!("Mindustry command may not be empty"),
+    };
+
+    let (min, max) = match INSTRUCTIONS.iter().find(|(n, ..)| *n == name) {
+        Some((_, min, max)) => (*min, *max),
+        None => bail!(
+            "unknown Mindustry instruction {} (use `asm` to pass it along anyway)",
+            name
+        ),
+    };
+
+    let args = tokens.len() - 1;
+    if args < min || args > max {
+        if min == max {
+            bail!("{} takes {} arguments but was given {}", name, min, args);
+        }
+        bail!(
+            "{} takes {} to {} arguments but was given {}",
+            name,
+            min,
+            max,
+            args
+        );
+    }
+
+    Ok(())
+}
+
+impl TryFrom<Vec<Symbol>> for MindustryCommand {
     type Error = Error;
-    fn try_from(other: Vec<Rc<String>>) -> Result<Self> {
+    fn try_from(other: Vec<Symbol>) -> Result<Self> {
         for token in other.iter() {
             if token.starts_with("*") {
                 bail!("Mindustry commands and their args may not start with * since we don't currently support stack vars there so it would be confusing");
             }
         }
-        // FIXME: Should probably validate further.
+        validate_instruction(&other)?;
         Ok(MindustryCommand(other))
     }
 }
 
-impl Into<Vec<Rc<String>>> for MindustryCommand {
-    fn into(self) -> Vec<Rc<String>> {
-        self.0
+impl From<MindustryCommand> for Vec<Symbol> {
+    fn from(other: MindustryCommand) -> Vec<Symbol> {
+        other.0
     }
 }
@@ -5,7 +5,10 @@ pub mod function_name;
 pub mod ir_index;
 pub mod label_name;
 pub mod mindustry_command;
+pub mod shared;
 pub mod stack_depth;
+pub mod stack_name;
+pub mod symbol;
 
 pub use address::*;
 pub use condition::*;
@@ -14,13 +17,49 @@ pub use function_name::*;
 pub use ir_index::*;
 pub use label_name::*;
 pub use mindustry_command::*;
+pub use shared::*;
 pub use stack_depth::*;
+pub use stack_name::*;
+pub use symbol::*;
 
 use std::convert::{AsRef, TryFrom};
-use std::rc::Rc;
 
 use crate::*;
 
+/// Mindustry literals and condition keywords, which may not be used as
+/// function or label names, as the words statements start with may not.
+const RESERVED_WORDS: &[&str] = &["always", "false", "never", "null", "true"];
+
+/// Checks that `name` is usable as a function or label name: ASCII letters,
+/// digits, and underscores, not starting with a digit, and neither a reserved
+/// word nor a word a statement may start with, to avoid confusion with them.
+pub fn validate_identifier(name: &str) -> Result<()> {
+    match name.chars().next() {
+        None => bail!("name may not be empty"),
+        Some(c) if c.is_ascii_digit() => {
+            bail!("name \"{}\" may not start with a digit", name)
+        }
+        _ => {}
+    }
+
+    if let Some(c) = name
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && *c != '_')
+    {
+        bail!(
+            "name \"{}\" contains '{}' (only letters, digits, and _ are allowed)",
+            name,
+            c
+        );
+    }
+
+    if RESERVED_WORDS.contains(&name) || parser::is_keyword(name) {
+        bail!("name \"{}\" is reserved", name);
+    }
+
+    Ok(())
+}
+
 /// Mindustry, as a rule, does not (statically) distinguish between Lhs and Rhs.
 /// You can write "op add 1 1 1" and it just won't do anything.
 ///
@@ -30,7 +69,7 @@ use crate::*;
 ///
 /// That's not to say that introducing a type system would be a bad thing, it's
 /// just out of scope.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Term {
     StackVar(StackVar),
 
@@ -39,38 +78,80 @@ pub enum Term {
     Mindustry(MindustryTerm),
 }
 
+/// What the names of the compiler's own variables, such as `MF_acc`, start
+/// with unless `CompileOptions::variable_prefix` says otherwise.
+pub const DEFAULT_VARIABLE_PREFIX: &str = "MF_";
+
 impl Term {
-    pub fn accumulator() -> Term {
-        MindustryTerm::accumulator().into()
+    pub fn accumulator(prefix: &str) -> Term {
+        MindustryTerm::accumulator(prefix).into()
     }
 }
 
 impl MindustryTerm {
     // FIXME: It would be nice to use this more, and have others for constants
     // like the stack.
-    pub fn accumulator() -> MindustryTerm {
-        Self::try_from("MF_acc").unwrap()
+    pub fn accumulator(prefix: &str) -> MindustryTerm {
+        Self::try_from(format!("{}acc", prefix).as_str()).unwrap()
     }
 
-    pub fn stack_sz() -> MindustryTerm {
-        Self::try_from("MF_stack_sz").unwrap()
-    }
-
-    pub fn stack_tmp() -> MindustryTerm {
-        Self::try_from("MF_stack_tmp").unwrap()
+    pub fn stack_sz(prefix: &str) -> MindustryTerm {
+        Self::try_from(format!("{}stack_sz", prefix).as_str()).unwrap()
     }
 
     pub fn zero() -> MindustryTerm {
         Self::try_from("0").unwrap()
     }
+
+    /// The value of this term if it is a non-negative integer literal, in any
+    /// of decimal, hex (`0x1F`), or binary (`0b1010`).
+    pub fn as_integer(&self) -> Option<usize> {
+        let text = self.as_ref();
+        if let Some(digits) = text.strip_prefix("0x") {
+            usize::from_str_radix(digits, 16).ok()
+        } else if let Some(digits) = text.strip_prefix("0b") {
+            usize::from_str_radix(digits, 2).ok()
+        } else {
+            text.parse().ok()
+        }
+    }
+}
+
+/// Mindustry supports hex (`0x1F`), binary (`0b1010`), and color (`%ff0000`
+/// or `%ff0000ff`) literals. We pass them along as is, but catch malformed ones
+/// here rather than letting them silently become variable names.
+fn validate_numeric_literal(text: &str) -> Result<()> {
+    let (digits, radix, lengths): (_, _, &[usize]) = if let Some(d) = text.strip_prefix("0x") {
+        (d, 16, &[])
+    } else if let Some(d) = text.strip_prefix("0b") {
+        (d, 2, &[])
+    } else if let Some(d) = text.strip_prefix('%') {
+        (d, 16, &[6, 8])
+    } else {
+        return Ok(());
+    };
+
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        bail!("malformed literal {}", text);
+    }
+
+    if !lengths.is_empty() && !lengths.contains(&digits.len()) {
+        bail!("color literal {} must have 6 or 8 hex digits", text);
+    }
+
+    if u64::from_str_radix(digits, radix).is_err() {
+        bail!("literal {} is too large", text);
+    }
+
+    Ok(())
 }
 
 /// A Mindustry term.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct MindustryTerm(Rc<String>);
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MindustryTerm(Symbol);
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct StackVar(Rc<String>);
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct StackVar(Symbol);
 
 impl From<MindustryTerm> for Term {
     fn from(other: MindustryTerm) -> Self {
@@ -111,7 +192,9 @@ impl TryFrom<&str> for Term {
             bail!("Symbol may not be empty");
         }
 
-        let value = Rc::new(other.to_string());
+        validate_numeric_literal(other)?;
+
+        let value = Symbol::new(other);
 
         if other.starts_with("*") {
             // Technically I think Mindustry will permit this, but I have to
@@ -152,12 +235,12 @@ impl AsRef<str> for Term {
 
 impl AsRef<str> for MindustryTerm {
     fn as_ref(&self) -> &str {
-        &self.0
+        self.0.as_str()
     }
 }
 
 impl AsRef<str> for StackVar {
     fn as_ref(&self) -> &str {
-        &self.0
+        self.0.as_str()
     }
 }
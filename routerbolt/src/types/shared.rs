@@ -0,0 +1,9 @@
+/// A reference-counted pointer, for data the IR shares, such as function
+/// definitions and backend parameters. This is `Rc`, unless the `sync`
+/// feature is enabled, in which case it's `Arc`, so that the IR is `Send` and
+/// `Sync` and programs can be compiled on several threads at once.
+#[cfg(not(feature = "sync"))]
+pub type Shared<T> = std::rc::Rc<T>;
+
+#[cfg(feature = "sync")]
+pub type Shared<T> = std::sync::Arc<T>;
@@ -1,5 +1,7 @@
+use serde::{Deserialize, Serialize};
+
 /// Stack depth relative to the top.
-#[derive(Copy, Clone, PartialEq, Debug, Eq, Hash)]
+#[derive(Copy, Clone, PartialEq, Debug, Eq, Hash, Serialize, Deserialize)]
 pub struct StackDepth(usize);
 
 impl std::fmt::Display for StackDepth {
@@ -0,0 +1,49 @@
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use crate::*;
+
+/// The name of a stack declared with `stack_config ... as name`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct StackName(Symbol);
+
+impl std::fmt::Display for StackName {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl TryFrom<String> for StackName {
+    type Error = Error;
+    fn try_from(other: String) -> Result<Self> {
+        Self::try_from(other.as_str())
+    }
+}
+
+impl TryFrom<&str> for StackName {
+    type Error = Error;
+    fn try_from(other: &str) -> Result<Self> {
+        validate_identifier(other)?;
+        Ok(StackName(Symbol::new(other)))
+    }
+}
+
+impl TryFrom<Rc<String>> for StackName {
+    type Error = Error;
+    fn try_from(other: Rc<String>) -> Result<Self> {
+        Self::try_from(other.as_str())
+    }
+}
+
+impl TryFrom<&Rc<String>> for StackName {
+    type Error = Error;
+    fn try_from(other: &Rc<String>) -> Result<Self> {
+        Self::try_from(other.as_str())
+    }
+}
+
+impl AsRef<str> for StackName {
+    fn as_ref(&self) -> &str {
+        self.0.as_str()
+    }
+}
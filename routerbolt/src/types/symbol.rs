@@ -0,0 +1,177 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::Shared;
+
+/// A shared string. Names and terms repeat a great deal in a program, so
+/// while a program is compiled, each distinct string is stored once, and a
+/// `Symbol` is just a pointer to it. That makes symbols cheap to clone, and to
+/// compare, since equal strings compiled together have the same pointer.
+/// Outside a compilation, each symbol has its own copy of its text.
+///
+/// Symbols are ordered by their text, so sorting them is deterministic.
+#[derive(Clone)]
+pub struct Symbol(Shared<str>);
+
+thread_local! {
+    // The strings interned by the compilation running on this thread, if
+    // any. See `Symbol::interning`.
+    static INTERNER: RefCell<Option<HashSet<Shared<str>>>> = const { RefCell::new(None) };
+}
+
+impl Symbol {
+    pub fn new(text: &str) -> Symbol {
+        INTERNER.with(|interner| match interner.borrow_mut().as_mut() {
+            Some(interner) => match interner.get(text) {
+                Some(interned) => Symbol(interned.clone()),
+                None => {
+                    let interned: Shared<str> = Shared::from(text);
+                    interner.insert(interned.clone());
+                    Symbol(interned)
+                }
+            },
+            None => Symbol(Shared::from(text)),
+        })
+    }
+
+    /// Runs `f`, interning the symbols it creates on this thread in a table
+    /// that's dropped when it returns, so it's only as large as the program
+    /// being compiled. Within another call, uses that one's table.
+    pub fn interning<T>(f: impl FnOnce() -> T) -> T {
+        let outer = INTERNER.with(|interner| interner.borrow().is_some());
+        if outer {
+            return f();
+        }
+
+        // Drops the table even if `f` panics.
+        struct Reset;
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                INTERNER.with(|interner| interner.borrow_mut().take());
+            }
+        }
+
+        INTERNER.with(|interner| *interner.borrow_mut() = Some(HashSet::default()));
+        let _reset = Reset;
+        f()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Symbol) -> bool {
+        Shared::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+impl Eq for Symbol {}
+
+impl std::hash::Hash for Symbol {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Symbol) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Symbol {
+    fn cmp(&self, other: &Symbol) -> std::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl std::ops::Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(other: &str) -> Symbol {
+        Symbol::new(other)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(other: String) -> Symbol {
+        Symbol::new(&other)
+    }
+}
+
+impl From<&String> for Symbol {
+    fn from(other: &String) -> Symbol {
+        Symbol::new(other)
+    }
+}
+
+impl From<Rc<String>> for Symbol {
+    fn from(other: Rc<String>) -> Symbol {
+        Symbol::new(&other)
+    }
+}
+
+impl From<&Rc<String>> for Symbol {
+    fn from(other: &Rc<String>) -> Symbol {
+        Symbol::new(other)
+    }
+}
+
+impl From<&Symbol> for Symbol {
+    fn from(other: &Symbol) -> Symbol {
+        other.clone()
+    }
+}
+
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Symbol, D::Error> {
+        Ok(Symbol::new(&String::deserialize(deserializer)?))
+    }
+}
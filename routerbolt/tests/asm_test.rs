@@ -1,3 +1,4 @@
+use std::convert::TryFrom;
 use std::rc::Rc;
 
 use routerbolt::*;
@@ -103,6 +104,48 @@ fn test_stack_peek_poke_cell() {
     test_stack_peek_poke_fixture(true);
 }
 
+fn test_stack_peek_poke_literal_forms_fixture(cell: bool) {
+    let a = Rc::new(String::from("a"));
+    let b = Rc::new(String::from("b"));
+
+    let text = "set MF_acc 7
+                push
+                set MF_acc 8
+                push
+                set MF_acc 9
+                push
+                peek 0x2
+                set a MF_acc
+                set MF_acc 5
+                poke 0b1
+                peek 1
+                set b MF_acc
+         ";
+    let output = test_compile(text, use_cell(cell, 64));
+
+    // Literal depths use the fast path, just like decimal ones.
+    let decimal = test_compile(
+        &text.replace("0x2", "2").replace("0b1", "1"),
+        use_cell(cell, 64),
+    );
+    assert_eq!(output, decimal);
+
+    let mut emu = Emulator::new(emu_cell(cell), &output.join("\n")).unwrap();
+    assert!(emu.run(200).len() < 190);
+    assert_eq!(emu.get_var(&a), Some(7));
+    assert_eq!(emu.get_var(&b), Some(5));
+}
+
+#[test]
+fn test_stack_peek_poke_literal_forms_stack() {
+    test_stack_peek_poke_literal_forms_fixture(false);
+}
+
+#[test]
+fn test_stack_peek_poke_literal_forms_cell() {
+    test_stack_peek_poke_literal_forms_fixture(true);
+}
+
 fn test_fibonacci_fixture(cell: bool) {
     let fibs: Vec<_> = (0..10)
         .map(|j| format!("set arg {}\ncallproc fibonacci\nset fib{} result\n", j, j))
@@ -445,3 +488,311 @@ fn direct_fibonacci_variable_test_stack() {
 fn direct_fibonacci_variable_test_cell() {
     direct_fibonacci_variable_test_fixture(true);
 }
+
+#[test]
+fn test_label_name_validation() {
+    for name in &["5", "end", "jump", "a-b", "x@y"] {
+        let text = format!("{}:\nset a 1", name);
+        let err = parser::parse(&text).unwrap_err();
+        assert!(format!("{:?}", err).contains("Line 1"), "{}", name);
+    }
+
+    assert!(parser::parse("top:\njump top always").is_ok());
+    assert!(parser::parse("jump 5 always").is_err());
+}
+
+#[test]
+fn test_keywords_are_reserved() {
+    let words = parser::STATEMENTS
+        .iter()
+        .chain(parser::UNIT_CONTROL.iter().map(|(name, _)| name))
+        .chain(INSTRUCTIONS.iter().map(|(name, _, _)| name));
+    for word in words {
+        assert!(parser::is_keyword(word), "{}", word);
+        assert!(FunctionName::try_from(*word).is_err(), "{}", word);
+        assert!(LabelName::try_from(*word).is_err(), "{}", word);
+    }
+
+    // Each statement is the parser's to handle, rather than passed along to
+    // Mindustry as an unknown instruction.
+    for word in parser::STATEMENTS {
+        if let Err(err) = parser::parse(word) {
+            assert!(
+                !err.to_string().contains("unknown Mindustry instruction"),
+                "{}",
+                err
+            );
+        }
+    }
+}
+
+fn test_function_local_labels_fixture(cell: bool) {
+    let text = "call count_up 3 -> a
+                call count_down 5 -> b
+                op add c a b
+                jump done always
+                loop_top:
+                set c 99
+                done:
+                end
+
+                fn count_up *n -> r {
+                  set r 0
+                  loop_top:
+                  op add r r 1
+                  jump loop_top lessThan r *n
+                  return r
+                }
+
+                fn count_down *n -> r {
+                  set r 0
+                  loop_top:
+                  op sub *n *n 1
+                  op add r r 2
+                  jump loop_top greaterThan *n 0
+                  return r
+                }";
+
+    let output = test_compile(text, use_cell(cell, 64));
+    let mut emu = Emulator::new(emu_cell(cell), &output.join("\n")).unwrap();
+    assert!(emu.run(2000).len() < 1990);
+    assert_eq!(emu.get_var(&Rc::new("a".to_string())), Some(3));
+    assert_eq!(emu.get_var(&Rc::new("b".to_string())), Some(10));
+    assert_eq!(emu.get_var(&Rc::new("c".to_string())), Some(13));
+}
+
+#[test]
+fn test_function_local_labels_stack() {
+    test_function_local_labels_fixture(false);
+}
+
+#[test]
+fn test_function_local_labels_cell() {
+    test_function_local_labels_fixture(true);
+}
+
+#[test]
+fn test_function_local_label_scope() {
+    let stack = "stack_config size 8\n";
+
+    // A function's labels are not visible outside it.
+    let text = format!("{}jump inner always\nfn f {{\ninner:\nreturn\n}}", stack);
+    let ir = parser::parse(&text).unwrap();
+    assert!(generate(&ir).is_err());
+
+    // But global labels are visible inside functions.
+    let text = format!("{}outer:\nfn f {{\njump outer always\nreturn\n}}", stack);
+    let ir = parser::parse(&text).unwrap();
+    assert!(generate(&ir).is_ok());
+
+    // Labels still may not be defined twice in the same function.
+    let text = format!("{}fn f {{\nx:\nx:\nreturn\n}}", stack);
+    assert!(parser::parse(&text).is_err());
+}
+
+fn test_named_stack_fixture(cell: bool) {
+    let a = Rc::new(String::from("a"));
+    let b = Rc::new(String::from("b"));
+    let c = Rc::new(String::from("c"));
+
+    // Whichever backend the default stack uses, the named one uses the other.
+    let data = if cell {
+        "stack_config size 16 as data"
+    } else {
+        "stack_config cell bank1 as data"
+    };
+
+    let text = format!(
+        "{}
+         set MF_acc 7
+         push data
+         set MF_acc 8
+         push
+         set MF_acc 9
+         push data
+         set MF_acc 5
+         poke 1 data
+         peek 0 data
+         set a MF_acc
+         pop
+         set b MF_acc
+         pop data
+         pop data
+         set c MF_acc
+        ",
+        data
+    );
+    let output = test_compile(&text, use_cell(cell, 16));
+    let mut emu = Emulator::new(Some(Cell::default()), &output.join("\n")).unwrap();
+
+    assert!(emu.run(200).len() < 190);
+    assert_eq!(emu.get_var(&a), Some(9));
+    assert_eq!(emu.get_var(&b), Some(8));
+    assert_eq!(emu.get_var(&c), Some(5));
+}
+
+#[test]
+fn test_named_stack_stack() {
+    test_named_stack_fixture(false);
+}
+
+#[test]
+fn test_named_stack_cell() {
+    test_named_stack_fixture(true);
+}
+
+fn test_named_stack_with_calls_fixture(cell: bool) {
+    // A procedure spills values to the data stack while the call stack holds
+    // its return address.
+    let text = "stack_config size 8 as data
+                set v 3
+                callproc spill
+                set v 4
+                callproc spill
+                pop data
+                set a MF_acc
+                pop data
+                set b MF_acc
+                end
+
+                spill:
+                set MF_acc v
+                push data
+                ret";
+
+    let output = test_compile(text, use_cell(cell, 8));
+    let mut emu = Emulator::new(emu_cell(cell), &output.join("\n")).unwrap();
+    step_until_equal(&mut emu, Some(4), Some(3), None, 500);
+}
+
+#[test]
+fn test_named_stack_with_calls_stack() {
+    test_named_stack_with_calls_fixture(false);
+}
+
+#[test]
+fn test_named_stack_with_calls_cell() {
+    test_named_stack_with_calls_fixture(true);
+}
+
+#[test]
+fn test_named_stack_errors() {
+    // Undeclared stack.
+    assert!(parser::parse("stack_config size 8\npush data").is_err());
+
+    // A named stack does not provide a default stack.
+    assert!(parser::parse("stack_config size 8 as data\npush").is_err());
+    assert!(parser::parse("stack_config size 8 as data\npush data").is_ok());
+
+    // Duplicate and empty named stacks.
+    assert!(parser::parse("stack_config size 8 as data\nstack_config size 4 as data").is_err());
+    assert!(parser::parse("stack_config size 0 as data").is_err());
+    assert!(parser::parse("stack_config size 8 as end").is_err());
+}
+
+#[test]
+fn test_external_stack_offset() {
+    let a = Rc::new(String::from("a"));
+    let b = Rc::new(String::from("b"));
+
+    let text = "stack_config cell bank1 offset 100 len 50
+                write 42 bank1 0
+                write 43 bank1 99
+                set MF_acc 7
+                push
+                set MF_acc 8
+                push
+                peek 1
+                set a MF_acc
+                pop
+                set b MF_acc
+                call f 5 -> c
+                end
+
+                fn f *x -> y {
+                  op add y *x 1
+                  return y
+                }";
+
+    let ir = parser::parse(text).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let mut emu = Emulator::new(emu_cell(true), &output.join("\n")).unwrap();
+
+    assert!(emu.run(200).len() < 190);
+    assert_eq!(emu.get_var(&a), Some(7));
+    assert_eq!(emu.get_var(&b), Some(8));
+    assert_eq!(emu.get_var(&Rc::new("c".to_string())), Some(6));
+    assert_eq!(emu.get_mem(0), Some(42));
+    assert_eq!(emu.get_mem(99), Some(43));
+
+    // The function call reused the same stack entries as the pushes.
+    assert!(emu.get_mem(100).is_some());
+    assert!(emu.get_mem(101).is_some());
+}
+
+#[test]
+fn test_external_stack_offset_errors() {
+    assert!(parser::parse("stack_config cell bank1 offset 10 len 20").is_ok());
+    assert!(parser::parse("stack_config cell bank1 len 20 offset 10 as data").is_ok());
+    assert!(parser::parse("stack_config cell bank1 len 0").is_err());
+    assert!(parser::parse("stack_config cell bank1 offset -1").is_err());
+    assert!(parser::parse("stack_config size 8 offset 10").is_err());
+    assert!(parser::parse("stack_config cell bank1 offset").is_err());
+
+    // Stacks sharing a cell must not overlap.
+    let disjoint = "stack_config cell bank1 len 100\nstack_config cell bank1 offset 100 as data";
+    assert!(parser::parse(disjoint).is_ok());
+    let overlap = "stack_config cell bank1 len 101\nstack_config cell bank1 offset 100 as data";
+    assert!(parser::parse(overlap).is_err());
+    let unbounded = "stack_config cell bank1\nstack_config cell bank1 offset 100 as data";
+    assert!(parser::parse(unbounded).is_err());
+    let other_cell = "stack_config cell bank1\nstack_config cell bank2 as data";
+    assert!(parser::parse(other_cell).is_ok());
+}
+
+#[test]
+fn test_compile_options_stack_config() {
+    let text = "stack_config size 4
+                call f
+                end
+
+                fn f {
+                  return
+                }";
+
+    let parse = |stack_config: &str| {
+        let mut options = parser::CompileOptions::default();
+        options.set_stack_config(stack_config).unwrap();
+        parser::parse_with_options(text, &options).unwrap()
+    };
+
+    // Replaces the configuration in the source.
+    let ir = parser::parse(text).unwrap();
+    assert!(matches!(ir.stack_config, StackConfig::Internal(4)));
+    let ir = parse("size 16");
+    assert!(matches!(ir.stack_config, StackConfig::Internal(16)));
+    let ir = parse("size auto");
+    assert!(matches!(ir.stack_config, StackConfig::Internal(1)));
+    let ir = parse("cell bank2 offset 8 len 4");
+    match &ir.stack_config {
+        StackConfig::External(ext) => {
+            assert_eq!(ext.cell_name.as_str(), "bank2");
+            assert_eq!(ext.offset, 8);
+            assert_eq!(ext.len, Some(4));
+        }
+        config => panic!("{:?}", config),
+    }
+
+    // Supplies one when the source has none.
+    let options = parser::CompileOptions {
+        stack_config: Some(StackConfig::Internal(8)),
+        ..Default::default()
+    };
+    let ir = parser::parse_with_options("push\npop", &options).unwrap();
+    assert!(matches!(ir.stack_config, StackConfig::Internal(8)));
+
+    let mut options = parser::CompileOptions::default();
+    assert!(options.set_stack_config("size 8 as data").is_err());
+    assert!(options.set_stack_config("cell").is_err());
+    assert!(options.set_stack_config("size x").is_err());
+}
@@ -0,0 +1,52 @@
+use routerbolt::*;
+
+const TEXT: &str = "//! assert a == 21 after 5000 steps
+                    //! assert bank1[2] == 21 after 5000 steps
+                    //! assert b == 5 after 20 steps
+                    //! assert a > 100 after 5000 steps
+                    //! assert c === null after 1 step
+                    set a 0
+                    set b 1
+                    top:
+                    op add c a b
+                    set a b
+                    set b c
+                    jump top lessThan b 30
+                    write a bank1 2
+                    end";
+
+#[test]
+fn test_run_assertions() {
+    let mut options = parser::CompileOptions::default();
+    options.set_stack_config("cell cell1").unwrap();
+    let results = run_assertions(TEXT, &options).unwrap();
+    let passed: Vec<_> = results.iter().map(|result| result.passed).collect();
+    assert_eq!(passed, vec![true, true, true, false, true]);
+    assert_eq!(results[3].actual, Value::Number(21.0));
+    assert_eq!(
+        results[3].to_string(),
+        "line 3: a > 100 after 5000 steps ... FAILED: a is 21"
+    );
+    assert_eq!(
+        results[0].to_string(),
+        "line 0: a == 21 after 5000 steps ... ok"
+    );
+}
+
+#[test]
+fn test_assertion_errors() {
+    let error = |text: &str| format!("{:#}", Assertion::parse_all(text).unwrap_err());
+
+    assert!(
+        error("//! assert a == 1").contains("Line 0: //! assert a == 1: assertions take the form")
+    );
+    assert!(error("set a 1\n//! assert a =~ 1 after 5 steps").contains("Line 1: "));
+    assert!(error("//! assert a =~ 1 after 5 steps").contains("unknown comparison =~"));
+    assert!(error("//! assert a == x after 5 steps").contains("x is not a number"));
+    assert!(error("//! assert a == 1 after many steps").contains("steps must be an integer"));
+
+    // Other comments are left alone.
+    assert!(Assertion::parse_all("//! a note\n// assert a == 1")
+        .unwrap()
+        .is_empty());
+}
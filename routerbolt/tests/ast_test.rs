@@ -0,0 +1,148 @@
+use std::convert::TryFrom;
+
+use routerbolt::*;
+
+const TEXT: &str = "stack_config size 8
+call f 2 -> a
+end
+
+// Comments and blank lines aren't statements.
+fn f *x -> r {
+  if lessThan *x 3 {
+    set r 1
+  } else {
+    set r 2
+  }
+  do {
+    op sub *x *x 1
+  } while greaterThan *x 0
+  asm {
+    set b 1
+  }
+done:
+  return r
+}";
+
+#[test]
+fn test_ast_structure() {
+    let ast = Ast::parse(TEXT).unwrap();
+    assert_eq!(ast.statements.len(), 4);
+    assert!(matches!(
+        ast.statements[0].kind,
+        StatementKind::Directive { .. }
+    ));
+    assert_eq!(ast.statements[3].line.line, 5);
+
+    let (args, returns, body) = match &ast.statements[3].kind {
+        StatementKind::Function {
+            name,
+            inline: false,
+            args,
+            returns,
+            body,
+        } => {
+            assert_eq!(*name, FunctionName::try_from("f").unwrap());
+            (args, returns, body)
+        }
+        kind => panic!("{:?}", kind),
+    };
+    assert_eq!(*args, vec![Expression::Term(Term::try_from("*x").unwrap())]);
+    assert_eq!(
+        *returns,
+        vec![Expression::Term(Term::try_from("r").unwrap())]
+    );
+    assert_eq!(body.end.as_ref().unwrap().line, 19);
+
+    let kinds: Vec<_> = body.statements.iter().map(|s| &s.kind).collect();
+    match kinds[0] {
+        StatementKind::If {
+            condition,
+            then,
+            otherwise: Some(otherwise),
+        } => {
+            assert_eq!(condition.len(), 3);
+            assert_eq!(then.statements.len(), 1);
+            assert_eq!(otherwise.statements.len(), 1);
+        }
+        kind => panic!("{:?}", kind),
+    }
+    match kinds[1] {
+        StatementKind::DoWhile { body, condition } => {
+            assert_eq!(body.statements.len(), 1);
+            assert_eq!(
+                condition[0],
+                Expression::Term(Term::try_from("greaterThan").unwrap())
+            );
+        }
+        kind => panic!("{:?}", kind),
+    }
+    assert!(matches!(kinds[2], StatementKind::Asm { lines, .. } if lines.len() == 1));
+    assert_eq!(*kinds[3], StatementKind::Label("done".to_string()));
+    assert!(matches!(kinds[4], StatementKind::Command { name, .. } if name == "return"));
+}
+
+#[test]
+fn test_ast_lines() {
+    // Lowering visits every line but blanks and comments, in order.
+    let ast = Ast::parse(TEXT).unwrap();
+    let lines: Vec<usize> = ast.lines().iter().map(|line| line.line).collect();
+    let expected: Vec<usize> = TEXT
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim().starts_with("//"))
+        .map(|(j, _)| j)
+        .collect();
+    assert_eq!(lines, expected);
+}
+
+#[test]
+fn test_ast_errors() {
+    for text in &[
+        "set a 1\n}",
+        "while always {\n} else {\n}",
+        "asm {\nset a 1",
+        "fn {\n}",
+        "frobnicate {\n}",
+    ] {
+        assert!(Ast::parse(text).is_err(), "{}", text);
+        assert!(parser::parse(text).is_err(), "{}", text);
+    }
+
+    // A block may run to the end of the program.
+    let ast = Ast::parse("loop {\nset a 1").unwrap();
+    assert!(matches!(&ast.statements[0].kind, StatementKind::Loop { body } if body.end.is_none()));
+}
+
+#[test]
+fn test_parse_lines() {
+    let ast = Ast::parse(TEXT).unwrap();
+    assert_eq!(Ast::parse_lines(TEXT.lines().enumerate()).unwrap(), ast);
+    assert_eq!(Ast::read(std::io::Cursor::new(TEXT)).unwrap(), ast);
+
+    // Owned lines, as from a generator, work too.
+    let owned = TEXT.lines().map(String::from).enumerate();
+    assert_eq!(Ast::parse_lines(owned).unwrap(), ast);
+
+    // Skipped lines are blank.
+    let ast = Ast::parse_lines(vec![(0, "set a 1"), (3, "set b 2")]).unwrap();
+    assert_eq!(ast.source, ["set a 1", "", "", "set b 2"]);
+    assert_eq!(ast.statements[1].line.line, 3);
+
+    let err = Ast::parse_lines(vec![(2, "set a 1"), (1, "set b 2")]).unwrap_err();
+    assert!(
+        err.to_string().contains("Line 2 is out of order"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_parse_lines_with_options() {
+    let options = parser::CompileOptions::with_profile(parser::Profile::Release);
+    let ir = parser::parse_lines_with_options(TEXT.lines().enumerate(), &options).unwrap();
+    let expected = parser::parse_with_options(TEXT, &options).unwrap();
+    assert_eq!(ir.generate().unwrap(), expected.generate().unwrap());
+
+    let err = parser::parse_lines_with_options(vec![(0, "frob {")], &options).unwrap_err();
+    assert!(err.to_string().contains("Line 1"), "{}", err);
+}
@@ -0,0 +1,48 @@
+use routerbolt::*;
+use test_util::*;
+
+#[test]
+fn test_backends_agree() {
+    let text = "call fib 8 -> f
+                print f
+                printflush message1
+                end
+
+                fn fib *n -> r {
+                  set r *n
+                  if greaterThan *n 1 {
+                    op sub n *n 1
+                    call fib n -> a
+                    set *n a
+                    op sub n n 1
+                    call fib n -> b
+                    op add r *n b
+                  }
+                  return r
+                }";
+    assert_backends_agree(text, &["f", "a", "b"]);
+}
+
+#[test]
+fn test_backends_differ() {
+    // The external stack keeps return addresses in `bank1`, so reading it
+    // shows which backend is in use.
+    let text = "call f
+                read x bank1 0
+                end
+
+                fn f {
+                  set y 1
+                  return
+                }";
+    let options = parser::CompileOptions::default();
+    let err = compare_backends(text, &options, 8, &["x", "y"], 1000, |_| ()).unwrap_err();
+    assert!(
+        err.to_string()
+            .ends_with("only with an external stack, x became 7 at step 11"),
+        "{}",
+        err
+    );
+
+    assert!(compare_backends(text, &options, 8, &["y"], 1000, |_| ()).is_ok());
+}
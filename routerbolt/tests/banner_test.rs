@@ -0,0 +1,55 @@
+use routerbolt::*;
+use test_util::*;
+
+fn compile(text: &str, stack_config: StackConfig, banner: &str) -> Vec<String> {
+    let options = parser::CompileOptions {
+        stack_config: Some(stack_config),
+        banner: Some(banner.to_string()),
+        ..Default::default()
+    };
+    let ir = parser::parse_with_options(text, &options).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    output
+}
+
+#[test]
+fn test_banner() {
+    let output = compile("set a 1", use_cell(false, 0), "myprog v3");
+    assert_eq!(output, vec!["set MF_build \"myprog v3\"", "set a 1"]);
+
+    // The stack setup is skipped to just past the banner.
+    let text = "call f -> a\nend\nfn f -> r {\n  set r 1\n  return r\n}";
+    let output = compile(text, use_cell(true, 0), "myprog v3");
+    assert_eq!(output[0], "set MF_build \"myprog v3\"");
+    assert_eq!(output[1], "jump 4 equal MF_init 1");
+
+    let mut emu = Emulator::new(emu_cell(true), &output.join("\n")).unwrap();
+    emu.run(100);
+    assert_eq!(emu.get_var("a"), Some(1));
+
+    let options = parser::CompileOptions {
+        banner: Some("two \"words\"".to_string()),
+        ..Default::default()
+    };
+    assert!(parser::parse_with_options("set a 1", &options).is_err());
+}
+
+#[test]
+fn test_expand_banner() {
+    let banner = parser::expand_banner("{name}-v3 {date} {hash}", "miner", "set a 1");
+    let tok: Vec<_> = banner.split(' ').collect();
+    assert_eq!(tok[0], "miner-v3");
+    assert_eq!(tok[1].len(), "2024-06-01".len());
+    assert!(tok[1].starts_with("20"));
+    assert_eq!(tok[2].len(), 8);
+
+    // The hash changes with the source.
+    assert_eq!(
+        banner,
+        parser::expand_banner("{name}-v3 {date} {hash}", "miner", "set a 1")
+    );
+    assert_ne!(
+        parser::expand_banner("{hash}", "miner", "set a 1"),
+        parser::expand_banner("{hash}", "miner", "set a 2")
+    );
+}
@@ -0,0 +1,69 @@
+use routerbolt::*;
+
+fn run(text: &str, length: BenchmarkLength) -> Benchmark {
+    let ir = parser::parse(text).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let cells = ir.stack_cells().into_iter().map(Cell::new);
+    let mut emu = Emulator::new(cells, &output.join("\n")).unwrap();
+    emu.set_source_map(&ir.source_map());
+    Benchmark::run(&mut emu, length)
+}
+
+#[test]
+fn test_benchmark_laps() {
+    // Every tenth lap takes a different branch.
+    let text = "set total 0
+                set i 0
+                loop {
+                  benchmark_lap
+                  op add i i 1
+                  if lessThan i 10 {
+                    op add total total i
+                  } else {
+                    set i 0
+                  }
+                }";
+    let benchmark = run(text, BenchmarkLength::Steps(1000));
+    assert_eq!(benchmark.steps, 1000);
+    assert_eq!(benchmark.laps[..3], [2, 7, 12]);
+    assert!(benchmark.lap_steps().iter().all(|&steps| steps == 5));
+    assert_eq!(benchmark.per_lap(), Some(5.0));
+    assert!(benchmark
+        .to_string()
+        .ends_with("Per lap: 5.0 instructions on average, 5 fewest, 5 most\n"));
+
+    // A lap at the end of the program is counted as it starts over.
+    let text = "set a 0
+                op add a a 1
+                op add a a 1
+                benchmark_lap";
+    let benchmark = run(text, BenchmarkLength::Steps(30));
+    assert_eq!(benchmark.laps, vec![0, 3, 6, 9, 12, 15, 18, 21, 24, 27]);
+    assert_eq!(benchmark.per_lap(), Some(3.0));
+
+    let faster = Benchmark {
+        laps: vec![0, 2, 4],
+        ..Benchmark::default()
+    };
+    assert_eq!(
+        benchmark.comparison(&faster).unwrap(),
+        "3.0 -> 2.0 instructions per lap (-33.3%)"
+    );
+}
+
+#[test]
+fn test_benchmark_without_laps() {
+    let text = "set a 1
+                wait 1
+                stop";
+    let benchmark = run(text, BenchmarkLength::Ticks(1000));
+    assert_eq!(benchmark.per_lap(), None);
+    assert_eq!(
+        benchmark.to_string(),
+        format!(
+            "Ran {} instructions in {} ticks, reaching 0 laps\nToo few laps to measure; mark the end of each iteration with benchmark_lap\n",
+            benchmark.steps, benchmark.ticks
+        )
+    );
+    assert!(parser::parse("benchmark_lap now").is_err());
+}
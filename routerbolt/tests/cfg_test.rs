@@ -0,0 +1,115 @@
+use std::convert::TryFrom;
+
+use routerbolt::*;
+use test_util::*;
+
+const TEXT: &str = "set i 0
+                    while lessThan i 3 {
+                      op add i i 1
+                    }
+                    call f i -> a
+                    end
+
+                    fn f *x -> r {
+                      if lessThan *x 3 {
+                        set y 1
+                      } else {
+                        set y 2
+                      }
+                      loop {
+                        break
+                      }
+                      return 2
+                    }
+
+                    fn unused {
+                      return
+                    }";
+
+fn test_cfg_fixture(cell: bool) {
+    let options = parser::CompileOptions {
+        stack_config: Some(use_cell(cell, 32)),
+        ..Default::default()
+    };
+    let ir = parser::parse_with_options(TEXT, &options).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let cfg = ir.cfg().unwrap();
+
+    // The blocks cover the ops in order.
+    assert_eq!(cfg.entry(), Some(0));
+    assert_eq!(cfg.blocks[0].ops.start, 0);
+    assert_eq!(cfg.blocks.last().unwrap().ops.end, ir.ops().len());
+    for pair in cfg.blocks.windows(2) {
+        assert_eq!(pair[0].ops.end, pair[1].ops.start);
+        assert_eq!(pair[0].end, pair[1].start);
+    }
+
+    // Every jump edge matches the jump that ends the block.
+    for block in cfg.blocks.iter() {
+        for edge in block.edges.iter().filter(|e| e.kind == EdgeKind::Jump) {
+            let last: usize = block.end.into();
+            let jump = format!("jump {} ", cfg.blocks[edge.target].start);
+            assert!(output[last - 1].starts_with(&jump), "{}", output[last - 1]);
+        }
+    }
+
+    // The while loop jumps back to its body.
+    assert!(cfg.blocks.iter().enumerate().any(|(id, block)| block
+        .edges
+        .iter()
+        .any(|e| e.kind == EdgeKind::Jump && e.target < id)));
+
+    let f = cfg.functions[&FunctionName::try_from("f").unwrap()];
+    let calls: Vec<_> = (0..cfg.blocks.len())
+        .filter(|id| {
+            cfg.blocks[*id].edges.contains(&Edge {
+                target: f,
+                kind: EdgeKind::Call,
+            })
+        })
+        .collect();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(cfg.predecessors(f), calls);
+
+    let exits = |exit| {
+        cfg.blocks
+            .iter()
+            .filter(|block| block.exit == Some(exit))
+            .count()
+    };
+    assert_eq!(exits(Exit::Return), 2);
+    assert_eq!(exits(Exit::End), 1);
+    assert_eq!(exits(Exit::Unknown), 0);
+
+    // The function that is never called can't be reached, nor can the end of
+    // the loop that always breaks.
+    let unused = cfg.functions[&FunctionName::try_from("unused").unwrap()];
+    let unreachable: Vec<_> = (0..cfg.blocks.len())
+        .filter(|id| !cfg.reachable()[*id])
+        .collect();
+    assert_eq!(unreachable.len(), 2);
+    assert_eq!(unreachable[1], unused);
+    let loop_end = &cfg.blocks[unreachable[0]];
+    assert!(output[*loop_end.start.as_ref()].starts_with("jump "));
+}
+
+#[test]
+fn test_cfg_stack() {
+    test_cfg_fixture(false);
+}
+
+#[test]
+fn test_cfg_cell() {
+    test_cfg_fixture(true);
+}
+
+#[test]
+fn test_cfg_asm() {
+    let ir = parser::parse("set a 1\nasm {\nstop\n}\nset a 2\nasm {\nset @counter 0\n}").unwrap();
+    let cfg = ir.cfg().unwrap();
+    let exits: Vec<_> = cfg.blocks.iter().map(|block| block.exit).collect();
+    assert_eq!(exits, vec![Some(Exit::Stop), Some(Exit::Unknown)]);
+    assert!(cfg.blocks.iter().all(|block| block.edges.is_empty()));
+    assert_eq!(cfg.reachable(), vec![true, false]);
+    assert_eq!(cfg.block_at(2.into()), Some(1));
+}
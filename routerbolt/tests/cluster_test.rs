@@ -0,0 +1,64 @@
+use routerbolt::*;
+
+#[test]
+fn test_producer_consumer() {
+    let mut cluster = Cluster::new(vec![Cell::memory_cell("cell1")]).unwrap();
+
+    // The producer counts up to 5, one a tick, and the consumer keeps a sum of
+    // each value it sees change.
+    let producer = cluster
+        .add_processor(
+            "jump 3 greaterThanEq i 5
+             op add i i 1
+             write i cell1 0
+             wait 0",
+        )
+        .unwrap();
+    let consumer = cluster
+        .add_processor(
+            "read v cell1 0
+             jump 0 equal v last
+             op add sum sum v
+             set last v",
+        )
+        .unwrap();
+
+    let events = cluster.run_ticks(100);
+    assert_eq!(cluster.ticks(), 100);
+    assert_eq!(cluster.get_cell_value("cell1", 0), Some(5.0));
+    assert_eq!(cluster.processor(producer).get_var("i"), Some(5));
+    assert_eq!(cluster.processor(consumer).get_var("sum"), Some(15));
+
+    // Two instructions a tick each.
+    let executed = events[consumer]
+        .iter()
+        .filter(|event| matches!(event, Event::Executed { .. }));
+    assert_eq!(executed.count(), 200);
+    assert_eq!(cluster.processor(consumer).ticks(), 100);
+}
+
+#[test]
+fn test_cluster_wait() {
+    let mut cluster = Cluster::new(vec![Cell::memory_cell("cell1")]).unwrap();
+    let waiter = cluster
+        .add_processor(
+            "wait 1
+             write 1 cell1 0
+             stop",
+        )
+        .unwrap();
+    let counter = cluster
+        .add_processor("op add n n 1\nwrite n cell1 1")
+        .unwrap();
+
+    // The waiting processor sits out a second, while the other runs.
+    cluster.run_ticks(60);
+    assert_eq!(cluster.get_cell_value("cell1", 0), None);
+    assert_eq!(cluster.get_cell_value("cell1", 1), Some(60.0));
+    cluster.run_ticks(1);
+    assert_eq!(cluster.get_cell_value("cell1", 0), Some(1.0));
+    assert_eq!(cluster.processor(waiter).ticks(), 61);
+    assert_eq!(cluster.processor(counter).get_var("n"), Some(61));
+
+    assert!(Cluster::new(vec![Cell::default(), Cell::default()]).is_err());
+}
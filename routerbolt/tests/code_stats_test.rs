@@ -0,0 +1,75 @@
+use std::convert::TryFrom;
+
+use routerbolt::*;
+
+#[test]
+fn test_code_stats() {
+    let text = "stack_config size 8
+                call f 3 -> a
+                end
+
+                fn f *n -> r {
+                  set r 0
+                  while greaterThan *n 0 {
+                    op sub *n *n 1
+                    op add r r 2
+                  }
+                  return r
+                }
+
+                fn g {
+                  loop {
+                    print 1
+                  }
+                }
+            ";
+
+    let ir = parser::parse(text).unwrap();
+    let (output, _, stats) = ir.generate_with_stats().unwrap();
+    assert_eq!(stats.total, output.len());
+    assert_eq!(
+        stats
+            .functions
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>(),
+        vec![
+            FunctionName::try_from("f").unwrap(),
+            FunctionName::try_from("g").unwrap(),
+        ]
+    );
+    assert_eq!(stats.functions[1].1, 2);
+    assert_eq!(
+        stats
+            .loops
+            .iter()
+            .map(|(line, _)| *line)
+            .collect::<Vec<_>>(),
+        vec![6, 14]
+    );
+    assert_eq!(stats.loops[1].1, 2);
+    assert_eq!(stats.stack_tables, vec![("stack".to_string(), 24)]);
+
+    let in_functions: usize = stats.functions.iter().map(|(_, size)| size).sum();
+    assert_eq!(stats.top_level + in_functions + 24, stats.total);
+
+    // Largest first, without loops, which overlap the functions.
+    let parts = stats.parts();
+    assert_eq!(parts[0], ("jump tables for stack".to_string(), 24));
+    assert_eq!(
+        parts.iter().map(|(_, size)| size).sum::<usize>(),
+        stats.total
+    );
+
+    // Against the default budget of a full processor.
+    assert_eq!(stats.limit, Some(MAX_INSTRUCTIONS));
+    assert_eq!(stats.headroom(), MAX_INSTRUCTIONS - stats.total);
+    let table = stats.to_string();
+    assert!(table.starts_with("part                    size  budget\ntop-level code   "));
+    assert!(table.contains("\njump tables for stack     24    2.4%\n"));
+    assert!(table.contains(&format!(
+        "\nheadroom               {:>5}",
+        MAX_INSTRUCTIONS - stats.total
+    )));
+    assert!(table.contains("\nLoops, also counted above:\nloop at line 7            16    1.6%\n"));
+}
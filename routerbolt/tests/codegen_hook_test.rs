@@ -0,0 +1,73 @@
+use routerbolt::*;
+
+#[test]
+fn test_codegen_hook_ranges() {
+    let text = "stack_config size 4\nset a 1\nprint a\ncall f\nend\nfn f {\n  set b 2\n  ret\n}";
+    let ir = parser::parse(text).unwrap();
+    let mut seen = Vec::default();
+    let mut hook = |generated: &mut GeneratedOp| -> anyhow::Result<()> {
+        seen.push((generated.index, generated.line, generated.range()));
+        Ok(())
+    };
+    let (output, _, _) = ir.generate_with_hook(&mut hook).unwrap();
+
+    // Every op is seen once, in order, and their code follows on.
+    assert_eq!(seen.len(), ir.ops().len());
+    let mut address = 0;
+    for (j, (index, line, range)) in seen.iter().enumerate() {
+        assert_eq!(*index, j);
+        assert_eq!(*line, ir.op_lines[j]);
+        assert_eq!(range.start, address);
+        address = range.end;
+    }
+    let size: usize = ir
+        .ops()
+        .iter()
+        .map(|op| -> usize { op.code_size(*ir.backend()).into() })
+        .sum();
+    assert_eq!(address, size);
+    assert_eq!(output, ir.generate().unwrap().0);
+    assert!(seen
+        .iter()
+        .any(|(_, line, range)| *line == Some(1) && range.len() == 1));
+}
+
+#[test]
+fn test_codegen_hook_rewrite() {
+    let ir = parser::parse("set a 1\nset b 2\nend").unwrap();
+    let mut hook = |generated: &mut GeneratedOp| -> anyhow::Result<()> {
+        for line in generated.code.iter_mut() {
+            if line == "set a 1" {
+                *line = "op add a a 1".to_string();
+            }
+        }
+        Ok(())
+    };
+    let (output, annotated, _) = ir.generate_with_hook(&mut hook).unwrap();
+    assert!(output.contains(&"op add a a 1".to_string()));
+    assert!(!output.contains(&"set a 1".to_string()));
+    assert!(annotated
+        .iter()
+        .any(|line| line.ends_with("\top add a a 1")));
+
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    emu.run(3);
+    assert_eq!(emu.get_var("a"), Some(1));
+    assert_eq!(emu.get_var("b"), Some(2));
+}
+
+#[test]
+fn test_codegen_hook_error() {
+    let ir = parser::parse("set a 1\nset b 2\nend").unwrap();
+    let mut hook = |generated: &mut GeneratedOp| -> anyhow::Result<()> {
+        anyhow::ensure!(generated.index == 0, "no more");
+        Ok(())
+    };
+    let error = ir.generate_with_hook(&mut hook).unwrap_err();
+    assert!(error.to_string().contains("no more"), "{}", error);
+    assert!(
+        error.to_string().contains("codegen hook at op 1"),
+        "{}",
+        error
+    );
+}
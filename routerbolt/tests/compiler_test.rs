@@ -0,0 +1,157 @@
+use routerbolt::*;
+
+#[test]
+fn test_compiler_options() {
+    let text =
+        "call double 4 -> x\nprint x\nend\nfn double *n -> r {\n  op mul r *n 2\n  return r\n}";
+    let program = Compiler::new()
+        .profile(parser::Profile::Release)
+        .stack_config("cell bank1")
+        .compile(text)
+        .unwrap();
+    assert_eq!(program.stats.total, program.code.len());
+    assert!(program.code.iter().any(|line| line.contains("bank1")));
+
+    let mut emu = Emulator::new(Some(Cell::new("bank1")), &program.code.join("\n")).unwrap();
+    emu.run(100);
+    assert_eq!(emu.get_var("x"), Some(8));
+
+    // The limit is checked when generating.
+    let error = Compiler::new()
+        .stack_config("size 4")
+        .instruction_limit(Some(5))
+        .compile(text)
+        .unwrap_err();
+    assert!(matches!(error, CompileError::SizeOverflow { limit: 5, .. }));
+
+    let error = Compiler::new()
+        .stack_config("frob")
+        .compile(text)
+        .unwrap_err();
+    assert!(error.to_string().contains("stack_config"), "{}", error);
+}
+
+#[test]
+fn test_compiler_defines() {
+    let compiler = Compiler::new()
+        .define("LIMIT", "10")
+        .define("ITEM", "@copper")
+        .define("LIMIT", "20");
+    let text = "// LIMIT stays in comments\nset a LIMIT\nprint \"LIMIT\"\nsensor n core ITEM";
+    assert_eq!(
+        compiler.expand(text).unwrap(),
+        "// LIMIT stays in comments\nset a 20\nprint \"LIMIT\"\nsensor n core @copper"
+    );
+
+    // Only whole words are replaced.
+    assert_eq!(
+        compiler.expand("set LIMITS LIMIT").unwrap(),
+        "set LIMITS 20"
+    );
+
+    // Nor in a comment after a statement, though `//` in a string isn't one.
+    assert_eq!(
+        compiler.expand("set a LIMIT // LIMIT of ITEM").unwrap(),
+        "set a 20 // LIMIT of ITEM"
+    );
+    assert_eq!(
+        compiler.expand("set s \"//\" ITEM // ITEM").unwrap(),
+        "set s \"//\" @copper // ITEM"
+    );
+
+    let program = compiler.compile("set a LIMIT").unwrap();
+    assert_eq!(program.code, vec!["set a 20"]);
+}
+
+#[test]
+fn test_compiler_includes() {
+    let compiler = Compiler::new()
+        .stack_config("size 4")
+        .include_resolver(|name| match name {
+            "math" => {
+                Ok("include util\nfn double *n -> r {\n  op mul r *n 2\n  return r\n}".to_string())
+            }
+            "util" => Ok("fn one -> r {\n  return 1\n}".to_string()),
+            _ => anyhow::bail!("no such file"),
+        });
+    let text = "include math\ninclude math\ncall double 4 -> x\ncall one -> y\nend";
+    let expanded = compiler.expand(text).unwrap();
+    assert!(expanded.starts_with("\n\ncall double 4 -> x\ncall one -> y\nend\n\nfn double"));
+    assert!(expanded.ends_with("fn one -> r {\n  return 1\n}"));
+
+    let program = compiler.compile(text).unwrap();
+    let mut emu = Emulator::new(None, &program.code.join("\n")).unwrap();
+    emu.run(200);
+    assert_eq!(emu.get_var("x"), Some(8));
+    assert_eq!(emu.get_var("y"), Some(1));
+
+    let error = compiler.compile("set a 1\ninclude maths").unwrap_err();
+    assert_eq!(error.line(), Some(1));
+    assert!(error.to_string().contains("no such file"), "{}", error);
+
+    // Without a resolver, there's nothing to include.
+    assert!(Compiler::new().compile("include math").is_err());
+    assert!(parser::parse("include math").is_err());
+}
+
+#[test]
+fn test_compile_to_string() {
+    assert_eq!(
+        compile_to_string("set a 1\nprint a").unwrap(),
+        "set a 1\nprint a"
+    );
+    let error = compile_to_string("set a 1\nfrob").unwrap_err();
+    assert_eq!(error.line(), Some(1));
+}
+
+#[test]
+fn test_compiler_variable_prefix() {
+    let text =
+        "call double 4 -> x\nprint x\nend\nfn double *n -> r {\n  op mul r *n 2\n  return r\n}";
+    let program = Compiler::new()
+        .stack_config("size 4")
+        .variable_prefix("app_")
+        .compile(text)
+        .unwrap();
+    assert!(program
+        .code
+        .iter()
+        .any(|line| line.contains("app_stack_sz")));
+    assert!(!program.code.iter().any(|line| line.contains("MF_")));
+
+    let mut emu = Emulator::new(None, &program.code.join("\n")).unwrap();
+    emu.run(200);
+    assert_eq!(emu.get_var("x"), Some(8));
+
+    // Strings are left alone.
+    let program = Compiler::new()
+        .variable_prefix("app_")
+        .compile("print \"MF_acc\"")
+        .unwrap();
+    assert_eq!(program.code, vec!["print \"MF_acc\""]);
+
+    // As are the program's own variables, even those that start with `MF_`,
+    // and the source in the annotated output.
+    let program = Compiler::new()
+        .stack_config("size 4")
+        .variable_prefix("app_")
+        .compile("set MF_count 1\ncall f\nprint MF_count\nend\nfn f {\n  return\n}")
+        .unwrap();
+    assert!(program.code.contains(&"set MF_count 1".to_string()));
+    assert!(program.code.contains(&"print MF_count".to_string()));
+    assert!(program.code.iter().any(|line| line.contains("app_acc")));
+    assert!(!program
+        .code
+        .iter()
+        .any(|line| line.contains("MF_") && !line.contains("MF_count")));
+    assert!(program
+        .annotated
+        .iter()
+        .any(|line| line.contains("set MF_count 1")));
+
+    let error = Compiler::new()
+        .variable_prefix("a b")
+        .compile(text)
+        .unwrap_err();
+    assert!(error.to_string().contains("variable_prefix"), "{}", error);
+}
@@ -0,0 +1,202 @@
+use std::convert::TryFrom;
+
+use routerbolt::*;
+use test_util::*;
+
+fn compile(text: &str, eliminate_dead_code: bool) -> (IntermediateRepresentation, Vec<String>) {
+    let options = parser::CompileOptions {
+        stack_config: Some(use_cell(false, 16)),
+        eliminate_dead_code,
+        ..Default::default()
+    };
+    let ir = parser::parse_with_options(text, &options).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    (ir, output)
+}
+
+fn after(instruction: &'static str, line: usize) -> DeadCodeReason {
+    DeadCodeReason::After { instruction, line }
+}
+
+#[test]
+fn test_dead_code_removed() {
+    let text = "set a 1
+                call f
+                set c 3
+                end
+                set x 1
+
+                fn f {
+                  set b 2
+                  return
+                  set x 2
+                }
+
+                fn unused {
+                  call also_unused
+                  return
+                }
+
+                fn also_unused {
+                  return
+                }
+            ";
+
+    let (_, full) = compile(text, false);
+    let (ir, output) = compile(text, true);
+    assert_eq!(
+        ir.dead_code,
+        vec![
+            DeadCode {
+                first_line: 4,
+                last_line: 4,
+                reason: after("end", 3),
+            },
+            DeadCode {
+                first_line: 9,
+                last_line: 9,
+                reason: after("return", 8),
+            },
+            DeadCode {
+                first_line: 12,
+                last_line: 15,
+                reason: DeadCodeReason::Uncalled(FunctionName::try_from("unused").unwrap()),
+            },
+            DeadCode {
+                first_line: 17,
+                last_line: 19,
+                reason: DeadCodeReason::Uncalled(FunctionName::try_from("also_unused").unwrap()),
+            },
+        ]
+    );
+    assert!(output.len() < full.len());
+    assert!(!output.contains(&"set x 1".to_string()));
+    assert!(!output.contains(&"set x 2".to_string()));
+
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    step_until_equal(&mut emu, Some(1), Some(2), Some(3), 30);
+
+    // Without the option, everything is kept.
+    let (ir, _) = compile(text, false);
+    assert!(ir.dead_code.is_empty());
+}
+
+#[test]
+fn test_dead_code_ends_with_block() {
+    let text = "set a 0
+                loop {
+                  op add a a 1
+                  if equal a 3 {
+                    break
+                    set x 1
+                  } else {
+                    continue
+                    set x 2
+                  }
+                  set x 3
+                }
+                set b 2
+                set c 3
+            ";
+
+    // Neither branch of the if falls out of it, so the rest of the loop body
+    // goes too, along with the `} else {` and the jump back to the top, since
+    // `continue` jumps there itself.
+    let (ir, output) = compile(text, true);
+    assert_eq!(
+        ir.dead_code,
+        vec![
+            DeadCode {
+                first_line: 5,
+                last_line: 6,
+                reason: after("break", 4),
+            },
+            DeadCode {
+                first_line: 8,
+                last_line: 11,
+                reason: after("continue", 7),
+            },
+        ]
+    );
+    assert!(!output.contains(&"set x 3".to_string()));
+
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    step_until_equal(&mut emu, Some(3), Some(2), Some(3), 100);
+}
+
+#[test]
+fn test_dead_code_kept_when_jumped_to() {
+    // Code a reachable jump lands in is kept, even inside a block whose start
+    // is dead.
+    let text = "jump dest always
+                set x 1
+                dest:
+                set a 1
+                jump nested equal a 1
+                end
+                if equal a 2 {
+                  nested:
+                  set b 2
+                }
+                set c 3
+            ";
+
+    let (ir, output) = compile(text, true);
+    assert_eq!(
+        ir.dead_code,
+        vec![
+            DeadCode {
+                first_line: 1,
+                last_line: 1,
+                reason: after("jump", 0),
+            },
+            DeadCode {
+                first_line: 6,
+                last_line: 6,
+                reason: after("end", 5),
+            },
+        ]
+    );
+    assert!(output.contains(&"set b 2".to_string()));
+    assert!(output.contains(&"set c 3".to_string()));
+
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    step_until_equal(&mut emu, Some(1), Some(2), Some(3), 30);
+}
+
+#[test]
+fn test_dead_code_kept_with_computed_jumps() {
+    // Setting `@counter` could go anywhere, so nothing is removed.
+    let text = "set @counter 3
+                end
+                set b 2
+                set c 3
+            ";
+
+    let (ir, output) = compile(text, true);
+    assert!(ir.dead_code.is_empty());
+    assert!(output.contains(&"set c 3".to_string()));
+}
+
+#[test]
+fn test_dead_code_reported() {
+    let text = "end
+                set x 1
+                set x 2
+            ";
+
+    let options = parser::CompileOptions {
+        eliminate_dead_code: true,
+        ..Default::default()
+    };
+    let ir = parser::parse_with_options(text, &options).unwrap();
+    let (output, annotated) = ir.generate().unwrap();
+    assert_eq!(output, vec!["end".to_string()]);
+    assert!(annotated.contains(&"//   lines 2-3 (after `end` at line 1)".to_string()));
+
+    // Errors in dead code are still reported.
+    let text = "end
+                set x
+            ";
+    assert!(parser::parse_with_options(text, &options).is_err());
+}
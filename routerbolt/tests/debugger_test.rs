@@ -0,0 +1,72 @@
+use routerbolt::*;
+
+fn debugger(text: &str) -> Debugger {
+    let ir = parser::parse(text).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let mut emu = Emulator::new(Some(Cell::new("cell1")), &output.join("\n")).unwrap();
+    emu.set_source_map(&ir.source_map());
+    Debugger::new(emu, 100)
+}
+
+const TEXT: &str = "set i 0
+                    top:
+                    op add i i 1
+                    write i cell1 i
+                    jump top lessThan i 5
+                    end";
+
+#[test]
+fn test_debugger_step_and_break() {
+    let mut debugger = debugger(TEXT);
+    assert_eq!(
+        debugger.execute("where").unwrap(),
+        vec!["At 0: \"set i 0\"\t// src 0: set i 0"]
+    );
+    assert_eq!(debugger.execute("step 2").unwrap().len(), 2);
+    assert_eq!(debugger.emu.counter(), 2);
+
+    assert_eq!(debugger.execute("b 1").unwrap(), vec!["Breakpoint at 1"]);
+    assert_eq!(debugger.execute("break").unwrap(), vec!["Breakpoints: 1"]);
+    let lines = debugger.execute("continue").unwrap();
+    assert_eq!(lines.last().unwrap(), "Hit breakpoint at 1");
+    assert_eq!(debugger.execute("print i").unwrap(), vec!["i = 1"]);
+
+    // A step runs the instruction under a breakpoint.
+    debugger.execute("s").unwrap();
+    assert_eq!(debugger.emu.get_var("i"), Some(2));
+
+    debugger.execute("delete 1").unwrap();
+    debugger.execute("c").unwrap();
+    assert_eq!(debugger.emu.get_var("i"), Some(5));
+}
+
+#[test]
+fn test_debugger_watch_and_print() {
+    let mut debugger = debugger(TEXT);
+    assert_eq!(
+        debugger.execute("watch cell1[3]").unwrap(),
+        vec!["Watchpoint on cell1[3]"]
+    );
+    let lines = debugger.execute("c").unwrap();
+    assert_eq!(
+        lines.last().unwrap(),
+        "Watchpoint cell1[3] changed from null to 3 at 2"
+    );
+    assert_eq!(
+        debugger.execute("p cell1[1..4]").unwrap(),
+        vec!["cell1[1] = 1", "cell1[2] = 2", "cell1[3] = 3"]
+    );
+    assert_eq!(
+        debugger.execute("print cell1[4]").unwrap(),
+        vec!["cell1[4] = null"]
+    );
+
+    debugger.execute("unwatch cell1[3]").unwrap();
+    debugger.execute("display i").unwrap();
+    let lines = debugger.execute("step").unwrap();
+    assert!(lines[0].starts_with("3:\ti:3 \"jump 1 lessThan i 5\""));
+
+    assert!(debugger.execute("frob").is_err());
+    assert!(debugger.execute("break 99").is_err());
+    assert!(debugger.execute("step x").is_err());
+}
@@ -0,0 +1,228 @@
+use routerbolt::*;
+
+fn compile(text: &str) -> Vec<String> {
+    parser::parse(text).unwrap().generate().unwrap().0
+}
+
+/// Decompiles the code for `text`, checking that the source compiles back to
+/// the same code, and gives the source.
+fn round_trip(text: &str) -> String {
+    let output = compile(text);
+    let source = decompile(&output.join("\n")).unwrap();
+    assert_eq!(compile(&source), output, "{}", source);
+    source
+}
+
+#[test]
+fn test_decompile_control_flow() {
+    let source = round_trip(
+        "set a 0
+         while lessThan a 10 {
+           if equal a 3 {
+             print a
+           } else {
+             op add a a 1
+           }
+           op add a a 1
+         }
+         do {
+           op sub a a 1
+         } while greaterThan a 0
+         loop {
+           if lessThan a 5 {
+             printflush message1
+           }
+           op add a a 1
+         }",
+    );
+
+    assert_eq!(
+        source,
+        "set a 0
+while lessThan a 10 {
+  if equal a 3 {
+    print a
+  } else {
+    op add a a 1
+  }
+  op add a a 1
+}
+do {
+  op sub a a 1
+} while greaterThan a 0
+loop {
+  if lessThan a 5 {
+    printflush message1
+  }
+  op add a a 1
+}
+"
+    );
+}
+
+#[test]
+fn test_decompile_functions() {
+    let source = round_trip(
+        "stack_config cell bank1
+         call hello
+         call fibonacci 12 -> n
+         print n
+         end
+
+         fn hello {
+           print \"Hello world!\"
+           return
+         }
+
+         fn fibonacci *n -> *answer {
+           let *result
+           if lessThan *n 2 {
+             return *n
+           }
+           op sub tmp *n 2
+           call fibonacci tmp -> *result
+           op sub tmp *n 1
+           call fibonacci tmp -> tmp
+           op add tmp *result tmp
+           return tmp
+         }",
+    );
+
+    assert!(source.starts_with("stack_config cell bank1\ncall function_"));
+    assert!(source.contains("fn function_16 {\n  print \"Hello world!\"\n  return\n}"));
+    assert!(source.contains(
+        "fn function_19 *arg1 -> result0 {
+  let *local1
+  if lessThan *arg1 2 {
+    return *arg1
+  }
+  op sub tmp *arg1 2
+  call function_19 tmp -> *local1"
+    ));
+}
+
+#[test]
+fn test_decompile_stack_arguments() {
+    let source = round_trip(
+        "stack_config cell bank1 offset 10
+         call h 1
+         end
+
+         fn h *x {
+           call k *x 2 -> *x
+           if lessThan *x 4 {
+             op add *x *x 1
+           }
+           print *x
+           return
+         }
+
+         fn k *a *b -> r {
+           op add r *a *b
+           return r
+         }",
+    );
+
+    assert!(source.starts_with("stack_config cell bank1 offset 10\n"));
+    assert!(source.contains("  call function_36 *arg1 2 -> *arg1\n"));
+    assert!(source.contains("  if lessThan *arg1 4 {\n    op add *arg1 *arg1 1\n  }\n"));
+    assert!(source.contains("  op add r *arg1 *arg2\n"));
+}
+
+#[test]
+fn test_decompile_low_level_stack() {
+    let source = round_trip(
+        "stack_config cell bank1
+         set num 5
+         callproc fibonacci
+         print num
+         end
+
+         fibonacci:
+           jump fibonacci__recursive greaterThan num 1
+           ret
+
+         fibonacci__recursive:
+           set MF_acc num
+           push
+           op sub num num 2
+           callproc fibonacci
+           set MF_acc num
+           push
+           peek 1
+           op sub num MF_acc 1
+           callproc fibonacci
+           pop
+           op add num num MF_acc
+           poke
+           pop
+           ret",
+    );
+
+    // With `push` and `pop`, it can't be a function.
+    assert!(source.contains("callproc label_10\n"));
+    assert!(source.contains("\npeek 1\n"));
+    assert!(source.contains("\npoke\npop\nret\n"));
+}
+
+#[test]
+fn test_decompile_hand_written() {
+    let text = "
+        set i 0
+        print i
+        op add i i 1
+        jump 1 lessThan i 5
+        printflush message1
+        someNewInstruction a b
+        jump 7 equal i 9
+        op mul t i 2
+        set @counter 0
+    ";
+    let source = decompile(text).unwrap();
+    assert_eq!(
+        source,
+        "loop {
+  set i 0
+  do {
+    print i
+    op add i i 1
+  } while lessThan i 5
+  printflush message1
+  asm someNewInstruction a b
+  jump label_7 equal i 9
+  label_7:
+  op mul t i 2
+}
+"
+    );
+
+    // Labels, as newer versions of Mindustry write them, and comments.
+    let text = "start:
+                # Count up.
+                op add i i 1
+                jump start lessThan i 5";
+    assert_eq!(
+        decompile(text).unwrap(),
+        "do {\n  op add i i 1\n} while lessThan i 5\n"
+    );
+}
+
+#[test]
+fn test_decompile_keeps_addresses() {
+    // A jump table reached through `@counter` still works afterwards.
+    let text = "set i 1
+                op add @counter @counter i
+                set a 10
+                set a 20
+                jump 6 greaterThan a 15
+                set b 1
+                end";
+    let source = decompile(text).unwrap();
+    let output = compile(&source);
+    assert_eq!(output.join("\n"), text.replace("                ", ""));
+
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    emu.run(6);
+    assert_eq!(emu.get_var("a"), Some(20));
+    assert_eq!(emu.get_var("b"), None);
+}
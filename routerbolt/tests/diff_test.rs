@@ -0,0 +1,106 @@
+use routerbolt::*;
+
+fn side(text: &str, options: &parser::CompileOptions) -> DiffSide {
+    let ir = IntermediateRepresentation::parse_with_options(text, options).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    DiffSide::new(&ir, &output)
+}
+
+fn diff(old: &str, new: &str) -> ProgramDiff {
+    let options = parser::CompileOptions::default();
+    ProgramDiff::new(side(old, &options), side(new, &options))
+}
+
+#[test]
+fn test_diff_edit() {
+    let old = "set a 0
+               top:
+               op add a a 1
+               jump top lessThan a 10
+               print a
+               end";
+    let new = "set a 0
+               top:
+               op add a a 2
+               print a
+               jump top lessThan a 10
+               print a
+               end";
+    let diff = diff(old, new);
+    assert!(!diff.is_empty());
+    assert_eq!(diff.counts(), (1, 2));
+    assert_eq!(
+        diff.lines,
+        vec![
+            DiffLine::Same { old: 0, new: 0 },
+            DiffLine::Removed { old: 1 },
+            DiffLine::Added { new: 1 },
+            DiffLine::Added { new: 2 },
+            DiffLine::Same { old: 2, new: 3 },
+            DiffLine::Same { old: 3, new: 4 },
+            DiffLine::Same { old: 4, new: 5 },
+        ]
+    );
+
+    // The jump to `top` is at a new address, but matches.
+    assert_eq!(
+        diff.to_string(),
+        "5 instructions -> 6 instructions: 1 removed, 2 added
+     0     0   set a 0                 // src 0: set a 0
+-    1         op add a a 1            // src 2: op add a a 1
++          1   op add a a 2            // src 2: op add a a 2
++          2   print a                 // src 3: print a
+     2     3   jump top lessThan a 10  // src 4: jump top lessThan a 10
+     3     4   print a                 // src 5: print a
+     4     5   end                     // src 6: end
+"
+    );
+}
+
+#[test]
+fn test_diff_context() {
+    let body: String = (0..20).map(|j| format!("set a{} {}\n", j, j)).collect();
+    let old = format!("{}end", body);
+    let new = format!("{}end", body.replace("set a10 10", "set a10 11"));
+    let text = diff(&old, &new).to_string();
+
+    // Only the instructions near the change are shown.
+    assert!(text.starts_with("21 instructions -> 21 instructions: 1 removed, 1 added\n...\n"));
+    assert!(!text.contains("set a6 6"));
+    assert!(text.contains("     7     7   set a7 7"));
+    assert!(text.contains("-   10         set a10 10"));
+    assert!(text.contains("+         10   set a10 11"));
+    assert!(text.contains("    13    13   set a13 13"));
+    assert!(text.ends_with("set a13 13  // src 13: set a13 13\n...\n"));
+
+    // Unlabeled jumps match even though the address they go to moved.
+    let old = "set a 0
+               while lessThan a 10 {
+                 op add a a 1
+               }";
+    let new = "set b 1
+               set a 0
+               while lessThan a 10 {
+                 op add a a 1
+               }";
+    let diff = diff(old, new);
+    assert_eq!(diff.counts(), (0, 1));
+}
+
+#[test]
+fn test_diff_options() {
+    let text = "set x 5
+                set y x
+                print y";
+    let debug = parser::CompileOptions::default();
+    let release = parser::CompileOptions::with_profile(parser::Profile::Release);
+    let diff = ProgramDiff::new(side(text, &debug), side(text, &release));
+    assert!(!diff.is_empty());
+
+    let same = ProgramDiff::new(side(text, &debug), side(text, &debug));
+    assert!(same.is_empty());
+    assert_eq!(
+        same.to_string(),
+        "3 instructions -> 3 instructions: 0 removed, 0 added\n"
+    );
+}
@@ -0,0 +1,78 @@
+use std::rc::Rc;
+
+use routerbolt::*;
+use test_util::*;
+
+fn compile(text: &str, stack_config: StackConfig, epilogue: Option<Epilogue>) -> Vec<String> {
+    let options = parser::CompileOptions {
+        stack_config: Some(stack_config),
+        epilogue,
+        ..Default::default()
+    };
+    let ir = parser::parse_with_options(text, &options).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    output
+}
+
+#[test]
+fn test_epilogue_auto() {
+    let output = compile("set a 1", use_cell(false, 0), None);
+    assert_eq!(output, vec!["set a 1"]);
+
+    let output = compile("epilogue end\nset a 1", use_cell(false, 0), None);
+    assert_eq!(output, vec!["set a 1", "end"]);
+
+    // Overridden by the options.
+    let output = compile(
+        "epilogue end\nset a 1",
+        use_cell(false, 0),
+        Some(Epilogue::Stop),
+    );
+    assert_eq!(output, vec!["set a 1", "stop"]);
+}
+
+fn test_epilogue_loop_fixture(cell: bool) {
+    let output = compile("epilogue loop\nop add a a 1", use_cell(cell, 4), None);
+
+    // The setup is skipped on the way around.
+    let start = 3;
+    assert_eq!(output[start], "op add a a 1");
+    assert!(output.contains(&format!("jump {} always x false", start)));
+
+    let mut emu = Emulator::new(emu_cell(cell), &output.join("\n")).unwrap();
+    emu.run(3 + 2 * 10);
+    assert_eq!(emu.get_var(&Rc::new("a".to_string())), Some(10));
+}
+
+#[test]
+fn test_epilogue_loop_stack() {
+    test_epilogue_loop_fixture(false);
+}
+
+#[test]
+fn test_epilogue_loop_cell() {
+    test_epilogue_loop_fixture(true);
+}
+
+#[test]
+fn test_epilogue_stop() {
+    let output = compile("epilogue stop\nop add a a 1", use_cell(false, 4), None);
+    assert_eq!(output[4], "stop");
+
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    emu.run(100);
+    emu.run(100);
+    assert_eq!(emu.get_var(&Rc::new("a".to_string())), Some(1));
+}
+
+#[test]
+fn test_epilogue_errors() {
+    for text in &[
+        "epilogue",
+        "epilogue halt",
+        "epilogue end stop",
+        "epilogue end\nepilogue stop",
+    ] {
+        assert!(parser::parse(text).is_err(), "{}", text);
+    }
+}
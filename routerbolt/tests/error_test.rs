@@ -0,0 +1,184 @@
+use routerbolt::*;
+
+fn compile(text: &str) -> CompileResult<Vec<String>> {
+    let ir = parser::parse(text)?;
+    let (output, _) = ir.generate()?;
+    Ok(output)
+}
+
+#[test]
+fn test_undefined_function() {
+    let err = compile("stack_config size 4\ncall nowhere").unwrap_err();
+    match &err {
+        CompileError::UndefinedSymbol {
+            kind, name, line, ..
+        } => {
+            assert_eq!(*kind, SymbolKind::Function);
+            assert_eq!(name, "nowhere");
+            assert_eq!(*line, Some(1));
+        }
+        _ => panic!("{:?}", err),
+    }
+    assert_eq!(err.line(), Some(1));
+    assert!(err.to_string().contains("Line 2: call nowhere"));
+    assert!(err.to_string().contains("function nowhere is not defined"));
+}
+
+#[test]
+fn test_undefined_label() {
+    let err = compile("jump nowhere always").unwrap_err();
+    match &err {
+        CompileError::UndefinedSymbol { kind, name, .. } => {
+            assert_eq!(*kind, SymbolKind::Label);
+            assert_eq!(name, "nowhere");
+        }
+        _ => panic!("{:?}", err),
+    }
+    assert!(err.to_string().contains("label nowhere is not defined"));
+}
+
+#[test]
+fn test_parse_error() {
+    let err = compile("set a 1\nif bogus a b {\n}").unwrap_err();
+    match &err {
+        CompileError::Parse { line, .. } => assert_eq!(*line, Some(1)),
+        _ => panic!("{:?}", err),
+    }
+}
+
+#[test]
+fn test_size_overflow() {
+    let options = parser::CompileOptions {
+        instruction_limit: Some(2),
+        ..Default::default()
+    };
+    let ir = parser::parse_with_options("set a 1\nset b 2\nset c 3", &options).unwrap();
+    let err = ir.generate().unwrap_err();
+    match &err {
+        CompileError::SizeOverflow { size, limit, .. } => {
+            assert_eq!(*size, 3);
+            assert_eq!(*limit, 2);
+        }
+        _ => panic!("{:?}", err),
+    }
+    assert!(err
+        .to_string()
+        .contains("program is 3 instructions, over the limit of 2"));
+}
+
+#[test]
+fn test_error_trait() {
+    fn describe(err: &dyn std::error::Error) -> String {
+        err.to_string()
+    }
+
+    let err = compile("stack_config size 4\ncall nowhere").unwrap_err();
+    assert_eq!(describe(&err), err.message());
+}
+
+#[test]
+fn test_multiple_diagnostics() {
+    let err =
+        compile("stack_config size 4\ncall nowhere\nset a 1\njump x bogus a b\ncall elsewhere")
+            .unwrap_err();
+    let diagnostics = err.diagnostics();
+    assert_eq!(
+        diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.line)
+            .collect::<Vec<_>>(),
+        vec![Some(1), Some(3), Some(4)]
+    );
+    assert!(diagnostics[2]
+        .message
+        .contains("function elsewhere is not defined"));
+    assert!(err.to_string().contains("Line 4: jump x bogus a b"));
+
+    // A single error is reported as it is.
+    let err = compile("stack_config size 4\ncall nowhere").unwrap_err();
+    assert_eq!(err.diagnostics().len(), 1);
+    assert!(matches!(err, CompileError::UndefinedSymbol { .. }));
+}
+
+#[test]
+fn test_block_error_stops() {
+    // Once a block fails to open, the lines after can't be parsed.
+    let err = compile("if bogus a b {\n  call nowhere\n}").unwrap_err();
+    assert_eq!(err.diagnostics().len(), 1);
+    assert_eq!(err.line(), Some(0));
+}
+
+#[test]
+fn test_suggestions() {
+    let err = compile(
+        "stack_config size 4
+         call fibonaci
+         end
+         fn fibonacci {
+           return
+         }
+         fn fibonacco {
+           return
+         }",
+    )
+    .unwrap_err();
+    match &err {
+        CompileError::UndefinedSymbol { suggestions, .. } => {
+            assert_eq!(suggestions, &["fibonacci", "fibonacco"])
+        }
+        _ => panic!("{:?}", err),
+    }
+    assert!(err
+        .to_string()
+        .contains("function fibonaci is not defined; did you mean fibonacci or fibonacco?"));
+
+    // Labels in a function are suggested as they are written.
+    let err = compile("stack_config size 4\njump don always\nend\nfn f {\n  done:\n  return\n}")
+        .unwrap_err();
+    assert!(err.to_string().contains("did you mean done?"));
+
+    let err = compile(
+        "stack_config size 4
+         fn f *count {
+           let *total
+           set *totl *count
+           return
+         }",
+    )
+    .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("stack variable *totl is not defined; did you mean *total?"));
+
+    // Nothing is close.
+    let err = compile("jump nowhere always\nsomewhere:").unwrap_err();
+    assert!(err.to_string().ends_with("label nowhere is not defined"));
+
+    assert_eq!(
+        suggestions(
+            "sort",
+            vec!["sorted", "sot", "sorts", "port", "shot", "fort", "sort"]
+        ),
+        vec!["fort", "port", "sorts"]
+    );
+}
+
+/// Embedders can use their own `Result` alongside the glob import, and box
+/// errors as they would any other.
+#[test]
+fn test_error_embedding() {
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+    fn build(text: &str) -> Result<usize> {
+        let ir = routerbolt::parser::parse(text)?;
+        Ok(ir.generate()?.0.len())
+    }
+
+    assert_eq!(build("set a 1").unwrap(), 1);
+    let err = build("jump nowhere always").unwrap_err();
+    let err = err.downcast::<routerbolt::error::CompileError>().unwrap();
+    assert!(matches!(
+        *err,
+        routerbolt::error::CompileError::UndefinedSymbol { .. }
+    ));
+}
@@ -0,0 +1,51 @@
+use std::convert::TryFrom;
+
+use routerbolt::*;
+
+#[test]
+fn test_execution_profile() {
+    let text = "stack_config size 8
+                set i 0
+                while lessThan i 3 {
+                  call f i -> r
+                  op add i i 1
+                }
+                end
+
+                fn f *n -> r {
+                  op mul r *n 2
+                  return r
+                }
+            ";
+
+    let ir = parser::parse(text).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let map = ir.source_map();
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    emu.set_source_map(&map);
+    emu.run(10000);
+
+    let profile = emu.execution_profile();
+    assert_eq!(profile.total, emu.steps() as u64);
+    assert_eq!(profile.instructions.len(), output.len());
+    assert_eq!(profile.instructions.iter().sum::<u64>(), profile.total);
+    assert_eq!(
+        profile.top_level + profile.functions.iter().map(|(_, n)| n).sum::<u64>(),
+        profile.total
+    );
+
+    // `set i 0` runs once, and the increment three times.
+    let line = |line| profile.lines.iter().find(|(l, _)| *l == line).unwrap().1;
+    assert_eq!(line(1), 1);
+    assert_eq!(line(4), 3);
+    assert!(line(3) > line(4));
+    assert_eq!(profile.functions.len(), 1);
+    assert_eq!(profile.functions[0].0, FunctionName::try_from("f").unwrap());
+    assert_eq!(profile.functions[0].1, line(9) + line(10));
+
+    // Lines come busiest first.
+    assert!(profile.lines.windows(2).all(|w| w[0].1 >= w[1].1));
+    let report = profile.to_string();
+    assert!(report.starts_with(&format!("Total: {} instructions run\n", profile.total)));
+    assert!(report.contains("  function f: "));
+}
@@ -16,7 +16,7 @@ fn basic_function_test_fixture(cell: bool) {
 
     let output = test_compile(text, use_cell(cell, 16));
     let mut emu = Emulator::new(emu_cell(cell), &output.join("\n")).unwrap();
-    step_until_equal(&mut emu, Some(1), Some(2), Some(3), 20);
+    step_until_equal(&mut emu, Some(1), Some(2), Some(3), 23);
 }
 
 #[test]
@@ -304,3 +304,447 @@ fn basic_test_return_recursive_stack() {
 fn basic_test_return_recursive_cell() {
     basic_return_recursive_test_fixture(true);
 }
+
+/// Quoted strings containing whitespace are a single argument.
+fn string_argument_test_fixture(cell: bool) {
+    let text = "call greet \"hello world\" -> a
+                end
+
+                fn greet *name -> r {
+                  if equal *name \"hello world\" {
+                    print *name
+                  }
+                  return \"good  bye\"
+                }
+            ";
+
+    let output = test_compile(text, use_cell(cell, 16));
+    let push = if cell {
+        "write \"hello world\" bank1 MF_stack_sz"
+    } else {
+        "set MF_acc \"hello world\""
+    };
+    assert!(output.contains(&push.to_string()));
+    assert!(output.contains(&"set MF_ret0 \"good  bye\"".to_string()));
+    let read = if cell { "MF_t0" } else { "MF_acc" };
+    assert!(output
+        .iter()
+        .any(|line| line.ends_with(&format!("equal {} \"hello world\"", read))));
+}
+
+#[test]
+fn string_argument_test_stack() {
+    string_argument_test_fixture(false);
+}
+
+#[test]
+fn string_argument_test_cell() {
+    string_argument_test_fixture(true);
+}
+
+#[test]
+fn function_name_validation_test() {
+    for name in &["5", "2fast", "end", "set", "my-func", "f.g", "true"] {
+        let text = format!("stack_config size 4\nfn {} {{\nreturn\n}}", name);
+        assert!(parser::parse(&text).is_err(), "{}", name);
+    }
+
+    for name in &["f", "_private", "fib2", "Main_Loop"] {
+        let text = format!("stack_config size 4\nfn {} {{\nreturn\n}}", name);
+        assert!(parser::parse(&text).is_ok(), "{}", name);
+    }
+}
+
+#[test]
+fn auto_stack_size_test() {
+    let text = "stack_config size auto
+                call outer 1 -> a
+                call leaf 2 -> b
+                end
+
+                fn outer *x -> y {
+                  let *tmp
+                  call leaf *x -> *tmp
+                  op add y *tmp 10
+                  return y
+                }
+
+                fn leaf *x -> y {
+                  op add y *x 1
+                  return y
+                }";
+
+    let ir = parser::parse(text).unwrap();
+
+    // outer: return address, *x and *tmp. leaf: return address and *x.
+    match &ir.stack_config {
+        StackConfig::Internal(size) => assert_eq!(*size, 5),
+        other => panic!("unexpected stack config {:?}", other),
+    }
+
+    let (output, _) = ir.generate().unwrap();
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    step_until_equal(&mut emu, Some(12), Some(3), None, 500);
+}
+
+#[test]
+fn auto_stack_size_no_calls_test() {
+    let ir = parser::parse("stack_config size auto\nset a 1").unwrap();
+    match &ir.stack_config {
+        StackConfig::Internal(size) => assert_eq!(*size, 0),
+        other => panic!("unexpected stack config {:?}", other),
+    }
+    ir.generate().unwrap();
+}
+
+#[test]
+fn auto_stack_size_error_test() {
+    // Direct and mutual recursion.
+    let text = "stack_config size auto
+                call f 1
+                end
+                fn f *n {
+                  call f *n
+                  return
+                }";
+    let err = format!("{:?}", parser::parse(text).unwrap_err());
+    assert!(err.contains("f -> f"), "{}", err);
+
+    let text = "stack_config size auto
+                call f
+                end
+                fn f {
+                  call g
+                  return
+                }
+                fn g {
+                  call f
+                  return
+                }";
+    let err = format!("{:?}", parser::parse(text).unwrap_err());
+    assert!(err.contains("f -> g -> f"), "{}", err);
+
+    // Low-level stack use can't be bounded.
+    assert!(parser::parse("stack_config size auto\npush").is_err());
+    assert!(parser::parse("stack_config size auto\npop").is_err());
+    assert!(parser::parse("stack_config size auto\nx:\ncallproc x").is_err());
+    assert!(parser::parse("stack_config size auto\npeek 0").is_ok());
+
+    // Only the call stack may be sized automatically.
+    assert!(parser::parse("stack_config size auto as data").is_err());
+}
+
+#[test]
+fn stack_usage_analysis_test() {
+    let program = "call outer 1 -> a
+                   end
+
+                   fn outer *x -> y {
+                     let *tmp
+                     call leaf *x -> *tmp
+                     op add y *tmp 10
+                     return y
+                   }
+
+                   fn leaf *x -> y {
+                     op add y *x 1
+                     return y
+                   }";
+
+    // Needs 5 entries: 3 for outer's frame and 2 for leaf's.
+    let ir = parser::parse(&format!("stack_config size 5\n{}", program)).unwrap();
+    assert_eq!(ir.stack_usage, StackUsage::Bounded(5));
+    let (_, annotated) = ir.generate().unwrap();
+    assert_eq!(annotated[0], "// Stack usage: at most 5 entries");
+
+    let err = parser::parse(&format!("stack_config size 4\n{}", program)).unwrap_err();
+    assert!(format!("{:?}", err).contains("may use 5 stack entries"));
+
+    // External stacks are only checked when bounded.
+    assert!(parser::parse(&format!("stack_config cell bank1 len 4\n{}", program)).is_err());
+    assert!(parser::parse(&format!("stack_config cell bank1 len 5\n{}", program)).is_ok());
+    assert!(parser::parse(&format!("stack_config cell bank1\n{}", program)).is_ok());
+
+    // Recursion can't be bounded, but isn't an error with an explicit size.
+    let text = "stack_config size 8
+                call f 1
+                end
+                fn f *n {
+                  call f *n
+                  return
+                }";
+    let ir = parser::parse(text).unwrap();
+    assert_eq!(
+        ir.stack_usage.to_string(),
+        "unbounded due to recursion at line 5 (f -> f)"
+    );
+
+    let ir = parser::parse("stack_config size 8\nset a 1\npush").unwrap();
+    assert_eq!(
+        ir.stack_usage.to_string(),
+        "unknown due to `push` at line 3"
+    );
+}
+
+fn banked_emulator(output: &[String], banks: &[&str]) -> Emulator {
+    let banks = banks.iter().map(|bank| Cell::new(*bank));
+    Emulator::new(banks, &output.join("\n")).unwrap()
+}
+
+#[test]
+fn banked_stack_recursion_test() {
+    // Each call uses 3 entries, so this needs more than one bank.
+    let text = "stack_config cell bank1 bank2 bank3
+                call count 300 -> r
+                print r
+                printflush message1
+                end
+
+                fn count *n -> r {
+                  if equal *n 0 {
+                    return 0
+                  }
+                  let *m
+                  op sub *m *n 1
+                  call count *m -> *m
+                  op add *m *m 1
+                  return *m
+                }";
+
+    let ir = parser::parse(text).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let mut emu = banked_emulator(&output, &["bank1", "bank2", "bank3"]);
+    let printed = emu.run(1_000_000);
+    assert!(printed
+        .iter()
+        .any(|line| line.ends_with("Printed to message1: 300")));
+    assert!(emu.get_cell_mem("bank2", 0).is_some());
+    assert_eq!(emu.get_cell_mem("bank3", 0), None);
+}
+
+#[test]
+fn banked_named_stack_test() {
+    let text = "stack_config size 4
+                stack_config cell bank2 bank3 offset 500 as data
+                set MF_acc 0
+                loop {
+                  push data
+                  op add MF_acc MF_acc 1
+                  if equal MF_acc 20 {
+                    break
+                  }
+                }
+                peek 12 data
+                set a MF_acc
+                pop data
+                set b MF_acc
+                poke 0 data
+                pop data
+                set c MF_acc
+                end";
+
+    let ir = parser::parse(text).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let mut emu = banked_emulator(&output, &["bank2", "bank3"]);
+    emu.run(10000);
+    assert_eq!(emu.get_var(&std::rc::Rc::new("a".to_string())), Some(7));
+    assert_eq!(emu.get_var(&std::rc::Rc::new("b".to_string())), Some(19));
+    assert_eq!(emu.get_var(&std::rc::Rc::new("c".to_string())), Some(19));
+    assert_eq!(emu.get_cell_mem("bank2", 511), Some(11));
+    assert_eq!(emu.get_cell_mem("bank3", 0), Some(12));
+}
+
+#[test]
+fn banked_stack_errors_test() {
+    assert!(parser::parse("stack_config cell bank1 bank2 len 1024").is_ok());
+    assert!(parser::parse("stack_config cell bank1 bank2 offset 1 len 1024").is_err());
+    assert!(parser::parse(
+        "stack_config cell bank1 bank2\nstack_config cell bank2 offset 100 as data"
+    )
+    .is_err());
+    assert!(
+        parser::parse("stack_config cell bank1 bank2\nstack_config cell bank3 as data").is_ok()
+    );
+    assert!(parser::parse("stack_config cell bank1 bank2 offset").is_err());
+}
+
+/// The final push of a call goes straight to the function with an internal
+/// stack, which reserves the whole frame first, and an external stack folds
+/// its increment into the reservation of any locals after it.
+#[test]
+fn test_call_fuses_final_push() {
+    let text = "call f 1 -> a
+                call g 2 -> b
+                set c 3
+                end
+
+                fn f *x -> r {
+                  return *x
+                }
+
+                fn g *x -> r {
+                  let *y
+                  set *y *x
+                  return *y
+                }
+            ";
+
+    let output = test_compile(text, use_cell(false, 16));
+    assert!(output.iter().any(|line| line.starts_with("set MF_resume ")));
+    assert!(!output
+        .iter()
+        .any(|line| line.starts_with("jump ") && line.ends_with(" always x false")));
+    assert!(output.contains(&"op add MF_stack_sz MF_stack_sz 3".to_string()));
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    step_until_equal(&mut emu, Some(1), Some(2), Some(3), 200);
+
+    let output = test_compile(text, use_cell(true, 16));
+    assert!(output.contains(&"op add MF_stack_sz MF_stack_sz 2".to_string()));
+    let mut emu = Emulator::new(emu_cell(true), &output.join("\n")).unwrap();
+    step_until_equal(&mut emu, Some(1), Some(2), Some(3), 200);
+}
+
+fn frame_return_fixture(stack_config: &str) {
+    // `f` always returns `*y` first, so the caller reads it from the popped
+    // frame. Its second value varies, so goes through `MF_ret1`.
+    let text = format!(
+        "{}
+         call g 4 -> a
+         call f 1 -> b c
+         end
+
+         fn f *x -> r s {{
+           let *y
+           op add *y *x 1
+           if equal *x 0 {{
+             return *y *x
+           }}
+           return *y 7
+         }}
+
+         fn g *x -> r {{
+           let *p
+           let *q
+           call f *x -> *p *q
+           op add *p *p *q
+           return *p
+         }}
+        ",
+        stack_config
+    );
+
+    let ir = parser::parse(&text).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    assert!(!output.iter().any(|line| line.contains("MF_ret0")));
+    assert!(output.iter().any(|line| line.contains("MF_ret1")));
+
+    let mut emu = Emulator::new(Some(Cell::default()), &output.join("\n")).unwrap();
+    step_until_equal(&mut emu, Some(12), Some(2), Some(7), 400);
+}
+
+#[test]
+fn test_frame_return_stack() {
+    frame_return_fixture("stack_config size 32");
+}
+
+#[test]
+fn test_frame_return_cell() {
+    frame_return_fixture("stack_config cell bank1 offset 10 len 32");
+}
+
+#[test]
+fn test_frame_return_canary() {
+    frame_return_fixture("stack_config size 32\ndebug stack_canary");
+    frame_return_fixture("stack_config cell bank1 offset 10 len 32\ndebug stack_canary");
+}
+
+#[test]
+fn test_call_argument_size() {
+    let call_size = |args: &str| {
+        let text = format!(
+            "call f
+             end
+
+             fn f {{
+               let *a
+               let *b
+               call g {} -> *a
+               return
+             }}
+
+             fn g *x *y -> r {{
+               return 1
+             }}
+            ",
+            args
+        );
+        test_compile(&text, use_cell(false, 16)).len()
+    };
+
+    // Each global argument is 3 instructions, and each stack variable 4.
+    assert_eq!(call_size("*a *b") - call_size("1 2"), 2);
+}
+
+#[test]
+fn test_call_deep_stack_args() {
+    // The args are read from well below the stack pointer.
+    let text = "call f 1 -> a
+                end
+
+                fn f *x -> r {
+                  let *p
+                  let *q
+                  let *s
+                  let *t
+                  let *u
+                  let *v
+                  let *w
+                  let *y
+                  let *z
+                  set *z 2
+                  call g *x *z *x *z -> r
+                  return r
+                }
+
+                fn g *a *b *c *d -> r {
+                  op add r *a *b
+                  op add r r *c
+                  op add r r *d
+                  return r
+                }
+            ";
+
+    for cell in [false, true] {
+        let output = test_compile(text, use_cell(cell, 32));
+        let mut emu = Emulator::new(emu_cell(cell), &output.join("\n")).unwrap();
+        step_until_equal(&mut emu, Some(6), None, None, 400);
+    }
+}
+
+#[test]
+fn stack_and_data_cells_test() {
+    // The stack is in a bank, and the function stores its results in a
+    // separate, smaller cell.
+    let text = "stack_config cell bank1
+                call square 3 -> a
+                call square 5 -> b
+                end
+
+                fn square *n -> r {
+                  set n *n
+                  op mul r n n
+                  write r cell1 n
+                  return r
+                }";
+
+    let ir = parser::parse(text).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let cells = vec![Cell::new("bank1"), Cell::with_size("cell1", 64)];
+    let mut emu = Emulator::new(cells, &output.join("\n")).unwrap();
+    emu.run(1000);
+    assert_eq!(emu.get_var("a"), Some(9));
+    assert_eq!(emu.get_var("b"), Some(25));
+    assert_eq!(emu.get_cell_mem("cell1", 3), Some(9));
+    assert_eq!(emu.get_cell_mem("cell1", 5), Some(25));
+    assert!(emu.get_cell_mem("bank1", 0).is_some());
+}
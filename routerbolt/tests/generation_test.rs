@@ -4,7 +4,11 @@ use test_util::*;
 #[test]
 fn test_passthrough() {
     let output = test_compile("set x 7", use_cell(false, 1));
-    assert!(output.starts_with(&["set MF_stack_sz 0".to_string()]),);
+    assert!(output.starts_with(&[
+        "jump 3 equal MF_init 1".to_string(),
+        "set MF_stack_sz 0".to_string(),
+        "set MF_init 1".to_string(),
+    ]));
 }
 
 #[test]
@@ -17,7 +21,14 @@ fn test_passthrough_with_empty_stack() {
 fn test_stack_gen() {
     // No stack gen if using memory bank.
     let output = test_compile("", use_cell(true, 3));
-    assert_eq!(output, vec!("set MF_stack_sz 0".to_string()));
+    assert_eq!(
+        output,
+        vec![
+            "jump 3 equal MF_init 1".to_string(),
+            "set MF_stack_sz 0".to_string(),
+            "set MF_init 1".to_string(),
+        ]
+    );
 
     for text in &["", ""] {
         let output = test_compile(text, use_cell(false, 0));
@@ -28,18 +39,40 @@ fn test_stack_gen() {
     assert_eq!(
         output,
         vec![
+            "jump 3 equal MF_init 1".to_string(),
             "set MF_stack_sz 0".to_string(),
+            "set MF_init 1".to_string(),
             "end".to_string(),
-            // Push
+            // Push and poke
             "set MF_stack[0] MF_acc".to_string(),
-            "op add MF_stack_sz MF_stack_sz 1".to_string(),
-            "set @counter MF_resume".to_string(),
             // Pop
             "set MF_acc MF_stack[0]".to_string(),
             "set @counter MF_resume".to_string(),
-            // Poke
-            "set MF_stack[0] MF_acc".to_string(),
-            "set @counter MF_resume".to_string(),
         ]
     );
 }
+
+fn restart_fixture(cell: bool) {
+    // Starting over from the top doesn't lose what's on the stack.
+    let text = "set MF_acc 5
+                push
+                end
+               ";
+    let output = test_compile(text, use_cell(cell, 4));
+    let mut emu = Emulator::new(emu_cell(cell), &output.join("\n")).unwrap();
+    let stack_sz = std::rc::Rc::new("MF_stack_sz".to_string());
+    emu.run(100);
+    assert_eq!(emu.get_var(&stack_sz), Some(1));
+    emu.run(100);
+    assert_eq!(emu.get_var(&stack_sz), Some(2));
+}
+
+#[test]
+fn test_restart_keeps_stack() {
+    restart_fixture(false);
+}
+
+#[test]
+fn test_restart_keeps_stack_cell() {
+    restart_fixture(true);
+}
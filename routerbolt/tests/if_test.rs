@@ -393,3 +393,41 @@ fn direct_variable_if_test_stack() {
 fn direct_variable_if_test_cell() {
     direct_variable_if_test_fixture(true);
 }
+
+#[test]
+fn test_condition_operator_validation() {
+    for text in &[
+        "if lesThan a 5 {\n}",
+        "while equals a 5 {\n}",
+        "do {\n} while lessthan a 5",
+        "top:\njump top greaterThanEquals a 5",
+        "busywait notequal a 5",
+    ] {
+        let err = parser::parse(text).unwrap_err();
+        assert!(
+            format!("{:?}", err).contains("Invalid condition"),
+            "{}",
+            text
+        );
+    }
+
+    for cond in &[
+        "equal",
+        "notEqual",
+        "lessThan",
+        "lessThanEq",
+        "greaterThan",
+        "greaterThanEq",
+        "strictEqual",
+        "always",
+    ] {
+        let text = format!("if {} a 5 {{\n}}", cond);
+        assert!(parser::parse(&text).is_ok(), "{}", cond);
+    }
+}
+
+#[test]
+fn test_condition_operator_error_has_line_number() {
+    let err = parser::parse("set a 1\n\nif lesThan a 5 {\n}").unwrap_err();
+    assert!(format!("{:?}", err).contains("Line 3"));
+}
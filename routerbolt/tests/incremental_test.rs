@@ -0,0 +1,170 @@
+use std::convert::TryFrom;
+
+use routerbolt::*;
+
+#[test]
+fn test_incremental_changes() {
+    let mut compiler = IncrementalCompiler::new(Compiler::new());
+    let text = "set a 1\n\nprint a\nend";
+    let code = compiler.compile(text).unwrap().code.clone();
+    assert_eq!(
+        compiler.change(),
+        Change::Recompiled {
+            lowered: 2,
+            reused: 0
+        }
+    );
+
+    compiler.compile(text).unwrap();
+    assert_eq!(compiler.change(), Change::Unchanged);
+
+    // Comments and indentation don't change the code, only the listing.
+    let program = compiler
+        .compile("  set a 1\n// say it\nprint a\nend")
+        .unwrap();
+    assert_eq!(program.code, code);
+    assert!(program
+        .annotated
+        .iter()
+        .any(|line| line == "// src 0: set a 1"));
+    assert_eq!(compiler.change(), Change::Relisted);
+
+    let program = compiler
+        .compile("set a 1\n// say it\nprint b\nend")
+        .unwrap();
+    assert_eq!(program.code, ["set a 1", "print b", "end"]);
+    assert_eq!(
+        compiler.change(),
+        Change::Recompiled {
+            lowered: 1,
+            reused: 1
+        }
+    );
+
+    // Inserting a line moves the `end` after it, which is reused.
+    let program = compiler
+        .compile("set a 1\n// say it\nset c 2\nprint b\nend")
+        .unwrap();
+    assert_eq!(program.ir.op_lines.last(), Some(&Some(4)));
+    assert_eq!(
+        compiler.change(),
+        Change::Recompiled {
+            lowered: 1,
+            reused: 1
+        }
+    );
+}
+
+#[test]
+fn test_incremental_lowers_only_edited_function() {
+    let program = |g: &str| {
+        format!(
+            "stack_config size 8
+            call f 1 -> a
+            call g a -> b
+            print b
+            end
+
+            fn f *x -> y {{
+              op add *x *x 1
+              return *x
+            }}
+
+            fn g *x -> y {{
+              {}
+              return *x
+            }}
+
+            fn h {{
+              loop {{
+                set c 1
+                break
+              }}
+              ret
+            }}",
+            g
+        )
+    };
+    let mut compiler = IncrementalCompiler::new(Compiler::new());
+    compiler.compile(&program("op mul *x *x 2")).unwrap();
+    let lowered = match compiler.change() {
+        Change::Recompiled { lowered, .. } => lowered,
+        change => panic!("not recompiled: {:?}", change),
+    };
+
+    // `g` grows, so `h` after it moves, but only `g` is lowered again.
+    let edited = program("op mul *x *x 2\n              op add *x *x 3");
+    let incremental = compiler.compile(&edited).unwrap();
+    let full = Compiler::new().compile(&edited).unwrap();
+    assert_eq!(incremental.code, full.code);
+    assert_eq!(incremental.annotated, full.annotated);
+    let h = FunctionName::try_from("h").unwrap();
+    assert_eq!(
+        incremental.ir.functions[&h].address,
+        full.ir.functions[&h].address
+    );
+    assert_eq!(
+        compiler.change(),
+        Change::Recompiled {
+            lowered: 1,
+            reused: lowered - 1
+        }
+    );
+
+    // A change to what `g` declares may change its calls anywhere.
+    let edited = program("let *t\n              set *t *x");
+    compiler.compile(&edited).unwrap();
+    assert_eq!(compiler.change(), Change::Recompiled { lowered, reused: 0 });
+}
+
+#[test]
+fn test_incremental_matches_full_compile() {
+    let edits = [
+        "stack_config size 4\ncall f\nend\nfn f {\n  set x 1\n  ret\n}",
+        "stack_config size 4\ncall f\nend\nfn f {\n  // x is one\n  set x 1\n  ret\n}",
+        "stack_config size 4\ncall f\ncall f\nend\nfn f {\n  // x is one\n  set x 1\n  ret\n}",
+        "stack_config size 4\ncall f\ncall f\nend\nfn f {\n  // x is one\n  asm {\n    set  x 1\n  }\n  ret\n}",
+        "stack_config size 4\ncall f\ncall f\nend\nfn f {\n  // x is 1\n  asm {\n    set  x 1\n  }\n  ret\n}",
+        // `tmp` is set and read once, so the peephole optimizer folds it away,
+        // until it's read again elsewhere.
+        "set tmp 1\nop add a tmp 1\nloop {\n  print a\n}",
+        "set tmp 1\nop add a tmp 1\nloop {\n  print tmp\n}",
+        "set tmp 1\nop add a tmp 1\nloop {\n  print a\n}",
+        // Inline calls before a part change the names of its labels.
+        "call one -> a\nif equal a 1 {\n  call one -> b\n}\nend\ninline fn one -> x {\n  jump skip always\n  skip:\n  return 1\n}",
+        "call one -> a\ncall one -> c\nif equal a 1 {\n  call one -> b\n}\nend\ninline fn one -> x {\n  jump skip always\n  skip:\n  return 1\n}",
+        "if equal a 1 {\n  call one -> b\n}\nend\ninline fn one -> x {\n  jump skip always\n  skip:\n  return 1\n}",
+        "\n\nif equal a 1 {\n  call one -> b\n}\nend\ninline fn one -> x {\n  jump skip always\n  skip:\n  return 1\n}",
+    ];
+    let mut compiler = IncrementalCompiler::new(Compiler::new().profile(parser::Profile::Release));
+    for text in edits.iter() {
+        let program = compiler.compile(text).unwrap();
+        let full = Compiler::new()
+            .profile(parser::Profile::Release)
+            .compile(text)
+            .unwrap();
+        assert_eq!(program.code, full.code, "{}", text);
+        assert_eq!(program.annotated, full.annotated, "{}", text);
+        assert_eq!(program.ir.labels, full.ir.labels, "{}", text);
+    }
+}
+
+#[test]
+fn test_incremental_error_keeps_last() {
+    let mut compiler = IncrementalCompiler::new(Compiler::new());
+    compiler.compile("set a 1\nend").unwrap();
+    assert!(compiler.compile("set a 1\nfrob {\nend").is_err());
+    assert_eq!(compiler.program().unwrap().code, ["set a 1", "end"]);
+
+    // Compared with the last program that compiled.
+    compiler.compile("set a 1 \nend").unwrap();
+    assert_eq!(compiler.change(), Change::Relisted);
+
+    // A label defined in two parts is only found to be when they're put
+    // together, whether or not either was lowered before.
+    let text = "a:\nset x 1\nloop {\n  a:\n}";
+    compiler.compile("a:\nset x 1\nloop {\n  b:\n}").unwrap();
+    let err = compiler.compile(text).unwrap_err();
+    assert_eq!(err, Compiler::new().compile(text).unwrap_err());
+    assert_eq!(err.line(), Some(3));
+}
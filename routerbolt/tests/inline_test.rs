@@ -0,0 +1,121 @@
+use routerbolt::*;
+use test_util::*;
+
+#[test]
+fn test_inline_without_stack() {
+    // Inline calls don't need a stack.
+    let text = "set a 1
+                call add1 a -> b
+                call add1 b -> c
+                end
+
+                inline fn add1 *x -> y {
+                  op add *x *x 1
+                  return *x
+                }
+            ";
+
+    let output = test_compile(text, use_cell(false, 0));
+    assert_eq!(
+        output,
+        vec![
+            "set a 1",
+            "set MF_add1_x a",
+            "op add MF_add1_x MF_add1_x 1",
+            "set b MF_add1_x",
+            "set MF_add1_x b",
+            "op add MF_add1_x MF_add1_x 1",
+            "set c MF_add1_x",
+            "end",
+        ]
+    );
+
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    step_until_equal(&mut emu, Some(1), Some(2), Some(3), 10);
+}
+
+fn inline_control_flow_fixture(cell: bool) {
+    let text = "call f 9 -> a
+                call f 1 -> b
+                set c 7
+                call swap b c -> b c
+                end
+
+                fn f *n -> r {
+                  let *m
+                  call shrink *n -> *m
+                  call shrink *m -> *m
+                  return *m
+                }
+
+                // Shrinks values greater than 3 down to 3, then one more.
+                inline fn shrink *v -> out {
+                  if lessThan *v 3 {
+                    return *v
+                  }
+                  again:
+                  op sub *v *v 1
+                  jump again greaterThan *v 3
+                  return *v
+                }
+
+                inline fn swap *p *q -> x y {
+                  return *q *p
+                }
+            ";
+
+    let output = test_compile(text, use_cell(cell, 16));
+    let mut emu = Emulator::new(emu_cell(cell), &output.join("\n")).unwrap();
+    step_until_equal(&mut emu, Some(2), Some(7), Some(1), 400);
+}
+
+#[test]
+fn test_inline_control_flow_stack() {
+    inline_control_flow_fixture(false);
+}
+
+#[test]
+fn test_inline_control_flow_cell() {
+    inline_control_flow_fixture(true);
+}
+
+#[test]
+fn test_inline_errors() {
+    let parse = |text: &str| parser::parse(text).map(|_| ());
+
+    // Inline functions can't make calls, since their variables are globals.
+    assert!(parse(
+        "call f
+         end
+         inline fn f {
+           call g
+         }
+         fn g {
+           return
+         }"
+    )
+    .is_err());
+
+    assert!(parse(
+        "call f 1 2
+         inline fn f *x {
+         }"
+    )
+    .is_err());
+
+    assert!(parse(
+        "call f -> a
+         inline fn f -> r {
+           return 1 2
+         }"
+    )
+    .is_err());
+
+    assert!(parse(
+        "if equal a 1 {
+           inline fn f {
+           }
+         }"
+    )
+    .is_err());
+}
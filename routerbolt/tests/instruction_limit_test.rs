@@ -0,0 +1,62 @@
+use anyhow::Result;
+use routerbolt::*;
+use test_util::*;
+
+fn compile(text: &str, stack_config: StackConfig, limit: Option<usize>) -> Result<Vec<String>> {
+    let options = parser::CompileOptions {
+        stack_config: Some(stack_config),
+        instruction_limit: limit,
+        ..Default::default()
+    };
+    let ir = parser::parse_with_options(text, &options)?;
+    let (output, _) = ir.generate()?;
+    Ok(output)
+}
+
+const TEXT: &str = "call f 1 -> a
+                    end
+
+                    fn f *x -> r {
+                      op add *x *x 1
+                      op add *x *x 1
+                      op add *x *x 1
+                      return *x
+                    }
+                ";
+
+#[test]
+fn test_instruction_limit() {
+    let output = compile(TEXT, use_cell(false, 16), None).unwrap();
+    let len = output.len();
+    assert!(compile(TEXT, use_cell(false, 16), Some(len)).is_ok());
+
+    let err = compile(TEXT, use_cell(false, 16), Some(len - 1)).unwrap_err();
+    let err = format!("{:?}", err);
+    assert!(err.contains(&format!(
+        "program is {} instructions, over the limit of {}",
+        len,
+        len - 1
+    )));
+
+    // The tables are the largest part, so an external stack is suggested.
+    assert!(err.contains("\n  jump tables for stack: 48\n"));
+    assert!(err.contains("function f: "));
+    assert!(err.contains("stack_config cell"));
+
+    // The function is larger than the tables here.
+    let err = compile(TEXT, use_cell(false, 2), Some(10)).unwrap_err();
+    let err = format!("{:?}", err);
+    assert!(err.contains("Largest parts:\n  function f: "), "{}", err);
+    assert!(!err.contains("stack_config cell"));
+}
+
+#[test]
+fn test_instruction_limit_default() {
+    // Mindustry's limit applies unless told otherwise.
+    assert!(compile(TEXT, use_cell(false, 200), Some(MAX_INSTRUCTIONS)).is_ok());
+    assert!(parser::parse("stack_config size 334\nset a 1")
+        .unwrap()
+        .generate()
+        .is_err());
+    assert!(compile(TEXT, use_cell(true, 300), Some(MAX_INSTRUCTIONS)).is_ok());
+}
@@ -0,0 +1,93 @@
+use std::convert::TryFrom;
+
+use routerbolt::*;
+
+#[test]
+fn test_generate_json() {
+    let text = "stack_config size 4
+                call f
+                top:
+                end
+
+                fn f {
+                  print \"hi\"
+                  return
+                }
+            ";
+
+    let ir = parser::parse(text).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let json = ir.generate_json().unwrap();
+    let f = &ir.functions()[&FunctionName::try_from("f").unwrap()];
+
+    assert!(json.starts_with("{\"instructions\":[\"jump 3 equal MF_init 1\","));
+    assert!(json.contains("\"print \\\"hi\\\"\""));
+    assert!(json.contains(&format!(
+        "\"labels\":{{\"top\":{}}}",
+        ir.labels().values().next().unwrap()
+    )));
+    assert!(json.contains(&format!(
+        "\"functions\":[{{\"name\":\"f\",\"address\":{},\"end\":{}}}]",
+        f.address.unwrap(),
+        f.end.unwrap()
+    )));
+
+    // The stack tables are the last 4 entries.
+    assert!(json.contains(&format!(
+        "\"stacks\":[{{\"name\":null,\"table_start\":{},\"table_end\":{}}}]",
+        output.len() - 12,
+        output.len()
+    )));
+    // Source lines count from 1, as in diagnostics.
+    assert!(json.contains("\"source_map\":[null,null,null,{\"line\":2,\"op\":3},"));
+
+    let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(
+        parsed["instructions"].as_array().unwrap().len(),
+        output.len()
+    );
+}
+
+#[test]
+fn test_diagnostic_json() {
+    let ir = parser::parse("top:\nset a 1").unwrap();
+    let warning = &ir.warnings()[0];
+    assert_eq!(
+        warning.to_json(Some("prog.mf")),
+        "{\"severity\":\"warning\",\"message\":\"label top is never jumped to (line 1)\",\"file\":\"prog.mf\",\"line\":1,\"code\":\"unused\"}"
+    );
+
+    // Errors have a code for their kind, and lines count from 1 without
+    // being repeated in the message.
+    let error = parser::parse("set a 1\nfoo \"bar\"").unwrap_err();
+    let json: Vec<_> = error
+        .diagnostics()
+        .iter()
+        .map(|diagnostic| diagnostic.to_json(None))
+        .collect();
+    assert_eq!(json.len(), 1);
+    assert!(json[0].starts_with(
+        "{\"severity\":\"error\",\"message\":\"foo \\\"bar\\\": parse mindustry command: "
+    ));
+    assert!(json[0].ends_with("\"file\":null,\"line\":2,\"code\":\"parse\"}"));
+
+    let error = parser::parse("stack_config size 4\ncall nope").unwrap_err();
+    let json = error.diagnostics()[0].to_json(None);
+    assert!(json.contains("\"code\":\"undefined_symbol\""), "{}", json);
+}
+
+#[test]
+fn test_diagnostic_lines_agree() {
+    // The human message and the JSON both count lines from 1.
+    let error = parser::parse("set a 1\nset b 2\nfoo \"bar\"").unwrap_err();
+    assert_eq!(error.line(), Some(2));
+    assert!(error.to_string().contains("Line 3: foo"), "{}", error);
+    let json = error.diagnostics()[0].to_json(None);
+    assert!(json.contains("\"line\":3,"), "{}", json);
+    assert!(!json.contains("Line 3"), "{}", json);
+
+    let ir = parser::parse("set a 1\nset b 2\ntop:\nset c 3").unwrap();
+    let warning = &ir.warnings()[0];
+    assert!(warning.to_string().ends_with("(line 3)"), "{}", warning);
+    assert!(warning.to_json(None).contains("\"line\":3,"));
+}
@@ -0,0 +1,133 @@
+use std::convert::TryFrom;
+
+use routerbolt::*;
+use test_util::*;
+
+fn liveness(text: &str, cell: bool) -> (IntermediateRepresentation, Liveness) {
+    let options = parser::CompileOptions {
+        stack_config: Some(use_cell(cell, 32)),
+        ..Default::default()
+    };
+    let ir = parser::parse_with_options(text, &options).unwrap();
+    let cfg = ir.cfg().unwrap();
+    let liveness = Liveness::new(&ir, &cfg);
+    (ir, liveness)
+}
+
+fn variables(names: &[&str]) -> Variables {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+#[test]
+fn test_clobbered_variable() {
+    let text = "set x 1
+                call f 0
+                print x
+                end
+
+                fn f *c {
+                  if equal *c 1 {
+                    set x 2
+                  }
+                  return
+                }";
+    let (ir, liveness) = liveness(text, false);
+    assert_eq!(liveness.calls.len(), 1);
+    // The stack is only set up the first time through.
+    assert_eq!(liveness.calls[0].live, variables(&["MF_init", "x"]));
+    assert_eq!(liveness.calls[0].clobbered, variables(&["x"]));
+    assert_eq!(
+        ir.clobbered(),
+        vec![ClobberedVariable {
+            function: FunctionName::try_from("f").unwrap(),
+            variable: "x".to_string(),
+            line: 1,
+        }]
+    );
+
+    let (_, annotated) = ir.generate().unwrap();
+    assert!(annotated.contains(
+        &"// Warning: call to f may overwrite x, which is read after the call (line 2)".to_string()
+    ));
+}
+
+#[test]
+fn test_always_written() {
+    // A function that sets a global on every path is how it's meant to be
+    // used, so the old value isn't live across the call.
+    let text = "set x 1
+                call g
+                call f
+                print x
+                end
+
+                fn g {
+                  set y 1
+                  return
+                }
+
+                fn f {
+                  call g
+                  if equal y 1 {
+                    set x 2
+                    return
+                  }
+                  set x 3
+                  return
+                }";
+    let (ir, liveness) = liveness(text, false);
+    assert!(ir.clobbered().is_empty());
+    assert!(liveness
+        .calls
+        .iter()
+        .all(|call| call.live == variables(&["MF_init"])));
+}
+
+#[test]
+fn test_live_temporaries() {
+    let text = "call f 1 2 -> a
+                print a
+                end
+
+                fn f *x *y -> r {
+                  op add r *x *y
+                  return r
+                }";
+    let (ir, liveness) = liveness(text, true);
+    let math = ir
+        .ops()
+        .iter()
+        .position(|op| matches!(op, IrOp::Math(..)))
+        .unwrap();
+
+    // Both reads are held in temporaries until the add.
+    assert!(liveness.live_after[math - 1].is_superset(&variables(&["MF_t0", "MF_t1"])));
+    assert!(!liveness.live_after[math].contains("MF_t0"));
+    assert!(liveness.live_after[math].contains("r"));
+}
+
+#[test]
+fn test_live_across_end() {
+    // Globals keep their value when the program starts over.
+    let ir = parser::parse("op add n n 1\nprint n\nend").unwrap();
+    let liveness = Liveness::new(&ir, &ir.cfg().unwrap());
+    assert_eq!(liveness.live_in[0], variables(&["n"]));
+}
+
+#[test]
+fn test_command_effects() {
+    let ir = parser::parse("ulocate building core false @copper x y found b").unwrap();
+    let effects = Effects::new(&ir.ops()[0], &ir.variable_prefix);
+    assert_eq!(effects.reads, variables(&[]));
+    assert_eq!(effects.writes, variables(&["x", "y", "found", "b"]));
+
+    let ir = parser::parse("asm {\njump 4 lessThan i \"limit\"\nop mul a b 0x10\n}").unwrap();
+    let effects: Vec<_> = ir
+        .ops()
+        .iter()
+        .map(|op| Effects::new(op, &ir.variable_prefix))
+        .collect();
+    assert_eq!(effects[0].reads, variables(&["i"]));
+    assert_eq!(effects[1].reads, variables(&["b"]));
+    assert_eq!(effects[1].writes, variables(&["a"]));
+}
@@ -412,3 +412,30 @@ fn direct_variable_loop_test_stack() {
 fn direct_variable_loop_test_cell() {
     direct_variable_loop_test_fixture(true);
 }
+
+#[test]
+fn test_while_checks_negated_condition_on_entry() {
+    let output = test_compile(
+        "while lessThan a 3 {
+           op add a a 1
+         }",
+        use_cell(false, 0),
+    );
+    assert_eq!(
+        output,
+        vec![
+            "jump 3 greaterThanEq a 3".to_string(),
+            "op add a a 1".to_string(),
+            "jump 1 lessThan a 3".to_string(),
+        ]
+    );
+
+    // strictEqual has no negation, so check at the end of the loop instead.
+    let output = test_compile(
+        "while strictEqual a null {
+           op add a a 1
+         }",
+        use_cell(false, 0),
+    );
+    assert_eq!(output[0], "jump 2 always x false");
+}
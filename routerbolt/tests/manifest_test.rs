@@ -0,0 +1,164 @@
+use std::path::{Path, PathBuf};
+
+use routerbolt::*;
+
+const MANIFEST: &str = r#"
+# Defaults for every target.
+profile = "release"
+out_dir = "build"
+banner = "{name} {hash}"
+
+[[library]]
+name = "math"
+sources = ["lib/math.mf"]
+
+[[target]]
+name = "counter"
+sources = ["counter.mf"]
+libraries = ["math"]
+stack_config = "cell bank1"
+
+[[target]]
+name = "printer"
+sources = [
+  "printer.mf",  # The main loop.
+  "extra.mf",
+]
+profile = "debug"
+banner = "printer v2"
+output = "out/printer.mlog"
+"#;
+
+#[test]
+fn test_parse_manifest() {
+    let manifest = Manifest::parse(MANIFEST, Path::new("project")).unwrap();
+    assert_eq!(
+        manifest.libraries,
+        vec![Library {
+            name: "math".to_string(),
+            sources: vec![PathBuf::from("lib/math.mf")],
+        }]
+    );
+    assert_eq!(
+        manifest.targets,
+        vec![
+            Target {
+                name: "counter".to_string(),
+                sources: vec![PathBuf::from("counter.mf")],
+                libraries: vec!["math".to_string()],
+                profile: parser::Profile::Release,
+                stack_config: Some("cell bank1".to_string()),
+                banner: Some("{name} {hash}".to_string()),
+                output: PathBuf::from("build/counter.mlog"),
+            },
+            Target {
+                name: "printer".to_string(),
+                sources: vec![PathBuf::from("printer.mf"), PathBuf::from("extra.mf")],
+                libraries: vec![],
+                profile: parser::Profile::Debug,
+                stack_config: None,
+                banner: Some("printer v2".to_string()),
+                output: PathBuf::from("out/printer.mlog"),
+            },
+        ]
+    );
+    assert_eq!(
+        manifest.path(&manifest.targets[0].output),
+        PathBuf::from("project/build/counter.mlog")
+    );
+}
+
+#[test]
+fn test_manifest_errors() {
+    let error = |text: &str| format!("{:#}", Manifest::parse(text, Path::new("")).unwrap_err());
+
+    assert!(error("[[target]]\nname = \"a\"").contains("missing field `sources`"));
+    assert!(
+        error("[[target]]\nname = \"a\"\nsources = [\"a.mf\"]\nstack = \"x\"")
+            .contains("unknown field `stack`")
+    );
+    assert!(error("[target]").contains("expected a sequence"));
+    assert!(error("[[program]]").contains("unknown field `program`"));
+    assert!(error("name = \"a").contains("line 1"));
+    assert!(error("profile = \"fast\"").contains("profile must be debug or release"));
+    assert!(error(
+        "[[library]]\nname = \"math\"\nsources = []\n[[target]]\nname = \"a\"\nsources = [\"a.mf\"]\nlibraries = [\"maths\"]"
+    )
+    .contains("target a: library maths is not defined; did you mean math?"));
+    assert!(error("[[target]]\nname = \"a\"\nsources = [\"a.mf\"]\n[[target]]\nname = \"a\"\nsources = [\"b.mf\"]")
+        .contains("duplicate target a"));
+}
+
+#[test]
+fn test_build_manifest() {
+    let root = std::env::temp_dir().join(format!("routerbolt_manifest_{}", std::process::id()));
+    std::fs::create_dir_all(root.join("lib")).unwrap();
+    let write = |path: &str, text: &str| std::fs::write(root.join(path), text).unwrap();
+    write("routerbolt.toml", MANIFEST);
+    write("counter.mf", "call double 4 -> x\nprint x\nend\n");
+    write(
+        "lib/math.mf",
+        "fn double *n -> r {\n  op mul r *n 2\n  return r\n}\n",
+    );
+    write("printer.mf", "set a 1\nprint a\n");
+    write("extra.mf", "frob a\n");
+
+    let manifest = Manifest::read(&root.join("routerbolt.toml")).unwrap();
+    assert_eq!(manifest.root, root);
+
+    // The library is included, so its functions follow the target's code.
+    let counter = manifest.target("counter").unwrap();
+    let sources = manifest.sources(counter).unwrap();
+    assert!(sources
+        .text
+        .starts_with("call double 4 -> x\nprint x\nend\n\nfn double"));
+    assert_eq!(
+        sources.locate(5),
+        Some((root.join("lib/math.mf").as_path(), 1))
+    );
+
+    let build = manifest.build(counter).unwrap();
+    assert_eq!(build.stats.total, build.code.len());
+    assert!(build.code[0].starts_with("set MF_build \"counter "));
+    let mut emu = Emulator::new(Some(Cell::default()), &build.code.join("\n")).unwrap();
+    emu.run(100);
+    assert_eq!(emu.get_var("x"), Some(8));
+
+    // Errors give the file each line is from.
+    let error = format!(
+        "{:#}",
+        manifest
+            .build(manifest.target("printer").unwrap())
+            .unwrap_err()
+    );
+    assert!(
+        error.contains(&format!(
+            "{} line 1: error: Line 3: frob a",
+            root.join("extra.mf").display()
+        )),
+        "{}",
+        error
+    );
+
+    // Sources may include libraries by name, and other files by path.
+    write(
+        "includer.mf",
+        "include math\ninclude util.mf\ncall double 3 -> x\ncall one -> y\nend\n",
+    );
+    write("util.mf", "fn one -> r {\n  return 1\n}\n");
+    let manifest = Manifest::parse(
+        "[[library]]\nname = \"math\"\nsources = [\"lib/math.mf\"]\n[[target]]\nname = \"includer\"\nsources = [\"includer.mf\"]\nstack_config = \"size 4\"",
+        &root,
+    )
+    .unwrap();
+    let includer = manifest.target("includer").unwrap();
+    let sources = manifest.sources(includer).unwrap();
+    assert_eq!(sources.locate(9), Some((root.join("util.mf").as_path(), 0)));
+    let build = manifest.build(includer).unwrap();
+    let mut emu = Emulator::new(None, &build.code.join("\n")).unwrap();
+    emu.run(200);
+    assert_eq!(emu.get_var("x"), Some(6));
+    assert_eq!(emu.get_var("y"), Some(1));
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
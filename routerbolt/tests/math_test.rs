@@ -0,0 +1,107 @@
+use routerbolt::*;
+use test_util::*;
+
+#[test]
+fn test_math_helpers_global() {
+    let text = "abs x
+                abs y x
+                min z x y
+                max z x y
+                clamp x 0 10
+                clamp y x 0 10";
+    let output = test_compile(text, use_cell(false, 0));
+    assert_eq!(
+        output,
+        vec![
+            "op abs x x 0".to_string(),
+            "op abs y x 0".to_string(),
+            "op min z x y".to_string(),
+            "op max z x y".to_string(),
+            "op max x x 0".to_string(),
+            "op min x x 10".to_string(),
+            "op max y x 0".to_string(),
+            "op min y y 10".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn test_math_helpers_form() {
+    for text in &[
+        "abs",
+        "abs a b c",
+        "min a b",
+        "max a b c d",
+        "clamp a b",
+        "clamp a b c d e",
+        "clamp x x 10",
+        "clamp x y 0 x",
+    ] {
+        assert!(parser::parse(text).is_err(), "{}", text);
+    }
+}
+
+fn test_math_helpers_stack_var_fixture(cell: bool) {
+    let text = "call f 15 -> a
+                end
+
+                fn f *x -> *r {
+                  let *low
+                  set *low 0
+                  clamp *x *low 10
+                  return *x
+                }";
+    let output = test_compile(text, use_cell(cell, 16));
+    let math: Vec<_> = output
+        .iter()
+        .filter(|line| line.starts_with("op max") || line.starts_with("op min"))
+        .collect();
+    let expected = if cell {
+        vec!["op max MF_t0 MF_t0 MF_t1", "op min MF_t1 MF_t1 10"]
+    } else {
+        vec!["op max MF_acc MF_t0 MF_acc", "op min MF_acc MF_acc 10"]
+    };
+    assert_eq!(math, expected);
+}
+
+#[test]
+fn test_math_helpers_stack_var_stack() {
+    test_math_helpers_stack_var_fixture(false);
+}
+
+#[test]
+fn test_math_helpers_stack_var_cell() {
+    test_math_helpers_stack_var_fixture(true);
+}
+
+fn test_math_helpers_run_fixture(cell: bool) {
+    let text = "call f -15 -> a
+                set b 2.5
+                clamp b 0 1
+                op pow c 2 10
+                op div c c 8
+                op shr c c 3
+                end
+
+                fn f *x -> *r {
+                  abs *x
+                  min *x *x 12
+                  return *x
+                }";
+    let output = test_compile(text, use_cell(cell, 16));
+    let mut emu = Emulator::new(emu_cell(cell), &output.join("\n")).unwrap();
+    emu.run(1000);
+    assert_eq!(emu.get_value("a"), Some(12.0));
+    assert_eq!(emu.get_value("b"), Some(1.0));
+    assert_eq!(emu.get_value("c"), Some(16.0));
+}
+
+#[test]
+fn test_math_helpers_run_stack() {
+    test_math_helpers_run_fixture(false);
+}
+
+#[test]
+fn test_math_helpers_run_cell() {
+    test_math_helpers_run_fixture(true);
+}
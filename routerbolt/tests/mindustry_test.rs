@@ -2,15 +2,18 @@ use routerbolt::*;
 use test_util::*;
 
 fn test_mindustry_fixture(cell: bool) {
-    let text = "set a 3\nop sub a a 1\nprint \"hello\"\nmade_up_single_token_ok\nprintflush message1\ngetlink result 0\nubind @poly";
+    let text = "set a 3\nop sub a a 1\nprint \"hello\"\nasm made_up_single_token_ok\nprintflush message1\ngetlink result 0\nubind @poly";
     let output = test_compile(text, use_cell(cell, 0));
 
-    let start = if cell { 1 } else { 0 };
+    let start = if cell { 3 } else { 0 };
 
-    // External always inits MZ_stack_sz.
+    // External always inits MZ_stack_sz, guarded to run once.
     let output = &output[start..];
 
-    let common: Vec<_> = text.lines().map(|l| l.to_string()).collect();
+    let common: Vec<_> = text
+        .lines()
+        .map(|l| l.trim_start_matches("asm ").to_string())
+        .collect();
 
     assert_eq!(output, &common[..]);
 
@@ -40,3 +43,166 @@ fn test_mindustry_print_set_whitespace() {
     let common: Vec<_> = text.lines().map(|l| l.to_string()).collect();
     assert_eq!(output, common);
 }
+
+#[test]
+fn test_mindustry_asm_block() {
+    let text = "set a 1
+                asm {
+                  set *weird \"unusual syntax\"
+                  // Dropped, like any other comment.
+
+                  op add b a 1
+                }
+                set c 3";
+    let output = test_compile(text, use_cell(false, 0));
+    assert_eq!(
+        output,
+        vec![
+            "set a 1".to_string(),
+            "set *weird \"unusual syntax\"".to_string(),
+            "op add b a 1".to_string(),
+            "set c 3".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn test_mindustry_asm_block_in_function() {
+    let text = "call f
+                end
+
+                fn f {
+                  asm {
+                    fn not_a_function {
+                    let *x
+                  }
+                  return
+                }";
+    let output = test_compile(text, use_cell(true, 0));
+    assert!(output.contains(&"fn not_a_function {".to_string()));
+    assert!(output.contains(&"let *x".to_string()));
+}
+
+#[test]
+fn test_mindustry_asm_block_unterminated() {
+    assert!(parser::parse("asm {\nset a 1").is_err());
+    assert!(parser::parse("asm\nset a 1\n}").is_err());
+}
+
+#[test]
+fn test_mindustry_numeric_literals() {
+    let text = "set a 0x1F\nop add b 0b1010 a\nset c %ff0000\nset d %ff0000ff\nset e 0xFFFFFFFF";
+    let output = test_compile(text, use_cell(false, 0));
+    let common: Vec<_> = text.lines().map(|l| l.to_string()).collect();
+    assert_eq!(output, common);
+
+    for text in &[
+        "set a 0x",
+        "set a 0xG1",
+        "set a 0b102",
+        "set a %ff00",
+        "set a %ff00000",
+        "set a %gg0000",
+        "op add a 0b 1",
+        "jump top lessThan a 0x\ntop:",
+        "set a 0x1FFFFFFFFFFFFFFFF",
+    ] {
+        assert!(parser::parse(text).is_err(), "{}", text);
+    }
+}
+
+#[test]
+fn test_mindustry_instruction_validation() {
+    for text in &[
+        "pritn \"hello\"",
+        "made_up_single_token",
+        "printflush",
+        "printflush message1 message2",
+        "read a bank1",
+        "end now",
+        "ucontrol",
+        "asm",
+    ] {
+        assert!(parser::parse(text).is_err(), "{}", text);
+    }
+
+    let text = "asm pritn \"hello\"\nasm {\nmade_up_single_token\n}";
+    let output = test_compile(text, use_cell(false, 0));
+    assert_eq!(
+        output,
+        vec![
+            "pritn \"hello\"".to_string(),
+            "made_up_single_token".to_string()
+        ]
+    );
+}
+
+fn test_mindustry_select_lookup_run_fixture(cell: bool) {
+    let text = "call smaller 9 7 -> a
+                lookup item b a
+                select c greaterThan a 8 a 0
+                print b
+                printflush message1
+                end
+
+                fn smaller *x *y -> *r {
+                  set x *x
+                  set y *y
+                  select r lessThan x y x y
+                  return r
+                }";
+    let output = test_compile(text, use_cell(cell, 16));
+    let mut emu = Emulator::new(emu_cell(cell), &output.join("\n")).unwrap();
+    let trace = emu.run(1000);
+    assert_eq!(emu.get_value("a"), Some(7.0));
+    assert_eq!(emu.get_value("c"), Some(0.0));
+    assert!(trace.contains(&"\tPrinted to message1: thorium".to_string()));
+}
+
+#[test]
+fn test_mindustry_select_lookup_run_cell() {
+    test_mindustry_select_lookup_run_fixture(true);
+}
+
+#[test]
+fn test_mindustry_select_lookup_run_stack() {
+    test_mindustry_select_lookup_run_fixture(false);
+}
+
+fn test_mindustry_draw_run_fixture(cell: bool) {
+    let text = "call square 4
+                call square 8
+                drawflush display1
+                end
+
+                fn square *size {
+                  set size *size
+                  draw rect 0 0 size size
+                  return
+                }";
+    let output = test_compile(text, use_cell(cell, 16));
+    let mut emu = Emulator::new(emu_cell(cell), &output.join("\n")).unwrap();
+    emu.run(1000);
+    let flushes = emu.draw_flushes();
+    assert_eq!(flushes.len(), 1);
+    assert_eq!(flushes[0].display, Symbol::new("display1"));
+    let sizes: Vec<_> = flushes[0]
+        .commands
+        .iter()
+        .map(|command| (command.command.clone(), command.args[2].num()))
+        .collect();
+    assert_eq!(
+        sizes,
+        vec![(Symbol::new("rect"), 4.0), (Symbol::new("rect"), 8.0)]
+    );
+}
+
+#[test]
+fn test_mindustry_draw_run_cell() {
+    test_mindustry_draw_run_fixture(true);
+}
+
+#[test]
+fn test_mindustry_draw_run_stack() {
+    test_mindustry_draw_run_fixture(false);
+}
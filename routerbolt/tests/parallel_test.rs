@@ -0,0 +1,55 @@
+#![cfg(feature = "parallel")]
+
+use std::convert::TryFrom;
+
+use routerbolt::*;
+
+/// A program with many functions, each with a loop and a label, so that
+/// lowering it is split into many parts.
+fn many_functions(count: usize) -> String {
+    let mut text = String::from("stack_config size 8\n");
+    for j in 0..count {
+        text.push_str(&format!("call f{}\n", j));
+    }
+    text.push_str("end\n");
+    for j in 0..count {
+        text.push_str(&format!(
+            "fn f{j} {{\nset x {j}\nloop {{\nif greaterThan x 3 {{\nbreak\n}}\nop add x x 1\n}}\nhere{j}:\nprint x\nreturn\n}}\n",
+            j = j
+        ));
+    }
+    text
+}
+
+#[test]
+fn test_parallel_lowering_is_in_order() {
+    let text = many_functions(40);
+    let ir = parser::parse(&text).unwrap();
+    let (code, _) = ir.generate().unwrap();
+
+    // The same each time, however the parts are scheduled.
+    for _ in 0..4 {
+        assert_eq!(parser::parse(&text).unwrap().generate().unwrap().0, code);
+    }
+
+    // Each function follows the one before it, with its labels inside it.
+    let mut last = None;
+    for j in 0..40 {
+        let name = FunctionName::try_from(format!("f{}", j).as_str()).unwrap();
+        let function = &ir.functions[&name];
+        let address = function.address.unwrap();
+        assert!(last.is_none_or(|last| last < address));
+        let label = LabelName::try_from(format!("here{}", j).as_str()).unwrap();
+        let label = ir.labels[&LabelName::scoped(&name, &label)];
+        assert!(address < label && label < function.end.unwrap());
+        last = Some(address);
+    }
+}
+
+#[test]
+fn test_parallel_lowering_reports_errors_in_order() {
+    let text = "stack_config size 4\nfn f {\njump x bogus a b\nreturn\n}\nfn g {\njump y bogus a b\nreturn\n}\ncall f\ncall g\n";
+    let err = parser::parse(text).unwrap_err();
+    let lines: Vec<_> = err.diagnostics().iter().map(|e| e.line).collect();
+    assert_eq!(lines, vec![Some(2), Some(6)]);
+}
@@ -0,0 +1,238 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use anyhow::Result;
+use routerbolt::*;
+
+const TEXT: &str = "set i 0
+                    top:
+                    op add i i 1
+                    jump again lessThan i 5
+                    end
+                    again:
+                    jump middle always
+                    middle:
+                    jump top always";
+
+fn compile(thread_jumps: bool) -> IntermediateRepresentation {
+    let options = parser::CompileOptions {
+        thread_jumps,
+        ..Default::default()
+    };
+    parser::parse_with_options(TEXT, &options).unwrap()
+}
+
+#[test]
+fn test_jump_threading() {
+    let plain = compile(false);
+    let threaded = compile(true);
+    let (plain, _) = plain.generate().unwrap();
+    let (threaded, _) = threaded.generate().unwrap();
+
+    // The chain is followed all the way to `top`.
+    assert_eq!(plain[2], "jump 4 lessThan i 5");
+    assert_eq!(threaded[2], "jump 1 lessThan i 5");
+    assert_eq!(threaded[4], "jump 1 always x false");
+    assert_eq!(plain.len(), threaded.len());
+
+    for output in [plain, threaded].iter() {
+        let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+        emu.run(100);
+        assert_eq!(emu.get_var(&Rc::new("i".to_string())), Some(5));
+    }
+}
+
+#[test]
+fn test_jump_threading_cycle() {
+    let text = "jump a equal x 1
+                end
+                a:
+                jump b always
+                b:
+                jump a always";
+    let mut ir = parser::parse(text).unwrap();
+    let (before, _) = ir.generate().unwrap();
+    assert_eq!(JumpThreading.run(&mut ir).unwrap(), Changed::No);
+    assert_eq!(ir.generate().unwrap().0, before);
+}
+
+/// Changes the program the first `remaining` times it runs.
+struct Countdown {
+    runs: Rc<Cell<usize>>,
+    remaining: usize,
+}
+
+impl IrPass for Countdown {
+    fn name(&self) -> &'static str {
+        "countdown"
+    }
+
+    fn run(&self, _ir: &mut IntermediateRepresentation) -> Result<Changed> {
+        self.runs.set(self.runs.get() + 1);
+        Ok((self.runs.get() <= self.remaining).into())
+    }
+}
+
+#[test]
+fn test_pass_manager_fixpoint() {
+    let mut ir = parser::parse("set a 1").unwrap();
+    let runs = Rc::new(Cell::new(0));
+    let mut manager = PassManager::default();
+    manager.add(Countdown {
+        runs: runs.clone(),
+        remaining: 3,
+    });
+    assert_eq!(manager.names(), vec!["countdown"]);
+
+    // Runs until a round makes no change.
+    assert_eq!(manager.run(&mut ir).unwrap(), Changed::Yes);
+    assert_eq!(runs.get(), 4);
+    assert_eq!(manager.run(&mut ir).unwrap(), Changed::No);
+    assert_eq!(runs.get(), 5);
+
+    // Or gives up.
+    runs.set(0);
+    manager.set_max_iterations(2);
+    assert_eq!(manager.run(&mut ir).unwrap(), Changed::Yes);
+    assert_eq!(runs.get(), 2);
+}
+
+#[test]
+fn test_passes_from_options() {
+    let release = parser::CompileOptions::with_profile(parser::Profile::Release);
+    assert_eq!(
+        PassManager::with_options(&release).names(),
+        vec!["jump-threading", "dead-code", "set-forwarding"]
+    );
+    assert!(PassManager::with_options(&Default::default())
+        .names()
+        .is_empty());
+}
+
+// Every construct that holds an address, with `noop`s scattered through it
+// for the passes below to shrink or remove.
+const RELOCATED: &str = "stack_config size 16
+                         debug stack_guard message1
+                         stack_config cell bank1 bank2 len 1024 as banked
+                         set i 0
+                         noop
+                         while lessThan i 3 {
+                           noop
+                           op add i i 1
+                         }
+                         do {
+                           noop
+                           op add i i 1
+                           if equal i 5 {
+                             noop
+                             continue
+                           } else {
+                             noop
+                           }
+                         } while lessThan i 6
+                         loop {
+                           noop
+                           call f i -> i
+                           if greaterThan i 8 {
+                             break
+                           }
+                         }
+                         set MF_acc i
+                         push banked
+                         pop banked
+                         set j MF_acc
+                         noop
+                         busywait lessThan j 9
+                         jump done always
+                         noop
+                         done:
+                         end
+
+                         fn f *x -> r {
+                           noop
+                           op add r *x 1
+                           return r
+                         }";
+
+fn is_noop(op: &IrOp) -> bool {
+    matches!(op, IrOp::MindustryCommand(op) if op.command.to_string() == "noop")
+}
+
+/// Turns each `noop` into an op that generates nothing.
+struct ShrinkNoops;
+
+impl IrPass for ShrinkNoops {
+    fn name(&self) -> &'static str {
+        "shrink-noops"
+    }
+
+    fn run(&self, ir: &mut IntermediateRepresentation) -> Result<Changed> {
+        let mut changed = false;
+        for op in ir.ops.iter_mut().filter(|op| is_noop(op)) {
+            *op = IrOp::BenchmarkLap(BenchmarkLapOp {});
+            changed = true;
+        }
+        Ok(changed.into())
+    }
+}
+
+/// Removes each `noop`.
+struct RemoveNoops;
+
+impl IrPass for RemoveNoops {
+    fn name(&self) -> &'static str {
+        "remove-noops"
+    }
+
+    fn run(&self, ir: &mut IntermediateRepresentation) -> Result<Changed> {
+        let remove: Vec<bool> = ir.ops().iter().map(is_noop).collect();
+        ir.remove_ops(&remove)?;
+        Ok(remove.contains(&true).into())
+    }
+}
+
+fn check_relocated<P: IrPass + 'static>(pass: P) {
+    let mut ir = parser::parse(RELOCATED).unwrap();
+    let mut manager = PassManager::default();
+    manager.add(pass);
+    assert_eq!(manager.run(&mut ir).unwrap(), Changed::Yes);
+
+    // The same as if the `noop`s had never been there.
+    let without: Vec<&str> = RELOCATED
+        .lines()
+        .filter(|line| line.trim() != "noop")
+        .collect();
+    let expected = parser::parse(&without.join("\n")).unwrap();
+    let (expected, _) = expected.generate().unwrap();
+    let (output, _) = ir.generate().unwrap();
+    assert_eq!(output, expected);
+
+    let cells = vec![
+        routerbolt::Cell::new("bank1"),
+        routerbolt::Cell::new("bank2"),
+    ];
+    let mut emu = Emulator::new(cells, &output.join("\n")).unwrap();
+    emu.run(1000);
+    assert_eq!(emu.get_var(&Rc::new("j".to_string())), Some(9));
+}
+
+#[test]
+fn test_pass_manager_relocates_resized_ops() {
+    check_relocated(ShrinkNoops);
+}
+
+#[test]
+fn test_remove_ops() {
+    check_relocated(RemoveNoops);
+}
+
+#[test]
+fn test_remove_ops_keeps_loops_of_breaks() {
+    let mut ir = parser::parse("loop {\nbreak\n}").unwrap();
+    let remove: Vec<bool> = ir
+        .ops()
+        .iter()
+        .map(|op| matches!(op, IrOp::InfiniteLoop(..)))
+        .collect();
+    assert!(ir.remove_ops(&remove).is_err());
+}
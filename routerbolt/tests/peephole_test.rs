@@ -0,0 +1,146 @@
+use routerbolt::*;
+use test_util::*;
+
+fn compile(text: &str, peephole: bool) -> (IntermediateRepresentation, Vec<String>) {
+    let options = parser::CompileOptions {
+        stack_config: Some(use_cell(false, 0)),
+        peephole,
+        ..Default::default()
+    };
+    let ir = parser::parse_with_options(text, &options).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    (ir, output)
+}
+
+fn lines(output: &[&str]) -> Vec<String> {
+    output.iter().map(|line| line.to_string()).collect()
+}
+
+#[test]
+fn test_peephole_patterns() {
+    let text = "set a a
+                set tmp 1
+                op add a tmp 1
+                set tmp2 2
+                set b tmp2
+                op add MF_stack_sz MF_stack_sz 2
+                op sub MF_stack_sz MF_stack_sz 1
+                op add MF_stack_sz MF_stack_sz 3
+                op add MF_stack_sz MF_stack_sz 1
+                op sub MF_stack_sz MF_stack_sz 1
+                set c 3
+            ";
+
+    let (_, output) = compile(text, false);
+    assert_eq!(output.len(), 11);
+
+    let (ir, output) = compile(text, true);
+    assert_eq!(
+        output,
+        lines(&[
+            "op add a 1 1",
+            "set b 2",
+            "op add MF_stack_sz MF_stack_sz 4",
+            "set c 3",
+        ])
+    );
+    assert_eq!(ir.peephole_saved, 7);
+
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    step_until_equal(&mut emu, Some(2), Some(2), Some(3), 5);
+}
+
+#[test]
+fn test_peephole_keeps_live_values() {
+    // `tmp` is read again later, so its value is still needed.
+    let text = "set tmp 1
+                op add a tmp 1
+                op add b tmp 1
+            ";
+    let (_, output) = compile(text, true);
+    assert_eq!(
+        output,
+        lines(&["set tmp 1", "op add a tmp 1", "op add b tmp 1"])
+    );
+}
+
+#[test]
+fn test_peephole_counts_uses_in_ir() {
+    // `tmp` is read once in the source, but at both copies of `g`.
+    let text = "set tmp 1
+                call g
+                call g
+                end
+
+                inline fn g {
+                  op add a a tmp
+                }
+            ";
+    let (ir, output) = compile(text, true);
+    assert_eq!(
+        output,
+        lines(&["set tmp 1", "op add a a tmp", "op add a a tmp", "end"])
+    );
+    assert_eq!(ir.peephole_saved, 0);
+
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    step_until_equal(&mut emu, Some(2), None, None, 5);
+
+    // Each value of `tmp2` is read once, though the name appears many times.
+    let text = "set tmp2 1
+                op add b tmp2 1
+                set tmp2 2
+                op add c tmp2 1
+            ";
+    let (ir, output) = compile(text, true);
+    assert_eq!(output, lines(&["op add b 1 1", "op add c 2 1"]));
+    assert_eq!(ir.peephole_saved, 2);
+}
+
+#[test]
+fn test_peephole_respects_jump_targets() {
+    // Something may jump between each of these pairs, so none are merged.
+    let text = "set tmp 1
+                dest:
+                op add a a tmp
+                set tmp2 1
+                if equal tmp2 1 {
+                  set tmp3 1
+                }
+                set b tmp2
+                op add c tmp3 1
+                jump dest lessThan a 3
+            ";
+    let (_, full) = compile(text, false);
+    let (ir, output) = compile(text, true);
+    assert_eq!(output, full);
+    assert_eq!(ir.peephole_saved, 0);
+
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    step_until_equal(&mut emu, Some(3), Some(1), Some(2), 40);
+}
+
+#[test]
+fn test_peephole_in_function() {
+    let text = "set a 1
+                call f 2 -> c
+                end
+
+                fn f *x -> y {
+                  set tmp *x
+                  set b tmp
+                  return 3
+                }
+            ";
+
+    let options = parser::CompileOptions {
+        stack_config: Some(use_cell(false, 16)),
+        peephole: true,
+        ..Default::default()
+    };
+    let ir = parser::parse_with_options(text, &options).unwrap();
+    let (output, _) = ir.generate().unwrap();
+
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    step_until_equal(&mut emu, Some(1), Some(2), Some(3), 100);
+}
@@ -0,0 +1,70 @@
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+use routerbolt::*;
+
+const TEXT: &str = "stack_config size 16
+                    debug stack_guard message1
+                    call f 2 -> a
+                    end
+
+                    fn f *x -> r {
+                      set y *x
+                      op add r y 1
+                      return r
+                    }
+
+                    fn unused {
+                      return
+                    }";
+
+fn compile(profile: parser::Profile) -> Vec<String> {
+    let options = parser::CompileOptions::with_profile(profile);
+    let ir = parser::parse_with_options(TEXT, &options).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    output
+}
+
+#[test]
+fn test_profiles() {
+    let debug = compile(parser::Profile::Debug);
+    let release = compile(parser::Profile::Release);
+
+    // The stack guard reports overflow to message1.
+    assert!(debug
+        .iter()
+        .any(|line| line.starts_with("printflush message1")));
+    assert!(!release.iter().any(|line| line.contains("message1")));
+    assert!(release.len() < debug.len());
+
+    for output in [debug, release].iter() {
+        let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+        emu.run(200);
+        assert_eq!(emu.get_var(&Rc::new("a".to_string())), Some(3));
+    }
+}
+
+#[test]
+fn test_debug_profile_is_default() {
+    let text = "stack_config size 16
+                debug stack_canary
+                call f
+                end
+
+                fn f {
+                  return
+                }";
+    let options = parser::CompileOptions::with_profile(parser::Profile::Debug);
+    let debug = parser::parse_with_options(text, &options).unwrap();
+    let default = parser::parse(text).unwrap();
+    assert_eq!(debug.generate().unwrap(), default.generate().unwrap());
+}
+
+#[test]
+fn test_profile_names() {
+    assert_eq!(
+        parser::Profile::try_from("release").unwrap(),
+        parser::Profile::Release
+    );
+    assert!(parser::Profile::try_from("fast").is_err());
+}
@@ -0,0 +1,44 @@
+use routerbolt::*;
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+const TEXT: &str = "call f
+                    end
+
+                    fn f {
+                      print 1
+                      return
+                    }
+                ";
+
+#[test]
+fn test_schematic() {
+    let ir = parser::parse(&format!("stack_config cell bank1\n{}", TEXT)).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let schematic = schematic(&ir, &output);
+
+    // The header, then a zlib stream. It's stored without compression, so the
+    // processor's code and links can be seen in it directly.
+    assert_eq!(&schematic[..7], b"msch\x01\x78\x01");
+    assert!(contains(&schematic, output.join("\n").as_bytes()));
+    assert!(contains(&schematic, b"\x00\x0bmemory-bank"));
+    assert!(contains(&schematic, b"\x00\x05bank1\x00\x01\x00\x00"));
+
+    let base64 = schematic_base64(&ir, &output);
+    assert!(base64.starts_with("bXNjaAF4AQ"));
+    assert_eq!(base64.len(), schematic.len().div_ceil(3) * 4);
+}
+
+#[test]
+fn test_schematic_internal_stack() {
+    // With an internal stack, the processor is all there is.
+    let ir = parser::parse(&format!("stack_config size 4\n{}", TEXT)).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let schematic = schematic(&ir, &output);
+    assert!(contains(&schematic, b"\x00\x0fmicro-processor"));
+    assert!(!contains(&schematic, b"memory"));
+}
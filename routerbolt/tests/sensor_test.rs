@@ -0,0 +1,63 @@
+use routerbolt::*;
+use test_util::*;
+
+#[test]
+fn test_sense_global() {
+    let text = "sense x vault1 @copper\nset y vault1.@totalItems\nset z \"a.@b\"";
+    let output = test_compile(text, use_cell(false, 0));
+    assert_eq!(
+        output,
+        vec![
+            "sensor x vault1 @copper".to_string(),
+            "sensor y vault1 @totalItems".to_string(),
+            "set z \"a.@b\"".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn test_sense_form() {
+    for text in &["sense x vault1", "sense x vault1 @copper extra"] {
+        assert!(parser::parse(text).is_err(), "{}", text);
+    }
+}
+
+#[test]
+fn test_sense_stack_var_outside_function() {
+    assert!(parser::parse("stack_config size 4\nsense *x vault1 @copper").is_err());
+}
+
+fn test_sense_stack_var_fixture(cell: bool) {
+    let text = "call f vault1
+                end
+
+                fn f *block {
+                  let *amount
+                  sense *amount *block @copper
+                  set *amount *block.@totalItems
+                  return
+                }";
+    let output = test_compile(text, use_cell(cell, 16));
+    let sensors: Vec<_> = output
+        .iter()
+        .filter(|line| line.starts_with("sensor"))
+        .collect();
+    let read = if cell { "MF_t0" } else { "MF_acc" };
+    assert_eq!(
+        sensors,
+        vec![
+            &format!("sensor {} {} @copper", read, read),
+            &format!("sensor {} {} @totalItems", read, read)
+        ]
+    );
+}
+
+#[test]
+fn test_sense_stack_var_stack() {
+    test_sense_stack_var_fixture(false);
+}
+
+#[test]
+fn test_sense_stack_var_cell() {
+    test_sense_stack_var_fixture(true);
+}
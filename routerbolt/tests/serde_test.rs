@@ -0,0 +1,73 @@
+use routerbolt::*;
+
+const TEXT: &str = "stack_config size 16
+                    debug stack_guard message1
+                    set i 0
+                    while lessThan i 3 {
+                      call f i -> a
+                      print a
+                      op add i i 1
+                    }
+                    end
+                    print \"unreachable\"
+
+                    fn f *x -> r {
+                      if equal *x 1 {
+                        op mul r *x 2
+                      } else {
+                        set r *x
+                      }
+                      return r
+                    }";
+
+fn round_trip(ir: &IntermediateRepresentation) -> IntermediateRepresentation {
+    let json = ir.to_json().unwrap();
+    let copy = IntermediateRepresentation::from_json(&json).unwrap();
+    assert_eq!(copy.to_json().unwrap(), json);
+    copy
+}
+
+#[test]
+fn test_round_trip() {
+    for profile in [parser::Profile::Debug, parser::Profile::Release].iter() {
+        let options = parser::CompileOptions::with_profile(*profile);
+        let ir = parser::parse_with_options(TEXT, &options).unwrap();
+        let copy = round_trip(&ir);
+
+        assert_eq!(copy.generate().unwrap(), ir.generate().unwrap());
+        assert_eq!(copy.dead_code, ir.dead_code);
+        assert_eq!(copy.stack_usage, ir.stack_usage);
+    }
+}
+
+#[test]
+fn test_round_trip_cell() {
+    let text = "stack_config cell bank1 bank2
+                call f
+                end
+
+                fn f {
+                  set MF_acc 1
+                  push
+                  return
+                }";
+    let ir = parser::parse(text).unwrap();
+    let copy = round_trip(&ir);
+    assert_eq!(copy.generate().unwrap(), ir.generate().unwrap());
+    assert_eq!(
+        copy.stack_usage,
+        StackUsage::Manual {
+            instruction: "push",
+            line: 6
+        }
+    );
+}
+
+#[test]
+fn test_from_json_rejects_unknown_instruction() {
+    let ir = parser::parse("stack_config size 4\npop\nend").unwrap();
+    let json = ir.to_json().unwrap();
+    assert!(json.contains("\"instruction\": \"pop\""));
+    let json = json.replace("\"instruction\": \"pop\"", "\"instruction\": \"frob\"");
+    assert!(IntermediateRepresentation::from_json(&json).is_err());
+}
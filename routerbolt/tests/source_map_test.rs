@@ -0,0 +1,204 @@
+use routerbolt::*;
+
+const TEXT: &str = "stack_config size 8
+set a 1
+call f 2 -> b
+set c 3
+end
+
+fn f *x -> r {
+  op add r *x 1
+  return r
+}
+";
+
+#[test]
+fn test_source_map() {
+    let ir = parser::parse(TEXT).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let map = ir.source_map();
+
+    // Setup code has no location, and nor do the stack tables.
+    assert_eq!(map.location(2), None);
+    // The program is followed by an `end` and 8 stack table entries.
+    assert_eq!(map.instructions.len(), output.len() - 1 - 24);
+    assert_eq!(map.location(output.len() - 1), None);
+
+    let set_a = map.location(3).unwrap();
+    assert_eq!(set_a.line, 1);
+    assert_eq!(output[3], "set a 1");
+
+    // Each line's instructions are contiguous, and come in source order.
+    let lines: Vec<usize> = map.instructions.iter().flatten().map(|l| l.line).collect();
+    let mut sorted = lines.clone();
+    sorted.sort_unstable();
+    assert_eq!(lines, sorted);
+    assert_eq!(map.breakpoints(&[3, 7, 4]).len(), 3);
+    assert!(map.breakpoints(&[5]).is_empty());
+
+    let text = map.to_string();
+    assert!(text.starts_with("0\t-\t-\n1\t-\t-\n2\t-\t-\n3\t2\t3\n"));
+}
+
+#[test]
+fn test_source_breakpoints() {
+    let ir = parser::parse(TEXT).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let map = ir.source_map();
+
+    let breakpoint = map.breakpoints(&[7]);
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    emu.set_breakpoints(breakpoint.clone());
+    let trace = emu.run(100);
+    assert!(trace.contains(&format!("Hit breakpoint at {}", breakpoint[0])));
+    // The line reads `*x` from the stack first.
+    assert_eq!(output[breakpoint[0] + 4], "op add r MF_acc 1");
+}
+
+#[test]
+fn test_source_line_breakpoints() {
+    let ir = parser::parse(TEXT).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let map = ir.source_map();
+
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    emu.set_source_map(&map);
+    emu.set_source_breakpoints(&[7, 5]);
+    let trace = emu.run(100);
+    assert_eq!(
+        trace.last().unwrap(),
+        &format!("Hit breakpoint at {}", map.breakpoints(&[7])[0])
+    );
+
+    // The trace shows the source line of each instruction that has one.
+    assert!(trace[0].ends_with('"'));
+    assert_eq!(trace[3], "3:\t\"set a 1\"\t// src 1: set a 1");
+}
+
+#[test]
+fn test_annotated_source_lines() {
+    let ir = parser::parse(TEXT).unwrap();
+    let (_, annotated) = ir.generate().unwrap();
+
+    // Each line is shown once, above the code generated from it.
+    let call = annotated
+        .iter()
+        .position(|line| line == "// src 2: call f 2 -> b")
+        .unwrap();
+    assert!(annotated[call + 1].starts_with("// Call f"));
+    assert_eq!(
+        annotated
+            .iter()
+            .filter(|line| line.starts_with("// src 7: "))
+            .count(),
+        1
+    );
+    assert!(annotated.contains(&"// src 7: op add r *x 1".to_string()));
+}
+
+#[test]
+fn test_step_over_and_out() {
+    let text = "stack_config size 8
+                set a 1
+                call f 2 -> b
+                set c 3
+                end
+
+                fn f *x -> r {
+                  call g *x -> r
+                  op add r r 1
+                  return r
+                }
+
+                fn g *x -> r {
+                  op mul r *x 10
+                  return r
+                }";
+    let ir = parser::parse(text).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let map = ir.source_map();
+    assert_eq!(map.calls.len(), 2);
+    let outer = map.calls[0];
+    let inner = map.calls[1];
+
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    emu.set_source_map(&map);
+
+    // Up to the call, stepping over is single stepping.
+    while emu.get_var("@counter") != Some(outer.start) {
+        assert_eq!(emu.step_over(100).len(), 1);
+    }
+
+    // Over the whole call, to its return address.
+    emu.step_over(1000);
+    assert_eq!(emu.get_var("@counter"), Some(outer.return_address));
+    assert_eq!(emu.get_var("r"), Some(21));
+
+    // From inside `g`, out to `f`, then out of `f`.
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    emu.set_source_map(&map);
+    emu.set_breakpoints(map.breakpoints(&[13]));
+    emu.run(1000);
+    assert_eq!(emu.get_var("r"), None);
+    emu.step_out(1000);
+    assert_eq!(emu.get_var("@counter"), Some(inner.return_address));
+    assert_eq!(emu.get_var("r"), Some(20));
+    emu.step_out(1000);
+    assert_eq!(emu.get_var("@counter"), Some(outer.return_address));
+    assert_eq!(emu.get_var("c"), None);
+
+    // Partway into setting up a call, stepping over finishes it.
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    emu.set_source_map(&map);
+    while emu.get_var("@counter") != Some(outer.start + 1) {
+        emu.run(1);
+    }
+    emu.step_over(1000);
+    assert_eq!(emu.get_var("@counter"), Some(outer.return_address));
+
+    // Outside any function, stepping out runs to the end.
+    emu.step_out(1000);
+    assert_eq!(emu.get_var("c"), Some(3));
+    assert_eq!(emu.get_var("@counter"), Some(0));
+}
+
+#[test]
+fn test_watch_stack_vars() {
+    for stack in ["size 8", "cell bank1"] {
+        let text = format!(
+            "stack_config {}
+             call f 2 -> b
+             end
+
+             fn f *x -> r {{
+               let *y
+               set *y 5
+               op add r *x *y
+               return r
+             }}",
+            stack
+        );
+        let ir = parser::parse(&text).unwrap();
+        let (output, _) = ir.generate().unwrap();
+        let map = ir.source_map();
+        let address = map.breakpoints(&[7])[0];
+
+        let cell = Some(Cell::default()).filter(|_| stack.starts_with("cell"));
+        let mut emu = Emulator::new(cell, &output.join("\n")).unwrap();
+        emu.set_watches(vec![Symbol::new("*x"), Symbol::new("*y")]);
+        emu.set_source_map(&map);
+        let trace = emu.run(1000);
+
+        // Outside the function, neither has a value.
+        assert!(
+            trace[0].starts_with("0:\t*x:<none> *y:<none> "),
+            "{}",
+            trace[0]
+        );
+        let at_line = trace
+            .iter()
+            .find(|line| line.starts_with(&format!("{}:", address)))
+            .unwrap();
+        assert!(at_line.contains("*x:2 *y:5 "), "{}: {}", stack, at_line);
+    }
+}
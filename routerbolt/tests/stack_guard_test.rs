@@ -0,0 +1,171 @@
+use routerbolt::*;
+use test_util::*;
+
+fn overflow_output(stack_config: &str, program: &str) -> Vec<String> {
+    debug_output(stack_config, "debug stack_guard", program, 5000)
+}
+
+fn debug_output(stack_config: &str, debug: &str, program: &str, steps: usize) -> Vec<String> {
+    let text = format!("{}\n{}\n{}", stack_config, debug, program);
+    let ir = parser::parse(&text).unwrap();
+    let (output, annotated) = ir.generate().unwrap();
+    for line in annotated {
+        eprintln!("\t{}", line);
+    }
+
+    let cell = if stack_config.contains("cell") {
+        emu_cell(true)
+    } else {
+        None
+    };
+    let mut emu = Emulator::new(cell, &output.join("\n")).unwrap();
+    emu.run(steps)
+}
+
+fn recursion_guard_fixture(stack_config: &str) {
+    let program = "call f 1
+                   end
+
+                   fn f *n {
+                     call f *n
+                     return
+                   }";
+
+    let output = overflow_output(stack_config, program);
+
+    // Halts reporting the overflow rather than running off the stack.
+    let reports = output
+        .iter()
+        .filter(|line| line.ends_with("Printed to message1: stack overflow in f"))
+        .count();
+    assert!(reports > 10, "{:#?}", output);
+
+    // Runs until stopped. A report printed on the last step adds a line.
+    assert!(output.len() >= 5000);
+}
+
+#[test]
+fn recursion_guard_stack() {
+    recursion_guard_fixture("stack_config size 8");
+}
+
+#[test]
+fn recursion_guard_cell() {
+    recursion_guard_fixture("stack_config cell bank1 offset 10 len 8");
+}
+
+#[test]
+fn push_guard_test() {
+    let program = "loop {
+                     push
+                     op add a a 1
+                   }";
+
+    let output = overflow_output("stack_config size 4", program);
+    assert!(output
+        .iter()
+        .any(|line| line.ends_with("Printed to message1: stack overflow outside any function")));
+
+    // Exactly filling the stack is fine.
+    let ir = parser::parse(&format!(
+        "stack_config size 4\ndebug stack_guard\n{}",
+        "set MF_acc 0\npush\npush\npush\npush\npop\nset a MF_acc"
+    ))
+    .unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    let output = emu.run(100);
+    assert!(output.len() < 100);
+    assert!(!output.iter().any(|line| line.contains("overflow")));
+}
+
+#[test]
+fn named_stack_guard_test() {
+    let program = "stack_config size 2 as data
+                   debug stack_guard display1
+                   loop {
+                     push data
+                   }";
+
+    let ir = parser::parse(program).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    let output = emu.run(100);
+    assert!(output
+        .iter()
+        .any(|line| line.ends_with("Printed to display1: stack overflow outside any function")));
+}
+
+#[test]
+fn stack_guard_errors_test() {
+    // Needs to know the size of external stacks.
+    assert!(parser::parse("stack_config cell bank1\ndebug stack_guard").is_err());
+    assert!(parser::parse("stack_config cell bank1 len 8\ndebug stack_guard").is_ok());
+    assert!(parser::parse("debug stack_guard message1 extra").is_err());
+    assert!(parser::parse("debug nonsense").is_err());
+}
+
+fn canary_fixture(stack_config: &str) {
+    // Leaves an extra entry on the stack, so the return address is off by one.
+    let program = "call f 1
+                   set done 1
+                   end
+
+                   fn f *n {
+                     push
+                     return
+                   }";
+
+    let output = debug_output(stack_config, "debug stack_canary", program, 100);
+    assert!(output
+        .iter()
+        .any(|line| line.ends_with("Printed to message1: stack corruption in f")));
+
+    // Well-behaved calls are unaffected, including recursive ones.
+    let program = "call f 3 -> r
+                   print r
+                   printflush message2
+                   end
+
+                   fn f *n -> r {
+                     if equal *n 0 {
+                       return 0
+                     }
+                     let *m
+                     op sub *m *n 1
+                     call f *m -> *m
+                     op add *m *m *n
+                     return *m
+                   }";
+
+    let output = debug_output(
+        stack_config,
+        "debug stack_canary\ndebug stack_guard",
+        program,
+        1000,
+    );
+    assert!(output.len() < 1000, "{:#?}", output);
+    assert!(output
+        .iter()
+        .any(|line| line.ends_with("Printed to message2: 6")));
+    assert!(!output
+        .iter()
+        .any(|line| line.contains("Printed to message1")));
+}
+
+#[test]
+fn canary_stack() {
+    canary_fixture("stack_config size 32");
+}
+
+#[test]
+fn canary_cell() {
+    canary_fixture("stack_config cell bank1 offset 10 len 32");
+}
+
+#[test]
+fn canary_errors_test() {
+    assert!(parser::parse("debug stack_canary").is_ok());
+    assert!(parser::parse("debug stack_canary display1").is_ok());
+    assert!(parser::parse("debug stack_canary message1 extra").is_err());
+}
@@ -0,0 +1,43 @@
+use routerbolt::*;
+
+#[test]
+fn test_symbol_file() {
+    let text = "stack_config size 4\ncall add 1 2 -> x\nend\nfn add *a *b -> r {\n  top:\n  op add r *a *b\n  return r\n}\nstart:\nend";
+    let ir = parser::parse(text).unwrap();
+    let symbols = ir.symbol_file();
+
+    let (name, range) = &symbols.functions[0];
+    assert_eq!(name.as_ref(), "add");
+    assert_eq!(symbols.functions.len(), 1);
+
+    let label = |name: &str| {
+        symbols
+            .labels
+            .iter()
+            .find(|(label, _)| label.as_ref() == name)
+            .map(|(_, address)| *address)
+    };
+    assert!(range.contains(&label("add.top").unwrap()));
+    assert!(label("start").unwrap() >= range.end);
+
+    let depths: Vec<(&str, usize)> = symbols
+        .stack_vars
+        .iter()
+        .map(|(_, var, depth)| (var.as_ref(), *depth))
+        .collect();
+    assert_eq!(depths, [("*b", 1), ("*a", 2)]);
+
+    // Matches the source map.
+    let source_map = ir.source_map();
+    assert_eq!(symbols.functions, source_map.functions);
+    assert_eq!(source_map.function(range.start), Some(name));
+
+    let text = symbols.to_string();
+    assert!(text.starts_with(&format!("fn\tadd\t{}\t{}\n", range.start, range.end)));
+    assert!(text.contains(&format!("label\tadd.top\t{}\n", label("add.top").unwrap())));
+    assert!(
+        text.ends_with("var\tadd\t*b\t1\nvar\tadd\t*a\t2\n"),
+        "{}",
+        text
+    );
+}
@@ -0,0 +1,56 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+use routerbolt::*;
+
+#[test]
+fn test_interning() {
+    let (a, b) = Symbol::interning(|| {
+        (
+            Symbol::new("MF_acc"),
+            Symbol::interning(|| Symbol::from("MF_acc".to_string())),
+        )
+    });
+    assert_eq!(a, b);
+    assert!(std::ptr::eq(a.as_str(), b.as_str()));
+    assert_ne!(a, Symbol::new("MF_tmp"));
+    assert_eq!(a, "MF_acc");
+    assert_eq!(a.to_string(), "MF_acc");
+    assert_eq!(format!("{:?}", a), "\"MF_acc\"");
+}
+
+#[test]
+fn test_ordering() {
+    let mut symbols: Vec<Symbol> = ["b", "c", "a"].iter().copied().map(Symbol::new).collect();
+    symbols.sort();
+    assert_eq!(symbols, vec!["a", "b", "c"]);
+}
+
+#[test]
+fn test_names_share_text() {
+    let label = LabelName::try_from("top").unwrap();
+    let function = FunctionName::try_from("top").unwrap();
+    assert_eq!(Symbol::from(label), Symbol::from(function));
+}
+
+#[test]
+fn test_serde() {
+    let symbol = Symbol::new("bank1");
+    let json = serde_json::to_string(&symbol).unwrap();
+    assert_eq!(json, "\"bank1\"");
+    assert_eq!(serde_json::from_str::<Symbol>(&json).unwrap(), symbol);
+}
+
+#[test]
+fn test_uninterned_symbols_are_equal() {
+    // Outside a compilation, and across compilations, equal text makes equal
+    // symbols even though it isn't shared.
+    let a = Symbol::new("bank1");
+    let b = Symbol::interning(|| Symbol::new("bank1"));
+    assert!(!std::ptr::eq(a.as_str(), b.as_str()));
+    assert_eq!(a, b);
+
+    let mut set = HashSet::new();
+    set.insert(a);
+    assert!(set.contains(&b));
+}
@@ -0,0 +1,80 @@
+use routerbolt::*;
+use test_util::*;
+
+const TEXT: &str = "call fib 10 -> a
+                    end
+
+                    fn fib *n -> r {
+                      jump fib_small lessThan *n 2
+                      op sub *n *n 1
+                      call fib *n -> r
+                      return r
+                    fib_small:
+                      return *n
+                    }";
+
+fn test_symbolic_fixture(cell: bool) {
+    let options = parser::CompileOptions {
+        stack_config: Some(use_cell(cell, 32)),
+        ..Default::default()
+    };
+    let ir = parser::parse_with_options(TEXT, &options).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    let program = ir.generate_symbolic().unwrap();
+
+    assert_eq!(program.instructions.len(), output.len());
+    // Labels in a function are scoped to it.
+    let read = if cell { "MF_t0" } else { "MF_acc" };
+    assert!(program
+        .instructions
+        .contains(&format!("jump fib.fib_small lessThan {} 2", read)));
+    let address = *ir.labels().values().next().unwrap();
+    assert!(program
+        .labels
+        .contains(&("fib.fib_small".to_string(), address.into())));
+
+    assert_eq!(program.resolve().unwrap(), output);
+
+    // The text forms read back as the same program.
+    let text = program.to_string();
+    let labels = program.labels_text();
+    let parsed = SymbolicProgram::parse(&text, &labels).unwrap();
+    assert_eq!(parsed, program);
+}
+
+#[test]
+fn test_symbolic_stack() {
+    test_symbolic_fixture(false);
+}
+
+#[test]
+fn test_symbolic_cell() {
+    test_symbolic_fixture(true);
+}
+
+#[test]
+fn test_resolve_patched() {
+    // Patch a jump to go elsewhere, by hand.
+    let program = SymbolicProgram::parse(
+        "set a 1\njump done equal a 1\nset a 2\njump L0 always x false\nend",
+        "L0\t0\ndone\t4\n",
+    )
+    .unwrap();
+    assert_eq!(
+        program.resolve().unwrap(),
+        vec![
+            "set a 1",
+            "jump 4 equal a 1",
+            "set a 2",
+            "jump 0 always x false",
+            "end"
+        ]
+    );
+
+    let program = SymbolicProgram::parse("jump nowhere always x false", "").unwrap();
+    let err = format!("{:?}", program.resolve().unwrap_err());
+    assert!(err.contains("label nowhere is not defined"));
+
+    assert!(SymbolicProgram::parse("end", "done").is_err());
+    assert!(SymbolicProgram::parse("end", "done four").is_err());
+}
@@ -0,0 +1,37 @@
+#![cfg(feature = "sync")]
+
+use std::sync::Arc;
+
+use routerbolt::*;
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn test_send_sync() {
+    assert_send_sync::<IntermediateRepresentation>();
+    assert_send_sync::<Emulator>();
+}
+
+#[test]
+fn test_compile_on_threads() {
+    let ir = Arc::new(
+        parser::parse("stack_config size 4\ncall f\nend\nfn f {\nprint 1\nreturn\n}").unwrap(),
+    );
+    let expected = ir.generate().unwrap();
+
+    let threads: Vec<_> = (0..4)
+        .map(|j| {
+            let ir = ir.clone();
+            std::thread::spawn(move || {
+                let own = parser::parse(&format!("set x {}\nprint x", j)).unwrap();
+                (ir.generate().unwrap(), own.generate().unwrap().0)
+            })
+        })
+        .collect();
+
+    for (j, thread) in threads.into_iter().enumerate() {
+        let (shared, own) = thread.join().unwrap();
+        assert_eq!(shared, expected);
+        assert_eq!(own, vec![format!("set x {}", j), "print x".to_string()]);
+    }
+}
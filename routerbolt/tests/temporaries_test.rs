@@ -0,0 +1,70 @@
+use std::rc::Rc;
+
+use routerbolt::*;
+use test_util::*;
+
+const PROGRAM: &str = "call f 3 4 -> a b
+                       end
+
+                       fn f *x *y -> *sum *diff {
+                         let *sum
+                         let *diff
+                         op add *sum *x *y
+                         op sub *diff *y *x
+                         if lessThan *x *y {
+                           set c 1
+                         }
+                         return *sum *diff
+                       }";
+
+fn test_temporaries_fixture(cell: bool) {
+    let output = test_compile(PROGRAM, use_cell(cell, 16));
+    let mut emu = Emulator::new(emu_cell(cell), &output.join("\n")).unwrap();
+    emu.run(500);
+    assert_eq!(emu.get_var(&Rc::new("a".to_string())), Some(7));
+    assert_eq!(emu.get_var(&Rc::new("b".to_string())), Some(1));
+    assert_eq!(emu.get_var(&Rc::new("c".to_string())), Some(1));
+
+    // Each statement gets its temporaries from the start again.
+    let ops: Vec<_> = output
+        .iter()
+        .filter(|line| line.starts_with("op ") && line.contains("MF_t0"))
+        .collect();
+    let expected = if cell {
+        vec!["op add MF_t0 MF_t0 MF_t1", "op sub MF_t0 MF_t0 MF_t1"]
+    } else {
+        vec!["op add MF_acc MF_t0 MF_acc", "op sub MF_acc MF_t0 MF_acc"]
+    };
+    assert_eq!(ops, expected);
+}
+
+#[test]
+fn test_temporaries_stack() {
+    test_temporaries_fixture(false);
+}
+
+#[test]
+fn test_temporaries_cell() {
+    test_temporaries_fixture(true);
+}
+
+#[test]
+fn test_temporaries_recycled() {
+    let mut temps = Temporaries::new(Backend::External, DEFAULT_VARIABLE_PREFIX);
+    let t0 = temps.allocate();
+    let t1 = temps.allocate();
+    assert_eq!((t0.as_ref(), t1.as_ref()), ("MF_t0", "MF_t1"));
+
+    temps.release(&t0);
+    assert_eq!(temps.allocate_last().as_ref(), "MF_t0");
+    assert_eq!(temps.allocate().as_ref(), "MF_t2");
+
+    temps.release_all();
+    assert_eq!(temps.allocate().as_ref(), "MF_t0");
+    assert_eq!(temps.count(), 3);
+
+    let mut temps = Temporaries::new(Backend::Internal, DEFAULT_VARIABLE_PREFIX);
+    assert_eq!(temps.allocate_last().as_ref(), "MF_acc");
+    temps.release(&MindustryTerm::accumulator(DEFAULT_VARIABLE_PREFIX));
+    assert_eq!(temps.allocate().as_ref(), "MF_t0");
+}
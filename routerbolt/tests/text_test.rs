@@ -0,0 +1,66 @@
+use routerbolt::*;
+
+const TEXT: &str = "stack_config size 16
+                    debug stack_guard message1
+                    set i 0
+                    while lessThan i 3 {
+                      call f i -> a
+                      print a
+                      op add i i 1
+                    }
+                    end
+
+                    fn f *x -> r {
+                      if equal *x 1 {
+                        op mul r *x 2
+                      } else {
+                        set r *x
+                      }
+                      return r
+                    }";
+
+#[test]
+fn test_round_trip() {
+    for profile in [parser::Profile::Debug, parser::Profile::Release].iter() {
+        let options = parser::CompileOptions::with_profile(*profile);
+        let ir = parser::parse_with_options(TEXT, &options).unwrap();
+        let text = print(&ir).unwrap();
+        let copy = parse_ir(&text).unwrap();
+
+        assert_eq!(print(&copy).unwrap(), text);
+        assert_eq!(copy.generate().unwrap(), ir.generate().unwrap());
+    }
+}
+
+#[test]
+fn test_print() {
+    let ir = parser::parse(TEXT).unwrap();
+    let text = print(&ir).unwrap();
+    assert!(text.starts_with(
+        "ops: [\n  MindustryCommand { command: [\"jump 3 equal MF_init 1\"] },  # 0 @0\n"
+    ));
+    assert!(text.contains("  Math { operation: add, dest: i, arg1: i, arg2: \"1\" },  # 8 @16\n"));
+    assert!(text.contains("\nstack_config: Internal(16)\n"));
+    assert!(text.contains("\nstack_usage: Bounded(2)\n"));
+}
+
+#[test]
+fn test_edit_text() {
+    // Tests can work on the IR directly by editing its text.
+    let ir = parser::parse("set a 1\nprint a").unwrap();
+    let text = print(&ir).unwrap().replace(
+        "Set { source: \"1\", dest: a }",
+        "Set { source: \"null\", dest: a }",
+    );
+    let ir = parse_ir(&text).unwrap();
+    assert_eq!(ir.generate().unwrap().0, vec!["set a null", "print a"]);
+}
+
+#[test]
+fn test_parse_ir_errors() {
+    let ir = parser::parse("set a 1").unwrap();
+    let text = print(&ir).unwrap();
+    assert!(parse_ir(&text.replace("Set {", "Set (")).is_err());
+    assert!(parse_ir(&text.replace("\"1\"", "\"1")).is_err());
+    assert!(parse_ir(&text.replace("Set", "Frob")).is_err());
+}
@@ -0,0 +1,101 @@
+use routerbolt::*;
+use test_util::*;
+
+#[test]
+fn test_unit_control_expansion() {
+    let text = "bind @poly
+                move x y
+                approach x y 5
+                itemTake core @copper 20
+                build 10 20 @router 0 0
+                idle";
+    let output = test_compile(text, use_cell(false, 0));
+    assert_eq!(
+        output,
+        vec![
+            "ubind @poly".to_string(),
+            "ucontrol move x y 0 0 0".to_string(),
+            "ucontrol approach x y 5 0 0".to_string(),
+            "ucontrol itemTake core @copper 20 0 0".to_string(),
+            "ucontrol build 10 20 @router 0 0".to_string(),
+            "ucontrol idle 0 0 0 0 0".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn test_unit_control_raw_unchanged() {
+    let text = "ucontrol stop 0 0 0 0 0\nstop";
+    let output = test_compile(text, use_cell(false, 0));
+    let common: Vec<_> = text.lines().map(|l| l.to_string()).collect();
+    assert_eq!(output, common);
+}
+
+#[test]
+fn test_unit_control_wrong_arity() {
+    for text in &[
+        "bind",
+        "bind @poly @mega",
+        "move x",
+        "approach x y",
+        "itemTake core @copper",
+        "idle now",
+    ] {
+        assert!(parser::parse(text).is_err(), "{}", text);
+    }
+}
+
+#[test]
+fn test_unit_control_no_stack_vars() {
+    let text = "stack_config size 4
+                fn f *x {
+                  move *x 1
+                  return
+                }";
+    assert!(parser::parse(text).is_err());
+}
+
+fn test_unit_control_run_fixture(cell: bool) {
+    let text = "sense amount vault1 @copper
+                if lessThan amount 100 {
+                  bind @poly
+                  itemTake vault1 @copper 20
+                }
+                set enabled switch1.@enabled
+                end";
+    let output = test_compile(text, use_cell(cell, 16));
+
+    let mut world = World::default();
+    world.link(WorldObject::new("vault1").with_sensor("@copper", Value::Number(40.0)));
+    world.link(WorldObject::new("switch1").with_sensor("@enabled", Value::Number(1.0)));
+    world.add_unit(WorldObject::new("@poly"));
+
+    let mut emu = Emulator::new(emu_cell(cell), &output.join("\n")).unwrap();
+    emu.set_world(world);
+    emu.run(1000);
+    assert_eq!(emu.get_var("enabled"), Some(1));
+    assert_eq!(
+        emu.world().commands(),
+        &[UnitCommand {
+            unit: Some(Symbol::new("@poly")),
+            command: Symbol::new("itemTake"),
+            args: vec![
+                Value::Object(Symbol::new("vault1")),
+                Value::Content(Symbol::new("@copper")),
+                Value::Number(20.0),
+                Value::Number(0.0),
+                Value::Number(0.0),
+            ],
+        }]
+    );
+}
+
+#[test]
+fn test_unit_control_run_cell() {
+    test_unit_control_run_fixture(true);
+}
+
+#[test]
+fn test_unit_control_run_stack() {
+    test_unit_control_run_fixture(false);
+}
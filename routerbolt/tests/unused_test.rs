@@ -0,0 +1,90 @@
+use std::convert::TryFrom;
+
+use routerbolt::*;
+use test_util::*;
+
+const TEXT: &str = "set a 1
+                    call f 2 -> c
+                    unused_label:
+                    end
+
+                    fn f *x -> y {
+                      let *unread
+                      let *read
+                      set *unread 1
+                      set *read *x
+                      jump done always
+                      done:
+                      return *read
+                    }
+
+                    fn g {
+                      call h
+                      return
+                    }
+
+                    fn h {
+                      return
+                    }
+                ";
+
+fn compile(strip_unused_functions: bool) -> (IntermediateRepresentation, Vec<String>) {
+    let options = parser::CompileOptions {
+        stack_config: Some(use_cell(false, 16)),
+        strip_unused_functions,
+        ..Default::default()
+    };
+    let ir = parser::parse_with_options(TEXT, &options).unwrap();
+    let (output, _) = ir.generate().unwrap();
+    (ir, output)
+}
+
+#[test]
+fn test_unused_symbols_reported() {
+    let (ir, _) = compile(false);
+    assert_eq!(
+        ir.unused,
+        vec![
+            UnusedSymbol::Label {
+                name: LabelName::try_from("unused_label").unwrap(),
+                line: 2,
+            },
+            UnusedSymbol::StackVar {
+                function: FunctionName::try_from("f").unwrap(),
+                name: StackVar::try_from("*unread").unwrap(),
+                line: 6,
+            },
+            UnusedSymbol::Function {
+                name: FunctionName::try_from("g").unwrap(),
+                line: 15,
+            },
+        ]
+    );
+
+    let (_, annotated) = ir.generate().unwrap();
+    assert!(annotated.contains(&"// Warning: function g is never called (line 16)".to_string()));
+}
+
+#[test]
+fn test_strip_unused_functions() {
+    let (_, full) = compile(false);
+    let (ir, output) = compile(true);
+
+    // `h` is only called from `g`, so goes too. Other dead code is kept.
+    let removed: Vec<String> = ir.dead_code.iter().map(|dead| dead.to_string()).collect();
+    assert_eq!(
+        removed,
+        vec![
+            "lines 16-19 (function g is never called)",
+            "lines 21-23 (function h is never called)",
+        ]
+    );
+    assert!(output.len() < full.len());
+    assert!(ir
+        .unused
+        .iter()
+        .all(|unused| !matches!(unused, UnusedSymbol::Function { .. })));
+
+    let mut emu = Emulator::new(None, &output.join("\n")).unwrap();
+    step_until_equal(&mut emu, Some(1), None, Some(2), 100);
+}
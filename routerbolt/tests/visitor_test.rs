@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use routerbolt::*;
+
+#[derive(Default)]
+struct Counter {
+    ops: usize,
+    loop_ends: usize,
+    calls: Vec<String>,
+    functions: Vec<String>,
+}
+
+impl IrVisitor for Counter {
+    fn visit_op(&mut self, op: &IrOp) {
+        self.ops += 1;
+        walk_op(self, op);
+    }
+
+    fn visit_loop_end(&mut self, _op: &LoopEndOp) {
+        self.loop_ends += 1;
+    }
+
+    fn visit_call(&mut self, op: &CallOp) {
+        self.calls.push(op.target_function.to_string());
+    }
+
+    fn visit_function(&mut self, name: &FunctionName, _size: AddressDelta) {
+        self.functions.push(name.to_string());
+    }
+}
+
+const TEXT: &str = "stack_config size 4
+                    set i 0
+                    while lessThan i 3 {
+                      call f
+                      op add i i 1
+                    }
+                    end
+
+                    fn f {
+                      print i
+                      return
+                    }";
+
+#[test]
+fn test_visitor() {
+    let ir = parser::parse(TEXT).unwrap();
+    let mut counter = Counter::default();
+    counter.visit_ops(ir.ops());
+
+    // The loop end is seen both in `ops` and inside the while.
+    assert_eq!(counter.ops, ir.ops().len() + 1);
+    assert_eq!(counter.loop_ends, 2);
+    assert_eq!(counter.calls, vec!["f"]);
+    assert_eq!(counter.functions, vec!["f"]);
+}
+
+/// Counts each kind of op, without looking inside loops.
+#[derive(Default)]
+struct Kinds(HashMap<&'static str, usize>);
+
+impl Kinds {
+    fn add(&mut self, kind: &'static str) {
+        *self.0.entry(kind).or_default() += 1;
+    }
+}
+
+impl IrVisitor for Kinds {
+    fn visit_while(&mut self, _op: &WhileOp) {
+        self.add("while");
+    }
+
+    fn visit_loop_end(&mut self, _op: &LoopEndOp) {
+        self.add("loop_end");
+    }
+
+    fn visit_math(&mut self, _op: &MathOp) {
+        self.add("math");
+    }
+}
+
+#[test]
+fn test_override_while() {
+    let ir = parser::parse(TEXT).unwrap();
+    let mut kinds = Kinds::default();
+    kinds.visit_ops(ir.ops());
+    assert_eq!(kinds.0["while"], 1);
+    assert_eq!(kinds.0["loop_end"], 1);
+    assert_eq!(kinds.0["math"], 1);
+    assert_eq!(kinds.0.len(), 3);
+}
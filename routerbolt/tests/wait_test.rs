@@ -0,0 +1,152 @@
+use routerbolt::*;
+use test_util::*;
+
+#[test]
+fn test_sleep_seconds() {
+    for text in &["sleep 2.5", "sleep 2.5 seconds"] {
+        let output = test_compile(text, use_cell(false, 0));
+        assert_eq!(output, vec!["wait 2.5".to_string()]);
+    }
+
+    let output = test_compile("sleep delay", use_cell(false, 0));
+    assert_eq!(output, vec!["wait delay".to_string()]);
+}
+
+#[test]
+fn test_sleep_ticks() {
+    let output = test_compile("sleep 30 ticks", use_cell(false, 0));
+    assert_eq!(output, vec!["wait 0.5".to_string()]);
+
+    let output = test_compile("sleep delay ticks", use_cell(false, 0));
+    assert_eq!(
+        output,
+        vec![
+            "op div MF_acc delay 60".to_string(),
+            "wait MF_acc".to_string()
+        ]
+    );
+}
+
+#[test]
+fn test_sleep_form() {
+    for text in &["sleep", "sleep 1 minutes", "sleep 1 ticks please"] {
+        assert!(parser::parse(text).is_err());
+    }
+}
+
+fn test_sleep_stack_var_fixture(cell: bool) {
+    let text = "call f 3
+                end
+
+                fn f *t {
+                  sleep *t
+                  return
+                }";
+    let output = test_compile(text, use_cell(cell, 16));
+    let wait = output.iter().find(|line| line.starts_with("wait"));
+    let read = if cell { "wait MF_t0" } else { "wait MF_acc" };
+    assert_eq!(wait.map(String::as_str), Some(read));
+}
+
+#[test]
+fn test_sleep_stack_var_stack() {
+    test_sleep_stack_var_fixture(false);
+}
+
+#[test]
+fn test_sleep_stack_var_cell() {
+    test_sleep_stack_var_fixture(true);
+}
+
+#[test]
+fn test_busywait_spins_in_place() {
+    let output = test_compile("set a 1\nbusywait lessThan a 5", use_cell(false, 0));
+    assert_eq!(
+        output,
+        vec!["set a 1".to_string(), "jump 1 lessThan a 5".to_string()]
+    );
+}
+
+fn test_busywait_fixture(cell: bool) {
+    let text = "set a 7
+                busywait lessThan a 5
+                set b 2
+                call f 1 -> c
+                end
+
+                fn f *x -> r {
+                  busywait greaterThan *x 2
+                  return 3
+                }";
+    let output = test_compile(text, use_cell(cell, 16));
+    let mut emu = Emulator::new(emu_cell(cell), &output.join("\n")).unwrap();
+    step_until_equal(&mut emu, Some(7), Some(2), Some(3), 100);
+}
+
+#[test]
+fn test_busywait_stack() {
+    test_busywait_fixture(false);
+}
+
+#[test]
+fn test_busywait_cell() {
+    test_busywait_fixture(true);
+}
+
+fn test_busywait_time_run_fixture(cell: bool) {
+    let text = "call pause 500
+                set done @time
+                end
+
+                fn pause *ms {
+                  op add deadline @time *ms
+                  busywait lessThan @time deadline
+                  return
+                }";
+    let output = test_compile(text, use_cell(cell, 16));
+    let mut emu = Emulator::new(emu_cell(cell), &output.join("\n")).unwrap();
+    emu.run(10_000);
+    let done = emu.get_value("done").unwrap();
+    assert!((500.0..1000.0).contains(&done), "{}", done);
+    assert!(emu.ticks() >= 30);
+}
+
+#[test]
+fn test_busywait_time_run_cell() {
+    test_busywait_time_run_fixture(true);
+}
+
+#[test]
+fn test_busywait_time_run_stack() {
+    test_busywait_time_run_fixture(false);
+}
+
+fn test_sleep_run_fixture(cell: bool) {
+    let text = "call nap 2
+                set after @second
+                sleep 30 ticks
+                set later @tick
+                end
+
+                fn nap *seconds {
+                  sleep *seconds
+                  return
+                }";
+    let output = test_compile(text, use_cell(cell, 16));
+    let mut emu = Emulator::new(emu_cell(cell), &output.join("\n")).unwrap();
+    emu.run(1000);
+    let after = emu.get_value("after").unwrap();
+    assert!((2.0..2.5).contains(&after), "{}", after);
+    let later = emu.get_value("later").unwrap();
+    assert!(later >= after * 60.0 + 30.0, "{}", later);
+}
+
+#[test]
+fn test_sleep_run_cell() {
+    test_sleep_run_fixture(true);
+}
+
+#[test]
+fn test_sleep_run_stack() {
+    test_sleep_run_fixture(false);
+}
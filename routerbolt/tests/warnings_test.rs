@@ -0,0 +1,220 @@
+use std::convert::TryFrom;
+
+use routerbolt::*;
+
+fn warnings(text: &str) -> Vec<(Option<Lint>, Option<usize>)> {
+    let ir = parser::parse(text).unwrap();
+    ir.warnings()
+        .iter()
+        .map(|warning| (warning.lint, warning.line))
+        .collect()
+}
+
+const TEXT: &str = "stack_config size 8
+                    call f 1 -> a
+                    print a
+                    top:
+                    fn f *x -> r {
+                      top:
+                      op add *x *x 1
+                      jump top equal *x 3
+                    }
+                    end
+                    fn g {
+                      let *unread
+                      return
+                    }
+                ";
+
+#[test]
+fn test_warnings() {
+    let ir = parser::parse(TEXT).unwrap();
+    let warnings = ir.warnings();
+    assert_eq!(
+        warnings
+            .iter()
+            .map(|warning| (warning.lint.unwrap(), warning.line.unwrap()))
+            .collect::<Vec<_>>(),
+        vec![
+            (Lint::Unused, 3),
+            (Lint::Fallthrough, 4),
+            (Lint::Shadowed, 5),
+            (Lint::Fallthrough, 7),
+            (Lint::Unused, 10),
+            (Lint::Unused, 11),
+        ]
+    );
+    assert!(warnings
+        .iter()
+        .all(|warning| warning.severity == Severity::Warning));
+    assert_eq!(
+        warnings[1].to_string(),
+        "warning: control can fall into function f from the code before it; put `end` before the definition (line 5)"
+    );
+    assert_eq!(
+        warnings[3].message,
+        "control can fall out of the end of function f without a `return` (line 8)"
+    );
+}
+
+#[test]
+fn test_allow() {
+    // On a statement.
+    let text = TEXT.replacen("top:\n", "#allow(unused)\ntop:\n", 1);
+    let found = warnings(&text);
+    assert!(!found.contains(&(Some(Lint::Unused), Some(4))));
+    assert!(found.contains(&(Some(Lint::Fallthrough), Some(5))));
+
+    // On a function, for the whole of it.
+    let text = TEXT.replace(
+        "fn f *x -> r {",
+        "#allow(fallthrough, shadowed)\nfn f *x -> r {",
+    );
+    let found = warnings(&text);
+    assert_eq!(
+        found
+            .iter()
+            .map(|(lint, _)| lint.unwrap())
+            .collect::<Vec<_>>(),
+        vec![Lint::Unused, Lint::Unused, Lint::Unused]
+    );
+
+    // Only the block that follows.
+    let text = TEXT.replace("fn g {", "#allow(fallthrough)\nfn g {");
+    assert_eq!(warnings(&text).len(), 6);
+
+    assert!(parser::parse("#allow(everything)").is_err());
+    assert!(parser::parse("#allow unused").is_err());
+}
+
+#[test]
+fn test_dead_code_note() {
+    let options = parser::CompileOptions {
+        eliminate_dead_code: true,
+        ..Default::default()
+    };
+    let ir = parser::parse_with_options("set a 1\nend\nset b 2", &options).unwrap();
+    let warnings = ir.warnings();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].severity, Severity::Note);
+    assert_eq!(warnings[0].lint, Some(Lint::DeadCode));
+    assert_eq!(warnings[0].line, Some(2));
+}
+
+#[test]
+fn test_lint_names() {
+    for lint in [
+        Lint::Unused,
+        Lint::Clobbered,
+        Lint::Shadowed,
+        Lint::Fallthrough,
+        Lint::DeadCode,
+        Lint::StackCapacity,
+    ]
+    .iter()
+    {
+        assert_eq!(Lint::try_from(lint.name()).unwrap(), *lint);
+    }
+}
+
+#[test]
+fn test_stack_capacity() {
+    // Calls need 1 + 2 entries at most, but only 2 are left in the cell.
+    let text = "stack_config cell cell1 offset 62
+                call f 1
+                end
+                fn f *x {
+                  let *y
+                  return
+                }";
+    let ir = parser::parse(text).unwrap();
+    let found: Vec<_> = ir
+        .warnings()
+        .into_iter()
+        .filter(|warning| warning.lint == Some(Lint::StackCapacity))
+        .collect();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].line, Some(0));
+    assert_eq!(
+        found[0].message,
+        "function calls may use 3 stack entries, but cell1 only holds 2 from address 62 (line 1)"
+    );
+
+    // The same program fits in a bank.
+    let ir = parser::parse(&text.replace("cell1", "bank1")).unwrap();
+    assert!(ir
+        .warnings()
+        .iter()
+        .all(|warning| warning.lint != Some(Lint::StackCapacity)));
+
+    // A named stack's `len` runs past the end of the cell.
+    let text = "stack_config size 4
+                stack_config cell cell2 offset 10 len 60 as data
+                stack_config cell cell3 len 64 as fits
+                stack_config cell mystery offset 1000 as unknown
+                push data
+                push fits";
+    let found = warnings(text);
+    assert_eq!(found, vec![(Some(Lint::StackCapacity), Some(1))]);
+    let ir = parser::parse(text).unwrap();
+    assert_eq!(
+        ir.warnings()[0].message,
+        "stack data ends at address 70 of cell2, which only holds 64 entries (line 2)"
+    );
+
+    let text = text.replace(
+        "stack_config cell cell2",
+        "#allow(stack_capacity)\nstack_config cell cell2",
+    );
+    assert!(warnings(&text).is_empty());
+}
+
+#[test]
+fn test_opt_in_lints() {
+    let body = "op add r r 1\n".repeat(LARGE_FUNCTION_SIZE + 1);
+    let text = format!(
+        "stack_config size 4
+         set limit 40
+         // 0 and 1 aren't magic, nor is 2 in a comment.
+         read x bank1 12
+         op mul y x 0.5
+         op add y y 1
+         call big
+         jump done lessThan y limit
+         done:
+         end
+         fn big {{
+           {}
+           return
+         }}",
+        body
+    );
+    let ir = parser::parse(&text).unwrap();
+
+    // Neither is checked unless asked for.
+    assert!(ir.warnings().is_empty());
+    for lint in Lint::ALL.iter() {
+        assert_eq!(Lint::try_from(lint.name()).unwrap(), *lint);
+    }
+
+    let found: Vec<_> = find_lints(&ir, Lint::ALL)
+        .into_iter()
+        .map(|warning| (warning.lint.unwrap(), warning.line.unwrap()))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (Lint::MagicNumber, 3),
+            (Lint::MagicNumber, 4),
+            (Lint::LargeFunction, 10),
+        ]
+    );
+    assert_eq!(
+        find_lints(&ir, &[Lint::LargeFunction])[0].message,
+        "function big is 106 instructions, over 100; consider splitting it up (line 11)"
+    );
+    assert_eq!(
+        find_lints(&ir, &[Lint::MagicNumber])[1].message,
+        "magic number 0.5; consider a named variable (line 5)"
+    );
+}
@@ -1,6 +1,7 @@
 use std::rc::Rc;
 
 use anyhow::{Context, Result};
+use wasm_bindgen::JsCast;
 use yew::prelude::*;
 
 use routerbolt::*;
@@ -10,6 +11,7 @@ const DEFAULT_PROGRAM: &str = include_str!("../../example.mf");
 enum Msg {
     Compile,
     Annotate,
+    Copy,
     EmulatorStep,
     EmulatorReset,
     CodeInput(yew::InputData),
@@ -29,6 +31,7 @@ struct Model {
     watches: Vec<Rc<String>>,
     breakpoints: Vec<usize>,
     link: ComponentLink<Self>,
+    output_ref: NodeRef,
     input_text: Rc<String>,
     output_text: Rc<String>,
     max_steps_per_click: usize,
@@ -38,24 +41,52 @@ struct Model {
     annotated: Rc<String>,
     emulator: Option<EmulatorState>,
     empty_emulator_cell: Option<Cell>,
+    more_emulator_cells: Vec<Cell>,
+    // Keeps the last program, so each edit lowers only the parts it changed.
+    compiler: IncrementalCompiler,
 }
 
 impl Model {
     fn compile_internal(&mut self) -> Result<()> {
         self.emulator.take();
         self.source = self.input_text.clone();
-        let ir = parser::parse(&self.source).context("parse")?;
+        let program = self.compiler.compile(&self.source).context("compile")?;
+        let ir = &program.ir;
         self.empty_emulator_cell = match &ir.stack_config {
             StackConfig::Internal(..) => None,
-            StackConfig::External(cell_name) => Some(Cell::new(cell_name.clone())),
+            StackConfig::External(ext) => Some(Cell::new(ext.cell_name.clone())),
         };
-        let (code, annotated) = generate(&ir).context("generate")?;
-        self.code = Rc::new(code.join("\n"));
+        self.more_emulator_cells = match &ir.stack_config {
+            StackConfig::Internal(..) => Vec::default(),
+            StackConfig::External(ext) => ext.more_cells.iter().cloned().map(Cell::new).collect(),
+        };
+        self.code = Rc::new(program.code.join("\n"));
         self.output_text = self.code.clone();
-        self.annotated = Rc::new(annotated.join("\n"));
+        self.annotated = Rc::new(program.annotated.join("\n"));
         Ok(())
     }
 
+    /// Puts the compiled program on the clipboard, to paste into a processor,
+    /// by selecting it in the output box and copying the selection.
+    fn copy_code(&mut self) {
+        self.compile();
+        self.output_text = self.code.clone();
+
+        let output = match self.output_ref.cast::<web_sys::HtmlTextAreaElement>() {
+            Some(output) => output,
+            None => return,
+        };
+        // The box shows the code only after rendering, so set it directly.
+        output.set_value(&self.code);
+        output.select();
+        let document: web_sys::HtmlDocument = yew::utils::document().unchecked_into();
+        if !matches!(document.exec_command("copy"), Ok(true)) {
+            self.emulator_output = Rc::new(
+                "*** COPY FAILED ***\nSelect the output and copy it by hand.".to_string(),
+            );
+        }
+    }
+
     fn step_emulator(&mut self) {
         self.compile();
 
@@ -73,6 +104,9 @@ impl Model {
                 }
                 Ok(mut emulator) => {
                     self.emulator_output = Rc::new(format!("*** EMULATOR READY ***\n"));
+                    for cell in self.more_emulator_cells.iter() {
+                        emulator.add_cell(cell.clone());
+                    }
                     emulator.set_watches(self.watches.clone());
                     emulator.set_breakpoints(self.breakpoints.clone());
                     emulator
@@ -127,6 +161,7 @@ impl Component for Model {
         let default_program = Rc::new(DEFAULT_PROGRAM.to_string());
         let mut this = Self {
             link,
+            output_ref: NodeRef::default(),
             input_text: default_program.clone(),
             output_text: Rc::new(String::default()),
             emulator_output: Rc::new(String::default()),
@@ -138,6 +173,8 @@ impl Component for Model {
             annotated: Rc::new(String::default()),
             emulator: None,
             empty_emulator_cell: None,
+            more_emulator_cells: Vec::default(),
+            compiler: IncrementalCompiler::new(Compiler::new()),
         };
 
         this.compile();
@@ -157,6 +194,10 @@ impl Component for Model {
                 self.output_text = self.annotated.clone();
                 true
             }
+            Msg::Copy => {
+                self.copy_code();
+                true
+            }
             Msg::EmulatorReset => {
                 self.emulator.take();
                 self.emulator_output = Rc::new(String::default());
@@ -216,6 +257,7 @@ impl Component for Model {
                 <td>
                   <button onclick=self.link.callback(|_| Msg::Compile)>{ "Compile" }</button>
                   <button onclick=self.link.callback(|_| Msg::Annotate)>{ "Annotate" }</button>
+                  <button onclick=self.link.callback(|_| Msg::Copy)>{ "Copy" }</button>
                 </td>
                 </tr>
                 <tr>
@@ -223,7 +265,7 @@ impl Component for Model {
                   <textarea oninput = self.link.callback(|text| Msg::CodeInput(text)) rows = "50" cols="100">{DEFAULT_PROGRAM}</textarea>
                 </td>
                 <td>
-                  <textarea rows = "50" cols="100">{self.output_text.as_str()}</textarea>
+                  <textarea ref=self.output_ref.clone() rows = "50" cols="100">{self.output_text.as_str()}</textarea>
                 </td>
                 </tr>
                 <tr>